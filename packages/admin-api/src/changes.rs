@@ -0,0 +1,169 @@
+//! Project request bodies.
+//!
+//! `POST /projects` and `PATCH /projects/{projectId}` take a JSON body of:
+//!
+//! - `projectId`: letters, digits, `-` and `_`, at most 64; creation only,
+//!   a random id when omitted. It's the `projectId` SDK tokens carry
+//! - `name`: a display name
+//! - `allowedOrigins`: browser origins allowed to send events; any when
+//!   empty
+//! - `samplingRate`: share of visitors whose events are kept, 0 to 1
+//! - `retentionDays`: days raw events are kept, 1 to `MAX_RETENTION_DAYS`
+//! - `timezone`: IANA timezone the project's days are reported in, e.g.
+//!   `America/New_York`; UTC when unset
+//!
+//! When updating, omitted fields are left as they are and `null` clears
+//! `samplingRate`, `retentionDays` or `timezone`.
+
+use serde::{Deserialize, Deserializer};
+
+use crate::projects::Project;
+
+/// Longest project id
+pub const MAX_PROJECT_ID_LEN: usize = 64;
+/// Longest retention a project may ask for, ten years
+pub const MAX_RETENTION_DAYS: u32 = 3_650;
+
+/// A present field that may be `null`
+fn nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::deserialize(deserializer).map(Some)
+}
+
+/// A validated create or update request
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct ProjectChanges {
+    pub project_id: Option<String>,
+    pub name: Option<String>,
+    pub allowed_origins: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub sampling_rate: Option<Option<f64>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub retention_days: Option<Option<u32>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub timezone: Option<Option<String>>,
+}
+
+impl ProjectChanges {
+    /// Parses a request body. Errors are messages for a 400.
+    pub fn parse(body: &[u8], creating: bool) -> Result<Self, String> {
+        let body = if body.is_empty() { b"{}".as_slice() } else { body };
+        let changes: Self = serde_json::from_slice(body).map_err(|e| format!("Invalid project request: {}", e))?;
+
+        match changes.project_id {
+            Some(_) if !creating => return Err("projectId can't be changed".to_string()),
+            Some(ref id) if !valid_project_id(id) => {
+                return Err(format!(
+                    "projectId must be 1 to {} letters, digits, '-' or '_'",
+                    MAX_PROJECT_ID_LEN
+                ))
+            }
+            _ => {}
+        }
+        if let Some(Some(rate)) = changes.sampling_rate {
+            if !(0.0..=1.0).contains(&rate) {
+                return Err("samplingRate must be between 0 and 1".to_string());
+            }
+        }
+        if let Some(Some(days)) = changes.retention_days {
+            if !(1..=MAX_RETENTION_DAYS).contains(&days) {
+                return Err(format!("retentionDays must be between 1 and {}", MAX_RETENTION_DAYS));
+            }
+        }
+        if let Some(Some(ref timezone)) = changes.timezone {
+            if timezone.parse::<chrono_tz::Tz>().is_err() {
+                return Err(format!("{} is not an IANA timezone", timezone));
+            }
+        }
+        if let Some(ref origins) = changes.allowed_origins {
+            if let Some(origin) = origins.iter().find(|o| !(o.starts_with("https://") || o.starts_with("http://"))) {
+                return Err(format!("{} is not an http(s) origin", origin));
+            }
+        }
+        Ok(changes)
+    }
+
+    /// Applies the changes to a project
+    pub fn apply(&self, project: &mut Project, now: i64) {
+        if let Some(ref name) = self.name {
+            project.name = name.trim().to_string();
+        }
+        if let Some(ref origins) = self.allowed_origins {
+            project.settings.allowed_origins = ingestion::origin::normalize(origins.clone());
+        }
+        if let Some(rate) = self.sampling_rate {
+            project.settings.sampling_rate = rate;
+        }
+        if let Some(days) = self.retention_days {
+            project.settings.retention_days = days;
+        }
+        if let Some(ref timezone) = self.timezone {
+            project.settings.timezone = timezone.clone();
+        }
+        project.updated_at = now;
+    }
+}
+
+fn valid_project_id(id: &str) -> bool {
+    (1..=MAX_PROJECT_ID_LEN).contains(&id.len())
+        && id.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::projects::ProjectSettings;
+
+    #[test]
+    fn test_validates_requests() {
+        let changes = ProjectChanges::parse(
+            br#"{"projectId": "acme-web", "allowedOrigins": ["https://App.Acme.com/"], "samplingRate": 0.25}"#,
+            true,
+        )
+        .unwrap();
+        assert_eq!(changes.project_id.as_deref(), Some("acme-web"));
+        assert_eq!(changes.sampling_rate, Some(Some(0.25)));
+        assert_eq!(changes.retention_days, None);
+        assert_eq!(ProjectChanges::parse(b"", true).unwrap(), ProjectChanges::default());
+
+        for body in [
+            r#"{"projectId": "acme web"}"#,
+            r#"{"samplingRate": 1.5}"#,
+            r#"{"retentionDays": 0}"#,
+            r#"{"timezone": "Mars/Olympus"}"#,
+            r#"{"allowedOrigins": ["acme.com"]}"#,
+            r#"{"retention": 30}"#,
+        ] {
+            assert!(ProjectChanges::parse(body.as_bytes(), true).is_err(), "{}", body);
+        }
+        assert!(ProjectChanges::parse(br#"{"projectId": "acme"}"#, false).is_err());
+    }
+
+    #[test]
+    fn test_applies_and_clears_settings() {
+        let settings = ProjectSettings {
+            sampling_rate: Some(0.5),
+            retention_days: Some(30),
+            ..Default::default()
+        };
+        let (mut project, _) = Project::new("acme".to_string(), String::new(), settings, 1);
+
+        let changes =
+            ProjectChanges::parse(
+                br#"{"allowedOrigins": ["https://App.Acme.com/"], "samplingRate": null, "timezone": "Europe/Berlin"}"#,
+                false,
+            )
+            .unwrap();
+        changes.apply(&mut project, 2);
+
+        assert_eq!(project.settings.allowed_origins, ["https://app.acme.com"]);
+        assert_eq!(project.settings.sampling_rate, None);
+        assert_eq!(project.settings.retention_days, Some(30));
+        assert_eq!(project.settings.timezone.as_deref(), Some("Europe/Berlin"));
+        assert_eq!(project.updated_at, 2);
+    }
+}
@@ -0,0 +1,362 @@
+//! Request handling: authentication and the `/projects` routes.
+//!
+//! Every request needs an `X-Admin-Token` matching `ADMIN_TOKEN`; without
+//! one configured the routes don't exist. Responses carry an
+//! `X-Request-Id`.
+//!
+//! - `GET /projects`: every project
+//! - `POST /projects`: creates a project, answering 201 with its `apiKey`
+//! - `GET /projects/{projectId}`
+//! - `PATCH /projects/{projectId}`: changes its settings
+//! - `POST /projects/{projectId}/rotate-key`: replaces its API key,
+//!   answering the new `apiKey`. Warm ingestion sandboxes may accept the
+//!   old one for up to `API_KEY_CACHE_TTL_SECS`, as they may keep old
+//!   settings
+//! - `DELETE /projects/{projectId}`: removes it and its key
+
+use chrono::Utc;
+use ingestion::admin::token_matches;
+use ingestion::request_id::{self, RequestId};
+use ingestion::shared::{create_error_response, create_response, env_var, header_value};
+use lambda_http::{Body, Error, Request, Response};
+use std::sync::Arc;
+
+use crate::changes::ProjectChanges;
+use crate::projects::{Project, ProjectSettings, ProjectStore};
+
+/// Configuration for the admin API
+#[derive(Debug, Clone, Default)]
+pub struct AdminApiConfig {
+    /// Token every request must carry; all routes answer 404 when unset
+    pub token: Option<String>,
+}
+
+impl AdminApiConfig {
+    pub fn from_env() -> Self {
+        Self {
+            token: env_var("ADMIN_TOKEN").filter(|t| !t.is_empty()),
+        }
+    }
+}
+
+/// Shared across requests
+pub struct AdminState {
+    pub config: AdminApiConfig,
+    pub projects: Box<dyn ProjectStore>,
+}
+
+/// Main Lambda handler
+pub async fn function_handler(request: Request, state: Arc<AdminState>) -> Result<Response<Body>, Error> {
+    let request_id = RequestId::from_request(&request);
+    let response = route(&request, &state).await?;
+    Ok(request_id::stamp(response, &request_id))
+}
+
+async fn route(request: &Request, state: &AdminState) -> Result<Response<Body>, Error> {
+    let Some(ref expected) = state.config.token else {
+        return Ok(create_error_response(404, "Not found"));
+    };
+    let given = header_value(request, "x-admin-token").unwrap_or_default();
+    if !token_matches(given, expected) {
+        tracing::warn!("Rejecting admin API call to {} with a bad admin token", request.uri().path());
+        return Ok(create_error_response(401, "Unauthorized"));
+    }
+
+    let path = request.uri().path().trim_end_matches('/');
+    let mut segments = path.split('/').skip_while(|segment| *segment != "projects");
+    if segments.next().is_none() {
+        return Ok(create_error_response(404, "Not found"));
+    }
+    let segments: Vec<&str> = segments.collect();
+    let now = Utc::now().timestamp_millis();
+
+    match (request.method().as_str(), segments.as_slice()) {
+        ("GET", []) => list(state).await,
+        ("POST", []) => create(request, state, now).await,
+        ("GET", [project_id]) => get(state, project_id).await,
+        ("PATCH", [project_id]) => update(request, state, project_id, now).await,
+        ("DELETE", [project_id]) => delete(state, project_id).await,
+        ("POST", [project_id, "rotate-key"]) => rotate_key(state, project_id, now).await,
+        (_, [] | [_] | [_, "rotate-key"]) => Ok(create_error_response(405, "Method not allowed")),
+        _ => Ok(create_error_response(404, "Not found")),
+    }
+}
+
+/// A project's body, with its plain API key when just issued
+fn project_body(project: &Project, api_key: Option<&str>) -> Result<serde_json::Value, Error> {
+    let mut body = serde_json::to_value(project)?;
+    if let (Some(body), Some(key)) = (body.as_object_mut(), api_key) {
+        body.insert("apiKey".to_string(), key.into());
+    }
+    Ok(body)
+}
+
+async fn list(state: &AdminState) -> Result<Response<Body>, Error> {
+    let projects = state.projects.list().await?;
+    Ok(create_response(200, serde_json::json!({ "projects": projects })))
+}
+
+async fn create(request: &Request, state: &AdminState, now: i64) -> Result<Response<Body>, Error> {
+    let changes = match ProjectChanges::parse(request.body(), true) {
+        Ok(changes) => changes,
+        Err(message) => return Ok(create_error_response(400, &message)),
+    };
+
+    let project_id = changes
+        .project_id
+        .clone()
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    let (mut project, key) = Project::new(project_id, String::new(), ProjectSettings::default(), now);
+    changes.apply(&mut project, now);
+    if !state.projects.create(&project).await? {
+        return Ok(create_error_response(409, "Project already exists"));
+    }
+    tracing::info!("Created project {}", project.project_id);
+    Ok(create_response(201, project_body(&project, Some(&key))?))
+}
+
+async fn get(state: &AdminState, project_id: &str) -> Result<Response<Body>, Error> {
+    Ok(match state.projects.get(project_id).await? {
+        Some(project) => create_response(200, project_body(&project, None)?),
+        None => create_error_response(404, "Project not found"),
+    })
+}
+
+async fn update(request: &Request, state: &AdminState, project_id: &str, now: i64) -> Result<Response<Body>, Error> {
+    let changes = match ProjectChanges::parse(request.body(), false) {
+        Ok(changes) => changes,
+        Err(message) => return Ok(create_error_response(400, &message)),
+    };
+    let Some(mut project) = state.projects.get(project_id).await? else {
+        return Ok(create_error_response(404, "Project not found"));
+    };
+
+    changes.apply(&mut project, now);
+    state.projects.update(&project, &project.key_hash).await?;
+    tracing::info!("Updated project {}", project_id);
+    Ok(create_response(200, project_body(&project, None)?))
+}
+
+async fn rotate_key(state: &AdminState, project_id: &str, now: i64) -> Result<Response<Body>, Error> {
+    let Some(mut project) = state.projects.get(project_id).await? else {
+        return Ok(create_error_response(404, "Project not found"));
+    };
+
+    let old_key_hash = project.key_hash.clone();
+    let key = project.rotate_key(now);
+    state.projects.update(&project, &old_key_hash).await?;
+    tracing::info!("Rotated the API key of project {}", project_id);
+    Ok(create_response(200, project_body(&project, Some(&key))?))
+}
+
+async fn delete(state: &AdminState, project_id: &str) -> Result<Response<Body>, Error> {
+    let Some(project) = state.projects.get(project_id).await? else {
+        return Ok(create_error_response(404, "Project not found"));
+    };
+
+    state.projects.delete(&project).await?;
+    tracing::info!("Deleted project {}", project_id);
+    Ok(create_response(200, serde_json::json!({ "projectId": project_id, "deleted": true })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use ingestion::auth::{key_hash, ApiKeyRecord, ApiKeyStore};
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    /// Projects and key records in memory, shared by clones, laid out the
+    /// way ingestion reads them
+    #[derive(Clone, Default)]
+    struct FakeProjects {
+        projects: Arc<Mutex<HashMap<String, Project>>>,
+        keys: Arc<Mutex<HashMap<String, ApiKeyRecord>>>,
+    }
+
+    impl FakeProjects {
+        fn save(&self, project: &Project) {
+            self.projects
+                .lock()
+                .unwrap()
+                .insert(project.project_id.clone(), project.clone());
+            self.keys.lock().unwrap().insert(
+                project.key_hash.clone(),
+                ApiKeyRecord {
+                    project_id: project.project_id.clone(),
+                    allowed_origins: project.settings.allowed_origins.clone(),
+                    sampling_rate: project.settings.sampling_rate,
+                    retention_days: project.settings.retention_days,
+                    timezone: project.settings.timezone.clone(),
+                },
+            );
+        }
+    }
+
+    #[async_trait]
+    impl ProjectStore for FakeProjects {
+        async fn create(&self, project: &Project) -> Result<bool, Error> {
+            if self.projects.lock().unwrap().contains_key(&project.project_id) {
+                return Ok(false);
+            }
+            self.save(project);
+            Ok(true)
+        }
+
+        async fn get(&self, project_id: &str) -> Result<Option<Project>, Error> {
+            Ok(self.projects.lock().unwrap().get(project_id).cloned())
+        }
+
+        async fn list(&self) -> Result<Vec<Project>, Error> {
+            let mut projects: Vec<_> = self.projects.lock().unwrap().values().cloned().collect();
+            projects.sort_by(|a, b| a.project_id.cmp(&b.project_id));
+            Ok(projects)
+        }
+
+        async fn update(&self, project: &Project, old_key_hash: &str) -> Result<(), Error> {
+            self.keys.lock().unwrap().remove(old_key_hash);
+            self.save(project);
+            Ok(())
+        }
+
+        async fn delete(&self, project: &Project) -> Result<(), Error> {
+            self.projects.lock().unwrap().remove(&project.project_id);
+            self.keys.lock().unwrap().remove(&project.key_hash);
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl ApiKeyStore for FakeProjects {
+        async fn get(&self, key_hash: &str) -> Result<Option<ApiKeyRecord>, Error> {
+            Ok(self.keys.lock().unwrap().get(key_hash).cloned())
+        }
+    }
+
+    fn state(fake: &FakeProjects) -> Arc<AdminState> {
+        Arc::new(AdminState {
+            config: AdminApiConfig {
+                token: Some("s3cret".to_string()),
+            },
+            projects: Box::new(fake.clone()),
+        })
+    }
+
+    fn request(method: &str, path: &str, body: &str) -> Request {
+        lambda_http::http::Request::builder()
+            .method(method)
+            .uri(path)
+            .header("X-Admin-Token", "s3cret")
+            .body(Body::Text(body.to_string()))
+            .unwrap()
+    }
+
+    fn body(response: &Response<Body>) -> serde_json::Value {
+        match response.body() {
+            Body::Text(text) => serde_json::from_str(text).unwrap(),
+            _ => panic!("expected a text body"),
+        }
+    }
+
+    async fn record(fake: &FakeProjects, key: &str) -> Option<ApiKeyRecord> {
+        ApiKeyStore::get(fake, &key_hash(key)).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_created_projects_authenticate_in_ingestion() {
+        let fake = FakeProjects::default();
+        let create = r#"{"projectId": "acme", "name": "Acme", "allowedOrigins": ["https://acme.com"], "samplingRate": 0.5}"#;
+        let response = function_handler(request("POST", "/prod/projects", create), state(&fake))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), 201);
+        let created = body(&response);
+        assert_eq!(created["projectId"], "acme");
+        assert_eq!(created["samplingRate"], 0.5);
+        let key = created["apiKey"].as_str().unwrap();
+        let record = record(&fake, key).await.unwrap();
+        assert_eq!(record.project_id, "acme");
+        assert_eq!(record.allowed_origins, ["https://acme.com"]);
+        assert_eq!(record.sampling_rate, Some(0.5));
+
+        let response = function_handler(request("POST", "/prod/projects", create), state(&fake))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 409);
+    }
+
+    #[tokio::test]
+    async fn test_updates_and_rotations_reach_the_key_record() {
+        let fake = FakeProjects::default();
+        let response = function_handler(request("POST", "/prod/projects", r#"{"projectId": "acme"}"#), state(&fake))
+            .await
+            .unwrap();
+        let old_key = body(&response)["apiKey"].as_str().unwrap().to_string();
+
+        let response = function_handler(
+            request("PATCH", "/prod/projects/acme", r#"{"retentionDays": 90}"#),
+            state(&fake),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(record(&fake, &old_key).await.unwrap().retention_days, Some(90));
+
+        let response = function_handler(request("POST", "/prod/projects/acme/rotate-key", ""), state(&fake))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let new_key = body(&response)["apiKey"].as_str().unwrap().to_string();
+        assert!(record(&fake, &old_key).await.is_none());
+        assert_eq!(record(&fake, &new_key).await.unwrap().retention_days, Some(90));
+
+        let response = function_handler(request("GET", "/prod/projects", ""), state(&fake))
+            .await
+            .unwrap();
+        let listed = body(&response);
+        assert_eq!(listed["projects"][0]["retentionDays"], 90);
+        assert!(listed["projects"][0].get("apiKey").is_none());
+
+        let response = function_handler(request("DELETE", "/prod/projects/acme", ""), state(&fake))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert!(record(&fake, &new_key).await.is_none());
+        let response = function_handler(request("GET", "/prod/projects/acme", ""), state(&fake))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_rejects_bad_tokens_and_requests() {
+        let fake = FakeProjects::default();
+        let mut unauthorized = request("GET", "/prod/projects", "");
+        unauthorized.headers_mut().insert("x-admin-token", "wrong".parse().unwrap());
+        let response = function_handler(unauthorized, state(&fake)).await.unwrap();
+        assert_eq!(response.status(), 401);
+        assert!(body(&response)["requestId"].is_string());
+
+        let response = function_handler(request("POST", "/prod/projects", r#"{"samplingRate": 2}"#), state(&fake))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+        let response = function_handler(request("PUT", "/prod/projects/acme", "{}"), state(&fake))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 405);
+        let response = function_handler(request("PATCH", "/prod/projects/nope", "{}"), state(&fake))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404);
+
+        let disabled = Arc::new(AdminState {
+            config: AdminApiConfig::default(),
+            projects: Box::new(fake.clone()),
+        });
+        let response = function_handler(request("GET", "/prod/projects", ""), disabled).await.unwrap();
+        assert_eq!(response.status(), 404);
+    }
+}
@@ -0,0 +1,12 @@
+//! Admin API for projects and their API keys.
+//!
+//! Creates, lists, changes and deletes projects, rotates their API keys
+//! and sets what ingestion enforces for them: allowed browser origins,
+//! sampling rate and retention days (see [`handler`] and [`changes`]).
+//! Projects are stored in the projects table ingestion's API key checks
+//! read (see [`projects`]), so a change reaches ingestion within its
+//! `API_KEY_CACHE_TTL_SECS`.
+
+pub mod changes;
+pub mod handler;
+pub mod projects;
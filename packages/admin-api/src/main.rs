@@ -0,0 +1,36 @@
+use lambda_http::{run, service_fn, Error, Request};
+use std::sync::Arc;
+
+use admin_api::handler::{function_handler, AdminApiConfig, AdminState};
+use admin_api::projects::DynamoProjectStore;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use ingestion::shared::env_var;
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    // Initialize tracing
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"))
+        )
+        .json()
+        .init();
+
+    let config = AdminApiConfig::from_env();
+    if config.token.is_none() {
+        tracing::warn!("ADMIN_TOKEN not set, every request will be answered 404");
+    }
+    let table = env_var("API_KEYS_TABLE").ok_or("API_KEYS_TABLE must name the projects table")?;
+    let aws = aws_config::load_from_env().await;
+    let state = Arc::new(AdminState {
+        config,
+        projects: Box::new(DynamoProjectStore::new(DynamoClient::new(&aws), table)),
+    });
+
+    run(service_fn(move |request: Request| {
+        let state = state.clone();
+        async move { function_handler(request, state).await }
+    }))
+    .await
+}
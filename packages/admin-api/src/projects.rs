@@ -0,0 +1,303 @@
+//! Projects and where they're stored.
+//!
+//! A project lives in the projects table ingestion authenticates against
+//! (`API_KEYS_TABLE`) as two items: the project itself under
+//! `project#{projectId}`, and its current API key under the key's SHA-256
+//! in the layout [`DynamoApiKeyStore`](ingestion::auth::DynamoApiKeyStore)
+//! reads, with the project's settings copied onto it so ingestion needs a
+//! single lookup. Both are written in one transaction. Plain keys are never
+//! stored; they're returned once, when created or rotated.
+
+use async_trait::async_trait;
+use aws_sdk_dynamodb::types::{AttributeValue, Delete, Put, TransactWriteItem};
+use aws_sdk_dynamodb::Client as DynamoClient;
+use ingestion::auth::key_hash;
+use lambda_http::Error;
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// Prefix of project items' partition keys
+const PROJECT_PREFIX: &str = "project#";
+
+/// What ingestion enforces for a project
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProjectSettings {
+    /// Browser origins allowed to send the project's events, normalized;
+    /// any when empty
+    pub allowed_origins: Vec<String>,
+    /// Share of visitors whose events are kept, 0 to 1; all when unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sampling_rate: Option<f64>,
+    /// Days raw events are kept; the lake's default when unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retention_days: Option<u32>,
+    /// IANA timezone the project's days are reported in; UTC when unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+}
+
+/// A project
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Project {
+    pub project_id: String,
+    pub name: String,
+    /// SHA-256 of the current API key
+    #[serde(skip)]
+    pub key_hash: String,
+    #[serde(flatten)]
+    pub settings: ProjectSettings,
+    /// Epoch milliseconds
+    pub created_at: i64,
+    /// Epoch milliseconds
+    pub updated_at: i64,
+}
+
+/// A new random API key
+pub fn generate_key() -> String {
+    format!("pk_live_{}", uuid::Uuid::new_v4().simple())
+}
+
+impl Project {
+    /// A project with a fresh API key, returned alongside it
+    pub fn new(project_id: String, name: String, settings: ProjectSettings, now: i64) -> (Self, String) {
+        let key = generate_key();
+        let project = Self {
+            project_id,
+            name,
+            key_hash: key_hash(&key),
+            settings,
+            created_at: now,
+            updated_at: now,
+        };
+        (project, key)
+    }
+
+    /// Replaces the API key, returning the new one
+    pub fn rotate_key(&mut self, now: i64) -> String {
+        let key = generate_key();
+        self.key_hash = key_hash(&key);
+        self.updated_at = now;
+        key
+    }
+}
+
+/// Project storage
+#[async_trait]
+pub trait ProjectStore: Send + Sync {
+    /// Stores a new project; false when its id is taken
+    async fn create(&self, project: &Project) -> Result<bool, Error>;
+    async fn get(&self, project_id: &str) -> Result<Option<Project>, Error>;
+    async fn list(&self) -> Result<Vec<Project>, Error>;
+    /// Saves a changed project, dropping `old_key_hash` when the key changed
+    async fn update(&self, project: &Project, old_key_hash: &str) -> Result<(), Error>;
+    /// Removes a project and its key
+    async fn delete(&self, project: &Project) -> Result<(), Error>;
+}
+
+/// DynamoDB-backed store, in the projects table
+pub struct DynamoProjectStore {
+    client: DynamoClient,
+    table_name: String,
+}
+
+impl DynamoProjectStore {
+    pub fn new(client: DynamoClient, table_name: String) -> Self {
+        Self { client, table_name }
+    }
+
+    fn put(&self, item: HashMap<String, AttributeValue>, condition: Option<&str>) -> Result<TransactWriteItem, Error> {
+        let put = Put::builder()
+            .table_name(&self.table_name)
+            .set_item(Some(item))
+            .set_condition_expression(condition.map(String::from))
+            .build()?;
+        Ok(TransactWriteItem::builder().put(put).build())
+    }
+
+    fn delete(&self, pk: String) -> Result<TransactWriteItem, Error> {
+        let delete = Delete::builder()
+            .table_name(&self.table_name)
+            .key("pk", AttributeValue::S(pk))
+            .build()?;
+        Ok(TransactWriteItem::builder().delete(delete).build())
+    }
+}
+
+#[async_trait]
+impl ProjectStore for DynamoProjectStore {
+    async fn create(&self, project: &Project) -> Result<bool, Error> {
+        let result = self
+            .client
+            .transact_write_items()
+            .transact_items(self.put(project_item(project), Some("attribute_not_exists(pk)"))?)
+            .transact_items(self.put(key_item(project), None)?)
+            .send()
+            .await;
+        match result {
+            Ok(_) => Ok(true),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_transaction_canceled_exception()) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn get(&self, project_id: &str) -> Result<Option<Project>, Error> {
+        let output = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("pk", AttributeValue::S(format!("{}{}", PROJECT_PREFIX, project_id)))
+            .consistent_read(true)
+            .send()
+            .await?;
+        Ok(output.item().and_then(project_from_item))
+    }
+
+    async fn list(&self) -> Result<Vec<Project>, Error> {
+        let mut projects = Vec::new();
+        let mut start_key = None;
+        loop {
+            let output = self
+                .client
+                .scan()
+                .table_name(&self.table_name)
+                .filter_expression("begins_with(pk, :prefix)")
+                .expression_attribute_values(":prefix", AttributeValue::S(PROJECT_PREFIX.to_string()))
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await?;
+            projects.extend(output.items().iter().filter_map(project_from_item));
+            start_key = output.last_evaluated_key().cloned();
+            if start_key.is_none() {
+                break;
+            }
+        }
+        projects.sort_by(|a, b| a.project_id.cmp(&b.project_id));
+        Ok(projects)
+    }
+
+    async fn update(&self, project: &Project, old_key_hash: &str) -> Result<(), Error> {
+        let mut request = self
+            .client
+            .transact_write_items()
+            .transact_items(self.put(project_item(project), None)?)
+            .transact_items(self.put(key_item(project), None)?);
+        if old_key_hash != project.key_hash {
+            request = request.transact_items(self.delete(old_key_hash.to_string())?);
+        }
+        request.send().await?;
+        Ok(())
+    }
+
+    async fn delete(&self, project: &Project) -> Result<(), Error> {
+        self.client
+            .transact_write_items()
+            .transact_items(self.delete(format!("{}{}", PROJECT_PREFIX, project.project_id))?)
+            .transact_items(self.delete(project.key_hash.clone())?)
+            .send()
+            .await?;
+        Ok(())
+    }
+}
+
+/// The settings' attributes, shared by both items
+fn settings_attributes(settings: &ProjectSettings) -> HashMap<String, AttributeValue> {
+    let mut item = HashMap::new();
+    // String sets can't be empty
+    if !settings.allowed_origins.is_empty() {
+        item.insert("allowed_origins".to_string(), AttributeValue::Ss(settings.allowed_origins.clone()));
+    }
+    if let Some(rate) = settings.sampling_rate {
+        item.insert("sampling_rate".to_string(), AttributeValue::N(rate.to_string()));
+    }
+    if let Some(days) = settings.retention_days {
+        item.insert("retention_days".to_string(), AttributeValue::N(days.to_string()));
+    }
+    if let Some(ref timezone) = settings.timezone {
+        item.insert("timezone".to_string(), AttributeValue::S(timezone.clone()));
+    }
+    item
+}
+
+fn project_item(project: &Project) -> HashMap<String, AttributeValue> {
+    let mut item = settings_attributes(&project.settings);
+    item.extend([
+        ("pk".to_string(), AttributeValue::S(format!("{}{}", PROJECT_PREFIX, project.project_id))),
+        ("project_id".to_string(), AttributeValue::S(project.project_id.clone())),
+        ("name".to_string(), AttributeValue::S(project.name.clone())),
+        ("key_hash".to_string(), AttributeValue::S(project.key_hash.clone())),
+        ("created_at".to_string(), AttributeValue::N(project.created_at.to_string())),
+        ("updated_at".to_string(), AttributeValue::N(project.updated_at.to_string())),
+    ]);
+    item
+}
+
+fn key_item(project: &Project) -> HashMap<String, AttributeValue> {
+    let mut item = settings_attributes(&project.settings);
+    item.extend([
+        ("pk".to_string(), AttributeValue::S(project.key_hash.clone())),
+        ("project_id".to_string(), AttributeValue::S(project.project_id.clone())),
+    ]);
+    item
+}
+
+fn project_from_item(item: &HashMap<String, AttributeValue>) -> Option<Project> {
+    let string = |name: &str| item.get(name).and_then(|v| v.as_s().ok()).cloned();
+    let number = |name: &str| item.get(name).and_then(|v| v.as_n().ok());
+    Some(Project {
+        project_id: string("project_id")?,
+        name: string("name").unwrap_or_default(),
+        key_hash: string("key_hash")?,
+        settings: ProjectSettings {
+            allowed_origins: item
+                .get("allowed_origins")
+                .and_then(|v| v.as_ss().ok())
+                .cloned()
+                .unwrap_or_default(),
+            sampling_rate: number("sampling_rate").and_then(|n| n.parse().ok()),
+            retention_days: number("retention_days").and_then(|n| n.parse().ok()),
+            timezone: string("timezone"),
+        },
+        created_at: number("created_at").and_then(|n| n.parse().ok()).unwrap_or_default(),
+        updated_at: number("updated_at").and_then(|n| n.parse().ok()).unwrap_or_default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_items_round_trip_and_keys_carry_the_settings() {
+        let settings = ProjectSettings {
+            allowed_origins: vec!["https://app.example.com".to_string()],
+            sampling_rate: Some(0.5),
+            retention_days: Some(90),
+            timezone: Some("America/New_York".to_string()),
+        };
+        let (project, key) = Project::new("acme".to_string(), "Acme".to_string(), settings, 1_700_000_000_000);
+
+        assert!(key.starts_with("pk_live_"));
+        assert_eq!(project.key_hash, key_hash(&key));
+        assert_eq!(project_from_item(&project_item(&project)), Some(project.clone()));
+
+        let key_item = key_item(&project);
+        assert_eq!(key_item["pk"].as_s().unwrap(), &project.key_hash);
+        assert_eq!(key_item["project_id"].as_s().unwrap(), "acme");
+        assert_eq!(key_item["sampling_rate"].as_n().unwrap(), "0.5");
+        assert_eq!(key_item["retention_days"].as_n().unwrap(), "90");
+        assert_eq!(key_item["timezone"].as_s().unwrap(), "America/New_York");
+    }
+
+    #[test]
+    fn test_rotation_replaces_the_key() {
+        let (mut project, key) = Project::new("acme".to_string(), String::new(), ProjectSettings::default(), 1);
+        let rotated = project.rotate_key(2);
+
+        assert_ne!(rotated, key);
+        assert_eq!(project.key_hash, key_hash(&rotated));
+        assert_eq!(project.updated_at, 2);
+        assert!(!key_item(&project).contains_key("allowed_origins"));
+    }
+}
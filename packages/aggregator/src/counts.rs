@@ -0,0 +1,249 @@
+//! Tallying a batch of events into per-bucket counters.
+//!
+//! Each event counts toward the minute, the hour and the day it happened
+//! in, for its project: every event, pageviews (the `AGGREGATES_PAGEVIEW_EVENTS` types,
+//! default `pageview`), the distinct sessions seen and the views per page
+//! path. Events flagged as bots are left out, as are canaries, which the
+//! handler only reports the arrival of. A batch is tallied in memory
+//! first so each counter gets one update however many events it covers.
+//!
+//! Minutes and hours are UTC. Days are the project's own: the `local_date`
+//! ingestion stamps in the project's reporting timezone (see
+//! `ingestion::enrichment::reporting_day`), else the UTC date.
+
+use chrono::{DateTime, Utc};
+use ingestion::canary::CanaryConfig;
+use ingestion::enrichment::duplicate_view::session_key;
+use ingestion::metrics::MetricsConfig;
+use ingestion::models::IngestEventPayload;
+use ingestion::shared::{env_list, env_or, env_var};
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
+
+/// Configuration for the aggregator
+#[derive(Debug, Clone)]
+pub struct AggregatorConfig {
+    pub table_name: String,
+    /// Event types counted as pageviews
+    pub pageview_events: Vec<String>,
+    /// How long minute buckets are kept
+    pub minute_ttl: Duration,
+    /// How long hour buckets are kept
+    pub hour_ttl: Duration,
+    /// How long day buckets are kept
+    pub day_ttl: Duration,
+    /// Table of visitors' recent activity, if realtime presence is kept
+    pub realtime_table: Option<String>,
+    /// How long presence is kept
+    pub realtime_ttl: Duration,
+    /// Which events are canaries, reported rather than counted
+    pub canary: CanaryConfig,
+    /// Where canary arrivals are reported
+    pub metrics: MetricsConfig,
+}
+
+impl Default for AggregatorConfig {
+    fn default() -> Self {
+        Self {
+            table_name: String::new(),
+            pageview_events: vec!["pageview".to_string()],
+            minute_ttl: Duration::from_secs(48 * 3600),
+            hour_ttl: Duration::from_secs(35 * 86_400),
+            day_ttl: Duration::from_secs(400 * 86_400),
+            realtime_table: None,
+            realtime_ttl: Duration::from_secs(30 * 60),
+            canary: CanaryConfig::default(),
+            metrics: MetricsConfig::default(),
+        }
+    }
+}
+
+impl AggregatorConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let pageview_events = env_list("AGGREGATES_PAGEVIEW_EVENTS");
+        Self {
+            table_name: env_var("AGGREGATES_TABLE").unwrap_or_default(),
+            pageview_events: if pageview_events.is_empty() { defaults.pageview_events } else { pageview_events },
+            minute_ttl: Duration::from_secs(3600 * env_or("AGGREGATES_MINUTE_TTL_HOURS", 48)),
+            hour_ttl: Duration::from_secs(86_400 * env_or("AGGREGATES_HOUR_TTL_DAYS", 35)),
+            day_ttl: Duration::from_secs(86_400 * env_or("AGGREGATES_DAY_TTL_DAYS", 400)),
+            realtime_table: env_var("REALTIME_TABLE").filter(|table| !table.is_empty()),
+            realtime_ttl: Duration::from_secs(60 * env_or("REALTIME_TTL_MINUTES", 30)),
+            canary: CanaryConfig::from_env(),
+            metrics: MetricsConfig::from_env(),
+        }
+    }
+}
+
+/// Width of a counter bucket
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Granularity {
+    Minute,
+    Hour,
+    Day,
+}
+
+impl Granularity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Minute => "minute",
+            Self::Hour => "hour",
+            Self::Day => "day",
+        }
+    }
+
+    /// Bucket label of a time, e.g. `2024-05-01T12:34`, `2024-05-01T12` or
+    /// `2024-05-01`
+    pub fn bucket(self, time: DateTime<Utc>) -> String {
+        match self {
+            Self::Minute => time.format("%Y-%m-%dT%H:%M").to_string(),
+            Self::Hour => time.format("%Y-%m-%dT%H").to_string(),
+            Self::Day => time.format("%Y-%m-%d").to_string(),
+        }
+    }
+}
+
+/// A project's bucket
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BucketKey {
+    pub project_id: String,
+    pub granularity: Granularity,
+    pub bucket: String,
+}
+
+impl BucketKey {
+    /// Partition key of the bucket's items
+    pub fn partition_key(&self) -> String {
+        format!("{}#{}#{}", self.project_id, self.granularity.as_str(), self.bucket)
+    }
+}
+
+/// Counters of one bucket
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BucketCounts {
+    pub events: u64,
+    pub pageviews: u64,
+    /// Session keys seen; counted as distinct across batches by the store
+    pub sessions: BTreeSet<String>,
+    /// Pageviews by page path
+    pub pages: BTreeMap<String, u64>,
+}
+
+/// Counters of a batch, by bucket
+#[derive(Debug, Default)]
+pub struct Tally {
+    pub buckets: BTreeMap<BucketKey, BucketCounts>,
+}
+
+impl Tally {
+    pub fn add(&mut self, event: &IngestEventPayload, config: &AggregatorConfig) {
+        let context = event.context.as_ref();
+        if context.is_some_and(|c| c.is_bot == Some(true)) {
+            return;
+        }
+        let Some(time) = DateTime::from_timestamp_millis(event.timestamp) else {
+            return;
+        };
+        let is_pageview = config.pageview_events.contains(&event.event_type);
+        let session = session_key(event);
+        let path = is_pageview.then(|| page_path(event)).flatten();
+
+        for granularity in [Granularity::Minute, Granularity::Hour, Granularity::Day] {
+            let bucket = match event.local_date {
+                Some(ref date) if granularity == Granularity::Day => date.clone(),
+                _ => granularity.bucket(time),
+            };
+            let key = BucketKey {
+                project_id: event.project_id.clone(),
+                granularity,
+                bucket,
+            };
+            let counts = self.buckets.entry(key).or_default();
+            counts.events += 1;
+            if is_pageview {
+                counts.pageviews += 1;
+            }
+            if let Some(ref session) = session {
+                counts.sessions.insert(session.clone());
+            }
+            if let Some(ref path) = path {
+                *counts.pages.entry(path.clone()).or_default() += 1;
+            }
+        }
+    }
+}
+
+/// Path of the viewed page: `context.page.path`, else the path of its url
+pub fn page_path(event: &IngestEventPayload) -> Option<String> {
+    let page = event.context.as_ref()?.page.as_ref()?;
+    match page.path {
+        Some(ref path) if !path.is_empty() => Some(path.clone()),
+        _ => Some(url::Url::parse(page.url.as_deref()?).ok()?.path().to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn event(value: serde_json::Value) -> IngestEventPayload {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_tallies_minute_and_hour_buckets() {
+        let config = AggregatorConfig::default();
+        let mut tally = Tally::default();
+        // 2023-11-14T22:13:20Z and a minute later
+        let at = |ms: i64, extra: serde_json::Value| {
+            let mut value = json!({"projectId": "p", "eventType": "pageview", "timestamp": ms, "anonymousId": "v1"});
+            value.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+            event(value)
+        };
+        tally.add(&at(1_700_000_000_000, json!({"context": {"page": {"url": "https://a.com/pricing?x=1"}}})), &config);
+        tally.add(&at(1_700_000_060_000, json!({"context": {"page": {"path": "/pricing"}}})), &config);
+        tally.add(&at(1_700_000_060_000, json!({"eventType": "click", "anonymousId": "v2"})), &config);
+        tally.add(&at(1_700_000_060_000, json!({"context": {"isBot": true}})), &config);
+
+        let hour = &tally.buckets[&BucketKey {
+            project_id: "p".to_string(),
+            granularity: Granularity::Hour,
+            bucket: "2023-11-14T22".to_string(),
+        }];
+        assert_eq!((hour.events, hour.pageviews), (3, 2));
+        assert_eq!(hour.sessions.len(), 2);
+        assert_eq!(hour.pages, BTreeMap::from([("/pricing".to_string(), 2)]));
+
+        let minutes: Vec<_> = tally
+            .buckets
+            .iter()
+            .filter(|(key, _)| key.granularity == Granularity::Minute)
+            .map(|(key, counts)| (key.partition_key(), counts.events))
+            .collect();
+        assert_eq!(minutes, [("p#minute#2023-11-14T22:13".to_string(), 1), ("p#minute#2023-11-14T22:14".to_string(), 2)]);
+    }
+
+    #[test]
+    fn test_days_are_the_projects_local_dates() {
+        let config = AggregatorConfig::default();
+        let mut tally = Tally::default();
+        // 2023-11-14T22:13:20Z, already the 15th in Tokyo
+        let at = |extra: serde_json::Value| {
+            let mut value = json!({"projectId": "p", "eventType": "click", "timestamp": 1_700_000_000_000i64});
+            value.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+            event(value)
+        };
+        tally.add(&at(json!({"localDate": "2023-11-15", "localHour": 7})), &config);
+        tally.add(&at(json!({})), &config);
+
+        let days: Vec<_> = tally
+            .buckets
+            .iter()
+            .filter(|(key, _)| key.granularity == Granularity::Day)
+            .map(|(key, counts)| (key.partition_key(), counts.events))
+            .collect();
+        assert_eq!(days, [("p#day#2023-11-14".to_string(), 1), ("p#day#2023-11-15".to_string(), 1)]);
+    }
+}
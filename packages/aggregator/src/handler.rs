@@ -0,0 +1,205 @@
+//! The Kinesis batch handler.
+//!
+//! A batch is decoded (unpacking KPL aggregates), tallied, and each bucket's
+//! counts are added to the store. The counters of a batch span many items,
+//! so a failure can't be pinned to a record: the whole batch is reported
+//! failed and retried. Session counts stay exact across retries; event,
+//! pageview and page counters of buckets already written before the
+//! failure are counted again. They're meant for a live view, with the raw
+//! events as the record of truth.
+//!
+//! Canaries are reported as having arrived (see `ingestion::canary`) and
+//! not counted.
+//!
+//! Presence, when kept, is written after the counters and on a best-effort
+//! basis: a failure is logged rather than retried, since a retry would
+//! count the batch again for a view that's stale within minutes anyway.
+
+use aws_lambda_events::event::kinesis::KinesisEvent;
+use aws_lambda_events::event::streams::{KinesisBatchItemFailure, KinesisEventResponse};
+use ingestion::aggregation;
+use ingestion::canary;
+use ingestion::models::IngestEventPayload;
+
+use crate::counts::{AggregatorConfig, Granularity, Tally};
+use crate::realtime::{Presence, PresenceStore};
+use crate::store::CounterStore;
+
+/// Counts a batch, reporting it failed if the store wouldn't take it, and
+/// records who's active in `presence_store` if given
+pub async fn handle(
+    event: KinesisEvent,
+    store: &dyn CounterStore,
+    presence_store: Option<&dyn PresenceStore>,
+    config: &AggregatorConfig,
+) -> KinesisEventResponse {
+    let now_ms = chrono::Utc::now().timestamp_millis();
+    let mut tally = Tally::default();
+    let mut presence = Presence::default();
+    let mut counted = 0;
+    for record in &event.records {
+        for data in aggregation::decode(&record.kinesis.data.0) {
+            match serde_json::from_slice::<IngestEventPayload>(&data) {
+                Ok(event) if canary::observe(&event, "aggregator", now_ms, &config.canary, &config.metrics) => {}
+                Ok(event) => {
+                    tally.add(&event, config);
+                    presence.add(&event, config, now_ms);
+                    counted += 1;
+                }
+                Err(e) => tracing::warn!("Skipping a record that isn't a JSON event: {}", e),
+            }
+        }
+    }
+
+    let now = now_ms / 1000;
+    for (key, counts) in &tally.buckets {
+        let ttl = match key.granularity {
+            Granularity::Minute => config.minute_ttl,
+            Granularity::Hour => config.hour_ttl,
+            Granularity::Day => config.day_ttl,
+        };
+        if let Err(e) = store.add(key, counts, now + ttl.as_secs() as i64).await {
+            tracing::error!("Failed to update {}, retrying the batch: {}", key.partition_key(), e);
+            let first = event.records.first().and_then(|record| record.kinesis.sequence_number.clone());
+            return KinesisEventResponse {
+                batch_item_failures: vec![KinesisBatchItemFailure { item_identifier: first }],
+            };
+        }
+    }
+
+    if let Some(presence_store) = presence_store {
+        let expires_at = now + config.realtime_ttl.as_secs() as i64;
+        for (key, activity) in &presence.visitors {
+            if let Err(e) = presence_store.touch(key, activity, expires_at).await {
+                tracing::warn!("Failed to record presence in {}: {}", key.project_id, e);
+                break;
+            }
+        }
+    }
+
+    tracing::info!("Counted {} events into {} buckets", counted, tally.buckets.len());
+    KinesisEventResponse {
+        batch_item_failures: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::counts::{BucketCounts, BucketKey};
+    use crate::realtime::{Activity, PresenceKey};
+    use async_trait::async_trait;
+    use lambda_runtime::Error;
+    use serde_json::json;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct FakeStore {
+        fail: bool,
+        added: Mutex<Vec<(String, u64)>>,
+    }
+
+    #[async_trait]
+    impl CounterStore for FakeStore {
+        async fn add(&self, key: &BucketKey, counts: &BucketCounts, _expires_at: i64) -> Result<(), Error> {
+            if self.fail {
+                return Err("ProvisionedThroughputExceededException".into());
+            }
+            self.added.lock().unwrap().push((key.partition_key(), counts.events));
+            Ok(())
+        }
+    }
+
+    #[derive(Default)]
+    struct FakePresence {
+        touched: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl PresenceStore for FakePresence {
+        async fn touch(&self, key: &PresenceKey, _activity: &Activity, _expires_at: i64) -> Result<(), Error> {
+            self.touched.lock().unwrap().push(key.sort_key());
+            Ok(())
+        }
+    }
+
+    fn batch(records: &[&str]) -> KinesisEvent {
+        let records: Vec<_> = records
+            .iter()
+            .enumerate()
+            .map(|(index, data)| {
+                let data = aws_lambda_events::encodings::Base64Data(data.as_bytes().to_vec());
+                json!({
+                    "kinesis": {
+                        "sequenceNumber": index.to_string(),
+                        "data": data,
+                        "approximateArrivalTimestamp": 1_700_000_000.0,
+                    },
+                })
+            })
+            .collect();
+        serde_json::from_value(json!({ "Records": records })).unwrap()
+    }
+
+    const EVENT: &str = r#"{"projectId":"p","eventType":"pageview","timestamp":1700000000000}"#;
+
+    #[tokio::test]
+    async fn test_counts_a_batch_into_buckets() {
+        let store = FakeStore::default();
+        let response = handle(batch(&[EVENT, "garbage", EVENT]), &store, None, &AggregatorConfig::default()).await;
+
+        assert!(response.batch_item_failures.is_empty());
+        assert_eq!(
+            *store.added.lock().unwrap(),
+            [
+                ("p#minute#2023-11-14T22:13".to_string(), 2),
+                ("p#hour#2023-11-14T22".to_string(), 2),
+                ("p#day#2023-11-14".to_string(), 2),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_canaries_arent_counted() {
+        let canary = r#"{"projectId":"__canary","eventType":"$canary","timestamp":1700000000000}"#;
+        let store = FakeStore::default();
+        let response = handle(batch(&[EVENT, canary]), &store, None, &AggregatorConfig::default()).await;
+
+        assert!(response.batch_item_failures.is_empty());
+        assert_eq!(store.added.lock().unwrap()[0], ("p#minute#2023-11-14T22:13".to_string(), 1));
+        assert_eq!(store.added.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_store_failure_retries_the_batch() {
+        let store = FakeStore {
+            fail: true,
+            ..Default::default()
+        };
+        let response = handle(batch(&[EVENT, EVENT]), &store, None, &AggregatorConfig::default()).await;
+
+        assert_eq!(
+            response.batch_item_failures,
+            [KinesisBatchItemFailure {
+                item_identifier: Some("0".to_string()),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_records_recent_visitors() {
+        let now = chrono::Utc::now().timestamp_millis();
+        let recent = format!(
+            r#"{{"projectId":"p","eventType":"pageview","timestamp":{},"anonymousId":"v1"}}"#,
+            now
+        );
+        let store = FakeStore::default();
+        let presence = FakePresence::default();
+        let response = handle(batch(&[EVENT, &recent]), &store, Some(&presence), &AggregatorConfig::default()).await;
+
+        assert!(response.batch_item_failures.is_empty());
+        let touched = presence.touched.lock().unwrap();
+        assert_eq!(touched.len(), 1);
+        assert!(touched[0].ends_with("#v1"));
+    }
+}
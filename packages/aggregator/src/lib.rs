@@ -0,0 +1,13 @@
+//! Kinesis → DynamoDB real-time aggregates.
+//!
+//! Consumes the ingest stream in Lambda batches and keeps per-project
+//! counters by minute, by hour and by the project's local day (events,
+//! pageviews, unique sessions and views per page) in DynamoDB, for a
+//! realtime dashboard that never reads raw events. See [`counts`] for what is counted and [`store`] for the
+//! table layout. It can also keep who's active right now (see
+//! [`realtime`]).
+
+pub mod counts;
+pub mod handler;
+pub mod realtime;
+pub mod store;
@@ -0,0 +1,39 @@
+use lambda_runtime::{run, service_fn, Error, LambdaEvent};
+use std::sync::Arc;
+
+use aggregator::counts::AggregatorConfig;
+use aggregator::handler::handle;
+use aggregator::realtime::{DynamoPresenceStore, PresenceStore};
+use aggregator::store::DynamoCounterStore;
+use aws_lambda_events::event::kinesis::KinesisEvent;
+use aws_sdk_dynamodb::Client as DynamoClient;
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    // Initialize tracing
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"))
+        )
+        .json()
+        .init();
+
+    let config = Arc::new(AggregatorConfig::from_env());
+    if config.table_name.is_empty() {
+        return Err("AGGREGATES_TABLE environment variable not set".into());
+    }
+    let aws = aws_config::load_from_env().await;
+    let client = DynamoClient::new(&aws);
+    let store = Arc::new(DynamoCounterStore::new(client.clone(), config.table_name.clone()));
+    let presence: Option<Arc<dyn PresenceStore>> = config
+        .realtime_table
+        .clone()
+        .map(|table| Arc::new(DynamoPresenceStore::new(client, table)) as Arc<dyn PresenceStore>);
+
+    run(service_fn(move |event: LambdaEvent<KinesisEvent>| {
+        let (store, presence, config) = (store.clone(), presence.clone(), config.clone());
+        async move { Ok::<_, Error>(handle(event.payload, store.as_ref(), presence.as_deref(), &config).await) }
+    }))
+    .await
+}
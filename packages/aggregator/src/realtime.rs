@@ -0,0 +1,192 @@
+//! Who's on a project right now, for the query API's `/realtime`.
+//!
+//! Alongside the counters, each visitor's latest page per minute is kept in
+//! a second table (`REALTIME_TABLE`, optional) under the project's partition
+//! key `pk`, with `sk` `{minute}#{visitor}`: a read of the last few minutes
+//! is one `Query` on `sk >= {first minute}`, and the visitors it returns
+//! are counted once each by the reader. Items carry `last_seen` (epoch
+//! milliseconds), `page` when there is one and an `expires_at` a little
+//! past the longest window a dashboard may ask for
+//! (`REALTIME_TTL_MINUTES`, default 30). Events older than that aren't
+//! recorded.
+
+use async_trait::async_trait;
+use aws_sdk_dynamodb::error::SdkError;
+use aws_sdk_dynamodb::operation::update_item::UpdateItemError;
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use chrono::DateTime;
+use ingestion::models::IngestEventPayload;
+use lambda_runtime::Error;
+use std::collections::BTreeMap;
+
+use crate::counts::{page_path, AggregatorConfig, Granularity};
+
+/// A visitor in one minute of a project
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PresenceKey {
+    pub project_id: String,
+    /// Minute bucket, as [`Granularity::Minute`] labels it
+    pub minute: String,
+    pub visitor: String,
+}
+
+impl PresenceKey {
+    pub fn sort_key(&self) -> String {
+        sort_key(&self.minute, &self.visitor)
+    }
+}
+
+/// Sort key of a visitor's item for a minute
+pub fn sort_key(minute: &str, visitor: &str) -> String {
+    format!("{}#{}", minute, visitor)
+}
+
+/// What a visitor was last seen doing in a minute
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Activity {
+    /// Epoch milliseconds
+    pub last_seen: i64,
+    pub page: Option<String>,
+}
+
+/// Who an event is from: the anonymous id, else the user id
+pub fn visitor(event: &IngestEventPayload) -> Option<&str> {
+    event
+        .anonymous_id
+        .as_deref()
+        .or(event.user_id.as_deref())
+        .filter(|id| !id.is_empty())
+}
+
+/// Visitors' latest activity in a batch, by project and minute
+#[derive(Debug, Default)]
+pub struct Presence {
+    pub visitors: BTreeMap<PresenceKey, Activity>,
+}
+
+impl Presence {
+    /// Records an event seen at `now` (epoch milliseconds), unless it's from
+    /// a bot or too old to show
+    pub fn add(&mut self, event: &IngestEventPayload, config: &AggregatorConfig, now: i64) {
+        if event.context.as_ref().is_some_and(|c| c.is_bot == Some(true)) {
+            return;
+        }
+        if event.timestamp < now - config.realtime_ttl.as_millis() as i64 {
+            return;
+        }
+        let (Some(visitor), Some(time)) = (visitor(event), DateTime::from_timestamp_millis(event.timestamp)) else {
+            return;
+        };
+        let key = PresenceKey {
+            project_id: event.project_id.clone(),
+            minute: Granularity::Minute.bucket(time),
+            visitor: visitor.to_string(),
+        };
+        let page = config
+            .pageview_events
+            .contains(&event.event_type)
+            .then(|| page_path(event))
+            .flatten();
+        match self.visitors.get_mut(&key) {
+            Some(activity) if activity.last_seen > event.timestamp => {}
+            Some(activity) => {
+                activity.last_seen = event.timestamp;
+                activity.page = page.or(activity.page.take());
+            }
+            None => {
+                self.visitors.insert(key, Activity {
+                    last_seen: event.timestamp,
+                    page,
+                });
+            }
+        }
+    }
+}
+
+/// Where visitors' presence is kept
+#[async_trait]
+pub trait PresenceStore: Send + Sync {
+    /// Records a visitor's activity in a minute, keeping it until
+    /// `expires_at` (epoch seconds), unless later activity is already there
+    async fn touch(&self, key: &PresenceKey, activity: &Activity, expires_at: i64) -> Result<(), Error>;
+}
+
+/// Presence in a DynamoDB table keyed by `pk` and `sk`
+pub struct DynamoPresenceStore {
+    client: DynamoClient,
+    table_name: String,
+}
+
+impl DynamoPresenceStore {
+    pub fn new(client: DynamoClient, table_name: String) -> Self {
+        Self { client, table_name }
+    }
+}
+
+#[async_trait]
+impl PresenceStore for DynamoPresenceStore {
+    async fn touch(&self, key: &PresenceKey, activity: &Activity, expires_at: i64) -> Result<(), Error> {
+        let mut set = "SET last_seen = :seen, expires_at = :ttl".to_string();
+        let mut update = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("pk", AttributeValue::S(key.project_id.clone()))
+            .key("sk", AttributeValue::S(key.sort_key()))
+            .condition_expression("attribute_not_exists(last_seen) OR last_seen <= :seen")
+            .expression_attribute_values(":seen", AttributeValue::N(activity.last_seen.to_string()))
+            .expression_attribute_values(":ttl", AttributeValue::N(expires_at.to_string()));
+        // A later event without a page (a click, say) keeps the page seen before
+        if let Some(ref page) = activity.page {
+            set.push_str(", page = :page");
+            update = update.expression_attribute_values(":page", AttributeValue::S(page.clone()));
+        }
+        match update.update_expression(set).send().await {
+            Ok(_) => Ok(()),
+            Err(SdkError::ServiceError(e)) if matches!(e.err(), UpdateItemError::ConditionalCheckFailedException(_)) => {
+                Ok(())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn event(value: serde_json::Value) -> IngestEventPayload {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_keeps_each_visitors_latest_page_per_minute() {
+        let config = AggregatorConfig::default();
+        let now = 1_700_000_100_000;
+        let mut presence = Presence::default();
+        let page = |ms: i64, path: &str| {
+            event(json!({"projectId": "p", "eventType": "pageview", "timestamp": ms, "anonymousId": "v1",
+                "context": {"page": {"path": path}}}))
+        };
+        presence.add(&page(1_700_000_010_000, "/pricing"), &config, now);
+        presence.add(&page(1_700_000_000_000, "/"), &config, now);
+        presence.add(
+            &event(json!({"projectId": "p", "eventType": "click", "timestamp": 1_700_000_030_000i64, "anonymousId": "v1"})),
+            &config,
+            now,
+        );
+        presence.add(&page(now - 3600 * 1000, "/old"), &config, now);
+        presence.add(&event(json!({"projectId": "p", "eventType": "pageview", "timestamp": now})), &config, now);
+
+        let visitors: Vec<_> = presence.visitors.iter().map(|(key, activity)| (key.sort_key(), activity.clone())).collect();
+        assert_eq!(visitors, [(
+            "2023-11-14T22:13#v1".to_string(),
+            Activity {
+                last_seen: 1_700_000_030_000,
+                page: Some("/pricing".to_string()),
+            }
+        )]);
+    }
+}
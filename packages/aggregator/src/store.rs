@@ -0,0 +1,105 @@
+//! Counter storage in DynamoDB.
+//!
+//! Items share the bucket's partition key `pk` (`{project}#{minute|hour}#{bucket}`)
+//! and are told apart by `sk`:
+//!
+//! - `totals`: `events`, `pageviews` and `sessions` counters
+//! - `page#{path}`: `views` of one page, so a dashboard reads the top pages
+//!   with one `Query` on `begins_with(sk, "page#")`
+//! - `session#{key}`: a marker per session, written only if absent, so a
+//!   session seen in several batches is counted once
+//!
+//! Counters are bumped with atomic `ADD` updates and every item carries an
+//! `expires_at` for the table's TTL.
+
+use async_trait::async_trait;
+use aws_sdk_dynamodb::error::SdkError;
+use aws_sdk_dynamodb::operation::put_item::PutItemError;
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use lambda_runtime::Error;
+
+use crate::counts::{BucketCounts, BucketKey};
+
+/// Where bucket counters are accumulated
+#[async_trait]
+pub trait CounterStore: Send + Sync {
+    /// Adds a batch's counts to a bucket, keeping it until `expires_at`
+    /// (epoch seconds)
+    async fn add(&self, key: &BucketKey, counts: &BucketCounts, expires_at: i64) -> Result<(), Error>;
+}
+
+/// Counters in a DynamoDB table keyed by `pk` and `sk`
+pub struct DynamoCounterStore {
+    client: DynamoClient,
+    table_name: String,
+}
+
+impl DynamoCounterStore {
+    pub fn new(client: DynamoClient, table_name: String) -> Self {
+        Self { client, table_name }
+    }
+
+    /// Bumps `counters` on one item
+    async fn bump(&self, pk: &str, sk: &str, counters: &[(&str, u64)], expires_at: i64) -> Result<(), Error> {
+        let mut update = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("pk", AttributeValue::S(pk.to_string()))
+            .key("sk", AttributeValue::S(sk.to_string()))
+            .expression_attribute_values(":ttl", AttributeValue::N(expires_at.to_string()));
+        let mut adds = Vec::new();
+        for (index, (name, value)) in counters.iter().enumerate() {
+            adds.push(format!("#c{} :c{}", index, index));
+            update = update
+                .expression_attribute_names(format!("#c{}", index), *name)
+                .expression_attribute_values(format!(":c{}", index), AttributeValue::N(value.to_string()));
+        }
+        update
+            .update_expression(format!("ADD {} SET expires_at = :ttl", adds.join(", ")))
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    /// Records a session in a bucket, returning whether it was new there
+    async fn mark_session(&self, pk: &str, session: &str, expires_at: i64) -> Result<bool, Error> {
+        let result = self
+            .client
+            .put_item()
+            .table_name(&self.table_name)
+            .item("pk", AttributeValue::S(pk.to_string()))
+            .item("sk", AttributeValue::S(format!("session#{}", session)))
+            .item("expires_at", AttributeValue::N(expires_at.to_string()))
+            .condition_expression("attribute_not_exists(sk)")
+            .send()
+            .await;
+        match result {
+            Ok(_) => Ok(true),
+            Err(SdkError::ServiceError(e)) if matches!(e.err(), PutItemError::ConditionalCheckFailedException(_)) => {
+                Ok(false)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[async_trait]
+impl CounterStore for DynamoCounterStore {
+    async fn add(&self, key: &BucketKey, counts: &BucketCounts, expires_at: i64) -> Result<(), Error> {
+        let pk = key.partition_key();
+        let mut new_sessions = 0;
+        for session in &counts.sessions {
+            if self.mark_session(&pk, session, expires_at).await? {
+                new_sessions += 1;
+            }
+        }
+        let totals = [("events", counts.events), ("pageviews", counts.pageviews), ("sessions", new_sessions)];
+        self.bump(&pk, "totals", &totals, expires_at).await?;
+        for (path, views) in &counts.pages {
+            self.bump(&pk, &format!("page#{}", path), &[("views", *views)], expires_at).await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,282 @@
+//! The batching client.
+
+use analytics_core::event::{EventContext, LibraryContext};
+use analytics_core::{Message, MessageBatch, SentAt};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::MissedTickBehavior;
+
+use crate::transport::{HttpTransport, Transport};
+use crate::{ClientConfig, Error};
+
+enum Command {
+    Send(Box<Message>),
+    /// Sends everything queued, then answers
+    Flush(oneshot::Sender<()>),
+}
+
+/// Queues messages for the background task that sends them; clones share
+/// the queue
+#[derive(Clone)]
+pub struct Client {
+    commands: mpsc::Sender<Command>,
+}
+
+impl Client {
+    /// A client for the ingest API over HTTP. Must be called within a Tokio
+    /// runtime, which runs the sending task.
+    pub fn new(config: ClientConfig) -> Result<Self, Error> {
+        let transport = HttpTransport::new(&config)?;
+        Ok(Self::with_transport(config, Arc::new(transport)))
+    }
+
+    /// A client sending through `transport`
+    pub fn with_transport(config: ClientConfig, transport: Arc<dyn Transport>) -> Self {
+        let (commands, queue) = mpsc::channel(config.max_queue.max(1));
+        tokio::spawn(run(queue, transport, config));
+        Self { commands }
+    }
+
+    /// Queues a track event
+    pub fn track(&self, user_id: &str, event: &str, properties: Value) -> Result<(), Error> {
+        self.send(Message {
+            kind: Some("track".to_string()),
+            user_id: Some(user_id.to_string()),
+            event: Some(event.to_string()),
+            properties: object("properties", properties)?,
+            ..Default::default()
+        })
+    }
+
+    /// Queues a pageview; `properties` may carry `url`, `path`, `title` and
+    /// `referrer`
+    pub fn page(&self, user_id: &str, name: &str, properties: Value) -> Result<(), Error> {
+        self.send(Message {
+            kind: Some("page".to_string()),
+            user_id: Some(user_id.to_string()),
+            name: Some(name.to_string()),
+            properties: object("properties", properties)?,
+            ..Default::default()
+        })
+    }
+
+    /// Queues traits for a user
+    pub fn identify(&self, user_id: &str, traits: Value) -> Result<(), Error> {
+        self.send(Message {
+            kind: Some("identify".to_string()),
+            user_id: Some(user_id.to_string()),
+            traits: object("traits", traits)?,
+            ..Default::default()
+        })
+    }
+
+    /// Queues any message, e.g. one for an anonymous user or a `group`;
+    /// `messageId`, `timestamp` and the library are filled in when missing
+    pub fn send(&self, mut message: Message) -> Result<(), Error> {
+        let present = |id: &Option<String>| id.as_deref().is_some_and(|id| !id.trim().is_empty());
+        if !present(&message.user_id) && !present(&message.anonymous_id) {
+            return Err("userId or anonymousId is required".into());
+        }
+        message.message_id.get_or_insert_with(|| uuid::Uuid::new_v4().to_string());
+        message.timestamp.get_or_insert_with(now);
+        let context = message.context.get_or_insert_with(EventContext::default);
+        context.library.get_or_insert_with(|| LibraryContext {
+            name: Some(env!("CARGO_PKG_NAME").to_string()),
+            version: Some(env!("CARGO_PKG_VERSION").to_string()),
+        });
+
+        self.commands.try_send(Command::Send(Box::new(message))).map_err(|e| match e {
+            mpsc::error::TrySendError::Full(_) => "Queue is full, message dropped".into(),
+            mpsc::error::TrySendError::Closed(_) => "Client is closed".into(),
+        })
+    }
+
+    /// Sends everything queued so far, returning once it's been sent (or
+    /// given up on)
+    pub async fn flush(&self) {
+        let (done, flushed) = oneshot::channel();
+        if self.commands.send(Command::Flush(done)).await.is_ok() {
+            let _ = flushed.await;
+        }
+    }
+
+    /// Flushes, for shutdown; the task stops once every clone is dropped
+    pub async fn close(self) {
+        self.flush().await;
+    }
+}
+
+/// A JSON object as a map; `null` is none
+fn object(name: &str, value: Value) -> Result<Option<HashMap<String, Value>>, Error> {
+    match value {
+        Value::Null => Ok(None),
+        Value::Object(fields) => Ok(Some(fields.into_iter().collect())),
+        _ => Err(format!("{} must be a JSON object", name).into()),
+    }
+}
+
+fn now() -> SentAt {
+    SentAt::Millis(chrono::Utc::now().timestamp_millis())
+}
+
+/// The sending task: batches queued messages until the client is dropped
+async fn run(mut commands: mpsc::Receiver<Command>, transport: Arc<dyn Transport>, config: ClientConfig) {
+    let mut queue = Vec::new();
+    let mut ticks = tokio::time::interval(config.flush_interval);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            command = commands.recv() => match command {
+                Some(Command::Send(message)) => {
+                    queue.push(*message);
+                    if queue.len() >= config.batch_size {
+                        deliver(std::mem::take(&mut queue), transport.as_ref(), &config).await;
+                    }
+                }
+                Some(Command::Flush(done)) => {
+                    flush(&mut queue, transport.as_ref(), &config).await;
+                    let _ = done.send(());
+                }
+                None => {
+                    flush(&mut queue, transport.as_ref(), &config).await;
+                    return;
+                }
+            },
+            _ = ticks.tick() => flush(&mut queue, transport.as_ref(), &config).await,
+        }
+    }
+}
+
+async fn flush(queue: &mut Vec<Message>, transport: &dyn Transport, config: &ClientConfig) {
+    while !queue.is_empty() {
+        let batch = queue.drain(..config.batch_size.clamp(1, queue.len())).collect();
+        deliver(batch, transport, config).await;
+    }
+}
+
+/// Posts a batch, retrying what might succeed later
+async fn deliver(batch: Vec<Message>, transport: &dyn Transport, config: &ClientConfig) {
+    let count = batch.len();
+    let mut batch = MessageBatch {
+        batch,
+        ..Default::default()
+    };
+    for attempt in 0..=config.max_retries {
+        // The server corrects timestamps by the gap from the send time
+        batch.sent_at = Some(now());
+        let retry_after = match transport.post(&batch).await {
+            Ok(answer) if (200..300).contains(&answer.status) => {
+                if answer.body.contains("\"errors\"") {
+                    tracing::warn!("Some of {} analytics messages were rejected: {}", count, answer.body);
+                }
+                return;
+            }
+            Ok(answer) if answer.retryable() => {
+                tracing::debug!("Analytics batch failed with {}, retrying", answer.status);
+                answer.retry_after
+            }
+            Ok(answer) => {
+                tracing::warn!("Dropped {} analytics messages: {} {}", count, answer.status, answer.body);
+                return;
+            }
+            Err(e) => {
+                tracing::debug!("Analytics batch failed: {}, retrying", e);
+                None
+            }
+        };
+        if attempt < config.max_retries {
+            tokio::time::sleep(config.backoff(attempt + 1, retry_after)).await;
+        }
+    }
+    tracing::warn!("Dropped {} analytics messages after {} retries", count, config.max_retries);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::Answer;
+    use async_trait::async_trait;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    /// Records batches, answering with scripted statuses and then 200s
+    #[derive(Default)]
+    struct Recording {
+        statuses: Mutex<Vec<u16>>,
+        batches: Mutex<Vec<MessageBatch>>,
+    }
+
+    #[async_trait]
+    impl Transport for Recording {
+        async fn post(&self, batch: &MessageBatch) -> Result<Answer, Error> {
+            self.batches.lock().unwrap().push(batch.clone());
+            let mut statuses = self.statuses.lock().unwrap();
+            let status = if statuses.is_empty() { 200 } else { statuses.remove(0) };
+            Ok(Answer {
+                status,
+                retry_after: None,
+                body: String::new(),
+            })
+        }
+    }
+
+    fn client(transport: &Arc<Recording>) -> Client {
+        let config = ClientConfig {
+            batch_size: 2,
+            retry_base: Duration::from_millis(10),
+            ..ClientConfig::new("http://localhost", "token")
+        };
+        Client::with_transport(config, transport.clone())
+    }
+
+    fn sizes(transport: &Recording) -> Vec<usize> {
+        transport.batches.lock().unwrap().iter().map(|batch| batch.batch.len()).collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_batches_fill_up_and_flush() {
+        let transport = Arc::new(Recording::default());
+        let client = client(&transport);
+
+        client.track("u1", "signup", serde_json::json!({"plan": "pro"})).unwrap();
+        client.page("u1", "Pricing", Value::Null).unwrap();
+        client.identify("u1", serde_json::json!({"email": "u1@example.com"})).unwrap();
+        client.flush().await;
+        assert_eq!(sizes(&transport), vec![2, 1]);
+
+        let batches = transport.batches.lock().unwrap();
+        let track = &batches[0].batch[0];
+        assert!(track.message_id.is_some() && track.timestamp.is_some());
+        let library = track.context.as_ref().and_then(|context| context.library.as_ref()).unwrap();
+        assert_eq!(library.name.as_deref(), Some("analytics-client"));
+        assert!(batches[0].sent_at.is_some());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retries_server_errors_but_not_rejections() {
+        let transport = Arc::new(Recording::default());
+        transport.statuses.lock().unwrap().extend([503, 429]);
+        let client = client(&transport);
+        client.track("u1", "signup", Value::Null).unwrap();
+        client.flush().await;
+        let batches = transport.batches.lock().unwrap().clone();
+        assert_eq!(batches.len(), 3);
+        assert_eq!(batches[0].batch[0].message_id, batches[2].batch[0].message_id);
+
+        transport.batches.lock().unwrap().clear();
+        transport.statuses.lock().unwrap().push(401);
+        client.track("u1", "signup", Value::Null).unwrap();
+        client.flush().await;
+        assert_eq!(sizes(&transport), vec![1]);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_messages_are_refused() {
+        let client = client(&Arc::new(Recording::default()));
+        assert!(client.track("", "signup", Value::Null).is_err());
+        assert!(client.track("u1", "signup", serde_json::json!([1, 2])).is_err());
+        assert!(client.send(Message::default()).is_err());
+    }
+}
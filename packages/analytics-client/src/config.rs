@@ -0,0 +1,53 @@
+//! Client configuration.
+
+use std::time::Duration;
+
+/// Configuration for a [`Client`](crate::Client)
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    /// Base URL of the ingest API, stage included
+    pub endpoint: String,
+    /// Project token, sent as the HTTP Basic username
+    pub write_key: String,
+    /// Sent as `X-API-Key` when the ingest API requires keys
+    pub api_key: Option<String>,
+    /// Most messages per request; a full batch is sent right away
+    pub batch_size: usize,
+    /// How long a message waits for its batch to fill
+    pub flush_interval: Duration,
+    /// Most messages waiting to be sent; more are refused
+    pub max_queue: usize,
+    /// Retries of a batch after its first attempt
+    pub max_retries: u32,
+    /// Backoff before the first retry, doubling with each one
+    pub retry_base: Duration,
+    pub max_backoff: Duration,
+}
+
+impl ClientConfig {
+    /// Defaults for the ingest API at `endpoint`
+    pub fn new(endpoint: impl Into<String>, write_key: impl Into<String>) -> Self {
+        Self {
+            endpoint: endpoint.into(),
+            write_key: write_key.into(),
+            api_key: None,
+            batch_size: 100,
+            flush_interval: Duration::from_secs(10),
+            max_queue: 10_000,
+            max_retries: 5,
+            retry_base: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+
+    /// Wait before retry `attempt` (1-based): exponential with full jitter,
+    /// but never shorter than the server asked for
+    pub fn backoff(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        let ceiling = self
+            .retry_base
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_backoff);
+        let jittered = ceiling.mul_f64(fastrand::f64());
+        retry_after.map_or(jittered, |after| jittered.max(after))
+    }
+}
@@ -0,0 +1,34 @@
+//! Server-side event tracking for Rust backends.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), analytics_client::Error> {
+//! use analytics_client::{Client, ClientConfig};
+//!
+//! let client = Client::new(ClientConfig::new("https://analytics.example.com/prod", "<project token>"))?;
+//! client.track("user-42", "order_completed", serde_json::json!({"total": 42.5}))?;
+//! client.page("user-42", "Pricing", serde_json::json!({"url": "https://example.com/pricing"}))?;
+//! client.close().await;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Calls only queue the message, stamped with a `messageId` (so the ingest
+//! API can drop retried duplicates) and its timestamp. A background task
+//! posts the queue to the Segment-compatible `POST /v1/batch`, which needs
+//! `SEGMENT_COMPAT_ENABLED` on the ingest API, whenever `batch_size`
+//! messages are waiting, every `flush_interval`, and on [`Client::flush`].
+//! Batches that fail with a 429, a 5xx or a network error are retried with
+//! jittered exponential backoff (honoring `Retry-After`); batches the API
+//! rejects are logged and dropped. When the queue is full, new messages are
+//! refused rather than blocking the caller.
+
+pub mod client;
+pub mod config;
+pub mod transport;
+
+pub use analytics_core::{Message, MessageBatch};
+pub use client::Client;
+pub use config::ClientConfig;
+
+/// Errors of the client, as in the ingestion crate
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
@@ -0,0 +1,89 @@
+//! How batches reach the ingest API.
+
+use analytics_core::MessageBatch;
+use async_trait::async_trait;
+use base64::Engine;
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper_rustls::HttpsConnector;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use std::time::Duration;
+
+use crate::{ClientConfig, Error};
+
+/// The ingest API's answer to a batch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Answer {
+    pub status: u16,
+    /// `Retry-After`, in seconds
+    pub retry_after: Option<Duration>,
+    pub body: String,
+}
+
+impl Answer {
+    /// Whether sending the batch again might succeed
+    pub fn retryable(&self) -> bool {
+        matches!(self.status, 408 | 429) || self.status >= 500
+    }
+}
+
+/// Where batches are posted; a trait so the client can be tested without a
+/// server
+#[async_trait]
+pub trait Transport: Send + Sync {
+    async fn post(&self, batch: &MessageBatch) -> Result<Answer, Error>;
+}
+
+/// The ingest API's `POST /v1/batch`
+pub struct HttpTransport {
+    http: Client<HttpsConnector<HttpConnector>, Full<Bytes>>,
+    url: String,
+    authorization: String,
+    api_key: Option<String>,
+}
+
+impl HttpTransport {
+    pub fn new(config: &ClientConfig) -> Result<Self, Error> {
+        let connector = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_native_roots()?
+            .https_or_http()
+            .enable_http1()
+            .build();
+        let credentials = base64::engine::general_purpose::STANDARD.encode(format!("{}:", config.write_key));
+        Ok(Self {
+            http: Client::builder(TokioExecutor::new()).build(connector),
+            url: format!("{}/v1/batch", config.endpoint.trim_end_matches('/')),
+            authorization: format!("Basic {}", credentials),
+            api_key: config.api_key.clone(),
+        })
+    }
+}
+
+#[async_trait]
+impl Transport for HttpTransport {
+    async fn post(&self, batch: &MessageBatch) -> Result<Answer, Error> {
+        let mut request = http::Request::post(&self.url)
+            .header("content-type", "application/json")
+            .header("authorization", &self.authorization)
+            .header("user-agent", concat!("analytics-client/", env!("CARGO_PKG_VERSION")));
+        if let Some(ref key) = self.api_key {
+            request = request.header("x-api-key", key);
+        }
+        let request = request.body(Full::new(Bytes::from(serde_json::to_vec(batch)?)))?;
+        let response = self.http.request(request).await?;
+        let status = response.status().as_u16();
+        let retry_after = response
+            .headers()
+            .get("retry-after")
+            .and_then(|value| value.to_str().ok()?.trim().parse().ok())
+            .map(Duration::from_secs);
+        let body = response.into_body().collect().await?.to_bytes();
+        Ok(Answer {
+            status,
+            retry_after,
+            body: String::from_utf8_lossy(&body).into_owned(),
+        })
+    }
+}
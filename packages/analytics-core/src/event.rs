@@ -0,0 +1,462 @@
+//! The normalized event: what the ingest API writes to the stream and every
+//! consumer reads back.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Internal normalized event structure
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IngestEventPayload {
+    /// Server-generated id, set when status tracking is enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub event_id: Option<String>,
+    pub project_id: String,
+    pub event_type: String,
+    pub timestamp: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub anonymous_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub properties: Option<HashMap<String, serde_json::Value>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context: Option<EventContext>,
+    /// Bot suspicion score (0 = human, 100 = certainly automated)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bot_score: Option<u8>,
+    /// Milliseconds since the user's previous event (`null` for their first)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ms_since_last_event: Option<Option<i64>>,
+    /// IANA timezone inferred server-side; absent when the client sent one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inferred_timezone: Option<String>,
+    /// Source of `inferred_timezone`, when recording it is enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub geo_source: Option<GeoSource>,
+    /// Whether the ingesting Lambda invocation was a cold start
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cold_start: Option<bool>,
+    /// Set when the user's previous event was impossibly far away
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub impossible_travel: Option<bool>,
+    /// Cookieless visitor id that rotates daily
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub daily_visitor_id: Option<String>,
+    /// Declared-unit properties whose values couldn't be converted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unit_violations: Option<Vec<String>>,
+    /// Problems of an event accepted in lenient validation mode
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub validation_warnings: Option<Vec<String>>,
+    /// Set when the pageview repeats the session's previous url within the window
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_duplicate_view: Option<bool>,
+    /// Set when the client timestamp was missing or non-positive and
+    /// server time was used instead
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp_defaulted: Option<bool>,
+    /// Set when the timestamp was outside the accepted range around server
+    /// time and was moved to its edge
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp_clamped: Option<bool>,
+    /// Hash-derived cohort, for aggregate-only privacy modes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cohort_bucket: Option<u32>,
+    /// Share of the project's sessions kept when the event was sampled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sample_rate: Option<f64>,
+    /// Session engagement so far, accumulated from heartbeats
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub engaged_time_ms: Option<i64>,
+    /// Consumer shard derived from the partition key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shard_hint: Option<u32>,
+    /// Traffic channel of a pageview (Direct, Organic Search, ...)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel: Option<String>,
+    /// Employer domain derived from an email property
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub company_domain: Option<String>,
+    /// SDK that sent the event (`unknown` when it didn't say)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sdk_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sdk_version: Option<String>,
+    /// Identity traits lifted from a legacy `properties.$set`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub traits: Option<HashMap<String, serde_json::Value>>,
+    /// Write-once traits lifted from a legacy `properties.$set_once`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub traits_set_once: Option<HashMap<String, serde_json::Value>>,
+    /// Account a `group` event associates the user with
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group_id: Option<String>,
+    /// Id an `alias` event merges into `user_id`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_id: Option<String>,
+    /// Canonical user the event's identity resolves to, stamped by the
+    /// identity-resolution consumer (`packages/identity-resolver`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resolved_user_id: Option<String>,
+    /// Client-generated id (`messageId`), for deduplicating retries
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<String>,
+    /// Position of the event in its session: the client's `sequence`, else
+    /// assigned at ingestion
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<u64>,
+    /// Set when the request carried `DNT: 1` or `Sec-GPC: 1`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub opted_out: Option<bool>,
+    /// Consent the user gave, as sent by the client
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub consent: Option<Consent>,
+    /// Set in lenient mode when `properties` don't match the event's schema
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schema_violation: Option<bool>,
+    /// Set when the event was re-published from the data lake
+    /// (`packages/replay`) rather than received from a client
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replayed: Option<bool>,
+    /// Days the project keeps its raw events, for the stores to expire them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retention_days: Option<u32>,
+    /// How the encrypted property values can be decrypted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<FieldEncryption>,
+    /// AWS region whose deployment ingested the event
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    /// Where the visitor first came from, stamped on conversion events
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_touch: Option<FirstTouch>,
+    /// Day the event happened on in its project's reporting timezone,
+    /// `YYYY-MM-DD`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub local_date: Option<String>,
+    /// Hour of `local_date` the event happened in, 0 to 23
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub local_hour: Option<u8>,
+}
+
+/// Envelope-encryption metadata for an event's encrypted fields. Each
+/// encrypted value is a base64 string of the 12-byte nonce followed by the
+/// AES-256-GCM ciphertext of the value's JSON, with the project id as
+/// associated data.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldEncryption {
+    /// KMS key the data key was generated under
+    pub key_id: String,
+    /// The data key as KMS encrypted it (base64), for `kms:Decrypt`
+    pub encrypted_data_key: String,
+    pub algorithm: String,
+    /// Paths of the encrypted values, like `properties.email`
+    pub fields: Vec<String>,
+}
+
+/// Event context structure
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct EventContext {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page: Option<PageContext>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+    /// Explicit IANA timezone reported by the client
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub screen: Option<ScreenContext>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub received_at: Option<i64>,
+    /// SDK self-identification
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub library: Option<LibraryContext>,
+    /// Client device, parsed from `user_agent` when enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device: Option<DeviceContext>,
+    /// Mobile app and device, as reported by the mobile SDKs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub app: Option<AppContext>,
+    /// Location resolved from `ip` when GeoIP is enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub geo: Option<GeoContext>,
+    /// Set when bot filtering flagged the event
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_bot: Option<bool>,
+    /// Campaign attribution, from the client or the page's UTM parameters
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub campaign: Option<CampaignContext>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+/// A visitor's first recorded campaign or external referrer
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FirstTouch {
+    /// Epoch milliseconds of the event that carried it
+    pub timestamp: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub campaign: Option<CampaignContext>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub referrer: Option<String>,
+    /// Page the visitor landed on
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub landing_page: Option<String>,
+}
+
+/// Campaign the visit came from (Segment's `context.campaign`)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct CampaignContext {
+    /// `utm_source`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// `utm_medium`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub medium: Option<String>,
+    /// `utm_campaign`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// `utm_term`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub term: Option<String>,
+    /// `utm_content`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    /// Google Ads click id
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gclid: Option<String>,
+    /// Meta click id
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fbclid: Option<String>,
+    /// Other campaign fields sent by the client
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+/// Device details derived from the user agent
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceContext {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub browser: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub browser_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub os: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub os_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_type: Option<DeviceType>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_bot: Option<bool>,
+    /// Device fields sent by the client (Segment's `id`, `model`, ...)
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+/// Mobile app details, reported by the SDK since apps have no user agent
+/// to parse
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct AppContext {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Marketing version, e.g. `4.2.0`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// Build number, e.g. `4210`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub build: Option<String>,
+    /// Hardware model, e.g. `iPhone15,2`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_model: Option<String>,
+    /// `iOS`, `Android`, ...
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub os_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub os_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub network_type: Option<NetworkType>,
+    /// Mobile carrier, e.g. `T-Mobile`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub carrier: Option<String>,
+}
+
+/// Connection the app was on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum NetworkType {
+    Wifi,
+    Cellular,
+    Ethernet,
+    Offline,
+    #[serde(other)]
+    Unknown,
+}
+
+/// Location of the client's IP
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct GeoContext {
+    /// ISO 3166-1 alpha-2 country code
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    /// ISO 3166-2 subdivision code, without the country prefix
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub city: Option<String>,
+}
+
+/// Coarse form factor of a client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceType {
+    Desktop,
+    Mobile,
+    Tablet,
+    Bot,
+    Unknown,
+}
+
+/// Where an event's geography came from, most to least precise
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GeoSource {
+    /// A GeoIP database lookup (no such lookup is wired up yet)
+    Geoip,
+    /// CloudFront viewer headers
+    CdnHeader,
+    /// The region of the client's locale
+    Inferred,
+    None,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct LibraryContext {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct PageContext {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub referrer: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct ScreenContext {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+}
+
+impl IngestEventPayload {
+    /// Collects non-fatal issues that SDK developers should fix but that
+    /// don't warrant rejecting the event
+    pub fn warnings(&self, deprecated_event_names: &[String]) -> Vec<String> {
+        let mut warnings = Vec::new();
+
+        if deprecated_event_names.contains(&self.event_type) {
+            warnings.push(format!("event name \"{}\" is deprecated", self.event_type));
+        }
+
+        if let Some(ref properties) = self.properties {
+            let mut keys: Vec<&String> = properties.keys().collect();
+            keys.sort();
+            for key in keys {
+                let well_formed = !key.is_empty()
+                    && key
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | '$'));
+                if !well_formed {
+                    warnings.push(format!("property \"{}\" has an unusual name", key));
+                } else if properties[key].is_null() {
+                    warnings.push(format!("property \"{}\" is null", key));
+                }
+            }
+        }
+
+        warnings
+    }
+
+    /// Ensures pageviews carry `context.page.url` and `context.page.path`,
+    /// deriving them from the top-level `url` property when absent.
+    /// Fails only when no url is available at all.
+    pub fn ensure_page_context(&mut self) -> Result<(), String> {
+        if self.event_type != "pageview" {
+            return Ok(());
+        }
+
+        let top_level_url = self
+            .properties
+            .as_ref()
+            .and_then(|p| p.get("url"))
+            .and_then(|v| v.as_str())
+            .filter(|url| !url.is_empty())
+            .map(String::from);
+
+        let context = self.context.get_or_insert_with(EventContext::default);
+        let page = context.page.get_or_insert_with(PageContext::default);
+
+        if page.url.as_deref().is_none_or(str::is_empty) {
+            page.url = top_level_url;
+        }
+
+        let Some(ref page_url) = page.url else {
+            return Err("pageview requires a url".to_string());
+        };
+
+        if page.path.as_deref().is_none_or(str::is_empty) {
+            page.path = url::Url::parse(page_url)
+                .map(|parsed| parsed.path().to_string())
+                .ok()
+                .or_else(|| page_url.starts_with('/').then(|| page_url.clone()));
+        }
+
+        Ok(())
+    }
+}
+
+/// Consent categories granted by the user, as reported by the client
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct Consent {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub analytics: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub marketing: Option<bool>,
+    /// Raw consent string from the CMP (e.g. IAB TCF)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub consent_string: Option<String>,
+}
+
@@ -0,0 +1,13 @@
+//! Event models shared by the ingest API, its consumers and the Rust
+//! tracking client (`packages/analytics-client`).
+//!
+//! [`event`] is the normalized event written to the stream; [`message`] is
+//! what server-side clients send to the Segment-compatible tracking API.
+//! Validation, enrichment and the browser payload formats stay with the
+//! ingest API, which re-exports these from `ingestion::models`.
+
+pub mod event;
+pub mod message;
+
+pub use event::IngestEventPayload;
+pub use message::{Message, MessageBatch, SentAt};
@@ -0,0 +1,199 @@
+//! Messages of the Segment-compatible tracking API (`POST /v1/batch` and
+//! friends), as server-side clients send them.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::event::{Consent, EventContext, IngestEventPayload, PageContext};
+
+/// Send time as epoch milliseconds or an RFC 3339 string
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(untagged)]
+pub enum SentAt {
+    Millis(i64),
+    Rfc3339(String),
+}
+
+
+impl SentAt {
+    /// Epoch milliseconds
+    pub fn millis(&self) -> Result<i64, String> {
+        match self {
+            Self::Millis(ms) => Ok(*ms),
+            Self::Rfc3339(time) => chrono::DateTime::parse_from_rfc3339(time)
+                .map(|time| time.timestamp_millis())
+                .map_err(|_| format!("sentAt is not a valid timestamp: {}", time)),
+        }
+    }
+
+    /// Milliseconds to add to the client's timestamps: our receive time
+    /// minus the client's send time
+    pub fn skew(&self) -> Result<i64, String> {
+        Ok(chrono::Utc::now().timestamp_millis() - self.millis()?)
+    }
+}
+
+
+/// A message of the tracking API (Segment-shaped); which fields matter
+/// depends on `type`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct Message {
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anonymous_id: Option<String>,
+    /// Track event name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event: Option<String>,
+    /// Page name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub properties: Option<HashMap<String, Value>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub traits: Option<HashMap<String, Value>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<EventContext>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<SentAt>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub consent: Option<Consent>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sent_at: Option<SentAt>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub write_key: Option<String>,
+}
+
+impl Message {
+    /// Normalizes to internal event format. `skew` (ms) is added to the
+    /// message's timestamp; a message without one gets server time later.
+    /// Note: project_id should be extracted from the write key, not payload
+    pub fn normalize(&self, project_id: String, skew: i64) -> Result<IngestEventPayload, String> {
+        let non_empty = |id: &Option<String>| id.clone().filter(|id| !id.trim().is_empty());
+        let user_id = non_empty(&self.user_id);
+        let anonymous_id = non_empty(&self.anonymous_id);
+        if user_id.is_none() && anonymous_id.is_none() {
+            return Err("userId or anonymousId is required".to_string());
+        }
+
+        let timestamp = match self.timestamp {
+            Some(ref timestamp) => timestamp.millis()? + skew,
+            None => 0, // Will be set by handler
+        };
+        let mut event = IngestEventPayload {
+            project_id,
+            timestamp,
+            user_id,
+            anonymous_id,
+            context: self.context.clone(),
+            message_id: non_empty(&self.message_id),
+            consent: self.consent.clone(),
+            ..Default::default()
+        };
+
+        match self.kind.as_deref().unwrap_or_default() {
+            "track" => {
+                event.event_type = non_empty(&self.event).ok_or("event is required")?;
+                event.properties = Some(self.properties.clone().unwrap_or_default());
+            }
+            "page" => {
+                let mut properties = self.properties.clone().unwrap_or_default();
+                if let Some(ref name) = self.name {
+                    properties.entry("name".to_string()).or_insert(Value::from(name.as_str()));
+                }
+                let context = event.context.get_or_insert_with(Default::default);
+                if context.page.is_none() {
+                    let field =
+                        |key: &str| properties.get(key).and_then(Value::as_str).map(String::from);
+                    context.page = Some(PageContext {
+                        url: field("url"),
+                        title: field("title"),
+                        path: field("path"),
+                        referrer: field("referrer"),
+                    });
+                }
+                event.event_type = "pageview".to_string();
+                event.properties = Some(properties);
+            }
+            "identify" => {
+                event.event_type = "identify".to_string();
+                event.traits = Some(self.traits.clone().unwrap_or_default());
+            }
+            "group" => {
+                event.event_type = "group".to_string();
+                event.group_id = Some(non_empty(&self.group_id).ok_or("groupId is required")?);
+                event.traits = Some(self.traits.clone().unwrap_or_default());
+            }
+            "alias" => {
+                if event.user_id.is_none() {
+                    return Err("userId is required".to_string());
+                }
+                event.event_type = "alias".to_string();
+                let previous_id = non_empty(&self.previous_id).ok_or("previousId is required")?;
+                event.previous_id = Some(previous_id);
+            }
+            other => return Err(format!("Unsupported message type: {:?}", other)),
+        }
+        Ok(event)
+    }
+}
+
+/// Body of `POST /v1/batch`, as a client sends it
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageBatch {
+    pub batch: Vec<Message>,
+    /// Client clock when the batch was sent, used for skew correction
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sent_at: Option<SentAt>,
+    /// Defaults for messages without their own context
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context: Option<EventContext>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_messages_round_trip_and_normalize() {
+        let message = Message {
+            kind: Some("track".to_string()),
+            user_id: Some("u1".to_string()),
+            event: Some("order_completed".to_string()),
+            properties: Some(HashMap::from([("total".to_string(), Value::from(42))])),
+            message_id: Some("m1".to_string()),
+            timestamp: Some(SentAt::Millis(1_700_000_000_000)),
+            ..Default::default()
+        };
+        let json = serde_json::to_value(&message).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "type": "track",
+                "userId": "u1",
+                "event": "order_completed",
+                "properties": {"total": 42},
+                "timestamp": 1_700_000_000_000_i64,
+                "messageId": "m1",
+            })
+        );
+
+        let event = serde_json::from_value::<Message>(json).unwrap().normalize("p".to_string(), 5).unwrap();
+        assert_eq!(event.event_type, "order_completed");
+        assert_eq!(event.timestamp, 1_700_000_000_005);
+        assert_eq!(event.message_id.as_deref(), Some("m1"));
+    }
+}
@@ -0,0 +1,203 @@
+//! Inserts over ClickHouse's HTTP interface.
+//!
+//! Rows are sent as `JSONEachRow` in one `INSERT` per chunk. With
+//! `CLICKHOUSE_ASYNC_INSERT` (the default) the server buffers small inserts
+//! and the request waits until the buffer is flushed, so a success means
+//! the rows are stored. Each insert carries a deduplication token derived
+//! from the stream positions it covers, so re-inserting a chunk after a
+//! timeout or a retried Lambda batch doesn't duplicate rows. 503s, 429s and
+//! connection failures are retried with jittered backoff; other errors are
+//! returned at once.
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper_rustls::HttpsConnector;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use ingestion::shared::{env_flag, env_opt, env_or, env_var};
+use lambda_runtime::Error;
+use std::time::Duration;
+
+use crate::rows::EventRow;
+
+/// Configuration for the ClickHouse connection
+#[derive(Debug, Clone)]
+pub struct ClickHouseConfig {
+    /// Base URL of the HTTP interface, e.g. `https://host:8443`
+    pub url: String,
+    pub database: String,
+    pub table: String,
+    pub user: String,
+    pub password: String,
+    pub async_insert: bool,
+    /// Retries of a failed insert after the first attempt
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled for each further one
+    pub retry_base: Duration,
+    /// Most rows per `INSERT`; a Kinesis batch is split on record boundaries
+    pub max_rows_per_insert: usize,
+}
+
+impl Default for ClickHouseConfig {
+    fn default() -> Self {
+        Self {
+            url: "http://localhost:8123".to_string(),
+            database: "default".to_string(),
+            table: "events".to_string(),
+            user: "default".to_string(),
+            password: String::new(),
+            async_insert: true,
+            max_retries: 3,
+            retry_base: Duration::from_millis(200),
+            max_rows_per_insert: 10_000,
+        }
+    }
+}
+
+impl ClickHouseConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            url: env_or("CLICKHOUSE_URL", defaults.url),
+            database: env_or("CLICKHOUSE_DATABASE", defaults.database),
+            table: env_or("CLICKHOUSE_TABLE", defaults.table),
+            user: env_or("CLICKHOUSE_USER", defaults.user),
+            password: env_var("CLICKHOUSE_PASSWORD").unwrap_or_default(),
+            async_insert: env_var("CLICKHOUSE_ASYNC_INSERT").is_none() || env_flag("CLICKHOUSE_ASYNC_INSERT"),
+            max_retries: env_or("CLICKHOUSE_MAX_RETRIES", defaults.max_retries),
+            retry_base: env_opt("CLICKHOUSE_RETRY_BASE_MS").map_or(defaults.retry_base, Duration::from_millis),
+            max_rows_per_insert: env_or("CLICKHOUSE_MAX_ROWS_PER_INSERT", defaults.max_rows_per_insert).max(1),
+        }
+    }
+
+    /// The insert URL for a chunk with `dedup_token`
+    pub fn insert_url(&self, dedup_token: &str) -> String {
+        let query = format!("INSERT INTO `{}`.`{}` FORMAT JSONEachRow", self.database, self.table);
+        let mut params = url::form_urlencoded::Serializer::new(String::new());
+        params.append_pair("query", &query);
+        params.append_pair("insert_deduplication_token", dedup_token);
+        if self.async_insert {
+            params.append_pair("async_insert", "1");
+            params.append_pair("wait_for_async_insert", "1");
+            params.append_pair("async_insert_deduplicate", "1");
+        }
+        format!("{}/?{}", self.url.trim_end_matches('/'), params.finish())
+    }
+}
+
+/// Where rows are inserted; a trait so the handler can be tested without a
+/// server
+#[async_trait]
+pub trait RowInserter: Send + Sync {
+    async fn insert(&self, rows: &[EventRow], dedup_token: &str) -> Result<(), Error>;
+}
+
+/// Inserts rows into ClickHouse over HTTP
+pub struct ClickHouseClient {
+    http: Client<HttpsConnector<HttpConnector>, Full<Bytes>>,
+    config: ClickHouseConfig,
+}
+
+impl ClickHouseClient {
+    pub fn new(config: ClickHouseConfig) -> Result<Self, Error> {
+        let connector = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_native_roots()?
+            .https_or_http()
+            .enable_http1()
+            .build();
+        Ok(Self {
+            http: Client::builder(TokioExecutor::new()).build(connector),
+            config,
+        })
+    }
+
+    /// One attempt; `Ok(false)` when the failure is worth retrying
+    async fn try_insert(&self, body: &Bytes, dedup_token: &str) -> Result<bool, Error> {
+        let request = http::Request::post(self.config.insert_url(dedup_token))
+            .header("content-type", "application/x-ndjson")
+            .header("x-clickhouse-user", &self.config.user)
+            .header("x-clickhouse-key", &self.config.password)
+            .body(Full::new(body.clone()))?;
+        let response = match self.http.request(request).await {
+            Ok(response) => response,
+            Err(e) => {
+                tracing::warn!("ClickHouse request failed: {}", e);
+                return Ok(false);
+            }
+        };
+        let status = response.status();
+        if status.is_success() {
+            return Ok(true);
+        }
+        let body = response.into_body().collect().await?.to_bytes();
+        let message = String::from_utf8_lossy(&body);
+        match status.as_u16() {
+            429 | 503 => {
+                tracing::warn!("ClickHouse answered {}: {}", status, message);
+                Ok(false)
+            }
+            _ => Err(format!("ClickHouse insert failed with {}: {}", status, message).into()),
+        }
+    }
+}
+
+#[async_trait]
+impl RowInserter for ClickHouseClient {
+    async fn insert(&self, rows: &[EventRow], dedup_token: &str) -> Result<(), Error> {
+        let mut body = Vec::new();
+        for row in rows {
+            serde_json::to_writer(&mut body, row)?;
+            body.push(b'\n');
+        }
+        let body = Bytes::from(body);
+
+        for attempt in 0..=self.config.max_retries {
+            if attempt > 0 {
+                tokio::time::sleep(backoff(self.config.retry_base, attempt)).await;
+            }
+            if self.try_insert(&body, dedup_token).await? {
+                return Ok(());
+            }
+        }
+        Err(format!("ClickHouse insert still failing after {} retries", self.config.max_retries).into())
+    }
+}
+
+/// Delay before retry `attempt` (1-based): exponential with full jitter
+pub fn backoff(base: Duration, attempt: u32) -> Duration {
+    let ceiling = base.saturating_mul(1 << attempt.min(10).saturating_sub(1));
+    ceiling.mul_f64(fastrand::f64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_url() {
+        let config = ClickHouseConfig {
+            url: "https://ch.example.com:8443/".to_string(),
+            database: "analytics".to_string(),
+            ..Default::default()
+        };
+        let url = config.insert_url("shard-1:10-20");
+        assert!(url.starts_with("https://ch.example.com:8443/?query=INSERT+INTO+%60analytics%60.%60events%60"));
+        assert!(url.contains("insert_deduplication_token=shard-1%3A10-20"));
+        assert!(url.contains("wait_for_async_insert=1"));
+
+        let sync = ClickHouseConfig {
+            async_insert: false,
+            ..Default::default()
+        };
+        assert!(!sync.insert_url("t").contains("async_insert"));
+    }
+
+    #[test]
+    fn test_backoff_is_capped_by_the_doubling_ceiling() {
+        let base = Duration::from_millis(100);
+        assert!(backoff(base, 1) <= base);
+        assert!(backoff(base, 3) <= base * 4);
+    }
+}
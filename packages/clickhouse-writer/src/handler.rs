@@ -0,0 +1,177 @@
+//! The Kinesis batch handler and its checkpointing.
+//!
+//! Records are turned into rows in stream order and inserted in chunks of
+//! whole records. When a chunk fails (after the client's retries), the
+//! handler stops and reports the chunk's first record as the batch item
+//! failure. Lambda then checkpoints every record before it, whose rows are
+//! stored, and retries the batch from there; later chunks weren't
+//! attempted, so nothing is skipped. Records that yield no rows are
+//! checkpointed along with their neighbours.
+
+use aws_lambda_events::event::kinesis::{KinesisEvent, KinesisEventRecord};
+use aws_lambda_events::event::streams::{KinesisBatchItemFailure, KinesisEventResponse};
+
+use crate::clickhouse::RowInserter;
+use crate::rows::{rows, EventRow};
+
+/// Rows of consecutive records, inserted together
+#[derive(Debug, Default)]
+struct Chunk<'a> {
+    first_sequence: &'a str,
+    last_sequence: &'a str,
+    rows: Vec<EventRow>,
+}
+
+fn sequence_number(record: &KinesisEventRecord) -> &str {
+    record.kinesis.sequence_number.as_deref().unwrap_or_default()
+}
+
+/// Splits a batch into chunks of at most `max_rows` rows, never splitting
+/// a record (a record with more rows gets a chunk of its own)
+fn chunks(records: &[KinesisEventRecord], max_rows: usize) -> Vec<Chunk<'_>> {
+    let mut chunks: Vec<Chunk> = Vec::new();
+    for record in records {
+        let sequence = sequence_number(record);
+        let arrived_at = record.kinesis.approximate_arrival_timestamp.0.timestamp_millis();
+        let record_rows = rows(&record.kinesis.data.0, sequence, arrived_at);
+        let full = chunks
+            .last()
+            .is_none_or(|chunk| !chunk.rows.is_empty() && chunk.rows.len() + record_rows.len() > max_rows);
+        if full {
+            chunks.push(Chunk {
+                first_sequence: sequence,
+                ..Default::default()
+            });
+        }
+        let chunk = chunks.last_mut().expect("chunk just pushed");
+        chunk.last_sequence = sequence;
+        chunk.rows.extend(record_rows);
+    }
+    chunks
+}
+
+/// Inserts a batch, reporting where to resume if an insert fails
+pub async fn handle(event: KinesisEvent, inserter: &dyn RowInserter, max_rows: usize) -> KinesisEventResponse {
+    let mut inserted = 0;
+    for chunk in chunks(&event.records, max_rows) {
+        if chunk.rows.is_empty() {
+            continue;
+        }
+        // The same records always make the same token, so a retried insert
+        // is deduplicated by ClickHouse
+        let dedup_token = format!("{}-{}", chunk.first_sequence, chunk.last_sequence);
+        if let Err(e) = inserter.insert(&chunk.rows, &dedup_token).await {
+            tracing::error!(
+                "Insert of {} rows failed, resuming from {}: {}",
+                chunk.rows.len(),
+                chunk.first_sequence,
+                e
+            );
+            return KinesisEventResponse {
+                batch_item_failures: vec![KinesisBatchItemFailure {
+                    item_identifier: Some(chunk.first_sequence.to_string()),
+                }],
+            };
+        }
+        inserted += chunk.rows.len();
+    }
+
+    tracing::info!("Inserted {} rows from {} records", inserted, event.records.len());
+    KinesisEventResponse {
+        batch_item_failures: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use lambda_runtime::Error;
+    use serde_json::json;
+    use std::sync::Mutex;
+
+    /// Records inserts, failing the `fail_at`th one
+    #[derive(Default)]
+    struct FakeInserter {
+        fail_at: Option<usize>,
+        inserts: Mutex<Vec<(Vec<String>, String)>>,
+    }
+
+    #[async_trait]
+    impl RowInserter for FakeInserter {
+        async fn insert(&self, rows: &[EventRow], dedup_token: &str) -> Result<(), Error> {
+            let mut inserts = self.inserts.lock().unwrap();
+            if self.fail_at == Some(inserts.len()) {
+                return Err("503 Service Unavailable".into());
+            }
+            let ids = rows.iter().map(|row| row.event_id.clone()).collect();
+            inserts.push((ids, dedup_token.to_string()));
+            Ok(())
+        }
+    }
+
+    fn batch(records: &[(&str, &[u8])]) -> KinesisEvent {
+        let records: Vec<_> = records
+            .iter()
+            .map(|(sequence, data)| {
+                json!({
+                    "kinesis": {
+                        "sequenceNumber": sequence,
+                        "data": base64_encode(data),
+                        "approximateArrivalTimestamp": 1_700_000_000.0,
+                        "partitionKey": "proj",
+                    },
+                    "eventSource": "aws:kinesis",
+                })
+            })
+            .collect();
+        serde_json::from_value(json!({ "Records": records })).unwrap()
+    }
+
+    fn base64_encode(data: &[u8]) -> String {
+        serde_json::to_value(aws_lambda_events::encodings::Base64Data(data.to_vec()))
+            .unwrap()
+            .as_str()
+            .unwrap()
+            .to_string()
+    }
+
+    const EVENT: &[u8] = br#"{"projectId":"proj","eventType":"pageview","timestamp":1700000000000}"#;
+
+    #[tokio::test]
+    async fn test_inserts_chunks_of_whole_records() {
+        let inserter = FakeInserter::default();
+        let event = batch(&[("1", EVENT), ("2", b"garbage"), ("3", EVENT), ("4", EVENT)]);
+
+        let response = handle(event, &inserter, 2).await;
+
+        assert!(response.batch_item_failures.is_empty());
+        let inserts = inserter.inserts.lock().unwrap();
+        assert_eq!(
+            *inserts,
+            [
+                (vec!["1-0".to_string(), "3-0".to_string()], "1-3".to_string()),
+                (vec!["4-0".to_string()], "4-4".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_insert_resumes_from_its_first_record() {
+        let inserter = FakeInserter {
+            fail_at: Some(1),
+            ..Default::default()
+        };
+        let event = batch(&[("1", EVENT), ("2", EVENT), ("3", EVENT), ("4", EVENT), ("5", EVENT)]);
+
+        let response = handle(event, &inserter, 2).await;
+
+        assert_eq!(
+            response.batch_item_failures,
+            [KinesisBatchItemFailure {
+                item_identifier: Some("3".to_string()),
+            }]
+        );
+        assert_eq!(inserter.inserts.lock().unwrap().len(), 1);
+    }
+}
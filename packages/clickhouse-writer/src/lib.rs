@@ -0,0 +1,12 @@
+//! Kinesis → ClickHouse consumer.
+//!
+//! Consumes the ingest stream in Lambda batches, maps each
+//! `IngestEventPayload` to a row of the ClickHouse `events` table (see
+//! [`rows`]) and inserts them over the HTTP interface (see [`clickhouse`]).
+//! The event source mapping must enable `ReportBatchItemFailures`: on a
+//! failed insert [`handler`] reports where the batch should resume, so
+//! Lambda checkpoints everything before it and retries from there.
+
+pub mod clickhouse;
+pub mod handler;
+pub mod rows;
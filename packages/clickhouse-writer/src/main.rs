@@ -0,0 +1,29 @@
+use lambda_runtime::{run, service_fn, Error, LambdaEvent};
+use std::sync::Arc;
+
+use aws_lambda_events::event::kinesis::KinesisEvent;
+use clickhouse_writer::clickhouse::{ClickHouseClient, ClickHouseConfig};
+use clickhouse_writer::handler::handle;
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    // Initialize tracing
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"))
+        )
+        .json()
+        .init();
+
+    let config = ClickHouseConfig::from_env();
+    let max_rows = config.max_rows_per_insert;
+    tracing::info!("Writing to {}.{} at {}", config.database, config.table, config.url);
+    let client = Arc::new(ClickHouseClient::new(config)?);
+
+    run(service_fn(move |event: LambdaEvent<KinesisEvent>| {
+        let client = client.clone();
+        async move { Ok::<_, Error>(handle(event.payload, client.as_ref(), max_rows).await) }
+    }))
+    .await
+}
@@ -0,0 +1,174 @@
+//! Mapping stream records to rows of the ClickHouse `events` table.
+//!
+//! The columns match what the storage package's ClickHouse adapter reads
+//! and writes. Records may be KPL aggregates; each user record inside is
+//! one event. Records that aren't JSON events (Avro-encoded streams, or a
+//! projection that dropped required fields) can't become rows and are
+//! skipped, as retrying them would never succeed.
+
+use ingestion::aggregation;
+use ingestion::models::IngestEventPayload;
+use serde::Serialize;
+
+/// One row of the `events` table, serialized for `JSONEachRow`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EventRow {
+    pub event_id: String,
+    pub project_id: String,
+    pub event_type: String,
+    /// Milliseconds since the epoch, for a `DateTime64(3)` column
+    pub timestamp: i64,
+    pub session_id: Option<String>,
+    pub user_id: Option<String>,
+    pub anonymous_id: Option<String>,
+    pub page_url: Option<String>,
+    pub page_title: Option<String>,
+    pub page_path: Option<String>,
+    pub page_referrer: Option<String>,
+    pub user_agent: Option<String>,
+    pub browser_name: Option<String>,
+    pub browser_version: Option<String>,
+    pub os_name: Option<String>,
+    pub os_version: Option<String>,
+    pub device_type: Option<String>,
+    pub screen_width: Option<u32>,
+    pub screen_height: Option<u32>,
+    pub country: Option<String>,
+    pub city: Option<String>,
+    pub region: Option<String>,
+    pub ip_address: Option<String>,
+    pub locale: Option<String>,
+    /// `properties` as a JSON string
+    pub properties: Option<String>,
+    /// Milliseconds since the epoch; the arrival time when the ingest API
+    /// didn't stamp one
+    pub received_at: i64,
+}
+
+impl EventRow {
+    /// The row for an event; `fallback_id` is used when the event has
+    /// neither an `eventId` nor a `messageId`, and `arrived_at` when it has
+    /// no `receivedAt`
+    pub fn from_event(event: IngestEventPayload, fallback_id: String, arrived_at: i64) -> Self {
+        let context = event.context.unwrap_or_default();
+        let page = context.page.unwrap_or_default();
+        let device = context.device.unwrap_or_default();
+        let app = context.app.unwrap_or_default();
+        let geo = context.geo.unwrap_or_default();
+        let session_id = event
+            .properties
+            .as_ref()
+            .and_then(|properties| properties.get("session_id"))
+            .and_then(|session| session.as_str())
+            .map(String::from);
+        let device_type = device
+            .device_type
+            .and_then(|device_type| serde_json::to_value(device_type).ok())
+            .and_then(|value| value.as_str().map(String::from));
+
+        Self {
+            event_id: event.event_id.or(event.message_id).unwrap_or(fallback_id),
+            project_id: event.project_id,
+            event_type: event.event_type,
+            timestamp: event.timestamp,
+            session_id,
+            user_id: event.user_id,
+            anonymous_id: event.anonymous_id,
+            page_url: page.url,
+            page_title: page.title,
+            page_path: page.path,
+            page_referrer: page.referrer,
+            user_agent: context.user_agent,
+            browser_name: device.browser,
+            browser_version: device.browser_version,
+            // Mobile events have no user agent; their SDK reports the OS
+            os_name: device.os.or(app.os_name),
+            os_version: device.os_version.or(app.os_version),
+            device_type,
+            screen_width: context.screen.as_ref().and_then(|screen| screen.width),
+            screen_height: context.screen.as_ref().and_then(|screen| screen.height),
+            country: geo.country,
+            city: geo.city,
+            region: geo.region,
+            ip_address: context.ip,
+            locale: context.locale,
+            properties: event.properties.and_then(|properties| serde_json::to_string(&properties).ok()),
+            received_at: context.received_at.unwrap_or(arrived_at),
+        }
+    }
+}
+
+/// Rows of one stream record, in order. Events without an id get one
+/// derived from the record's sequence number, so a retried insert repeats
+/// the same ids.
+pub fn rows(data: &[u8], sequence_number: &str, arrived_at: i64) -> Vec<EventRow> {
+    aggregation::decode(data)
+        .into_iter()
+        .enumerate()
+        .filter_map(|(index, data)| match serde_json::from_slice::<IngestEventPayload>(&data) {
+            Ok(event) => Some(EventRow::from_event(event, format!("{}-{}", sequence_number, index), arrived_at)),
+            Err(e) => {
+                tracing::warn!("Skipping user record {} of {}: {}", index, sequence_number, e);
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ingestion::partitioning::PartitionKey;
+    use serde_json::json;
+
+    fn event(extra: serde_json::Value) -> Vec<u8> {
+        let mut event = json!({"projectId": "proj", "eventType": "pageview", "timestamp": 1_700_000_000_000_i64});
+        event.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+        serde_json::to_vec(&event).unwrap()
+    }
+
+    #[test]
+    fn test_maps_event_to_row() {
+        let data = event(json!({
+            "messageId": "msg-1",
+            "properties": {"session_id": "s1", "plan": "pro"},
+            "context": {
+                "page": {"url": "https://a.com/x", "path": "/x"},
+                "screen": {"width": 1280, "height": 720},
+                "device": {"browser": "Firefox", "deviceType": "desktop"},
+                "geo": {"country": "DE", "city": "Berlin"},
+                "ip": "203.0.113.7",
+                "receivedAt": 1_700_000_000_500_i64,
+            },
+        }));
+        let rows = rows(&data, "4955", 0);
+        assert_eq!(rows.len(), 1);
+        let row = &rows[0];
+        assert_eq!(row.event_id, "msg-1");
+        assert_eq!(row.session_id.as_deref(), Some("s1"));
+        assert_eq!(row.page_path.as_deref(), Some("/x"));
+        assert_eq!((row.screen_width, row.screen_height), (Some(1280), Some(720)));
+        assert_eq!(row.device_type.as_deref(), Some("desktop"));
+        assert_eq!(row.country.as_deref(), Some("DE"));
+        assert_eq!(row.received_at, 1_700_000_000_500);
+        let properties: serde_json::Value = serde_json::from_str(row.properties.as_deref().unwrap()).unwrap();
+        assert_eq!(properties["plan"], "pro");
+
+        let data = event(json!({"context": {"app": {"osName": "iOS", "osVersion": "18.1"}}}));
+        let row = &super::rows(&data, "4956", 0)[0];
+        assert_eq!((row.os_name.as_deref(), row.os_version.as_deref()), (Some("iOS"), Some("18.1")));
+    }
+
+    #[test]
+    fn test_unpacks_aggregates_and_skips_undecodable_records() {
+        let key = PartitionKey {
+            key: "proj".to_string(),
+            explicit_hash_key: None,
+        };
+        let record = aggregation::encode(&key, &[event(json!({})), b"not json".to_vec(), event(json!({}))]);
+        let rows = rows(&record, "4955", 42);
+        let ids: Vec<_> = rows.iter().map(|row| row.event_id.as_str()).collect();
+        assert_eq!(ids, ["4955-0", "4955-2"]);
+        assert_eq!(rows[0].received_at, 42);
+    }
+}
@@ -0,0 +1,78 @@
+//! The deletion audit trail.
+//!
+//! Each completed deletion leaves one item in `DELETION_AUDIT_TABLE`, keyed
+//! by `deletion_id`: the project, when it was requested and completed, how
+//! many ids it covered and what each store removed. The user id itself is
+//! kept only as a SHA-256 hash, so the record proves a deletion for
+//! whoever asks about that user without keeping the identifier around.
+
+use async_trait::async_trait;
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use lambda_runtime::Error;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+
+/// What a deletion did
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditRecord {
+    pub deletion_id: String,
+    pub project_id: String,
+    /// Hex SHA-256 of the user id
+    pub user_hash: String,
+    /// Ids covered, linked ones included
+    pub ids: u64,
+    /// Epoch milliseconds
+    pub requested_at: i64,
+    /// Epoch milliseconds
+    pub completed_at: i64,
+    /// Items or rows removed, by store
+    pub removed: BTreeMap<String, u64>,
+}
+
+/// Hex SHA-256 of a user id
+pub fn user_hash(user_id: &str) -> String {
+    hex::encode(Sha256::digest(user_id.as_bytes()))
+}
+
+/// Where audit records go
+#[async_trait]
+pub trait AuditLog: Send + Sync {
+    async fn record(&self, record: &AuditRecord) -> Result<(), Error>;
+}
+
+/// Audit records in a DynamoDB table keyed by `deletion_id`
+pub struct DynamoAuditLog {
+    client: DynamoClient,
+    table_name: String,
+}
+
+impl DynamoAuditLog {
+    pub fn new(client: DynamoClient, table_name: String) -> Self {
+        Self { client, table_name }
+    }
+}
+
+#[async_trait]
+impl AuditLog for DynamoAuditLog {
+    async fn record(&self, record: &AuditRecord) -> Result<(), Error> {
+        let number = |value: u64| AttributeValue::N(value.to_string());
+        let removed: HashMap<String, AttributeValue> =
+            record.removed.iter().map(|(store, count)| (store.clone(), number(*count))).collect();
+        self.client
+            .put_item()
+            .table_name(&self.table_name)
+            .item("deletion_id", AttributeValue::S(record.deletion_id.clone()))
+            .item("project_id", AttributeValue::S(record.project_id.clone()))
+            .item("user_hash", AttributeValue::S(record.user_hash.clone()))
+            .item("ids", number(record.ids))
+            .item("requested_at", AttributeValue::N(record.requested_at.to_string()))
+            .item("completed_at", AttributeValue::N(record.completed_at.to_string()))
+            .item("removed", AttributeValue::M(removed))
+            .send()
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,114 @@
+//! Deleting a user's rows from the ClickHouse `events` table.
+//!
+//! Rows whose `user_id` or `anonymous_id` is one of the ids are counted,
+//! then removed with a lightweight `DELETE`, which hides them at once and
+//! drops them at the next merge. `mutations_sync=2` waits for the delete
+//! to reach every replica before the request succeeds. The ids and project
+//! are query parameters, never spliced into the SQL. The connection
+//! settings are the writer's (`CLICKHOUSE_URL` and the rest).
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use clickhouse_writer::clickhouse::ClickHouseConfig;
+use http_body_util::{BodyExt, Full};
+use hyper_rustls::HttpsConnector;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use lambda_runtime::Error;
+
+use crate::erase::Eraser;
+
+/// Filter on the project and ids parameters
+const MATCHING: &str = "project_id = {project:String} \
+    AND (user_id IN {ids:Array(String)} OR anonymous_id IN {ids:Array(String)})";
+
+/// An `Array(String)` parameter value: a quoted, escaped ClickHouse literal
+pub fn array_param(ids: &[String]) -> String {
+    let quoted: Vec<String> = ids
+        .iter()
+        .map(|id| format!("'{}'", id.replace('\\', "\\\\").replace('\'', "\\'")))
+        .collect();
+    format!("[{}]", quoted.join(","))
+}
+
+/// Removes rows over HTTP
+pub struct ClickHouseEraser {
+    http: Client<HttpsConnector<HttpConnector>, Full<Bytes>>,
+    config: ClickHouseConfig,
+}
+
+impl ClickHouseEraser {
+    pub fn new(config: ClickHouseConfig) -> Result<Self, Error> {
+        let connector = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_native_roots()?
+            .https_or_http()
+            .enable_http1()
+            .build();
+        Ok(Self {
+            http: Client::builder(TokioExecutor::new()).build(connector),
+            config,
+        })
+    }
+
+    /// The URL for a statement about `ids` in a project
+    pub fn url(&self, project_id: &str, ids: &[String]) -> String {
+        let mut params = url::form_urlencoded::Serializer::new(String::new());
+        params.append_pair("mutations_sync", "2");
+        params.append_pair("param_project", project_id);
+        params.append_pair("param_ids", &array_param(ids));
+        format!("{}/?{}", self.config.url.trim_end_matches('/'), params.finish())
+    }
+
+    fn table(&self) -> String {
+        format!("`{}`.`{}`", self.config.database, self.config.table)
+    }
+
+    /// Runs a statement, returning its output
+    async fn run(&self, project_id: &str, ids: &[String], sql: String) -> Result<String, Error> {
+        let request = http::Request::post(self.url(project_id, ids))
+            .header("x-clickhouse-user", &self.config.user)
+            .header("x-clickhouse-key", &self.config.password)
+            .body(Full::new(Bytes::from(sql)))?;
+        let response = self.http.request(request).await?;
+        let status = response.status();
+        let body = response.into_body().collect().await?.to_bytes();
+        let body = String::from_utf8_lossy(&body).into_owned();
+        if !status.is_success() {
+            return Err(format!("ClickHouse statement failed with {}: {}", status, body).into());
+        }
+        Ok(body)
+    }
+}
+
+#[async_trait]
+impl Eraser for ClickHouseEraser {
+    fn name(&self) -> &str {
+        "clickhouse"
+    }
+
+    async fn erase(&self, project_id: &str, ids: &[String]) -> Result<u64, Error> {
+        let count = format!("SELECT count() FROM {} WHERE {} FORMAT TabSeparated", self.table(), MATCHING);
+        let rows: u64 = self.run(project_id, ids, count).await?.trim().parse()?;
+        if rows > 0 {
+            let delete = format!("DELETE FROM {} WHERE {}", self.table(), MATCHING);
+            self.run(project_id, ids, delete).await?;
+        }
+        Ok(rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ids_are_passed_as_an_escaped_array() {
+        let ids = ["u1".to_string(), "o'brien\\".to_string()];
+        assert_eq!(array_param(&ids), r"['u1','o\'brien\\']");
+
+        let eraser = ClickHouseEraser::new(ClickHouseConfig::default()).unwrap();
+        let url = eraser.url("p", &ids);
+        assert!(url.starts_with("http://localhost:8123/?mutations_sync=2&param_project=p&param_ids="));
+    }
+}
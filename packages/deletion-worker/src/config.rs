@@ -0,0 +1,40 @@
+//! Worker configuration.
+
+use ingestion::shared::{env_list, env_or, env_var};
+
+/// Configuration for the deletion worker
+#[derive(Debug, Clone)]
+pub struct DeletionWorkerConfig {
+    /// Where audit records are written
+    pub audit_table: String,
+    /// DynamoDB tables keyed by `pk` = `{project}#{id}` to delete from:
+    /// sessions, identity links, last-seen and similar per-visitor state
+    pub tables: Vec<String>,
+    /// The identity resolver's table, to find the ids linked to a user
+    pub identity_table: Option<String>,
+    /// Most ids one request may expand to
+    pub max_ids: usize,
+}
+
+impl Default for DeletionWorkerConfig {
+    fn default() -> Self {
+        Self {
+            audit_table: String::new(),
+            tables: Vec::new(),
+            identity_table: None,
+            max_ids: 1000,
+        }
+    }
+}
+
+impl DeletionWorkerConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            audit_table: env_var("DELETION_AUDIT_TABLE").unwrap_or_default(),
+            tables: env_list("DELETION_TABLES"),
+            identity_table: env_var("IDENTITY_TABLE").filter(|table| !table.is_empty()),
+            max_ids: env_or("DELETION_MAX_IDS", defaults.max_ids).max(1),
+        }
+    }
+}
@@ -0,0 +1,55 @@
+//! What each store removes, and per-visitor state in DynamoDB.
+
+use async_trait::async_trait;
+use aws_sdk_dynamodb::types::{AttributeValue, ReturnValue};
+use aws_sdk_dynamodb::Client as DynamoClient;
+use lambda_runtime::Error;
+
+/// A store holding events or state about users
+#[async_trait]
+pub trait Eraser: Send + Sync {
+    /// Name in the audit record
+    fn name(&self) -> &str;
+    /// Removes everything about `ids` in a project, returning how many
+    /// items or rows went
+    async fn erase(&self, project_id: &str, ids: &[String]) -> Result<u64, Error>;
+}
+
+/// DynamoDB tables keyed by `pk` = `{project}#{id}`
+pub struct DynamoEraser {
+    client: DynamoClient,
+    tables: Vec<String>,
+}
+
+impl DynamoEraser {
+    pub fn new(client: DynamoClient, tables: Vec<String>) -> Self {
+        Self { client, tables }
+    }
+}
+
+#[async_trait]
+impl Eraser for DynamoEraser {
+    fn name(&self) -> &str {
+        "dynamodb"
+    }
+
+    async fn erase(&self, project_id: &str, ids: &[String]) -> Result<u64, Error> {
+        let mut deleted = 0;
+        for table in &self.tables {
+            for id in ids {
+                let output = self
+                    .client
+                    .delete_item()
+                    .table_name(table)
+                    .key("pk", AttributeValue::S(format!("{}#{}", project_id, id)))
+                    .return_values(ReturnValue::AllOld)
+                    .send()
+                    .await?;
+                if output.attributes().is_some() {
+                    deleted += 1;
+                }
+            }
+        }
+        Ok(deleted)
+    }
+}
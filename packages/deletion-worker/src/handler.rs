@@ -0,0 +1,189 @@
+//! The SQS batch handler.
+//!
+//! Each message is one [`DeletionRequest`], handled on its own: its ids are
+//! widened through identity links, every store erases them and the audit
+//! record is written last. A message that fails anywhere is reported as a
+//! batch item failure and comes back after the queue's visibility timeout;
+//! the stores already cleared find nothing the second time. Messages that
+//! aren't deletion requests are logged and dropped, as retrying can't fix
+//! them.
+
+use aws_lambda_events::event::sqs::{BatchItemFailure, SqsBatchResponse, SqsEvent};
+use ingestion::deletion::DeletionRequest;
+use lambda_runtime::Error;
+use std::collections::BTreeMap;
+
+use crate::audit::{user_hash, AuditLog, AuditRecord};
+use crate::config::DeletionWorkerConfig;
+use crate::erase::Eraser;
+use crate::identity::{linked_ids, IdentityLinks};
+
+/// What the worker deletes from and reports to
+pub struct Stores<'a> {
+    pub links: Option<&'a dyn IdentityLinks>,
+    pub erasers: &'a [Box<dyn Eraser>],
+    pub audit: &'a dyn AuditLog,
+}
+
+/// Carries out one request, returning its audit record
+pub async fn delete(
+    request: &DeletionRequest,
+    stores: &Stores<'_>,
+    config: &DeletionWorkerConfig,
+    now: impl Fn() -> i64,
+) -> Result<AuditRecord, Error> {
+    let mut ids = vec![request.user_id.clone()];
+    ids.extend(request.anonymous_ids.iter().cloned());
+    if let Some(links) = stores.links {
+        ids = linked_ids(links, &request.project_id, &ids, config.max_ids)
+            .await?
+            .into_iter()
+            .collect();
+    }
+
+    let mut removed = BTreeMap::new();
+    for eraser in stores.erasers {
+        let count = eraser.erase(&request.project_id, &ids).await?;
+        *removed.entry(eraser.name().to_string()).or_default() += count;
+    }
+
+    let record = AuditRecord {
+        deletion_id: request.deletion_id.clone(),
+        project_id: request.project_id.clone(),
+        user_hash: user_hash(&request.user_id),
+        ids: ids.len() as u64,
+        requested_at: request.requested_at,
+        completed_at: now(),
+        removed,
+    };
+    stores.audit.record(&record).await?;
+    Ok(record)
+}
+
+/// Handles a batch, reporting the messages that should be retried
+pub async fn handle(event: SqsEvent, stores: &Stores<'_>, config: &DeletionWorkerConfig) -> SqsBatchResponse {
+    let mut batch_item_failures = Vec::new();
+    for message in event.records {
+        let message_id = message.message_id.unwrap_or_default();
+        let request: DeletionRequest = match serde_json::from_str(message.body.as_deref().unwrap_or_default()) {
+            Ok(request) => request,
+            Err(e) => {
+                tracing::error!("Dropping message {} that isn't a deletion request: {}", message_id, e);
+                continue;
+            }
+        };
+        match delete(&request, stores, config, || chrono::Utc::now().timestamp_millis()).await {
+            Ok(record) => tracing::info!(
+                "Completed deletion {} in project {}: {:?}",
+                record.deletion_id,
+                record.project_id,
+                record.removed
+            ),
+            Err(e) => {
+                tracing::error!("Deletion {} failed, retrying: {}", request.deletion_id, e);
+                batch_item_failures.push(BatchItemFailure {
+                    item_identifier: message_id,
+                });
+            }
+        }
+    }
+    SqsBatchResponse { batch_item_failures }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity::tests::FakeLinks;
+    use async_trait::async_trait;
+    use serde_json::json;
+    use std::sync::Mutex;
+
+    /// Erases nothing but remembers what it was asked to, or fails
+    struct FakeEraser {
+        fail: bool,
+        asked: Mutex<Vec<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl Eraser for FakeEraser {
+        fn name(&self) -> &str {
+            "fake"
+        }
+
+        async fn erase(&self, _project_id: &str, ids: &[String]) -> Result<u64, Error> {
+            if self.fail {
+                return Err("ThrottlingException".into());
+            }
+            self.asked.lock().unwrap().push(ids.to_vec());
+            Ok(ids.len() as u64)
+        }
+    }
+
+    #[derive(Default)]
+    struct FakeAudit(Mutex<Vec<AuditRecord>>);
+
+    #[async_trait]
+    impl AuditLog for FakeAudit {
+        async fn record(&self, record: &AuditRecord) -> Result<(), Error> {
+            self.0.lock().unwrap().push(record.clone());
+            Ok(())
+        }
+    }
+
+    fn eraser(fail: bool) -> Box<dyn Eraser> {
+        Box::new(FakeEraser {
+            fail,
+            asked: Mutex::new(Vec::new()),
+        })
+    }
+
+    fn batch(bodies: &[&str]) -> SqsEvent {
+        let records: Vec<_> = bodies
+            .iter()
+            .enumerate()
+            .map(|(index, body)| json!({ "messageId": format!("m{}", index), "body": body }))
+            .collect();
+        serde_json::from_value(json!({ "Records": records })).unwrap()
+    }
+
+    const REQUEST: &str = r#"{"deletionId":"d1","projectId":"p","userId":"user-1","requestedAt":1700000000000}"#;
+
+    #[tokio::test]
+    async fn test_erases_linked_ids_and_audits() {
+        let links = FakeLinks::default().link("anon-1", "user-1");
+        let audit = FakeAudit::default();
+        let erasers = [eraser(false)];
+        let stores = Stores {
+            links: Some(&links),
+            erasers: &erasers,
+            audit: &audit,
+        };
+
+        let response = handle(batch(&[REQUEST, "not json"]), &stores, &DeletionWorkerConfig::default()).await;
+
+        assert!(response.batch_item_failures.is_empty());
+        let records = audit.0.lock().unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!((records[0].ids, records[0].removed["fake"]), (2, 2));
+        assert_eq!(records[0].user_hash, user_hash("user-1"));
+        assert_ne!(records[0].user_hash, "user-1");
+    }
+
+    #[tokio::test]
+    async fn test_failed_deletions_are_retried_without_an_audit() {
+        let audit = FakeAudit::default();
+        let erasers = [eraser(false), eraser(true)];
+        let stores = Stores {
+            links: None,
+            erasers: &erasers,
+            audit: &audit,
+        };
+
+        let response = handle(batch(&[REQUEST]), &stores, &DeletionWorkerConfig::default()).await;
+
+        assert_eq!(response.batch_item_failures, [BatchItemFailure {
+            item_identifier: "m0".to_string(),
+        }]);
+        assert!(audit.0.lock().unwrap().is_empty());
+    }
+}
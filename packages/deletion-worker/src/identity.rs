@@ -0,0 +1,158 @@
+//! Finding every id that belongs to a user.
+//!
+//! The identity resolver keeps a parent link per id (`pk` =
+//! `{project}#{id}`, attribute `parent`): an anonymous id points at the user
+//! it was identified as, and an aliased id at the id it was merged into. A
+//! user's ids are everything connected to the requested ones through those
+//! links, in either direction. Parents are one `GetItem` each; children
+//! need a `Scan` filtered on `parent`, one per level of links, which reads
+//! the whole table. Deletions are rare enough for that.
+
+use async_trait::async_trait;
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use lambda_runtime::Error;
+use std::collections::BTreeSet;
+
+/// The identity resolver's links
+#[async_trait]
+pub trait IdentityLinks: Send + Sync {
+    /// The id `id` is linked to, if any
+    async fn parent(&self, project_id: &str, id: &str) -> Result<Option<String>, Error>;
+    /// Ids linked to any of `ids`
+    async fn children(&self, project_id: &str, ids: &[String]) -> Result<Vec<String>, Error>;
+}
+
+/// Links in the identity resolver's DynamoDB table
+pub struct DynamoIdentityLinks {
+    client: DynamoClient,
+    table_name: String,
+}
+
+impl DynamoIdentityLinks {
+    pub fn new(client: DynamoClient, table_name: String) -> Self {
+        Self { client, table_name }
+    }
+}
+
+#[async_trait]
+impl IdentityLinks for DynamoIdentityLinks {
+    async fn parent(&self, project_id: &str, id: &str) -> Result<Option<String>, Error> {
+        let output = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("pk", AttributeValue::S(format!("{}#{}", project_id, id)))
+            .consistent_read(true)
+            .send()
+            .await?;
+        Ok(output
+            .item()
+            .and_then(|item| item.get("parent"))
+            .and_then(|parent| parent.as_s().ok())
+            .cloned())
+    }
+
+    async fn children(&self, project_id: &str, ids: &[String]) -> Result<Vec<String>, Error> {
+        let prefix = format!("{}#", project_id);
+        let mut children = Vec::new();
+        // FilterExpression operands are capped at 100 per IN
+        for chunk in ids.chunks(100) {
+            let names: Vec<String> = (0..chunk.len()).map(|index| format!(":p{}", index)).collect();
+            let mut start_key = None;
+            loop {
+                let mut scan = self
+                    .client
+                    .scan()
+                    .table_name(&self.table_name)
+                    .filter_expression(format!("begins_with(pk, :project) AND parent IN ({})", names.join(", ")))
+                    .expression_attribute_values(":project", AttributeValue::S(prefix.clone()))
+                    .set_exclusive_start_key(start_key);
+                for (name, id) in names.iter().zip(chunk) {
+                    scan = scan.expression_attribute_values(name, AttributeValue::S(id.clone()));
+                }
+                let output = scan.send().await?;
+                children.extend(output.items().iter().filter_map(|item| {
+                    let key = item.get("pk")?.as_s().ok()?;
+                    Some(key.strip_prefix(&prefix)?.to_string())
+                }));
+                start_key = output.last_evaluated_key;
+                if start_key.is_none() {
+                    break;
+                }
+            }
+        }
+        Ok(children)
+    }
+}
+
+/// `ids` and every id linked to them, up to `max_ids`
+pub async fn linked_ids(
+    links: &dyn IdentityLinks,
+    project_id: &str,
+    ids: &[String],
+    max_ids: usize,
+) -> Result<BTreeSet<String>, Error> {
+    let mut found: BTreeSet<String> = ids.iter().cloned().collect();
+    let mut frontier: Vec<String> = found.iter().cloned().collect();
+    while !frontier.is_empty() {
+        let mut next = links.children(project_id, &frontier).await?;
+        for id in &frontier {
+            next.extend(links.parent(project_id, id).await?);
+        }
+        frontier = next.into_iter().filter(|id| found.insert(id.clone())).collect();
+        if found.len() >= max_ids {
+            tracing::warn!("Stopping at {} linked ids in project {}", found.len(), project_id);
+            break;
+        }
+    }
+    Ok(found)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// Links as `(project, id) -> parent`
+    #[derive(Default)]
+    pub(crate) struct FakeLinks(pub HashMap<(String, String), String>);
+
+    impl FakeLinks {
+        pub(crate) fn link(mut self, id: &str, parent: &str) -> Self {
+            self.0.insert(("p".to_string(), id.to_string()), parent.to_string());
+            self
+        }
+    }
+
+    #[async_trait]
+    impl IdentityLinks for FakeLinks {
+        async fn parent(&self, project_id: &str, id: &str) -> Result<Option<String>, Error> {
+            Ok(self.0.get(&(project_id.to_string(), id.to_string())).cloned())
+        }
+
+        async fn children(&self, project_id: &str, ids: &[String]) -> Result<Vec<String>, Error> {
+            Ok(self
+                .0
+                .iter()
+                .filter(|((project, _), parent)| project == project_id && ids.contains(parent))
+                .map(|((_, id), _)| id.clone())
+                .collect())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_follows_links_both_ways() {
+        let links = FakeLinks::default()
+            .link("anon-1", "user-1")
+            .link("anon-2", "user-1")
+            .link("user-1", "user-0")
+            .link("anon-3", "user-2");
+
+        let ids = linked_ids(&links, "p", &["anon-1".to_string()], 100).await.unwrap();
+        assert_eq!(ids.into_iter().collect::<Vec<_>>(), ["anon-1", "anon-2", "user-0", "user-1"]);
+
+        let ids = linked_ids(&links, "other", &["anon-1".to_string()], 100).await.unwrap();
+        assert_eq!(ids.len(), 1);
+    }
+}
@@ -0,0 +1,288 @@
+//! Deleting a user's rows from the Parquet data lake.
+//!
+//! Every file under the project's prefix of the Parquet writer's bucket
+//! (`PARQUET_BUCKET`, `PARQUET_PREFIX`) is read and the rows whose
+//! `user_id` or `anonymous_id` is one of the ids are dropped. A file that
+//! loses rows is rewritten in place under the same key, with its schema
+//! and `schema_version` metadata; one that loses all of them is deleted.
+//! Files without matching rows are left alone. Rewriting a file replaces
+//! S3's only copy unless the bucket is versioned, in which case the old
+//! versions must be expired by a lifecycle rule to complete the deletion.
+
+use arrow_array::{Array, BooleanArray, RecordBatch, StringArray};
+use arrow_select::filter::filter_record_batch;
+use async_trait::async_trait;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::Client as S3Client;
+use bytes::Bytes;
+use lambda_runtime::Error;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use parquet_writer::files::project_path;
+use std::collections::BTreeSet;
+
+use crate::erase::Eraser;
+
+/// The lake's objects; a trait so scrubbing can be tested without S3
+#[async_trait]
+pub trait LakeStore: Send + Sync {
+    /// Keys under `prefix`
+    async fn list(&self, prefix: &str) -> Result<Vec<String>, Error>;
+    async fn get(&self, key: &str) -> Result<Vec<u8>, Error>;
+    async fn put(&self, key: &str, body: Vec<u8>) -> Result<(), Error>;
+    async fn delete(&self, key: &str) -> Result<(), Error>;
+}
+
+/// Objects in an S3 bucket
+pub struct S3LakeStore {
+    client: S3Client,
+    bucket: String,
+}
+
+impl S3LakeStore {
+    pub fn new(client: S3Client, bucket: String) -> Self {
+        Self { client, bucket }
+    }
+}
+
+#[async_trait]
+impl LakeStore for S3LakeStore {
+    async fn list(&self, prefix: &str) -> Result<Vec<String>, Error> {
+        let mut keys = Vec::new();
+        let mut token = None;
+        loop {
+            let output = self
+                .client
+                .list_objects_v2()
+                .bucket(&self.bucket)
+                .prefix(prefix)
+                .set_continuation_token(token)
+                .send()
+                .await?;
+            keys.extend(output.contents().iter().filter_map(|object| object.key().map(String::from)));
+            token = output.next_continuation_token;
+            if token.is_none() {
+                return Ok(keys);
+            }
+        }
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>, Error> {
+        let output = self.client.get_object().bucket(&self.bucket).key(key).send().await?;
+        Ok(output.body.collect().await?.into_bytes().to_vec())
+    }
+
+    async fn put(&self, key: &str, body: Vec<u8>) -> Result<(), Error> {
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .content_type("application/vnd.apache.parquet")
+            .body(ByteStream::from(body))
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), Error> {
+        self.client.delete_object().bucket(&self.bucket).key(key).send().await?;
+        Ok(())
+    }
+}
+
+/// What scrubbing did to a file
+#[derive(Debug, PartialEq)]
+pub enum Scrubbed {
+    /// No rows matched
+    Unchanged,
+    /// The file without the removed rows
+    Rewritten { file: Vec<u8>, removed: u64 },
+    /// Every row matched
+    Emptied { removed: u64 },
+}
+
+/// Which rows of a batch to keep: those whose ids aren't in `ids`
+fn keep(batch: &RecordBatch, ids: &BTreeSet<String>) -> BooleanArray {
+    let column = |name: &str| {
+        batch
+            .column_by_name(name)
+            .and_then(|column| column.as_any().downcast_ref::<StringArray>())
+    };
+    let (users, anonymous) = (column("user_id"), column("anonymous_id"));
+    let matches = |values: Option<&StringArray>, row: usize| {
+        values.is_some_and(|values| values.is_valid(row) && ids.contains(values.value(row)))
+    };
+    (0..batch.num_rows())
+        .map(|row| Some(!(matches(users, row) || matches(anonymous, row))))
+        .collect()
+}
+
+/// Drops the rows of `ids` from a Parquet file
+pub fn scrub(file: Vec<u8>, ids: &BTreeSet<String>) -> Result<Scrubbed, Error> {
+    let builder = ParquetRecordBatchReaderBuilder::try_new(Bytes::from(file))?;
+    let schema = builder.schema().clone();
+    let metadata = builder.metadata().file_metadata().key_value_metadata().cloned();
+    let mut kept = Vec::new();
+    let mut removed = 0;
+    for batch in builder.build()? {
+        let batch = batch?;
+        let mask = keep(&batch, ids);
+        removed += (batch.num_rows() - mask.true_count()) as u64;
+        kept.push(filter_record_batch(&batch, &mask)?);
+    }
+
+    if removed == 0 {
+        return Ok(Scrubbed::Unchanged);
+    }
+    if kept.iter().all(|batch| batch.num_rows() == 0) {
+        return Ok(Scrubbed::Emptied { removed });
+    }
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .set_key_value_metadata(metadata)
+        .build();
+    let mut file = Vec::new();
+    let mut writer = ArrowWriter::try_new(&mut file, schema, Some(properties))?;
+    for batch in kept.iter().filter(|batch| batch.num_rows() > 0) {
+        writer.write(batch)?;
+    }
+    writer.close()?;
+    Ok(Scrubbed::Rewritten { file, removed })
+}
+
+/// Scrubs every file of a project
+pub struct LakeEraser {
+    store: Box<dyn LakeStore>,
+    prefix: String,
+}
+
+impl LakeEraser {
+    pub fn new(store: Box<dyn LakeStore>, prefix: String) -> Self {
+        Self { store, prefix }
+    }
+}
+
+#[async_trait]
+impl Eraser for LakeEraser {
+    fn name(&self) -> &str {
+        "lake"
+    }
+
+    async fn erase(&self, project_id: &str, ids: &[String]) -> Result<u64, Error> {
+        let ids: BTreeSet<String> = ids.iter().cloned().collect();
+        let prefix = format!("{}/{}/", self.prefix.trim_end_matches('/'), project_path(project_id));
+        let mut removed = 0;
+        for key in self.store.list(&prefix).await? {
+            if !key.ends_with(".parquet") {
+                continue;
+            }
+            match scrub(self.store.get(&key).await?, &ids)? {
+                Scrubbed::Unchanged => {}
+                Scrubbed::Rewritten { file, removed: rows } => {
+                    self.store.put(&key, file).await?;
+                    removed += rows;
+                }
+                Scrubbed::Emptied { removed: rows } => {
+                    self.store.delete(&key).await?;
+                    removed += rows;
+                }
+            }
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parquet_writer::schema::{encode, EventRow, SCHEMA_VERSION};
+    use std::collections::BTreeMap;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct FakeLake(Mutex<BTreeMap<String, Vec<u8>>>);
+
+    #[async_trait]
+    impl LakeStore for FakeLake {
+        async fn list(&self, prefix: &str) -> Result<Vec<String>, Error> {
+            Ok(self.0.lock().unwrap().keys().filter(|key| key.starts_with(prefix)).cloned().collect())
+        }
+
+        async fn get(&self, key: &str) -> Result<Vec<u8>, Error> {
+            Ok(self.0.lock().unwrap()[key].clone())
+        }
+
+        async fn put(&self, key: &str, body: Vec<u8>) -> Result<(), Error> {
+            self.0.lock().unwrap().insert(key.to_string(), body);
+            Ok(())
+        }
+
+        async fn delete(&self, key: &str) -> Result<(), Error> {
+            self.0.lock().unwrap().remove(key);
+            Ok(())
+        }
+    }
+
+    fn row(event_id: &str, user_id: Option<&str>, anonymous_id: &str) -> EventRow {
+        EventRow {
+            event_id: event_id.to_string(),
+            project_id: "p".to_string(),
+            event_type: "pageview".to_string(),
+            timestamp: 1_700_000_000_000,
+            received_at: 1_700_000_000_000,
+            user_id: user_id.map(String::from),
+            anonymous_id: Some(anonymous_id.to_string()),
+            session_id: None,
+            page_url: None,
+            page_path: None,
+            page_referrer: None,
+            country: None,
+            is_bot: None,
+            properties: None,
+            context: None,
+            region: None,
+            local_date: None,
+            local_hour: None,
+        }
+    }
+
+    fn event_ids(file: &[u8]) -> Vec<String> {
+        let reader = ParquetRecordBatchReaderBuilder::try_new(Bytes::from(file.to_vec()))
+            .unwrap()
+            .build()
+            .unwrap();
+        reader
+            .flat_map(|batch| {
+                let batch = batch.unwrap();
+                let ids = batch.column_by_name("event_id").unwrap().as_any().downcast_ref::<StringArray>().unwrap().clone();
+                ids.iter().map(|id| id.unwrap().to_string()).collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_rewrites_or_deletes_files_with_the_users_rows() {
+        let lake = FakeLake::default();
+        let mixed = encode(&[row("e1", Some("u1"), "a1"), row("e2", None, "a2"), row("e3", None, "a1")], 10).unwrap();
+        let theirs = encode(&[row("e4", Some("u1"), "a9")], 10).unwrap();
+        let others = encode(&[row("e5", None, "a2")], 10).unwrap();
+        lake.put("events/project_id=p/dt=2023-11-14/hr=22/0-1.parquet", mixed).await.unwrap();
+        lake.put("events/project_id=p/dt=2023-11-14/hr=23/2-2.parquet", theirs).await.unwrap();
+        lake.put("events/project_id=p/dt=2023-11-15/hr=00/3-3.parquet", others.clone()).await.unwrap();
+
+        let eraser = LakeEraser::new(Box::new(lake), "events".to_string());
+        let removed = eraser.erase("p", &["u1".to_string(), "a1".to_string()]).await.unwrap();
+        assert_eq!(removed, 3);
+
+        let lake = eraser.store.list("events/").await.unwrap();
+        assert_eq!(lake.len(), 2);
+        let rewritten = eraser.store.get(&lake[0]).await.unwrap();
+        assert_eq!(event_ids(&rewritten), ["e2"]);
+        let metadata = ParquetRecordBatchReaderBuilder::try_new(Bytes::from(rewritten)).unwrap().metadata().clone();
+        let version = metadata.file_metadata().key_value_metadata().unwrap()[0].value.clone();
+        assert_eq!(version, Some(SCHEMA_VERSION.to_string()));
+        assert_eq!(eraser.store.get(&lake[1]).await.unwrap(), others);
+    }
+}
@@ -0,0 +1,20 @@
+//! GDPR deletion worker.
+//!
+//! Consumes the deletion requests the ingest API's admin route queues on
+//! SQS (see `ingestion::deletion`). For each, the user's ids are widened to
+//! every anonymous id identity resolution linked to them (see
+//! [`identity`]), then each store removes what it holds for those ids:
+//! DynamoDB tables keyed by `{project}#{id}` ([`erase`]), the ClickHouse
+//! `events` table ([`clickhouse`]) and the Parquet data lake ([`lake`]).
+//! Once all of them succeed an audit record is written (see [`audit`]);
+//! a request that fails anywhere is retried whole (see [`handler`]), which
+//! is safe since every step is idempotent. The event source mapping must
+//! enable `ReportBatchItemFailures`.
+
+pub mod audit;
+pub mod clickhouse;
+pub mod config;
+pub mod erase;
+pub mod handler;
+pub mod identity;
+pub mod lake;
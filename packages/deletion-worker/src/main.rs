@@ -0,0 +1,74 @@
+use lambda_runtime::{run, service_fn, Error, LambdaEvent};
+use std::sync::Arc;
+
+use aws_lambda_events::event::sqs::SqsEvent;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use aws_sdk_s3::Client as S3Client;
+use clickhouse_writer::clickhouse::ClickHouseConfig;
+use deletion_worker::audit::{AuditLog, DynamoAuditLog};
+use deletion_worker::clickhouse::ClickHouseEraser;
+use deletion_worker::config::DeletionWorkerConfig;
+use deletion_worker::erase::{DynamoEraser, Eraser};
+use deletion_worker::handler::{handle, Stores};
+use deletion_worker::identity::{DynamoIdentityLinks, IdentityLinks};
+use deletion_worker::lake::{LakeEraser, S3LakeStore};
+use ingestion::shared::env_var;
+use parquet_writer::files::ParquetWriterConfig;
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    // Initialize tracing
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"))
+        )
+        .json()
+        .init();
+
+    let config = Arc::new(DeletionWorkerConfig::from_env());
+    if config.audit_table.is_empty() {
+        return Err("DELETION_AUDIT_TABLE environment variable not set".into());
+    }
+    let aws = aws_config::load_from_env().await;
+    let dynamo = DynamoClient::new(&aws);
+
+    // The identity links go with the rest of the user's state
+    let mut tables = config.tables.clone();
+    if let Some(ref table) = config.identity_table {
+        if !tables.contains(table) {
+            tables.push(table.clone());
+        }
+    }
+    let mut erasers: Vec<Box<dyn Eraser>> = vec![Box::new(DynamoEraser::new(dynamo.clone(), tables))];
+    if env_var("CLICKHOUSE_URL").is_some() {
+        erasers.push(Box::new(ClickHouseEraser::new(ClickHouseConfig::from_env())?));
+    }
+    let lake = ParquetWriterConfig::from_env();
+    if !lake.bucket.is_empty() {
+        erasers.push(Box::new(LakeEraser::new(
+            Box::new(S3LakeStore::new(S3Client::new(&aws), lake.bucket)),
+            lake.prefix,
+        )));
+    }
+    let links: Option<Box<dyn IdentityLinks>> = config
+        .identity_table
+        .clone()
+        .map(|table| Box::new(DynamoIdentityLinks::new(dynamo.clone(), table)) as Box<dyn IdentityLinks>);
+    let audit: Box<dyn AuditLog> = Box::new(DynamoAuditLog::new(dynamo, config.audit_table.clone()));
+    let stores = Arc::new((links, erasers, audit));
+
+    run(service_fn(move |event: LambdaEvent<SqsEvent>| {
+        let (stores, config) = (stores.clone(), config.clone());
+        async move {
+            let (links, erasers, audit) = stores.as_ref();
+            let stores = Stores {
+                links: links.as_deref(),
+                erasers,
+                audit: audit.as_ref(),
+            };
+            Ok::<_, Error>(handle(event.payload, &stores, &config).await)
+        }
+    }))
+    .await
+}
@@ -0,0 +1,118 @@
+//! The Kinesis batch handler.
+//!
+//! A batch is decoded (unpacking KPL aggregates), its heartbeats rolled up,
+//! and each rollup added to the store. The rollups of a batch span many
+//! items, so a failure can't be pinned to a record: the whole batch is
+//! reported failed and retried, and rollups already written before the
+//! failure are counted again.
+
+use aws_lambda_events::event::kinesis::KinesisEvent;
+use aws_lambda_events::event::streams::{KinesisBatchItemFailure, KinesisEventResponse};
+use ingestion::aggregation;
+use ingestion::models::IngestEventPayload;
+
+use crate::rollup::{RollupConfig, Tally};
+use crate::store::RollupStore;
+
+/// Rolls a batch up, reporting it failed if the store wouldn't take it
+pub async fn handle(event: KinesisEvent, store: &dyn RollupStore, config: &RollupConfig) -> KinesisEventResponse {
+    let mut tally = Tally::default();
+    for record in &event.records {
+        for data in aggregation::decode(&record.kinesis.data.0) {
+            match serde_json::from_slice::<IngestEventPayload>(&data) {
+                Ok(event) => tally.add(&event),
+                Err(e) => tracing::warn!("Skipping a record that isn't a JSON event: {}", e),
+            }
+        }
+    }
+
+    let rollups = tally.rollups(config);
+    let expires_at = chrono::Utc::now().timestamp() + config.ttl.as_secs() as i64;
+    for (key, time) in &rollups {
+        if let Err(e) = store.add(key, time, expires_at).await {
+            tracing::error!("Failed to update {} {}, retrying the batch: {}", key.session, key.path, e);
+            let first = event.records.first().and_then(|record| record.kinesis.sequence_number.clone());
+            return KinesisEventResponse {
+                batch_item_failures: vec![KinesisBatchItemFailure { item_identifier: first }],
+            };
+        }
+    }
+
+    tracing::info!("Rolled {} heartbeats up into {} pages", tally.len(), rollups.len());
+    KinesisEventResponse {
+        batch_item_failures: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rollup::{PageKey, PageTime};
+    use async_trait::async_trait;
+    use lambda_runtime::Error;
+    use serde_json::json;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct FakeStore {
+        fail: bool,
+        added: Mutex<Vec<(String, i64)>>,
+    }
+
+    #[async_trait]
+    impl RollupStore for FakeStore {
+        async fn add(&self, key: &PageKey, time: &PageTime, _expires_at: i64) -> Result<(), Error> {
+            if self.fail {
+                return Err("ProvisionedThroughputExceededException".into());
+            }
+            self.added.lock().unwrap().push((key.path.clone(), time.engaged_ms));
+            Ok(())
+        }
+    }
+
+    fn batch(records: &[&str]) -> KinesisEvent {
+        let records: Vec<_> = records
+            .iter()
+            .enumerate()
+            .map(|(index, data)| {
+                let data = aws_lambda_events::encodings::Base64Data(data.as_bytes().to_vec());
+                json!({
+                    "kinesis": {
+                        "sequenceNumber": index.to_string(),
+                        "data": data,
+                        "approximateArrivalTimestamp": 1_700_000_000.0,
+                    },
+                })
+            })
+            .collect();
+        serde_json::from_value(json!({ "Records": records })).unwrap()
+    }
+
+    const HEARTBEAT: &str = r#"{"projectId":"p","eventType":"heartbeat","timestamp":1700000000000,
+        "properties":{"session_id":"s1","url":"https://a.io/pricing"}}"#;
+
+    #[tokio::test]
+    async fn test_rolls_a_batch_up() {
+        let store = FakeStore::default();
+        let response = handle(batch(&[HEARTBEAT, "garbage"]), &store, &RollupConfig::default()).await;
+
+        assert!(response.batch_item_failures.is_empty());
+        assert_eq!(*store.added.lock().unwrap(), [("/pricing".to_string(), 15_000)]);
+    }
+
+    #[tokio::test]
+    async fn test_retries_the_batch_when_the_store_fails() {
+        let store = FakeStore {
+            fail: true,
+            ..Default::default()
+        };
+        let response = handle(batch(&[HEARTBEAT, HEARTBEAT]), &store, &RollupConfig::default()).await;
+
+        assert_eq!(
+            response.batch_item_failures,
+            [KinesisBatchItemFailure {
+                item_identifier: Some("0".to_string()),
+            }]
+        );
+    }
+}
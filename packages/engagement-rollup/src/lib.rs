@@ -0,0 +1,11 @@
+//! Kinesis → DynamoDB engagement rollups.
+//!
+//! Consumes the stream heartbeats are routed to (a `STREAM_ROUTES` entry
+//! like `{"eventType": "heartbeat", "stream": "heartbeats"}`, so they never
+//! reach the raw event writers) and keeps one rollup per session and page:
+//! time on page and engaged time, instead of every ping. See [`rollup`]
+//! for how pings are rolled up and [`store`] for the table layout.
+
+pub mod handler;
+pub mod rollup;
+pub mod store;
@@ -0,0 +1,33 @@
+use lambda_runtime::{run, service_fn, Error, LambdaEvent};
+use std::sync::Arc;
+
+use aws_lambda_events::event::kinesis::KinesisEvent;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use engagement_rollup::handler::handle;
+use engagement_rollup::rollup::RollupConfig;
+use engagement_rollup::store::DynamoRollupStore;
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    // Initialize tracing
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"))
+        )
+        .json()
+        .init();
+
+    let config = Arc::new(RollupConfig::from_env());
+    if config.table_name.is_empty() {
+        return Err("ENGAGEMENT_ROLLUP_TABLE environment variable not set".into());
+    }
+    let aws = aws_config::load_from_env().await;
+    let store = Arc::new(DynamoRollupStore::new(DynamoClient::new(&aws), config.table_name.clone()));
+
+    run(service_fn(move |event: LambdaEvent<KinesisEvent>| {
+        let (store, config) = (store.clone(), config.clone());
+        async move { Ok::<_, Error>(handle(event.payload, store.as_ref(), &config).await) }
+    }))
+    .await
+}
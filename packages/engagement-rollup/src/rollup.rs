@@ -0,0 +1,189 @@
+//! Rolling a batch of heartbeats up per session and page.
+//!
+//! SDKs ping `POST /heartbeat` every `ENGAGEMENT_HEARTBEAT_INTERVAL_MS`
+//! while a page is open. The pings of each session on each page path are
+//! collected from the batch and replaced by one rollup:
+//!
+//! - `opened_at`: one interval before the first ping, when the page was
+//!   opened; time on page is `last_seen - opened_at`
+//! - `last_seen`: the latest ping
+//! - `engaged_ms`: for each ping the user was `engaged` in, the time since
+//!   the previous ping capped at an interval (a full interval for the first
+//!   of the batch), so extra pings on visibility changes don't inflate it
+//! - `heartbeats`: the number of pings
+//!
+//! Events other than heartbeats, and bots, are skipped, so the consumer
+//! can also read the main ingest stream.
+
+use ingestion::enrichment::duplicate_view::{page_url, session_key};
+use ingestion::enrichment::engagement::HEARTBEAT;
+use ingestion::models::IngestEventPayload;
+use ingestion::shared::{env_or, env_var};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Configuration for the rollup
+#[derive(Debug, Clone)]
+pub struct RollupConfig {
+    pub table_name: String,
+    /// How often SDKs ping; the most one ping adds to engaged time
+    pub heartbeat_interval_ms: i64,
+    /// How long rollups are kept
+    pub ttl: Duration,
+}
+
+impl Default for RollupConfig {
+    fn default() -> Self {
+        Self {
+            table_name: String::new(),
+            heartbeat_interval_ms: 15_000,
+            ttl: Duration::from_secs(90 * 86_400),
+        }
+    }
+}
+
+impl RollupConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            table_name: env_var("ENGAGEMENT_ROLLUP_TABLE").unwrap_or_default(),
+            heartbeat_interval_ms: env_or("ENGAGEMENT_HEARTBEAT_INTERVAL_MS", defaults.heartbeat_interval_ms),
+            ttl: Duration::from_secs(86_400 * env_or("ENGAGEMENT_ROLLUP_TTL_DAYS", 90)),
+        }
+    }
+}
+
+/// A session on a page
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PageKey {
+    pub project_id: String,
+    /// Session key (`{project}#{session}`)
+    pub session: String,
+    pub path: String,
+}
+
+/// What a batch adds to a session's time on a page
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PageTime {
+    /// Epoch milliseconds
+    pub opened_at: i64,
+    /// Epoch milliseconds
+    pub last_seen: i64,
+    pub engaged_ms: i64,
+    pub heartbeats: u64,
+}
+
+/// Heartbeats of a batch, by session and page
+#[derive(Debug, Default)]
+pub struct Tally {
+    /// Ping times and whether the user was engaged
+    pings: BTreeMap<PageKey, Vec<(i64, bool)>>,
+}
+
+impl Tally {
+    pub fn add(&mut self, event: &IngestEventPayload) {
+        if event.event_type != HEARTBEAT || event.context.as_ref().is_some_and(|c| c.is_bot == Some(true)) {
+            return;
+        }
+        let (Some(session), Some(path)) = (session_key(event), page_path(event)) else {
+            return;
+        };
+        let engaged = event
+            .properties
+            .as_ref()
+            .and_then(|p| p.get("engaged"))
+            .and_then(|v| v.as_bool())
+            .unwrap_or(true);
+        let key = PageKey {
+            project_id: event.project_id.clone(),
+            session,
+            path,
+        };
+        self.pings.entry(key).or_default().push((event.timestamp, engaged));
+    }
+
+    /// Heartbeats tallied
+    pub fn len(&self) -> usize {
+        self.pings.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pings.is_empty()
+    }
+
+    /// One rollup per session and page
+    pub fn rollups(&self, config: &RollupConfig) -> Vec<(PageKey, PageTime)> {
+        let interval = config.heartbeat_interval_ms;
+        self.pings
+            .iter()
+            .map(|(key, pings)| {
+                let mut pings = pings.clone();
+                pings.sort_unstable();
+                let mut time = PageTime {
+                    opened_at: pings[0].0 - interval,
+                    last_seen: pings[pings.len() - 1].0,
+                    heartbeats: pings.len() as u64,
+                    engaged_ms: 0,
+                };
+                let mut previous: Option<i64> = None;
+                for (timestamp, engaged) in pings {
+                    if engaged {
+                        time.engaged_ms += previous.map_or(interval, |previous| (timestamp - previous).min(interval));
+                    }
+                    previous = Some(timestamp);
+                }
+                (key.clone(), time)
+            })
+            .collect()
+    }
+}
+
+/// Page path from the context, else parsed from the page url
+fn page_path(event: &IngestEventPayload) -> Option<String> {
+    let path = event.context.as_ref().and_then(|c| c.page.as_ref()).and_then(|p| p.path.clone());
+    path.or_else(|| url::Url::parse(page_url(event)?).ok().map(|url| url.path().to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn heartbeat(session: &str, url: &str, timestamp: i64, engaged: bool) -> IngestEventPayload {
+        serde_json::from_value(json!({
+            "projectId": "p",
+            "eventType": "heartbeat",
+            "timestamp": timestamp,
+            "properties": {"session_id": session, "url": url, "engaged": engaged},
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_rolls_up_pings_per_session_and_page() {
+        let mut tally = Tally::default();
+        // Out of order, with an idle ping and an extra one 5s after another
+        for (timestamp, engaged) in [(30_000, true), (15_000, true), (45_000, false), (50_000, true)] {
+            tally.add(&heartbeat("s1", "https://a.io/pricing?x=1", timestamp, engaged));
+        }
+        tally.add(&heartbeat("s1", "https://a.io/", 60_000, true));
+        tally.add(&heartbeat("s2", "https://a.io/pricing", 15_000, true));
+        let mut pageview = heartbeat("s1", "https://a.io/", 1, true);
+        pageview.event_type = "pageview".to_string();
+        tally.add(&pageview);
+        assert_eq!(tally.len(), 6);
+
+        let rollups = tally.rollups(&RollupConfig::default());
+        let paths: Vec<_> = rollups.iter().map(|(key, _)| (key.session.as_str(), key.path.as_str())).collect();
+        assert_eq!(paths, [("p#s1", "/"), ("p#s1", "/pricing"), ("p#s2", "/pricing")]);
+        assert_eq!(
+            rollups[1].1,
+            PageTime {
+                opened_at: 0,
+                last_seen: 50_000,
+                engaged_ms: 15_000 + 15_000 + 5_000,
+                heartbeats: 4,
+            }
+        );
+    }
+}
@@ -0,0 +1,63 @@
+//! Rollup storage in DynamoDB.
+//!
+//! One item per session and page: partition key `pk` (S, the session key
+//! `{project}#{session}`), sort key `sk` (S, `page#{path}`), so a session's
+//! pages are one `Query`. Attributes `project_id` (S), `opened_at` and
+//! `last_seen` (N, epoch milliseconds), the `engaged_ms` and `heartbeats`
+//! counters (N) and `expires_at` (N) for the table's TTL.
+//!
+//! `opened_at` is only set by a session's first batch on the page and
+//! `last_seen` by each later one, in stream order; counters are bumped with
+//! atomic `ADD` updates.
+
+use async_trait::async_trait;
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use lambda_runtime::Error;
+
+use crate::rollup::{PageKey, PageTime};
+
+/// Where rollups are accumulated
+#[async_trait]
+pub trait RollupStore: Send + Sync {
+    /// Adds a batch's rollup of a session's page, keeping it until
+    /// `expires_at` (epoch seconds)
+    async fn add(&self, key: &PageKey, time: &PageTime, expires_at: i64) -> Result<(), Error>;
+}
+
+/// Rollups in a DynamoDB table keyed by `pk` and `sk`
+pub struct DynamoRollupStore {
+    client: DynamoClient,
+    table_name: String,
+}
+
+impl DynamoRollupStore {
+    pub fn new(client: DynamoClient, table_name: String) -> Self {
+        Self { client, table_name }
+    }
+}
+
+#[async_trait]
+impl RollupStore for DynamoRollupStore {
+    async fn add(&self, key: &PageKey, time: &PageTime, expires_at: i64) -> Result<(), Error> {
+        let n = |value: i64| AttributeValue::N(value.to_string());
+        self.client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("pk", AttributeValue::S(key.session.clone()))
+            .key("sk", AttributeValue::S(format!("page#{}", key.path)))
+            .update_expression(
+                "SET project_id = :project, opened_at = if_not_exists(opened_at, :opened), \
+                 last_seen = :last, expires_at = :ttl ADD engaged_ms :engaged, heartbeats :heartbeats",
+            )
+            .expression_attribute_values(":project", AttributeValue::S(key.project_id.clone()))
+            .expression_attribute_values(":opened", n(time.opened_at))
+            .expression_attribute_values(":last", n(time.last_seen))
+            .expression_attribute_values(":ttl", n(expires_at))
+            .expression_attribute_values(":engaged", n(time.engaged_ms))
+            .expression_attribute_values(":heartbeats", AttributeValue::N(time.heartbeats.to_string()))
+            .send()
+            .await?;
+        Ok(())
+    }
+}
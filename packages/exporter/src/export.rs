@@ -0,0 +1,292 @@
+//! Reading a job's events from the lake and writing its export.
+//!
+//! The lake is partitioned by the day an event arrived (`dt=YYYY-MM-DD`,
+//! see `parquet_writer::files`), not by its timestamp, so a job reads the
+//! partitions from the day before its `from` (for clients whose clocks run
+//! ahead) to `late_arrival` past its `to`, and keeps the rows whose
+//! `timestamp` is in range. Every row is given the current lake schema,
+//! with the columns an older file lacks as null, so one export has one set
+//! of columns. CSV exports have a header row, RFC 3339 timestamps and
+//! empty cells for nulls; Parquet exports are in the lake's schema. An
+//! export is built in memory, so the worker's memory bounds how much one
+//! job can hold; `EXPORT_MAX_DAYS` keeps requests within it.
+
+use arrow_array::{new_null_array, Array, ArrayRef, BooleanArray, RecordBatch, TimestampMillisecondArray};
+use arrow_cast::cast;
+use arrow_cast::display::{ArrayFormatter, FormatOptions};
+use arrow_schema::{DataType, SchemaRef};
+use arrow_select::filter::filter_record_batch;
+use bytes::Bytes;
+use chrono::{DateTime, Duration, NaiveDate};
+use lambda_runtime::Error;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use parquet::format::KeyValue;
+use parquet_writer::files::project_path;
+use parquet_writer::schema::{schema, SCHEMA_VERSION};
+
+use crate::jobs::{ExportConfig, ExportFormat, ExportJob};
+use crate::store::ObjectStore;
+
+/// The lake's partitions of a project that may hold events with timestamps
+/// in `[from, to)`, one key prefix per day
+pub fn partition_prefixes(lake_prefix: &str, project_id: &str, from: i64, to: i64, late_arrival: Duration) -> Vec<String> {
+    let day = |ms: i64| DateTime::from_timestamp_millis(ms).unwrap_or_default().date_naive();
+    let first = day(from) - Duration::days(1);
+    let last: NaiveDate = day(to.saturating_add(late_arrival.num_milliseconds()));
+    first
+        .iter_days()
+        .take_while(|date| *date <= last)
+        .map(|date| {
+            format!(
+                "{}/{}/dt={}/",
+                lake_prefix.trim_end_matches('/'),
+                project_path(project_id),
+                date.format("%Y-%m-%d")
+            )
+        })
+        .collect()
+}
+
+/// A batch in `schema`: its columns by name, null where it has none or one
+/// of another type
+fn conform(batch: &RecordBatch, schema: &SchemaRef) -> Result<RecordBatch, Error> {
+    let columns: Vec<ArrayRef> = schema
+        .fields()
+        .iter()
+        .map(|field| {
+            batch
+                .column_by_name(field.name())
+                .filter(|column| column.data_type() == field.data_type())
+                .cloned()
+                .unwrap_or_else(|| new_null_array(field.data_type(), batch.num_rows()))
+        })
+        .collect();
+    Ok(RecordBatch::try_new(schema.clone(), columns)?)
+}
+
+/// Which rows of a batch have a timestamp in `[from, to)`
+fn in_range(batch: &RecordBatch, from: i64, to: i64) -> BooleanArray {
+    let timestamps = batch
+        .column_by_name("timestamp")
+        .and_then(|column| column.as_any().downcast_ref::<TimestampMillisecondArray>());
+    (0..batch.num_rows())
+        .map(|row| {
+            Some(timestamps.is_some_and(|timestamps| {
+                timestamps.is_valid(row) && (from..to).contains(&timestamps.value(row))
+            }))
+        })
+        .collect()
+}
+
+/// Quotes a CSV cell if it needs it
+fn csv_cell(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// An export being written
+enum Writer {
+    Csv(Vec<u8>),
+    Parquet(Box<ArrowWriter<Vec<u8>>>),
+}
+
+impl Writer {
+    fn new(format: ExportFormat, schema: &SchemaRef) -> Result<Self, Error> {
+        Ok(match format {
+            ExportFormat::Csv => {
+                let header: Vec<String> = schema.fields().iter().map(|field| csv_cell(field.name())).collect();
+                Self::Csv(format!("{}\n", header.join(",")).into_bytes())
+            }
+            ExportFormat::Parquet => {
+                let properties = WriterProperties::builder()
+                    .set_compression(Compression::SNAPPY)
+                    .set_key_value_metadata(Some(vec![KeyValue::new(
+                        "schema_version".to_string(),
+                        SCHEMA_VERSION.to_string(),
+                    )]))
+                    .build();
+                Self::Parquet(Box::new(ArrowWriter::try_new(Vec::new(), schema.clone(), Some(properties))?))
+            }
+        })
+    }
+
+    fn write(&mut self, batch: &RecordBatch) -> Result<(), Error> {
+        match self {
+            Self::Csv(out) => {
+                // The lake's timestamps are UTC, formatted as such without
+                // needing a time zone database
+                let options = FormatOptions::default()
+                    .with_display_error(true)
+                    .with_timestamp_format(Some("%Y-%m-%dT%H:%M:%S%.3fZ"));
+                let columns = batch
+                    .columns()
+                    .iter()
+                    .map(|column| match column.data_type() {
+                        DataType::Timestamp(unit, Some(_)) => cast(column, &DataType::Timestamp(*unit, None)),
+                        _ => Ok(column.clone()),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                let formatters = columns
+                    .iter()
+                    .map(|column| ArrayFormatter::try_new(column.as_ref(), &options))
+                    .collect::<Result<Vec<_>, _>>()?;
+                for row in 0..batch.num_rows() {
+                    let cells: Vec<String> = formatters
+                        .iter()
+                        .map(|formatter| csv_cell(&formatter.value(row).to_string()))
+                        .collect();
+                    out.extend_from_slice(cells.join(",").as_bytes());
+                    out.push(b'\n');
+                }
+            }
+            Self::Parquet(writer) => writer.write(batch)?,
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Vec<u8>, Error> {
+        Ok(match self {
+            Self::Csv(out) => out,
+            Self::Parquet(writer) => writer.into_inner()?,
+        })
+    }
+}
+
+/// A job's export and how many rows it has
+pub async fn export(lake: &dyn ObjectStore, job: &ExportJob, config: &ExportConfig) -> Result<(Vec<u8>, u64), Error> {
+    let schema = schema();
+    let late_arrival = Duration::from_std(config.late_arrival)?;
+    let mut writer = Writer::new(job.format, &schema)?;
+    let mut rows = 0;
+    for prefix in partition_prefixes(&config.lake.prefix, &job.project_id, job.from, job.to, late_arrival) {
+        for key in lake.list(&prefix).await? {
+            if !key.ends_with(".parquet") {
+                continue;
+            }
+            let reader = ParquetRecordBatchReaderBuilder::try_new(Bytes::from(lake.get(&key).await?))?.build()?;
+            for batch in reader {
+                let batch = batch?;
+                let batch = filter_record_batch(&batch, &in_range(&batch, job.from, job.to))?;
+                if batch.num_rows() > 0 {
+                    writer.write(&conform(&batch, &schema)?)?;
+                    rows += batch.num_rows() as u64;
+                }
+            }
+        }
+    }
+    Ok((writer.finish()?, rows))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use arrow_array::StringArray;
+    use async_trait::async_trait;
+    use parquet_writer::schema::{encode, EventRow};
+    use std::collections::BTreeMap;
+    use std::sync::Mutex;
+
+    /// A bucket in memory
+    #[derive(Default)]
+    pub struct FakeBucket(pub Mutex<BTreeMap<String, Vec<u8>>>);
+
+    #[async_trait]
+    impl ObjectStore for FakeBucket {
+        async fn list(&self, prefix: &str) -> Result<Vec<String>, Error> {
+            Ok(self.0.lock().unwrap().keys().filter(|key| key.starts_with(prefix)).cloned().collect())
+        }
+
+        async fn get(&self, key: &str) -> Result<Vec<u8>, Error> {
+            Ok(self.0.lock().unwrap()[key].clone())
+        }
+
+        async fn put(&self, key: &str, body: Vec<u8>, _content_type: &str) -> Result<(), Error> {
+            self.0.lock().unwrap().insert(key.to_string(), body);
+            Ok(())
+        }
+    }
+
+    pub fn row(event_id: &str, timestamp: i64, page_path: Option<&str>) -> EventRow {
+        EventRow {
+            event_id: event_id.to_string(),
+            project_id: "p".to_string(),
+            event_type: "pageview".to_string(),
+            timestamp,
+            received_at: timestamp,
+            user_id: None,
+            anonymous_id: Some("a1".to_string()),
+            session_id: None,
+            page_url: None,
+            page_path: page_path.map(String::from),
+            page_referrer: None,
+            country: None,
+            is_bot: None,
+            properties: None,
+            context: None,
+            region: None,
+            local_date: None,
+            local_hour: None,
+        }
+    }
+
+    /// A lake with files on 2023-11-14 and -15, and one of another project
+    pub fn lake() -> FakeBucket {
+        let lake = FakeBucket::default();
+        let files = [
+            ("events/project_id=p/dt=2023-11-14/hr=22/0-1.parquet", vec![
+                row("e1", 1_699_999_000_000, Some("/")),
+                row("e2", 1_700_000_000_000, Some("/a,\"b\"")),
+            ]),
+            ("events/project_id=p/dt=2023-11-15/hr=00/2-2.parquet", vec![row("e3", 1_700_006_400_000, None)]),
+            ("events/project_id=q/dt=2023-11-14/hr=22/3-3.parquet", vec![row("e4", 1_700_000_000_000, None)]),
+        ];
+        for (key, rows) in files {
+            lake.0.lock().unwrap().insert(key.to_string(), encode(&rows, 10).unwrap());
+        }
+        lake
+    }
+
+    #[test]
+    fn test_reads_the_days_events_may_have_arrived_on() {
+        let prefixes = partition_prefixes("events/", "p", 1_700_000_000_000, 1_700_006_400_000, Duration::hours(24));
+        assert_eq!(prefixes, [
+            "events/project_id=p/dt=2023-11-13/",
+            "events/project_id=p/dt=2023-11-14/",
+            "events/project_id=p/dt=2023-11-15/",
+            "events/project_id=p/dt=2023-11-16/",
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_exports_the_rows_in_range_as_csv() {
+        let job = ExportJob::new("j".to_string(), "p".to_string(), ExportFormat::Csv, 1_700_000_000_000, 1_700_086_400_000, 0);
+        let (file, rows) = export(&lake(), &job, &ExportConfig::default()).await.unwrap();
+
+        assert_eq!(rows, 2);
+        let csv = String::from_utf8(file).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert!(lines[0].starts_with("event_id,project_id,event_type,timestamp,received_at,user_id,"));
+        assert!(lines[1].starts_with("e2,p,pageview,2023-11-14T22:13:20.000Z,2023-11-14T22:13:20.000Z,,a1,,,\"/a,\"\"b\"\"\","));
+        assert!(lines[2].starts_with("e3,"));
+        assert_eq!(lines.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_exports_parquet_in_the_lake_schema() {
+        let job = ExportJob::new("j".to_string(), "p".to_string(), ExportFormat::Parquet, 1_699_990_000_000, 1_700_000_000_001, 0);
+        let (file, rows) = export(&lake(), &job, &ExportConfig::default()).await.unwrap();
+
+        assert_eq!(rows, 2);
+        let reader = ParquetRecordBatchReaderBuilder::try_new(Bytes::from(file)).unwrap().build().unwrap();
+        let batches: Vec<RecordBatch> = reader.map(|batch| batch.unwrap()).collect();
+        assert_eq!(batches[0].schema().fields().len(), schema().fields().len());
+        let ids = batches[0].column_by_name("event_id").unwrap().as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(ids.iter().flatten().collect::<Vec<_>>(), ["e1", "e2"]);
+    }
+}
@@ -0,0 +1,164 @@
+//! The SQS batch handler.
+//!
+//! Each message names one job, which is marked `running`, exported and
+//! marked `succeeded` with its key and row count, or `failed` with the
+//! error. A failed export isn't retried: the caller sees why and can ask
+//! again. Only a failure to record the job's status is reported as a batch
+//! item failure, so the message comes back after the queue's visibility
+//! timeout. Jobs already finished (a message delivered twice) and messages
+//! that don't name a known job are logged and dropped.
+
+use aws_lambda_events::event::sqs::{BatchItemFailure, SqsBatchResponse, SqsEvent};
+use lambda_runtime::Error;
+
+use crate::export::export;
+use crate::jobs::{ExportConfig, ExportJob, JobMessage, JobStatus, JobStore};
+use crate::store::ObjectStore;
+
+/// What the worker reads from and writes to
+pub struct Stores<'a> {
+    pub jobs: &'a dyn JobStore,
+    pub lake: &'a dyn ObjectStore,
+    pub exports: &'a dyn ObjectStore,
+}
+
+/// When a job's item expires, in epoch seconds
+fn expires_at(job: &ExportJob, config: &ExportConfig) -> i64 {
+    job.created_at / 1000 + config.job_ttl.as_secs() as i64
+}
+
+/// Carries out one job, returning it as last recorded
+pub async fn run(mut job: ExportJob, stores: &Stores<'_>, config: &ExportConfig) -> Result<ExportJob, Error> {
+    job.status = JobStatus::Running;
+    stores.jobs.put(&job, expires_at(&job, config)).await?;
+
+    let key = job.object_key(&config.prefix);
+    let exported = match export(stores.lake, &job, config).await {
+        Ok((file, rows)) => stores.exports.put(&key, file, job.format.content_type()).await.map(|_| rows),
+        Err(e) => Err(e),
+    };
+    match exported {
+        Ok(rows) => {
+            job.status = JobStatus::Succeeded;
+            job.rows = Some(rows);
+            job.key = Some(key);
+        }
+        Err(e) => {
+            job.status = JobStatus::Failed;
+            job.error = Some(e.to_string());
+        }
+    }
+    stores.jobs.put(&job, expires_at(&job, config)).await?;
+    Ok(job)
+}
+
+/// Handles a batch, reporting the messages that should be retried
+pub async fn handle(event: SqsEvent, stores: &Stores<'_>, config: &ExportConfig) -> SqsBatchResponse {
+    let mut batch_item_failures = Vec::new();
+    for message in event.records {
+        let message_id = message.message_id.unwrap_or_default();
+        let job_id = match serde_json::from_str::<JobMessage>(message.body.as_deref().unwrap_or_default()) {
+            Ok(message) => message.job_id,
+            Err(e) => {
+                tracing::error!("Dropping message {} that isn't an export job: {}", message_id, e);
+                continue;
+            }
+        };
+        let result = match stores.jobs.get(&job_id).await {
+            Ok(None) => {
+                tracing::error!("Dropping message {} for unknown export job {}", message_id, job_id);
+                continue;
+            }
+            Ok(Some(job)) if matches!(job.status, JobStatus::Succeeded | JobStatus::Failed) => {
+                tracing::info!("Export job {} already {}", job_id, job.status.as_str());
+                continue;
+            }
+            Ok(Some(job)) => run(job, stores, config).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(job) => tracing::info!(
+                "Export job {} in project {} {}: {:?} rows, error {:?}",
+                job.job_id,
+                job.project_id,
+                job.status.as_str(),
+                job.rows,
+                job.error
+            ),
+            Err(e) => {
+                tracing::error!("Export job {} failed, retrying: {}", job_id, e);
+                batch_item_failures.push(BatchItemFailure {
+                    item_identifier: message_id,
+                });
+            }
+        }
+    }
+    SqsBatchResponse { batch_item_failures }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::tests::{lake, FakeBucket};
+    use crate::jobs::tests::FakeJobs;
+    use crate::jobs::ExportFormat;
+    use serde_json::json;
+
+    fn batch(bodies: &[&str]) -> SqsEvent {
+        let records: Vec<_> = bodies
+            .iter()
+            .enumerate()
+            .map(|(index, body)| json!({ "messageId": format!("m{}", index), "body": body }))
+            .collect();
+        serde_json::from_value(json!({ "Records": records })).unwrap()
+    }
+
+    async fn jobs(jobs: &[ExportJob]) -> FakeJobs {
+        let store = FakeJobs::default();
+        for job in jobs {
+            store.put(job, 0).await.unwrap();
+        }
+        store
+    }
+
+    #[tokio::test]
+    async fn test_exports_pending_jobs_once() {
+        let job = ExportJob::new("j1".to_string(), "p".to_string(), ExportFormat::Csv, 1_699_990_000_000, 1_700_086_400_000, 0);
+        let (jobs, lake, exports) = (jobs(&[job]).await, lake(), FakeBucket::default());
+        let stores = Stores {
+            jobs: &jobs,
+            lake: &lake,
+            exports: &exports,
+        };
+        let message = r#"{"jobId":"j1"}"#;
+
+        let response = handle(batch(&[message, message, r#"{"jobId":"j2"}"#]), &stores, &ExportConfig::default()).await;
+
+        assert!(response.batch_item_failures.is_empty());
+        let statuses: Vec<_> = jobs.writes.lock().unwrap().iter().map(|job| job.status).collect();
+        assert_eq!(statuses, [JobStatus::Pending, JobStatus::Running, JobStatus::Succeeded]);
+        let job = jobs.get("j1").await.unwrap().unwrap();
+        assert_eq!((job.rows, job.key.as_deref()), (Some(3), Some("exports/project_id=p/j1.csv")));
+        assert!(exports.0.lock().unwrap().contains_key("exports/project_id=p/j1.csv"));
+    }
+
+    #[tokio::test]
+    async fn test_failed_exports_are_recorded_not_retried() {
+        let job = ExportJob::new("j1".to_string(), "p".to_string(), ExportFormat::Csv, 1_699_990_000_000, 1_700_086_400_000, 0);
+        let (jobs, lake, exports) = (jobs(&[job]).await, lake(), FakeBucket::default());
+        lake.0.lock().unwrap().insert("events/project_id=p/dt=2023-11-14/hr=23/9-9.parquet".to_string(), b"junk".to_vec());
+        let stores = Stores {
+            jobs: &jobs,
+            lake: &lake,
+            exports: &exports,
+        };
+
+        let response = handle(batch(&[r#"{"jobId":"j1"}"#]), &stores, &ExportConfig::default()).await;
+
+        assert!(response.batch_item_failures.is_empty());
+        let job = jobs.get("j1").await.unwrap().unwrap();
+        assert_eq!(job.status, JobStatus::Failed);
+        assert!(job.error.is_some());
+        assert!(exports.0.lock().unwrap().is_empty());
+    }
+}
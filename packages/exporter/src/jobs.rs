@@ -0,0 +1,368 @@
+//! Export jobs, where they're kept and how they're queued.
+//!
+//! A job is one item of `EXPORT_JOBS_TABLE`, keyed by `job_id`, written as
+//! `pending` by the query API, `running` and then `succeeded` or `failed`
+//! by the worker. Items carry an `expires_at` (`EXPORT_JOB_TTL_DAYS`,
+//! default 7) for the table's TTL; the exported objects should be expired
+//! by a lifecycle rule on `EXPORT_BUCKET` after the same time. The queue
+//! (`EXPORT_QUEUE_URL`) only carries the job id.
+
+use async_trait::async_trait;
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use aws_sdk_sqs::Client as SqsClient;
+use ingestion::shared::{env_or, env_var};
+use lambda_runtime::Error;
+use parquet_writer::files::{project_path, ParquetWriterConfig};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Configuration shared by the query API's export routes and the worker
+#[derive(Debug, Clone)]
+pub struct ExportConfig {
+    /// Where jobs are kept
+    pub jobs_table: String,
+    /// Where job ids are queued for the worker
+    pub queue_url: String,
+    /// Where exports are written
+    pub bucket: String,
+    /// Key prefix for exports
+    pub prefix: String,
+    /// How long jobs are kept
+    pub job_ttl: Duration,
+    /// How long a download link lasts
+    pub link_ttl: Duration,
+    /// Longest range one job may cover, in days
+    pub max_days: i64,
+    /// How long after an event's timestamp it may still arrive; the lake's
+    /// partitions that late are read too
+    pub late_arrival: Duration,
+    /// The lake the events are read from
+    pub lake: ParquetWriterConfig,
+}
+
+impl Default for ExportConfig {
+    fn default() -> Self {
+        Self {
+            jobs_table: String::new(),
+            queue_url: String::new(),
+            bucket: String::new(),
+            prefix: "exports".to_string(),
+            job_ttl: Duration::from_secs(7 * 24 * 3600),
+            link_ttl: Duration::from_secs(3600),
+            max_days: 31,
+            late_arrival: Duration::from_secs(24 * 3600),
+            lake: ParquetWriterConfig::default(),
+        }
+    }
+}
+
+impl ExportConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            jobs_table: env_var("EXPORT_JOBS_TABLE").unwrap_or_default(),
+            queue_url: env_var("EXPORT_QUEUE_URL").unwrap_or_default(),
+            bucket: env_var("EXPORT_BUCKET").unwrap_or_default(),
+            prefix: env_or("EXPORT_PREFIX", defaults.prefix),
+            job_ttl: Duration::from_secs(env_or("EXPORT_JOB_TTL_DAYS", 7u64).max(1) * 24 * 3600),
+            link_ttl: Duration::from_secs(env_or("EXPORT_LINK_TTL_SECONDS", 3600u64).clamp(60, 7 * 24 * 3600)),
+            max_days: env_or("EXPORT_MAX_DAYS", defaults.max_days).max(1),
+            late_arrival: Duration::from_secs(env_or("EXPORT_LATE_ARRIVAL_HOURS", 24u64) * 3600),
+            lake: ParquetWriterConfig::from_env(),
+        }
+    }
+}
+
+/// What an export is written as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    Parquet,
+}
+
+impl ExportFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Parquet => "parquet",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv",
+            Self::Parquet => "application/vnd.apache.parquet",
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "csv" => Ok(Self::Csv),
+            "parquet" => Ok(Self::Parquet),
+            _ => Err("format must be csv or parquet".to_string()),
+        }
+    }
+}
+
+/// Where a job is at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobStatus {
+    Pending,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+impl JobStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Running => "running",
+            Self::Succeeded => "succeeded",
+            Self::Failed => "failed",
+        }
+    }
+}
+
+impl FromStr for JobStatus {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "pending" => Ok(Self::Pending),
+            "running" => Ok(Self::Running),
+            "succeeded" => Ok(Self::Succeeded),
+            "failed" => Ok(Self::Failed),
+            _ => Err(format!("unknown job status {}", value)),
+        }
+    }
+}
+
+/// An export of a project's events with timestamps in `[from, to)`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExportJob {
+    pub job_id: String,
+    pub project_id: String,
+    pub format: ExportFormat,
+    /// Epoch milliseconds
+    pub from: i64,
+    /// Epoch milliseconds, exclusive
+    pub to: i64,
+    pub status: JobStatus,
+    /// Epoch milliseconds
+    pub created_at: i64,
+    /// Rows exported, once succeeded
+    pub rows: Option<u64>,
+    /// The export's key in the bucket, once succeeded
+    pub key: Option<String>,
+    /// Why it failed
+    pub error: Option<String>,
+}
+
+impl ExportJob {
+    /// A new pending job
+    pub fn new(job_id: String, project_id: String, format: ExportFormat, from: i64, to: i64, now: i64) -> Self {
+        Self {
+            job_id,
+            project_id,
+            format,
+            from,
+            to,
+            status: JobStatus::Pending,
+            created_at: now,
+            rows: None,
+            key: None,
+            error: None,
+        }
+    }
+
+    /// Where its export goes: `{prefix}/project_id=…/{job_id}.{csv|parquet}`
+    pub fn object_key(&self, prefix: &str) -> String {
+        format!(
+            "{}/{}/{}.{}",
+            prefix.trim_end_matches('/'),
+            project_path(&self.project_id),
+            self.job_id,
+            self.format.as_str()
+        )
+    }
+}
+
+/// The message queued for a job
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobMessage {
+    pub job_id: String,
+}
+
+/// Where jobs are kept
+#[async_trait]
+pub trait JobStore: Send + Sync {
+    /// Writes a job, keeping it until `expires_at` (epoch seconds)
+    async fn put(&self, job: &ExportJob, expires_at: i64) -> Result<(), Error>;
+    async fn get(&self, job_id: &str) -> Result<Option<ExportJob>, Error>;
+}
+
+/// Jobs in a DynamoDB table keyed by `job_id`
+pub struct DynamoJobStore {
+    client: DynamoClient,
+    table_name: String,
+}
+
+impl DynamoJobStore {
+    pub fn new(client: DynamoClient, table_name: String) -> Self {
+        Self { client, table_name }
+    }
+}
+
+fn number(item: &HashMap<String, AttributeValue>, name: &str) -> Option<i64> {
+    item.get(name)?.as_n().ok()?.parse().ok()
+}
+
+fn string(item: &HashMap<String, AttributeValue>, name: &str) -> Option<String> {
+    item.get(name)?.as_s().ok().cloned()
+}
+
+/// Reads a job item; `None` if it's missing an attribute
+fn job(item: &HashMap<String, AttributeValue>) -> Option<ExportJob> {
+    Some(ExportJob {
+        job_id: string(item, "job_id")?,
+        project_id: string(item, "project_id")?,
+        format: string(item, "format")?.parse().ok()?,
+        from: number(item, "from")?,
+        to: number(item, "to")?,
+        status: string(item, "status")?.parse().ok()?,
+        created_at: number(item, "created_at")?,
+        rows: number(item, "rows").map(|rows| rows as u64),
+        key: string(item, "object_key"),
+        error: string(item, "error"),
+    })
+}
+
+#[async_trait]
+impl JobStore for DynamoJobStore {
+    async fn put(&self, job: &ExportJob, expires_at: i64) -> Result<(), Error> {
+        let mut put = self
+            .client
+            .put_item()
+            .table_name(&self.table_name)
+            .item("job_id", AttributeValue::S(job.job_id.clone()))
+            .item("project_id", AttributeValue::S(job.project_id.clone()))
+            .item("format", AttributeValue::S(job.format.as_str().to_string()))
+            .item("from", AttributeValue::N(job.from.to_string()))
+            .item("to", AttributeValue::N(job.to.to_string()))
+            .item("status", AttributeValue::S(job.status.as_str().to_string()))
+            .item("created_at", AttributeValue::N(job.created_at.to_string()))
+            .item("expires_at", AttributeValue::N(expires_at.to_string()));
+        if let Some(rows) = job.rows {
+            put = put.item("rows", AttributeValue::N(rows.to_string()));
+        }
+        if let Some(ref key) = job.key {
+            put = put.item("object_key", AttributeValue::S(key.clone()));
+        }
+        if let Some(ref error) = job.error {
+            put = put.item("error", AttributeValue::S(error.clone()));
+        }
+        put.send().await?;
+        Ok(())
+    }
+
+    async fn get(&self, job_id: &str) -> Result<Option<ExportJob>, Error> {
+        let output = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("job_id", AttributeValue::S(job_id.to_string()))
+            .consistent_read(true)
+            .send()
+            .await?;
+        Ok(output.item().and_then(job))
+    }
+}
+
+/// Where job ids are queued for the worker
+#[async_trait]
+pub trait JobQueue: Send + Sync {
+    async fn enqueue(&self, job_id: &str) -> Result<(), Error>;
+}
+
+/// Queues job ids on SQS
+pub struct SqsJobQueue {
+    client: SqsClient,
+    queue_url: String,
+}
+
+impl SqsJobQueue {
+    pub fn new(client: SqsClient, queue_url: String) -> Self {
+        Self { client, queue_url }
+    }
+}
+
+#[async_trait]
+impl JobQueue for SqsJobQueue {
+    async fn enqueue(&self, job_id: &str) -> Result<(), Error> {
+        let message = JobMessage {
+            job_id: job_id.to_string(),
+        };
+        self.client
+            .send_message()
+            .queue_url(&self.queue_url)
+            .message_body(serde_json::to_string(&message)?)
+            .send()
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Jobs in memory, with every write kept in order
+    #[derive(Default)]
+    pub struct FakeJobs {
+        pub writes: Mutex<Vec<ExportJob>>,
+    }
+
+    #[async_trait]
+    impl JobStore for FakeJobs {
+        async fn put(&self, job: &ExportJob, _expires_at: i64) -> Result<(), Error> {
+            self.writes.lock().unwrap().push(job.clone());
+            Ok(())
+        }
+
+        async fn get(&self, job_id: &str) -> Result<Option<ExportJob>, Error> {
+            Ok(self.writes.lock().unwrap().iter().rev().find(|job| job.job_id == job_id).cloned())
+        }
+    }
+
+    #[test]
+    fn test_job_keys_and_items() {
+        let mut job = ExportJob::new("j1".to_string(), "my project".to_string(), ExportFormat::Csv, 1, 2, 3);
+        assert_eq!(job.object_key("exports/"), "exports/project_id=my+project/j1.csv");
+
+        job.status = JobStatus::Succeeded;
+        job.rows = Some(10);
+        let item = HashMap::from([
+            ("job_id".to_string(), AttributeValue::S("j1".to_string())),
+            ("project_id".to_string(), AttributeValue::S("my project".to_string())),
+            ("format".to_string(), AttributeValue::S("csv".to_string())),
+            ("from".to_string(), AttributeValue::N("1".to_string())),
+            ("to".to_string(), AttributeValue::N("2".to_string())),
+            ("status".to_string(), AttributeValue::S("succeeded".to_string())),
+            ("created_at".to_string(), AttributeValue::N("3".to_string())),
+            ("rows".to_string(), AttributeValue::N("10".to_string())),
+        ]);
+        assert_eq!(super::job(&item), Some(job));
+        assert_eq!("PARQUET".parse(), Ok(ExportFormat::Parquet));
+        assert!("json".parse::<ExportFormat>().is_err());
+    }
+}
@@ -0,0 +1,14 @@
+//! Raw event exports.
+//!
+//! The query API's `POST /exports` records an [`ExportJob`](jobs::ExportJob)
+//! and queues its id; this worker picks it up from SQS, reads the project's
+//! files in the Parquet data lake for the job's range (see [`export`]),
+//! writes them as one CSV or Parquet object to the export bucket and marks
+//! the job done. `GET /exports/{jobId}` polls the job and hands out a
+//! presigned link once it succeeded. See [`jobs`] for the job table and
+//! queue, [`store`] for the buckets and [`handler`] for the worker.
+
+pub mod export;
+pub mod handler;
+pub mod jobs;
+pub mod store;
@@ -0,0 +1,49 @@
+use lambda_runtime::{run, service_fn, Error, LambdaEvent};
+use std::sync::Arc;
+
+use aws_lambda_events::event::sqs::SqsEvent;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use aws_sdk_s3::Client as S3Client;
+use exporter::handler::{handle, Stores};
+use exporter::jobs::{DynamoJobStore, ExportConfig};
+use exporter::store::S3ObjectStore;
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    // Initialize tracing
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"))
+        )
+        .json()
+        .init();
+
+    let config = Arc::new(ExportConfig::from_env());
+    if config.jobs_table.is_empty() {
+        return Err("EXPORT_JOBS_TABLE environment variable not set".into());
+    }
+    if config.bucket.is_empty() {
+        return Err("EXPORT_BUCKET environment variable not set".into());
+    }
+    if config.lake.bucket.is_empty() {
+        return Err("PARQUET_BUCKET environment variable not set".into());
+    }
+    let aws = aws_config::load_from_env().await;
+    let s3 = S3Client::new(&aws);
+    let stores = Arc::new((
+        DynamoJobStore::new(DynamoClient::new(&aws), config.jobs_table.clone()),
+        S3ObjectStore::new(s3.clone(), config.lake.bucket.clone()),
+        S3ObjectStore::new(s3, config.bucket.clone()),
+    ));
+
+    run(service_fn(move |event: LambdaEvent<SqsEvent>| {
+        let (stores, config) = (stores.clone(), config.clone());
+        async move {
+            let (jobs, lake, exports) = stores.as_ref();
+            let stores = Stores { jobs, lake, exports };
+            Ok::<_, Error>(handle(event.payload, &stores, &config).await)
+        }
+    }))
+    .await
+}
@@ -0,0 +1,67 @@
+//! The buckets read from and written to.
+
+use async_trait::async_trait;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::Client as S3Client;
+use lambda_runtime::Error;
+
+/// A bucket's objects; a trait so exports can be tested without S3
+#[async_trait]
+pub trait ObjectStore: Send + Sync {
+    /// Keys under `prefix`
+    async fn list(&self, prefix: &str) -> Result<Vec<String>, Error>;
+    async fn get(&self, key: &str) -> Result<Vec<u8>, Error>;
+    async fn put(&self, key: &str, body: Vec<u8>, content_type: &str) -> Result<(), Error>;
+}
+
+/// Objects in an S3 bucket
+pub struct S3ObjectStore {
+    client: S3Client,
+    bucket: String,
+}
+
+impl S3ObjectStore {
+    pub fn new(client: S3Client, bucket: String) -> Self {
+        Self { client, bucket }
+    }
+}
+
+#[async_trait]
+impl ObjectStore for S3ObjectStore {
+    async fn list(&self, prefix: &str) -> Result<Vec<String>, Error> {
+        let mut keys = Vec::new();
+        let mut token = None;
+        loop {
+            let output = self
+                .client
+                .list_objects_v2()
+                .bucket(&self.bucket)
+                .prefix(prefix)
+                .set_continuation_token(token)
+                .send()
+                .await?;
+            keys.extend(output.contents().iter().filter_map(|object| object.key().map(String::from)));
+            token = output.next_continuation_token;
+            if token.is_none() {
+                return Ok(keys);
+            }
+        }
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>, Error> {
+        let output = self.client.get_object().bucket(&self.bucket).key(key).send().await?;
+        Ok(output.body.collect().await?.into_bytes().to_vec())
+    }
+
+    async fn put(&self, key: &str, body: Vec<u8>, content_type: &str) -> Result<(), Error> {
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .content_type(content_type)
+            .body(ByteStream::from(body))
+            .send()
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,122 @@
+//! Flattening events into columns.
+//!
+//! Firehose's record format conversion maps top-level JSON keys to the
+//! columns of a Glue table, so nested objects either need a struct column
+//! per shape or end up as opaque strings. An event is flattened instead:
+//!
+//! - top-level fields keep their place, renamed to snake_case
+//!   (`anonymousId` becomes `anonymous_id`)
+//! - every leaf of `context` becomes its own `context_*` column, named
+//!   after its path (`context.page.url` becomes `context_page_url`,
+//!   `context.geo.country` becomes `context_geo_country`)
+//! - free-form maps ([`JSON_COLUMNS`]) and arrays inside `context` are
+//!   JSON strings, since their keys aren't known up front
+//!
+//! Column names are lowercase ASCII letters, digits and underscores; any
+//! other character in a client-supplied key becomes an underscore.
+
+use ingestion::models::IngestEventPayload;
+use serde_json::{Map, Value};
+
+/// Top-level fields kept as JSON strings
+pub const JSON_COLUMNS: [&str; 3] = ["properties", "traits", "traitsSetOnce"];
+
+/// The snake_case column name of a key
+pub fn column_name(key: &str) -> String {
+    let mut name = String::with_capacity(key.len() + 4);
+    let mut previous: Option<char> = None;
+    for c in key.chars() {
+        if c.is_ascii_uppercase() && previous.is_some_and(|p| p.is_ascii_lowercase() || p.is_ascii_digit()) {
+            name.push('_');
+        }
+        name.push(if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' });
+        previous = Some(c);
+    }
+    name
+}
+
+/// An event as one flat JSON object
+pub fn flatten(event: &IngestEventPayload) -> Map<String, Value> {
+    let Ok(Value::Object(fields)) = serde_json::to_value(event) else {
+        return Map::new();
+    };
+    let mut columns = Map::with_capacity(fields.len() + 16);
+    for (key, value) in fields {
+        match value {
+            Value::Object(context) if key == "context" => spread(&mut columns, "context", context),
+            value if JSON_COLUMNS.contains(&key.as_str()) => {
+                columns.insert(column_name(&key), Value::String(value.to_string()));
+            }
+            value => {
+                columns.insert(column_name(&key), value);
+            }
+        }
+    }
+    columns
+}
+
+/// Adds an object's leaves as `{prefix}_{path}` columns
+fn spread(columns: &mut Map<String, Value>, prefix: &str, object: Map<String, Value>) {
+    for (key, value) in object {
+        let name = format!("{}_{}", prefix, column_name(&key));
+        match value {
+            Value::Object(nested) => spread(columns, &name, nested),
+            Value::Array(_) => {
+                columns.insert(name, Value::String(value.to_string()));
+            }
+            value => {
+                columns.insert(name, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_column_names_are_snake_case() {
+        assert_eq!(column_name("anonymousId"), "anonymous_id");
+        assert_eq!(column_name("traitsSetOnce"), "traits_set_once");
+        assert_eq!(column_name("project_id"), "project_id");
+        assert_eq!(column_name("UTM Source"), "utm_source");
+        assert_eq!(column_name("x-app.build"), "x_app_build");
+    }
+
+    #[test]
+    fn test_context_becomes_columns() {
+        let event: IngestEventPayload = serde_json::from_value(json!({
+            "projectId": "proj",
+            "eventType": "signup",
+            "timestamp": 1_700_000_000_000i64,
+            "anonymousId": "anon-1",
+            "properties": {"plan": "pro"},
+            "context": {
+                "page": {"url": "https://shop.io/join", "path": "/join"},
+                "userAgent": "Mozilla/5.0",
+                "geo": {"country": "DE"},
+                "experiments": ["a", "b"]
+            }
+        }))
+        .unwrap();
+
+        let columns = Value::Object(flatten(&event));
+        assert_eq!(
+            columns,
+            json!({
+                "project_id": "proj",
+                "event_type": "signup",
+                "timestamp": 1_700_000_000_000i64,
+                "anonymous_id": "anon-1",
+                "properties": "{\"plan\":\"pro\"}",
+                "context_page_url": "https://shop.io/join",
+                "context_page_path": "/join",
+                "context_user_agent": "Mozilla/5.0",
+                "context_geo_country": "DE",
+                "context_experiments": "[\"a\",\"b\"]"
+            })
+        );
+    }
+}
@@ -0,0 +1,211 @@
+//! The Firehose data-transformation contract.
+//!
+//! Firehose hands over a batch of base64 records and expects every
+//! `recordId` back with a result:
+//!
+//! - `Ok`: the record's events, flattened (see [`flatten`]), one JSON
+//!   object per line. A KPL aggregate stays one record with a line per
+//!   event.
+//! - `Dropped`: every event is of a type in `FIREHOSE_DROP_EVENT_TYPES`
+//!   (heartbeats by default, which `engagement-rollup` rolls up instead)
+//! - `ProcessingFailed`: the record isn't JSON events; Firehose writes it
+//!   as received under the error output prefix
+//!
+//! With `FIREHOSE_PARTITION_KEYS_ENABLED`, `Ok` records also carry the
+//! `project_id`, `dt` and `hr` partition keys for dynamic partitioning, from
+//! the first event's `receivedAt` (the record's arrival time when it has
+//! none), as `parquet-writer` partitions.
+
+use aws_lambda_events::encodings::Base64Data;
+use aws_lambda_events::event::firehose::{
+    KinesisFirehoseEvent, KinesisFirehoseResponse, KinesisFirehoseResponseRecord,
+    KinesisFirehoseResponseRecordMetadata,
+};
+use chrono::DateTime;
+use ingestion::aggregation;
+use ingestion::models::IngestEventPayload;
+use ingestion::shared::{env_flag, env_list};
+use std::collections::HashMap;
+
+use crate::flatten::flatten;
+
+pub const OK: &str = "Ok";
+pub const DROPPED: &str = "Dropped";
+pub const PROCESSING_FAILED: &str = "ProcessingFailed";
+
+/// Configuration for the transformation
+#[derive(Debug, Clone)]
+pub struct TransformConfig {
+    /// Event types left out of the delivery stream
+    pub drop_event_types: Vec<String>,
+    /// Return partition keys for dynamic partitioning
+    pub partition_keys: bool,
+}
+
+impl Default for TransformConfig {
+    fn default() -> Self {
+        Self {
+            drop_event_types: vec!["heartbeat".to_string()],
+            partition_keys: false,
+        }
+    }
+}
+
+impl TransformConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let drop_event_types = env_list("FIREHOSE_DROP_EVENT_TYPES");
+        Self {
+            drop_event_types: if drop_event_types.is_empty() {
+                defaults.drop_event_types
+            } else {
+                drop_event_types
+            },
+            partition_keys: env_flag("FIREHOSE_PARTITION_KEYS_ENABLED"),
+        }
+    }
+}
+
+/// What became of one record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// Newline-delimited flattened events and their partition keys
+    Transformed(Vec<u8>, HashMap<String, String>),
+    Dropped,
+    Failed(String),
+}
+
+/// Transforms one record's data; `arrived_at` is its arrival time in epoch
+/// milliseconds
+pub fn transform(data: &[u8], arrived_at: i64, config: &TransformConfig) -> Outcome {
+    let mut events = Vec::new();
+    for (index, data) in aggregation::decode(data).into_iter().enumerate() {
+        match serde_json::from_slice::<IngestEventPayload>(&data) {
+            Ok(event) => events.push(event),
+            Err(e) => return Outcome::Failed(format!("user record {} isn't a JSON event: {}", index, e)),
+        }
+    }
+    events.retain(|event| !config.drop_event_types.contains(&event.event_type));
+    let Some(first) = events.first() else {
+        return Outcome::Dropped;
+    };
+
+    let mut partition_keys = HashMap::new();
+    if config.partition_keys {
+        let received_at = first.context.as_ref().and_then(|c| c.received_at).unwrap_or(arrived_at);
+        let time = DateTime::from_timestamp_millis(received_at).unwrap_or_default();
+        partition_keys.insert("project_id".to_string(), first.project_id.clone());
+        partition_keys.insert("dt".to_string(), time.format("%Y-%m-%d").to_string());
+        partition_keys.insert("hr".to_string(), time.format("%H").to_string());
+    }
+
+    let mut lines = Vec::new();
+    for event in &events {
+        match serde_json::to_vec(&flatten(event)) {
+            Ok(line) => {
+                lines.extend(line);
+                lines.push(b'\n');
+            }
+            Err(e) => return Outcome::Failed(format!("failed to serialize {}: {}", event.event_type, e)),
+        }
+    }
+    Outcome::Transformed(lines, partition_keys)
+}
+
+/// Transforms a batch, answering every record
+pub fn handle(event: KinesisFirehoseEvent, config: &TransformConfig) -> KinesisFirehoseResponse {
+    let (mut transformed, mut dropped, mut failed) = (0, 0, 0);
+    let records = event
+        .records
+        .into_iter()
+        .map(|record| {
+            let arrived_at = record.approximate_arrival_timestamp.0.timestamp_millis();
+            let (result, data, partition_keys) = match transform(&record.data.0, arrived_at, config) {
+                Outcome::Transformed(lines, partition_keys) => {
+                    transformed += 1;
+                    (OK, lines, partition_keys)
+                }
+                Outcome::Dropped => {
+                    dropped += 1;
+                    (DROPPED, Vec::new(), HashMap::new())
+                }
+                Outcome::Failed(reason) => {
+                    tracing::warn!("Failed to transform record {:?}: {}", record.record_id, reason);
+                    failed += 1;
+                    (PROCESSING_FAILED, record.data.0, HashMap::new())
+                }
+            };
+            KinesisFirehoseResponseRecord {
+                record_id: record.record_id,
+                result: Some(result.to_string()),
+                data: Base64Data(data),
+                metadata: KinesisFirehoseResponseRecordMetadata { partition_keys },
+            }
+        })
+        .collect();
+
+    tracing::info!("Transformed {} records, dropped {}, failed {}", transformed, dropped, failed);
+    KinesisFirehoseResponse { records }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn batch(records: &[&str]) -> KinesisFirehoseEvent {
+        let records: Vec<_> = records
+            .iter()
+            .enumerate()
+            .map(|(index, data)| {
+                let base64 = aws_lambda_events::encodings::Base64Data(data.as_bytes().to_vec());
+                json!({
+                    "recordId": format!("r{}", index),
+                    "approximateArrivalTimestamp": 1_700_000_000_000i64,
+                    "data": base64,
+                })
+            })
+            .collect();
+        serde_json::from_value(json!({ "invocationId": "inv", "records": records })).unwrap()
+    }
+
+    #[test]
+    fn test_every_record_is_answered() {
+        let signup = r#"{"projectId":"proj","eventType":"signup","timestamp":1,"context":{"page":{"path":"/join"}}}"#;
+        let heartbeat = r#"{"projectId":"proj","eventType":"heartbeat","timestamp":1}"#;
+        let response = handle(batch(&[signup, heartbeat, "not json"]), &TransformConfig::default());
+
+        let results: Vec<_> = response
+            .records
+            .iter()
+            .map(|record| (record.record_id.as_deref().unwrap(), record.result.as_deref().unwrap()))
+            .collect();
+        assert_eq!(results, [("r0", OK), ("r1", DROPPED), ("r2", PROCESSING_FAILED)]);
+
+        let line: serde_json::Value = serde_json::from_slice(&response.records[0].data.0).unwrap();
+        assert_eq!(line["context_page_path"], "/join");
+        assert!(response.records[0].data.0.ends_with(b"\n"));
+        // Failed records go to the error output as they came
+        assert_eq!(response.records[2].data.0, b"not json");
+    }
+
+    #[test]
+    fn test_partition_keys_follow_the_received_time() {
+        let config = TransformConfig {
+            partition_keys: true,
+            ..Default::default()
+        };
+        let event = r#"{"projectId":"proj","eventType":"signup","timestamp":1,"context":{"receivedAt":1700000000000}}"#;
+        let Outcome::Transformed(_, keys) = transform(event.as_bytes(), 0, &config) else {
+            panic!("expected the event to be transformed");
+        };
+        assert_eq!(keys["project_id"], "proj");
+        assert_eq!(keys["dt"], "2023-11-14");
+        assert_eq!(keys["hr"], "22");
+
+        let Outcome::Transformed(_, keys) = transform(event.as_bytes(), 0, &TransformConfig::default()) else {
+            panic!("expected the event to be transformed");
+        };
+        assert!(keys.is_empty());
+    }
+}
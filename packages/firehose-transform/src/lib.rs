@@ -0,0 +1,11 @@
+//! Firehose data transformation for the S3 data lake.
+//!
+//! Attached to the delivery stream that copies the event stream to S3, it
+//! turns each event into one flat JSON object whose `context` is spread
+//! into top-level `context_*` columns, so the Parquet files Firehose
+//! converts to have plain columns instead of one nested blob. See
+//! [`flatten`] for the column layout and [`handler`] for the contract with
+//! Firehose.
+
+pub mod flatten;
+pub mod handler;
@@ -0,0 +1,25 @@
+use lambda_runtime::{run, service_fn, Error, LambdaEvent};
+use std::sync::Arc;
+
+use aws_lambda_events::event::firehose::KinesisFirehoseEvent;
+use firehose_transform::handler::{handle, TransformConfig};
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    // Initialize tracing
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"))
+        )
+        .json()
+        .init();
+
+    let config = Arc::new(TransformConfig::from_env());
+
+    run(service_fn(move |event: LambdaEvent<KinesisFirehoseEvent>| {
+        let config = config.clone();
+        async move { Ok::<_, Error>(handle(event.payload, &config)) }
+    }))
+    .await
+}
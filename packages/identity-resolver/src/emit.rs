@@ -0,0 +1,44 @@
+//! Writing resolved events to the output stream.
+//!
+//! Events are JSON, like the ingest stream's, partitioned by project, and
+//! written with the ingest API's batched `PutRecords` and retry settings
+//! (`RETRY_*`). A retried batch writes its events again; they keep their
+//! `eventId`/`messageId`, so consumers can drop the repeats.
+
+use async_trait::async_trait;
+use aws_sdk_kinesis::Client as KinesisClient;
+use ingestion::models::IngestEventPayload;
+use ingestion::put_records;
+use ingestion::retry::RetryConfig;
+use lambda_runtime::Error;
+
+/// Where resolved events go; a trait so the handler can be tested without
+/// a stream
+#[async_trait]
+pub trait EventEmitter: Send + Sync {
+    async fn emit(&self, events: &[IngestEventPayload]) -> Result<(), Error>;
+}
+
+/// Writes resolved events to a Kinesis stream
+pub struct KinesisEmitter {
+    client: KinesisClient,
+    stream_name: String,
+    retry: RetryConfig,
+}
+
+impl KinesisEmitter {
+    pub fn new(client: KinesisClient, stream_name: String, retry: RetryConfig) -> Self {
+        Self {
+            client,
+            stream_name,
+            retry,
+        }
+    }
+}
+
+#[async_trait]
+impl EventEmitter for KinesisEmitter {
+    async fn emit(&self, events: &[IngestEventPayload]) -> Result<(), Error> {
+        put_records::put_events(&self.client, &self.stream_name, events, &self.retry).await
+    }
+}
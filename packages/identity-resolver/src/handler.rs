@@ -0,0 +1,159 @@
+//! The Kinesis batch handler.
+//!
+//! A batch is decoded (unpacking KPL aggregates) and walked in stream
+//! order: each event first teaches the graph its link, if any, then gets
+//! its `resolvedUserId`, so events after an `identify` in the same batch
+//! already resolve. If the graph or the output stream fails, the whole
+//! batch is reported failed and retried; links are idempotent, and the
+//! events written again keep their ids. Events of a visitor must reach one
+//! shard in order (`PARTITION_KEY_STRATEGY` `project` or `user`).
+
+use aws_lambda_events::event::kinesis::KinesisEvent;
+use aws_lambda_events::event::streams::{KinesisBatchItemFailure, KinesisEventResponse};
+use ingestion::aggregation;
+use ingestion::models::IngestEventPayload;
+use lambda_runtime::Error;
+
+use crate::emit::EventEmitter;
+use crate::identity::{IdentityConfig, Resolver};
+use crate::store::IdentityStore;
+
+async fn resolve_all(
+    events: &mut [IngestEventPayload],
+    store: &dyn IdentityStore,
+    config: &IdentityConfig,
+) -> Result<usize, Error> {
+    let mut resolver = Resolver::new(store, config);
+    let mut resolved = 0;
+    for event in events.iter_mut() {
+        resolver.learn(event).await?;
+        event.resolved_user_id = resolver.resolve(event).await?;
+        resolved += usize::from(event.resolved_user_id.is_some());
+    }
+    Ok(resolved)
+}
+
+/// Resolves and re-emits a batch, reporting it failed if that didn't work
+pub async fn handle(
+    event: KinesisEvent,
+    store: &dyn IdentityStore,
+    emitter: &dyn EventEmitter,
+    config: &IdentityConfig,
+) -> KinesisEventResponse {
+    let mut events = Vec::new();
+    for record in &event.records {
+        for data in aggregation::decode(&record.kinesis.data.0) {
+            match serde_json::from_slice::<IngestEventPayload>(&data) {
+                Ok(event) => events.push(event),
+                Err(e) => tracing::warn!("Skipping a record that isn't a JSON event: {}", e),
+            }
+        }
+    }
+
+    let result = match resolve_all(&mut events, store, config).await {
+        Ok(resolved) => emitter.emit(&events).await.map(|()| resolved),
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(resolved) => {
+            tracing::info!("Resolved {} of {} events to a user", resolved, events.len());
+            KinesisEventResponse {
+                batch_item_failures: Vec::new(),
+            }
+        }
+        Err(e) => {
+            tracing::error!("Failed to resolve the batch, retrying it: {}", e);
+            let first = event.records.first().and_then(|record| record.kinesis.sequence_number.clone());
+            KinesisEventResponse {
+                batch_item_failures: vec![KinesisBatchItemFailure { item_identifier: first }],
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity::tests::FakeStore;
+    use async_trait::async_trait;
+    use serde_json::json;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct FakeEmitter {
+        fail: bool,
+        events: Mutex<Vec<IngestEventPayload>>,
+    }
+
+    #[async_trait]
+    impl EventEmitter for FakeEmitter {
+        async fn emit(&self, events: &[IngestEventPayload]) -> Result<(), Error> {
+            if self.fail {
+                return Err("ProvisionedThroughputExceededException".into());
+            }
+            self.events.lock().unwrap().extend_from_slice(events);
+            Ok(())
+        }
+    }
+
+    fn batch(events: &[serde_json::Value]) -> KinesisEvent {
+        let records: Vec<_> = events
+            .iter()
+            .enumerate()
+            .map(|(index, event)| {
+                let mut full = json!({"projectId": "p", "eventType": "track", "timestamp": 1});
+                full.as_object_mut().unwrap().extend(event.as_object().unwrap().clone());
+                let data = aws_lambda_events::encodings::Base64Data(serde_json::to_vec(&full).unwrap());
+                json!({
+                    "kinesis": {
+                        "sequenceNumber": index.to_string(),
+                        "data": data,
+                        "approximateArrivalTimestamp": 1_700_000_000.0,
+                    },
+                })
+            })
+            .collect();
+        serde_json::from_value(json!({ "Records": records })).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_stamps_resolved_user_ids() {
+        let (store, emitter) = (FakeStore::default(), FakeEmitter::default());
+        let event = batch(&[
+            json!({"anonymousId": "a1"}),
+            json!({"eventType": "identify", "anonymousId": "a1", "userId": "u1"}),
+            json!({"anonymousId": "a1"}),
+        ]);
+
+        let response = handle(event, &store, &emitter, &IdentityConfig::default()).await;
+
+        assert!(response.batch_item_failures.is_empty());
+        let resolved: Vec<_> = emitter
+            .events
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|e| e.resolved_user_id.clone())
+            .collect();
+        assert_eq!(resolved, [None, Some("u1".to_string()), Some("u1".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn test_emit_failure_retries_the_batch() {
+        let store = FakeStore::default();
+        let emitter = FakeEmitter {
+            fail: true,
+            ..Default::default()
+        };
+        let event = batch(&[json!({"userId": "u1"})]);
+
+        let response = handle(event, &store, &emitter, &IdentityConfig::default()).await;
+
+        assert_eq!(
+            response.batch_item_failures,
+            [KinesisBatchItemFailure {
+                item_identifier: Some("0".to_string()),
+            }]
+        );
+    }
+}
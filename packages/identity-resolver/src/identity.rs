@@ -0,0 +1,245 @@
+//! The identity graph and resolving ids through it.
+//!
+//! The graph is a forest per project: each id points at a parent and the
+//! root of its tree is the canonical user id. Links come from two events:
+//!
+//! - `identify` with both ids links the `anonymousId` to the user, unless
+//!   it already belongs to someone (a shared device keeps its first user)
+//! - `alias` merges `previousId`'s tree into `userId`'s, so two user ids
+//!   found to be one person end up with a single canonical id
+//!
+//! Only roots are re-pointed, so the graph can't grow a cycle. Lookups
+//! follow at most `IDENTITY_MAX_HOPS` parents and point a long chain's
+//! start straight at its root for next time.
+
+use ingestion::models::IngestEventPayload;
+use ingestion::shared::{env_or, env_var};
+use lambda_runtime::Error;
+use std::collections::HashMap;
+
+use crate::store::IdentityStore;
+
+/// Configuration for identity resolution
+#[derive(Debug, Clone)]
+pub struct IdentityConfig {
+    /// DynamoDB table of the graph
+    pub table_name: String,
+    /// Stream resolved events are written to
+    pub stream_name: String,
+    /// Most parents a lookup follows
+    pub max_hops: usize,
+}
+
+impl Default for IdentityConfig {
+    fn default() -> Self {
+        Self {
+            table_name: String::new(),
+            stream_name: String::new(),
+            max_hops: 8,
+        }
+    }
+}
+
+impl IdentityConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            table_name: env_var("IDENTITY_TABLE").unwrap_or_default(),
+            stream_name: env_var("IDENTITY_STREAM").unwrap_or_default(),
+            max_hops: env_or("IDENTITY_MAX_HOPS", defaults.max_hops).max(1),
+        }
+    }
+}
+
+/// A link an event asks for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Link<'a> {
+    Identify { anonymous_id: &'a str, user_id: &'a str },
+    Alias { previous_id: &'a str, user_id: &'a str },
+}
+
+fn non_empty(id: &Option<String>) -> Option<&str> {
+    id.as_deref().filter(|id| !id.is_empty())
+}
+
+/// The link an `identify` or `alias` event asks for
+pub fn link(event: &IngestEventPayload) -> Option<Link<'_>> {
+    let user_id = non_empty(&event.user_id)?;
+    let link = match event.event_type.as_str() {
+        "identify" => Link::Identify {
+            anonymous_id: non_empty(&event.anonymous_id)?,
+            user_id,
+        },
+        "alias" => Link::Alias {
+            previous_id: non_empty(&event.previous_id)?,
+            user_id,
+        },
+        _ => return None,
+    };
+    Some(link)
+}
+
+/// Resolves ids through the graph, caching what a batch has read
+pub struct Resolver<'a> {
+    store: &'a dyn IdentityStore,
+    max_hops: usize,
+    parents: HashMap<(String, String), Option<String>>,
+}
+
+impl<'a> Resolver<'a> {
+    pub fn new(store: &'a dyn IdentityStore, config: &IdentityConfig) -> Self {
+        Self {
+            store,
+            max_hops: config.max_hops,
+            parents: HashMap::new(),
+        }
+    }
+
+    async fn parent(&mut self, project_id: &str, id: &str) -> Result<Option<String>, Error> {
+        let key = (project_id.to_string(), id.to_string());
+        if let Some(parent) = self.parents.get(&key) {
+            return Ok(parent.clone());
+        }
+        let parent = self.store.parent(project_id, id).await?;
+        self.parents.insert(key, parent.clone());
+        Ok(parent)
+    }
+
+    async fn set_parent(&mut self, project_id: &str, id: &str, parent: &str) -> Result<(), Error> {
+        self.store.set_parent(project_id, id, parent).await?;
+        self.parents
+            .insert((project_id.to_string(), id.to_string()), Some(parent.to_string()));
+        Ok(())
+    }
+
+    /// The root of an id's tree; the id itself when it has no parent
+    pub async fn root(&mut self, project_id: &str, id: &str) -> Result<String, Error> {
+        let mut root = id.to_string();
+        let mut hops = 0;
+        while hops < self.max_hops {
+            match self.parent(project_id, &root).await? {
+                Some(parent) if parent != root => root = parent,
+                _ => break,
+            }
+            hops += 1;
+        }
+        if hops == self.max_hops {
+            tracing::warn!("Stopped resolving {} after {} hops", id, hops);
+        }
+        if hops > 1 {
+            self.set_parent(project_id, id, &root).await?;
+        }
+        Ok(root)
+    }
+
+    /// Records the link an event asks for, if any
+    pub async fn learn(&mut self, event: &IngestEventPayload) -> Result<(), Error> {
+        let project_id = event.project_id.as_str();
+        match link(event) {
+            Some(Link::Identify { anonymous_id, user_id }) => {
+                if self.root(project_id, anonymous_id).await? != anonymous_id {
+                    return Ok(());
+                }
+                let user = self.root(project_id, user_id).await?;
+                if user != anonymous_id {
+                    self.set_parent(project_id, anonymous_id, &user).await?;
+                }
+            }
+            Some(Link::Alias { previous_id, user_id }) => {
+                let previous = self.root(project_id, previous_id).await?;
+                let user = self.root(project_id, user_id).await?;
+                if previous != user {
+                    self.set_parent(project_id, &previous, &user).await?;
+                }
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// The canonical user of an event: the root of its user id, else of its
+    /// anonymous id once that's linked to a user
+    pub async fn resolve(&mut self, event: &IngestEventPayload) -> Result<Option<String>, Error> {
+        if let Some(user_id) = non_empty(&event.user_id) {
+            return Ok(Some(self.root(&event.project_id, user_id).await?));
+        }
+        let Some(anonymous_id) = non_empty(&event.anonymous_id) else {
+            return Ok(None);
+        };
+        let root = self.root(&event.project_id, anonymous_id).await?;
+        Ok((root != anonymous_id).then_some(root))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use serde_json::json;
+    use std::sync::Mutex;
+
+    /// The graph in memory
+    #[derive(Default)]
+    pub(crate) struct FakeStore {
+        pub parents: Mutex<HashMap<String, String>>,
+    }
+
+    #[async_trait]
+    impl IdentityStore for FakeStore {
+        async fn parent(&self, project_id: &str, id: &str) -> Result<Option<String>, Error> {
+            Ok(self.parents.lock().unwrap().get(&format!("{}#{}", project_id, id)).cloned())
+        }
+
+        async fn set_parent(&self, project_id: &str, id: &str, parent: &str) -> Result<(), Error> {
+            let key = format!("{}#{}", project_id, id);
+            self.parents.lock().unwrap().insert(key, parent.to_string());
+            Ok(())
+        }
+    }
+
+    fn event(value: serde_json::Value) -> IngestEventPayload {
+        let mut event = json!({"projectId": "p", "eventType": "track", "timestamp": 1});
+        event.as_object_mut().unwrap().extend(value.as_object().unwrap().clone());
+        serde_json::from_value(event).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_identify_and_alias_merge_identities() {
+        let store = FakeStore::default();
+        let config = IdentityConfig::default();
+        let mut resolver = Resolver::new(&store, &config);
+
+        resolver
+            .learn(&event(json!({"eventType": "identify", "anonymousId": "a1", "userId": "u1"})))
+            .await
+            .unwrap();
+        // A shared device keeps its first user
+        resolver
+            .learn(&event(json!({"eventType": "identify", "anonymousId": "a1", "userId": "u9"})))
+            .await
+            .unwrap();
+        resolver
+            .learn(&event(json!({"eventType": "alias", "previousId": "u1", "userId": "u2"})))
+            .await
+            .unwrap();
+
+        let anonymous = event(json!({"anonymousId": "a1"}));
+        assert_eq!(resolver.resolve(&anonymous).await.unwrap().as_deref(), Some("u2"));
+        let old_user = event(json!({"userId": "u1"}));
+        assert_eq!(resolver.resolve(&old_user).await.unwrap().as_deref(), Some("u2"));
+        let stranger = event(json!({"anonymousId": "a2"}));
+        assert_eq!(resolver.resolve(&stranger).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_long_chains_are_shortened() {
+        let store = FakeStore::default();
+        for (id, parent) in [("a", "b"), ("b", "c"), ("c", "d")] {
+            store.set_parent("p", id, parent).await.unwrap();
+        }
+        let config = IdentityConfig::default();
+
+        assert_eq!(Resolver::new(&store, &config).root("p", "a").await.unwrap(), "d");
+        assert_eq!(store.parents.lock().unwrap()["p#a"], "d");
+    }
+}
@@ -0,0 +1,12 @@
+//! Kinesis identity resolution.
+//!
+//! Consumes the ingest stream in Lambda batches, keeps an identity graph
+//! in DynamoDB from `identify` and `alias` events (see [`identity`] and
+//! [`store`]), and writes every event to an output stream with
+//! `resolvedUserId` set to the canonical user it belongs to (see [`emit`]),
+//! so stores downstream count a person once across devices and logins.
+
+pub mod emit;
+pub mod handler;
+pub mod identity;
+pub mod store;
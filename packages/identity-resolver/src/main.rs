@@ -0,0 +1,44 @@
+use lambda_runtime::{run, service_fn, Error, LambdaEvent};
+use std::sync::Arc;
+
+use aws_lambda_events::event::kinesis::KinesisEvent;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use aws_sdk_kinesis::Client as KinesisClient;
+use identity_resolver::emit::KinesisEmitter;
+use identity_resolver::handler::handle;
+use identity_resolver::identity::IdentityConfig;
+use identity_resolver::store::DynamoIdentityStore;
+use ingestion::retry::RetryConfig;
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    // Initialize tracing
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"))
+        )
+        .json()
+        .init();
+
+    let config = Arc::new(IdentityConfig::from_env());
+    if config.table_name.is_empty() {
+        return Err("IDENTITY_TABLE environment variable not set".into());
+    }
+    if config.stream_name.is_empty() {
+        return Err("IDENTITY_STREAM environment variable not set".into());
+    }
+    let aws = aws_config::load_from_env().await;
+    let store = Arc::new(DynamoIdentityStore::new(DynamoClient::new(&aws), config.table_name.clone()));
+    let emitter = Arc::new(KinesisEmitter::new(
+        KinesisClient::new(&aws),
+        config.stream_name.clone(),
+        RetryConfig::from_env(),
+    ));
+
+    run(service_fn(move |event: LambdaEvent<KinesisEvent>| {
+        let (store, emitter, config) = (store.clone(), emitter.clone(), config.clone());
+        async move { Ok::<_, Error>(handle(event.payload, store.as_ref(), emitter.as_ref(), &config).await) }
+    }))
+    .await
+}
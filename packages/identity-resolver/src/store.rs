@@ -0,0 +1,63 @@
+//! The identity graph in DynamoDB.
+//!
+//! One item per id that has a parent, keyed by `pk` (`{project}#{id}`),
+//! with the parent id in `parent`. Roots have no item.
+
+use async_trait::async_trait;
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use lambda_runtime::Error;
+
+/// Where the graph's parent links are kept
+#[async_trait]
+pub trait IdentityStore: Send + Sync {
+    /// The id's parent, if it has one
+    async fn parent(&self, project_id: &str, id: &str) -> Result<Option<String>, Error>;
+    async fn set_parent(&self, project_id: &str, id: &str, parent: &str) -> Result<(), Error>;
+}
+
+/// Parent links in a DynamoDB table keyed by `pk`
+pub struct DynamoIdentityStore {
+    client: DynamoClient,
+    table_name: String,
+}
+
+impl DynamoIdentityStore {
+    pub fn new(client: DynamoClient, table_name: String) -> Self {
+        Self { client, table_name }
+    }
+}
+
+fn pk(project_id: &str, id: &str) -> AttributeValue {
+    AttributeValue::S(format!("{}#{}", project_id, id))
+}
+
+#[async_trait]
+impl IdentityStore for DynamoIdentityStore {
+    async fn parent(&self, project_id: &str, id: &str) -> Result<Option<String>, Error> {
+        let output = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("pk", pk(project_id, id))
+            .consistent_read(true)
+            .send()
+            .await?;
+        Ok(output
+            .item()
+            .and_then(|item| item.get("parent"))
+            .and_then(|parent| parent.as_s().ok())
+            .cloned())
+    }
+
+    async fn set_parent(&self, project_id: &str, id: &str, parent: &str) -> Result<(), Error> {
+        self.client
+            .put_item()
+            .table_name(&self.table_name)
+            .item("pk", pk(project_id, id))
+            .item("parent", AttributeValue::S(parent.to_string()))
+            .send()
+            .await?;
+        Ok(())
+    }
+}
@@ -3,11 +3,13 @@ import * as lambda from 'aws-cdk-lib/aws-lambda';
 import * as lambdaNodejs from 'aws-cdk-lib/aws-lambda-nodejs';
 import * as apigateway from 'aws-cdk-lib/aws-apigateway';
 import * as s3 from 'aws-cdk-lib/aws-s3';
+import * as sqs from 'aws-cdk-lib/aws-sqs';
 import * as kinesis from 'aws-cdk-lib/aws-kinesis';
 import * as firehose from 'aws-cdk-lib/aws-kinesisfirehose';
 import * as iam from 'aws-cdk-lib/aws-iam';
 import * as lambdaEventSources from 'aws-cdk-lib/aws-lambda-event-sources';
 import * as dsql from 'aws-cdk-lib/aws-dsql';
+import * as dynamodb from 'aws-cdk-lib/aws-dynamodb';
 import { Construct } from 'constructs';
 import * as path from 'path';
 
@@ -19,6 +21,7 @@ export class IngestApi extends Construct {
   public readonly api: apigateway.RestApi;
   public readonly ingestLambda: lambda.Function;
   public readonly eventStream: kinesis.Stream;
+  public readonly deadLetterQueue: sqs.Queue;
   public readonly rawEventsBucket: s3.Bucket;
   public readonly firehoseToS3: firehose.DeliveryStream;
   public readonly processorLambda: lambda.Function;
@@ -108,6 +111,12 @@ export class IngestApi extends Construct {
       })
     );
 
+    // Events Kinesis wouldn't take after retries, kept for replay
+    this.deadLetterQueue = new sqs.Queue(this, 'IngestDeadLetterQueue', {
+      encryption: sqs.QueueEncryption.SQS_MANAGED,
+      retentionPeriod: cdk.Duration.days(14),
+    });
+
     this.ingestLambda = new lambda.Function(this, 'IngestFunction', {
       runtime: lambda.Runtime.PROVIDED_AL2023,
       architecture: lambda.Architecture.ARM_64,
@@ -117,6 +126,7 @@ export class IngestApi extends Construct {
       ),
       environment: {
         STREAM_NAME: this.eventStream.streamName,
+        DLQ_QUEUE_URL: this.deadLetterQueue.queueUrl,
         RUST_BACKTRACE: '1',
         RUST_LOG: 'info',
       },
@@ -125,7 +135,87 @@ export class IngestApi extends Construct {
       description: 'Ingests analytics events and writes to Kinesis Stream',
     });
 
+    // Optional GeoIP database layer (mmdb under /opt/geoip), e.g. `-c geoipLayerArn=...`
+    const geoipLayerArn = this.node.tryGetContext('geoipLayerArn');
+    if (geoipLayerArn) {
+      this.ingestLambda.addLayers(
+        lambda.LayerVersion.fromLayerVersionArn(this, 'GeoIpLayer', geoipLayerArn)
+      );
+      this.ingestLambda.addEnvironment('GEOIP_ENABLED', 'true');
+    }
+
+    // Optional separate stream for crawler traffic, e.g. `-c botStream=true`
+    if (this.node.tryGetContext('botStream')) {
+      const botStream = new kinesis.Stream(this, 'BotEventStream', {
+        streamMode: kinesis.StreamMode.ON_DEMAND,
+        removalPolicy: cdk.RemovalPolicy.DESTROY,
+        encryption: kinesis.StreamEncryption.MANAGED,
+      });
+      botStream.grantWrite(this.ingestLambda);
+      this.ingestLambda.addEnvironment('BOT_FILTER_ENABLED', 'true');
+      this.ingestLambda.addEnvironment('BOT_FILTER_ACTION', 'route');
+      this.ingestLambda.addEnvironment('BOT_STREAM_NAME', botStream.streamName);
+    }
+
+    // Optional per-event status for GET /status/{eventId}, e.g. `-c statusTracking=true`
+    if (this.node.tryGetContext('statusTracking')) {
+      const statusTable = new dynamodb.Table(this, 'EventStatusTable', {
+        partitionKey: { name: 'pk', type: dynamodb.AttributeType.STRING },
+        billingMode: dynamodb.BillingMode.PAY_PER_REQUEST,
+        removalPolicy: cdk.RemovalPolicy.DESTROY,
+      });
+      statusTable.grantReadWriteData(this.ingestLambda);
+      this.ingestLambda.addEnvironment('STATUS_TRACKING_ENABLED', 'true');
+      this.ingestLambda.addEnvironment('EVENT_STATUS_TABLE', statusTable.tableName);
+    }
+
+    // User deletions: DELETE /users/{userId} queues a request, which the
+    // deletion worker carries out against every store and audits. The
+    // Parquet lake and ClickHouse are erased too when the worker is given
+    // PARQUET_BUCKET and CLICKHOUSE_URL.
+    const deletionDeadLetterQueue = new sqs.Queue(this, 'DeletionDeadLetterQueue', {
+      encryption: sqs.QueueEncryption.SQS_MANAGED,
+      retentionPeriod: cdk.Duration.days(14),
+    });
+    const deletionQueue = new sqs.Queue(this, 'DeletionQueue', {
+      encryption: sqs.QueueEncryption.SQS_MANAGED,
+      visibilityTimeout: cdk.Duration.minutes(6),
+      deadLetterQueue: { queue: deletionDeadLetterQueue, maxReceiveCount: 5 },
+    });
+    const deletionAuditTable = new dynamodb.Table(this, 'DeletionAuditTable', {
+      partitionKey: { name: 'deletion_id', type: dynamodb.AttributeType.STRING },
+      billingMode: dynamodb.BillingMode.PAY_PER_REQUEST,
+      pointInTimeRecovery: true,
+      removalPolicy: cdk.RemovalPolicy.RETAIN, // Proof of deletions outlives the stack
+    });
+    const deletionWorker = new lambda.Function(this, 'DeletionWorkerFunction', {
+      runtime: lambda.Runtime.PROVIDED_AL2023,
+      architecture: lambda.Architecture.ARM_64,
+      handler: 'bootstrap',
+      code: lambda.Code.fromAsset(
+        path.join(__dirname, '../../deletion-worker/target/lambda/deletion-worker')
+      ),
+      environment: {
+        DELETION_AUDIT_TABLE: deletionAuditTable.tableName,
+        RUST_BACKTRACE: '1',
+        RUST_LOG: 'info',
+      },
+      timeout: cdk.Duration.minutes(5),
+      memorySize: 512,
+      description: 'Deletes a user\'s events from every store and records an audit entry',
+    });
+    deletionWorker.addEventSource(
+      new lambdaEventSources.SqsEventSource(deletionQueue, {
+        batchSize: 1,
+        reportBatchItemFailures: true,
+      })
+    );
+    deletionAuditTable.grantWriteData(deletionWorker);
+    deletionQueue.grantSendMessages(this.ingestLambda);
+    this.ingestLambda.addEnvironment('DELETION_QUEUE_URL', deletionQueue.queueUrl);
+
     this.eventStream.grantReadWrite(this.ingestLambda);
+    this.deadLetterQueue.grantSendMessages(this.ingestLambda);
 
     this.api = new apigateway.RestApi(this, 'Api', {
       restApiName: 'Product Analytics Ingest API',
@@ -140,13 +230,15 @@ export class IngestApi extends Construct {
       },
       defaultCorsPreflightOptions: {
         allowOrigins: apigateway.Cors.ALL_ORIGINS,
-        allowMethods: ['POST', 'OPTIONS'],
+        allowMethods: ['GET', 'POST', 'OPTIONS'],
         allowHeaders: [
           'Content-Type',
           'X-Amz-Date',
           'Authorization',
           'X-Api-Key',
           'X-Amz-Security-Token',
+          'X-SDK-Name',
+          'X-SDK-Version',
         ],
         maxAge: cdk.Duration.days(1),
       },
@@ -168,6 +260,102 @@ export class IngestApi extends Construct {
     const event = this.api.root.addResource('event');
     event.addMethod('POST', ingestIntegration);
 
+    // POST /identify - Attach traits to a user
+    const identify = this.api.root.addResource('identify');
+    identify.addMethod('POST', ingestIntegration);
+
+    // POST /group - Associate a user with an account
+    const group = this.api.root.addResource('group');
+    group.addMethod('POST', ingestIntegration);
+
+    // POST /alias - Link a previous id to a user
+    const alias = this.api.root.addResource('alias');
+    alias.addMethod('POST', ingestIntegration);
+
+    // POST /vitals - Web Vitals measurements (LCP, CLS, INP, TTFB)
+    const vitals = this.api.root.addResource('vitals');
+    vitals.addMethod('POST', ingestIntegration);
+
+    // POST /errors - Frontend errors, with stack and breadcrumbs
+    const errors = this.api.root.addResource('errors');
+    errors.addMethod('POST', ingestIntegration);
+
+    // POST /heartbeat - Engagement pings, rolled up by packages/engagement-rollup
+    const heartbeat = this.api.root.addResource('heartbeat');
+    heartbeat.addMethod('POST', ingestIntegration);
+
+    // POST /click - Auto-captured clicks, deduplicated and throttled per session
+    const click = this.api.root.addResource('click');
+    click.addMethod('POST', ingestIntegration);
+
+    // POST /scroll - Auto-captured scroll depth, kept only when deeper than the page's deepest so far
+    const scroll = this.api.root.addResource('scroll');
+    scroll.addMethod('POST', ingestIntegration);
+
+    // POST /exposure - Experiment exposures, deduplicated per session
+    const exposure = this.api.root.addResource('exposure');
+    exposure.addMethod('POST', ingestIntegration);
+
+    // POST /screen - Mobile app screen views, with app context
+    const screen = this.api.root.addResource('screen');
+    screen.addMethod('POST', ingestIntegration);
+
+    // POST /cloudevents - CloudEvents envelopes (enabled via CLOUDEVENTS_ENABLED)
+    const cloudEvents = this.api.root.addResource('cloudevents');
+    cloudEvents.addMethod('POST', ingestIntegration);
+
+    // POST /batch - Arrays of events, or {"events": [...]} envelopes (BATCH_ENVELOPE_ENABLED)
+    const batch = this.api.root.addResource('batch');
+    batch.addMethod('POST', ingestIntegration);
+
+    // POST /v1/* and /v2/* - The endpoints above, versioned: unversioned
+    // paths are v1, and v2 requires timestamps and always answers JSON
+    const endpoints = [
+      'view', 'event', 'identify', 'group', 'alias', 'vitals', 'errors',
+      'heartbeat', 'click', 'scroll', 'exposure', 'screen', 'cloudevents', 'batch',
+    ];
+    const v1 = this.api.root.addResource('v1');
+    const v2 = this.api.root.addResource('v2');
+    for (const version of [v1, v2]) {
+      for (const endpoint of endpoints) {
+        version.addResource(endpoint).addMethod('POST', ingestIntegration);
+      }
+    }
+
+    // POST /v1/{track,page,identify,batch} - Segment-compatible API (SEGMENT_COMPAT_ENABLED)
+    for (const call of ['track', 'page']) {
+      v1.addResource(call).addMethod('POST', ingestIntegration);
+    }
+
+    // GET /pixel.gif - Tracking pixel for email opens and no-JS pages (PIXEL_TRACKING_ENABLED)
+    const pixel = this.api.root.addResource('pixel.gif');
+    pixel.addMethod('GET', ingestIntegration);
+
+    // GET /status/{eventId} - Processing status (`-c statusTracking=true`)
+    const status = this.api.root.addResource('status').addResource('{eventId}');
+    status.addMethod('GET', ingestIntegration);
+
+    // GET /livez and /readyz - Liveness and readiness probes (HEALTH_PROBES_ENABLED)
+    for (const probe of ['livez', 'readyz']) {
+      this.api.root.addResource(probe).addMethod('GET', ingestIntegration);
+    }
+
+    // GET /health - Deep check of the stream and every configured sink, for canaries (HEALTH_PROBES_ENABLED)
+    const health = this.api.root.addResource('health');
+    health.addMethod('GET', ingestIntegration);
+
+    // GET /openapi.json - OpenAPI description of the ingest API
+    const openapi = this.api.root.addResource('openapi.json');
+    openapi.addMethod('GET', ingestIntegration);
+
+    // POST /admin/refresh-config - Reload the serving sandbox's cached config now (ADMIN_TOKEN)
+    const refreshConfig = this.api.root.addResource('admin').addResource('refresh-config');
+    refreshConfig.addMethod('POST', ingestIntegration);
+
+    // DELETE /users/{userId}?projectId=... - Queue a user's deletion (ADMIN_TOKEN)
+    const user = this.api.root.addResource('users').addResource('{userId}');
+    user.addMethod('DELETE', ingestIntegration);
+
     // CloudFormation Outputs
     new cdk.CfnOutput(this, 'IngestApiEndpoint', {
       value: this.api.url,
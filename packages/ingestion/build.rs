@@ -0,0 +1,32 @@
+use std::process::Command;
+
+fn main() -> std::io::Result<()> {
+    // Reported by `GET /health`; CI can pass GIT_SHA when building outside
+    // a checkout
+    let git = |args: &[&str]| {
+        Command::new("git")
+            .args(args)
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .and_then(|output| String::from_utf8(output.stdout).ok())
+            .map(|out| out.trim().to_string())
+    };
+    let git_sha = std::env::var("GIT_SHA")
+        .ok()
+        .or_else(|| git(&["rev-parse", "--short=12", "HEAD"]))
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=GIT_SHA={}", git_sha);
+    println!("cargo:rerun-if-env-changed=GIT_SHA");
+    if let Some(git_dir) = git(&["rev-parse", "--absolute-git-dir"]) {
+        println!("cargo:rerun-if-changed={}/HEAD", git_dir);
+        println!("cargo:rerun-if-changed={}/logs/HEAD", git_dir);
+    }
+
+    // Vendored, so builds don't need protoc installed
+    let protoc = protoc_bin_vendored::protoc_bin_path().map_err(std::io::Error::other)?;
+    println!("cargo:rerun-if-changed=proto");
+    prost_build::Config::new()
+        .protoc_executable(protoc)
+        .compile_protos(&["proto/events.proto", "proto/kpl_aggregation.proto"], &["proto"])
+}
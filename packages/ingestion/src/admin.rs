@@ -0,0 +1,274 @@
+//! Config caching and the admin refresh route.
+//!
+//! [`Config`] is loaded at cold start and cached in a [`ConfigCache`]. With
+//! `CONFIG_MAX_AGE_SECS` set, a request arriving after the cache expired
+//! reloads it first; otherwise it lives for the whole sandbox.
+//! `POST /admin/refresh-config` with a matching `X-Admin-Token` reloads it
+//! immediately and reports which fields changed.
+//!
+//! Each reload first fetches any SSM or AppConfig values (see
+//! [`config_source`](crate::config_source)); everything else comes from the
+//! sandbox's own environment. Only `Config` is reloaded: stores, sinks and
+//! clients stay as built at cold start.
+//!
+//! The refresh route is not a fleet-wide reload. It reaches whichever
+//! sandbox API Gateway picks, and only that sandbox's cache is reloaded. To
+//! roll a change out (flipping a kill-switch during an incident), change the
+//! SSM parameter or AppConfig profile and let every sandbox pick it up within
+//! `CONFIG_MAX_AGE_SECS`; the route only shortens the wait for one of them.
+
+use lambda_http::{Body, Error, Request, Response};
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::config_source::RemoteConfig;
+use crate::shared::{create_error_response, create_response, env_opt, env_var, AppState, Config};
+
+/// Configuration for the admin route and config caching
+#[derive(Debug, Clone, Default)]
+pub struct AdminConfig {
+    /// Token required by admin routes; they are disabled when unset
+    pub token: Option<String>,
+    /// Reload the cached config once it is older than this
+    pub config_max_age: Option<Duration>,
+}
+
+impl AdminConfig {
+    pub fn from_env() -> Self {
+        Self {
+            token: env_var("ADMIN_TOKEN").filter(|t| !t.is_empty()),
+            config_max_age: env_opt("CONFIG_MAX_AGE_SECS").map(Duration::from_secs),
+        }
+    }
+}
+
+/// The current config and a way to reload it
+pub struct ConfigCache {
+    current: RwLock<(Arc<Config>, Instant)>,
+    load: Box<dyn Fn() -> Config + Send + Sync>,
+    remote: Option<RemoteConfig>,
+}
+
+impl ConfigCache {
+    pub fn new(config: Arc<Config>, load: impl Fn() -> Config + Send + Sync + 'static) -> Self {
+        Self {
+            current: RwLock::new((config, Instant::now())),
+            load: Box::new(load),
+            remote: None,
+        }
+    }
+
+    /// Fetches remote config values before each reload
+    pub fn with_remote(mut self, remote: Option<RemoteConfig>) -> Self {
+        self.remote = remote;
+        self
+    }
+
+    /// Whether the cached config outlived its max age
+    fn is_expired(&self) -> bool {
+        let current = self.current.read().unwrap();
+        current.0.admin.config_max_age.is_some_and(|max_age| current.1.elapsed() >= max_age)
+    }
+
+    /// Cached config, reloaded first if it outlived its max age
+    pub async fn current(&self) -> Arc<Config> {
+        if self.is_expired() {
+            return self.refresh().await.0;
+        }
+        self.current.read().unwrap().0.clone()
+    }
+
+    /// Reloads now, returning the new config and the names of changed
+    /// fields. A reload that fails validation keeps the current config.
+    pub async fn refresh(&self) -> (Arc<Config>, Vec<String>) {
+        if let Some(ref remote) = self.remote {
+            remote.apply().await;
+        }
+        let fresh = (self.load)();
+        let mut current = self.current.write().unwrap();
+        if let Err(e) = fresh.validate() {
+            tracing::error!("Keeping the current config, the reloaded one is invalid: {}", e);
+            current.1 = Instant::now();
+            return (current.0.clone(), Vec::new());
+        }
+        let fresh = Arc::new(fresh);
+        let changed = changed_fields(&current.0, &fresh);
+        *current = (fresh.clone(), Instant::now());
+        (fresh, changed)
+    }
+}
+
+/// Top-level `Config` fields whose values differ. Compares `Debug` output so
+/// new fields are covered without listing them here; lines are compared
+/// unordered because map fields have no stable order.
+fn changed_fields(old: &Config, new: &Config) -> Vec<String> {
+    let (old, new) = (format!("{:#?}", old), format!("{:#?}", new));
+    let (old, new) = (debug_fields(&old), debug_fields(&new));
+    new.iter()
+        .filter(|(name, lines)| old.get(*name) != Some(lines))
+        .map(|(name, _)| name.to_string())
+        .collect()
+}
+
+/// Splits pretty `Debug` output of a struct into sorted lines per field
+fn debug_fields(debug: &str) -> BTreeMap<&str, Vec<&str>> {
+    let mut fields: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    let mut current = None;
+    for line in debug.lines() {
+        let top_level = line.strip_prefix("    ").filter(|l| !l.starts_with(' '));
+        if let Some((name, _)) = top_level.and_then(|l| l.split_once(':')) {
+            current = Some(name);
+        }
+        if let Some(name) = current {
+            fields.entry(name).or_default().push(line);
+        }
+    }
+    for lines in fields.values_mut() {
+        lines.sort_unstable();
+    }
+    fields
+}
+
+/// Compares without short-circuiting on the first differing byte
+pub fn token_matches(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// The rejection for an admin call without the admin token: 404 when admin
+/// routes are disabled, 401 for a wrong token
+pub fn reject_unauthorized(request: &Request, state: &AppState) -> Option<Response<Body>> {
+    let Some(ref expected) = state.config.admin.token else {
+        return Some(create_error_response(404, "Not found"));
+    };
+
+    let given = request
+        .headers()
+        .get("x-admin-token")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    if !token_matches(given, expected) {
+        tracing::warn!("Rejecting admin call to {} with a bad admin token", request.uri().path());
+        return Some(create_error_response(401, "Unauthorized"));
+    }
+    None
+}
+
+/// Handler for POST /admin/refresh-config. Reloads the config of this
+/// sandbox only; others reload when their cache expires.
+pub async fn handle_refresh(request: &Request, state: &AppState) -> Result<Response<Body>, Error> {
+    if let Some(rejection) = reject_unauthorized(request, state) {
+        return Ok(rejection);
+    }
+
+    let (_, changed) = state.config_cache.refresh().await;
+    tracing::info!("Config refreshed, changed: {:?}", changed);
+    Ok(create_response(200, serde_json::json!({ "changed": changed })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::function_handler;
+    use crate::shared::test_state;
+
+    fn config(cloudevents_enabled: bool) -> Config {
+        Config {
+            cloudevents_enabled,
+            admin: AdminConfig {
+                token: Some("s3cret".to_string()),
+                config_max_age: None,
+            },
+            ..Default::default()
+        }
+    }
+
+    fn refresh(token: Option<&str>) -> Request {
+        let mut builder = lambda_http::http::Request::builder()
+            .method("POST")
+            .uri("/prod/admin/refresh-config");
+        if let Some(token) = token {
+            builder = builder.header("X-Admin-Token", token);
+        }
+        builder.body(Body::Empty).unwrap()
+    }
+
+    fn state() -> Arc<AppState> {
+        let mut state = test_state(config(false));
+        state.config_cache = Arc::new(ConfigCache::new(state.config.clone(), || config(true)));
+        Arc::new(state)
+    }
+
+    #[tokio::test]
+    async fn test_authorized_refresh_reloads_and_reports_changes() {
+        let state = state();
+
+        let response = function_handler(refresh(Some("s3cret")), state.clone()).await.unwrap();
+
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = match response.body() {
+            Body::Text(text) => serde_json::from_str(text).unwrap(),
+            other => panic!("unexpected body {:?}", other),
+        };
+        assert_eq!(body["changed"], serde_json::json!(["cloudevents_enabled"]));
+        assert!(state.config_cache.current().await.cloudevents_enabled);
+        assert!(state.with_current_config().await.config.cloudevents_enabled);
+    }
+
+    #[tokio::test]
+    async fn test_unauthorized_refresh_is_rejected() {
+        let state = state();
+
+        for token in [None, Some("wrong"), Some("s3cre")] {
+            let response = function_handler(refresh(token), state.clone()).await.unwrap();
+            assert_eq!(response.status(), 401);
+        }
+        assert!(!state.config_cache.current().await.cloudevents_enabled);
+
+        // Without a configured token the route doesn't exist
+        let state = Arc::new(test_state(Config::default()));
+        let response = function_handler(refresh(Some("")), state).await.unwrap();
+        assert_eq!(response.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_reload_keeps_current_config() {
+        let cache = ConfigCache::new(Arc::new(config(false)), || {
+            let mut config = config(true);
+            config.identity_hash.enabled = true;
+            config
+        });
+
+        let (current, changed) = cache.refresh().await;
+        assert!(changed.is_empty());
+        assert!(!current.cloudevents_enabled);
+        assert!(!cache.current().await.identity_hash.enabled);
+    }
+
+    #[tokio::test]
+    async fn test_refresh_only_reloads_its_own_sandbox() {
+        // Two sandboxes of the same function, each with its own cache
+        let max_age = |cloudevents_enabled| Config {
+            admin: AdminConfig {
+                token: None,
+                config_max_age: Some(Duration::from_millis(50)),
+            },
+            ..config(cloudevents_enabled)
+        };
+        let refreshed = ConfigCache::new(Arc::new(max_age(false)), move || max_age(true));
+        let other = ConfigCache::new(Arc::new(max_age(false)), move || max_age(true));
+
+        refreshed.refresh().await;
+        assert!(refreshed.current().await.cloudevents_enabled);
+        assert!(!other.current().await.cloudevents_enabled);
+
+        // The other one catches up once its cache expires
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(other.current().await.cloudevents_enabled);
+    }
+}
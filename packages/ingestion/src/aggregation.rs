@@ -0,0 +1,227 @@
+//! KPL-style record aggregation.
+//!
+//! Kinesis bills per record and per 25 KB payload unit, so thousands of
+//! small events cost far more as thousands of records than packed
+//! together. With `KPL_AGGREGATION_ENABLED`, events sharing a partition key
+//! are packed into Kinesis Producer Library aggregated records (see
+//! `proto/kpl_aggregation.proto`) of up to `KPL_AGGREGATION_MAX_BYTES`
+//! (default 51200, the KPL's own default). Consumers built on the KCL or an
+//! aws-kinesis-agg deaggregator see the original records; Firehose
+//! deaggregates on its own. A key with a single event is written plain.
+
+use lambda_http::Error;
+use md5::{Digest, Md5};
+use prost::Message;
+
+use crate::put_records::{Record, Serialized};
+use crate::partitioning::PartitionKey;
+use crate::shared::{env_flag, env_or};
+
+/// Types generated from `proto/kpl_aggregation.proto`
+pub mod kpl {
+    include!(concat!(env!("OUT_DIR"), "/kpl.rs"));
+}
+
+/// Leading bytes that mark an aggregated record
+pub const MAGIC: [u8; 4] = [0xF3, 0x89, 0x9A, 0xC2];
+/// Largest Kinesis record, data plus partition key
+const MAX_RECORD_BYTES: usize = 1024 * 1024;
+/// Trailing MD5 digest
+const DIGEST_BYTES: usize = 16;
+
+/// Configuration for record aggregation
+#[derive(Debug, Clone)]
+pub struct AggregationConfig {
+    pub enabled: bool,
+    /// Largest aggregated record, in bytes
+    pub max_bytes: usize,
+}
+
+impl Default for AggregationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_bytes: 51_200,
+        }
+    }
+}
+
+impl AggregationConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            enabled: env_flag("KPL_AGGREGATION_ENABLED"),
+            max_bytes: env_or("KPL_AGGREGATION_MAX_BYTES", defaults.max_bytes).min(MAX_RECORD_BYTES),
+        }
+    }
+}
+
+/// An aggregated record holding `data` under one partition key
+pub fn encode(key: &PartitionKey, data: &[Vec<u8>]) -> Vec<u8> {
+    let aggregated = kpl::AggregatedRecord {
+        partition_key_table: vec![key.key.clone()],
+        explicit_hash_key_table: key.explicit_hash_key.iter().cloned().collect(),
+        records: data
+            .iter()
+            .map(|data| kpl::Record {
+                partition_key_index: 0,
+                explicit_hash_key_index: key.explicit_hash_key.as_ref().map(|_| 0),
+                data: data.clone(),
+                tags: Vec::new(),
+            })
+            .collect(),
+    };
+    let body = aggregated.encode_to_vec();
+    let mut record = Vec::with_capacity(MAGIC.len() + body.len() + DIGEST_BYTES);
+    record.extend_from_slice(&MAGIC);
+    record.extend_from_slice(&body);
+    record.extend_from_slice(&Md5::digest(&body));
+    record
+}
+
+/// The user records of a stream record, for consumers: those of an
+/// aggregate whose framing and digest check out, otherwise the record itself
+pub fn decode(record: &[u8]) -> Vec<Vec<u8>> {
+    let aggregated = record
+        .strip_prefix(&MAGIC)
+        .filter(|rest| rest.len() >= DIGEST_BYTES)
+        .map(|rest| rest.split_at(rest.len() - DIGEST_BYTES))
+        .filter(|(body, digest)| Md5::digest(body)[..] == **digest)
+        .and_then(|(body, _)| kpl::AggregatedRecord::decode(body).ok());
+    match aggregated {
+        Some(aggregated) => aggregated.records.into_iter().map(|record| record.data).collect(),
+        None => vec![record.to_vec()],
+    }
+}
+
+/// Bytes one user record adds to an aggregate: the protobuf framing of
+/// the record and its data
+fn entry_size(data: &[u8]) -> usize {
+    let record = prost::encoding::encoded_len_varint(data.len() as u64) + 1 + data.len() + 2;
+    prost::encoding::encoded_len_varint(record as u64) + 1 + record
+}
+
+/// Packs serialized events into stream records, events sharing a partition
+/// key in order into aggregates of up to `max_bytes`
+pub fn aggregate<'a>(serialized: Vec<Serialized<'a>>, config: &AggregationConfig) -> Result<Vec<Record<'a>>, Error> {
+    // By key, in order of each key's first event
+    let mut groups: Vec<(PartitionKey, Vec<Serialized<'a>>)> = Vec::new();
+    for event in serialized {
+        match groups.iter_mut().find(|(key, _)| *key == event.key) {
+            Some((_, group)) => group.push(event),
+            None => groups.push((event.key.clone(), vec![event])),
+        }
+    }
+
+    let mut records = Vec::new();
+    for (key, group) in groups {
+        let overhead = MAGIC.len() + DIGEST_BYTES + key.key.len() + 4
+            + key.explicit_hash_key.as_ref().map_or(0, |hash_key| hash_key.len() + 4);
+        let mut packed: Vec<Serialized<'a>> = Vec::new();
+        let mut bytes = overhead;
+        for event in group {
+            let size = entry_size(&event.data);
+            if !packed.is_empty() && bytes + size > config.max_bytes {
+                records.push(flush(&key, std::mem::take(&mut packed))?);
+                bytes = overhead;
+            }
+            bytes += size;
+            packed.push(event);
+        }
+        if !packed.is_empty() {
+            records.push(flush(&key, packed)?);
+        }
+    }
+    Ok(records)
+}
+
+/// One stream record for a run of events: plain for one, aggregated for more
+fn flush<'a>(key: &PartitionKey, mut packed: Vec<Serialized<'a>>) -> Result<Record<'a>, Error> {
+    if packed.len() == 1 {
+        return Record::new(packed.remove(0));
+    }
+    let data: Vec<Vec<u8>> = packed.iter().map(|event| event.data.clone()).collect();
+    let events = packed.into_iter().map(|event| event.event).collect();
+    Record::with_events(events, key, encode(key, &data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::IngestEventPayload;
+
+    fn event(project_id: &str) -> IngestEventPayload {
+        IngestEventPayload {
+            project_id: project_id.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn serialized<'a>(event: &'a IngestEventPayload, data: &[u8]) -> Serialized<'a> {
+        Serialized {
+            event,
+            key: PartitionKey {
+                key: event.project_id.clone(),
+                explicit_hash_key: None,
+            },
+            data: data.to_vec(),
+        }
+    }
+
+    /// User records of an aggregated record, checking its framing
+    fn deaggregate(record: &[u8]) -> Vec<Vec<u8>> {
+        assert_eq!(record[..4], MAGIC);
+        let (body, digest) = record[4..].split_at(record.len() - 4 - DIGEST_BYTES);
+        assert_eq!(digest, &Md5::digest(body)[..]);
+        let aggregated = kpl::AggregatedRecord::decode(body).unwrap();
+        aggregated.records.into_iter().map(|r| r.data).collect()
+    }
+
+    #[test]
+    fn test_aggregates_by_partition_key_in_order() {
+        let (a, b) = (event("a"), event("b"));
+        let serialized = vec![
+            serialized(&a, b"a1"),
+            serialized(&b, b"b1"),
+            serialized(&a, b"a2"),
+            serialized(&a, b"a3"),
+        ];
+        let records = aggregate(serialized, &AggregationConfig::default()).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].events.len(), 3);
+        assert_eq!(deaggregate(records[0].data()), [b"a1".to_vec(), b"a2".to_vec(), b"a3".to_vec()]);
+        // A lone event isn't wrapped
+        assert_eq!(records[1].data(), b"b1");
+    }
+
+    #[test]
+    fn test_aggregates_stay_within_max_bytes() {
+        let a = event("a");
+        let config = AggregationConfig {
+            enabled: true,
+            max_bytes: 1_000,
+        };
+        let serialized = (0..20).map(|_| serialized(&a, &[b'x'; 190])).collect();
+        let records = aggregate(serialized, &config).unwrap();
+        assert!(records.len() > 1);
+        assert_eq!(records.iter().map(|r| r.events.len()).sum::<usize>(), 20);
+        for record in &records {
+            assert!(record.data().len() < config.max_bytes, "{}", record.data().len());
+        }
+    }
+
+    #[test]
+    fn test_decode_unpacks_aggregates_and_passes_plain_records() {
+        let key = PartitionKey {
+            key: "a".to_string(),
+            explicit_hash_key: None,
+        };
+        let record = encode(&key, &[b"a1".to_vec(), b"a2".to_vec()]);
+        assert_eq!(decode(&record), vec![b"a1".to_vec(), b"a2".to_vec()]);
+
+        assert_eq!(decode(br#"{"a":1}"#), vec![br#"{"a":1}"#.to_vec()]);
+        let mut corrupted = record.clone();
+        corrupted[6] ^= 0xff;
+        assert_eq!(decode(&corrupted), vec![corrupted.clone()]);
+    }
+}
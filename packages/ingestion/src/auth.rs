@@ -0,0 +1,469 @@
+//! API key authentication.
+//!
+//! With `API_KEY_AUTH_ENABLED`, ingest requests must carry an `X-API-Key`
+//! belonging to the project named by their token. Keys are looked up in an
+//! [`ApiKeyStore`] (a DynamoDB projects table in production) by their
+//! SHA-256, so the table never holds a usable key. Lookups, including
+//! misses, are cached per sandbox for `API_KEY_CACHE_TTL_SECS`, which is also
+//! how long a revoked key keeps working.
+//!
+//! With `API_KEY_ALLOWED_ORIGINS_ENABLED` as well, a key's record also lists
+//! the browser origins allowed to use it. Requests from other origins are
+//! rejected with a 403, and responses echo only an approved origin in
+//! `Access-Control-Allow-Origin`. Requests without an origin (server-side
+//! clients) are unaffected.
+//!
+//! With `API_KEY_SAMPLING_ENABLED` as well, a project's sampling rate keeps
+//! that share of its sessions (see
+//! [`sampling`](crate::enrichment::sampling)). A key's `retention_days`
+//! is stamped on its events when retention tagging is on (see
+//! [`retention`](crate::enrichment::retention)), and its `timezone` sets
+//! the day their local date is counted in (see
+//! [`reporting_day`](crate::enrichment::reporting_day)). Records are written by
+//! `packages/admin-api`.
+
+use async_trait::async_trait;
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use lambda_http::{Body, Error, Request, Response};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::jwt;
+use crate::origin::{self, request_origin};
+use crate::signing;
+use crate::shared::{create_error_response, env_flag, env_or, env_var, header_value, AppState};
+
+/// Cached lookups kept before expired ones are swept out
+const MAX_CACHED_KEYS: usize = 10_000;
+
+/// Configuration for API key authentication
+#[derive(Debug, Clone)]
+pub struct ApiKeyConfig {
+    pub enabled: bool,
+    /// DynamoDB projects table; in-memory (no keys) when unset
+    pub table_name: Option<String>,
+    pub cache_ttl: Duration,
+    /// Restrict browser origins to each key's `allowed_origins`
+    pub project_origins: bool,
+    /// Sample events at each key's `sampling_rate`
+    pub project_sampling: bool,
+}
+
+impl Default for ApiKeyConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            table_name: None,
+            cache_ttl: Duration::from_secs(60),
+            project_origins: false,
+            project_sampling: false,
+        }
+    }
+}
+
+impl ApiKeyConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            enabled: env_flag("API_KEY_AUTH_ENABLED"),
+            table_name: env_var("API_KEYS_TABLE"),
+            cache_ttl: Duration::from_secs(env_or(
+                "API_KEY_CACHE_TTL_SECS",
+                defaults.cache_ttl.as_secs(),
+            )),
+            project_origins: env_flag("API_KEY_ALLOWED_ORIGINS_ENABLED"),
+            project_sampling: env_flag("API_KEY_SAMPLING_ENABLED"),
+        }
+    }
+}
+
+/// What an API key grants
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ApiKeyRecord {
+    pub project_id: String,
+    /// Browser origins allowed to use the key, normalized
+    pub allowed_origins: Vec<String>,
+    /// Share of the project's visitors whose events are kept, 0 to 1; all
+    /// when unset
+    pub sampling_rate: Option<f64>,
+    /// Days the project's raw events are kept; the lake's default when unset
+    pub retention_days: Option<u32>,
+    /// IANA timezone the project reports days in; UTC when unset
+    pub timezone: Option<String>,
+}
+
+/// Hex SHA-256 of a key, as stored
+pub fn key_hash(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+/// API key store, keyed by [`key_hash`]
+#[async_trait]
+pub trait ApiKeyStore: Send + Sync {
+    async fn get(&self, key_hash: &str) -> Result<Option<ApiKeyRecord>, Error>;
+}
+
+/// Process-local store, used in tests and when no table is configured
+#[derive(Debug, Default)]
+pub struct InMemoryApiKeyStore {
+    entries: Mutex<HashMap<String, ApiKeyRecord>>,
+}
+
+impl InMemoryApiKeyStore {
+    /// Registers a plain key
+    pub fn insert(&self, key: &str, record: ApiKeyRecord) {
+        self.entries.lock().unwrap().insert(key_hash(key), record);
+    }
+}
+
+#[async_trait]
+impl ApiKeyStore for InMemoryApiKeyStore {
+    async fn get(&self, key_hash: &str) -> Result<Option<ApiKeyRecord>, Error> {
+        Ok(self.entries.lock().unwrap().get(key_hash).cloned())
+    }
+}
+
+/// DynamoDB-backed store
+/// Table schema: partition key `pk` (S, the key's hex SHA-256), attributes
+/// `project_id` (S) and optionally `allowed_origins` (SS), `sampling_rate`
+/// (N), `retention_days` (N) and `timezone` (S)
+pub struct DynamoApiKeyStore {
+    client: DynamoClient,
+    table_name: String,
+}
+
+impl DynamoApiKeyStore {
+    pub fn new(client: DynamoClient, table_name: String) -> Self {
+        Self { client, table_name }
+    }
+}
+
+#[async_trait]
+impl ApiKeyStore for DynamoApiKeyStore {
+    async fn get(&self, key_hash: &str) -> Result<Option<ApiKeyRecord>, Error> {
+        let output = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("pk", AttributeValue::S(key_hash.to_string()))
+            .send()
+            .await?;
+
+        let Some(item) = output.item() else {
+            return Ok(None);
+        };
+        let Some(project_id) = item.get("project_id").and_then(|v| v.as_s().ok()) else {
+            return Ok(None);
+        };
+        let allowed_origins = item
+            .get("allowed_origins")
+            .and_then(|v| v.as_ss().ok())
+            .cloned()
+            .unwrap_or_default();
+        let number = |name: &str| item.get(name).and_then(|v| v.as_n().ok());
+
+        Ok(Some(ApiKeyRecord {
+            project_id: project_id.clone(),
+            allowed_origins: origin::normalize(allowed_origins),
+            sampling_rate: number("sampling_rate").and_then(|n| n.parse().ok()),
+            retention_days: number("retention_days").and_then(|n| n.parse().ok()),
+            timezone: item.get("timezone").and_then(|v| v.as_s().ok()).cloned(),
+        }))
+    }
+}
+
+/// Recent lookups in front of an [`ApiKeyStore`]
+pub struct ApiKeyCache {
+    store: Arc<dyn ApiKeyStore>,
+    entries: Mutex<HashMap<String, (Option<ApiKeyRecord>, Instant)>>,
+}
+
+impl ApiKeyCache {
+    pub fn new(store: Arc<dyn ApiKeyStore>) -> Self {
+        Self {
+            store,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Record for a plain key, from the cache while younger than `ttl`
+    pub async fn lookup(&self, key: &str, ttl: Duration) -> Result<Option<ApiKeyRecord>, Error> {
+        let hash = key_hash(key);
+        if let Some((record, fetched_at)) = self.entries.lock().unwrap().get(&hash) {
+            if fetched_at.elapsed() < ttl {
+                return Ok(record.clone());
+            }
+        }
+
+        let record = self.store.get(&hash).await?;
+
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= MAX_CACHED_KEYS {
+            entries.retain(|_, (_, fetched_at)| fetched_at.elapsed() < ttl);
+        }
+        entries.insert(hash, (record.clone(), Instant::now()));
+        Ok(record)
+    }
+}
+
+/// Checks the request's API key against its project, unless a verified
+/// bearer token for the project stands in for it (see [`jwt`]), and that a
+/// signing project's request was signed (see [`signing`]). Returns the
+/// rejection to send when it doesn't pass.
+pub async fn check_api_key(
+    request: &Request,
+    project_id: &str,
+    state: &AppState,
+) -> Result<Option<Response<Body>>, Error> {
+    if let Some(rejection) = signing::require(request, project_id, &state.config.signing) {
+        return Ok(Some(rejection));
+    }
+
+    let config = &state.config.api_keys;
+    if !config.enabled {
+        return Ok(None);
+    }
+
+    // A token the issuer signed for this project vouches for the request
+    if request
+        .extensions()
+        .get::<jwt::Verified>()
+        .is_some_and(|verified| verified.project_id == project_id)
+    {
+        return Ok(None);
+    }
+
+    let Some(key) = header_value(request, "x-api-key") else {
+        return Ok(Some(create_error_response(401, "Unauthorized: Missing API key")));
+    };
+
+    Ok(match state.api_keys.lookup(key, config.cache_ttl).await? {
+        None => Some(create_error_response(401, "Unauthorized: Invalid API key")),
+        Some(record) if record.project_id != project_id => {
+            tracing::warn!("Rejecting API key of project {} used for {}", record.project_id, project_id);
+            Some(create_error_response(403, "API key does not belong to this project"))
+        }
+        Some(record) if config.project_origins && !origin_permitted(request, &record) => {
+            tracing::warn!("Rejecting request from an origin not allowed for {}", project_id);
+            Some(create_error_response(403, "Origin not allowed for this project"))
+        }
+        Some(_) => None,
+    })
+}
+
+/// The sampling rate of the request's API key, when it has one and
+/// project sampling is on
+pub async fn sampling_rate(request: &Request, state: &AppState) -> Result<Option<f64>, Error> {
+    let config = &state.config.api_keys;
+    let enabled = config.enabled && config.project_sampling;
+    let Some(key) = header_value(request, "x-api-key").filter(|_| enabled) else {
+        return Ok(None);
+    };
+    let record = state.api_keys.lookup(key, config.cache_ttl).await?;
+    Ok(record.and_then(|record| record.sampling_rate))
+}
+
+/// The retention of the request's API key, when it has one and keys are
+/// checked
+pub async fn retention_days(request: &Request, state: &AppState) -> Result<Option<u32>, Error> {
+    let config = &state.config.api_keys;
+    let Some(key) = header_value(request, "x-api-key").filter(|_| config.enabled) else {
+        return Ok(None);
+    };
+    let record = state.api_keys.lookup(key, config.cache_ttl).await?;
+    Ok(record.and_then(|record| record.retention_days))
+}
+
+/// The reporting timezone of the request's API key, when it has one and
+/// keys are checked
+pub async fn timezone(request: &Request, state: &AppState) -> Result<Option<String>, Error> {
+    let config = &state.config.api_keys;
+    let Some(key) = header_value(request, "x-api-key").filter(|_| config.enabled) else {
+        return Ok(None);
+    };
+    let record = state.api_keys.lookup(key, config.cache_ttl).await?;
+    Ok(record.and_then(|record| record.timezone))
+}
+
+/// Whether a request without an origin, or from one of the key's origins
+fn origin_permitted(request: &Request, record: &ApiKeyRecord) -> bool {
+    request_origin(request).is_none_or(|o| origin::origin_allowed(&record.allowed_origins, &o))
+}
+
+/// The request's origin if its API key allows it, for CORS headers
+pub async fn approved_origin(request: &Request, state: &AppState) -> Result<Option<String>, Error> {
+    let (Some(origin), Some(key)) = (request_origin(request), header_value(request, "x-api-key")) else {
+        return Ok(None);
+    };
+    let record = state.api_keys.lookup(key, state.config.api_keys.cache_ttl).await?;
+    Ok(record
+        .filter(|record| origin::origin_allowed(&record.allowed_origins, &origin))
+        .map(|_| origin))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::{test_state, Config};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn state() -> AppState {
+        state_with(ApiKeyConfig {
+            enabled: true,
+            ..Default::default()
+        })
+    }
+
+    fn state_with(api_keys: ApiKeyConfig) -> AppState {
+        let store = InMemoryApiKeyStore::default();
+        store.insert(
+            "pk_live_a",
+            ApiKeyRecord {
+                project_id: "proj-a".to_string(),
+                allowed_origins: vec!["https://app.a.com".to_string()],
+                ..Default::default()
+            },
+        );
+        let mut state = test_state(Config {
+            api_keys,
+            ..Default::default()
+        });
+        state.api_keys = Arc::new(ApiKeyCache::new(Arc::new(store)));
+        state
+    }
+
+    fn request(key: Option<&str>) -> Request {
+        let mut builder = lambda_http::http::Request::builder();
+        if let Some(key) = key {
+            builder = builder.header("X-API-Key", key);
+        }
+        builder.body(Body::Empty).unwrap()
+    }
+
+    async fn status(key: Option<&str>, project_id: &str) -> Option<u16> {
+        check_api_key(&request(key), project_id, &state())
+            .await
+            .unwrap()
+            .map(|response| response.status().as_u16())
+    }
+
+    #[tokio::test]
+    async fn test_key_must_exist_and_match_project() {
+        assert_eq!(status(Some("pk_live_a"), "proj-a").await, None);
+        assert_eq!(status(None, "proj-a").await, Some(401));
+        assert_eq!(status(Some("pk_live_b"), "proj-a").await, Some(401));
+        assert_eq!(status(Some("pk_live_a"), "proj-b").await, Some(403));
+    }
+
+    #[tokio::test]
+    async fn test_handlers_reject_keys_of_other_projects() {
+        use base64::Engine;
+        let claims = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .encode(r#"{"projectId":"proj-b"}"#);
+        let request = lambda_http::http::Request::builder()
+            .method("POST")
+            .uri("/event")
+            .header("Authorization", format!("Bearer e30.{}.sig", claims))
+            .header("X-API-Key", "pk_live_a")
+            .body(Body::Text("{}".to_string()))
+            .unwrap();
+
+        let response = crate::router::function_handler(request, Arc::new(state())).await.unwrap();
+        assert_eq!(response.status(), 403);
+    }
+
+    #[tokio::test]
+    async fn test_project_origins_restrict_requests_and_cors() {
+        let state = Arc::new(state_with(ApiKeyConfig {
+            enabled: true,
+            project_origins: true,
+            ..Default::default()
+        }));
+        let claims = base64::Engine::encode(
+            &base64::engine::general_purpose::URL_SAFE_NO_PAD,
+            r#"{"projectId":"proj-a"}"#,
+        );
+        let post = |origin: &str| {
+            lambda_http::http::Request::builder()
+                .method("POST")
+                .uri("/event")
+                .header("Authorization", format!("Bearer e30.{}.sig", claims))
+                .header("X-API-Key", "pk_live_a")
+                .header("Origin", origin)
+                .body(Body::Text("{}".to_string()))
+                .unwrap()
+        };
+
+        // Approved: past auth (then rejected as an invalid event), origin echoed
+        let response = crate::router::function_handler(post("https://app.a.com"), state.clone())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+        assert_eq!(response.headers()["access-control-allow-origin"], "https://app.a.com");
+        assert_eq!(response.headers()["vary"], "Origin");
+
+        let response = crate::router::function_handler(post("https://evil.io"), state)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 403);
+        assert!(!response.headers().contains_key("access-control-allow-origin"));
+    }
+
+    #[tokio::test]
+    async fn test_sampling_rate_comes_from_the_key() {
+        let store = InMemoryApiKeyStore::default();
+        store.insert(
+            "pk_live_s",
+            ApiKeyRecord {
+                project_id: "proj-s".to_string(),
+                sampling_rate: Some(0.1),
+                ..Default::default()
+            },
+        );
+        let mut state = state_with(ApiKeyConfig {
+            enabled: true,
+            project_sampling: true,
+            ..Default::default()
+        });
+        state.api_keys = Arc::new(ApiKeyCache::new(Arc::new(store)));
+
+        assert_eq!(sampling_rate(&request(Some("pk_live_s")), &state).await.unwrap(), Some(0.1));
+        assert_eq!(sampling_rate(&request(Some("pk_live_x")), &state).await.unwrap(), None);
+        assert_eq!(sampling_rate(&request(None), &state).await.unwrap(), None);
+
+        state.config = Arc::new(Config::default());
+        assert_eq!(sampling_rate(&request(Some("pk_live_s")), &state).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_disabled_auth_ignores_keys() {
+        let state = test_state(Config::default());
+        assert!(check_api_key(&request(None), "proj-a", &state).await.unwrap().is_none());
+    }
+
+    struct CountingStore(AtomicUsize);
+
+    #[async_trait]
+    impl ApiKeyStore for CountingStore {
+        async fn get(&self, _key_hash: &str) -> Result<Option<ApiKeyRecord>, Error> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(None)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lookups_are_cached_until_ttl() {
+        let store = Arc::new(CountingStore(AtomicUsize::new(0)));
+        let cache = ApiKeyCache::new(store.clone());
+
+        cache.lookup("k", Duration::from_secs(60)).await.unwrap();
+        cache.lookup("k", Duration::from_secs(60)).await.unwrap();
+        assert_eq!(store.0.load(Ordering::SeqCst), 1);
+
+        cache.lookup("k", Duration::ZERO).await.unwrap();
+        assert_eq!(store.0.load(Ordering::SeqCst), 2);
+    }
+}
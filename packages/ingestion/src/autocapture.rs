@@ -0,0 +1,254 @@
+//! Server-side throttling of auto-captured events.
+//!
+//! SDK auto-capture reports every click (`POST /click`) and every scroll
+//! milestone (`POST /scroll`), which is mostly noise once a user starts
+//! rage-clicking or scrolling up and down a page. Before they're written,
+//! per session:
+//!
+//! - a `scroll_depth` event only passes if it is deeper than the deepest
+//!   bucket already reported for its page
+//! - a `click` on the same selector of the same page within
+//!   `AUTOCAPTURE_CLICK_DEDUP_MS` of the previous one is a repeat
+//! - at most `AUTOCAPTURE_CLICKS_PER_MINUTE` clicks pass in any minute
+//!
+//! Throttled events are answered 202 like written ones, so SDKs don't
+//! retry them, and counted in the `AutocaptureThrottled` metric. Sessions
+//! idle for `AUTOCAPTURE_SESSION_TTL_SECS` are forgotten. Like rate
+//! limiting, state is kept per Lambda sandbox, so a session spread over
+//! several sandboxes is throttled less.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::metrics::MetricSet;
+use crate::models::IngestEventPayload;
+use crate::shared::{env_or, AppState};
+
+/// Configuration for auto-capture throttling
+#[derive(Debug, Clone)]
+pub struct AutocaptureConfig {
+    /// Window in which a repeated click on the same element is dropped
+    pub click_dedup: Duration,
+    /// Clicks let through per session and minute
+    pub clicks_per_minute: usize,
+    /// How long an idle session's state is kept
+    pub session_ttl: Duration,
+}
+
+impl Default for AutocaptureConfig {
+    fn default() -> Self {
+        Self {
+            click_dedup: Duration::from_millis(1_000),
+            clicks_per_minute: 60,
+            session_ttl: Duration::from_secs(30 * 60),
+        }
+    }
+}
+
+impl AutocaptureConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            click_dedup: Duration::from_millis(env_or(
+                "AUTOCAPTURE_CLICK_DEDUP_MS",
+                defaults.click_dedup.as_millis() as u64,
+            )),
+            clicks_per_minute: env_or("AUTOCAPTURE_CLICKS_PER_MINUTE", defaults.clicks_per_minute).max(1),
+            session_ttl: Duration::from_secs(
+                env_or("AUTOCAPTURE_SESSION_TTL_SECS", defaults.session_ttl.as_secs()).max(1),
+            ),
+        }
+    }
+}
+
+/// What's been let through for one session
+#[derive(Debug)]
+struct Session {
+    /// Deepest scroll bucket by page
+    depths: HashMap<String, u64>,
+    /// The last click let through, by page and selector, and when
+    last_click: Option<(String, Instant)>,
+    /// When the clicks of the last minute were let through
+    clicks: VecDeque<Instant>,
+    touched: Instant,
+}
+
+#[derive(Debug)]
+struct Sessions {
+    sessions: HashMap<String, Session>,
+    swept: Instant,
+}
+
+/// Auto-capture state by session key
+#[derive(Debug)]
+pub struct AutocaptureThrottle(Mutex<Sessions>);
+
+impl Default for AutocaptureThrottle {
+    fn default() -> Self {
+        Self(Mutex::new(Sessions {
+            sessions: HashMap::new(),
+            swept: Instant::now(),
+        }))
+    }
+}
+
+impl AutocaptureThrottle {
+    /// Whether an event should be written; anything but a click or a
+    /// scroll depth with a session always is
+    pub fn admit(&self, event: &IngestEventPayload, config: &AutocaptureConfig) -> bool {
+        self.admit_at(event, config, Instant::now())
+    }
+
+    fn admit_at(&self, event: &IngestEventPayload, config: &AutocaptureConfig, now: Instant) -> bool {
+        let property = |name: &str| event.properties.as_ref()?.get(name);
+        let is_click = event.event_type == "click";
+        if !is_click && event.event_type != "scroll_depth" {
+            return true;
+        }
+        let (Some(session), Some(page)) = (
+            property("session_id").and_then(|v| v.as_str()),
+            property("url").and_then(|v| v.as_str()),
+        ) else {
+            return true;
+        };
+
+        let mut state = self.0.lock().unwrap();
+        // Idle sessions are dropped at most once per TTL
+        if now.saturating_duration_since(state.swept) >= config.session_ttl {
+            state
+                .sessions
+                .retain(|_, session| now.saturating_duration_since(session.touched) < config.session_ttl);
+            state.swept = now;
+        }
+        let session = state
+            .sessions
+            .entry(format!("{}#{}", event.project_id, session))
+            .or_insert_with(|| Session {
+                depths: HashMap::new(),
+                last_click: None,
+                clicks: VecDeque::new(),
+                touched: now,
+            });
+        session.touched = now;
+
+        if !is_click {
+            let depth = property("depth").and_then(|v| v.as_u64()).unwrap_or(0);
+            if session.depths.get(page).is_some_and(|deepest| depth <= *deepest) {
+                return false;
+            }
+            session.depths.insert(page.to_string(), depth);
+            return true;
+        }
+
+        let target = format!("{} {}", page, property("selector").and_then(|v| v.as_str()).unwrap_or_default());
+        let repeated = session
+            .last_click
+            .as_ref()
+            .is_some_and(|(last, at)| *last == target && now.saturating_duration_since(*at) < config.click_dedup);
+        while session
+            .clicks
+            .front()
+            .is_some_and(|at| now.saturating_duration_since(*at) >= Duration::from_secs(60))
+        {
+            session.clicks.pop_front();
+        }
+        if repeated || session.clicks.len() >= config.clicks_per_minute {
+            return false;
+        }
+        session.last_click = Some((target, now));
+        session.clicks.push_back(now);
+        true
+    }
+}
+
+/// Whether an event should be written, counting those that aren't
+pub fn admit(event: &IngestEventPayload, state: &AppState) -> bool {
+    if state.autocapture.admit(event, &state.config.autocapture) {
+        return true;
+    }
+    MetricSet::new(&state.config.metrics)
+        .dimension("ProjectId", event.project_id.as_str())
+        .dimension("EventType", event.event_type.as_str())
+        .count("AutocaptureThrottled", 1)
+        .emit();
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn event(event_type: &str, properties: serde_json::Value) -> IngestEventPayload {
+        let mut properties: HashMap<String, serde_json::Value> = serde_json::from_value(properties).unwrap();
+        properties.insert("session_id".to_string(), "s1".into());
+        properties.entry("url".to_string()).or_insert("https://shop.io/".into());
+        IngestEventPayload {
+            project_id: "proj".to_string(),
+            event_type: event_type.to_string(),
+            properties: Some(properties),
+            ..Default::default()
+        }
+    }
+
+    fn scroll(depth: u64) -> IngestEventPayload {
+        event("scroll_depth", serde_json::json!({ "depth": depth }))
+    }
+
+    fn click(selector: &str) -> IngestEventPayload {
+        event("click", serde_json::json!({ "selector": selector }))
+    }
+
+    #[test]
+    fn test_only_deeper_scrolls_pass() {
+        let throttle = AutocaptureThrottle::default();
+        let config = AutocaptureConfig::default();
+        let admitted: Vec<bool> = [25, 50, 25, 50, 75]
+            .map(|depth| throttle.admit(&scroll(depth), &config))
+            .to_vec();
+        assert_eq!(admitted, [true, true, false, false, true]);
+
+        // Each page has its own depth
+        let other_page = event("scroll_depth", serde_json::json!({ "depth": 25, "url": "https://shop.io/cart" }));
+        assert!(throttle.admit(&other_page, &config));
+    }
+
+    #[test]
+    fn test_repeated_clicks_are_dropped() {
+        let throttle = AutocaptureThrottle::default();
+        let config = AutocaptureConfig::default();
+        let start = Instant::now();
+
+        assert!(throttle.admit_at(&click("#buy"), &config, start));
+        assert!(!throttle.admit_at(&click("#buy"), &config, start + Duration::from_millis(300)));
+        assert!(throttle.admit_at(&click("#cart"), &config, start + Duration::from_millis(400)));
+        assert!(throttle.admit_at(&click("#buy"), &config, start + Duration::from_millis(1_500)));
+    }
+
+    #[test]
+    fn test_clicks_are_capped_per_minute() {
+        let throttle = AutocaptureThrottle::default();
+        let config = AutocaptureConfig {
+            clicks_per_minute: 3,
+            ..Default::default()
+        };
+        let start = Instant::now();
+        let at = |seconds| start + Duration::from_secs(seconds);
+
+        let admitted: Vec<bool> = (0..5)
+            .map(|n| throttle.admit_at(&click(&format!("#item-{}", n)), &config, at(n)))
+            .collect();
+        assert_eq!(admitted, [true, true, true, false, false]);
+        assert!(throttle.admit_at(&click("#later"), &config, at(61)));
+    }
+
+    #[test]
+    fn test_other_events_always_pass() {
+        let throttle = AutocaptureThrottle::default();
+        let pageview = event("pageview", serde_json::json!({}));
+        for _ in 0..3 {
+            assert!(throttle.admit(&pageview, &AutocaptureConfig::default()));
+        }
+    }
+}
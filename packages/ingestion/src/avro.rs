@@ -0,0 +1,242 @@
+//! Avro record encoding for the stream.
+//!
+//! Records go to Kinesis as JSON by default. With `RECORD_ENCODING=avro`
+//! they're Avro binary instead, in the AWS Glue Schema Registry wire format
+//! (a version byte, a compression byte and the 16-byte schema version id,
+//! then the Avro body), so Firehose can convert to Parquet against the
+//! registered schema without a transformation Lambda.
+//!
+//! The schema is `schemas/ingest_event.avsc`. Register it in the registry
+//! and set `AVRO_SCHEMA_VERSION_ID` to the version's id; without one, Avro
+//! mode falls back to JSON. Core fields are typed columns; `properties`,
+//! `context` and `traits` are JSON strings, and every other top-level field
+//! is a JSON value in the `attributes` map. Encoding happens after field
+//! projection, so dropped fields are null or absent.
+
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+use crate::shared::{env_opt, env_or};
+
+/// The registered schema
+pub const SCHEMA: &str = include_str!("../schemas/ingest_event.avsc");
+
+/// Glue Schema Registry header version
+const GLUE_HEADER_VERSION: u8 = 3;
+/// Glue Schema Registry "no compression"
+const GLUE_COMPRESSION_NONE: u8 = 0;
+
+/// Optional string columns, in schema order after the required ones
+const OPTIONAL_STRINGS: [&str; 3] = ["userId", "anonymousId", "messageId"];
+/// Free-form objects stored as JSON strings, in schema order
+const JSON_COLUMNS: [&str; 3] = ["properties", "context", "traits"];
+
+/// How stream records are serialized
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RecordEncoding {
+    #[default]
+    Json,
+    Avro,
+}
+
+impl std::str::FromStr for RecordEncoding {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "json" => Ok(Self::Json),
+            "avro" => Ok(Self::Avro),
+            other => Err(format!("unknown record encoding \"{}\"", other)),
+        }
+    }
+}
+
+/// Configuration for record encoding
+#[derive(Debug, Clone, Default)]
+pub struct RecordEncodingConfig {
+    pub encoding: RecordEncoding,
+    /// Glue schema version id of [`SCHEMA`]
+    pub schema_version_id: Option<uuid::Uuid>,
+}
+
+impl RecordEncodingConfig {
+    pub fn from_env() -> Self {
+        let encoding = env_or::<String>("RECORD_ENCODING", "json".to_string())
+            .parse()
+            .unwrap_or_else(|e| {
+                tracing::warn!("Ignoring RECORD_ENCODING: {}", e);
+                RecordEncoding::Json
+            });
+        let schema_version_id = env_opt::<String>("AVRO_SCHEMA_VERSION_ID").and_then(|id| {
+            id.parse()
+                .map_err(|e| tracing::warn!("Ignoring AVRO_SCHEMA_VERSION_ID {}: {}", id, e))
+                .ok()
+        });
+        if encoding == RecordEncoding::Avro && schema_version_id.is_none() {
+            tracing::warn!("RECORD_ENCODING=avro needs AVRO_SCHEMA_VERSION_ID; writing JSON");
+            return Self::default();
+        }
+        Self {
+            encoding,
+            schema_version_id,
+        }
+    }
+
+    /// Whether records go to the stream as plain JSON
+    pub fn writes_json(&self) -> bool {
+        !matches!((self.encoding, self.schema_version_id), (RecordEncoding::Avro, Some(_)))
+    }
+
+    /// Serializes a (projected) record for the stream
+    pub fn encode(&self, record: &Value) -> Result<Vec<u8>, serde_json::Error> {
+        match (self.encoding, self.schema_version_id) {
+            (RecordEncoding::Avro, Some(schema_version_id)) => Ok(glue_frame(&schema_version_id, &encode(record))),
+            _ => serde_json::to_vec(record),
+        }
+    }
+}
+
+/// An Avro body wrapped in the Glue Schema Registry header
+pub fn glue_frame(schema_version_id: &uuid::Uuid, body: &[u8]) -> Vec<u8> {
+    let mut framed = Vec::with_capacity(18 + body.len());
+    framed.push(GLUE_HEADER_VERSION);
+    framed.push(GLUE_COMPRESSION_NONE);
+    framed.extend_from_slice(schema_version_id.as_bytes());
+    framed.extend_from_slice(body);
+    framed
+}
+
+fn write_long(out: &mut Vec<u8>, value: i64) {
+    let mut zigzag = ((value << 1) ^ (value >> 63)) as u64;
+    while zigzag >= 0x80 {
+        out.push((zigzag as u8 & 0x7f) | 0x80);
+        zigzag >>= 7;
+    }
+    out.push(zigzag as u8);
+}
+
+fn write_string(out: &mut Vec<u8>, value: &str) {
+    write_long(out, value.len() as i64);
+    out.extend_from_slice(value.as_bytes());
+}
+
+/// A `["null", "string"]` union
+fn write_optional(out: &mut Vec<u8>, value: Option<&str>) {
+    match value {
+        Some(value) => {
+            write_long(out, 1);
+            write_string(out, value);
+        }
+        None => write_long(out, 0),
+    }
+}
+
+/// A nullable column's string: strings as they are, other values as JSON
+fn column(fields: &Map<String, Value>, name: &str) -> Option<String> {
+    match fields.get(name)? {
+        Value::Null => None,
+        Value::String(s) => Some(s.clone()),
+        other => Some(other.to_string()),
+    }
+}
+
+/// Encodes a serialized event as an Avro `IngestEvent` body
+pub fn encode(record: &Value) -> Vec<u8> {
+    let empty = Map::new();
+    let fields = record.as_object().unwrap_or(&empty);
+    let mut out = Vec::with_capacity(256);
+
+    write_optional(&mut out, column(fields, "eventId").as_deref());
+    write_string(&mut out, fields.get("projectId").and_then(Value::as_str).unwrap_or_default());
+    write_string(&mut out, fields.get("eventType").and_then(Value::as_str).unwrap_or_default());
+    write_long(&mut out, fields.get("timestamp").and_then(Value::as_i64).unwrap_or_default());
+    for name in OPTIONAL_STRINGS.into_iter().chain(JSON_COLUMNS) {
+        write_optional(&mut out, column(fields, name).as_deref());
+    }
+
+    // Sorted, so equal records encode identically
+    let columns = ["eventId", "projectId", "eventType", "timestamp"];
+    let attributes: BTreeMap<&String, String> = fields
+        .iter()
+        .filter(|(name, value)| {
+            !value.is_null()
+                && !columns.contains(&name.as_str())
+                && !OPTIONAL_STRINGS.contains(&name.as_str())
+                && !JSON_COLUMNS.contains(&name.as_str())
+        })
+        .map(|(name, value)| (name, value.to_string()))
+        .collect();
+    if !attributes.is_empty() {
+        write_long(&mut out, attributes.len() as i64);
+        for (name, value) in attributes {
+            write_string(&mut out, name);
+            write_string(&mut out, &value);
+        }
+    }
+    write_long(&mut out, 0);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_zigzag_longs() {
+        let encoded = |value: i64| {
+            let mut out = Vec::new();
+            write_long(&mut out, value);
+            out
+        };
+        assert_eq!(encoded(0), [0x00]);
+        assert_eq!(encoded(-1), [0x01]);
+        assert_eq!(encoded(1), [0x02]);
+        assert_eq!(encoded(-64), [0x7f]);
+        assert_eq!(encoded(64), [0x80, 0x01]);
+        assert_eq!(encoded(1_700_000_000_000).len(), 6);
+    }
+
+    #[test]
+    fn test_encodes_columns_and_attributes() {
+        let record = json!({
+            "projectId": "p",
+            "eventType": "e",
+            "timestamp": 1,
+            "userId": "u",
+            "properties": {"plan": "pro"},
+            "botScore": 5,
+            "coldStart": null,
+        });
+        let mut expected = vec![0x00, 0x02, b'p', 0x02, b'e', 0x02, 0x02, 0x02, b'u', 0x00, 0x00, 0x02];
+        write_string(&mut expected, r#"{"plan":"pro"}"#);
+        expected.extend([0x00, 0x00, 0x02]);
+        write_string(&mut expected, "botScore");
+        write_string(&mut expected, "5");
+        expected.push(0x00);
+        assert_eq!(encode(&record), expected);
+
+        // Projected-away fields are null, and no attributes an empty map
+        let minimal = encode(&json!({"projectId": "p", "eventType": "e", "timestamp": 1}));
+        assert_eq!(minimal, [0x00, 0x02, b'p', 0x02, b'e', 0x02, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_glue_framing_and_json_fallback() {
+        let id = uuid::Uuid::from_u128(0x0102030405060708090a0b0c0d0e0f10);
+        let config = RecordEncodingConfig {
+            encoding: RecordEncoding::Avro,
+            schema_version_id: Some(id),
+        };
+        let record = json!({"projectId": "p", "eventType": "e", "timestamp": 1});
+        let framed = config.encode(&record).unwrap();
+        assert_eq!(&framed[..2], [3, 0]);
+        assert_eq!(&framed[2..18], id.as_bytes());
+        assert_eq!(&framed[18..], encode(&record));
+
+        let json = RecordEncodingConfig::default().encode(&record).unwrap();
+        assert_eq!(serde_json::from_slice::<Value>(&json).unwrap(), record);
+        assert!(serde_json::from_str::<Value>(SCHEMA).is_ok());
+        assert_eq!("AVRO".parse::<RecordEncoding>(), Ok(RecordEncoding::Avro));
+    }
+}
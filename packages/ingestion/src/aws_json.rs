@@ -0,0 +1,120 @@
+//! A minimal client for AWS JSON-protocol APIs without an SDK crate here.
+//!
+//! Each call is a `POST /` to the service's regional endpoint with an
+//! `X-Amz-Target` header naming the operation and a JSON body, signed with
+//! SigV4 using the function's own credentials. Errors come back as the
+//! status and the service's error body; there are no SDK retries, so
+//! callers treat a failed call as final.
+//!
+//! SNS only speaks the older query protocol, so [`AwsJsonClient::call_query`]
+//! sends form-encoded input the same way and hands back the XML response.
+
+use aws_config::SdkConfig;
+use aws_credential_types::provider::{ProvideCredentials, SharedCredentialsProvider};
+use aws_sigv4::http_request::{sign, SignableBody, SignableRequest, SigningSettings};
+use aws_sigv4::sign::v4;
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper_rustls::HttpsConnector;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use lambda_http::Error;
+use serde_json::Value;
+use std::time::SystemTime;
+
+/// A signed JSON client for one service
+#[derive(Clone)]
+pub struct AwsJsonClient {
+    http: Client<HttpsConnector<HttpConnector>, Full<Bytes>>,
+    credentials: SharedCredentialsProvider,
+    region: String,
+    /// Signing name, which is also the endpoint prefix
+    service: &'static str,
+    /// Prefix of `X-Amz-Target`, e.g. `AWSEvents`
+    target_prefix: &'static str,
+    endpoint: String,
+}
+
+impl AwsJsonClient {
+    pub fn new(config: &SdkConfig, service: &'static str, target_prefix: &'static str) -> Result<Self, Error> {
+        let credentials = config.credentials_provider().ok_or("No AWS credentials provider")?;
+        let region = config.region().ok_or("No AWS region")?.to_string();
+        let connector = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_native_roots()?
+            .https_only()
+            .enable_http1()
+            .build();
+        Ok(Self {
+            http: Client::builder(TokioExecutor::new()).build(connector),
+            credentials,
+            endpoint: format!("https://{}.{}.amazonaws.com/", service, region),
+            region,
+            service,
+            target_prefix,
+        })
+    }
+
+    /// Calls `operation` with `input`, returning the parsed response
+    pub async fn call(&self, operation: &str, input: &Value) -> Result<Value, Error> {
+        let request = http::Request::post(&self.endpoint)
+            .header("content-type", "application/x-amz-json-1.1")
+            .header("x-amz-target", format!("{}.{}", self.target_prefix, operation));
+        let body = self.send(request, operation, serde_json::to_vec(input)?).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// Calls query-protocol `action` of API `version` with `params`,
+    /// returning the XML response
+    pub async fn call_query(&self, action: &str, version: &str, params: &[(String, String)]) -> Result<String, Error> {
+        let body = url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("Action", action)
+            .append_pair("Version", version)
+            .extend_pairs(params)
+            .finish();
+        let request = http::Request::post(&self.endpoint).header("content-type", "application/x-www-form-urlencoded");
+        let body = self.send(request, action, body.into_bytes()).await?;
+        Ok(String::from_utf8_lossy(&body).into_owned())
+    }
+
+    /// Signs and sends a request, returning the body of a successful response
+    async fn send(&self, request: http::request::Builder, operation: &str, body: Vec<u8>) -> Result<Bytes, Error> {
+        let mut request = request.body(Full::new(Bytes::from(body.clone())))?;
+
+        let identity = self.credentials.provide_credentials().await?.into();
+        let params = v4::SigningParams::builder()
+            .identity(&identity)
+            .region(&self.region)
+            .name(self.service)
+            .time(SystemTime::now())
+            .settings(SigningSettings::default())
+            .build()?
+            .into();
+        let signable = SignableRequest::new(
+            "POST",
+            &self.endpoint,
+            request
+                .headers()
+                .iter()
+                .filter_map(|(name, value)| Some((name.as_str(), value.to_str().ok()?))),
+            SignableBody::Bytes(&body),
+        )?;
+        let (instructions, _) = sign(signable, &params)?.into_parts();
+        instructions.apply_to_request_http1x(&mut request);
+
+        let response = self.http.request(request).await?;
+        let status = response.status();
+        let body = response.into_body().collect().await?.to_bytes();
+        if !status.is_success() {
+            return Err(format!(
+                "{} {} failed with {}: {}",
+                self.service,
+                operation,
+                status,
+                String::from_utf8_lossy(&body)
+            )
+            .into());
+        }
+        Ok(body)
+    }
+}
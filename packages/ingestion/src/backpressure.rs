@@ -0,0 +1,271 @@
+//! Load shedding while the stream is throttling writes.
+//!
+//! Every Kinesis write reports how many of its record attempts were
+//! throttled (`ProvisionedThroughputExceededException`) to the sandbox's
+//! [`ShardPressure`]. With `BACKPRESSURE_ENABLED`, once at least
+//! `BACKPRESSURE_THROTTLE_RATIO` of the attempts over the last
+//! `BACKPRESSURE_WINDOW_SECS` were throttled (and there were at least
+//! `BACKPRESSURE_MIN_RECORDS` of them, so a single unlucky write doesn't
+//! count), low-priority events are shed: a single event of a type in
+//! `BACKPRESSURE_SHED_EVENT_TYPES` gets a 503 with `Retry-After`, and a
+//! batch rejects such events with reason `overloaded`. Conversions,
+//! identifies and everything else not listed are still written, so they
+//! get the shards' remaining capacity instead of every request timing out
+//! alike.
+//!
+//! Like rate limiting, pressure is tracked per Lambda sandbox.
+
+use lambda_http::{Body, Response};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::metrics::MetricSet;
+use crate::shared::{create_error_response, env_flag, env_list, env_or, AppState};
+
+/// Configuration for load shedding
+#[derive(Debug, Clone)]
+pub struct BackpressureConfig {
+    pub enabled: bool,
+    /// Share of throttled record attempts at which shedding starts
+    pub throttle_ratio: f64,
+    /// How far back throttling is looked at
+    pub window: Duration,
+    /// Fewest record attempts in the window for its ratio to count
+    pub min_records: usize,
+    /// `Retry-After` of shed requests
+    pub retry_after_secs: u64,
+    /// Event types shed under pressure
+    pub shed_event_types: Vec<String>,
+}
+
+impl Default for BackpressureConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            throttle_ratio: 0.1,
+            window: Duration::from_secs(30),
+            min_records: 100,
+            retry_after_secs: 5,
+            shed_event_types: ["autocapture", "click", "scroll_depth", "heartbeat", "web_vital"]
+                .map(String::from)
+                .to_vec(),
+        }
+    }
+}
+
+impl BackpressureConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let shed_event_types = env_list("BACKPRESSURE_SHED_EVENT_TYPES");
+        Self {
+            enabled: env_flag("BACKPRESSURE_ENABLED"),
+            throttle_ratio: env_or("BACKPRESSURE_THROTTLE_RATIO", defaults.throttle_ratio).clamp(0.0, 1.0),
+            window: Duration::from_secs(env_or("BACKPRESSURE_WINDOW_SECS", defaults.window.as_secs()).max(1)),
+            min_records: env_or("BACKPRESSURE_MIN_RECORDS", defaults.min_records).max(1),
+            retry_after_secs: env_or("BACKPRESSURE_RETRY_AFTER_SECS", defaults.retry_after_secs).max(1),
+            shed_event_types: if shed_event_types.is_empty() {
+                defaults.shed_event_types
+            } else {
+                shed_event_types
+            },
+        }
+    }
+
+    /// Whether events of this type are shed under pressure
+    pub fn sheds(&self, event_type: &str) -> bool {
+        self.shed_event_types.iter().any(|shed| shed == event_type)
+    }
+}
+
+/// Record attempts of one write and how many were throttled
+#[derive(Debug)]
+struct Sample {
+    at: Instant,
+    records: usize,
+    throttled: usize,
+}
+
+/// Recent stream writes of this sandbox
+#[derive(Debug, Default)]
+pub struct ShardPressure {
+    samples: Mutex<VecDeque<Sample>>,
+}
+
+impl ShardPressure {
+    /// Records a write of `records` attempts, `throttled` of them throttled
+    pub fn record(&self, records: usize, throttled: usize, config: &BackpressureConfig) {
+        self.record_at(records, throttled, config, Instant::now());
+    }
+
+    fn record_at(&self, records: usize, throttled: usize, config: &BackpressureConfig, now: Instant) {
+        if records == 0 {
+            return;
+        }
+        let mut samples = self.samples.lock().unwrap();
+        prune(&mut samples, config, now);
+        samples.push_back(Sample { at: now, records, throttled });
+    }
+
+    /// Whether throttling over the window calls for shedding
+    pub fn is_saturated(&self, config: &BackpressureConfig) -> bool {
+        self.is_saturated_at(config, Instant::now())
+    }
+
+    fn is_saturated_at(&self, config: &BackpressureConfig, now: Instant) -> bool {
+        let mut samples = self.samples.lock().unwrap();
+        prune(&mut samples, config, now);
+        let (records, throttled) = samples
+            .iter()
+            .fold((0, 0), |(records, throttled), sample| (records + sample.records, throttled + sample.throttled));
+        records >= config.min_records && throttled as f64 >= config.throttle_ratio * records as f64
+    }
+}
+
+/// Drops samples older than the window
+fn prune(samples: &mut VecDeque<Sample>, config: &BackpressureConfig, now: Instant) {
+    while samples
+        .front()
+        .is_some_and(|sample| now.saturating_duration_since(sample.at) > config.window)
+    {
+        samples.pop_front();
+    }
+}
+
+/// Whether an event of this type should be shed right now
+pub fn sheds(state: &AppState, event_type: &str) -> bool {
+    let config = &state.config.backpressure;
+    config.enabled && config.sheds(event_type) && state.shard_pressure.is_saturated(config)
+}
+
+/// 503 for a shed single event, when it should be shed
+pub fn check(state: &AppState, project_id: &str, event_type: &str) -> Option<Response<Body>> {
+    if !sheds(state, event_type) {
+        return None;
+    }
+    record_shed(state, project_id, 1);
+    Some(overloaded(&state.config.backpressure))
+}
+
+/// Counts shed events
+pub fn record_shed(state: &AppState, project_id: &str, count: usize) {
+    MetricSet::new(&state.config.metrics)
+        .dimension("ProjectId", project_id)
+        .count("EventsShed", count)
+        .emit();
+}
+
+/// 503 telling the client when to try again
+pub fn overloaded(config: &BackpressureConfig) -> Response<Body> {
+    let mut response = create_error_response(503, "Service overloaded");
+    with_retry_after(&mut response, config);
+    response
+}
+
+/// Adds `Retry-After` to a response some of whose events were shed
+pub fn with_retry_after(response: &mut Response<Body>, config: &BackpressureConfig) {
+    response
+        .headers_mut()
+        .insert("Retry-After", config.retry_after_secs.into());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::function_handler;
+    use crate::shared::{test_state, Config};
+    use crate::sink::RecordingSink;
+    use std::sync::Arc;
+
+    fn config() -> BackpressureConfig {
+        BackpressureConfig {
+            enabled: true,
+            min_records: 10,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_saturated_only_while_throttling_is_sustained() {
+        let pressure = ShardPressure::default();
+        let start = Instant::now();
+
+        // Too few attempts to tell
+        pressure.record_at(5, 5, &config(), start);
+        assert!(!pressure.is_saturated_at(&config(), start));
+
+        pressure.record_at(50, 1, &config(), start);
+        assert!(pressure.is_saturated_at(&config(), start));
+
+        // Healthy writes dilute the ratio, and old ones age out
+        pressure.record_at(100, 0, &config(), start + Duration::from_secs(10));
+        assert!(!pressure.is_saturated_at(&config(), start + Duration::from_secs(10)));
+        pressure.record_at(10, 2, &config(), start + Duration::from_secs(35));
+        assert!(pressure.is_saturated_at(&config(), start + Duration::from_secs(41)));
+        assert!(!pressure.is_saturated_at(&config(), start + Duration::from_secs(70)));
+    }
+
+    fn request(path: &str, body: String) -> lambda_http::Request {
+        use base64::Engine;
+        let claims = serde_json::json!({ "projectId": "proj" }).to_string();
+        let token = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(claims);
+        lambda_http::http::Request::builder()
+            .method("POST")
+            .uri(path)
+            .header("Authorization", format!("Bearer e30.{}.sig", token))
+            .body(lambda_http::Body::Text(body))
+            .unwrap()
+    }
+
+    fn event(name: &str) -> String {
+        format!(r#"{{"en":"{}","ts":1,"o":"https://a.io/","r":"","sw":1,"sh":1}}"#, name)
+    }
+
+    /// State whose stream is throttling, writing to a recording sink
+    fn saturated_state() -> (Arc<AppState>, Arc<RecordingSink>) {
+        let mut config = Config {
+            backpressure: config(),
+            ..Default::default()
+        };
+        config.s3_parquet.projects = vec!["proj".to_string()];
+        let sink = Arc::new(RecordingSink::default());
+        let mut state = test_state(config);
+        state.parquet_sink = Some(sink.clone());
+        state.shard_pressure.record(100, 50, &state.config.backpressure);
+        (Arc::new(state), sink)
+    }
+
+    #[tokio::test]
+    async fn test_low_priority_events_are_shed_under_pressure() {
+        let (state, sink) = saturated_state();
+
+        let response = function_handler(request("/event", event("autocapture")), state.clone()).await.unwrap();
+        assert_eq!(response.status(), 503);
+        assert_eq!(response.headers()["Retry-After"], "5");
+
+        let response = function_handler(request("/event", event("purchase")), state).await.unwrap();
+        assert_eq!(response.status(), 202);
+        assert_eq!(sink.events.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_batches_reject_only_low_priority_events_under_pressure() {
+        let (state, sink) = saturated_state();
+
+        let mixed = format!("[{},{}]", event("autocapture"), event("purchase"));
+        let response = function_handler(request("/batch", mixed), state.clone()).await.unwrap();
+        assert_eq!(response.status(), 202);
+        assert_eq!(response.headers()["Retry-After"], "5");
+        let body: serde_json::Value = match response.body() {
+            lambda_http::Body::Text(text) => serde_json::from_str(text).unwrap(),
+            _ => panic!("expected a text body"),
+        };
+        assert_eq!(body["accepted"], 1);
+        assert_eq!(body["errors"][0]["reason"], "overloaded");
+        assert_eq!(sink.events.lock().unwrap()[0].event_type, "purchase");
+
+        let shed_only = format!("[{}]", event("heartbeat"));
+        let response = function_handler(request("/batch", shed_only), state).await.unwrap();
+        assert_eq!(response.status(), 503);
+    }
+}
@@ -0,0 +1,112 @@
+//! `navigator.sendBeacon` support.
+//!
+//! Beacons are what survive page unload, but they can't set headers and only
+//! avoid a CORS preflight with a `text/plain` (or untyped `Blob`) body. The
+//! body is parsed as JSON whatever its content type, so the missing piece is
+//! credentials: with `BEACON_SUPPORT_ENABLED`, a `token` query parameter
+//! stands in for `Authorization: Bearer` and `api_key` for `X-API-Key`.
+//! Headers still win when both are sent. Query strings end up in access
+//! logs, so clients should only fall back to them for beacons.
+
+use lambda_http::http::HeaderValue;
+use lambda_http::{Request, RequestExt};
+
+/// Query parameter carrying the project token
+pub const TOKEN_PARAM: &str = "token";
+/// Query parameter carrying the API key
+pub const API_KEY_PARAM: &str = "api_key";
+
+/// Copies query string credentials into the headers the handlers read
+pub fn promote_query_credentials(request: &mut Request) {
+    let Some(params) = request.query_string_parameters_ref() else {
+        return;
+    };
+    let token = params
+        .first(TOKEN_PARAM)
+        .and_then(|token| HeaderValue::from_str(&format!("Bearer {}", token.trim())).ok());
+    let api_key = params
+        .first(API_KEY_PARAM)
+        .and_then(|key| HeaderValue::from_str(key.trim()).ok());
+
+    let headers = request.headers_mut();
+    if let Some(token) = token {
+        headers.entry("authorization").or_insert(token);
+    }
+    if let Some(api_key) = api_key {
+        headers.entry("x-api-key").or_insert(api_key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{ApiKeyCache, ApiKeyConfig, ApiKeyRecord, InMemoryApiKeyStore};
+    use crate::router::function_handler;
+    use crate::shared::{test_state, AppState, Config};
+    use crate::sink::RecordingSink;
+    use lambda_http::Body;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    fn beacon(params: &[(&str, &str)]) -> Request {
+        let params: HashMap<String, Vec<String>> = params
+            .iter()
+            .map(|(name, value)| (name.to_string(), vec![value.to_string()]))
+            .collect();
+        lambda_http::http::Request::builder()
+            .method("POST")
+            .uri("/prod/event")
+            .header("Content-Type", "text/plain;charset=UTF-8")
+            .body(Body::Text(
+                r#"{"en":"page_hidden","ts":1700000000000,"o":"https://a.com/","r":"","sw":1280,"sh":720}"#
+                    .to_string(),
+            ))
+            .unwrap()
+            .with_query_string_parameters(params)
+    }
+
+    fn state(beacon_support: bool) -> Arc<AppState> {
+        let mut config = Config {
+            beacon_support,
+            api_keys: ApiKeyConfig {
+                enabled: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        config.s3_parquet.projects = vec!["proj".to_string()];
+        let store = InMemoryApiKeyStore::default();
+        store.insert(
+            "pk_live_a",
+            ApiKeyRecord {
+                project_id: "proj".to_string(),
+                allowed_origins: Vec::new(),
+                ..Default::default()
+            },
+        );
+        let mut state = test_state(config);
+        state.api_keys = Arc::new(ApiKeyCache::new(Arc::new(store)));
+        state.parquet_sink = Some(Arc::new(RecordingSink::default()));
+        Arc::new(state)
+    }
+
+    #[tokio::test]
+    async fn test_beacon_authenticates_with_query_credentials() {
+        use base64::Engine;
+        let claims = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(r#"{"projectId":"proj"}"#);
+        let token = format!("e30.{}.sig", claims);
+        let request = || beacon(&[("token", &token), ("api_key", "pk_live_a")]);
+
+        let response = function_handler(request(), state(true)).await.unwrap();
+        assert_eq!(response.status(), 202);
+
+        let response = function_handler(request(), state(false)).await.unwrap();
+        assert_eq!(response.status(), 401);
+
+        // A header, when present, is the credential that counts
+        let mut request = request();
+        request.headers_mut().insert("x-api-key", HeaderValue::from_static("pk_live_other"));
+        let response = function_handler(request, state(true)).await.unwrap();
+        assert_eq!(response.status(), 401);
+    }
+}
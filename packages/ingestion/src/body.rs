@@ -0,0 +1,501 @@
+//! Request body parsing with hard limits.
+//!
+//! Pathological bodies (megabytes of JSON, or thousands of nested arrays) are
+//! rejected before serde ever sees them, so they fail fast with a 400 instead
+//! of burning heap or risking a stack overflow in the deserializer.
+
+use lambda_http::Request;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::io::Read;
+
+use crate::shared::{env_or, header_value};
+
+/// Limits applied to every JSON request body
+#[derive(Debug, Clone)]
+pub struct JsonLimits {
+    /// Maximum body size in bytes
+    pub max_body_bytes: usize,
+    /// Maximum nesting depth of objects and arrays
+    pub max_depth: usize,
+    /// Reject anything but whitespace after the JSON value (`{...}xyz`);
+    /// when off, trailing data is ignored
+    pub reject_trailing_data: bool,
+}
+
+impl Default for JsonLimits {
+    fn default() -> Self {
+        Self {
+            max_body_bytes: 1024 * 1024,
+            max_depth: 32,
+            reject_trailing_data: true,
+        }
+    }
+}
+
+impl JsonLimits {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            max_body_bytes: env_or("JSON_MAX_BODY_BYTES", defaults.max_body_bytes),
+            max_depth: env_or("JSON_MAX_DEPTH", defaults.max_depth),
+            reject_trailing_data: env_or("JSON_REJECT_TRAILING_DATA", defaults.reject_trailing_data),
+        }
+    }
+}
+
+/// Parses a JSON body after enforcing size and depth limits
+pub fn parse_json<T: DeserializeOwned>(body: &str, limits: &JsonLimits) -> Result<T, String> {
+    parse_json_in_place(&mut body.as_bytes().to_vec(), limits)
+}
+
+/// Parses a JSON body after enforcing size and depth limits, borrowing
+/// strings from `body` where the model allows it (`Cow` fields).
+///
+/// Parsing is done with simd-json, which unescapes strings into `body`
+/// itself, so the buffer is garbage afterwards.
+#[tracing::instrument(name = "parse", skip_all)]
+pub fn parse_json_in_place<'a, T: Deserialize<'a>>(body: &'a mut [u8], limits: &JsonLimits) -> Result<T, String> {
+    if body.len() > limits.max_body_bytes {
+        return Err(format!(
+            "Request body exceeds maximum size of {} bytes",
+            limits.max_body_bytes
+        ));
+    }
+
+    // Anything after the top-level value is whitespace, rejected, or ignored
+    let end = check_depth(body, limits.max_depth)?;
+    let (value, trailing) = body.split_at_mut(end);
+    if limits.reject_trailing_data && !trailing.iter().all(u8::is_ascii_whitespace) {
+        return Err("Unexpected data after the JSON body".to_string());
+    }
+    parse_value(value)
+}
+
+/// Parses one JSON value with simd-json, reusing its buffers
+fn parse_value<'a, T: Deserialize<'a>>(value: &'a mut [u8]) -> Result<T, String> {
+    BUFFERS
+        .with_borrow_mut(|buffers| simd_json::serde::from_slice_with_buffers(value, buffers))
+        .map_err(|e| format!("Invalid JSON in request body: {}", e))
+}
+
+thread_local! {
+    /// simd-json's scratch space, reused across requests
+    static BUFFERS: RefCell<simd_json::Buffers> = RefCell::default();
+}
+
+/// Whether the body is MessagePack
+pub fn is_msgpack(request: &Request) -> bool {
+    header_value(request, "content-type").is_some_and(|ct| {
+        let ct = ct.trim_start().to_ascii_lowercase();
+        ["application/msgpack", "application/x-msgpack", "application/vnd.msgpack"]
+            .iter()
+            .any(|msgpack| ct.starts_with(msgpack))
+    })
+}
+
+/// Transcodes a MessagePack body to JSON, so it deserializes into the same
+/// models with the same limits. Maps need string keys.
+pub fn msgpack_to_json(body: &[u8]) -> Result<Vec<u8>, String> {
+    let value: serde_json::Value =
+        rmp_serde::from_slice(body).map_err(|e| format!("Invalid MessagePack in request body: {}", e))?;
+    serde_json::to_vec(&value).map_err(|e| e.to_string())
+}
+
+/// Whether the body is newline-delimited JSON
+pub fn is_ndjson(request: &Request) -> bool {
+    header_value(request, "content-type").is_some_and(|ct| {
+        let ct = ct.trim_start().to_ascii_lowercase();
+        ct.starts_with("application/x-ndjson") || ct.starts_with("application/ndjson")
+    })
+}
+
+/// A non-blank NDJSON line's 1-based number and its value or parse error
+pub type NdjsonLine = (usize, Result<serde_json::Value, String>);
+
+/// Parses a newline-delimited JSON body one line at a time. Only the body
+/// as a whole can fail; a bad line doesn't stop the lines after it.
+pub fn parse_ndjson(
+    body: &str,
+    limits: &JsonLimits,
+) -> Result<Vec<NdjsonLine>, String> {
+    if body.len() > limits.max_body_bytes {
+        return Err(format!(
+            "Request body exceeds maximum size of {} bytes",
+            limits.max_body_bytes
+        ));
+    }
+
+    let single = JsonLimits {
+        max_body_bytes: usize::MAX,
+        reject_trailing_data: true,
+        ..limits.clone()
+    };
+    Ok(body
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| (index + 1, parse_json(line, &single)))
+        .collect())
+}
+
+/// Ensures the body lambda_http assembled is the whole body. A
+/// `Transfer-Encoding: chunked` body that still carries its chunk framing is
+/// decoded (returned as `Some`); framing that stops short of the terminating
+/// zero-size chunk, or a body shorter than its `Content-Length`, is an error.
+pub fn complete_body(request: &Request, body: &[u8]) -> Result<Option<Vec<u8>>, String> {
+    let chunked = header_value(request, "transfer-encoding")
+        .is_some_and(|te| te.to_ascii_lowercase().contains("chunked"));
+    if chunked && looks_chunk_framed(body) {
+        return dechunk(body).map(Some);
+    }
+
+    let content_length = header_value(request, "content-length").and_then(|v| v.parse::<usize>().ok());
+    if let Some(expected) = content_length {
+        if body.len() != expected {
+            return Err(format!(
+                "Request body is incomplete: expected {} bytes, received {}",
+                expected,
+                body.len()
+            ));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Decodes a `Content-Encoding` of gzip, br or deflate (returned as
+/// `Some`), reading at most `max_bytes` of output so a compression bomb is
+/// cut off early. Errors carry the status to answer with.
+pub fn decompress(
+    request: &Request,
+    body: &[u8],
+    max_bytes: usize,
+) -> Result<Option<Vec<u8>>, (u16, String)> {
+    let Some(encoding) = header_value(request, "content-encoding") else {
+        return Ok(None);
+    };
+
+    let mut decoded = Vec::new();
+    let limit = max_bytes as u64 + 1;
+    let read = match encoding.to_ascii_lowercase().as_str() {
+        "identity" => return Ok(None),
+        "gzip" | "x-gzip" => flate2::read::MultiGzDecoder::new(body)
+            .take(limit)
+            .read_to_end(&mut decoded),
+        "br" => brotli_decompressor::Decompressor::new(body, 4096)
+            .take(limit)
+            .read_to_end(&mut decoded),
+        // Properly zlib-wrapped, but some clients send raw deflate
+        "deflate" => flate2::read::ZlibDecoder::new(body)
+            .take(limit)
+            .read_to_end(&mut decoded)
+            .or_else(|_| {
+                decoded.clear();
+                flate2::read::DeflateDecoder::new(body).take(limit).read_to_end(&mut decoded)
+            }),
+        other => return Err((415, format!("Unsupported Content-Encoding: {}", other))),
+    };
+
+    read.map_err(|e| (400, format!("Invalid {} request body: {}", encoding, e)))?;
+    if decoded.len() > max_bytes {
+        return Err((
+            413,
+            format!("Decompressed request body exceeds maximum size of {} bytes", max_bytes),
+        ));
+    }
+    Ok(Some(decoded))
+}
+
+/// Whether the body starts with a chunk-size line (`1a\r\n`)
+fn looks_chunk_framed(body: &[u8]) -> bool {
+    let Some(line_end) = body.windows(2).position(|w| w == b"\r\n") else {
+        return false;
+    };
+    let size = body[..line_end].split(|&b| b == b';').next().unwrap_or_default();
+    !size.is_empty() && size.iter().all(u8::is_ascii_hexdigit)
+}
+
+/// Decodes chunked framing, failing if the body was cut off
+fn dechunk(mut body: &[u8]) -> Result<Vec<u8>, String> {
+    let truncated = || "Request body is incomplete: chunked body was truncated".to_string();
+    let mut decoded = Vec::with_capacity(body.len());
+
+    loop {
+        let line_end = body.windows(2).position(|w| w == b"\r\n").ok_or_else(truncated)?;
+        let size = body[..line_end].split(|&b| b == b';').next().unwrap_or_default();
+        let size = std::str::from_utf8(size)
+            .ok()
+            .and_then(|size| usize::from_str_radix(size.trim(), 16).ok())
+            .ok_or_else(|| "Malformed chunked body".to_string())?;
+        body = &body[line_end + 2..];
+
+        if size == 0 {
+            return Ok(decoded);
+        }
+        // A size near usize::MAX would overflow the framing check
+        let end = size.checked_add(2).filter(|&end| end <= body.len()).ok_or_else(truncated)?;
+        if &body[size..end] != b"\r\n" {
+            return Err(truncated());
+        }
+        decoded.extend_from_slice(&body[..size]);
+        body = &body[size + 2..];
+    }
+}
+
+/// Scans the raw bytes for nesting depth without building any values.
+/// Returns where the top-level object or array ends (the whole body for
+/// anything else, or one that never closes).
+fn check_depth(bytes: &[u8], max_depth: usize) -> Result<usize, String> {
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for (index, &byte) in bytes.iter().enumerate() {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+
+        match byte {
+            b'"' => in_string = true,
+            b'{' | b'[' => {
+                depth += 1;
+                if depth > max_depth {
+                    return Err(format!(
+                        "JSON nesting exceeds maximum depth of {}",
+                        max_depth
+                    ));
+                }
+            }
+            b'}' | b']' if depth == 1 => return Ok(index + 1),
+            b'}' | b']' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+
+    Ok(bytes.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::CompressedEvent;
+    use std::io::Write;
+
+    fn encoded(encoding: &str) -> Request {
+        lambda_http::http::Request::builder()
+            .header("Content-Encoding", encoding)
+            .body(lambda_http::Body::Empty)
+            .unwrap()
+    }
+
+    /// A brotli stream holding `data` as one uncompressed meta-block
+    fn brotli_stored(data: &[u8]) -> Vec<u8> {
+        let header = (((data.len() - 1) as u32) << 4) | (1 << 20);
+        let mut stream = header.to_le_bytes()[..3].to_vec();
+        stream.extend_from_slice(data);
+        stream.push(0x03);
+        stream
+    }
+
+    #[test]
+    fn test_decompresses_gzip_brotli_and_deflate() {
+        let json = br#"[{"en":"pageview"}]"#;
+
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(json).unwrap();
+        let gzip = gzip.finish().unwrap();
+        assert_eq!(decompress(&encoded("gzip"), &gzip, 1024).unwrap().unwrap(), json);
+
+        let brotli = brotli_stored(json);
+        assert_eq!(decompress(&encoded("br"), &brotli, 1024).unwrap().unwrap(), json);
+
+        // Zlib-wrapped as specified, and raw as some clients send it
+        let mut zlib = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        zlib.write_all(json).unwrap();
+        let zlib = zlib.finish().unwrap();
+        assert_eq!(decompress(&encoded("deflate"), &zlib, 1024).unwrap().unwrap(), json);
+
+        let mut raw = flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+        raw.write_all(json).unwrap();
+        let raw = raw.finish().unwrap();
+        assert_eq!(decompress(&encoded("deflate"), &raw, 1024).unwrap().unwrap(), json);
+
+        let plain = lambda_http::http::Request::builder().body(lambda_http::Body::Empty).unwrap();
+        assert_eq!(decompress(&plain, json, 1024), Ok(None));
+    }
+
+    #[test]
+    fn test_decompression_bomb_and_unknown_encoding_rejected() {
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+        gzip.write_all(&vec![b' '; 10 * 1024 * 1024]).unwrap();
+        let bomb = gzip.finish().unwrap();
+        assert!(bomb.len() < 64 * 1024);
+
+        assert_eq!(decompress(&encoded("gzip"), &bomb, 1024 * 1024).unwrap_err().0, 413);
+        assert_eq!(decompress(&encoded("gzip"), b"not gzip", 1024).unwrap_err().0, 400);
+        assert_eq!(decompress(&encoded("zstd"), b"", 1024).unwrap_err().0, 415);
+    }
+
+    #[test]
+    fn test_rejects_deeply_nested_json_bomb() {
+        let bomb = format!("{}{}", "[".repeat(100_000), "]".repeat(100_000));
+
+        let result = parse_json::<serde_json::Value>(&bomb, &JsonLimits::default());
+        assert_eq!(
+            result.unwrap_err(),
+            "JSON nesting exceeds maximum depth of 32"
+        );
+    }
+
+    #[test]
+    fn test_rejects_oversized_body() {
+        let limits = JsonLimits {
+            max_body_bytes: 1024,
+            ..Default::default()
+        };
+        let body = format!(
+            r#"{{"en":"pageview","ts":1,"o":"https://example.com","r":"","sw":1,"sh":1,"ed":{{"blob":"{}"}}}}"#,
+            "x".repeat(2048)
+        );
+
+        let result = parse_json_in_place::<CompressedEvent>(&mut body.into_bytes(), &limits).map(|_| ());
+        assert_eq!(
+            result.unwrap_err(),
+            "Request body exceeds maximum size of 1024 bytes"
+        );
+    }
+
+    #[test]
+    fn test_brackets_inside_strings_do_not_count() {
+        let body = r#"{"en":"[[[[{{{{","ts":1,"o":"https://example.com","r":"\"[","sw":1,"sh":1}"#;
+        let limits = JsonLimits {
+            max_depth: 1,
+            ..Default::default()
+        };
+
+        let mut bytes = body.as_bytes().to_vec();
+        let event: CompressedEvent = parse_json_in_place(&mut bytes, &limits).unwrap();
+        assert_eq!(event.en, "[[[[{{{{");
+        assert_eq!(event.r, "\"[");
+    }
+
+    #[test]
+    fn test_trailing_data_after_json() {
+        let clean = r#"{"en":"pageview","ts":1,"o":"https://example.com","r":"","sw":1,"sh":1}"#;
+        let limits = JsonLimits::default();
+        let parse = |body: String, limits: &JsonLimits| {
+            parse_json_in_place::<CompressedEvent>(&mut body.into_bytes(), limits).map(|_| ())
+        };
+
+        assert!(parse(clean.to_string(), &limits).is_ok());
+        assert!(parse(format!("{} \r\n\t", clean), &limits).is_ok());
+        assert_eq!(
+            parse(format!("{}xyz", clean), &limits).unwrap_err(),
+            "Unexpected data after the JSON body"
+        );
+        assert!(parse(format!("{}{{}}", clean), &limits).is_err());
+
+        let lenient = JsonLimits {
+            reject_trailing_data: false,
+            ..Default::default()
+        };
+        assert!(parse(format!("{}xyz", clean), &lenient).is_ok());
+    }
+
+    #[test]
+    fn test_strings_are_borrowed_from_the_body() {
+        let mut body = br#"{"en":"sign\u0075p","ts":1,"o":"https://example.com/?q=\"a\"","r":"","sw":1,"sh":1}"#.to_vec();
+        let event: CompressedEvent = parse_json_in_place(&mut body, &JsonLimits::default()).unwrap();
+
+        assert!(matches!(event.en, std::borrow::Cow::Borrowed("signup")));
+        assert!(matches!(event.o, std::borrow::Cow::Borrowed(r#"https://example.com/?q="a""#)));
+    }
+
+    fn request(headers: &[(&str, &str)]) -> Request {
+        let mut builder = lambda_http::http::Request::builder();
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        builder.body(lambda_http::Body::Empty).unwrap()
+    }
+
+    #[test]
+    fn test_msgpack_transcodes_to_the_same_models() {
+        let json = serde_json::json!({"en": "pageview", "ts": 5, "o": "https://a.io", "r": "", "sw": 1, "sh": 2});
+        let packed = rmp_serde::to_vec_named(&json).unwrap();
+        assert!(packed.len() < json.to_string().len());
+
+        let mut transcoded = msgpack_to_json(&packed).unwrap();
+        let event: CompressedEvent = parse_json_in_place(&mut transcoded, &JsonLimits::default()).unwrap();
+        assert_eq!(event.en, "pageview");
+        assert_eq!(event.sh, 2);
+
+        assert!(msgpack_to_json(&[0xc1]).unwrap_err().starts_with("Invalid MessagePack"));
+        let request = lambda_http::http::Request::builder()
+            .header("Content-Type", "application/x-msgpack")
+            .body(lambda_http::Body::Empty)
+            .unwrap();
+        assert!(is_msgpack(&request));
+    }
+
+    #[test]
+    fn test_ndjson_lines_parse_independently() {
+        let body = "{\"en\":\"a\"}\r\n\n{\"en\":\n[[[[1]]]]\n{\"en\":\"b\"} x\n{\"en\":\"c\"}";
+        let limits = JsonLimits {
+            max_depth: 3,
+            ..Default::default()
+        };
+        let lines = parse_ndjson(body, &limits).unwrap();
+        let numbers: Vec<_> = lines.iter().map(|(line, _)| *line).collect();
+        assert_eq!(numbers, [1, 3, 4, 5, 6]);
+        assert!(lines[0].1.is_ok());
+        assert!(lines[1].1.as_ref().unwrap_err().starts_with("Invalid JSON"));
+        assert!(lines[2].1.as_ref().unwrap_err().contains("maximum depth"));
+        assert!(lines[3].1.is_err());
+        assert_eq!(lines[4].1.as_ref().unwrap()["en"], "c");
+
+        let tight = JsonLimits {
+            max_body_bytes: 8,
+            ..Default::default()
+        };
+        assert!(parse_ndjson(body, &tight).is_err());
+    }
+
+    #[test]
+    fn test_chunk_framed_body_is_decoded() {
+        let chunked = request(&[("Transfer-Encoding", "chunked")]);
+        let body = b"7\r\n{\"en\":\"\r\na;ext=1\r\npageview\"}\r\n0\r\n\r\n";
+
+        let decoded = complete_body(&chunked, body).unwrap().unwrap();
+        assert_eq!(decoded, br#"{"en":"pageview"}"#);
+
+        // Already assembled by the gateway: passed through untouched
+        assert_eq!(complete_body(&chunked, br#"{"en":"pageview"}"#).unwrap(), None);
+    }
+
+    #[test]
+    fn test_truncated_bodies_are_rejected() {
+        let chunked = request(&[("Transfer-Encoding", "chunked")]);
+        for body in [&b"7\r\n{\"en\":\"\r\n9\r\npagev"[..], b"7\r\n{\"en\":\"\r\n"] {
+            let error = complete_body(&chunked, body).unwrap_err();
+            assert!(error.starts_with("Request body is incomplete"), "{}", error);
+        }
+
+        // A chunk size that would overflow is just more than was sent
+        let huge = b"ffffffffffffffff\r\n{}\r\n0\r\n\r\n";
+        let error = complete_body(&chunked, huge).unwrap_err();
+        assert!(error.starts_with("Request body is incomplete"), "{}", error);
+
+        let sized = request(&[("Content-Length", "64")]);
+        assert!(complete_body(&sized, br#"{"en":"pagev"#).is_err());
+        assert_eq!(complete_body(&sized, &[b' '; 64]).unwrap(), None);
+    }
+}
@@ -0,0 +1,283 @@
+//! Cross-invocation micro-batching.
+//!
+//! Each request normally writes its own few events with a `PutRecords` call
+//! of its own. With `EVENT_BUFFER_ENABLED`, `process_events` appends them to
+//! an [`EventBuffer`] shared by every invocation the sandbox serves and
+//! acknowledges right away. The buffer is written out as one batch once it
+//! holds `EVENT_BUFFER_MAX_EVENTS` (500 by default, one `PutRecords` call)
+//! or its oldest event is `EVENT_BUFFER_MAX_AGE_MS` old, so bursty traffic
+//! costs a fraction of the stream calls.
+//!
+//! A due buffer is flushed by the request that finds it due, by
+//! [`flush_periodically`] (which only gets to run while the sandbox is
+//! thawed), and whatever is left when Lambda shuts the sandbox down (see
+//! `shutdown`).
+//!
+//! Buffered events are acknowledged before they're durable: a sandbox that
+//! crashes loses what it had buffered. A flush that fails puts its events
+//! back for the next one, only those not written when the sink took some;
+//! past `EVENT_BUFFER_MAX_PENDING` events the oldest are dropped.
+
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::models::IngestEventPayload;
+use crate::shared::{env_flag, env_or, write_events, AppState};
+use crate::sink::PartialWrite;
+
+/// Configuration for the event buffer
+#[derive(Debug, Clone)]
+pub struct BufferConfig {
+    pub enabled: bool,
+    /// Buffered events that trigger a flush
+    pub max_events: usize,
+    /// Age of the oldest buffered event that triggers a flush
+    pub max_age: Duration,
+    /// Most events kept after failed flushes
+    pub max_pending: usize,
+}
+
+impl Default for BufferConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_events: 500,
+            max_age: Duration::from_millis(1_000),
+            max_pending: 10_000,
+        }
+    }
+}
+
+impl BufferConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            enabled: env_flag("EVENT_BUFFER_ENABLED"),
+            max_events: env_or("EVENT_BUFFER_MAX_EVENTS", defaults.max_events).max(1),
+            max_age: Duration::from_millis(env_or("EVENT_BUFFER_MAX_AGE_MS", defaults.max_age.as_millis() as u64)),
+            max_pending: env_or("EVENT_BUFFER_MAX_PENDING", defaults.max_pending),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Pending {
+    events: Vec<IngestEventPayload>,
+    /// When the oldest buffered event was buffered
+    since: Option<Instant>,
+}
+
+impl Pending {
+    fn due(&self, config: &BufferConfig) -> bool {
+        self.events.len() >= config.max_events
+            || self.since.is_some_and(|since| since.elapsed() >= config.max_age)
+    }
+
+    fn take(&mut self) -> Option<Vec<IngestEventPayload>> {
+        self.since = None;
+        Some(std::mem::take(&mut self.events)).filter(|events| !events.is_empty())
+    }
+}
+
+/// Events waiting to be written, shared across invocations
+#[derive(Debug, Default)]
+pub struct EventBuffer {
+    pending: Mutex<Pending>,
+}
+
+impl EventBuffer {
+    /// Appends events, returning everything buffered when that's now due
+    pub fn push(&self, events: Vec<IngestEventPayload>, config: &BufferConfig) -> Option<Vec<IngestEventPayload>> {
+        let mut pending = self.pending.lock().unwrap();
+        pending.since.get_or_insert_with(Instant::now);
+        pending.events.extend(events);
+        if pending.due(config) {
+            return pending.take();
+        }
+        None
+    }
+
+    /// Takes everything buffered if it's due, or regardless with `all`
+    pub fn take(&self, config: &BufferConfig, all: bool) -> Option<Vec<IngestEventPayload>> {
+        let mut pending = self.pending.lock().unwrap();
+        if all || pending.due(config) {
+            return pending.take();
+        }
+        None
+    }
+
+    /// Puts back events whose flush failed, ahead of those buffered since
+    pub fn requeue(&self, mut events: Vec<IngestEventPayload>, config: &BufferConfig) {
+        let mut pending = self.pending.lock().unwrap();
+        events.append(&mut pending.events);
+        let overflow = events.len().saturating_sub(config.max_pending);
+        if overflow > 0 {
+            tracing::error!("Event buffer is full, dropping the {} oldest events", overflow);
+            events.drain(..overflow);
+        }
+        pending.since = Some(Instant::now());
+        pending.events = events;
+    }
+
+    /// Number of buffered events
+    pub fn len(&self) -> usize {
+        self.pending.lock().unwrap().events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Writes out a batch taken from the buffer, putting back what wasn't
+/// written
+pub async fn write(events: Vec<IngestEventPayload>, state: &Arc<AppState>) {
+    let count = events.len();
+    let Err(e) = write_events(events.clone(), state).await else {
+        return;
+    };
+    // Those the sink did take would be written twice if kept
+    let unwritten: Vec<_> = match e.downcast_ref::<PartialWrite>() {
+        Some(partial) => {
+            let failed: HashSet<usize> = partial.failed.iter().map(|(position, _)| *position).collect();
+            events
+                .into_iter()
+                .enumerate()
+                .filter(|(position, _)| failed.contains(position))
+                .map(|(_, event)| event)
+                .collect()
+        }
+        None => events,
+    };
+    tracing::error!(
+        "Failed to flush {} of {} buffered events, keeping them: {}",
+        unwritten.len(),
+        count,
+        e
+    );
+    state.event_buffer.requeue(unwritten, &state.config.event_buffer);
+}
+
+/// Writes out the buffer if it's due, or regardless with `all`
+pub async fn flush(state: &Arc<AppState>, all: bool) {
+    if let Some(events) = state.event_buffer.take(&state.config.event_buffer, all) {
+        write(events, state).await;
+    }
+}
+
+/// Flushes the buffer once it's due, for as long as the sandbox lives
+pub async fn flush_periodically(state: Arc<AppState>) {
+    loop {
+        tokio::time::sleep(state.config.event_buffer.max_age).await;
+        flush(&state.with_current_config().await, false).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::{test_state, Config};
+    use crate::sink::EventSink;
+    use async_trait::async_trait;
+    use lambda_http::Error;
+
+    fn event(n: usize) -> IngestEventPayload {
+        IngestEventPayload {
+            project_id: "proj".to_string(),
+            event_type: format!("e{}", n),
+            ..Default::default()
+        }
+    }
+
+    fn config() -> BufferConfig {
+        BufferConfig {
+            enabled: true,
+            max_events: 3,
+            max_age: Duration::from_secs(1),
+            max_pending: 4,
+        }
+    }
+
+    #[test]
+    fn test_flushes_once_full() {
+        let buffer = EventBuffer::default();
+        assert!(buffer.push(vec![event(1)], &config()).is_none());
+        assert!(buffer.push(vec![event(2)], &config()).is_none());
+
+        let flushed = buffer.push(vec![event(3), event(4)], &config()).unwrap();
+        assert_eq!(flushed.len(), 4);
+        assert!(buffer.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_flushes_once_the_oldest_event_is_too_old() {
+        let buffer = EventBuffer::default();
+        assert!(buffer.push(vec![event(1)], &config()).is_none());
+        tokio::time::advance(Duration::from_millis(600)).await;
+        assert!(buffer.take(&config(), false).is_none());
+
+        tokio::time::advance(Duration::from_millis(600)).await;
+        assert_eq!(buffer.take(&config(), false).unwrap().len(), 1);
+        assert!(buffer.take(&config(), true).is_none());
+    }
+
+    /// Fails every write
+    struct DownSink;
+
+    #[async_trait]
+    impl EventSink for DownSink {
+        async fn send(&self, _: Vec<IngestEventPayload>) -> Result<(), Error> {
+            Err("stream unavailable".into())
+        }
+    }
+
+    /// Writes every other event
+    struct HalfSink;
+
+    #[async_trait]
+    impl EventSink for HalfSink {
+        async fn send(&self, events: Vec<IngestEventPayload>) -> Result<(), Error> {
+            let failed = (0..events.len())
+                .filter(|position| position % 2 == 1)
+                .map(|position| (position, "ProvisionedThroughputExceededException".to_string()))
+                .collect();
+            crate::sink::all_written(failed)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_partial_flushes_keep_only_the_failed_events() {
+        let mut state = test_state(Config {
+            event_buffer: config(),
+            ..Default::default()
+        });
+        state.event_sink = Some(Arc::new(HalfSink));
+        let state = Arc::new(state);
+
+        write(vec![event(1), event(2), event(3), event(4)], &state).await;
+        let kept = state.event_buffer.take(&state.config.event_buffer, true).unwrap();
+        let names: Vec<_> = kept.iter().map(|event| event.event_type.as_str()).collect();
+        assert_eq!(names, ["e2", "e4"]);
+    }
+
+    #[tokio::test]
+    async fn test_failed_flushes_keep_the_newest_events() {
+        let mut state = test_state(Config {
+            event_buffer: config(),
+            ..Default::default()
+        });
+        state.event_sink = Some(Arc::new(DownSink));
+        let state = Arc::new(state);
+
+        write(vec![event(1), event(2), event(3)], &state).await;
+        assert_eq!(state.event_buffer.len(), 3);
+
+        let due = state.event_buffer.push(vec![event(4), event(5)], &state.config.event_buffer);
+        write(due.unwrap(), &state).await;
+        let kept = state.event_buffer.take(&state.config.event_buffer, true).unwrap();
+        let names: Vec<_> = kept.iter().map(|event| event.event_type.as_str()).collect();
+        assert_eq!(names, ["e2", "e3", "e4", "e5"]);
+    }
+}
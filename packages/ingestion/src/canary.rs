@@ -0,0 +1,151 @@
+//! End-to-end canary events.
+//!
+//! With `CANARY_ENABLED`, every sandbox writes a synthetic `$canary` event
+//! for `CANARY_PROJECT_ID` (default `__canary`) to the event sink every
+//! `CANARY_INTERVAL_MS` (default a minute) while it lives, emitting
+//! `CanaryEmitted`, or `CanaryWriteFailures` when the write fails. The
+//! event's `timestamp` is when it was written.
+//!
+//! Consumers that see a canary call [`observe`], emitting `CanaryReceived`
+//! and `CanaryLatency` (arrival time minus `timestamp`) by `Consumer`, so
+//! the two counts over a period give the pipeline's loss and the latency
+//! its end-to-end delay. Canaries go straight to the sink, skipping
+//! validation, enrichment and metering, and consumers leave them out of
+//! what they count. A consumer retrying a batch reports its canaries
+//! again, so received can briefly exceed emitted.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::metrics::{MetricSet, MetricsConfig, Unit};
+use crate::models::IngestEventPayload;
+use crate::shared::{env_flag, env_or, write_events, AppState};
+
+/// Event type of canaries
+pub const EVENT_TYPE: &str = "$canary";
+
+/// Configuration for canary events
+#[derive(Debug, Clone)]
+pub struct CanaryConfig {
+    pub enabled: bool,
+    /// Project canaries are written for, kept apart from real traffic
+    pub project_id: String,
+    /// How often each sandbox writes one
+    pub interval: Duration,
+}
+
+impl Default for CanaryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            project_id: "__canary".to_string(),
+            interval: Duration::from_secs(60),
+        }
+    }
+}
+
+impl CanaryConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            enabled: env_flag("CANARY_ENABLED"),
+            project_id: env_or("CANARY_PROJECT_ID", defaults.project_id),
+            interval: Duration::from_millis(env_or("CANARY_INTERVAL_MS", 60_000)),
+        }
+    }
+}
+
+/// A canary written at `now_ms`
+pub fn event(config: &CanaryConfig, now_ms: i64) -> IngestEventPayload {
+    IngestEventPayload {
+        project_id: config.project_id.clone(),
+        event_type: EVENT_TYPE.to_string(),
+        timestamp: now_ms,
+        ..Default::default()
+    }
+}
+
+/// Whether an event is a canary
+pub fn is_canary(event: &IngestEventPayload, config: &CanaryConfig) -> bool {
+    event.event_type == EVENT_TYPE && event.project_id == config.project_id
+}
+
+/// Reports a canary's arrival at `consumer`, returning whether the event
+/// was one
+pub fn observe(
+    event: &IngestEventPayload,
+    consumer: &str,
+    now_ms: i64,
+    config: &CanaryConfig,
+    metrics: &MetricsConfig,
+) -> bool {
+    if !is_canary(event, config) {
+        return false;
+    }
+    let latency = (now_ms - event.timestamp).max(0);
+    tracing::debug!("Canary reached {} after {}ms", consumer, latency);
+    MetricSet::new(metrics)
+        .dimension("Consumer", consumer)
+        .count("CanaryReceived", 1)
+        .metric("CanaryLatency", latency as f64, Unit::Milliseconds)
+        .emit();
+    true
+}
+
+/// Writes one canary
+pub async fn emit(state: &Arc<AppState>) {
+    let canary = event(&state.config.canary, chrono::Utc::now().timestamp_millis());
+    let metric = match write_events(vec![canary], state).await {
+        Ok(()) => "CanaryEmitted",
+        Err(e) => {
+            tracing::warn!("Failed to write a canary: {}", e);
+            "CanaryWriteFailures"
+        }
+    };
+    MetricSet::new(&state.config.metrics).count(metric, 1).emit();
+}
+
+/// Writes a canary every interval, for as long as the sandbox lives
+pub async fn emit_periodically(state: Arc<AppState>) {
+    loop {
+        tokio::time::sleep(state.config.canary.interval).await;
+        emit(&state.with_current_config().await).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::{test_state, Config};
+    use crate::sink::RecordingSink;
+
+    #[test]
+    fn test_only_the_canary_project_has_canaries() {
+        let config = CanaryConfig::default();
+        let canary = event(&config, 1_700_000_000_000);
+        assert!(is_canary(&canary, &config));
+        assert!(observe(&canary, "aggregator", 1_700_000_000_250, &config, &MetricsConfig::default()));
+
+        let impostor = IngestEventPayload {
+            project_id: "p".to_string(),
+            ..canary.clone()
+        };
+        assert!(!is_canary(&impostor, &config));
+        assert!(!observe(&impostor, "aggregator", 1_700_000_000_250, &config, &MetricsConfig::default()));
+    }
+
+    #[tokio::test]
+    async fn test_emit_writes_a_canary_to_the_sink() {
+        let mut state = test_state(Config::default());
+        let sink = Arc::new(RecordingSink::default());
+        state.event_sink = Some(sink.clone());
+        let state = Arc::new(state);
+
+        let before = chrono::Utc::now().timestamp_millis();
+        emit(&state).await;
+        let events = sink.events.lock().unwrap();
+        assert_eq!(events.len(), 1);
+        assert!(is_canary(&events[0], &state.config.canary));
+        assert!(events[0].timestamp >= before);
+    }
+}
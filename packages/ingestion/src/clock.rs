@@ -0,0 +1,84 @@
+//! Client clock handling.
+//!
+//! Events carry the client's own idea of when they happened. A `sentAt`
+//! on the event (or its batch) lets the models shift `timestamp` by the
+//! gap between the client's send time and our receive time, which corrects
+//! a device whose clock is simply wrong. What's left can still be absurd:
+//! an event from a device that never had its clock set, or one replayed
+//! from a months-old queue. With `TIMESTAMP_MAX_PAST_MS` and/or
+//! `TIMESTAMP_MAX_FUTURE_MS`, corrected timestamps further than that from
+//! server time are clamped to the bound and the event is marked
+//! `timestamp_clamped`.
+
+use crate::models::IngestEventPayload;
+use crate::shared::env_opt;
+
+/// How far from server time a client timestamp may be
+#[derive(Debug, Clone, Default)]
+pub struct TimestampBounds {
+    /// Oldest accepted age, in milliseconds
+    pub max_past_ms: Option<i64>,
+    /// Furthest accepted lead, in milliseconds
+    pub max_future_ms: Option<i64>,
+}
+
+impl TimestampBounds {
+    pub fn from_env() -> Self {
+        Self {
+            max_past_ms: env_opt("TIMESTAMP_MAX_PAST_MS"),
+            max_future_ms: env_opt("TIMESTAMP_MAX_FUTURE_MS"),
+        }
+    }
+}
+
+/// Clamps the event's timestamp into the bounds around `now`
+pub fn clamp(payload: &mut IngestEventPayload, now: i64, bounds: &TimestampBounds) {
+    let earliest = bounds.max_past_ms.map_or(i64::MIN, |max| now - max);
+    let latest = bounds.max_future_ms.map_or(i64::MAX, |max| now + max);
+    let clamped = payload.timestamp.clamp(earliest, latest.max(earliest));
+    if clamped != payload.timestamp {
+        tracing::debug!(
+            "Clamped {} event timestamp {} to {}",
+            payload.event_type,
+            payload.timestamp,
+            clamped
+        );
+        payload.timestamp = clamped;
+        payload.timestamp_clamped = Some(true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_700_000_000_000;
+    const DAY: i64 = 86_400_000;
+
+    fn clamped(timestamp: i64, bounds: &TimestampBounds) -> (i64, Option<bool>) {
+        let mut event = IngestEventPayload {
+            timestamp,
+            ..Default::default()
+        };
+        clamp(&mut event, NOW, bounds);
+        (event.timestamp, event.timestamp_clamped)
+    }
+
+    #[test]
+    fn test_clamps_far_past_and_future() {
+        let bounds = TimestampBounds {
+            max_past_ms: Some(30 * DAY),
+            max_future_ms: Some(DAY),
+        };
+        assert_eq!(clamped(NOW - DAY, &bounds), (NOW - DAY, None));
+        assert_eq!(clamped(NOW - 365 * DAY, &bounds), (NOW - 30 * DAY, Some(true)));
+        assert_eq!(clamped(NOW + 2 * DAY, &bounds), (NOW + DAY, Some(true)));
+    }
+
+    #[test]
+    fn test_unbounded_by_default() {
+        let bounds = TimestampBounds::default();
+        assert_eq!(clamped(1, &bounds), (1, None));
+        assert_eq!(clamped(NOW + 365 * DAY, &bounds), (NOW + 365 * DAY, None));
+    }
+}
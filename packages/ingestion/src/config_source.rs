@@ -0,0 +1,210 @@
+//! Config values from SSM Parameter Store and AppConfig.
+//!
+//! Every `from_env` reads through [`env_var`](crate::shared::env_var), so
+//! remote values apply as overrides of environment variables of the same
+//! name:
+//!
+//! - SSM: each parameter under `CONFIG_SSM_PATH`, so
+//!   `/ingestion/prod/RATE_LIMIT_RPS` overrides `RATE_LIMIT_RPS`
+//!   (decrypted; parameters in nested folders go by their last segment)
+//! - AppConfig: each top-level key of the JSON profile
+//!   `CONFIG_APPCONFIG_PROFILE` (`application/environment/profile`), read
+//!   from the AppConfig Lambda extension. Strings are used as they are;
+//!   other values as JSON, so list variables want a comma-separated string
+//!
+//! AppConfig wins over SSM. Values are fetched at cold start and again each
+//! time the [`ConfigCache`](crate::admin::ConfigCache) reloads, so with
+//! `CONFIG_MAX_AGE_SECS` rate limits, allowed origins and sampling rates
+//! change without a redeploy. A failed fetch keeps the previous values.
+
+use aws_config::SdkConfig;
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use lambda_http::Error;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+use crate::aws_json::AwsJsonClient;
+use crate::shared::{env_or, env_var, set_env_overrides};
+
+/// Where remote config values come from
+#[derive(Debug, Clone)]
+pub struct ConfigSources {
+    /// SSM path whose parameters override variables; unused when unset
+    pub ssm_path: Option<String>,
+    /// AppConfig `application/environment/profile`; unused when unset
+    pub appconfig_profile: Option<String>,
+    /// Port of the AppConfig Lambda extension
+    pub appconfig_port: u16,
+}
+
+impl Default for ConfigSources {
+    fn default() -> Self {
+        Self {
+            ssm_path: None,
+            appconfig_profile: None,
+            appconfig_port: 2772,
+        }
+    }
+}
+
+impl ConfigSources {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            ssm_path: env_var("CONFIG_SSM_PATH").filter(|path| !path.is_empty()),
+            appconfig_profile: env_var("CONFIG_APPCONFIG_PROFILE").filter(|profile| !profile.is_empty()),
+            appconfig_port: env_or("AWS_APPCONFIG_EXTENSION_HTTP_PORT", defaults.appconfig_port),
+        }
+    }
+}
+
+/// Fetches override values from the configured sources
+#[derive(Clone)]
+pub struct RemoteConfig {
+    ssm: Option<(AwsJsonClient, String)>,
+    appconfig: Option<(Client<HttpConnector, Empty<Bytes>>, String)>,
+}
+
+impl RemoteConfig {
+    /// A fetcher for `sources`, or `None` when none is configured
+    pub fn new(sources: &ConfigSources, aws: &SdkConfig) -> Result<Option<Self>, Error> {
+        let ssm = match sources.ssm_path {
+            Some(ref path) => Some((AwsJsonClient::new(aws, "ssm", "AmazonSSM")?, path.clone())),
+            None => None,
+        };
+        let appconfig = match sources.appconfig_profile.as_deref().map(|profile| profile.split('/').collect::<Vec<_>>()) {
+            Some(parts) => {
+                let [application, environment, profile] = parts[..] else {
+                    return Err("CONFIG_APPCONFIG_PROFILE must be application/environment/profile".into());
+                };
+                let url = format!(
+                    "http://localhost:{}/applications/{}/environments/{}/configurations/{}",
+                    sources.appconfig_port, application, environment, profile
+                );
+                Some((Client::builder(TokioExecutor::new()).build_http(), url))
+            }
+            None => None,
+        };
+        Ok((ssm.is_some() || appconfig.is_some()).then_some(Self { ssm, appconfig }))
+    }
+
+    /// Fetches the current values of all sources
+    pub async fn fetch(&self) -> Result<HashMap<String, String>, Error> {
+        let mut values = HashMap::new();
+        if let Some((ref client, ref path)) = self.ssm {
+            let mut next_token: Option<String> = None;
+            loop {
+                let mut input = json!({ "Path": path, "Recursive": true, "WithDecryption": true });
+                if let Some(token) = next_token {
+                    input["NextToken"] = json!(token);
+                }
+                let output = client.call("GetParametersByPath", &input).await?;
+                values.extend(ssm_values(path, &output));
+                next_token = output["NextToken"].as_str().map(String::from);
+                if next_token.is_none() {
+                    break;
+                }
+            }
+        }
+        if let Some((ref client, ref url)) = self.appconfig {
+            let response = client.get(url.parse()?).await?;
+            let status = response.status();
+            let body = response.into_body().collect().await?.to_bytes();
+            if !status.is_success() {
+                return Err(format!("AppConfig returned {}: {}", status, String::from_utf8_lossy(&body)).into());
+            }
+            values.extend(appconfig_values(&serde_json::from_slice(&body)?)?);
+        }
+        Ok(values)
+    }
+
+    /// Fetches and installs the values as overrides; on failure the previous
+    /// ones stay
+    pub async fn apply(&self) {
+        match self.fetch().await {
+            Ok(values) => {
+                tracing::info!("Loaded {} config values from remote sources", values.len());
+                set_env_overrides(values);
+            }
+            Err(e) => tracing::warn!("Keeping previous remote config, fetch failed: {}", e),
+        }
+    }
+}
+
+/// Variables from a `GetParametersByPath` response for `path`
+pub fn ssm_values(path: &str, output: &Value) -> HashMap<String, String> {
+    let parameters = output["Parameters"].as_array().map(Vec::as_slice).unwrap_or_default();
+    parameters
+        .iter()
+        .filter_map(|parameter| {
+            let name = parameter["Name"].as_str()?.strip_prefix(path.trim_end_matches('/'))?;
+            let name = name.strip_prefix('/')?.rsplit('/').next()?;
+            Some((name.to_string(), parameter["Value"].as_str()?.to_string())).filter(|(name, _)| !name.is_empty())
+        })
+        .collect()
+}
+
+/// Variables from an AppConfig JSON profile
+pub fn appconfig_values(document: &Value) -> Result<HashMap<String, String>, Error> {
+    let object = document.as_object().ok_or("AppConfig profile must be a JSON object")?;
+    Ok(object
+        .iter()
+        .filter(|(_, value)| !value.is_null())
+        .map(|(name, value)| {
+            let value = match value {
+                Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            (name.clone(), value)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ssm_parameters_become_variables() {
+        let output = json!({
+            "Parameters": [
+                {"Name": "/ingestion/prod/RATE_LIMIT_RPS", "Value": "250"},
+                {"Name": "/ingestion/prod/origins/ALLOWED_ORIGINS", "Value": "https://a.com,https://b.com"},
+                {"Name": "/ingestion/production/OTHER", "Value": "x"},
+            ]
+        });
+        let values = ssm_values("/ingestion/prod/", &output);
+        assert_eq!(values.len(), 2);
+        assert_eq!(values["RATE_LIMIT_RPS"], "250");
+        assert_eq!(values["ALLOWED_ORIGINS"], "https://a.com,https://b.com");
+    }
+
+    #[test]
+    fn test_appconfig_values_become_variables() {
+        let values = appconfig_values(&json!({
+            "RATE_LIMIT_RPS": 250,
+            "CLOUDEVENTS_ENABLED": true,
+            "STREAM_ROUTES": [{"project": "p", "stream": "s"}],
+            "ADMIN_TOKEN": null,
+        }))
+        .unwrap();
+        assert_eq!(values["RATE_LIMIT_RPS"], "250");
+        assert_eq!(values["CLOUDEVENTS_ENABLED"], "true");
+        assert_eq!(values["STREAM_ROUTES"], r#"[{"project":"p","stream":"s"}]"#);
+        assert!(!values.contains_key("ADMIN_TOKEN"));
+        assert!(appconfig_values(&json!([1])).is_err());
+    }
+
+    #[test]
+    fn test_overrides_take_precedence_over_the_environment() {
+        std::env::set_var("CONFIG_SOURCE_TEST_RPS", "5");
+        assert_eq!(env_or("CONFIG_SOURCE_TEST_RPS", 0), 5);
+        set_env_overrides(HashMap::from([("CONFIG_SOURCE_TEST_RPS".to_string(), "7".to_string())]));
+        assert_eq!(env_or("CONFIG_SOURCE_TEST_RPS", 0), 7);
+        set_env_overrides(HashMap::new());
+    }
+}
@@ -0,0 +1,166 @@
+//! Consent enforcement.
+//!
+//! Events may carry a `consent` object with the categories the user agreed
+//! to (`analytics`, `marketing`) and the CMP's consent string. A project with
+//! a policy lists the categories it requires; an event missing any of them
+//! (absent or `false`) is either redacted (identifiers stripped, kept for
+//! aggregate counts) or rejected with a 422. `CONSENT_POLICY` is the default
+//! policy and `CONSENT_PROJECT_POLICIES` (a JSON object of project id to
+//! policy) overrides it, e.g.
+//! `{"eu-shop": {"required": ["analytics"], "action": "reject"}}`.
+//! Projects without a policy are not checked.
+
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::enrichment::privacy_signals::strip_identifiers;
+use crate::models::{Consent, IngestEventPayload};
+use crate::shared::env_json;
+
+/// A consent category
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConsentCategory {
+    Analytics,
+    Marketing,
+}
+
+impl ConsentCategory {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Analytics => "analytics",
+            Self::Marketing => "marketing",
+        }
+    }
+
+    fn granted(&self, consent: &Consent) -> bool {
+        let granted = match self {
+            Self::Analytics => consent.analytics,
+            Self::Marketing => consent.marketing,
+        };
+        granted == Some(true)
+    }
+}
+
+/// What to do with an event lacking required consent
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConsentAction {
+    #[default]
+    Redact,
+    Reject,
+}
+
+/// A project's consent requirements
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ConsentPolicy {
+    pub required: Vec<ConsentCategory>,
+    #[serde(default)]
+    pub action: ConsentAction,
+}
+
+/// Configuration for consent enforcement
+#[derive(Debug, Clone, Default)]
+pub struct ConsentConfig {
+    pub default: Option<ConsentPolicy>,
+    pub projects: HashMap<String, ConsentPolicy>,
+}
+
+impl ConsentConfig {
+    pub fn from_env() -> Self {
+        Self {
+            default: env_json("CONSENT_POLICY"),
+            projects: env_json("CONSENT_PROJECT_POLICIES").unwrap_or_default(),
+        }
+    }
+
+    pub fn policy_for(&self, project_id: &str) -> Option<&ConsentPolicy> {
+        self.projects.get(project_id).or(self.default.as_ref())
+    }
+}
+
+/// Enforces the project's policy on a normalized event, redacting it in
+/// place or returning the rejection message
+pub fn enforce(payload: &mut IngestEventPayload, config: &ConsentConfig) -> Result<(), String> {
+    let Some(policy) = config.policy_for(&payload.project_id) else {
+        return Ok(());
+    };
+
+    let consent = payload.consent.clone().unwrap_or_default();
+    let missing: Vec<_> = policy
+        .required
+        .iter()
+        .filter(|category| !category.granted(&consent))
+        .map(ConsentCategory::name)
+        .collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    match policy.action {
+        ConsentAction::Reject => Err(format!("Missing consent for {}", missing.join(", "))),
+        ConsentAction::Redact => {
+            strip_identifiers(payload);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(project_id: &str, consent: Option<Consent>) -> IngestEventPayload {
+        IngestEventPayload {
+            project_id: project_id.to_string(),
+            user_id: Some("u1".to_string()),
+            anonymous_id: Some("a1".to_string()),
+            consent,
+            ..Default::default()
+        }
+    }
+
+    fn config() -> ConsentConfig {
+        ConsentConfig {
+            default: Some(ConsentPolicy {
+                required: vec![ConsentCategory::Analytics],
+                action: ConsentAction::Redact,
+            }),
+            projects: serde_json::from_str(
+                r#"{"ads": {"required": ["analytics", "marketing"], "action": "reject"}}"#,
+            )
+            .unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_granted_consent_passes_untouched() {
+        let consent = Consent {
+            analytics: Some(true),
+            marketing: Some(true),
+            consent_string: Some("CP1234".to_string()),
+        };
+        let mut payload = event("ads", Some(consent));
+        assert!(enforce(&mut payload, &config()).is_ok());
+        assert_eq!(payload.user_id.as_deref(), Some("u1"));
+    }
+
+    #[test]
+    fn test_missing_consent_is_redacted_or_rejected() {
+        let mut payload = event("shop", None);
+        assert!(enforce(&mut payload, &config()).is_ok());
+        assert!(payload.user_id.is_none() && payload.anonymous_id.is_none());
+
+        let analytics_only = Consent {
+            analytics: Some(true),
+            marketing: Some(false),
+            ..Default::default()
+        };
+        let mut payload = event("ads", Some(analytics_only));
+        assert_eq!(enforce(&mut payload, &config()), Err("Missing consent for marketing".to_string()));
+
+        let mut payload = event("shop", None);
+        assert!(enforce(&mut payload, &ConsentConfig::default()).is_ok());
+        assert_eq!(payload.user_id.as_deref(), Some("u1"));
+    }
+}
@@ -0,0 +1,268 @@
+//! Event deduplication by `messageId`.
+//!
+//! SDKs retry after a timeout without knowing whether the first attempt
+//! landed. With `MESSAGE_DEDUP_ENABLED`, an event carrying a `messageId`
+//! claims it (scoped to the project) in a [`MessageIdStore`] before it's
+//! written, and a repeat within `MESSAGE_DEDUP_TTL_SECS` is acknowledged
+//! without being written again. Claims of events that then fail to reach
+//! the stream are released, so the retry goes through. Events without a
+//! `messageId` are never deduplicated.
+//!
+//! The in-memory store only sees retries landing on the same sandbox and is
+//! bounded to `MESSAGE_DEDUP_MAX_ENTRIES`; set `MESSAGE_DEDUP_TABLE` for
+//! deduplication across sandboxes.
+
+use async_trait::async_trait;
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use lambda_http::Error;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::models::IngestEventPayload;
+use crate::shared::{env_flag, env_or, env_var};
+
+/// Longest `messageId` accepted as a dedup key; longer ones aren't deduplicated
+const MAX_MESSAGE_ID_LEN: usize = 256;
+
+/// Configuration for deduplication
+#[derive(Debug, Clone)]
+pub struct DedupConfig {
+    pub enabled: bool,
+    /// DynamoDB table backing the store; in-memory when unset
+    pub table_name: Option<String>,
+    /// How long a message id is remembered
+    pub ttl: Duration,
+    /// Most ids the in-memory store keeps
+    pub max_entries: usize,
+}
+
+impl Default for DedupConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            table_name: None,
+            ttl: Duration::from_secs(24 * 60 * 60),
+            max_entries: 100_000,
+        }
+    }
+}
+
+impl DedupConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            enabled: env_flag("MESSAGE_DEDUP_ENABLED"),
+            table_name: env_var("MESSAGE_DEDUP_TABLE"),
+            ttl: Duration::from_secs(env_or("MESSAGE_DEDUP_TTL_SECS", defaults.ttl.as_secs())),
+            max_entries: env_or("MESSAGE_DEDUP_MAX_ENTRIES", defaults.max_entries),
+        }
+    }
+}
+
+/// Dedup key of an event, when it has a usable `messageId`
+pub fn dedup_key(event: &IngestEventPayload) -> Option<String> {
+    let message_id = event.message_id.as_deref()?.trim();
+    (!message_id.is_empty() && message_id.len() <= MAX_MESSAGE_ID_LEN)
+        .then(|| format!("{}#{}", event.project_id, message_id))
+}
+
+/// Message ids seen recently
+#[async_trait]
+pub trait MessageIdStore: Send + Sync {
+    /// Records the key, returning `false` if it was already recorded
+    async fn claim(&self, key: &str) -> Result<bool, Error>;
+    /// Forgets a key whose event failed to be written
+    async fn release(&self, key: &str) -> Result<(), Error>;
+}
+
+/// Process-local store: ids expire after the TTL, and the oldest are
+/// evicted first once `max_entries` is reached
+pub struct InMemoryMessageIdStore {
+    ttl: Duration,
+    max_entries: usize,
+    entries: Mutex<(HashMap<String, Instant>, VecDeque<String>)>,
+}
+
+impl InMemoryMessageIdStore {
+    pub fn new(config: &DedupConfig) -> Self {
+        Self {
+            ttl: config.ttl,
+            max_entries: config.max_entries.max(1),
+            entries: Mutex::new((HashMap::new(), VecDeque::new())),
+        }
+    }
+}
+
+impl Default for InMemoryMessageIdStore {
+    fn default() -> Self {
+        Self::new(&DedupConfig::default())
+    }
+}
+
+#[async_trait]
+impl MessageIdStore for InMemoryMessageIdStore {
+    async fn claim(&self, key: &str) -> Result<bool, Error> {
+        let mut guard = self.entries.lock().unwrap();
+        let (seen, order) = &mut *guard;
+        if seen.get(key).is_some_and(|claimed_at| claimed_at.elapsed() < self.ttl) {
+            return Ok(false);
+        }
+
+        while seen.len() >= self.max_entries {
+            let Some(oldest) = order.pop_front() else {
+                break;
+            };
+            seen.remove(&oldest);
+        }
+        if seen.insert(key.to_string(), Instant::now()).is_none() {
+            order.push_back(key.to_string());
+        }
+        Ok(true)
+    }
+
+    async fn release(&self, key: &str) -> Result<(), Error> {
+        let mut guard = self.entries.lock().unwrap();
+        let (seen, order) = &mut *guard;
+        seen.remove(key);
+        order.retain(|queued| queued != key);
+        Ok(())
+    }
+}
+
+/// DynamoDB-backed store
+/// Table schema: partition key `pk` (S), TTL attribute `expires_at` (N)
+pub struct DynamoMessageIdStore {
+    client: DynamoClient,
+    table_name: String,
+    ttl: Duration,
+}
+
+impl DynamoMessageIdStore {
+    pub fn new(client: DynamoClient, table_name: String, config: &DedupConfig) -> Self {
+        Self {
+            client,
+            table_name,
+            ttl: config.ttl,
+        }
+    }
+}
+
+#[async_trait]
+impl MessageIdStore for DynamoMessageIdStore {
+    async fn claim(&self, key: &str) -> Result<bool, Error> {
+        let now = chrono::Utc::now().timestamp();
+        let claimed = self
+            .client
+            .put_item()
+            .table_name(&self.table_name)
+            .item("pk", AttributeValue::S(key.to_string()))
+            .item("expires_at", AttributeValue::N((now + self.ttl.as_secs() as i64).to_string()))
+            // DynamoDB deletes expired items lazily, so they count as absent
+            .condition_expression("attribute_not_exists(pk) OR expires_at < :now")
+            .expression_attribute_values(":now", AttributeValue::N(now.to_string()))
+            .send()
+            .await;
+        match claimed {
+            Ok(_) => Ok(true),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_conditional_check_failed_exception()) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn release(&self, key: &str) -> Result<(), Error> {
+        self.client
+            .delete_item()
+            .table_name(&self.table_name)
+            .key("pk", AttributeValue::S(key.to_string()))
+            .send()
+            .await?;
+        Ok(())
+    }
+}
+
+/// Claims the event's message id. Returns `Ok(None)` for a duplicate, and
+/// otherwise the claimed key (if any) to release should the write fail.
+pub async fn claim(
+    event: &IngestEventPayload,
+    store: &dyn MessageIdStore,
+    config: &DedupConfig,
+) -> Result<Option<Option<String>>, Error> {
+    let Some(key) = dedup_key(event).filter(|_| config.enabled) else {
+        return Ok(Some(None));
+    };
+    if store.claim(&key).await? {
+        Ok(Some(Some(key)))
+    } else {
+        tracing::info!("Skipping duplicate message {}", key);
+        Ok(None)
+    }
+}
+
+/// Releases claims after a failed write, logging (not raising) any error
+/// so the write's own error is what reaches the client
+pub async fn release_all(store: &dyn MessageIdStore, keys: &[String]) {
+    for key in keys {
+        if let Err(e) = store.release(key).await {
+            tracing::warn!("Failed to release message id {}: {}", key, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(project_id: &str, message_id: Option<&str>) -> IngestEventPayload {
+        IngestEventPayload {
+            project_id: project_id.to_string(),
+            message_id: message_id.map(String::from),
+            ..Default::default()
+        }
+    }
+
+    fn config() -> DedupConfig {
+        DedupConfig {
+            enabled: true,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_repeats_are_claimed_once_per_project() {
+        let store = InMemoryMessageIdStore::default();
+        let config = config();
+
+        let claimed = claim(&event("a", Some("m1")), &store, &config).await.unwrap();
+        assert_eq!(claimed, Some(Some("a#m1".to_string())));
+        assert_eq!(claim(&event("a", Some(" m1 ")), &store, &config).await.unwrap(), None);
+        assert!(claim(&event("b", Some("m1")), &store, &config).await.unwrap().is_some());
+        // Without an id there's nothing to deduplicate on
+        for _ in 0..2 {
+            assert_eq!(claim(&event("a", None), &store, &config).await.unwrap(), Some(None));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_released_and_expired_ids_can_be_claimed_again() {
+        let store = InMemoryMessageIdStore::new(&DedupConfig {
+            ttl: Duration::from_millis(20),
+            max_entries: 2,
+            ..config()
+        });
+
+        assert!(store.claim("a#1").await.unwrap());
+        store.release("a#1").await.unwrap();
+        assert!(store.claim("a#1").await.unwrap());
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert!(store.claim("a#1").await.unwrap());
+
+        // Bounded: the oldest id is evicted
+        assert!(store.claim("a#2").await.unwrap());
+        assert!(store.claim("a#3").await.unwrap());
+        assert!(store.claim("a#1").await.unwrap());
+        assert!(!store.claim("a#3").await.unwrap());
+    }
+}
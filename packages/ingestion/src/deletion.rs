@@ -0,0 +1,196 @@
+//! The admin route requesting a user's deletion.
+//!
+//! `DELETE /users/{userId}?projectId=…` with the `X-Admin-Token` queues a
+//! [`DeletionRequest`] on `DELETION_QUEUE_URL` and answers 202 with its
+//! `deletionId`; `packages/deletion-worker` then removes the user's events
+//! from every store and records an audit entry. Extra anonymous ids known
+//! to belong to the user can be listed in `anonymousId` (comma-separated);
+//! ids linked to the user by identity resolution are found by the worker.
+
+use async_trait::async_trait;
+use aws_sdk_sqs::Client as SqsClient;
+use lambda_http::{Body, Error, Request, Response};
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+
+use crate::admin;
+use crate::shared::{create_error_response, create_response, env_var, query_param, AppState};
+
+/// Configuration for deletion requests
+#[derive(Debug, Clone, Default)]
+pub struct DeletionConfig {
+    /// Queue the deletion worker reads; the route answers 503 without it
+    pub queue_url: Option<String>,
+}
+
+impl DeletionConfig {
+    pub fn from_env() -> Self {
+        Self {
+            queue_url: env_var("DELETION_QUEUE_URL").filter(|url| !url.is_empty()),
+        }
+    }
+}
+
+/// A request to delete everything recorded about a user in a project
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeletionRequest {
+    pub deletion_id: String,
+    pub project_id: String,
+    pub user_id: String,
+    #[serde(default)]
+    pub anonymous_ids: Vec<String>,
+    /// Epoch milliseconds
+    pub requested_at: i64,
+}
+
+/// Where deletion requests are queued
+#[async_trait]
+pub trait DeletionQueue: Send + Sync {
+    async fn enqueue(&self, request: &DeletionRequest) -> Result<(), Error>;
+}
+
+/// Queues deletion requests on SQS
+pub struct SqsDeletionQueue {
+    client: SqsClient,
+    queue_url: String,
+}
+
+impl SqsDeletionQueue {
+    pub fn new(client: SqsClient, queue_url: String) -> Self {
+        Self { client, queue_url }
+    }
+}
+
+#[async_trait]
+impl DeletionQueue for SqsDeletionQueue {
+    async fn enqueue(&self, request: &DeletionRequest) -> Result<(), Error> {
+        self.client
+            .send_message()
+            .queue_url(&self.queue_url)
+            .message_body(serde_json::to_string(request)?)
+            .send()
+            .await?;
+        Ok(())
+    }
+}
+
+/// Keeps queued requests in memory, for tests
+#[derive(Default)]
+pub struct InMemoryDeletionQueue {
+    pub requests: Mutex<Vec<DeletionRequest>>,
+}
+
+#[async_trait]
+impl DeletionQueue for InMemoryDeletionQueue {
+    async fn enqueue(&self, request: &DeletionRequest) -> Result<(), Error> {
+        self.requests.lock().unwrap().push(request.clone());
+        Ok(())
+    }
+}
+
+/// Handler for DELETE /users/{userId}
+pub async fn handle_deletion(request: &Request, user_id: String, state: &AppState) -> Result<Response<Body>, Error> {
+    if let Some(rejection) = admin::reject_unauthorized(request, state) {
+        return Ok(rejection);
+    }
+    let Some(ref queue) = state.deletion_queue else {
+        return Ok(create_error_response(503, "Deletion queue not configured"));
+    };
+    let Some(project_id) = query_param(request, "projectId").filter(|id| !id.is_empty()) else {
+        return Ok(create_error_response(400, "projectId is required"));
+    };
+    let anonymous_ids = query_param(request, "anonymousId")
+        .map(|ids| {
+            ids.split(',')
+                .map(str::trim)
+                .filter(|id| !id.is_empty())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let deletion = DeletionRequest {
+        deletion_id: uuid::Uuid::new_v4().to_string(),
+        project_id: project_id.to_string(),
+        user_id,
+        anonymous_ids,
+        requested_at: chrono::Utc::now().timestamp_millis(),
+    };
+    queue.enqueue(&deletion).await?;
+    tracing::info!("Queued deletion {} in project {}", deletion.deletion_id, deletion.project_id);
+    Ok(create_response(
+        202,
+        serde_json::json!({
+            "deletionId": deletion.deletion_id,
+            "projectId": deletion.project_id,
+            "status": "queued",
+        }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::admin::AdminConfig;
+    use crate::router::function_handler;
+    use crate::shared::{test_state, Config};
+    use lambda_http::RequestExt;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    fn request(path: &str, token: &str, query: &[(&str, &str)]) -> Request {
+        let query: HashMap<String, String> = query.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        lambda_http::http::Request::builder()
+            .method("DELETE")
+            .uri(path)
+            .header("X-Admin-Token", token)
+            .body(Body::Empty)
+            .unwrap()
+            .with_query_string_parameters(query)
+    }
+
+    fn state(queue: Arc<InMemoryDeletionQueue>) -> Arc<AppState> {
+        let mut state = test_state(Config {
+            admin: AdminConfig {
+                token: Some("s3cret".to_string()),
+                config_max_age: None,
+            },
+            ..Default::default()
+        });
+        state.deletion_queue = Some(queue);
+        Arc::new(state)
+    }
+
+    #[tokio::test]
+    async fn test_queues_a_deletion() {
+        let queue = Arc::new(InMemoryDeletionQueue::default());
+        let query = [("projectId", "p"), ("anonymousId", "a1, a2")];
+        let response = function_handler(request("/prod/users/jane%40example.com", "s3cret", &query), state(queue.clone()))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), 202);
+        let requests = queue.requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(
+            (requests[0].user_id.as_str(), requests[0].anonymous_ids.clone()),
+            ("jane@example.com", vec!["a1".to_string(), "a2".to_string()])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_needs_the_admin_token_and_a_project() {
+        let queue = Arc::new(InMemoryDeletionQueue::default());
+        let response = function_handler(request("/prod/users/u1", "wrong", &[("projectId", "p")]), state(queue.clone()))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 401);
+
+        let response = function_handler(request("/prod/users/u1", "s3cret", &[]), state(queue.clone()))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+        assert!(queue.requests.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,287 @@
+//! Typed e-commerce events.
+//!
+//! With `ECOMMERCE_VALIDATION_ENABLED`, `order_completed`, `product_added`
+//! and `checkout_started` events must carry a well-formed cart in their
+//! `properties`, so revenue numbers downstream can be trusted:
+//!
+//! - `products`: a non-empty list of `{"sku", "price", "quantity"}`;
+//!   prices are non-negative amounts (numbers or numeric strings),
+//!   quantities positive integers (1 when absent)
+//! - `currency`: an ISO 4217 code, e.g. `"eur"`, stored upper-cased
+//! - `revenue`: a non-negative amount; the products' total when absent
+//!
+//! Valid events get these properties rewritten in normalized form (numbers
+//! as numbers, other product fields kept); invalid ones are rejected with a
+//! 422 listing every violation. Other events pass untouched.
+
+use lambda_http::{Body, Response};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+use crate::models::IngestEventPayload;
+use crate::schema::Violation;
+use crate::shared::{create_response, env_flag};
+
+/// Event types with a typed cart
+pub const EVENT_TYPES: [&str; 3] = ["order_completed", "product_added", "checkout_started"];
+
+/// Configuration for e-commerce validation
+#[derive(Debug, Clone, Default)]
+pub struct EcommerceConfig {
+    pub enabled: bool,
+}
+
+impl EcommerceConfig {
+    pub fn from_env() -> Self {
+        Self {
+            enabled: env_flag("ECOMMERCE_VALIDATION_ENABLED"),
+        }
+    }
+}
+
+/// A cart line
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Product {
+    pub sku: String,
+    pub price: f64,
+    pub quantity: u32,
+    /// Other fields sent with the product (`name`, `category`, ...)
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// The typed properties of an e-commerce event
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Cart {
+    pub products: Vec<Product>,
+    pub currency: String,
+    pub revenue: f64,
+}
+
+impl Cart {
+    /// Reads a cart from event properties, listing every violation
+    pub fn parse(properties: &HashMap<String, Value>) -> Result<Self, Vec<Violation>> {
+        let mut violations = Vec::new();
+        let mut violation = |path: String, message: &str| {
+            violations.push(Violation {
+                path,
+                message: message.to_string(),
+            })
+        };
+
+        let products = match properties.get("products") {
+            Some(Value::Array(products)) if !products.is_empty() => products
+                .iter()
+                .enumerate()
+                .filter_map(|(index, product)| {
+                    let path = format!("properties.products[{}]", index);
+                    let product = Product::parse(product, &path);
+                    product.map_err(|(path, message)| violation(path, message)).ok()
+                })
+                .collect(),
+            Some(Value::Array(_)) => {
+                violation("properties.products".to_string(), "must not be empty");
+                Vec::new()
+            }
+            Some(_) => {
+                violation("properties.products".to_string(), "must be a list");
+                Vec::new()
+            }
+            None => {
+                violation("properties.products".to_string(), "is required");
+                Vec::new()
+            }
+        };
+
+        let currency = match properties.get("currency") {
+            Some(Value::String(code)) if code.len() == 3 && code.chars().all(|c| c.is_ascii_alphabetic()) => {
+                code.to_ascii_uppercase()
+            }
+            Some(_) => {
+                violation("properties.currency".to_string(), "must be a 3-letter ISO 4217 code");
+                String::new()
+            }
+            None => {
+                violation("properties.currency".to_string(), "is required");
+                String::new()
+            }
+        };
+
+        let revenue = match properties.get("revenue").filter(|v| !v.is_null()) {
+            Some(value) => amount(value).unwrap_or_else(|| {
+                violation("properties.revenue".to_string(), "must be a non-negative amount");
+                0.0
+            }),
+            None => products
+                .iter()
+                .map(|product: &Product| product.price * f64::from(product.quantity))
+                .sum(),
+        };
+
+        if !violations.is_empty() {
+            return Err(violations);
+        }
+        Ok(Self {
+            products,
+            currency,
+            revenue,
+        })
+    }
+}
+
+impl Product {
+    fn parse(value: &Value, path: &str) -> Result<Self, (String, &'static str)> {
+        let Value::Object(fields) = value else {
+            return Err((path.to_string(), "must be an object"));
+        };
+        let mut extra = fields.clone();
+
+        let sku = match extra.remove("sku") {
+            Some(Value::String(sku)) if !sku.trim().is_empty() => sku.trim().to_string(),
+            Some(_) => return Err((format!("{}.sku", path), "must be a non-empty string")),
+            None => return Err((format!("{}.sku", path), "is required")),
+        };
+        let price = match extra.remove("price") {
+            Some(price) => amount(&price).ok_or((format!("{}.price", path), "must be a non-negative amount"))?,
+            None => return Err((format!("{}.price", path), "is required")),
+        };
+        let quantity = match extra.remove("quantity") {
+            Some(quantity) => quantity
+                .as_u64()
+                .and_then(|q| u32::try_from(q).ok())
+                .filter(|q| *q > 0)
+                .ok_or((format!("{}.quantity", path), "must be a positive integer"))?,
+            None => 1,
+        };
+
+        Ok(Self {
+            sku,
+            price,
+            quantity,
+            extra,
+        })
+    }
+}
+
+/// A non-negative, finite amount, from a number or a numeric string
+fn amount(value: &Value) -> Option<f64> {
+    let amount = match value {
+        Value::Number(n) => n.as_f64()?,
+        Value::String(s) => s.trim().parse().ok()?,
+        _ => return None,
+    };
+    (amount.is_finite() && amount >= 0.0).then_some(amount)
+}
+
+/// Validates an e-commerce event and normalizes its cart in place
+pub fn normalize(payload: &mut IngestEventPayload, config: &EcommerceConfig) -> Result<(), Vec<Violation>> {
+    if !config.enabled || !EVENT_TYPES.contains(&payload.event_type.as_str()) {
+        return Ok(());
+    }
+
+    let properties = payload.properties.get_or_insert_with(Default::default);
+    let cart = Cart::parse(properties)?;
+    properties.insert("products".to_string(), serde_json::json!(cart.products));
+    properties.insert("currency".to_string(), Value::String(cart.currency));
+    properties.insert("revenue".to_string(), serde_json::json!(cart.revenue));
+    Ok(())
+}
+
+/// The 422 for an invalid e-commerce event
+pub fn violation_response(violations: &[Violation]) -> Response<Body> {
+    create_response(
+        422,
+        serde_json::json!({
+            "error": "Invalid e-commerce event",
+            "violations": violations,
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn event(event_type: &str, properties: Value) -> IngestEventPayload {
+        IngestEventPayload {
+            project_id: "shop".to_string(),
+            event_type: event_type.to_string(),
+            properties: serde_json::from_value(properties).unwrap(),
+            ..Default::default()
+        }
+    }
+
+    fn enabled() -> EcommerceConfig {
+        EcommerceConfig { enabled: true }
+    }
+
+    #[test]
+    fn test_normalizes_a_valid_cart() {
+        let mut order = event(
+            "order_completed",
+            json!({
+                "order_id": "o-1",
+                "currency": "eur",
+                "products": [
+                    {"sku": " tee-m ", "price": "19.50", "quantity": 2, "name": "Tee"},
+                    {"sku": "mug", "price": 8},
+                ],
+            }),
+        );
+        assert_eq!(normalize(&mut order, &enabled()), Ok(()));
+        assert_eq!(
+            json!(order.properties),
+            json!({
+                "order_id": "o-1",
+                "currency": "EUR",
+                "revenue": 47.0,
+                "products": [
+                    {"sku": "tee-m", "price": 19.5, "quantity": 2, "name": "Tee"},
+                    {"sku": "mug", "price": 8.0, "quantity": 1},
+                ],
+            })
+        );
+
+        // A given revenue (after tax, shipping, discounts) is kept
+        let mut checkout = event(
+            "checkout_started",
+            json!({"currency": "USD", "revenue": 12.25, "products": [{"sku": "a", "price": 10}]}),
+        );
+        assert_eq!(normalize(&mut checkout, &enabled()), Ok(()));
+        assert_eq!(checkout.properties.unwrap()["revenue"], 12.25);
+    }
+
+    #[test]
+    fn test_lists_every_violation() {
+        let mut added = event(
+            "product_added",
+            json!({
+                "currency": "euro",
+                "revenue": -1,
+                "products": [{"sku": "", "price": 1}, {"sku": "b", "price": "free"}, {"sku": "c", "price": 1, "quantity": 1.5}],
+            }),
+        );
+        let violations = normalize(&mut added, &enabled()).unwrap_err();
+        let messages: Vec<_> = violations.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            messages,
+            [
+                "properties.products[0].sku: must be a non-empty string",
+                "properties.products[1].price: must be a non-negative amount",
+                "properties.products[2].quantity: must be a positive integer",
+                "properties.currency: must be a 3-letter ISO 4217 code",
+                "properties.revenue: must be a non-negative amount",
+            ]
+        );
+
+        let mut empty = event("order_completed", Value::Null);
+        let violations = normalize(&mut empty, &enabled()).unwrap_err();
+        assert_eq!(violations.len(), 2);
+
+        // Other events, and everything when disabled, pass untouched
+        assert_eq!(normalize(&mut event("pageview", Value::Null), &enabled()), Ok(()));
+        assert_eq!(normalize(&mut event("order_completed", Value::Null), &EcommerceConfig::default()), Ok(()));
+    }
+}
@@ -0,0 +1,285 @@
+//! Bot and crawler filtering.
+//!
+//! Where [`bot_score`](super::bot_score) grades how suspicious an event is,
+//! this makes a yes/no call from strong signals and acts on it. An event is a
+//! bot when its user agent carries a crawler or automation marker, when it
+//! comes from a headless browser, or when its IP falls in a configured
+//! datacenter range. `BOT_FILTER_ACTION` then decides what happens:
+//!
+//! - `flag` (default): keep the event with `context.isBot = true`
+//! - `drop`: discard it
+//! - `route`: flag it and write it to `BOT_STREAM_NAME` instead of the main
+//!   stream, so crawler traffic stays inspectable without inflating counts.
+//!   Projects pinned to a residency zone keep their zone's stream.
+
+use lambda_http::Request;
+use std::net::IpAddr;
+
+use crate::enrichment::bot_score::BOT_UA_MARKERS;
+use crate::models::IngestEventPayload;
+use crate::shared::{env_flag, env_list, env_opt, env_or, header_value};
+
+/// Fragments only headless browsers put in their user agent or client hints
+const HEADLESS_MARKERS: &[&str] = &["headlesschrome", "headless", "phantomjs", "electron/"];
+
+/// What to do with an event detected as a bot
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BotAction {
+    #[default]
+    Flag,
+    Drop,
+    Route,
+}
+
+impl std::str::FromStr for BotAction {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "flag" => Ok(Self::Flag),
+            "drop" => Ok(Self::Drop),
+            "route" => Ok(Self::Route),
+            other => Err(format!("unknown bot action \"{}\"", other)),
+        }
+    }
+}
+
+/// An IP network in CIDR notation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl std::str::FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (network, prefix) = value.trim().split_once('/').unwrap_or((value.trim(), ""));
+        let network: IpAddr = network.parse().map_err(|_| format!("invalid network \"{}\"", value))?;
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            "" => max,
+            prefix => prefix
+                .parse()
+                .ok()
+                .filter(|prefix| *prefix <= max)
+                .ok_or_else(|| format!("invalid prefix in \"{}\"", value))?,
+        };
+        Ok(Self { network, prefix })
+    }
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let (network, ip, bits) = match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => (u32::from(network) as u128, u32::from(ip) as u128, 32),
+            (IpAddr::V6(network), IpAddr::V6(ip)) => (u128::from(network), u128::from(ip), 128),
+            _ => return false,
+        };
+        let shift = bits - u32::from(self.prefix);
+        shift >= bits || (network >> shift) == (ip >> shift)
+    }
+}
+
+/// Configuration for bot filtering
+#[derive(Debug, Clone)]
+pub struct BotFilterConfig {
+    pub enabled: bool,
+    pub action: BotAction,
+    /// Lowercase user-agent fragments that mark a crawler or automation tool
+    pub user_agent_markers: Vec<String>,
+    /// Hosting and cloud provider ranges that real visitors don't browse from
+    pub datacenter_ranges: Vec<Cidr>,
+    /// Stream for bot traffic under [`BotAction::Route`]
+    pub stream_name: Option<String>,
+}
+
+impl Default for BotFilterConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            action: BotAction::default(),
+            user_agent_markers: BOT_UA_MARKERS.iter().map(|marker| marker.to_string()).collect(),
+            datacenter_ranges: Vec::new(),
+            stream_name: None,
+        }
+    }
+}
+
+impl BotFilterConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let markers = env_list("BOT_FILTER_USER_AGENTS");
+        let datacenter_ranges = env_list("BOT_FILTER_DATACENTER_CIDRS")
+            .iter()
+            .filter_map(|range| match range.parse() {
+                Ok(cidr) => Some(cidr),
+                Err(e) => {
+                    tracing::warn!("Ignoring BOT_FILTER_DATACENTER_CIDRS entry: {}", e);
+                    None
+                }
+            })
+            .collect();
+        Self {
+            enabled: env_flag("BOT_FILTER_ENABLED"),
+            action: env_or("BOT_FILTER_ACTION", defaults.action),
+            user_agent_markers: if markers.is_empty() {
+                defaults.user_agent_markers
+            } else {
+                markers.iter().map(|marker| marker.to_ascii_lowercase()).collect()
+            },
+            datacenter_ranges,
+            stream_name: env_opt("BOT_STREAM_NAME"),
+        }
+    }
+
+    /// Stream flagged events are written to, when routing them
+    pub fn bot_stream(&self) -> Option<&str> {
+        self.stream_name
+            .as_deref()
+            .filter(|_| self.enabled && self.action == BotAction::Route)
+    }
+}
+
+/// Why an event was taken for a bot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BotReason {
+    UserAgent,
+    Headless,
+    Datacenter,
+}
+
+/// Checks the request and event against the configured signals
+pub fn detect(payload: &IngestEventPayload, request: &Request, config: &BotFilterConfig) -> Option<BotReason> {
+    let context = payload.context.as_ref();
+    let user_agent = context
+        .and_then(|c| c.user_agent.as_deref())
+        .or_else(|| header_value(request, "user-agent"))
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    let client_hints = header_value(request, "sec-ch-ua").unwrap_or("").to_ascii_lowercase();
+
+    if HEADLESS_MARKERS
+        .iter()
+        .any(|marker| user_agent.contains(marker) || client_hints.contains(marker))
+    {
+        return Some(BotReason::Headless);
+    }
+
+    if config.user_agent_markers.iter().any(|marker| user_agent.contains(marker.as_str())) {
+        return Some(BotReason::UserAgent);
+    }
+
+    let ip = context
+        .and_then(|c| c.ip.as_deref())
+        .and_then(|ip| ip.trim().parse::<IpAddr>().ok());
+    if ip.is_some_and(|ip| config.datacenter_ranges.iter().any(|range| range.contains(ip))) {
+        return Some(BotReason::Datacenter);
+    }
+
+    None
+}
+
+/// Flags a detected bot, or returns `false` when it should be dropped
+pub fn apply(payload: &mut IngestEventPayload, request: &Request, config: &BotFilterConfig) -> bool {
+    let Some(reason) = detect(payload, request, config) else {
+        return true;
+    };
+
+    if config.action == BotAction::Drop {
+        tracing::info!("Dropping bot event ({:?})", reason);
+        return false;
+    }
+
+    payload.context.get_or_insert_with(Default::default).is_bot = Some(true);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::EventContext;
+    use lambda_http::Body;
+
+    fn request(headers: &[(&str, &str)]) -> Request {
+        let mut builder = lambda_http::http::Request::builder();
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        builder.body(Body::Empty).unwrap()
+    }
+
+    fn payload(ip: &str) -> IngestEventPayload {
+        IngestEventPayload {
+            context: Some(EventContext {
+                ip: Some(ip.to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn config(action: BotAction) -> BotFilterConfig {
+        BotFilterConfig {
+            enabled: true,
+            action,
+            datacenter_ranges: vec!["3.0.0.0/9".parse().unwrap(), "2600:1f00::/24".parse().unwrap()],
+            ..Default::default()
+        }
+    }
+
+    const CHROME: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) Chrome/120.0.0.0 Safari/537.36";
+
+    #[test]
+    fn test_detects_each_signal() {
+        let config = config(BotAction::Flag);
+        let detect = |ip: &str, headers: &[(&str, &str)]| detect(&payload(ip), &request(headers), &config);
+
+        assert_eq!(detect("81.2.69.142", &[("User-Agent", CHROME)]), None);
+        assert_eq!(
+            detect("81.2.69.142", &[("User-Agent", "Mozilla/5.0 (compatible; bingbot/2.0)")]),
+            Some(BotReason::UserAgent)
+        );
+        assert_eq!(
+            detect("81.2.69.142", &[("User-Agent", CHROME), ("Sec-CH-UA", "\"HeadlessChrome\";v=\"120\"")]),
+            Some(BotReason::Headless)
+        );
+        assert_eq!(detect("3.120.0.1", &[("User-Agent", CHROME)]), Some(BotReason::Datacenter));
+        assert_eq!(detect("2600:1f18::1", &[("User-Agent", CHROME)]), Some(BotReason::Datacenter));
+        assert_eq!(detect("3.200.0.1", &[("User-Agent", CHROME)]), None);
+    }
+
+    #[test]
+    fn test_actions() {
+        let bot = request(&[("User-Agent", "curl/8.4.0")]);
+
+        let mut event = payload("81.2.69.142");
+        assert!(apply(&mut event, &bot, &config(BotAction::Flag)));
+        assert_eq!(event.context.unwrap().is_bot, Some(true));
+
+        let mut event = payload("81.2.69.142");
+        assert!(!apply(&mut event, &bot, &config(BotAction::Drop)));
+
+        let mut event = payload("81.2.69.142");
+        assert!(apply(&mut event, &request(&[("User-Agent", CHROME)]), &config(BotAction::Drop)));
+        assert_eq!(event.context.unwrap().is_bot, None);
+
+        let routing = BotFilterConfig {
+            stream_name: Some("bots".to_string()),
+            ..config(BotAction::Route)
+        };
+        assert_eq!(routing.bot_stream(), Some("bots"));
+        assert_eq!(config(BotAction::Flag).bot_stream(), None);
+    }
+
+    #[test]
+    fn test_cidr_parsing() {
+        assert!("10.0.0.0/8".parse::<Cidr>().unwrap().contains("10.1.2.3".parse().unwrap()));
+        assert!("0.0.0.0/0".parse::<Cidr>().unwrap().contains("8.8.8.8".parse().unwrap()));
+        assert!("1.2.3.4".parse::<Cidr>().unwrap().contains("1.2.3.4".parse().unwrap()));
+        assert!(!"10.0.0.0/8".parse::<Cidr>().unwrap().contains("::1".parse().unwrap()));
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("nope/8".parse::<Cidr>().is_err());
+    }
+}
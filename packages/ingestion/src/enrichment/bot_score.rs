@@ -0,0 +1,264 @@
+//! Bot suspicion scoring.
+//!
+//! Rather than a binary bot flag, several weak signals are combined into a
+//! weighted 0-100 `bot_score`. Whether to drop an event is a separate,
+//! configurable threshold so analysts can tune aggressiveness downstream.
+
+use lambda_http::Request;
+
+use crate::models::IngestEventPayload;
+use crate::shared::{env_flag, env_opt, env_or};
+
+/// User-agent fragments that identify automated clients
+pub(crate) const BOT_UA_MARKERS: &[&str] = &[
+    "bot",
+    "crawl",
+    "spider",
+    "slurp",
+    "headless",
+    "phantomjs",
+    "selenium",
+    "puppeteer",
+    "playwright",
+    "curl/",
+    "wget/",
+    "python-requests",
+    "python-urllib",
+    "go-http-client",
+    "okhttp",
+    "node-fetch",
+    "libwww-perl",
+    "scrapy",
+];
+
+/// Screen dimensions outside this range don't exist on real devices
+const MIN_SCREEN_DIMENSION: u32 = 100;
+const MAX_SCREEN_DIMENSION: u32 = 10_000;
+
+/// Configuration for bot scoring
+#[derive(Debug, Clone)]
+pub struct BotScoreConfig {
+    pub enabled: bool,
+    /// Weight of a user agent matching a known automation marker
+    pub user_agent_weight: u32,
+    /// Weight of missing headers every browser sends
+    pub missing_headers_weight: u32,
+    /// Weight of an impossible screen size
+    pub screen_weight: u32,
+    /// Weight of a client clock far away from server time
+    pub timing_weight: u32,
+    /// Events scoring at or above this are dropped; `None` keeps everything
+    pub drop_threshold: Option<u8>,
+    /// Maximum tolerated distance between client and server time, in ms
+    pub max_clock_drift_ms: i64,
+}
+
+impl Default for BotScoreConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            user_agent_weight: 50,
+            missing_headers_weight: 20,
+            screen_weight: 15,
+            timing_weight: 15,
+            drop_threshold: None,
+            max_clock_drift_ms: 24 * 60 * 60 * 1000,
+        }
+    }
+}
+
+impl BotScoreConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            enabled: env_flag("BOT_SCORE_ENABLED"),
+            user_agent_weight: env_or("BOT_SCORE_WEIGHT_USER_AGENT", defaults.user_agent_weight),
+            missing_headers_weight: env_or(
+                "BOT_SCORE_WEIGHT_MISSING_HEADERS",
+                defaults.missing_headers_weight,
+            ),
+            screen_weight: env_or("BOT_SCORE_WEIGHT_SCREEN", defaults.screen_weight),
+            timing_weight: env_or("BOT_SCORE_WEIGHT_TIMING", defaults.timing_weight),
+            drop_threshold: env_opt("BOT_SCORE_DROP_THRESHOLD"),
+            max_clock_drift_ms: env_or("BOT_SCORE_MAX_CLOCK_DRIFT_MS", defaults.max_clock_drift_ms),
+        }
+    }
+
+    /// Computes the weighted score for a set of signals, capped at 100
+    pub fn score(&self, signals: &BotSignals) -> u8 {
+        let weighted = [
+            (signals.suspicious_user_agent, self.user_agent_weight),
+            (signals.missing_headers, self.missing_headers_weight),
+            (signals.impossible_screen, self.screen_weight),
+            (signals.implausible_timing, self.timing_weight),
+        ];
+
+        let total: u32 = weighted
+            .iter()
+            .filter(|(hit, _)| *hit)
+            .map(|(_, weight)| *weight)
+            .sum();
+
+        total.min(100) as u8
+    }
+
+    /// Whether an event with this score should be dropped
+    pub fn should_drop(&self, score: u8) -> bool {
+        self.drop_threshold.is_some_and(|threshold| score >= threshold)
+    }
+}
+
+/// Individual bot heuristics observed on a request
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BotSignals {
+    pub suspicious_user_agent: bool,
+    pub missing_headers: bool,
+    pub impossible_screen: bool,
+    pub implausible_timing: bool,
+}
+
+impl BotSignals {
+    /// Collects signals from the request headers and the enriched payload
+    pub fn collect(payload: &IngestEventPayload, request: &Request, config: &BotScoreConfig) -> Self {
+        let headers = request.headers();
+        let user_agent = headers
+            .get("user-agent")
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or("")
+            .to_ascii_lowercase();
+
+        let suspicious_user_agent = BOT_UA_MARKERS.iter().any(|marker| user_agent.contains(marker));
+
+        // Every browser sends both of these with fetch and sendBeacon
+        let missing_headers = user_agent.is_empty() || !headers.contains_key("accept-language");
+
+        let screen = payload.context.as_ref().and_then(|c| c.screen.as_ref());
+        let impossible_screen = match screen {
+            Some(screen) => [screen.width, screen.height].iter().any(|dimension| {
+                dimension.is_some_and(|d| !(MIN_SCREEN_DIMENSION..=MAX_SCREEN_DIMENSION).contains(&d))
+            }),
+            None => false,
+        };
+
+        let received_at = payload.context.as_ref().and_then(|c| c.received_at);
+        let implausible_timing = received_at
+            .is_some_and(|received| (received - payload.timestamp).abs() > config.max_clock_drift_ms);
+
+        Self {
+            suspicious_user_agent,
+            missing_headers,
+            impossible_screen,
+            implausible_timing,
+        }
+    }
+}
+
+/// Stamps `bot_score` onto the payload when enabled.
+/// Returns `false` when the score crosses the drop threshold.
+pub fn apply(payload: &mut IngestEventPayload, request: &Request, config: &BotScoreConfig) -> bool {
+    if !config.enabled {
+        return true;
+    }
+
+    let signals = BotSignals::collect(payload, request, config);
+    let score = config.score(&signals);
+    payload.bot_score = Some(score);
+
+    if config.should_drop(score) {
+        tracing::info!("Dropping event with bot score {} ({:?})", score, signals);
+        return false;
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{EventContext, ScreenContext};
+    use lambda_http::Body;
+
+    fn payload(width: u32, height: u32, timestamp: i64, received_at: i64) -> IngestEventPayload {
+        IngestEventPayload {
+            timestamp,
+            context: Some(EventContext {
+                screen: Some(ScreenContext {
+                    width: Some(width),
+                    height: Some(height),
+                }),
+                received_at: Some(received_at),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_clearly_bot_scores_100() {
+        let config = BotScoreConfig {
+            enabled: true,
+            drop_threshold: Some(80),
+            ..Default::default()
+        };
+        let request = lambda_http::http::Request::builder()
+            .header("user-agent", "Mozilla/5.0 (compatible; Googlebot/2.1)")
+            .body(Body::Empty)
+            .unwrap();
+        let event = payload(0, 0, 0, 1767348474997);
+
+        let signals = BotSignals::collect(&event, &request, &config);
+        assert_eq!(
+            signals,
+            BotSignals {
+                suspicious_user_agent: true,
+                missing_headers: true,
+                impossible_screen: true,
+                implausible_timing: true,
+            }
+        );
+        assert_eq!(config.score(&signals), 100);
+
+        let mut event = event;
+        assert!(!apply(&mut event, &request, &config));
+        assert_eq!(event.bot_score, Some(100));
+    }
+
+    #[test]
+    fn test_clearly_human_scores_0() {
+        let config = BotScoreConfig {
+            enabled: true,
+            drop_threshold: Some(80),
+            ..Default::default()
+        };
+        let request = lambda_http::http::Request::builder()
+            .header(
+                "user-agent",
+                "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 Chrome/120.0",
+            )
+            .header("accept-language", "en-US,en;q=0.9")
+            .body(Body::Empty)
+            .unwrap();
+        let mut event = payload(1920, 1080, 1767348474000, 1767348474997);
+
+        assert_eq!(BotSignals::collect(&event, &request, &config), BotSignals::default());
+        assert!(apply(&mut event, &request, &config));
+        assert_eq!(event.bot_score, Some(0));
+    }
+
+    #[test]
+    fn test_weights_are_configurable() {
+        let config = BotScoreConfig {
+            enabled: true,
+            missing_headers_weight: 70,
+            ..Default::default()
+        };
+        let signals = BotSignals {
+            missing_headers: true,
+            impossible_screen: true,
+            ..Default::default()
+        };
+
+        assert_eq!(config.score(&signals), 85);
+        assert!(!config.should_drop(85));
+    }
+}
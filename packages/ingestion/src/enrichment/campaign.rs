@@ -0,0 +1,165 @@
+//! Campaign attribution for pageviews.
+//!
+//! Reads `utm_source`, `utm_medium`, `utm_campaign`, `utm_term`,
+//! `utm_content`, `gclid` and `fbclid` from the page url into
+//! `context.campaign`, so consumers don't each re-parse urls. Parameters
+//! missing from the page url are taken from the referrer's, which catches
+//! tagged links that redirect before the page loads. Fields the client
+//! already sent in `context.campaign` are kept.
+
+use url::Url;
+
+use crate::enrichment::duplicate_view::page_url;
+use crate::models::{CampaignContext, IngestEventPayload};
+use crate::shared::env_flag;
+
+/// Configuration for campaign attribution
+#[derive(Debug, Clone, Default)]
+pub struct CampaignConfig {
+    pub enabled: bool,
+}
+
+impl CampaignConfig {
+    pub fn from_env() -> Self {
+        Self {
+            enabled: env_flag("CAMPAIGN_ATTRIBUTION_ENABLED"),
+        }
+    }
+}
+
+/// Campaign parameters of a url; empty for urls that don't parse
+pub fn parse(url: &str) -> CampaignContext {
+    let mut campaign = CampaignContext::default();
+    let Ok(url) = Url::parse(url) else {
+        return campaign;
+    };
+    for (key, value) in url.query_pairs() {
+        let value = value.trim();
+        if value.is_empty() {
+            continue;
+        }
+        let field = match key.to_ascii_lowercase().as_str() {
+            "utm_source" => &mut campaign.source,
+            "utm_medium" => &mut campaign.medium,
+            "utm_campaign" => &mut campaign.name,
+            "utm_term" => &mut campaign.term,
+            "utm_content" => &mut campaign.content,
+            "gclid" => &mut campaign.gclid,
+            "fbclid" => &mut campaign.fbclid,
+            _ => continue,
+        };
+        // The first occurrence wins
+        field.get_or_insert_with(|| value.to_string());
+    }
+    campaign
+}
+
+/// Fills the fields `campaign` lacks from `other`
+pub(crate) fn fill(campaign: &mut CampaignContext, other: CampaignContext) {
+    for (field, value) in [
+        (&mut campaign.source, other.source),
+        (&mut campaign.medium, other.medium),
+        (&mut campaign.name, other.name),
+        (&mut campaign.term, other.term),
+        (&mut campaign.content, other.content),
+        (&mut campaign.gclid, other.gclid),
+        (&mut campaign.fbclid, other.fbclid),
+    ] {
+        if field.is_none() {
+            *field = value;
+        }
+    }
+}
+
+/// Stamps `context.campaign` on pageviews with campaign parameters
+pub fn apply(payload: &mut IngestEventPayload) {
+    if payload.event_type != "pageview" {
+        return;
+    }
+    let mut found = page_url(payload).map(parse).unwrap_or_default();
+    let referrer = payload
+        .context
+        .as_ref()
+        .and_then(|c| c.page.as_ref())
+        .and_then(|p| p.referrer.as_deref());
+    fill(&mut found, referrer.map(parse).unwrap_or_default());
+    if found == CampaignContext::default() {
+        return;
+    }
+
+    let context = payload.context.get_or_insert_with(Default::default);
+    let campaign = context.campaign.get_or_insert_with(Default::default);
+    fill(campaign, found);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{EventContext, PageContext};
+
+    fn pageview(url: &str, referrer: Option<&str>, campaign: Option<CampaignContext>) -> IngestEventPayload {
+        IngestEventPayload {
+            event_type: "pageview".to_string(),
+            context: Some(EventContext {
+                page: Some(PageContext {
+                    url: Some(url.to_string()),
+                    referrer: referrer.map(String::from),
+                    ..Default::default()
+                }),
+                campaign,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn campaign(payload: IngestEventPayload) -> Option<CampaignContext> {
+        payload.context.unwrap().campaign
+    }
+
+    #[test]
+    fn test_parses_utm_and_click_ids() {
+        let parsed = parse(
+            "https://shop.io/?UTM_Source=google&utm_medium=cpc&utm_campaign=spring%20sale\
+             &utm_term=shoes&utm_content=hero&gclid=abc&fbclid=&utm_source=bing",
+        );
+        assert_eq!(parsed.source.as_deref(), Some("google"));
+        assert_eq!(parsed.name.as_deref(), Some("spring sale"));
+        assert_eq!(parsed.term.as_deref(), Some("shoes"));
+        assert_eq!(parsed.content.as_deref(), Some("hero"));
+        assert_eq!(parsed.gclid.as_deref(), Some("abc"));
+        assert_eq!(parsed.fbclid, None);
+        assert_eq!(parse("not a url"), CampaignContext::default());
+    }
+
+    #[test]
+    fn test_referrer_and_client_fields_fill_gaps() {
+        let mut event = pageview(
+            "https://shop.io/?utm_source=newsletter",
+            Some("https://links.shop.io/r?utm_source=other&utm_medium=email&fbclid=f1"),
+            Some(CampaignContext {
+                name: Some("spring".to_string()),
+                source: Some("client".to_string()),
+                ..Default::default()
+            }),
+        );
+        apply(&mut event);
+        let campaign = campaign(event).unwrap();
+        assert_eq!(campaign.source.as_deref(), Some("client"));
+        assert_eq!(campaign.medium.as_deref(), Some("email"));
+        assert_eq!(campaign.name.as_deref(), Some("spring"));
+        assert_eq!(campaign.fbclid.as_deref(), Some("f1"));
+    }
+
+    #[test]
+    fn test_untagged_and_non_pageview_events_untouched() {
+        let mut event = pageview("https://shop.io/", Some("https://google.com/"), None);
+        apply(&mut event);
+        assert_eq!(campaign(event), None);
+
+        let mut event = pageview("https://shop.io/?utm_source=x", None, None);
+        event.event_type = "signup".to_string();
+        apply(&mut event);
+        assert_eq!(campaign(event), None);
+    }
+}
@@ -0,0 +1,289 @@
+//! Traffic channel classification for pageviews.
+//!
+//! Derives a single `channel` from the page url's UTM parameters and the
+//! referrer, loosely following GA4's default channel grouping. Rules are
+//! checked in order and the first match wins:
+//!
+//! 1. **Direct**: no UTM parameters, no click id and no external referrer
+//!    (a referrer on the page's own host counts as none).
+//! 2. **Paid Search**: a paid-search click id, or a search-engine source
+//!    with a paid medium.
+//! 3. **Email**: an email source or medium.
+//! 4. **Social**: a social-network source or referrer, or a social medium.
+//! 5. **Organic Search**: a search-engine source or referrer, or the
+//!    `organic` medium.
+//! 6. **Referral**: anything else with a source or referrer.
+//!
+//! The source is `utm_source` when present, otherwise the referrer's host.
+//! Domain lists match the host or any subdomain; an entry ending in `.`
+//! (`google.`) matches any TLD. Medium patterns may start or end with `*`.
+
+use url::Url;
+
+use crate::enrichment::duplicate_view::page_url;
+use crate::models::IngestEventPayload;
+use crate::shared::{env_flag, env_list};
+
+const DEFAULT_SEARCH_ENGINES: &[&str] = &[
+    "baidu.com",
+    "bing.com",
+    "duckduckgo.com",
+    "ecosia.org",
+    "google.",
+    "search.brave.com",
+    "yahoo.",
+    "yandex.",
+];
+
+const DEFAULT_SOCIAL_NETWORKS: &[&str] = &[
+    "facebook.com",
+    "instagram.com",
+    "linkedin.com",
+    "lnkd.in",
+    "pinterest.com",
+    "reddit.com",
+    "t.co",
+    "tiktok.com",
+    "twitter.com",
+    "x.com",
+    "youtube.com",
+];
+
+const DEFAULT_PAID_MEDIUMS: &[&str] = &["cpc", "ppc", "paid*", "retargeting"];
+const DEFAULT_EMAIL_MEDIUMS: &[&str] = &["email", "e-mail", "e_mail", "newsletter"];
+const DEFAULT_SOCIAL_MEDIUMS: &[&str] = &["social", "social-media", "social-network", "sm"];
+const DEFAULT_PAID_CLICK_IDS: &[&str] = &["gclid", "msclkid"];
+
+/// Configuration for channel classification
+#[derive(Debug, Clone)]
+pub struct ChannelConfig {
+    pub enabled: bool,
+    pub search_engines: Vec<String>,
+    pub social_networks: Vec<String>,
+    pub paid_mediums: Vec<String>,
+    pub email_mediums: Vec<String>,
+    pub social_mediums: Vec<String>,
+    /// Query parameters that mark an ad click on a search engine
+    pub paid_click_ids: Vec<String>,
+}
+
+fn owned(values: &[&str]) -> Vec<String> {
+    values.iter().map(|v| v.to_string()).collect()
+}
+
+impl Default for ChannelConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            search_engines: owned(DEFAULT_SEARCH_ENGINES),
+            social_networks: owned(DEFAULT_SOCIAL_NETWORKS),
+            paid_mediums: owned(DEFAULT_PAID_MEDIUMS),
+            email_mediums: owned(DEFAULT_EMAIL_MEDIUMS),
+            social_mediums: owned(DEFAULT_SOCIAL_MEDIUMS),
+            paid_click_ids: owned(DEFAULT_PAID_CLICK_IDS),
+        }
+    }
+}
+
+impl ChannelConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let list_or = |key: &str, default: Vec<String>| {
+            let list = env_list(key);
+            if list.is_empty() { default } else { list }
+        };
+        Self {
+            enabled: env_flag("CHANNEL_CLASSIFICATION_ENABLED"),
+            search_engines: list_or("CHANNEL_SEARCH_ENGINES", defaults.search_engines),
+            social_networks: list_or("CHANNEL_SOCIAL_NETWORKS", defaults.social_networks),
+            paid_mediums: list_or("CHANNEL_PAID_MEDIUMS", defaults.paid_mediums),
+            email_mediums: list_or("CHANNEL_EMAIL_MEDIUMS", defaults.email_mediums),
+            social_mediums: list_or("CHANNEL_SOCIAL_MEDIUMS", defaults.social_mediums),
+            paid_click_ids: list_or("CHANNEL_PAID_CLICK_IDS", defaults.paid_click_ids),
+        }
+    }
+}
+
+/// Whether a host (or bare `utm_source` like `google`) is in a domain list
+pub(crate) fn in_domains(host: &str, domains: &[String]) -> bool {
+    let host = host.trim_start_matches("www.");
+    domains.iter().any(|domain| {
+        let domain = domain.to_ascii_lowercase();
+        match domain.strip_suffix('.') {
+            Some(name) => {
+                host == name || host.starts_with(&domain) || host.contains(&format!(".{}", domain))
+            }
+            None => {
+                host == domain
+                    || host.ends_with(&format!(".{}", domain))
+                    || domain.split('.').next() == Some(host)
+            }
+        }
+    })
+}
+
+/// Whether a value matches any pattern (`*` allowed at either end)
+fn matches_any(value: &str, patterns: &[String]) -> bool {
+    patterns.iter().any(|pattern| {
+        let pattern = pattern.to_ascii_lowercase();
+        match (pattern.strip_prefix('*'), pattern.strip_suffix('*')) {
+            (Some(rest), _) if rest.ends_with('*') => value.contains(rest.trim_end_matches('*')),
+            (Some(suffix), _) => value.ends_with(suffix),
+            (_, Some(prefix)) => value.starts_with(prefix),
+            _ => value == pattern,
+        }
+    })
+}
+
+/// Channel for a landing url and referrer
+pub fn classify(url: Option<&str>, referrer: Option<&str>, config: &ChannelConfig) -> &'static str {
+    let page = url.and_then(|u| Url::parse(u).ok());
+    let param = |name: &str| {
+        page.as_ref()?
+            .query_pairs()
+            .find(|(key, value)| key == name && !value.is_empty())
+            .map(|(_, value)| value.to_ascii_lowercase())
+    };
+    let source = param("utm_source");
+    let medium = param("utm_medium");
+    let campaign = param("utm_campaign");
+    let click_id = config.paid_click_ids.iter().any(|id| param(id).is_some());
+
+    let page_host = page.as_ref().and_then(|u| u.host_str()).map(str::to_ascii_lowercase);
+    let referrer_host = referrer
+        .and_then(|r| Url::parse(r).ok())
+        .and_then(|r| r.host_str().map(str::to_ascii_lowercase))
+        .filter(|host| Some(host) != page_host.as_ref());
+
+    if source.is_none() && medium.is_none() && campaign.is_none() && !click_id && referrer_host.is_none() {
+        return "Direct";
+    }
+
+    let source = source.or(referrer_host.clone()).unwrap_or_default();
+    let medium = medium.unwrap_or_default();
+    let from_search = in_domains(&source, &config.search_engines);
+
+    if click_id || (from_search && matches_any(&medium, &config.paid_mediums)) {
+        return "Paid Search";
+    }
+    if matches_any(&source, &config.email_mediums) || matches_any(&medium, &config.email_mediums) {
+        return "Email";
+    }
+    if in_domains(&source, &config.social_networks) || matches_any(&medium, &config.social_mediums) {
+        return "Social";
+    }
+    if from_search || medium == "organic" {
+        return "Organic Search";
+    }
+    if !source.is_empty() || medium == "referral" {
+        return "Referral";
+    }
+    "Direct"
+}
+
+/// Stamps `channel` on pageviews
+pub fn apply(payload: &mut IngestEventPayload, config: &ChannelConfig) {
+    if payload.event_type != "pageview" {
+        return;
+    }
+    let referrer = payload
+        .context
+        .as_ref()
+        .and_then(|c| c.page.as_ref())
+        .and_then(|p| p.referrer.as_deref());
+    payload.channel = Some(classify(page_url(payload), referrer, config).to_string());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel(url: &str, referrer: Option<&str>) -> &'static str {
+        classify(Some(url), referrer, &ChannelConfig::default())
+    }
+
+    #[test]
+    fn test_direct() {
+        assert_eq!(channel("https://shop.io/", None), "Direct");
+        assert_eq!(channel("https://shop.io/cart", Some("https://shop.io/")), "Direct");
+    }
+
+    #[test]
+    fn test_paid_search() {
+        assert_eq!(
+            channel("https://shop.io/?utm_source=google&utm_medium=cpc", None),
+            "Paid Search"
+        );
+        assert_eq!(channel("https://shop.io/?gclid=abc", Some("https://www.google.com/")), "Paid Search");
+        assert_eq!(
+            channel("https://shop.io/?utm_medium=paidsearch", Some("https://www.bing.com/")),
+            "Paid Search"
+        );
+    }
+
+    #[test]
+    fn test_organic_search() {
+        assert_eq!(channel("https://shop.io/", Some("https://www.google.de/")), "Organic Search");
+        assert_eq!(channel("https://shop.io/", Some("https://duckduckgo.com/")), "Organic Search");
+        assert_eq!(
+            channel("https://shop.io/?utm_source=newsite&utm_medium=organic", None),
+            "Organic Search"
+        );
+    }
+
+    #[test]
+    fn test_social() {
+        assert_eq!(channel("https://shop.io/", Some("https://t.co/xyz")), "Social");
+        assert_eq!(channel("https://shop.io/", Some("https://m.facebook.com/")), "Social");
+        assert_eq!(
+            channel("https://shop.io/?utm_source=mastodon&utm_medium=social", None),
+            "Social"
+        );
+    }
+
+    #[test]
+    fn test_email() {
+        assert_eq!(
+            channel("https://shop.io/?utm_source=weekly&utm_medium=email", None),
+            "Email"
+        );
+        assert_eq!(
+            channel("https://shop.io/?utm_source=newsletter&utm_campaign=spring", None),
+            "Email"
+        );
+    }
+
+    #[test]
+    fn test_referral() {
+        assert_eq!(channel("https://shop.io/", Some("https://blog.example.org/post")), "Referral");
+        assert_eq!(channel("https://shop.io/?utm_source=partner", None), "Referral");
+    }
+
+    #[test]
+    fn test_configured_patterns() {
+        let config = ChannelConfig {
+            search_engines: vec!["kagi.com".to_string()],
+            paid_mediums: vec!["*ads*".to_string()],
+            ..Default::default()
+        };
+        let classify = |url: &str, referrer| classify(Some(url), referrer, &config);
+
+        assert_eq!(classify("https://shop.io/", Some("https://kagi.com/search")), "Organic Search");
+        assert_eq!(classify("https://shop.io/?utm_source=kagi&utm_medium=textads", None), "Paid Search");
+        assert_eq!(classify("https://shop.io/", Some("https://www.google.com/")), "Referral");
+    }
+
+    #[test]
+    fn test_only_pageviews_are_stamped() {
+        let mut event = IngestEventPayload {
+            event_type: "signup".to_string(),
+            ..Default::default()
+        };
+        apply(&mut event, &ChannelConfig::default());
+        assert_eq!(event.channel, None);
+
+        event.event_type = "pageview".to_string();
+        apply(&mut event, &ChannelConfig::default());
+        assert_eq!(event.channel.as_deref(), Some("Direct"));
+    }
+}
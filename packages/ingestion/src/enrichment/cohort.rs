@@ -0,0 +1,178 @@
+//! Privacy-safe cohort buckets.
+//!
+//! Places each user in one of N buckets by hashing their id, which is
+//! enough for approximate funnels without tracking individuals. In strict
+//! mode every identifier is removed afterwards (user, anonymous, previous,
+//! resolved, visitor and group ids, the IP and user agent, and identify
+//! traits) so only `cohort_bucket` is stored. Runs last, after the
+//! per-user stores have used the ids.
+//!
+//! `COHORT_SALT` is required: unsalted buckets can be recomputed from a
+//! known id, which would tie a bucket back to a person.
+
+use sha2::{Digest, Sha256};
+
+use super::privacy_signals::strip_identifiers;
+use crate::models::IngestEventPayload;
+use crate::shared::{env_flag, env_or, env_var};
+
+/// Configuration for cohort bucketing
+#[derive(Debug, Clone)]
+pub struct CohortConfig {
+    pub enabled: bool,
+    /// Number of buckets; ids map to `0..buckets`
+    pub buckets: u32,
+    /// Remove every identifier once bucketed
+    pub strict: bool,
+    pub salt: String,
+}
+
+impl Default for CohortConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            buckets: 100,
+            strict: false,
+            salt: String::new(),
+        }
+    }
+}
+
+impl CohortConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            enabled: env_flag("COHORT_BUCKETS_ENABLED"),
+            buckets: env_or("COHORT_BUCKET_COUNT", defaults.buckets).max(1),
+            strict: env_flag("COHORT_STRICT_PRIVACY"),
+            salt: env_var("COHORT_SALT").unwrap_or_default(),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.enabled && self.salt.is_empty() {
+            return Err("COHORT_SALT is required when COHORT_BUCKETS_ENABLED is set".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Bucket for an id within a project
+pub fn bucket(salt: &str, project_id: &str, id: &str, buckets: u32) -> u32 {
+    let digest = Sha256::new()
+        .chain_update(salt.as_bytes())
+        .chain_update(b":")
+        .chain_update(project_id.as_bytes())
+        .chain_update(b":")
+        .chain_update(id.as_bytes())
+        .finalize();
+    let prefix = u64::from_be_bytes(digest[..8].try_into().expect("digest is 32 bytes"));
+    (prefix % u64::from(buckets.max(1))) as u32
+}
+
+/// Stamps `cohort_bucket`, stripping identifiers in strict mode
+pub fn apply(payload: &mut IngestEventPayload, config: &CohortConfig) {
+    let id = payload.user_id.as_deref().or(payload.anonymous_id.as_deref());
+    if let Some(id) = id {
+        payload.cohort_bucket = Some(bucket(&config.salt, &payload.project_id, id, config.buckets));
+    }
+
+    if config.strict {
+        strip_identifiers(payload);
+        payload.daily_visitor_id = None;
+        payload.group_id = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::EventContext;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    fn event(user_id: &str) -> IngestEventPayload {
+        IngestEventPayload {
+            project_id: "proj".to_string(),
+            user_id: Some(user_id.to_string()),
+            anonymous_id: Some("anon-1".to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn config(strict: bool) -> CohortConfig {
+        CohortConfig {
+            enabled: true,
+            buckets: 16,
+            strict,
+            salt: "pepper".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_bucket_is_stable_and_in_range() {
+        let mut first = event("u-42");
+        let mut second = event("u-42");
+        apply(&mut first, &config(false));
+        apply(&mut second, &config(false));
+
+        assert_eq!(first.cohort_bucket, second.cohort_bucket);
+        assert!(first.cohort_bucket.unwrap() < 16);
+        assert_eq!(first.user_id.as_deref(), Some("u-42"));
+
+        let buckets: std::collections::HashSet<u32> = (0..200)
+            .map(|i| bucket("pepper", "proj", &format!("u-{}", i), 16))
+            .collect();
+        assert!(buckets.len() > 8, "ids should spread across buckets");
+    }
+
+    #[test]
+    fn test_strict_mode_removes_every_identifier() {
+        let mut event = IngestEventPayload {
+            previous_id: Some("guest-7".to_string()),
+            resolved_user_id: Some("u-1".to_string()),
+            daily_visitor_id: Some("dv-1".to_string()),
+            group_id: Some("acme".to_string()),
+            traits: Some(HashMap::from([("email".to_string(), json!("jane@shop.io"))])),
+            traits_set_once: Some(HashMap::from([("first_name".to_string(), json!("Jane"))])),
+            context: Some(EventContext {
+                ip: Some("203.0.113.7".to_string()),
+                user_agent: Some("Mozilla/5.0".to_string()),
+                locale: Some("en-US".to_string()),
+                ..Default::default()
+            }),
+            ..event("u-42")
+        };
+        apply(&mut event, &config(true));
+
+        assert!(event.cohort_bucket.is_some());
+        assert_eq!(event.user_id, None);
+        assert_eq!(event.anonymous_id, None);
+        assert_eq!(event.previous_id, None);
+        assert_eq!(event.resolved_user_id, None);
+        assert_eq!(event.daily_visitor_id, None);
+        assert_eq!(event.group_id, None);
+        assert_eq!(event.traits, None);
+        assert_eq!(event.traits_set_once, None);
+        let context = event.context.as_ref().unwrap();
+        assert_eq!(context.ip, None);
+        assert_eq!(context.user_agent, None);
+        assert_eq!(context.locale.as_deref(), Some("en-US"));
+
+        let json = serde_json::to_value(&event).unwrap();
+        for field in ["userId", "anonymousId", "previousId", "resolvedUserId", "groupId", "traits"] {
+            assert!(json.get(field).is_none(), "{}", field);
+        }
+    }
+
+    #[test]
+    fn test_buckets_require_a_salt() {
+        assert!(config(true).validate().is_ok());
+        let unsalted = CohortConfig {
+            salt: String::new(),
+            ..config(false)
+        };
+        assert!(unsalted.validate().is_err());
+        assert!(CohortConfig::default().validate().is_ok());
+    }
+}
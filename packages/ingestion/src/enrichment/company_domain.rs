@@ -0,0 +1,125 @@
+//! Company domain resolution for account-level rollups.
+//!
+//! Takes the domain of the first valid email found under the configured
+//! property keys and stamps it as `company_domain`, unless it belongs to a
+//! free-email provider. Runs before identity hashing replaces the email.
+
+use crate::enrichment::identity_hash::normalize_email;
+use crate::models::IngestEventPayload;
+use crate::shared::{env_flag, env_list};
+
+/// Consumer mailbox providers that say nothing about the employer
+const DEFAULT_FREE_EMAIL_DOMAINS: &[&str] = &[
+    "aol.com",
+    "gmail.com",
+    "gmx.de",
+    "gmx.net",
+    "googlemail.com",
+    "hotmail.com",
+    "icloud.com",
+    "live.com",
+    "mail.com",
+    "me.com",
+    "msn.com",
+    "outlook.com",
+    "proton.me",
+    "protonmail.com",
+    "qq.com",
+    "web.de",
+    "yahoo.com",
+    "yandex.ru",
+];
+
+/// Configuration for company domain resolution
+#[derive(Debug, Clone)]
+pub struct CompanyDomainConfig {
+    pub enabled: bool,
+    /// Property keys that may hold an email, checked in order
+    pub email_keys: Vec<String>,
+    pub free_email_domains: Vec<String>,
+}
+
+impl Default for CompanyDomainConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            email_keys: vec!["email".to_string()],
+            free_email_domains: DEFAULT_FREE_EMAIL_DOMAINS.iter().map(|d| d.to_string()).collect(),
+        }
+    }
+}
+
+impl CompanyDomainConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let email_keys = env_list("COMPANY_DOMAIN_EMAIL_KEYS");
+        let free_email_domains = env_list("FREE_EMAIL_DOMAINS");
+        Self {
+            enabled: env_flag("COMPANY_DOMAIN_ENABLED"),
+            email_keys: if email_keys.is_empty() { defaults.email_keys } else { email_keys },
+            free_email_domains: if free_email_domains.is_empty() {
+                defaults.free_email_domains
+            } else {
+                free_email_domains
+            },
+        }
+    }
+}
+
+/// Company domain for an email, `None` for invalid or free-email addresses
+pub fn company_domain(email: &str, config: &CompanyDomainConfig) -> Option<String> {
+    let email = normalize_email(email)?;
+    let (_, domain) = email.rsplit_once('@')?;
+    let free = config
+        .free_email_domains
+        .iter()
+        .any(|free| free.eq_ignore_ascii_case(domain));
+    (!free).then(|| domain.to_string())
+}
+
+/// Stamps `company_domain` from the first configured email property
+pub fn apply(payload: &mut IngestEventPayload, config: &CompanyDomainConfig) {
+    let Some(ref properties) = payload.properties else {
+        return;
+    };
+
+    let email = config
+        .email_keys
+        .iter()
+        .find_map(|key| properties.get(key).and_then(|value| value.as_str()));
+    payload.company_domain = email.and_then(|email| company_domain(email, config));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn resolve(email: &str) -> Option<String> {
+        let mut payload = IngestEventPayload {
+            properties: Some(HashMap::from([(
+                "email".to_string(),
+                serde_json::json!(email),
+            )])),
+            ..Default::default()
+        };
+        apply(&mut payload, &CompanyDomainConfig::default());
+        payload.company_domain
+    }
+
+    #[test]
+    fn test_corporate_email() {
+        assert_eq!(resolve("Jane.Doe@Acme.io").as_deref(), Some("acme.io"));
+    }
+
+    #[test]
+    fn test_free_email_provider() {
+        assert_eq!(resolve("jane.doe@gmail.com"), None);
+    }
+
+    #[test]
+    fn test_malformed_email() {
+        assert_eq!(resolve("jane.doe at acme.io"), None);
+        assert_eq!(resolve("jane@localhost"), None);
+    }
+}
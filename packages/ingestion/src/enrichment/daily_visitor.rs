@@ -0,0 +1,154 @@
+//! Cookieless daily visitor ids.
+//!
+//! Derives `daily_visitor_id` from the client IP and user agent hashed with a
+//! salt that rotates at UTC midnight. The id is stable for a visitor within
+//! a day but cannot be linked across days, and stands in as `anonymousId`
+//! when the client sent none.
+
+use sha2::{Digest, Sha256};
+
+use crate::models::IngestEventPayload;
+use crate::shared::{env_flag, env_var};
+
+const DAY_MS: i64 = 86_400_000;
+
+/// Configuration for daily visitor ids
+#[derive(Debug, Clone, Default)]
+pub struct DailyVisitorConfig {
+    pub enabled: bool,
+    /// Secret mixed into each day's salt so ids can't be recomputed offline
+    pub secret: String,
+}
+
+impl DailyVisitorConfig {
+    pub fn from_env() -> Self {
+        Self {
+            enabled: env_flag("DAILY_VISITOR_ID_ENABLED"),
+            secret: env_var("DAILY_VISITOR_ID_SECRET").unwrap_or_default(),
+        }
+    }
+
+    /// Without a secret, ids are a public function of IP, user agent and
+    /// date that anyone can recompute
+    pub fn validate(&self) -> Result<(), String> {
+        if self.enabled && self.secret.is_empty() {
+            return Err("DAILY_VISITOR_ID_SECRET is required when DAILY_VISITOR_ID_ENABLED is set".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Salt for the UTC day containing `now_ms`
+fn daily_salt(secret: &str, now_ms: i64) -> [u8; 32] {
+    let day = now_ms.div_euclid(DAY_MS);
+    Sha256::new()
+        .chain_update(secret.as_bytes())
+        .chain_update(b":")
+        .chain_update(day.to_be_bytes())
+        .finalize()
+        .into()
+}
+
+/// Visitor id for an IP/user agent pair within one project and day
+pub fn visitor_id(secret: &str, now_ms: i64, project_id: &str, ip: &str, user_agent: &str) -> String {
+    let digest = Sha256::new()
+        .chain_update(daily_salt(secret, now_ms))
+        .chain_update(project_id.as_bytes())
+        .chain_update(b"\0")
+        .chain_update(ip.as_bytes())
+        .chain_update(b"\0")
+        .chain_update(user_agent.as_bytes())
+        .finalize();
+    hex::encode(&digest[..16])
+}
+
+/// Stamps `daily_visitor_id` and fills a missing `anonymous_id` with it
+pub fn apply(payload: &mut IngestEventPayload, config: &DailyVisitorConfig) {
+    let Some(context) = payload.context.as_ref() else {
+        return;
+    };
+    let Some(ip) = context.ip.as_deref().filter(|ip| !ip.is_empty()) else {
+        return;
+    };
+    let user_agent = context.user_agent.as_deref().unwrap_or_default();
+    let now = context.received_at.unwrap_or(payload.timestamp);
+
+    let id = visitor_id(&config.secret, now, &payload.project_id, ip, user_agent);
+    if payload.anonymous_id.is_none() {
+        payload.anonymous_id = Some(id.clone());
+    }
+    payload.daily_visitor_id = Some(id);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::EventContext;
+
+    const NOON: i64 = 1_700_000_000_000 / DAY_MS * DAY_MS + DAY_MS / 2;
+
+    fn config() -> DailyVisitorConfig {
+        DailyVisitorConfig {
+            enabled: true,
+            secret: "s3cret".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_ids_require_a_secret() {
+        assert!(config().validate().is_ok());
+        let public = DailyVisitorConfig {
+            secret: String::new(),
+            ..config()
+        };
+        assert!(public.validate().is_err());
+    }
+
+    fn payload(ip: &str, received_at: i64) -> IngestEventPayload {
+        IngestEventPayload {
+            project_id: "proj".to_string(),
+            context: Some(EventContext {
+                ip: Some(ip.to_string()),
+                user_agent: Some("Mozilla/5.0".to_string()),
+                received_at: Some(received_at),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_stable_within_a_day() {
+        let mut morning = payload("203.0.113.7", NOON - DAY_MS / 2 + 1);
+        let mut evening = payload("203.0.113.7", NOON + DAY_MS / 2 - 1);
+        let mut other = payload("198.51.100.1", NOON);
+        apply(&mut morning, &config());
+        apply(&mut evening, &config());
+        apply(&mut other, &config());
+
+        assert!(morning.daily_visitor_id.is_some());
+        assert_eq!(morning.daily_visitor_id, evening.daily_visitor_id);
+        assert_ne!(morning.daily_visitor_id, other.daily_visitor_id);
+        assert_eq!(morning.anonymous_id, morning.daily_visitor_id);
+    }
+
+    #[test]
+    fn test_rotates_at_utc_midnight() {
+        let mut before = payload("203.0.113.7", NOON + DAY_MS / 2 - 1);
+        let mut after = payload("203.0.113.7", NOON + DAY_MS / 2);
+        apply(&mut before, &config());
+        apply(&mut after, &config());
+
+        assert_ne!(before.daily_visitor_id, after.daily_visitor_id);
+    }
+
+    #[test]
+    fn test_client_anonymous_id_is_kept() {
+        let mut event = payload("203.0.113.7", NOON);
+        event.anonymous_id = Some("anon-1".to_string());
+        apply(&mut event, &config());
+
+        assert_eq!(event.anonymous_id.as_deref(), Some("anon-1"));
+        assert!(event.daily_visitor_id.is_some());
+    }
+}
@@ -0,0 +1,259 @@
+//! Collapsing of consecutive identical pageviews.
+//!
+//! Reloads and SPA re-renders produce bursts of pageviews for the same url.
+//! Each session's last pageview is kept in a [`LastPageviewStore`]; a
+//! pageview repeating that url within the window is tagged
+//! `is_duplicate_view: true`, or dropped outright when configured.
+
+use async_trait::async_trait;
+use aws_sdk_dynamodb::types::{AttributeValue, ReturnValue};
+use aws_sdk_dynamodb::Client as DynamoClient;
+use lambda_http::Error;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::models::IngestEventPayload;
+use crate::shared::{env_flag, env_or, env_var};
+
+/// Configuration for duplicate pageview collapsing
+#[derive(Debug, Clone)]
+pub struct DuplicateViewConfig {
+    pub enabled: bool,
+    /// Repeats closer together than this count as duplicates
+    pub window_ms: i64,
+    /// Drop duplicates instead of tagging them
+    pub drop: bool,
+    /// DynamoDB table backing the store; in-memory when unset
+    pub table_name: Option<String>,
+}
+
+impl Default for DuplicateViewConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            window_ms: 2_000,
+            drop: false,
+            table_name: None,
+        }
+    }
+}
+
+impl DuplicateViewConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            enabled: env_flag("DUPLICATE_VIEW_COLLAPSE_ENABLED"),
+            window_ms: env_or("DUPLICATE_VIEW_WINDOW_MS", defaults.window_ms),
+            drop: env_flag("DUPLICATE_VIEW_DROP"),
+            table_name: env_var("LAST_PAGEVIEW_TABLE"),
+        }
+    }
+}
+
+/// A session's most recent pageview
+#[derive(Debug, Clone, PartialEq)]
+pub struct LastPageview {
+    pub url: String,
+    pub timestamp: i64,
+}
+
+/// Per-session last-pageview store
+#[async_trait]
+pub trait LastPageviewStore: Send + Sync {
+    /// Stores `pageview` for `key`, returning the one it replaced
+    async fn replace(&self, key: &str, pageview: LastPageview) -> Result<Option<LastPageview>, Error>;
+    async fn get(&self, key: &str) -> Result<Option<LastPageview>, Error>;
+}
+
+/// Process-local store, used in tests and when no table is configured
+#[derive(Debug, Default)]
+pub struct InMemoryLastPageviewStore {
+    entries: Mutex<HashMap<String, LastPageview>>,
+}
+
+#[async_trait]
+impl LastPageviewStore for InMemoryLastPageviewStore {
+    async fn replace(&self, key: &str, pageview: LastPageview) -> Result<Option<LastPageview>, Error> {
+        Ok(self.entries.lock().unwrap().insert(key.to_string(), pageview))
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<LastPageview>, Error> {
+        Ok(self.entries.lock().unwrap().get(key).cloned())
+    }
+}
+
+/// DynamoDB-backed store
+/// Table schema: partition key `pk` (S), attributes `url` (S), `ts` (N)
+pub struct DynamoLastPageviewStore {
+    client: DynamoClient,
+    table_name: String,
+}
+
+impl DynamoLastPageviewStore {
+    pub fn new(client: DynamoClient, table_name: String) -> Self {
+        Self { client, table_name }
+    }
+}
+
+/// The pageview stored in an item
+pub fn pageview_attributes(item: Option<&HashMap<String, AttributeValue>>) -> Option<LastPageview> {
+    let item = item?;
+    Some(LastPageview {
+        url: item.get("url")?.as_s().ok()?.clone(),
+        timestamp: item.get("ts")?.as_n().ok()?.parse().ok()?,
+    })
+}
+
+#[async_trait]
+impl LastPageviewStore for DynamoLastPageviewStore {
+    async fn replace(&self, key: &str, pageview: LastPageview) -> Result<Option<LastPageview>, Error> {
+        let output = self
+            .client
+            .put_item()
+            .table_name(&self.table_name)
+            .item("pk", AttributeValue::S(key.to_string()))
+            .item("url", AttributeValue::S(pageview.url))
+            .item("ts", AttributeValue::N(pageview.timestamp.to_string()))
+            .return_values(ReturnValue::AllOld)
+            .send()
+            .await?;
+        Ok(pageview_attributes(output.attributes()))
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<LastPageview>, Error> {
+        let output = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("pk", AttributeValue::S(key.to_string()))
+            .consistent_read(true)
+            .send()
+            .await?;
+        Ok(pageview_attributes(output.item()))
+    }
+}
+
+/// Session key: an explicit `session_id` property, else the visitor
+pub fn session_key(payload: &IngestEventPayload) -> Option<String> {
+    let session = payload
+        .properties
+        .as_ref()
+        .and_then(|p| p.get("session_id"))
+        .and_then(|v| v.as_str())
+        .or(payload.anonymous_id.as_deref())
+        .or(payload.user_id.as_deref())?;
+    Some(format!("{}#{}", payload.project_id, session))
+}
+
+/// The key `apply` looks up for this event, if any
+pub fn lookup_key(payload: &IngestEventPayload) -> Option<String> {
+    if payload.event_type != "pageview" || page_url(payload).is_none() {
+        return None;
+    }
+    session_key(payload)
+}
+
+/// Page url from the context, falling back to a `url` property
+pub fn page_url(payload: &IngestEventPayload) -> Option<&str> {
+    payload
+        .context
+        .as_ref()
+        .and_then(|c| c.page.as_ref())
+        .and_then(|p| p.url.as_deref())
+        .or_else(|| payload.properties.as_ref()?.get("url")?.as_str())
+}
+
+/// Tags (or, with `drop`, rejects) repeated pageviews.
+/// Returns `false` when the event should be dropped.
+pub async fn apply(
+    payload: &mut IngestEventPayload,
+    store: &dyn LastPageviewStore,
+    config: &DuplicateViewConfig,
+) -> bool {
+    if payload.event_type != "pageview" {
+        return true;
+    }
+    let (Some(key), Some(url)) = (session_key(payload), page_url(payload)) else {
+        return true;
+    };
+    let current = LastPageview {
+        url: url.to_string(),
+        timestamp: payload.timestamp,
+    };
+
+    let previous = match store.replace(&key, current.clone()).await {
+        Ok(previous) => previous,
+        Err(e) => {
+            tracing::warn!("Failed to update last-pageview store: {}", e);
+            return true;
+        }
+    };
+
+    let duplicate = previous.is_some_and(|previous| {
+        previous.url == current.url && (current.timestamp - previous.timestamp).abs() <= config.window_ms
+    });
+    if !duplicate {
+        return true;
+    }
+    if config.drop {
+        return false;
+    }
+
+    payload.is_duplicate_view = Some(true);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pageview(url: &str, timestamp: i64) -> IngestEventPayload {
+        IngestEventPayload {
+            project_id: "proj".to_string(),
+            event_type: "pageview".to_string(),
+            anonymous_id: Some("anon".to_string()),
+            timestamp,
+            properties: Some(HashMap::from([("url".to_string(), serde_json::json!(url))])),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_repeat_within_window_is_tagged_or_dropped() {
+        let store = InMemoryLastPageviewStore::default();
+        let config = DuplicateViewConfig {
+            enabled: true,
+            ..Default::default()
+        };
+
+        let mut first = pageview("https://a.io/pricing", 10_000);
+        let mut reload = pageview("https://a.io/pricing", 11_500);
+        assert!(apply(&mut first, &store, &config).await);
+        assert!(apply(&mut reload, &store, &config).await);
+        assert_eq!(first.is_duplicate_view, None);
+        assert_eq!(reload.is_duplicate_view, Some(true));
+
+        let dropping = DuplicateViewConfig { drop: true, ..config };
+        let mut again = pageview("https://a.io/pricing", 12_000);
+        assert!(!apply(&mut again, &store, &dropping).await);
+    }
+
+    #[tokio::test]
+    async fn test_repeat_outside_window_or_other_url_is_kept() {
+        let store = InMemoryLastPageviewStore::default();
+        let config = DuplicateViewConfig {
+            enabled: true,
+            drop: true,
+            ..Default::default()
+        };
+
+        let mut first = pageview("https://a.io/pricing", 10_000);
+        let mut later = pageview("https://a.io/pricing", 30_000);
+        let mut elsewhere = pageview("https://a.io/docs", 30_500);
+        assert!(apply(&mut first, &store, &config).await);
+        assert!(apply(&mut later, &store, &config).await);
+        assert!(apply(&mut elsewhere, &store, &config).await);
+        assert_eq!(later.is_duplicate_view, None);
+        assert_eq!(elsewhere.is_duplicate_view, None);
+    }
+}
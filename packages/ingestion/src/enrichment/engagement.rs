@@ -0,0 +1,281 @@
+//! Engagement time from heartbeat events.
+//!
+//! SDKs send a `heartbeat` event every `heartbeat_interval_ms` while the
+//! page is in use. Each heartbeat adds the time since the session's previous
+//! one, capped at the interval, to a per-session total kept in an
+//! [`EngagementStore`]; the first heartbeat, or one arriving after more than
+//! `max_gap_ms` of silence, adds a single interval. Every event of the
+//! session is stamped with the running `engaged_time_ms`.
+
+use async_trait::async_trait;
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use lambda_http::Error;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::enrichment::duplicate_view::session_key;
+use crate::models::IngestEventPayload;
+use crate::shared::{env_flag, env_or, env_var};
+
+/// Event type of engagement heartbeats
+pub const HEARTBEAT: &str = "heartbeat";
+
+/// Attempts at updating an accumulator that is changing concurrently
+const MAX_SWAP_ATTEMPTS: usize = 3;
+
+/// Configuration for engagement tracking
+#[derive(Debug, Clone)]
+pub struct EngagementConfig {
+    pub enabled: bool,
+    /// DynamoDB table backing the store; in-memory when unset
+    pub table_name: Option<String>,
+    /// How often SDKs send heartbeats; the most one heartbeat can add
+    pub heartbeat_interval_ms: i64,
+    /// Silence after which the user is considered to have left
+    pub max_gap_ms: i64,
+}
+
+impl Default for EngagementConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            table_name: None,
+            heartbeat_interval_ms: 15_000,
+            max_gap_ms: 60_000,
+        }
+    }
+}
+
+impl EngagementConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            enabled: env_flag("ENGAGEMENT_TRACKING_ENABLED"),
+            table_name: env_var("ENGAGEMENT_TABLE"),
+            heartbeat_interval_ms: env_or(
+                "ENGAGEMENT_HEARTBEAT_INTERVAL_MS",
+                defaults.heartbeat_interval_ms,
+            ),
+            max_gap_ms: env_or("ENGAGEMENT_MAX_GAP_MS", defaults.max_gap_ms),
+        }
+    }
+}
+
+/// A session's accumulated engagement
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Engagement {
+    pub engaged_ms: i64,
+    pub last_heartbeat: i64,
+}
+
+/// Per-session engagement accumulator store
+#[async_trait]
+pub trait EngagementStore: Send + Sync {
+    async fn get(&self, key: &str) -> Result<Option<Engagement>, Error>;
+    /// Stores `new` if the session's accumulator still equals `expected`.
+    /// Returns `false` when it was changed concurrently.
+    async fn swap(&self, key: &str, expected: Option<Engagement>, new: Engagement) -> Result<bool, Error>;
+}
+
+/// Process-local store, used in tests and when no table is configured
+#[derive(Debug, Default)]
+pub struct InMemoryEngagementStore {
+    entries: Mutex<HashMap<String, Engagement>>,
+}
+
+#[async_trait]
+impl EngagementStore for InMemoryEngagementStore {
+    async fn get(&self, key: &str) -> Result<Option<Engagement>, Error> {
+        Ok(self.entries.lock().unwrap().get(key).copied())
+    }
+
+    async fn swap(&self, key: &str, expected: Option<Engagement>, new: Engagement) -> Result<bool, Error> {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.get(key).copied() != expected {
+            return Ok(false);
+        }
+        entries.insert(key.to_string(), new);
+        Ok(true)
+    }
+}
+
+/// DynamoDB-backed store
+/// Table schema: partition key `pk` (S), attributes `engaged_ms` (N) and
+/// `last_heartbeat` (N)
+pub struct DynamoEngagementStore {
+    client: DynamoClient,
+    table_name: String,
+}
+
+impl DynamoEngagementStore {
+    pub fn new(client: DynamoClient, table_name: String) -> Self {
+        Self { client, table_name }
+    }
+}
+
+/// The accumulator stored in an item
+pub fn engagement_attributes(item: Option<&HashMap<String, AttributeValue>>) -> Option<Engagement> {
+    let number = |name: &str| -> Option<i64> { item?.get(name)?.as_n().ok()?.parse().ok() };
+    number("engaged_ms").zip(number("last_heartbeat")).map(|(engaged_ms, last_heartbeat)| Engagement {
+        engaged_ms,
+        last_heartbeat,
+    })
+}
+
+#[async_trait]
+impl EngagementStore for DynamoEngagementStore {
+    async fn get(&self, key: &str) -> Result<Option<Engagement>, Error> {
+        let output = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("pk", AttributeValue::S(key.to_string()))
+            .consistent_read(true)
+            .send()
+            .await?;
+        Ok(engagement_attributes(output.item()))
+    }
+
+    async fn swap(&self, key: &str, expected: Option<Engagement>, new: Engagement) -> Result<bool, Error> {
+        let mut request = self
+            .client
+            .put_item()
+            .table_name(&self.table_name)
+            .item("pk", AttributeValue::S(key.to_string()))
+            .item("engaged_ms", AttributeValue::N(new.engaged_ms.to_string()))
+            .item("last_heartbeat", AttributeValue::N(new.last_heartbeat.to_string()));
+        request = match expected {
+            None => request.condition_expression("attribute_not_exists(pk)"),
+            Some(expected) => request
+                .condition_expression("engaged_ms = :engaged AND last_heartbeat = :last")
+                .expression_attribute_values(":engaged", AttributeValue::N(expected.engaged_ms.to_string()))
+                .expression_attribute_values(":last", AttributeValue::N(expected.last_heartbeat.to_string())),
+        };
+
+        match request.send().await {
+            Ok(_) => Ok(true),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_conditional_check_failed_exception()) => {
+                Ok(false)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Accumulator after a heartbeat at `timestamp`; `None` for duplicate or
+/// out-of-order heartbeats, which change nothing
+pub fn accumulate(
+    previous: Option<Engagement>,
+    timestamp: i64,
+    config: &EngagementConfig,
+) -> Option<Engagement> {
+    let interval = config.heartbeat_interval_ms;
+    let credit = match previous {
+        None => interval,
+        Some(previous) if timestamp <= previous.last_heartbeat => return None,
+        Some(previous) => match timestamp - previous.last_heartbeat {
+            gap if gap > config.max_gap_ms => interval,
+            gap => gap.min(interval),
+        },
+    };
+    Some(Engagement {
+        engaged_ms: previous.map_or(0, |p| p.engaged_ms) + credit,
+        last_heartbeat: timestamp,
+    })
+}
+
+/// Adds a heartbeat to the session's total, returning the new total
+async fn record_heartbeat(
+    store: &dyn EngagementStore,
+    key: &str,
+    timestamp: i64,
+    config: &EngagementConfig,
+) -> Result<i64, Error> {
+    for _ in 0..MAX_SWAP_ATTEMPTS {
+        let previous = store.get(key).await?;
+        let Some(next) = accumulate(previous, timestamp, config) else {
+            return Ok(previous.map_or(0, |p| p.engaged_ms));
+        };
+        if store.swap(key, previous, next).await? {
+            return Ok(next.engaged_ms);
+        }
+    }
+    Err(format!("Engagement for {} kept changing concurrently", key).into())
+}
+
+/// The key `apply` looks up for this event: its session
+pub fn lookup_key(payload: &IngestEventPayload) -> Option<String> {
+    session_key(payload)
+}
+
+/// Counts heartbeats and stamps `engaged_time_ms` on the session's events
+pub async fn apply(
+    payload: &mut IngestEventPayload,
+    store: &dyn EngagementStore,
+    config: &EngagementConfig,
+) {
+    let Some(key) = lookup_key(payload) else {
+        return;
+    };
+
+    let total = if payload.event_type == HEARTBEAT {
+        record_heartbeat(store, &key, payload.timestamp, config).await
+    } else {
+        store
+            .get(&key)
+            .await
+            .map(|engagement| engagement.map_or(0, |e| e.engaged_ms))
+    };
+
+    match total {
+        Ok(total) => payload.engaged_time_ms = Some(total),
+        Err(e) => tracing::warn!("Failed to update engagement store: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(event_type: &str, anonymous_id: &str, timestamp: i64) -> IngestEventPayload {
+        IngestEventPayload {
+            project_id: "proj".to_string(),
+            event_type: event_type.to_string(),
+            anonymous_id: Some(anonymous_id.to_string()),
+            timestamp,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_heartbeats_accumulate_engaged_time() {
+        let store = InMemoryEngagementStore::default();
+        let config = EngagementConfig {
+            enabled: true,
+            ..Default::default()
+        };
+
+        let mut pageview = event("pageview", "a1", 0);
+        apply(&mut pageview, &store, &config).await;
+        assert_eq!(pageview.engaged_time_ms, Some(0));
+
+        // First beat, a regular one, an early one, a duplicate, an
+        // out-of-order one, and one after the user was away
+        let mut totals = Vec::new();
+        for timestamp in [15_000, 30_000, 40_000, 40_000, 35_000, 200_000] {
+            let mut heartbeat = event(HEARTBEAT, "a1", timestamp);
+            apply(&mut heartbeat, &store, &config).await;
+            totals.push(heartbeat.engaged_time_ms.unwrap());
+        }
+        assert_eq!(totals, [15_000, 30_000, 40_000, 40_000, 40_000, 55_000]);
+
+        let mut track = event("signup", "a1", 205_000);
+        apply(&mut track, &store, &config).await;
+        assert_eq!(track.engaged_time_ms, Some(55_000));
+
+        let mut other_session = event("pageview", "a2", 205_000);
+        apply(&mut other_session, &store, &config).await;
+        assert_eq!(other_session.engaged_time_ms, Some(0));
+    }
+}
@@ -0,0 +1,223 @@
+//! Experiment assignment tags.
+//!
+//! Clients report A/B assignments as a map of experiment to variant, but
+//! not all in the same place or shape. With `EXPERIMENT_TAGGING_ENABLED`,
+//! the map is read from the `EXPERIMENTS_FIELD` property (`experiments` by
+//! default), or the context field of that name, and replaced by one flat
+//! `experiment_<name>` property per assignment, so every event can be
+//! sliced by variant the same way.
+//!
+//! Experiment names are lowercased, with anything but ASCII letters and
+//! digits turned into `_` (`Checkout V2` becomes `experiment_checkout_v2`).
+//! Variants are strings, or numbers and booleans written as strings.
+//! Anything else, a field that isn't an object, and names that end up the
+//! same (`Checkout V2` and `checkout-v2`) are dropped. With
+//! `VALIDATION_WARNINGS_ENABLED`, whatever is dropped is listed in the
+//! response's warnings.
+
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+use crate::models::IngestEventPayload;
+use crate::shared::{env_flag, env_var};
+
+/// Prefix of the flattened assignment properties
+const PREFIX: &str = "experiment_";
+
+/// Configuration for experiment tagging
+#[derive(Debug, Clone)]
+pub struct ExperimentsConfig {
+    pub enabled: bool,
+    /// Property or context field holding the assignments
+    pub field: String,
+}
+
+impl Default for ExperimentsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            field: "experiments".to_string(),
+        }
+    }
+}
+
+impl ExperimentsConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            enabled: env_flag("EXPERIMENT_TAGGING_ENABLED"),
+            field: env_var("EXPERIMENTS_FIELD")
+                .map(|field| field.trim().to_string())
+                .filter(|field| !field.is_empty())
+                .unwrap_or(defaults.field),
+        }
+    }
+}
+
+/// An experiment name as a property suffix, if anything is left of it
+fn normalize_name(name: &str) -> Option<String> {
+    let name: String = name
+        .trim()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+        .collect();
+    let name = name.trim_matches('_');
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// A variant as a string, if it's a scalar
+fn normalize_variant(variant: &Value) -> Option<String> {
+    match variant {
+        Value::String(s) => Some(s.trim().to_string()).filter(|s| !s.is_empty()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+/// The assignments field of an event, wherever it was sent
+fn field<'a>(payload: &'a IngestEventPayload, config: &ExperimentsConfig) -> Option<&'a Value> {
+    let from_properties = payload.properties.as_ref().and_then(|p| p.get(&config.field));
+    from_properties.or_else(|| payload.context.as_ref().and_then(|c| c.extra.get(&config.field)))
+}
+
+/// The flattened properties of well-formed assignments, and a warning for
+/// each assignment dropped
+fn flatten(assignments: &Map<String, Value>) -> (BTreeMap<String, String>, Vec<String>) {
+    let mut by_key: BTreeMap<String, Vec<(&String, String)>> = BTreeMap::new();
+    let mut warnings = Vec::new();
+    for (name, variant) in assignments {
+        match (normalize_name(name), normalize_variant(variant)) {
+            (Some(key), Some(variant)) => by_key.entry(key).or_default().push((name, variant)),
+            (None, _) => warnings.push(format!("experiment \"{}\" has no usable name", name)),
+            (_, None) => warnings.push(format!("experiment \"{}\" has a malformed variant", name)),
+        }
+    }
+
+    let mut properties = BTreeMap::new();
+    for (key, named) in by_key {
+        match named.as_slice() {
+            [(_, variant)] => {
+                properties.insert(format!("{}{}", PREFIX, key), variant.clone());
+            }
+            // Either would overwrite the other, so neither is kept
+            _ => {
+                let names: Vec<_> = named.iter().map(|(name, _)| format!("\"{}\"", name)).collect();
+                warnings.push(format!("experiments {} collide as {}{}", names.join(", "), PREFIX, key));
+            }
+        }
+    }
+    (properties, warnings)
+}
+
+/// What `apply` will drop from the event's assignments, for the response's
+/// validation warnings
+pub fn warnings(payload: &IngestEventPayload, config: &ExperimentsConfig) -> Vec<String> {
+    match field(payload, config) {
+        None => Vec::new(),
+        Some(Value::Object(assignments)) => flatten(assignments).1,
+        Some(_) => vec![format!("{} is not an object", config.field)],
+    }
+}
+
+/// Replaces the assignments field with `experiment_<name>` properties
+pub fn apply(payload: &mut IngestEventPayload, config: &ExperimentsConfig) {
+    let from_properties = payload.properties.as_mut().and_then(|p| p.remove(&config.field));
+    let from_context = || payload.context.as_mut().and_then(|c| c.extra.remove(&config.field));
+    let Some(assignments) = from_properties.or_else(from_context) else {
+        return;
+    };
+
+    let Value::Object(assignments) = assignments else {
+        tracing::warn!("Dropping {} of event {}: not an object", config.field, payload.event_type);
+        return;
+    };
+
+    let (flattened, warnings) = flatten(&assignments);
+    for warning in warnings {
+        tracing::warn!("Dropping assignment of event {}: {}", payload.event_type, warning);
+    }
+    let properties = payload.properties.get_or_insert_with(Default::default);
+    properties.extend(flattened.into_iter().map(|(key, variant)| (key, Value::String(variant))));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn tagged(properties: Value) -> HashMap<String, Value> {
+        let mut event = IngestEventPayload {
+            project_id: "proj".to_string(),
+            event_type: "checkout".to_string(),
+            properties: serde_json::from_value(properties).unwrap(),
+            ..Default::default()
+        };
+        apply(&mut event, &ExperimentsConfig::default());
+        event.properties.unwrap()
+    }
+
+    #[test]
+    fn test_flattens_well_formed_assignments() {
+        let properties = tagged(serde_json::json!({
+            "plan": "pro",
+            "experiments": {"Checkout V2": "treatment", "pricing-test": 3, "new_nav": true},
+        }));
+
+        assert!(!properties.contains_key("experiments"));
+        assert_eq!(properties["plan"], "pro");
+        assert_eq!(properties["experiment_checkout_v2"], "treatment");
+        assert_eq!(properties["experiment_pricing_test"], "3");
+        assert_eq!(properties["experiment_new_nav"], "true");
+    }
+
+    #[test]
+    fn test_drops_malformed_assignments() {
+        let properties = tagged(serde_json::json!({
+            "experiments": {"checkout": "control", "pricing": {"variant": "b"}, "nav": "", "--": "a"},
+        }));
+        assert_eq!(properties.len(), 1);
+        assert_eq!(properties["experiment_checkout"], "control");
+
+        let properties = tagged(serde_json::json!({"plan": "pro", "experiments": ["checkout:control"]}));
+        assert_eq!(properties.len(), 1);
+        assert_eq!(properties["plan"], "pro");
+    }
+
+    #[test]
+    fn test_colliding_and_non_ascii_names_are_dropped() {
+        let properties = tagged(serde_json::json!({
+            "experiments": {"Checkout V2": "a", "checkout-v2": "b", "nav": "c", "Prüfung": "d"},
+        }));
+        assert!(!properties.contains_key("experiment_checkout_v2"));
+        assert_eq!(properties["experiment_nav"], "c");
+        assert_eq!(properties["experiment_pr_fung"], "d");
+        assert_eq!(properties.len(), 2);
+    }
+
+    #[test]
+    fn test_dropped_assignments_are_warned_about() {
+        let event = |properties: Value| IngestEventPayload {
+            properties: serde_json::from_value(properties).unwrap(),
+            ..Default::default()
+        };
+        let config = ExperimentsConfig::default();
+
+        let assignments = serde_json::json!({
+            "experiments": {"Checkout V2": "a", "checkout-v2": "b", "pricing": {"variant": "b"}, "--": "a", "nav": "c"},
+        });
+        assert_eq!(
+            warnings(&event(assignments), &config),
+            [
+                "experiment \"--\" has no usable name",
+                "experiment \"pricing\" has a malformed variant",
+                "experiments \"Checkout V2\", \"checkout-v2\" collide as experiment_checkout_v2",
+            ]
+        );
+        assert_eq!(
+            warnings(&event(serde_json::json!({"experiments": ["checkout:control"]})), &config),
+            ["experiments is not an object"]
+        );
+        assert!(warnings(&event(serde_json::json!({"plan": "pro"})), &config).is_empty());
+    }
+}
@@ -0,0 +1,308 @@
+//! First-touch attribution.
+//!
+//! The first event of an `anonymous_id` that carries campaign parameters
+//! (its page url's UTM parameters and click ids, or `context.campaign`) or
+//! a referrer from another site is remembered in a [`FirstTouchStore`]:
+//! the campaign, the referrer and the landing page. Later touches leave it
+//! alone. Conversion events (`FIRST_TOUCH_CONVERSION_EVENTS`) are stamped
+//! with the remembered `first_touch`, so attribution reports read it off
+//! the conversion instead of joining against every earlier pageview.
+//!
+//! First touches expire after `FIRST_TOUCH_TTL_DAYS` (the table's TTL
+//! attribute `expires_at`), which bounds the attribution window.
+
+use async_trait::async_trait;
+use aws_sdk_dynamodb::operation::put_item::PutItemError;
+use aws_sdk_dynamodb::types::{AttributeValue, ReturnValuesOnConditionCheckFailure};
+use aws_sdk_dynamodb::Client as DynamoClient;
+use lambda_http::Error;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use url::Url;
+
+use crate::enrichment::campaign;
+use crate::enrichment::duplicate_view::page_url;
+use crate::models::{CampaignContext, FirstTouch, IngestEventPayload};
+use crate::shared::{env_flag, env_list, env_or, env_var};
+
+/// Configuration for first-touch attribution
+#[derive(Debug, Clone)]
+pub struct FirstTouchConfig {
+    pub enabled: bool,
+    /// DynamoDB table backing the store; in-memory when unset
+    pub table_name: Option<String>,
+    /// Event types stamped with the first touch
+    pub conversion_events: Vec<String>,
+    /// How long a first touch is kept
+    pub ttl: Duration,
+}
+
+impl Default for FirstTouchConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            table_name: None,
+            conversion_events: ["signup", "purchase", "order_completed", "subscription_started"]
+                .map(String::from)
+                .to_vec(),
+            ttl: Duration::from_secs(90 * 86_400),
+        }
+    }
+}
+
+impl FirstTouchConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let conversion_events = env_list("FIRST_TOUCH_CONVERSION_EVENTS");
+        Self {
+            enabled: env_flag("FIRST_TOUCH_ENABLED"),
+            table_name: env_var("FIRST_TOUCH_TABLE"),
+            conversion_events: if conversion_events.is_empty() {
+                defaults.conversion_events
+            } else {
+                conversion_events
+            },
+            ttl: Duration::from_secs(env_or("FIRST_TOUCH_TTL_DAYS", defaults.ttl.as_secs() / 86_400).max(1) * 86_400),
+        }
+    }
+
+    pub fn is_conversion(&self, event_type: &str) -> bool {
+        self.conversion_events.iter().any(|conversion| conversion == event_type)
+    }
+}
+
+/// Per-visitor first touch store
+#[async_trait]
+pub trait FirstTouchStore: Send + Sync {
+    /// Keeps `touch` unless `key` already has a first touch, returning
+    /// whichever is first
+    async fn remember(&self, key: &str, touch: &FirstTouch) -> Result<FirstTouch, Error>;
+    async fn get(&self, key: &str) -> Result<Option<FirstTouch>, Error>;
+}
+
+/// Process-local store, used in tests and when no table is configured
+#[derive(Debug, Default)]
+pub struct InMemoryFirstTouchStore {
+    entries: Mutex<HashMap<String, FirstTouch>>,
+}
+
+#[async_trait]
+impl FirstTouchStore for InMemoryFirstTouchStore {
+    async fn remember(&self, key: &str, touch: &FirstTouch) -> Result<FirstTouch, Error> {
+        let mut entries = self.entries.lock().unwrap();
+        Ok(entries.entry(key.to_string()).or_insert_with(|| touch.clone()).clone())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<FirstTouch>, Error> {
+        Ok(self.entries.lock().unwrap().get(key).cloned())
+    }
+}
+
+/// DynamoDB-backed store
+/// Table schema: partition key `pk` (S), attribute `touch` (S, JSON), TTL
+/// attribute `expires_at` (N)
+pub struct DynamoFirstTouchStore {
+    client: DynamoClient,
+    table_name: String,
+    ttl: Duration,
+}
+
+impl DynamoFirstTouchStore {
+    pub fn new(client: DynamoClient, table_name: String, config: &FirstTouchConfig) -> Self {
+        Self {
+            client,
+            table_name,
+            ttl: config.ttl,
+        }
+    }
+}
+
+fn touch_attribute(item: Option<&HashMap<String, AttributeValue>>) -> Option<FirstTouch> {
+    let touch = item?.get("touch")?.as_s().ok()?;
+    serde_json::from_str(touch).ok()
+}
+
+/// The touch stored in an item, unless it expired. DynamoDB deletes
+/// expired items lazily, so they may still be read.
+pub fn unexpired_touch(item: Option<&HashMap<String, AttributeValue>>) -> Option<FirstTouch> {
+    let now = chrono::Utc::now().timestamp();
+    let expired = item
+        .and_then(|item| item.get("expires_at")?.as_n().ok()?.parse::<i64>().ok())
+        .is_some_and(|expires_at| expires_at < now);
+    touch_attribute(item).filter(|_| !expired)
+}
+
+#[async_trait]
+impl FirstTouchStore for DynamoFirstTouchStore {
+    async fn remember(&self, key: &str, touch: &FirstTouch) -> Result<FirstTouch, Error> {
+        let now = chrono::Utc::now().timestamp();
+        let result = self
+            .client
+            .put_item()
+            .table_name(&self.table_name)
+            .item("pk", AttributeValue::S(key.to_string()))
+            .item("touch", AttributeValue::S(serde_json::to_string(touch)?))
+            .item("expires_at", AttributeValue::N((now + self.ttl.as_secs() as i64).to_string()))
+            // DynamoDB deletes expired items lazily, so they count as absent
+            .condition_expression("attribute_not_exists(pk) OR expires_at < :now")
+            .expression_attribute_values(":now", AttributeValue::N(now.to_string()))
+            .return_values_on_condition_check_failure(ReturnValuesOnConditionCheckFailure::AllOld)
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => Ok(touch.clone()),
+            Err(err) => match err.into_service_error() {
+                // An earlier touch is already kept
+                PutItemError::ConditionalCheckFailedException(e) => {
+                    Ok(touch_attribute(e.item()).unwrap_or_else(|| touch.clone()))
+                }
+                other => Err(other.into()),
+            },
+        }
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<FirstTouch>, Error> {
+        let output = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("pk", AttributeValue::S(key.to_string()))
+            .send()
+            .await?;
+        Ok(unexpired_touch(output.item()))
+    }
+}
+
+/// The touch an event carries, if it has campaign parameters or a
+/// referrer from another site
+pub fn touch_of(payload: &IngestEventPayload) -> Option<FirstTouch> {
+    let url = page_url(payload);
+    // What the client sent wins, as in `campaign`
+    let mut found = payload
+        .context
+        .as_ref()
+        .and_then(|c| c.campaign.clone())
+        .unwrap_or_default();
+    campaign::fill(&mut found, url.map(campaign::parse).unwrap_or_default());
+    let campaign = Some(found).filter(|found| *found != CampaignContext::default());
+
+    let host = |url: &str| Url::parse(url).ok()?.host_str().map(str::to_ascii_lowercase);
+    let referrer = payload
+        .context
+        .as_ref()
+        .and_then(|c| c.page.as_ref())
+        .and_then(|p| p.referrer.as_deref())
+        .filter(|referrer| host(referrer).is_some_and(|from| Some(from) != url.and_then(host)));
+
+    if campaign.is_none() && referrer.is_none() {
+        return None;
+    }
+    Some(FirstTouch {
+        timestamp: payload.timestamp,
+        campaign,
+        referrer: referrer.map(String::from),
+        landing_page: url.map(String::from),
+    })
+}
+
+/// The key `apply` looks up for this event, if any
+pub fn lookup_key(payload: &IngestEventPayload, config: &FirstTouchConfig) -> Option<String> {
+    let anonymous_id = payload.anonymous_id.as_deref()?;
+    let looks_up = config.is_conversion(&payload.event_type) || touch_of(payload).is_some();
+    looks_up.then(|| format!("{}#{}", payload.project_id, anonymous_id))
+}
+
+/// Remembers the visitor's first touch and stamps it on conversions
+pub async fn apply(payload: &mut IngestEventPayload, store: &dyn FirstTouchStore, config: &FirstTouchConfig) {
+    let Some(anonymous_id) = payload.anonymous_id.as_deref() else {
+        return;
+    };
+    let key = format!("{}#{}", payload.project_id, anonymous_id);
+    let touch = touch_of(payload);
+    let conversion = config.is_conversion(&payload.event_type);
+
+    let first = match touch {
+        Some(ref touch) => store.remember(&key, touch).await.map(Some),
+        None if conversion => store.get(&key).await,
+        None => return,
+    };
+    match first {
+        Ok(first) if conversion => payload.first_touch = first,
+        Ok(_) => {}
+        Err(e) => tracing::warn!("Failed to look up the first touch: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{EventContext, PageContext};
+
+    fn event(event_type: &str, timestamp: i64, url: &str, referrer: Option<&str>) -> IngestEventPayload {
+        IngestEventPayload {
+            project_id: "proj".to_string(),
+            event_type: event_type.to_string(),
+            anonymous_id: Some("anon-1".to_string()),
+            timestamp,
+            context: Some(EventContext {
+                page: Some(PageContext {
+                    url: Some(url.to_string()),
+                    referrer: referrer.map(String::from),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_touches_need_a_campaign_or_an_external_referrer() {
+        let internal = event("pageview", 1, "https://shop.io/cart", Some("https://shop.io/"));
+        assert_eq!(touch_of(&internal), None);
+
+        let referred = event("pageview", 1, "https://shop.io/", Some("https://news.site/post"));
+        let touch = touch_of(&referred).unwrap();
+        assert_eq!(touch.referrer.as_deref(), Some("https://news.site/post"));
+        assert_eq!(touch.campaign, None);
+
+        let tagged = event("pageview", 1, "https://shop.io/?utm_source=newsletter", None);
+        let touch = touch_of(&tagged).unwrap();
+        assert_eq!(touch.campaign.unwrap().source.as_deref(), Some("newsletter"));
+        assert_eq!(touch.landing_page.as_deref(), Some("https://shop.io/?utm_source=newsletter"));
+    }
+
+    #[tokio::test]
+    async fn test_conversions_get_the_first_touch() {
+        let store = InMemoryFirstTouchStore::default();
+        let config = FirstTouchConfig::default();
+
+        let mut first = event("pageview", 1_000, "https://shop.io/?utm_source=ads&utm_campaign=spring", None);
+        let mut second = event("pageview", 2_000, "https://shop.io/", Some("https://search.example/"));
+        let mut purchase = event("purchase", 3_000, "https://shop.io/checkout", None);
+        for event in [&mut first, &mut second, &mut purchase] {
+            apply(event, &store, &config).await;
+        }
+
+        // Only conversions carry it
+        assert_eq!(first.first_touch, None);
+        let touch = purchase.first_touch.unwrap();
+        assert_eq!(touch.timestamp, 1_000);
+        assert_eq!(touch.campaign.unwrap().name.as_deref(), Some("spring"));
+    }
+
+    #[tokio::test]
+    async fn test_conversions_without_a_touch_stay_unattributed() {
+        let store = InMemoryFirstTouchStore::default();
+        let mut signup = event("signup", 1_000, "https://shop.io/join", Some("https://shop.io/"));
+        apply(&mut signup, &store, &FirstTouchConfig::default()).await;
+        assert_eq!(signup.first_touch, None);
+
+        // A conversion that is itself the first touch carries it
+        let mut tagged = event("signup", 2_000, "https://shop.io/join?utm_source=partner", None);
+        apply(&mut tagged, &store, &FirstTouchConfig::default()).await;
+        assert_eq!(tagged.first_touch.unwrap().timestamp, 2_000);
+    }
+}
@@ -0,0 +1,180 @@
+//! GeoIP enrichment.
+//!
+//! With `GEOIP_ENABLED`, `context.ip` is resolved against a MaxMind City
+//! database (GeoLite2 or GeoIP2) into `context.geo`. The database ships in a
+//! Lambda layer and is read once per sandbox from `GEOIP_DATABASE_PATH`. By
+//! default the raw IP is then removed from the event, so only the derived
+//! location is stored; set `GEOIP_DROP_IP=false` to keep it.
+
+use maxminddb::geoip2;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+
+use crate::models::{GeoContext, IngestEventPayload};
+use crate::shared::{env_flag, env_or};
+
+/// Where Lambda mounts layer contents
+const DEFAULT_DATABASE_PATH: &str = "/opt/geoip/GeoLite2-City.mmdb";
+
+/// Configuration for GeoIP enrichment
+#[derive(Debug, Clone)]
+pub struct GeoIpConfig {
+    pub enabled: bool,
+    pub database_path: String,
+    /// Remove `context.ip` once it has been resolved
+    pub drop_ip: bool,
+}
+
+impl Default for GeoIpConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            database_path: DEFAULT_DATABASE_PATH.to_string(),
+            drop_ip: true,
+        }
+    }
+}
+
+impl GeoIpConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            enabled: env_flag("GEOIP_ENABLED"),
+            database_path: env_or("GEOIP_DATABASE_PATH", defaults.database_path),
+            drop_ip: env_or("GEOIP_DROP_IP", defaults.drop_ip),
+        }
+    }
+}
+
+/// Resolves IP addresses to locations
+pub trait GeoIpLookup: Send + Sync {
+    fn lookup(&self, ip: IpAddr) -> Option<GeoContext>;
+}
+
+/// Fixed locations, for tests and local runs
+#[derive(Default)]
+pub struct InMemoryGeoIp {
+    locations: Mutex<HashMap<IpAddr, GeoContext>>,
+}
+
+impl InMemoryGeoIp {
+    pub fn insert(&self, ip: IpAddr, geo: GeoContext) {
+        self.locations.lock().unwrap().insert(ip, geo);
+    }
+}
+
+impl GeoIpLookup for InMemoryGeoIp {
+    fn lookup(&self, ip: IpAddr) -> Option<GeoContext> {
+        self.locations.lock().unwrap().get(&ip).cloned()
+    }
+}
+
+/// A MaxMind City database held in memory
+pub struct MmdbGeoIp {
+    reader: maxminddb::Reader<Vec<u8>>,
+}
+
+impl MmdbGeoIp {
+    pub fn open(path: &str) -> Result<Self, maxminddb::MaxMindDbError> {
+        Ok(Self {
+            reader: maxminddb::Reader::open_readfile(path)?,
+        })
+    }
+}
+
+impl GeoIpLookup for MmdbGeoIp {
+    fn lookup(&self, ip: IpAddr) -> Option<GeoContext> {
+        let result = self.reader.lookup(ip).ok()?;
+        let city = match result.decode::<geoip2::City>() {
+            Ok(city) => city?,
+            Err(e) => {
+                tracing::warn!("Failed to decode GeoIP record: {}", e);
+                return None;
+            }
+        };
+        let geo = GeoContext {
+            country: city.country.iso_code.map(String::from),
+            region: city.subdivisions.first().and_then(|s| s.iso_code).map(String::from),
+            city: city.city.names.english.map(String::from),
+        };
+        (geo.country.is_some() || geo.city.is_some()).then_some(geo)
+    }
+}
+
+/// Stamps `context.geo` from `context.ip`, then drops the IP if configured
+pub fn apply(payload: &mut IngestEventPayload, geoip: &dyn GeoIpLookup, config: &GeoIpConfig) {
+    let Some(context) = payload.context.as_mut() else {
+        return;
+    };
+
+    let ip = context.ip.as_deref().and_then(|ip| ip.trim().parse::<IpAddr>().ok());
+    if let Some(geo) = ip.and_then(|ip| geoip.lookup(ip)) {
+        context.geo = Some(geo);
+    }
+
+    if config.drop_ip {
+        context.ip = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::EventContext;
+
+    fn payload(ip: &str) -> IngestEventPayload {
+        IngestEventPayload {
+            context: Some(EventContext {
+                ip: Some(ip.to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn geoip() -> InMemoryGeoIp {
+        let geoip = InMemoryGeoIp::default();
+        geoip.insert(
+            "81.2.69.142".parse().unwrap(),
+            GeoContext {
+                country: Some("GB".to_string()),
+                region: Some("ENG".to_string()),
+                city: Some("London".to_string()),
+            },
+        );
+        geoip
+    }
+
+    #[test]
+    fn test_resolves_ip_and_drops_it() {
+        let mut event = payload("81.2.69.142");
+        apply(&mut event, &geoip(), &GeoIpConfig::default());
+
+        let context = event.context.unwrap();
+        let geo = context.geo.unwrap();
+        assert_eq!(geo.country.as_deref(), Some("GB"));
+        assert_eq!(geo.region.as_deref(), Some("ENG"));
+        assert_eq!(geo.city.as_deref(), Some("London"));
+        assert!(context.ip.is_none());
+    }
+
+    #[test]
+    fn test_unresolved_ip_is_still_dropped_unless_kept() {
+        let mut event = payload("not-an-ip");
+        apply(&mut event, &geoip(), &GeoIpConfig::default());
+        let context = event.context.unwrap();
+        assert!(context.geo.is_none());
+        assert!(context.ip.is_none());
+
+        let keep = GeoIpConfig {
+            drop_ip: false,
+            ..Default::default()
+        };
+        let mut event = payload("81.2.69.142");
+        apply(&mut event, &geoip(), &keep);
+        let context = event.context.unwrap();
+        assert!(context.geo.is_some());
+        assert_eq!(context.ip.as_deref(), Some("81.2.69.142"));
+    }
+}
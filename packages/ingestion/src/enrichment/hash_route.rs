@@ -0,0 +1,122 @@
+//! Path normalization for hash-routed SPAs.
+//!
+//! Apps using hash routing (`https://app.io/#/dashboard/settings`) keep the
+//! route in the url fragment, so every page reports the same `path`. When
+//! enabled, a fragment that looks like a route (`#/...` or hashbang `#!/...`)
+//! is promoted into `context.page.path`, appended to the url's own path.
+//! Urls without a route-like fragment are left alone.
+
+use url::Url;
+
+use crate::enrichment::duplicate_view::page_url;
+use crate::models::{EventContext, IngestEventPayload, PageContext};
+use crate::shared::{env_flag, env_list};
+
+/// Configuration for hash-route normalization
+#[derive(Debug, Clone, Default)]
+pub struct HashRouteConfig {
+    pub enabled: bool,
+    /// Projects using hash routing; all projects when empty
+    pub projects: Vec<String>,
+}
+
+impl HashRouteConfig {
+    pub fn from_env() -> Self {
+        Self {
+            enabled: env_flag("HASH_ROUTE_PATHS_ENABLED"),
+            projects: env_list("HASH_ROUTE_PROJECTS"),
+        }
+    }
+
+    fn applies_to(&self, project_id: &str) -> bool {
+        self.projects.is_empty() || self.projects.iter().any(|p| p == project_id)
+    }
+}
+
+/// Effective path of a hash-routed url, `None` if the fragment isn't a route
+pub fn route_path(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    let fragment = url.fragment()?;
+    let route = fragment.strip_prefix('!').unwrap_or(fragment);
+    if !route.starts_with('/') {
+        return None;
+    }
+    let route = route.split(['?', '#']).next().unwrap_or(route);
+    Some(format!("{}{}", url.path().trim_end_matches('/'), route))
+}
+
+/// Replaces `context.page.path` with the route from the url fragment
+pub fn apply(payload: &mut IngestEventPayload, config: &HashRouteConfig) {
+    if !config.applies_to(&payload.project_id) {
+        return;
+    }
+    let Some(path) = page_url(payload).and_then(route_path) else {
+        return;
+    };
+
+    let context = payload.context.get_or_insert_with(EventContext::default);
+    context.page.get_or_insert_with(PageContext::default).path = Some(path);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pageview(url: &str) -> IngestEventPayload {
+        IngestEventPayload {
+            project_id: "proj".to_string(),
+            event_type: "pageview".to_string(),
+            context: Some(EventContext {
+                page: Some(PageContext {
+                    url: Some(url.to_string()),
+                    path: Some("/".to_string()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn path(event: &IngestEventPayload) -> Option<&str> {
+        event.context.as_ref()?.page.as_ref()?.path.as_deref()
+    }
+
+    #[test]
+    fn test_hash_route_becomes_path() {
+        let config = HashRouteConfig {
+            enabled: true,
+            projects: Vec::new(),
+        };
+
+        let mut event = pageview("https://app.io/#/dashboard/settings?tab=billing");
+        apply(&mut event, &config);
+        assert_eq!(path(&event), Some("/dashboard/settings"));
+
+        let mut event = pageview("https://app.io/legacy/#!/reports");
+        apply(&mut event, &config);
+        assert_eq!(path(&event), Some("/legacy/reports"));
+    }
+
+    #[test]
+    fn test_normal_urls_are_unaffected() {
+        let config = HashRouteConfig {
+            enabled: true,
+            projects: vec!["proj".to_string()],
+        };
+
+        let mut event = pageview("https://app.io/pricing#faq");
+        apply(&mut event, &config);
+        assert_eq!(path(&event), Some("/"));
+
+        let mut event = pageview("https://app.io/");
+        apply(&mut event, &config);
+        assert_eq!(path(&event), Some("/"));
+
+        // Projects not listed keep their path even with a route fragment
+        let mut event = pageview("https://app.io/#/dashboard");
+        event.project_id = "other".to_string();
+        apply(&mut event, &config);
+        assert_eq!(path(&event), Some("/"));
+    }
+}
@@ -0,0 +1,216 @@
+//! Privacy-preserving identity hashing.
+//!
+//! For configured property keys holding emails or phone numbers, the value
+//! is normalized (lowercased/trimmed email, E.164 phone) and replaced with a
+//! salted SHA-256 hash, so the same person hashes identically across events
+//! without the raw value ever being stored. Values that fail normalization
+//! are dropped.
+
+use sha2::{Digest, Sha256};
+
+use crate::models::IngestEventPayload;
+use crate::shared::{env_flag, env_list, env_var};
+
+/// Configuration for identity hashing
+#[derive(Debug, Clone, Default)]
+pub struct IdentityHashConfig {
+    pub enabled: bool,
+    /// Property keys holding email addresses
+    pub email_keys: Vec<String>,
+    /// Property keys holding phone numbers
+    pub phone_keys: Vec<String>,
+    pub salt: String,
+    /// Calling code (without `+`) for national numbers like `030 1234567`
+    pub default_calling_code: Option<String>,
+}
+
+impl IdentityHashConfig {
+    pub fn from_env() -> Self {
+        Self {
+            enabled: env_flag("IDENTITY_HASHING_ENABLED"),
+            email_keys: env_list("IDENTITY_HASH_EMAIL_KEYS"),
+            phone_keys: env_list("IDENTITY_HASH_PHONE_KEYS"),
+            salt: env_var("IDENTITY_HASH_SALT").unwrap_or_default(),
+            default_calling_code: env_var("IDENTITY_HASH_DEFAULT_CALLING_CODE"),
+        }
+    }
+
+    /// Unsalted hashes of emails and user ids are reversed by a dictionary
+    /// attack, so hashing needs a salt
+    pub fn validate(&self) -> Result<(), String> {
+        if self.enabled && self.salt.is_empty() {
+            return Err("IDENTITY_HASH_SALT is required when IDENTITY_HASHING_ENABLED is set".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Lowercases and trims an email, rejecting anything not shaped like one
+pub fn normalize_email(raw: &str) -> Option<String> {
+    let email = raw.trim().to_lowercase();
+    let (local, domain) = email.split_once('@')?;
+
+    let valid = !local.is_empty()
+        && !domain.contains('@')
+        && domain.contains('.')
+        && !domain.starts_with('.')
+        && !domain.ends_with('.')
+        && !email.chars().any(char::is_whitespace);
+
+    valid.then_some(email)
+}
+
+/// Normalizes a phone number to E.164 (`+` followed by 8-15 digits)
+pub fn normalize_phone(raw: &str, default_calling_code: Option<&str>) -> Option<String> {
+    let trimmed = raw.trim();
+    if trimmed
+        .chars()
+        .any(|c| !(c.is_ascii_digit() || matches!(c, '+' | ' ' | '-' | '.' | '(' | ')')))
+    {
+        return None;
+    }
+
+    let digits: String = trimmed.chars().filter(char::is_ascii_digit).collect();
+    let international = if trimmed.starts_with('+') {
+        digits
+    } else if let Some(rest) = digits.strip_prefix("00") {
+        rest.to_string()
+    } else if let (Some(rest), Some(code)) = (digits.strip_prefix('0'), default_calling_code) {
+        format!("{}{}", code, rest)
+    } else {
+        return None;
+    };
+
+    let valid = (8..=15).contains(&international.len()) && !international.starts_with('0');
+    valid.then(|| format!("+{}", international))
+}
+
+/// Salted SHA-256 of a normalized identifier, hex encoded
+pub fn hash_identifier(salt: &str, normalized: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(salt.as_bytes());
+    hasher.update(b":");
+    hasher.update(normalized.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+/// Replaces configured email/phone properties with their salted hashes
+pub fn apply(payload: &mut IngestEventPayload, config: &IdentityHashConfig) {
+    let Some(ref mut properties) = payload.properties else {
+        return;
+    };
+
+    let keys = config
+        .email_keys
+        .iter()
+        .map(|key| (key, true))
+        .chain(config.phone_keys.iter().map(|key| (key, false)));
+
+    for (key, is_email) in keys {
+        let Some(value) = properties.remove(key) else {
+            continue;
+        };
+
+        let normalized = value.as_str().and_then(|raw| {
+            if is_email {
+                normalize_email(raw)
+            } else {
+                normalize_phone(raw, config.default_calling_code.as_deref())
+            }
+        });
+
+        match normalized {
+            Some(normalized) => {
+                let hashed = hash_identifier(&config.salt, &normalized);
+                properties.insert(key.clone(), serde_json::json!(hashed));
+            }
+            None => {
+                tracing::debug!("Dropping unnormalizable identity property {}", key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn config() -> IdentityHashConfig {
+        IdentityHashConfig {
+            enabled: true,
+            email_keys: vec!["email".to_string()],
+            phone_keys: vec!["phone".to_string()],
+            salt: "pepper".to_string(),
+            default_calling_code: Some("49".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_hashing_requires_a_salt() {
+        assert!(config().validate().is_ok());
+        let unsalted = IdentityHashConfig {
+            salt: String::new(),
+            ..config()
+        };
+        assert!(unsalted.validate().is_err());
+        assert!(IdentityHashConfig::default().validate().is_ok());
+    }
+
+    fn event(key: &str, value: serde_json::Value) -> IngestEventPayload {
+        IngestEventPayload {
+            properties: Some(HashMap::from([
+                (key.to_string(), value),
+                ("plan".to_string(), serde_json::json!("pro")),
+            ])),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_email_is_normalized_then_hashed() {
+        let mut hashed = event("email", serde_json::json!("  Jane.Doe@Example.COM "));
+        apply(&mut hashed, &config());
+
+        let properties = hashed.properties.unwrap();
+        assert_eq!(
+            properties["email"],
+            hash_identifier("pepper", "jane.doe@example.com")
+        );
+        assert_eq!(properties["plan"], "pro");
+
+        for malformed in ["jane.doe", "jane@localhost", "@example.com", "a@b@c.com"] {
+            assert_eq!(normalize_email(malformed), None, "{}", malformed);
+        }
+        let mut malformed = event("email", serde_json::json!("not-an-email"));
+        apply(&mut malformed, &config());
+        assert!(!malformed.properties.unwrap().contains_key("email"));
+    }
+
+    #[test]
+    fn test_phone_is_normalized_to_e164_then_hashed() {
+        for raw in ["+49 30 1234567", "0049-30-1234567", "030 1234567", "(030) 123-4567"] {
+            assert_eq!(
+                normalize_phone(raw, Some("49")).as_deref(),
+                Some("+49301234567"),
+                "{}",
+                raw
+            );
+        }
+
+        let mut hashed = event("phone", serde_json::json!("+49 (30) 123 4567"));
+        apply(&mut hashed, &config());
+        assert_eq!(
+            hashed.properties.unwrap()["phone"],
+            hash_identifier("pepper", "+49301234567")
+        );
+
+        for malformed in ["12345", "call me", "+1 555 CALL NOW", "030 1234567"] {
+            let mut dropped = event("phone", serde_json::json!(malformed));
+            let mut config = config();
+            config.default_calling_code = None;
+            apply(&mut dropped, &config);
+            assert!(!dropped.properties.unwrap().contains_key("phone"), "{}", malformed);
+        }
+    }
+}
@@ -0,0 +1,291 @@
+//! Impossible-travel detection.
+//!
+//! Compares each event's location with the user's previous one and stamps
+//! `impossible_travel: true` when the implied ground speed exceeds a
+//! configurable threshold. The last-seen location lives in a
+//! [`LocationStore`] and, like the last-seen timestamp, only moves forward.
+
+use async_trait::async_trait;
+use aws_sdk_dynamodb::operation::update_item::UpdateItemError;
+use aws_sdk_dynamodb::types::{AttributeValue, ReturnValue, ReturnValuesOnConditionCheckFailure};
+use aws_sdk_dynamodb::Client as DynamoClient;
+use lambda_http::{Error, Request};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::models::IngestEventPayload;
+use crate::shared::{env_flag, env_or, env_var, header_value};
+
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// Configuration for impossible-travel detection
+#[derive(Debug, Clone)]
+pub struct ImpossibleTravelConfig {
+    pub enabled: bool,
+    /// Implied speeds above this are flagged (default: faster than an airliner)
+    pub max_speed_kmh: f64,
+    /// Hops shorter than this are never flagged, to absorb geolocation noise
+    pub min_distance_km: f64,
+    /// DynamoDB table backing the store; in-memory when unset
+    pub table_name: Option<String>,
+}
+
+impl Default for ImpossibleTravelConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_speed_kmh: 1000.0,
+            min_distance_km: 100.0,
+            table_name: None,
+        }
+    }
+}
+
+impl ImpossibleTravelConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            enabled: env_flag("IMPOSSIBLE_TRAVEL_ENABLED"),
+            max_speed_kmh: env_or("IMPOSSIBLE_TRAVEL_MAX_SPEED_KMH", defaults.max_speed_kmh),
+            min_distance_km: env_or("IMPOSSIBLE_TRAVEL_MIN_DISTANCE_KM", defaults.min_distance_km),
+            table_name: env_var("LAST_LOCATION_TABLE"),
+        }
+    }
+}
+
+/// A located, timestamped sighting of a user
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sighting {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub timestamp: i64,
+}
+
+impl Sighting {
+    /// Great-circle distance to another sighting in kilometres
+    pub fn distance_km(&self, other: &Sighting) -> f64 {
+        let (lat1, lat2) = (self.latitude.to_radians(), other.latitude.to_radians());
+        let dlat = lat2 - lat1;
+        let dlon = (other.longitude - self.longitude).to_radians();
+
+        let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+    }
+}
+
+/// Per-user last-seen location store
+#[async_trait]
+pub trait LocationStore: Send + Sync {
+    /// Records `sighting` for `key` if it is newer than the stored one.
+    /// Returns the previously stored sighting, if any.
+    async fn record(&self, key: &str, sighting: Sighting) -> Result<Option<Sighting>, Error>;
+    async fn get(&self, key: &str) -> Result<Option<Sighting>, Error>;
+}
+
+/// Process-local store, used in tests and when no table is configured
+#[derive(Debug, Default)]
+pub struct InMemoryLocationStore {
+    entries: Mutex<HashMap<String, Sighting>>,
+}
+
+#[async_trait]
+impl LocationStore for InMemoryLocationStore {
+    async fn record(&self, key: &str, sighting: Sighting) -> Result<Option<Sighting>, Error> {
+        let mut entries = self.entries.lock().unwrap();
+        let previous = entries.get(key).copied();
+        if previous.is_none_or(|previous| previous.timestamp < sighting.timestamp) {
+            entries.insert(key.to_string(), sighting);
+        }
+        Ok(previous)
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Sighting>, Error> {
+        Ok(self.entries.lock().unwrap().get(key).copied())
+    }
+}
+
+/// DynamoDB-backed store
+/// Table schema: partition key `pk` (S), attributes `lat`, `lon`, `ts` (N)
+pub struct DynamoLocationStore {
+    client: DynamoClient,
+    table_name: String,
+}
+
+impl DynamoLocationStore {
+    pub fn new(client: DynamoClient, table_name: String) -> Self {
+        Self { client, table_name }
+    }
+}
+
+/// The sighting stored in an item
+pub fn sighting_attributes(item: Option<&HashMap<String, AttributeValue>>) -> Option<Sighting> {
+    let item = item?;
+    let number = |name: &str| item.get(name)?.as_n().ok()?.parse::<f64>().ok();
+    Some(Sighting {
+        latitude: number("lat")?,
+        longitude: number("lon")?,
+        timestamp: number("ts")? as i64,
+    })
+}
+
+#[async_trait]
+impl LocationStore for DynamoLocationStore {
+    async fn record(&self, key: &str, sighting: Sighting) -> Result<Option<Sighting>, Error> {
+        let result = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("pk", AttributeValue::S(key.to_string()))
+            .update_expression("SET lat = :lat, lon = :lon, ts = :ts")
+            .condition_expression("attribute_not_exists(ts) OR ts < :ts")
+            .expression_attribute_values(":lat", AttributeValue::N(sighting.latitude.to_string()))
+            .expression_attribute_values(":lon", AttributeValue::N(sighting.longitude.to_string()))
+            .expression_attribute_values(":ts", AttributeValue::N(sighting.timestamp.to_string()))
+            .return_values(ReturnValue::UpdatedOld)
+            .return_values_on_condition_check_failure(ReturnValuesOnConditionCheckFailure::AllOld)
+            .send()
+            .await;
+
+        match result {
+            Ok(output) => Ok(sighting_attributes(output.attributes())),
+            Err(err) => match err.into_service_error() {
+                // Stored sighting is newer than this event
+                UpdateItemError::ConditionalCheckFailedException(e) => {
+                    Ok(sighting_attributes(e.item()))
+                }
+                other => Err(other.into()),
+            },
+        }
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Sighting>, Error> {
+        let output = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("pk", AttributeValue::S(key.to_string()))
+            .consistent_read(true)
+            .send()
+            .await?;
+        Ok(sighting_attributes(output.item()))
+    }
+}
+
+/// The key `apply` looks up for an event seen at `location`, if any
+pub fn lookup_key(payload: &IngestEventPayload, location: Option<(f64, f64)>) -> Option<String> {
+    location?;
+    let user = payload.user_id.as_ref().or(payload.anonymous_id.as_ref())?;
+    Some(format!("{}#{}", payload.project_id, user))
+}
+
+/// Viewer coordinates resolved by CloudFront from the client IP
+pub fn viewer_location(request: &Request) -> Option<(f64, f64)> {
+    let latitude = header_value(request, "cloudfront-viewer-latitude")?.parse().ok()?;
+    let longitude = header_value(request, "cloudfront-viewer-longitude")?.parse().ok()?;
+    Some((latitude, longitude))
+}
+
+/// Whether moving between two sightings implies an impossible speed
+pub fn is_impossible(previous: &Sighting, current: &Sighting, config: &ImpossibleTravelConfig) -> bool {
+    let distance = previous.distance_km(current);
+    if distance < config.min_distance_km {
+        return false;
+    }
+
+    let hours = (current.timestamp - previous.timestamp).abs() as f64 / 3_600_000.0;
+    hours == 0.0 || distance / hours > config.max_speed_kmh
+}
+
+/// Compares the event location with the user's previous one and stores it
+pub async fn apply(
+    payload: &mut IngestEventPayload,
+    location: Option<(f64, f64)>,
+    store: &dyn LocationStore,
+    config: &ImpossibleTravelConfig,
+) {
+    let (Some(key), Some((latitude, longitude))) = (lookup_key(payload, location), location) else {
+        return;
+    };
+    let current = Sighting {
+        latitude,
+        longitude,
+        timestamp: payload.timestamp,
+    };
+
+    match store.record(&key, current).await {
+        Ok(Some(previous)) => {
+            payload.impossible_travel = Some(is_impossible(&previous, &current, config));
+        }
+        Ok(None) => {}
+        Err(e) => {
+            tracing::warn!("Failed to update location store: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BERLIN: (f64, f64) = (52.52, 13.405);
+    const MUNICH: (f64, f64) = (48.137, 11.575);
+    const NEW_YORK: (f64, f64) = (40.713, -74.006);
+
+    fn event(timestamp: i64) -> IngestEventPayload {
+        IngestEventPayload {
+            project_id: "proj".to_string(),
+            user_id: Some("u1".to_string()),
+            timestamp,
+            ..Default::default()
+        }
+    }
+
+    const HOUR: i64 = 3_600_000;
+
+    #[tokio::test]
+    async fn test_normal_travel_sequence() {
+        let config = ImpossibleTravelConfig::default();
+        let store = InMemoryLocationStore::default();
+
+        let mut first = event(0);
+        let mut second = event(5 * HOUR);
+        apply(&mut first, Some(BERLIN), &store, &config).await;
+        apply(&mut second, Some(MUNICH), &store, &config).await;
+
+        assert_eq!(first.impossible_travel, None);
+        assert_eq!(second.impossible_travel, Some(false));
+    }
+
+    #[tokio::test]
+    async fn test_impossible_travel_sequence() {
+        let config = ImpossibleTravelConfig::default();
+        let store = InMemoryLocationStore::default();
+
+        let mut first = event(0);
+        let mut second = event(HOUR);
+        let mut third = event(HOUR + 60_000);
+        apply(&mut first, Some(BERLIN), &store, &config).await;
+        apply(&mut second, Some(NEW_YORK), &store, &config).await;
+        // Staying put in New York afterwards is fine
+        apply(&mut third, Some(NEW_YORK), &store, &config).await;
+
+        assert_eq!(second.impossible_travel, Some(true));
+        assert_eq!(third.impossible_travel, Some(false));
+    }
+
+    #[test]
+    fn test_distance() {
+        let berlin = Sighting {
+            latitude: BERLIN.0,
+            longitude: BERLIN.1,
+            timestamp: 0,
+        };
+        let new_york = Sighting {
+            latitude: NEW_YORK.0,
+            longitude: NEW_YORK.1,
+            timestamp: 0,
+        };
+        let distance = berlin.distance_km(&new_york);
+        assert!((6350.0..6420.0).contains(&distance), "{}", distance);
+    }
+}
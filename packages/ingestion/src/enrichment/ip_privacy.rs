@@ -0,0 +1,173 @@
+//! IP anonymization.
+//!
+//! Decides what of `context.ip` reaches the stream. `IP_PRIVACY_MODE` sets
+//! the default and `IP_PRIVACY_PROJECTS` (a JSON object of project id to
+//! mode) overrides it per project:
+//!
+//! - `keep` (default): the raw address
+//! - `truncate`: the network only, IPv4 to /24 and IPv6 to /48
+//! - `hash`: a salted SHA-256 (`IP_HASH_SALT`), still joinable across events
+//!
+//! Runs after every enrichment that reads the address (bot filtering,
+//! daily visitor ids, GeoIP), so they still see the full IP.
+
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::models::IngestEventPayload;
+use crate::shared::{env_json, env_or, env_var};
+
+/// How an IP address is stored
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IpMode {
+    #[default]
+    Keep,
+    Truncate,
+    Hash,
+}
+
+impl std::str::FromStr for IpMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "keep" => Ok(Self::Keep),
+            "truncate" => Ok(Self::Truncate),
+            "hash" => Ok(Self::Hash),
+            other => Err(format!("unknown IP privacy mode \"{}\"", other)),
+        }
+    }
+}
+
+/// Configuration for IP anonymization
+#[derive(Debug, Clone, Default)]
+pub struct IpPrivacyConfig {
+    pub mode: IpMode,
+    /// Per-project overrides of `mode`
+    pub projects: HashMap<String, IpMode>,
+    pub salt: String,
+}
+
+impl IpPrivacyConfig {
+    pub fn from_env() -> Self {
+        Self {
+            mode: env_or("IP_PRIVACY_MODE", IpMode::Keep),
+            projects: env_json("IP_PRIVACY_PROJECTS").unwrap_or_default(),
+            salt: env_var("IP_HASH_SALT").unwrap_or_default(),
+        }
+    }
+
+    /// Whether any project has its addresses rewritten
+    pub fn enabled(&self) -> bool {
+        self.mode != IpMode::Keep || self.projects.values().any(|mode| *mode != IpMode::Keep)
+    }
+
+    pub fn mode_for(&self, project_id: &str) -> IpMode {
+        self.projects.get(project_id).copied().unwrap_or(self.mode)
+    }
+}
+
+/// The address with its host bits zeroed: /24 for IPv4, /48 for IPv6
+pub fn truncate(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(ip) => IpAddr::V4(Ipv4Addr::from(u32::from(ip) & 0xffff_ff00)),
+        IpAddr::V6(ip) => IpAddr::V6(Ipv6Addr::from(u128::from(ip) & !((1u128 << 80) - 1))),
+    }
+}
+
+/// Salted SHA-256 of an address, hex encoded
+pub fn hash(salt: &str, ip: IpAddr) -> String {
+    let digest = Sha256::new()
+        .chain_update(salt.as_bytes())
+        .chain_update(b":")
+        .chain_update(ip.to_string().as_bytes())
+        .finalize();
+    hex::encode(digest)
+}
+
+/// Rewrites `context.ip` according to the project's mode. Values that
+/// don't parse as an address are removed rather than passed through.
+pub fn apply(payload: &mut IngestEventPayload, config: &IpPrivacyConfig) {
+    let mode = config.mode_for(&payload.project_id);
+    let Some(context) = payload.context.as_mut().filter(|_| mode != IpMode::Keep) else {
+        return;
+    };
+    let Some(raw) = context.ip.take() else {
+        return;
+    };
+
+    context.ip = raw.trim().parse::<IpAddr>().ok().map(|ip| match mode {
+        IpMode::Truncate => truncate(ip).to_string(),
+        IpMode::Hash => hash(&config.salt, ip),
+        IpMode::Keep => ip.to_string(),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::EventContext;
+
+    fn payload(project_id: &str, ip: &str) -> IngestEventPayload {
+        IngestEventPayload {
+            project_id: project_id.to_string(),
+            context: Some(EventContext {
+                ip: Some(ip.to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn stored_ip(config: &IpPrivacyConfig, project_id: &str, ip: &str) -> Option<String> {
+        let mut event = payload(project_id, ip);
+        apply(&mut event, config);
+        event.context.unwrap().ip
+    }
+
+    #[test]
+    fn test_truncates_to_network() {
+        let config = IpPrivacyConfig {
+            mode: IpMode::Truncate,
+            ..Default::default()
+        };
+        assert_eq!(stored_ip(&config, "proj", "81.2.69.142").as_deref(), Some("81.2.69.0"));
+        assert_eq!(
+            stored_ip(&config, "proj", "2001:db8:85a3:8d3:1319:8a2e:370:7348").as_deref(),
+            Some("2001:db8:85a3::")
+        );
+        assert_eq!(stored_ip(&config, "proj", "garbage"), None);
+    }
+
+    #[test]
+    fn test_hash_is_salted_and_stable() {
+        let config = |salt: &str| IpPrivacyConfig {
+            mode: IpMode::Hash,
+            salt: salt.to_string(),
+            ..Default::default()
+        };
+        let first = stored_ip(&config("s1"), "proj", "81.2.69.142").unwrap();
+        assert_eq!(first.len(), 64);
+        assert_eq!(stored_ip(&config("s1"), "proj", " 81.2.69.142 ").unwrap(), first);
+        assert_ne!(stored_ip(&config("s2"), "proj", "81.2.69.142").unwrap(), first);
+    }
+
+    #[test]
+    fn test_project_overrides_default() {
+        let config = IpPrivacyConfig {
+            mode: IpMode::Keep,
+            projects: HashMap::from([("eu-shop".to_string(), IpMode::Truncate)]),
+            ..Default::default()
+        };
+        assert!(config.enabled());
+        assert_eq!(stored_ip(&config, "eu-shop", "81.2.69.142").as_deref(), Some("81.2.69.0"));
+        assert_eq!(stored_ip(&config, "us-shop", "81.2.69.142").as_deref(), Some("81.2.69.142"));
+
+        let projects: HashMap<String, IpMode> = serde_json::from_str(r#"{"eu-shop":"hash"}"#).unwrap();
+        assert_eq!(projects["eu-shop"], IpMode::Hash);
+        assert!(!IpPrivacyConfig::default().enabled());
+    }
+}
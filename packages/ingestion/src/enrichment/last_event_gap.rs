@@ -0,0 +1,215 @@
+//! Time since a user's previous event.
+//!
+//! Stamps `ms_since_last_event` on each event, `null` for a user's first
+//! ever event. The per-user last-seen timestamp is kept in a
+//! [`LastSeenStore`] and only moves forward (conditional update), so
+//! out-of-order deliveries never rewind it.
+
+use async_trait::async_trait;
+use aws_sdk_dynamodb::operation::update_item::UpdateItemError;
+use aws_sdk_dynamodb::types::{AttributeValue, ReturnValue, ReturnValuesOnConditionCheckFailure};
+use aws_sdk_dynamodb::Client as DynamoClient;
+use lambda_http::Error;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::models::IngestEventPayload;
+use crate::shared::{env_flag, env_var};
+
+/// Configuration for the time-since-previous-event enrichment
+#[derive(Debug, Clone, Default)]
+pub struct LastEventGapConfig {
+    pub enabled: bool,
+    /// DynamoDB table backing the store; in-memory when unset
+    pub table_name: Option<String>,
+}
+
+impl LastEventGapConfig {
+    pub fn from_env() -> Self {
+        Self {
+            enabled: env_flag("LAST_EVENT_GAP_ENABLED"),
+            table_name: env_var("LAST_SEEN_TABLE"),
+        }
+    }
+}
+
+/// Per-user last-seen timestamp store
+#[async_trait]
+pub trait LastSeenStore: Send + Sync {
+    /// Records `timestamp` for `key` if it is newer than the stored value.
+    /// Returns the previously stored timestamp, if any.
+    async fn record(&self, key: &str, timestamp: i64) -> Result<Option<i64>, Error>;
+    async fn get(&self, key: &str) -> Result<Option<i64>, Error>;
+}
+
+/// Process-local store, used in tests and when no table is configured
+#[derive(Debug, Default)]
+pub struct InMemoryLastSeenStore {
+    entries: Mutex<HashMap<String, i64>>,
+}
+
+#[async_trait]
+impl LastSeenStore for InMemoryLastSeenStore {
+    async fn record(&self, key: &str, timestamp: i64) -> Result<Option<i64>, Error> {
+        let mut entries = self.entries.lock().unwrap();
+        let previous = entries.get(key).copied();
+        if previous.is_none_or(|previous| previous < timestamp) {
+            entries.insert(key.to_string(), timestamp);
+        }
+        Ok(previous)
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<i64>, Error> {
+        Ok(self.entries.lock().unwrap().get(key).copied())
+    }
+}
+
+/// DynamoDB-backed store
+/// Table schema: partition key `pk` (S), attribute `last_seen` (N)
+pub struct DynamoLastSeenStore {
+    client: DynamoClient,
+    table_name: String,
+}
+
+impl DynamoLastSeenStore {
+    pub fn new(client: DynamoClient, table_name: String) -> Self {
+        Self { client, table_name }
+    }
+}
+
+/// The timestamp stored in an item
+pub fn last_seen_attribute(item: Option<&HashMap<String, AttributeValue>>) -> Option<i64> {
+    item.and_then(|item| item.get("last_seen"))
+        .and_then(|value| value.as_n().ok())
+        .and_then(|n| n.parse().ok())
+}
+
+#[async_trait]
+impl LastSeenStore for DynamoLastSeenStore {
+    async fn record(&self, key: &str, timestamp: i64) -> Result<Option<i64>, Error> {
+        let result = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("pk", AttributeValue::S(key.to_string()))
+            .update_expression("SET last_seen = :ts")
+            .condition_expression("attribute_not_exists(last_seen) OR last_seen < :ts")
+            .expression_attribute_values(":ts", AttributeValue::N(timestamp.to_string()))
+            .return_values(ReturnValue::UpdatedOld)
+            .return_values_on_condition_check_failure(ReturnValuesOnConditionCheckFailure::AllOld)
+            .send()
+            .await;
+
+        match result {
+            Ok(output) => Ok(last_seen_attribute(output.attributes())),
+            Err(err) => match err.into_service_error() {
+                // Stored value is newer than this event
+                UpdateItemError::ConditionalCheckFailedException(e) => {
+                    Ok(last_seen_attribute(e.item()))
+                }
+                other => Err(other.into()),
+            },
+        }
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<i64>, Error> {
+        let output = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("pk", AttributeValue::S(key.to_string()))
+            .consistent_read(true)
+            .send()
+            .await?;
+        Ok(last_seen_attribute(output.item()))
+    }
+}
+
+/// The key `apply` looks up for this event: the user (or anonymous) id
+pub fn lookup_key(payload: &IngestEventPayload) -> Option<String> {
+    let user = payload.user_id.as_ref().or(payload.anonymous_id.as_ref())?;
+    Some(format!("{}#{}", payload.project_id, user))
+}
+
+/// Stamps `ms_since_last_event` using the user (or anonymous) id as key
+pub async fn apply(payload: &mut IngestEventPayload, store: &dyn LastSeenStore) {
+    let Some(key) = lookup_key(payload) else {
+        return;
+    };
+
+    match store.record(&key, payload.timestamp).await {
+        Ok(previous) => {
+            // Out-of-order events (older than the stored value) get no gap
+            let gap = previous
+                .map(|previous| payload.timestamp - previous)
+                .filter(|gap| *gap >= 0);
+            payload.ms_since_last_event = match previous {
+                None => Some(None),
+                Some(_) => gap.map(Some),
+            };
+        }
+        Err(e) => {
+            tracing::warn!("Failed to update last-seen store: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(user_id: &str, timestamp: i64) -> IngestEventPayload {
+        IngestEventPayload {
+            project_id: "proj".to_string(),
+            user_id: Some(user_id.to_string()),
+            timestamp,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_first_event_is_null() {
+        let store = InMemoryLastSeenStore::default();
+        let mut first = event("u1", 1_000);
+
+        apply(&mut first, &store).await;
+
+        assert_eq!(first.ms_since_last_event, Some(None));
+        let json = serde_json::to_value(&first).unwrap();
+        assert!(json["msSinceLastEvent"].is_null());
+        assert!(json.as_object().unwrap().contains_key("msSinceLastEvent"));
+    }
+
+    #[tokio::test]
+    async fn test_subsequent_events_get_gap() {
+        let store = InMemoryLastSeenStore::default();
+        let mut first = event("u1", 1_000);
+        let mut second = event("u1", 4_500);
+        let mut other_user = event("u2", 5_000);
+        let mut third = event("u1", 10_000);
+
+        apply(&mut first, &store).await;
+        apply(&mut second, &store).await;
+        apply(&mut other_user, &store).await;
+        apply(&mut third, &store).await;
+
+        assert_eq!(second.ms_since_last_event, Some(Some(3_500)));
+        assert_eq!(other_user.ms_since_last_event, Some(None));
+        assert_eq!(third.ms_since_last_event, Some(Some(5_500)));
+    }
+
+    #[tokio::test]
+    async fn test_out_of_order_event_does_not_rewind() {
+        let store = InMemoryLastSeenStore::default();
+        let mut late = event("u1", 10_000);
+        let mut early = event("u1", 2_000);
+        let mut next = event("u1", 11_000);
+
+        apply(&mut late, &store).await;
+        apply(&mut early, &store).await;
+        apply(&mut next, &store).await;
+
+        assert_eq!(early.ms_since_last_event, None);
+        assert_eq!(next.ms_since_last_event, Some(Some(1_000)));
+    }
+}
@@ -0,0 +1,130 @@
+//! Mixpanel-style `$set` / `$set_once` compatibility.
+//!
+//! Clients migrating from Mixpanel put identity traits under
+//! `properties.$set` (and `$set_once`) on track events. In compat mode
+//! those sub-objects are lifted out of the properties into `traits` /
+//! `traits_set_once`, and optionally a synthetic `identify` event carrying
+//! them is emitted alongside the original.
+
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::models::IngestEventPayload;
+use crate::shared::env_flag;
+
+/// Configuration for legacy trait folding
+#[derive(Debug, Clone, Default)]
+pub struct LegacyTraitsConfig {
+    pub enabled: bool,
+    /// Emit a synthetic identify event for events that carried traits
+    pub emit_identify: bool,
+}
+
+impl LegacyTraitsConfig {
+    pub fn from_env() -> Self {
+        Self {
+            enabled: env_flag("LEGACY_SET_COMPAT_ENABLED"),
+            emit_identify: env_flag("LEGACY_SET_EMIT_IDENTIFY"),
+        }
+    }
+}
+
+/// Removes an object-valued property, leaving other shapes in place
+fn take_object(properties: &mut HashMap<String, Value>, key: &str) -> Option<HashMap<String, Value>> {
+    if !properties.get(key).is_some_and(Value::is_object) {
+        return None;
+    }
+    match properties.remove(key) {
+        Some(Value::Object(fields)) => Some(fields.into_iter().collect()),
+        _ => None,
+    }
+}
+
+/// Lifts `$set`/`$set_once` into traits. Returns the synthetic identify
+/// event when one should be emitted.
+pub fn apply(payload: &mut IngestEventPayload, config: &LegacyTraitsConfig) -> Option<IngestEventPayload> {
+    let properties = payload.properties.as_mut()?;
+    let set = take_object(properties, "$set");
+    let set_once = take_object(properties, "$set_once");
+    if set.is_none() && set_once.is_none() {
+        return None;
+    }
+
+    payload.traits = set;
+    payload.traits_set_once = set_once;
+
+    config.emit_identify.then(|| IngestEventPayload {
+        project_id: payload.project_id.clone(),
+        event_type: "identify".to_string(),
+        timestamp: payload.timestamp,
+        user_id: payload.user_id.clone(),
+        anonymous_id: payload.anonymous_id.clone(),
+        context: payload.context.clone(),
+        traits: payload.traits.clone(),
+        traits_set_once: payload.traits_set_once.clone(),
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track() -> IngestEventPayload {
+        IngestEventPayload {
+            project_id: "proj".to_string(),
+            event_type: "signup".to_string(),
+            user_id: Some("u1".to_string()),
+            timestamp: 1_000,
+            properties: Some(HashMap::from([
+                ("plan".to_string(), serde_json::json!("pro")),
+                ("$set".to_string(), serde_json::json!({"name": "Jane", "plan": "pro"})),
+                ("$set_once".to_string(), serde_json::json!({"first_seen": "2024-01-01"})),
+            ])),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_compat_on_lifts_traits_and_emits_identify() {
+        let config = LegacyTraitsConfig {
+            enabled: true,
+            emit_identify: true,
+        };
+        let mut event = track();
+
+        let identify = apply(&mut event, &config).unwrap();
+
+        let properties = event.properties.as_ref().unwrap();
+        assert!(!properties.contains_key("$set"));
+        assert!(!properties.contains_key("$set_once"));
+        assert_eq!(properties["plan"], "pro");
+        assert_eq!(event.traits.as_ref().unwrap()["name"], "Jane");
+        assert_eq!(event.traits_set_once.as_ref().unwrap()["first_seen"], "2024-01-01");
+
+        assert_eq!(identify.event_type, "identify");
+        assert_eq!(identify.user_id.as_deref(), Some("u1"));
+        assert_eq!(identify.traits, event.traits);
+        assert_eq!(identify.properties, None);
+    }
+
+    #[tokio::test]
+    async fn test_compat_off_leaves_set_in_properties() {
+        let state = crate::shared::test_state(crate::shared::Config {
+            legacy_traits: LegacyTraitsConfig {
+                enabled: false,
+                emit_identify: true,
+            },
+            ..Default::default()
+        });
+        let request = lambda_http::http::Request::builder()
+            .body(lambda_http::Body::Empty)
+            .unwrap();
+
+        let events = crate::enrichment::apply(track(), &request, &state).await;
+
+        assert_eq!(events.len(), 1);
+        assert!(events[0].properties.as_ref().unwrap().contains_key("$set"));
+        assert_eq!(events[0].traits, None);
+    }
+}
@@ -0,0 +1,146 @@
+//! Per-event cap on store lookups.
+//!
+//! Several enrichments consult their own DynamoDB-backed store, so one event
+//! can fan out into many round trips. Each event gets a [`LookupBudget`],
+//! and the stores handed to the enrichments are wrapped in [`Budgeted`],
+//! which fails calls once the budget is spent. Enrichments already treat a
+//! failing store as "skip this stamp", so an event over the cap keeps its
+//! earlier enrichments and loses the later ones (in `apply` order) rather
+//! than waiting on more lookups. An event's lookups run one at a time, or
+//! at most `STORE_LOOKUPS_MAX_CONCURRENT` at once when they are shared by
+//! the request (see [`store_lookups`](super::store_lookups)), so the cap
+//! also bounds how long an event can spend on them.
+
+use async_trait::async_trait;
+use lambda_http::Error;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::enrichment::duplicate_view::{LastPageview, LastPageviewStore};
+use crate::enrichment::engagement::{Engagement, EngagementStore};
+use crate::enrichment::first_touch::FirstTouchStore;
+use crate::enrichment::impossible_travel::{LocationStore, Sighting};
+use crate::enrichment::last_event_gap::LastSeenStore;
+use crate::models::FirstTouch;
+
+/// Store calls left for one event
+#[derive(Debug)]
+pub struct LookupBudget {
+    remaining: AtomicUsize,
+    used: AtomicUsize,
+}
+
+impl LookupBudget {
+    pub fn new(max_calls: Option<usize>) -> Self {
+        Self {
+            remaining: AtomicUsize::new(max_calls.unwrap_or(usize::MAX)),
+            used: AtomicUsize::new(0),
+        }
+    }
+
+    /// Claims one call, failing once the budget is spent
+    pub fn take(&self) -> Result<(), Error> {
+        self.remaining
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| n.checked_sub(1))
+            .map_err(|_| "Store lookup budget for this event is spent")?;
+        self.used.fetch_add(1, Ordering::AcqRel);
+        Ok(())
+    }
+
+    /// Calls let through so far
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Acquire)
+    }
+}
+
+/// A store whose calls are charged to a budget
+pub struct Budgeted<'a, S> {
+    store: S,
+    budget: &'a LookupBudget,
+}
+
+impl<'a, S> Budgeted<'a, S> {
+    pub fn new(store: S, budget: &'a LookupBudget) -> Self {
+        Self { store, budget }
+    }
+}
+
+#[async_trait]
+impl<S> LastPageviewStore for Budgeted<'_, S>
+where
+    S: Deref<Target = dyn LastPageviewStore> + Send + Sync,
+{
+    async fn replace(&self, key: &str, pageview: LastPageview) -> Result<Option<LastPageview>, Error> {
+        self.budget.take()?;
+        self.store.replace(key, pageview).await
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<LastPageview>, Error> {
+        self.budget.take()?;
+        self.store.get(key).await
+    }
+}
+
+#[async_trait]
+impl<S> LastSeenStore for Budgeted<'_, S>
+where
+    S: Deref<Target = dyn LastSeenStore> + Send + Sync,
+{
+    async fn record(&self, key: &str, timestamp: i64) -> Result<Option<i64>, Error> {
+        self.budget.take()?;
+        self.store.record(key, timestamp).await
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<i64>, Error> {
+        self.budget.take()?;
+        self.store.get(key).await
+    }
+}
+
+#[async_trait]
+impl<S> EngagementStore for Budgeted<'_, S>
+where
+    S: Deref<Target = dyn EngagementStore> + Send + Sync,
+{
+    async fn get(&self, key: &str) -> Result<Option<Engagement>, Error> {
+        self.budget.take()?;
+        self.store.get(key).await
+    }
+
+    async fn swap(&self, key: &str, expected: Option<Engagement>, new: Engagement) -> Result<bool, Error> {
+        self.budget.take()?;
+        self.store.swap(key, expected, new).await
+    }
+}
+
+#[async_trait]
+impl<S> LocationStore for Budgeted<'_, S>
+where
+    S: Deref<Target = dyn LocationStore> + Send + Sync,
+{
+    async fn record(&self, key: &str, sighting: Sighting) -> Result<Option<Sighting>, Error> {
+        self.budget.take()?;
+        self.store.record(key, sighting).await
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Sighting>, Error> {
+        self.budget.take()?;
+        self.store.get(key).await
+    }
+}
+
+#[async_trait]
+impl<S> FirstTouchStore for Budgeted<'_, S>
+where
+    S: Deref<Target = dyn FirstTouchStore> + Send + Sync,
+{
+    async fn remember(&self, key: &str, touch: &FirstTouch) -> Result<FirstTouch, Error> {
+        self.budget.take()?;
+        self.store.remember(key, touch).await
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<FirstTouch>, Error> {
+        self.budget.take()?;
+        self.store.get(key).await
+    }
+}
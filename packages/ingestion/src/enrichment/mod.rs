@@ -0,0 +1,377 @@
+//! Optional enrichments applied after `enrich_event`.
+//!
+//! Each enrichment lives in its own module and is a no-op unless enabled
+//! through [`Config`](crate::shared::Config).
+
+use lambda_http::Request;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+use crate::auth;
+use crate::models::IngestEventPayload;
+use crate::enrichment::lookup_budget::{Budgeted, LookupBudget};
+use crate::enrichment::store_lookups::{LookupKeys, Shared, StoreLookups};
+use crate::shared::{AppState, ColdStart};
+
+pub mod bot_filter;
+pub mod bot_score;
+pub mod campaign;
+pub mod channel;
+pub mod cohort;
+pub mod company_domain;
+pub mod daily_visitor;
+pub mod duplicate_view;
+pub mod engagement;
+pub mod experiments;
+pub mod first_touch;
+pub mod geoip;
+pub mod hash_route;
+pub mod identity_hash;
+pub mod impossible_travel;
+pub mod ip_privacy;
+pub mod last_event_gap;
+pub mod legacy_traits;
+pub mod lookup_budget;
+pub mod privacy_signals;
+pub mod referrer;
+pub mod reporting_day;
+pub mod retention;
+pub mod sampling;
+pub mod sequence;
+pub mod shard_hint;
+pub mod store_lookups;
+pub mod timezone;
+pub mod units;
+pub mod url_normalize;
+pub mod user_agent;
+
+/// Runs CPU-bound enrichment work while holding a permit from the shared
+/// limit, so bursts of concurrent requests don't all parse at once
+pub async fn with_cpu_permit<T>(permits: &Semaphore, work: impl FnOnce() -> T) -> T {
+    let _permit = permits
+        .acquire()
+        .await
+        .expect("enrichment semaphore is never closed");
+    work()
+}
+
+/// Runs all enabled enrichments over an event.
+/// Returns the events to process: empty when the event should be dropped,
+/// and with any derived events (such as a synthetic identify) after it.
+#[tracing::instrument(name = "enrich", skip_all)]
+pub async fn apply(
+    mut payload: IngestEventPayload,
+    request: &Request,
+    state: &AppState,
+) -> Vec<IngestEventPayload> {
+    let config = &state.config;
+
+    // First, so a sampled-out event costs nothing more. A failed key
+    // lookup leaves only the configured rates.
+    if config.sampling.enabled || config.api_keys.project_sampling {
+        let key_rate = match auth::sampling_rate(request, state).await {
+            Ok(rate) => rate,
+            Err(e) => {
+                tracing::warn!("Sampling without the API key's rate, lookup failed: {}", e);
+                None
+            }
+        };
+        if !sampling::apply(&mut payload, key_rate, &config.sampling) {
+            return Vec::new();
+        }
+    }
+
+    let keep = with_cpu_permit(&state.enrichment_permits, || {
+        // First, so nothing below derives ids from what it strips
+        if config.privacy_signals.enabled()
+            && !privacy_signals::apply(&mut payload, request, &config.privacy_signals)
+        {
+            return false;
+        }
+
+        if !bot_score::apply(&mut payload, request, &config.bot_score) {
+            return false;
+        }
+
+        if config.bot_filter.enabled && !bot_filter::apply(&mut payload, request, &config.bot_filter) {
+            return false;
+        }
+
+        if config.timezone.enabled {
+            timezone::apply(&mut payload, request, &config.timezone);
+        }
+
+        if config.hash_route.enabled {
+            hash_route::apply(&mut payload, &config.hash_route);
+        }
+
+        if config.campaign.enabled {
+            campaign::apply(&mut payload);
+        }
+
+        if config.channel.enabled {
+            channel::apply(&mut payload, &config.channel);
+        }
+
+        if config.referrer.enabled {
+            referrer::apply(&mut payload, &config.channel);
+        }
+
+        // After the steps above, which read the parameters it strips
+        if config.url_normalize.enabled {
+            url_normalize::apply(&mut payload, &config.url_normalize);
+        }
+
+        // Needs the raw email, so before identity hashing
+        if config.company_domain.enabled {
+            company_domain::apply(&mut payload, &config.company_domain);
+        }
+
+        if config.identity_hash.enabled {
+            identity_hash::apply(&mut payload, &config.identity_hash);
+        }
+
+        if config.units.enabled {
+            units::apply(&mut payload, &config.units);
+        }
+
+        if config.experiments.enabled {
+            experiments::apply(&mut payload, &config.experiments);
+        }
+
+        // Before the per-user stores, which key on anonymous_id
+        if config.daily_visitor.enabled {
+            daily_visitor::apply(&mut payload, &config.daily_visitor);
+        }
+
+        // Last, since it may drop the IP the steps above read
+        if let Some(geoip) = state.geoip.as_deref().filter(|_| config.geoip.enabled) {
+            geoip::apply(&mut payload, geoip, &config.geoip);
+        }
+
+        if config.ip_privacy.enabled() {
+            ip_privacy::apply(&mut payload, &config.ip_privacy);
+        }
+
+        true
+    })
+    .await;
+    if !keep {
+        return Vec::new();
+    }
+
+    // After daily visitor ids, which can stand in for the session
+    if config.sequence.enabled {
+        sequence::apply(&mut payload, &state.session_sequences, &config.sequence);
+    }
+
+    if config.cold_start_tracking {
+        let cold_start = request.extensions().get::<ColdStart>().is_some_and(|c| c.0);
+        payload.cold_start = Some(cold_start);
+    }
+
+    // Store-backed steps share the event's lookup budget, in this order,
+    // and the request's lookups when they are shared
+    let budget = LookupBudget::new(config.store_lookups.max_per_event);
+    let lookups = request.extensions().get::<Arc<StoreLookups>>().map(Arc::as_ref);
+    if let Some(lookups) = lookups {
+        lookups.prefetch(&LookupKeys::of(&payload, request, config), state, &budget).await;
+    }
+
+    if config.duplicate_view.enabled
+        && !duplicate_view::apply(
+            &mut payload,
+            &Shared::new(Budgeted::new(state.last_pageview_store.as_ref(), &budget), lookups),
+            &config.duplicate_view,
+        )
+        .await
+    {
+        return Vec::new();
+    }
+
+    if config.last_event_gap.enabled {
+        let store = Shared::new(Budgeted::new(state.last_seen_store.as_ref(), &budget), lookups);
+        last_event_gap::apply(&mut payload, &store).await;
+    }
+
+    if config.engagement.enabled {
+        engagement::apply(
+            &mut payload,
+            &Shared::new(Budgeted::new(state.engagement_store.as_ref(), &budget), lookups),
+            &config.engagement,
+        )
+        .await;
+    }
+
+    if config.impossible_travel.enabled {
+        impossible_travel::apply(
+            &mut payload,
+            impossible_travel::viewer_location(request),
+            &Shared::new(Budgeted::new(state.location_store.as_ref(), &budget), lookups),
+            &config.impossible_travel,
+        )
+        .await;
+    }
+
+    if config.first_touch.enabled {
+        first_touch::apply(
+            &mut payload,
+            &Shared::new(Budgeted::new(state.first_touch_store.as_ref(), &budget), lookups),
+            &config.first_touch,
+        )
+        .await;
+    }
+
+    // After the stores, so the identify shares the track's ids, but before
+    // cohort bucketing so strict mode strips them from both
+    let mut derived = None;
+    if config.legacy_traits.enabled {
+        derived = legacy_traits::apply(&mut payload, &config.legacy_traits);
+    }
+
+    // Last: strict mode removes the ids the stores above key on
+    let mut events: Vec<_> = std::iter::once(payload).chain(derived).collect();
+    if config.cohort.enabled {
+        for event in &mut events {
+            cohort::apply(event, &config.cohort);
+        }
+    }
+
+    if config.shard_hint.enabled {
+        for event in &mut events {
+            shard_hint::apply(event, &config.shard_hint, &config.partitioning);
+        }
+    }
+
+    if config.retention.enabled {
+        let key_days = match auth::retention_days(request, state).await {
+            Ok(days) => days,
+            Err(e) => {
+                tracing::warn!("Tagging retention without the API key's, lookup failed: {}", e);
+                None
+            }
+        };
+        for event in &mut events {
+            retention::apply(event, key_days, &config.retention);
+        }
+    }
+
+    if config.reporting_day.enabled {
+        let key_timezone = match auth::timezone(request, state).await {
+            Ok(timezone) => timezone,
+            Err(e) => {
+                tracing::warn!("Dating events without the API key's timezone, lookup failed: {}", e);
+                None
+            }
+        };
+        for event in &mut events {
+            reporting_day::apply(event, key_timezone.as_deref(), &config.reporting_day);
+        }
+    }
+
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn test_cpu_permits_bound_concurrency() {
+        let permits = Arc::new(Semaphore::new(2));
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+
+        let tasks: Vec<_> = (0..32)
+            .map(|_| {
+                let (permits, in_flight, peak) = (permits.clone(), in_flight.clone(), peak.clone());
+                tokio::spawn(async move {
+                    with_cpu_permit(&permits, || {
+                        let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                        peak.fetch_max(now, Ordering::SeqCst);
+                        std::thread::sleep(Duration::from_millis(5));
+                        in_flight.fetch_sub(1, Ordering::SeqCst);
+                    })
+                    .await
+                })
+            })
+            .collect();
+
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        assert!(peak.load(Ordering::SeqCst) <= 2);
+        assert!(peak.load(Ordering::SeqCst) >= 1);
+    }
+
+    /// Runs one pageview through every store-backed enrichment, returning
+    /// the enriched event and the number of store calls it made
+    async fn store_calls(cap: Option<usize>) -> (IngestEventPayload, usize) {
+        use crate::enrichment::duplicate_view::{InMemoryLastPageviewStore, LastPageviewStore};
+        use crate::enrichment::engagement::{EngagementStore, InMemoryEngagementStore};
+        use crate::enrichment::impossible_travel::{InMemoryLocationStore, LocationStore};
+        use crate::enrichment::last_event_gap::{InMemoryLastSeenStore, LastSeenStore};
+        use crate::models::{EventContext, PageContext};
+
+        let mut config = crate::shared::Config {
+            ..Default::default()
+        };
+        config.store_lookups.max_per_event = cap;
+        config.duplicate_view.enabled = true;
+        config.last_event_gap.enabled = true;
+        config.engagement.enabled = true;
+        config.impossible_travel.enabled = true;
+
+        // An uncapped outer budget only counts the calls reaching the stores
+        let counter: &'static LookupBudget = Box::leak(Box::new(LookupBudget::new(None)));
+        let mut state = crate::shared::test_state(config);
+        let last_pageview: Arc<dyn LastPageviewStore> = Arc::new(InMemoryLastPageviewStore::default());
+        let last_seen: Arc<dyn LastSeenStore> = Arc::new(InMemoryLastSeenStore::default());
+        let engagement: Arc<dyn EngagementStore> = Arc::new(InMemoryEngagementStore::default());
+        let location: Arc<dyn LocationStore> = Arc::new(InMemoryLocationStore::default());
+        state.last_pageview_store = Arc::new(Budgeted::new(last_pageview, counter));
+        state.last_seen_store = Arc::new(Budgeted::new(last_seen, counter));
+        state.engagement_store = Arc::new(Budgeted::new(engagement, counter));
+        state.location_store = Arc::new(Budgeted::new(location, counter));
+
+        let request = lambda_http::http::Request::builder()
+            .header("CloudFront-Viewer-Latitude", "52.52")
+            .header("CloudFront-Viewer-Longitude", "13.405")
+            .body(lambda_http::Body::Empty)
+            .unwrap();
+        let pageview = IngestEventPayload {
+            project_id: "proj".to_string(),
+            event_type: "pageview".to_string(),
+            anonymous_id: Some("anon-1".to_string()),
+            timestamp: 1_000,
+            context: Some(EventContext {
+                page: Some(PageContext {
+                    url: Some("https://shop.io/".to_string()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let mut events = apply(pageview, &request, &state).await;
+        (events.remove(0), counter.used())
+    }
+
+    #[tokio::test]
+    async fn test_store_calls_stay_within_cap() {
+        let (uncapped, calls) = store_calls(None).await;
+        assert_eq!(calls, 4);
+        assert_eq!(uncapped.engaged_time_ms, Some(0));
+
+        let (capped, calls) = store_calls(Some(2)).await;
+        assert_eq!(calls, 2);
+        // Earlier steps keep their stamps, later ones are skipped
+        assert_eq!(capped.ms_since_last_event, Some(None));
+        assert_eq!(capped.engaged_time_ms, None);
+    }
+}
@@ -0,0 +1,182 @@
+//! Do Not Track and Global Privacy Control.
+//!
+//! A request carrying `DNT: 1` or `Sec-GPC: 1` is an opt-out. What that means
+//! is a per-project policy: `PRIVACY_SIGNAL_POLICY` sets the default and
+//! `PRIVACY_SIGNAL_PROJECTS` (a JSON object of project id to policy)
+//! overrides it:
+//!
+//! - `ignore` (default): ingest as usual
+//! - `tag`: ingest, stamped `opted_out: true`
+//! - `strip`: tag and remove everything identifying (ids, traits, IP, user
+//!   agent), keeping the event for aggregate counts
+//! - `drop`: discard the event
+//!
+//! Runs before the other enrichments so nothing is derived from the
+//! identifiers it strips.
+
+use lambda_http::Request;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::models::IngestEventPayload;
+use crate::shared::{env_json, env_or, header_value};
+
+/// Handling of events from users who opted out
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SignalPolicy {
+    #[default]
+    Ignore,
+    Tag,
+    Strip,
+    Drop,
+}
+
+impl std::str::FromStr for SignalPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "ignore" => Ok(Self::Ignore),
+            "tag" => Ok(Self::Tag),
+            "strip" => Ok(Self::Strip),
+            "drop" => Ok(Self::Drop),
+            other => Err(format!("unknown privacy signal policy \"{}\"", other)),
+        }
+    }
+}
+
+/// Configuration for privacy signal handling
+#[derive(Debug, Clone, Default)]
+pub struct PrivacySignalConfig {
+    pub policy: SignalPolicy,
+    /// Per-project overrides of `policy`
+    pub projects: HashMap<String, SignalPolicy>,
+}
+
+impl PrivacySignalConfig {
+    pub fn from_env() -> Self {
+        Self {
+            policy: env_or("PRIVACY_SIGNAL_POLICY", SignalPolicy::Ignore),
+            projects: env_json("PRIVACY_SIGNAL_PROJECTS").unwrap_or_default(),
+        }
+    }
+
+    /// Whether any project acts on the signals
+    pub fn enabled(&self) -> bool {
+        self.policy != SignalPolicy::Ignore
+            || self.projects.values().any(|policy| *policy != SignalPolicy::Ignore)
+    }
+
+    pub fn policy_for(&self, project_id: &str) -> SignalPolicy {
+        self.projects.get(project_id).copied().unwrap_or(self.policy)
+    }
+}
+
+/// Whether the request carries `DNT: 1` or `Sec-GPC: 1`
+pub fn opted_out(request: &Request) -> bool {
+    ["dnt", "sec-gpc"]
+        .iter()
+        .any(|header| header_value(request, header) == Some("1"))
+}
+
+/// Removes the fields that identify a person or device
+pub fn strip_identifiers(payload: &mut IngestEventPayload) {
+    payload.user_id = None;
+    payload.anonymous_id = None;
+    payload.previous_id = None;
+    payload.resolved_user_id = None;
+    payload.traits = None;
+    payload.traits_set_once = None;
+    if let Some(context) = payload.context.as_mut() {
+        context.ip = None;
+        context.user_agent = None;
+    }
+}
+
+/// Applies the project's policy to an opted-out request.
+/// Returns `false` when the event should be dropped.
+pub fn apply(payload: &mut IngestEventPayload, request: &Request, config: &PrivacySignalConfig) -> bool {
+    let policy = config.policy_for(&payload.project_id);
+    if policy == SignalPolicy::Ignore || !opted_out(request) {
+        return true;
+    }
+
+    match policy {
+        SignalPolicy::Drop => {
+            tracing::debug!("Dropping event from opted-out user");
+            return false;
+        }
+        SignalPolicy::Strip => strip_identifiers(payload),
+        SignalPolicy::Tag | SignalPolicy::Ignore => {}
+    }
+    payload.opted_out = Some(true);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::EventContext;
+    use lambda_http::Body;
+
+    fn request(headers: &[(&str, &str)]) -> Request {
+        let mut builder = lambda_http::http::Request::builder();
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        builder.body(Body::Empty).unwrap()
+    }
+
+    fn payload(project_id: &str) -> IngestEventPayload {
+        IngestEventPayload {
+            project_id: project_id.to_string(),
+            user_id: Some("u1".to_string()),
+            anonymous_id: Some("a1".to_string()),
+            context: Some(EventContext {
+                ip: Some("81.2.69.142".to_string()),
+                user_agent: Some("Mozilla/5.0".to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_policies() {
+        let config = PrivacySignalConfig {
+            policy: SignalPolicy::Tag,
+            projects: HashMap::from([
+                ("strict".to_string(), SignalPolicy::Drop),
+                ("aggregate".to_string(), SignalPolicy::Strip),
+                ("legacy".to_string(), SignalPolicy::Ignore),
+            ]),
+        };
+        let gpc = request(&[("Sec-GPC", "1")]);
+
+        let mut event = payload("shop");
+        assert!(apply(&mut event, &gpc, &config));
+        assert_eq!(event.opted_out, Some(true));
+        assert_eq!(event.user_id.as_deref(), Some("u1"));
+
+        let mut event = payload("aggregate");
+        assert!(apply(&mut event, &request(&[("DNT", "1")]), &config));
+        assert_eq!(event.opted_out, Some(true));
+        assert!(event.user_id.is_none() && event.anonymous_id.is_none());
+        assert!(event.context.unwrap().ip.is_none());
+
+        assert!(!apply(&mut payload("strict"), &gpc, &config));
+
+        let mut event = payload("legacy");
+        assert!(apply(&mut event, &gpc, &config));
+        assert_eq!(event.opted_out, None);
+    }
+
+    #[test]
+    fn test_only_explicit_opt_outs_count() {
+        assert!(opted_out(&request(&[("DNT", "1")])));
+        assert!(opted_out(&request(&[("Sec-GPC", " 1 ")])));
+        assert!(!opted_out(&request(&[("DNT", "0")])));
+        assert!(!opted_out(&request(&[])));
+    }
+}
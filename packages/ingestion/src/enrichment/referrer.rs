@@ -0,0 +1,212 @@
+//! Referrer classification for pageviews.
+//!
+//! Stamps `referrer_type` and `referrer_source` on pageview properties:
+//!
+//! - `direct`: no referrer
+//! - `internal`: the page's own host, or a subdomain of it
+//! - `email`: a webmail client (checked before search, since
+//!   `mail.google.com` is also a Google host)
+//! - `search` / `social`: a host in the search-engine / social-network
+//!   lists shared with [channel classification](super::channel)
+//! - `external`: anything else
+//!
+//! `referrer_source` names the engine or network (`Google`, `Facebook`),
+//! falling back to the referrer's host for external sites and entries
+//! added to the lists. Unlike `channel`, UTM parameters play no part: this
+//! describes where the browser actually came from.
+
+use url::Url;
+
+use crate::enrichment::channel::{in_domains, ChannelConfig};
+use crate::enrichment::duplicate_view::page_url;
+use crate::models::IngestEventPayload;
+use crate::shared::env_flag;
+
+const WEBMAIL_HOSTS: &[&str] = &[
+    "mail.google.com",
+    "mail.yahoo.com",
+    "mail.proton.me",
+    "outlook.live.com",
+    "outlook.office.com",
+    "outlook.office365.com",
+];
+
+/// Display names for well-known referrers, by domain list entry
+const SOURCE_NAMES: &[(&str, &str)] = &[
+    ("baidu.com", "Baidu"),
+    ("bing.com", "Bing"),
+    ("duckduckgo.com", "DuckDuckGo"),
+    ("ecosia.org", "Ecosia"),
+    ("google.", "Google"),
+    ("search.brave.com", "Brave"),
+    ("yahoo.", "Yahoo"),
+    ("yandex.", "Yandex"),
+    ("facebook.com", "Facebook"),
+    ("instagram.com", "Instagram"),
+    ("linkedin.com", "LinkedIn"),
+    ("lnkd.in", "LinkedIn"),
+    ("pinterest.com", "Pinterest"),
+    ("reddit.com", "Reddit"),
+    ("t.co", "Twitter"),
+    ("tiktok.com", "TikTok"),
+    ("twitter.com", "Twitter"),
+    ("x.com", "X"),
+    ("youtube.com", "YouTube"),
+    ("mail.google.com", "Gmail"),
+    ("mail.yahoo.com", "Yahoo Mail"),
+    ("mail.proton.me", "Proton Mail"),
+    ("outlook.live.com", "Outlook"),
+    ("outlook.office.com", "Outlook"),
+    ("outlook.office365.com", "Outlook"),
+];
+
+/// Configuration for referrer classification
+#[derive(Debug, Clone, Default)]
+pub struct ReferrerConfig {
+    pub enabled: bool,
+}
+
+impl ReferrerConfig {
+    pub fn from_env() -> Self {
+        Self {
+            enabled: env_flag("REFERRER_CLASSIFICATION_ENABLED"),
+        }
+    }
+}
+
+/// A referrer's category and source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Classification {
+    pub referrer_type: &'static str,
+    pub source: Option<String>,
+}
+
+/// Name of the list entry `host` matches, or the host itself
+fn source_name(host: &str, domains: &[String]) -> String {
+    domains
+        .iter()
+        .find(|domain| in_domains(host, std::slice::from_ref(domain)))
+        .and_then(|domain| SOURCE_NAMES.iter().find(|(entry, _)| entry == domain))
+        .map_or_else(|| host.trim_start_matches("www.").to_string(), |(_, name)| name.to_string())
+}
+
+/// Classifies a referrer relative to the page it led to
+pub fn classify(url: Option<&str>, referrer: Option<&str>, config: &ChannelConfig) -> Classification {
+    let direct = Classification {
+        referrer_type: "direct",
+        source: None,
+    };
+    let Some(host) = referrer
+        .and_then(|r| Url::parse(r).ok())
+        .and_then(|r| r.host_str().map(str::to_ascii_lowercase))
+    else {
+        return direct;
+    };
+
+    let page_host = url
+        .and_then(|u| Url::parse(u).ok())
+        .and_then(|u| u.host_str().map(str::to_ascii_lowercase));
+    if let Some(page_host) = page_host {
+        let site = page_host.trim_start_matches("www.");
+        if host.trim_start_matches("www.") == site || host.ends_with(&format!(".{}", site)) {
+            return Classification {
+                referrer_type: "internal",
+                source: Some(host),
+            };
+        }
+    }
+
+    let webmail: Vec<String> = WEBMAIL_HOSTS.iter().map(|h| h.to_string()).collect();
+    let (referrer_type, domains) = if in_domains(&host, &webmail) {
+        ("email", webmail.as_slice())
+    } else if in_domains(&host, &config.search_engines) {
+        ("search", config.search_engines.as_slice())
+    } else if in_domains(&host, &config.social_networks) {
+        ("social", config.social_networks.as_slice())
+    } else {
+        ("external", [].as_slice())
+    };
+    Classification {
+        referrer_type,
+        source: Some(source_name(&host, domains)),
+    }
+}
+
+/// Stamps `referrer_type` and `referrer_source` on pageview properties
+pub fn apply(payload: &mut IngestEventPayload, config: &ChannelConfig) {
+    if payload.event_type != "pageview" {
+        return;
+    }
+    let referrer = payload
+        .context
+        .as_ref()
+        .and_then(|c| c.page.as_ref())
+        .and_then(|p| p.referrer.as_deref())
+        .or_else(|| payload.properties.as_ref()?.get("referrer")?.as_str());
+    let classification = classify(page_url(payload), referrer, config);
+
+    let properties = payload.properties.get_or_insert_with(Default::default);
+    properties.insert("referrer_type".to_string(), classification.referrer_type.into());
+    if let Some(source) = classification.source {
+        properties.insert("referrer_source".to_string(), source.into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn classified(referrer: Option<&str>) -> (&'static str, Option<String>) {
+        let classification = classify(Some("https://www.shop.io/cart"), referrer, &ChannelConfig::default());
+        (classification.referrer_type, classification.source)
+    }
+
+    #[test]
+    fn test_categories_and_sources() {
+        assert_eq!(classified(None), ("direct", None));
+        assert_eq!(classified(Some("not a url")), ("direct", None));
+        assert_eq!(classified(Some("https://shop.io/")), ("internal", Some("shop.io".to_string())));
+        assert_eq!(
+            classified(Some("https://blog.shop.io/post")),
+            ("internal", Some("blog.shop.io".to_string()))
+        );
+        assert_eq!(classified(Some("https://www.google.co.uk/")), ("search", Some("Google".to_string())));
+        assert_eq!(classified(Some("https://mail.google.com/")), ("email", Some("Gmail".to_string())));
+        assert_eq!(classified(Some("https://t.co/abc")), ("social", Some("Twitter".to_string())));
+        assert_eq!(classified(Some("https://m.facebook.com/")), ("social", Some("Facebook".to_string())));
+        assert_eq!(
+            classified(Some("https://www.example.org/post")),
+            ("external", Some("example.org".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_configured_engines_named_by_host() {
+        let config = ChannelConfig {
+            search_engines: vec!["kagi.com".to_string()],
+            ..Default::default()
+        };
+        let classification = classify(None, Some("https://kagi.com/search?q=shoes"), &config);
+        assert_eq!(classification.referrer_type, "search");
+        assert_eq!(classification.source.as_deref(), Some("kagi.com"));
+    }
+
+    #[test]
+    fn test_stamped_on_pageview_properties() {
+        let mut event = IngestEventPayload {
+            event_type: "pageview".to_string(),
+            properties: Some(
+                [
+                    ("url".to_string(), "https://shop.io/".into()),
+                    ("referrer".to_string(), "https://duckduckgo.com/".into()),
+                ]
+                .into(),
+            ),
+            ..Default::default()
+        };
+        apply(&mut event, &ChannelConfig::default());
+        let properties = event.properties.unwrap();
+        assert_eq!(properties["referrer_type"], "search");
+        assert_eq!(properties["referrer_source"], "DuckDuckGo");
+    }
+}
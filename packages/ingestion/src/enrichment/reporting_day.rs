@@ -0,0 +1,125 @@
+//! Local reporting dates.
+//!
+//! A project's daily numbers are only right if its day starts at its own
+//! midnight. With `REPORTING_DAY_ENABLED`, each event is stamped with the
+//! `local_date` and `local_hour` its `timestamp` falls in, in the project's
+//! reporting timezone, so the aggregator's day buckets and the Parquet
+//! files' `local_date` column agree without either looking the project up.
+//!
+//! The timezone comes from `PROJECT_TIMEZONES` (`{"acme":
+//! "America/New_York"}`), else the `timezone` of the request's API key
+//! record (set through `packages/admin-api`), else
+//! `REPORTING_TIMEZONE_DEFAULT`, else UTC. Names are IANA zones; unknown
+//! ones are ignored with a warning. Daylight-saving changes are applied,
+//! so a local day can be 23 or 25 hours long.
+
+use chrono::{DateTime, Timelike};
+use chrono_tz::Tz;
+use std::collections::HashMap;
+
+use crate::models::IngestEventPayload;
+use crate::shared::{env_flag, env_json, env_var};
+
+/// Configuration for reporting dates
+#[derive(Debug, Clone, Default)]
+pub struct ReportingDayConfig {
+    pub enabled: bool,
+    /// Timezone of projects without their own; UTC when unset
+    pub default_timezone: Option<Tz>,
+    /// Timezones by project, over the API key record's
+    pub projects: HashMap<String, Tz>,
+}
+
+impl ReportingDayConfig {
+    pub fn from_env() -> Self {
+        let projects: HashMap<String, String> = env_json("PROJECT_TIMEZONES").unwrap_or_default();
+        Self {
+            enabled: env_flag("REPORTING_DAY_ENABLED"),
+            default_timezone: env_var("REPORTING_TIMEZONE_DEFAULT").and_then(|name| parse_timezone(&name)),
+            projects: projects
+                .into_iter()
+                .filter_map(|(project_id, name)| Some((project_id, parse_timezone(&name)?)))
+                .collect(),
+        }
+    }
+
+    /// A project's timezone, given what its API key record says
+    pub fn timezone_for(&self, project_id: &str, key_timezone: Option<&str>) -> Tz {
+        self.projects
+            .get(project_id)
+            .copied()
+            .or_else(|| key_timezone.and_then(parse_timezone))
+            .or(self.default_timezone)
+            .unwrap_or(Tz::UTC)
+    }
+}
+
+/// An IANA timezone by name, logging names that aren't one
+pub fn parse_timezone(name: &str) -> Option<Tz> {
+    match name.trim().parse() {
+        Ok(tz) => Some(tz),
+        Err(_) => {
+            tracing::warn!("Ignoring unknown timezone {:?}", name);
+            None
+        }
+    }
+}
+
+/// Stamps `local_date` and `local_hour` on the event
+pub fn apply(payload: &mut IngestEventPayload, key_timezone: Option<&str>, config: &ReportingDayConfig) {
+    let Some(time) = DateTime::from_timestamp_millis(payload.timestamp) else {
+        return;
+    };
+    let local = time.with_timezone(&config.timezone_for(&payload.project_id, key_timezone));
+    payload.local_date = Some(local.format("%Y-%m-%d").to_string());
+    payload.local_hour = Some(local.hour() as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stamped(
+        project_id: &str,
+        timestamp: i64,
+        key_timezone: Option<&str>,
+        config: &ReportingDayConfig,
+    ) -> (String, u8) {
+        let mut event = IngestEventPayload {
+            project_id: project_id.to_string(),
+            timestamp,
+            ..Default::default()
+        };
+        apply(&mut event, key_timezone, config);
+        (event.local_date.unwrap(), event.local_hour.unwrap())
+    }
+
+    #[test]
+    fn test_project_timezone_then_key_timezone_then_default() {
+        let mut config = ReportingDayConfig {
+            enabled: true,
+            default_timezone: Some(Tz::Europe__Berlin),
+            projects: HashMap::from([("acme".to_string(), Tz::America__New_York)]),
+        };
+        // 2023-11-15T02:30:00Z
+        let at = 1_700_015_400_000;
+
+        assert_eq!(stamped("acme", at, Some("Asia/Tokyo"), &config), ("2023-11-14".to_string(), 21));
+        assert_eq!(stamped("other", at, Some("Asia/Tokyo"), &config), ("2023-11-15".to_string(), 11));
+        assert_eq!(stamped("other", at, Some("Mars/Olympus"), &config), ("2023-11-15".to_string(), 3));
+        config.default_timezone = None;
+        assert_eq!(stamped("other", at, None, &config), ("2023-11-15".to_string(), 2));
+    }
+
+    #[test]
+    fn test_days_follow_daylight_saving() {
+        let config = ReportingDayConfig {
+            enabled: true,
+            default_timezone: Some(Tz::America__New_York),
+            ..Default::default()
+        };
+        // 04:30Z is the evening before in EST, 00:30 the same day in EDT
+        assert_eq!(stamped("p", 1_699_936_200_000, None, &config), ("2023-11-13".to_string(), 23));
+        assert_eq!(stamped("p", 1_689_481_800_000, None, &config), ("2023-07-16".to_string(), 0));
+    }
+}
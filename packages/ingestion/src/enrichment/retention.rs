@@ -0,0 +1,78 @@
+//! Per-project retention tags.
+//!
+//! With `RETENTION_TAGGING_ENABLED`, events are stamped with the number of
+//! days their project keeps raw events, so stores can expire them without
+//! looking the project up. The days come from `PROJECT_RETENTION_DAYS`
+//! (`{"acme": 30}`), else the `retention_days` of the request's API key
+//! record (set through `packages/admin-api`), else
+//! `RETENTION_DEFAULT_DAYS`; events of projects with none are left
+//! untagged. `packages/retention-purger` deletes what has expired using
+//! the same settings.
+
+use std::collections::HashMap;
+
+use crate::models::IngestEventPayload;
+use crate::shared::{env_flag, env_json, env_opt};
+
+/// Configuration for retention tagging
+#[derive(Debug, Clone, Default)]
+pub struct RetentionConfig {
+    pub enabled: bool,
+    /// Days for projects without their own
+    pub default_days: Option<u32>,
+    /// Days by project, over the API key record's
+    pub projects: HashMap<String, u32>,
+}
+
+impl RetentionConfig {
+    pub fn from_env() -> Self {
+        Self {
+            enabled: env_flag("RETENTION_TAGGING_ENABLED"),
+            default_days: env_opt("RETENTION_DEFAULT_DAYS"),
+            projects: env_json("PROJECT_RETENTION_DAYS").unwrap_or_default(),
+        }
+    }
+
+    /// A project's retention, given what its API key record says
+    pub fn days_for(&self, project_id: &str, key_days: Option<u32>) -> Option<u32> {
+        self.projects
+            .get(project_id)
+            .copied()
+            .or(key_days)
+            .or(self.default_days)
+            .filter(|days| *days > 0)
+    }
+}
+
+/// Stamps `retention_days` on the event
+pub fn apply(payload: &mut IngestEventPayload, key_days: Option<u32>, config: &RetentionConfig) {
+    payload.retention_days = config.days_for(&payload.project_id, key_days);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_project_days_then_key_days_then_default() {
+        let mut config = RetentionConfig {
+            enabled: true,
+            default_days: Some(365),
+            projects: HashMap::from([("acme".to_string(), 30)]),
+        };
+        let tagged = |project_id: &str, key_days: Option<u32>, config: &RetentionConfig| {
+            let mut event = IngestEventPayload {
+                project_id: project_id.to_string(),
+                ..Default::default()
+            };
+            apply(&mut event, key_days, config);
+            event.retention_days
+        };
+
+        assert_eq!(tagged("acme", Some(90), &config), Some(30));
+        assert_eq!(tagged("other", Some(90), &config), Some(90));
+        assert_eq!(tagged("other", None, &config), Some(365));
+        config.default_days = None;
+        assert_eq!(tagged("other", None, &config), None);
+    }
+}
@@ -0,0 +1,131 @@
+//! Per-project sampling.
+//!
+//! A project can keep only a share of its events, by event type. With
+//! `SAMPLING_ENABLED`, `SAMPLING_RATES` sets rates per project and event
+//! type, `*` standing for any type, e.g.
+//! `{"acme": {"pageview": 0.1, "*": 0.5}}`. With
+//! `API_KEY_SAMPLING_ENABLED`, a project's API key record may also carry a
+//! `sampling_rate` (set through `packages/admin-api`), which applies to
+//! the types `SAMPLING_RATES` doesn't list for the project.
+//!
+//! Events are picked by hashing their session (a `session_id` property,
+//! else the visitor), so a kept session keeps all of its sampled events.
+//! Kept events carry the rate in `sample_rate`, so consumers can weight
+//! counts by its inverse. Events without a session or visitor are kept
+//! unsampled.
+
+use std::collections::HashMap;
+
+use super::cohort;
+use super::duplicate_view::session_key;
+use crate::models::IngestEventPayload;
+use crate::shared::{env_flag, env_json};
+
+/// Buckets a session is hashed into; the rate's resolution
+const BUCKETS: u32 = 10_000;
+
+/// Configuration for sampling
+#[derive(Debug, Clone, Default)]
+pub struct SamplingConfig {
+    pub enabled: bool,
+    /// Rates by project, then event type (`*` for any), 0 to 1
+    pub rates: HashMap<String, HashMap<String, f64>>,
+}
+
+impl SamplingConfig {
+    pub fn from_env() -> Self {
+        Self {
+            enabled: env_flag("SAMPLING_ENABLED"),
+            rates: env_json("SAMPLING_RATES").unwrap_or_default(),
+        }
+    }
+
+    /// The configured rate for a project's events of a type
+    pub fn rate_for(&self, project_id: &str, event_type: &str) -> Option<f64> {
+        let rates = self.rates.get(project_id).filter(|_| self.enabled)?;
+        rates.get(event_type).or_else(|| rates.get("*")).copied()
+    }
+}
+
+/// Samples an event at the configured rate, else `key_rate`, stamping the
+/// rate on kept events. Returns `false` when the event should be dropped.
+pub fn apply(payload: &mut IngestEventPayload, key_rate: Option<f64>, config: &SamplingConfig) -> bool {
+    let rate = config
+        .rate_for(&payload.project_id, &payload.event_type)
+        .or(key_rate)
+        .map(|rate| rate.clamp(0.0, 1.0));
+    let (Some(rate), Some(session)) = (rate.filter(|rate| *rate < 1.0), session_key(payload)) else {
+        return true;
+    };
+
+    let bucket = cohort::bucket("sampling", &payload.project_id, &session, BUCKETS);
+    if f64::from(bucket) >= rate * f64::from(BUCKETS) {
+        return false;
+    }
+    payload.sample_rate = Some(rate);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> SamplingConfig {
+        SamplingConfig {
+            enabled: true,
+            rates: HashMap::from([(
+                "proj".to_string(),
+                HashMap::from([("pageview".to_string(), 0.1), ("*".to_string(), 0.5)]),
+            )]),
+        }
+    }
+
+    fn event(event_type: &str, session: &str) -> IngestEventPayload {
+        IngestEventPayload {
+            project_id: "proj".to_string(),
+            event_type: event_type.to_string(),
+            properties: Some(HashMap::from([("session_id".to_string(), session.into())])),
+            anonymous_id: Some("anon".to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn kept(event_type: &str, key_rate: Option<f64>, config: &SamplingConfig) -> usize {
+        (0..1_000)
+            .filter(|i| apply(&mut event(event_type, &format!("s-{}", i)), key_rate, config))
+            .count()
+    }
+
+    #[test]
+    fn test_samples_by_event_type() {
+        assert!((70..130).contains(&kept("pageview", None, &config())));
+        assert!((440..560).contains(&kept("signup", None, &config())));
+        assert_eq!(kept("pageview", None, &SamplingConfig::default()), 1_000);
+
+        // The key's rate fills in for projects without configured rates
+        let mut other = event("pageview", "s-1");
+        other.project_id = "other".to_string();
+        assert!(apply(&mut other, Some(1.0), &config()));
+        assert_eq!(other.sample_rate, None);
+        assert!(!(0..100).any(|i| apply(&mut event("pageview", &format!("s-{}", i)), Some(0.0), &SamplingConfig::default())));
+    }
+
+    #[test]
+    fn test_keeps_whole_sessions_and_stamps_the_rate() {
+        let session_kept = (0..20).find(|i| apply(&mut event("pageview", &format!("s-{}", i)), None, &config()));
+        let session = format!("s-{}", session_kept.unwrap());
+        for _ in 0..5 {
+            let mut event = event("pageview", &session);
+            assert!(apply(&mut event, None, &config()));
+            assert_eq!(event.sample_rate, Some(0.1));
+        }
+
+        let mut anonymous = IngestEventPayload {
+            project_id: "proj".to_string(),
+            event_type: "pageview".to_string(),
+            ..Default::default()
+        };
+        assert!(apply(&mut anonymous, None, &config()));
+        assert_eq!(anonymous.sample_rate, None);
+    }
+}
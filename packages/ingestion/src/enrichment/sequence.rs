@@ -0,0 +1,169 @@
+//! Per-session sequence numbers.
+//!
+//! Clients may send a `sequence` with each event; events without one get
+//! the next number of their session (a `session_id` property, else the
+//! visitor) from a counter kept in the sandbox, starting at 0. A client's
+//! own numbers move the counter past them, so numbers assigned later still
+//! follow. Consumers use the sequence to spot gaps and to reorder events
+//! that arrived out of order.
+//!
+//! Counters of sessions idle for `SESSION_SEQUENCE_TTL_SECS` are dropped.
+//! They live per Lambda sandbox, so a session whose requests land on
+//! several sandboxes gets overlapping numbers; clients that need a strict
+//! order should send their own.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::enrichment::duplicate_view::session_key;
+use crate::models::IngestEventPayload;
+use crate::shared::{env_flag, env_or};
+
+/// Configuration for session sequence numbers
+#[derive(Debug, Clone)]
+pub struct SequenceConfig {
+    pub enabled: bool,
+    /// How long an idle session's counter is kept
+    pub ttl: Duration,
+}
+
+impl Default for SequenceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            ttl: Duration::from_secs(30 * 60),
+        }
+    }
+}
+
+impl SequenceConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            enabled: env_flag("SESSION_SEQUENCE_ENABLED"),
+            ttl: Duration::from_secs(env_or("SESSION_SEQUENCE_TTL_SECS", defaults.ttl.as_secs()).max(1)),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Counter {
+    next: u64,
+    touched: Instant,
+}
+
+#[derive(Debug)]
+struct Counters {
+    sessions: HashMap<String, Counter>,
+    swept: Instant,
+}
+
+/// Next sequence number by session key
+#[derive(Debug)]
+pub struct SessionSequences(Mutex<Counters>);
+
+impl Default for SessionSequences {
+    fn default() -> Self {
+        Self(Mutex::new(Counters {
+            sessions: HashMap::new(),
+            swept: Instant::now(),
+        }))
+    }
+}
+
+impl SessionSequences {
+    /// The session's next number, or `client`'s if it sent one; either way
+    /// the counter moves past it
+    pub fn next(&self, session: &str, client: Option<u64>, config: &SequenceConfig) -> u64 {
+        self.next_at(session, client, config, Instant::now())
+    }
+
+    fn next_at(&self, session: &str, client: Option<u64>, config: &SequenceConfig, now: Instant) -> u64 {
+        let mut counters = self.0.lock().unwrap();
+        // Idle sessions are dropped at most once per TTL
+        if now.saturating_duration_since(counters.swept) >= config.ttl {
+            counters
+                .sessions
+                .retain(|_, counter| now.saturating_duration_since(counter.touched) < config.ttl);
+            counters.swept = now;
+        }
+
+        let counter = counters.sessions.entry(session.to_string()).or_insert(Counter { next: 0, touched: now });
+        if now.saturating_duration_since(counter.touched) >= config.ttl {
+            counter.next = 0;
+        }
+        let sequence = client.unwrap_or(counter.next);
+        counter.next = counter.next.max(sequence.saturating_add(1));
+        counter.touched = now;
+        sequence
+    }
+}
+
+/// Stamps `sequence` on an event of a known session
+pub fn apply(payload: &mut IngestEventPayload, sequences: &SessionSequences, config: &SequenceConfig) {
+    let Some(session) = session_key(payload) else {
+        return;
+    };
+    payload.sequence = Some(sequences.next(&session, payload.sequence, config));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn event(session: &str, sequence: Option<u64>) -> IngestEventPayload {
+        IngestEventPayload {
+            project_id: "proj".to_string(),
+            event_type: "pageview".to_string(),
+            properties: Some(HashMap::from([("session_id".to_string(), session.into())])),
+            sequence,
+            ..Default::default()
+        }
+    }
+
+    fn sequences_of(events: &[(&str, Option<u64>)]) -> Vec<Option<u64>> {
+        let sequences = SessionSequences::default();
+        events
+            .iter()
+            .map(|&(session, sequence)| {
+                let mut event = event(session, sequence);
+                apply(&mut event, &sequences, &SequenceConfig::default());
+                event.sequence
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_sessions_count_up_independently() {
+        let events = [("s1", None), ("s1", None), ("s2", None), ("s1", None)];
+        assert_eq!(sequences_of(&events), [Some(0), Some(1), Some(0), Some(2)]);
+    }
+
+    #[test]
+    fn test_client_sequences_are_kept_and_advance_the_counter() {
+        let events = [("s1", Some(7)), ("s1", None), ("s1", Some(3)), ("s1", None)];
+        assert_eq!(sequences_of(&events), [Some(7), Some(8), Some(3), Some(9)]);
+    }
+
+    #[test]
+    fn test_idle_sessions_start_over() {
+        let sequences = SessionSequences::default();
+        let config = SequenceConfig::default();
+        let start = Instant::now();
+
+        assert_eq!(sequences.next_at("proj#s1", None, &config, start), 0);
+        assert_eq!(sequences.next_at("proj#s1", None, &config, start + Duration::from_secs(60)), 1);
+        let later = start + Duration::from_secs(60) + config.ttl;
+        assert_eq!(sequences.next_at("proj#s1", None, &config, later), 0);
+        assert_eq!(sequences.0.lock().unwrap().sessions.len(), 1);
+    }
+
+    #[test]
+    fn test_events_without_a_session_are_left_alone() {
+        let mut event = IngestEventPayload::default();
+        apply(&mut event, &SessionSequences::default(), &SequenceConfig::default());
+        assert_eq!(event.sequence, None);
+    }
+}
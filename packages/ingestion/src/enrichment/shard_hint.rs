@@ -0,0 +1,90 @@
+//! Deterministic shard hints for downstream consumers.
+//!
+//! Stamps `shard_hint = hash(partition key) % N` so consumers (such as the
+//! ClickHouse loader) can split work without recomputing the hash. The key
+//! comes from [`stable_key`], the same key that picks the stream partition,
+//! so events sharing a partition always share a hint. Under the `random`
+//! and `round_robin` strategies, which have no stable key, it's the
+//! project.
+
+use sha2::{Digest, Sha256};
+
+use crate::models::IngestEventPayload;
+use crate::partitioning::{stable_key, PartitionConfig};
+use crate::shared::{env_flag, env_or};
+
+/// Configuration for shard hints
+#[derive(Debug, Clone)]
+pub struct ShardHintConfig {
+    pub enabled: bool,
+    /// Number of consumer shards; hints are in `0..shards`
+    pub shards: u32,
+}
+
+impl Default for ShardHintConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            shards: 16,
+        }
+    }
+}
+
+impl ShardHintConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            enabled: env_flag("SHARD_HINT_ENABLED"),
+            shards: env_or("SHARD_HINT_COUNT", defaults.shards).max(1),
+        }
+    }
+}
+
+/// Shard for a partition key
+pub fn shard_hint(key: &str, shards: u32) -> u32 {
+    let digest = Sha256::digest(key.as_bytes());
+    let prefix = u64::from_be_bytes(digest[..8].try_into().expect("digest is 32 bytes"));
+    (prefix % u64::from(shards.max(1))) as u32
+}
+
+/// Stamps `shard_hint` from the event's partition key
+pub fn apply(payload: &mut IngestEventPayload, config: &ShardHintConfig, partitioning: &PartitionConfig) {
+    payload.shard_hint = Some(shard_hint(&stable_key(payload, partitioning), config.shards));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(project_id: &str) -> IngestEventPayload {
+        IngestEventPayload {
+            project_id: project_id.to_string(),
+            user_id: Some("u-1".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_hint_is_stable_and_in_range() {
+        let config = ShardHintConfig {
+            enabled: true,
+            shards: 8,
+        };
+
+        let mut first = event("proj-a");
+        let mut second = event("proj-a");
+        second.user_id = Some("u-2".to_string());
+        apply(&mut first, &config, &PartitionConfig::default());
+        apply(&mut second, &config, &PartitionConfig::default());
+
+        // Same partition key, same hint, whatever else differs
+        assert_eq!(first.shard_hint, second.shard_hint);
+        assert_eq!(first.shard_hint, Some(shard_hint("proj-a", 8)));
+
+        let hints: std::collections::HashSet<u32> = (0..200)
+            .map(|i| shard_hint(&format!("proj-{}", i), 8))
+            .collect();
+        assert!(hints.iter().all(|&hint| hint < 8));
+        assert_eq!(hints.len(), 8, "keys should spread across all shards");
+    }
+}
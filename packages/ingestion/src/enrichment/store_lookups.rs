@@ -0,0 +1,746 @@
+//! Store lookups shared by the events of a request.
+//!
+//! Duplicate views, the last-event gap, engagement, impossible travel and
+//! first touch each keep per-user or per-session state in a store, and
+//! normally make one call to it per event. With `STORE_LOOKUPS_BATCHED`,
+//! each request gets a [`StoreLookups`] (put in place by the middleware)
+//! and the stores are used through it instead:
+//!
+//! - composite read: before an event's store-backed steps run, everything
+//!   they will look up is read at once, with one `BatchGetItem` across the
+//!   DynamoDB tables (see [`DynamoLookupReader`]) and gets for stores kept
+//!   in memory
+//! - caching: a key is read once per request; later events of the same
+//!   user or session are answered from what the request already knows
+//! - batching: writes are coalesced to one per key and made once the
+//!   handler has answered, with the stores' own conditional writes (only
+//!   newer timestamps and sightings, the first touch kept, engagement
+//!   compare-and-swap). A request answered with a 5xx writes nothing, so
+//!   its retry isn't taken for a repeat
+//! - at most `STORE_LOOKUPS_MAX_CONCURRENT` store calls of a request are in
+//!   flight at once
+//!
+//! Reads are charged to the event's [`LookupBudget`] in step order, and a
+//! key past the budget isn't read, so its step is skipped as it would be
+//! without batching. Writes aren't charged; there is at most one per key
+//! read.
+//!
+//! Two requests for the same user in different sandboxes no longer
+//! interleave per event: timestamps and sightings still only move forward,
+//! but the last pageview is whichever request writes last, and heartbeats
+//! of a request losing the engagement swap are dropped (and logged).
+
+use async_trait::async_trait;
+use aws_sdk_dynamodb::types::{AttributeValue, KeysAndAttributes};
+use aws_sdk_dynamodb::Client as DynamoClient;
+use lambda_http::{Error, Request};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+use tokio::sync::Semaphore;
+
+use crate::enrichment::duplicate_view::{self, LastPageview, LastPageviewStore};
+use crate::enrichment::engagement::{self, Engagement, EngagementStore};
+use crate::enrichment::first_touch::{self, FirstTouchStore};
+use crate::enrichment::impossible_travel::{self, LocationStore, Sighting};
+use crate::enrichment::last_event_gap::{self, LastSeenStore};
+use crate::enrichment::lookup_budget::LookupBudget;
+use crate::models::{FirstTouch, IngestEventPayload};
+use crate::shared::{env_flag, env_opt, env_or, AppState, Config};
+
+/// Configuration for store lookups
+#[derive(Debug, Clone)]
+pub struct StoreLookupsConfig {
+    /// Share lookups across the events of a request
+    pub batched: bool,
+    /// Maximum store lookups one event's enrichments may make
+    pub max_per_event: Option<usize>,
+    /// Maximum store calls of one request in flight at once, at least 1
+    pub max_concurrent: usize,
+}
+
+impl Default for StoreLookupsConfig {
+    fn default() -> Self {
+        Self {
+            batched: false,
+            max_per_event: None,
+            max_concurrent: 4,
+        }
+    }
+}
+
+impl StoreLookupsConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            batched: env_flag("STORE_LOOKUPS_BATCHED"),
+            max_per_event: env_opt("STORE_LOOKUPS_MAX_PER_EVENT"),
+            max_concurrent: env_or("STORE_LOOKUPS_MAX_CONCURRENT", defaults.max_concurrent).max(1),
+        }
+    }
+}
+
+/// What an event's store-backed steps will look up, by store
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct LookupKeys {
+    pub last_pageview: Option<String>,
+    pub last_seen: Option<String>,
+    pub engagement: Option<String>,
+    pub location: Option<String>,
+    pub first_touch: Option<String>,
+}
+
+impl LookupKeys {
+    /// The keys the enabled steps will look up for this event
+    pub fn of(payload: &IngestEventPayload, request: &Request, config: &Config) -> Self {
+        let location = impossible_travel::viewer_location(request);
+        Self {
+            last_pageview: config
+                .duplicate_view
+                .enabled
+                .then(|| duplicate_view::lookup_key(payload))
+                .flatten(),
+            last_seen: config.last_event_gap.enabled.then(|| last_event_gap::lookup_key(payload)).flatten(),
+            engagement: config.engagement.enabled.then(|| engagement::lookup_key(payload)).flatten(),
+            location: config
+                .impossible_travel
+                .enabled
+                .then(|| impossible_travel::lookup_key(payload, location))
+                .flatten(),
+            first_touch: config
+                .first_touch
+                .enabled
+                .then(|| first_touch::lookup_key(payload, &config.first_touch))
+                .flatten(),
+        }
+    }
+}
+
+/// What a composite read found, by store: `None` for keys it didn't read,
+/// `Some(None)` for keys with nothing stored
+#[derive(Debug, Default)]
+pub struct Reads {
+    pub last_pageview: Option<Option<LastPageview>>,
+    pub last_seen: Option<Option<i64>>,
+    pub engagement: Option<Option<Engagement>>,
+    pub location: Option<Option<Sighting>>,
+    pub first_touch: Option<Option<FirstTouch>>,
+}
+
+/// A key's value as it was read, and as the request left it
+struct Entry<T> {
+    read: Option<T>,
+    current: Option<T>,
+}
+
+/// The keys of one store that a request read
+struct Cache<T>(Mutex<HashMap<String, Entry<T>>>);
+
+impl<T> Default for Cache<T> {
+    fn default() -> Self {
+        Self(Mutex::default())
+    }
+}
+
+impl<T: Clone + PartialEq> Cache<T> {
+    fn contains(&self, key: &str) -> bool {
+        self.0.lock().unwrap().contains_key(key)
+    }
+
+    /// The value as the request left it; `None` when the key wasn't read
+    fn current(&self, key: &str) -> Option<Option<T>> {
+        self.0.lock().unwrap().get(key).map(|entry| entry.current.clone())
+    }
+
+    /// Remembers a read, unless the key is known already
+    fn insert_read(&self, key: &str, value: Option<T>) {
+        self.0.lock().unwrap().entry(key.to_string()).or_insert_with(|| Entry {
+            read: value.clone(),
+            current: value,
+        });
+    }
+
+    /// Remembers what a composite read found, if it read the key
+    fn insert_found(&self, key: Option<String>, found: Option<Option<T>>) {
+        if let Some((key, value)) = key.zip(found) {
+            self.insert_read(&key, value);
+        }
+    }
+
+    fn set(&self, key: &str, value: T) {
+        if let Some(entry) = self.0.lock().unwrap().get_mut(key) {
+            entry.current = Some(value);
+        }
+    }
+
+    /// Keys the request changed: the key, its value as read and as left
+    fn changed(&self) -> Vec<(String, Option<T>, T)> {
+        let entries = self.0.lock().unwrap();
+        entries
+            .iter()
+            .filter(|(_, entry)| entry.current != entry.read)
+            .filter_map(|(key, entry)| Some((key.clone(), entry.read.clone(), entry.current.clone()?)))
+            .collect()
+    }
+}
+
+/// The store state a request has read and changed
+pub struct StoreLookups {
+    permits: Semaphore,
+    last_pageviews: Cache<LastPageview>,
+    last_seen: Cache<i64>,
+    engagement: Cache<Engagement>,
+    locations: Cache<Sighting>,
+    first_touches: Cache<FirstTouch>,
+}
+
+impl StoreLookups {
+    pub fn new(config: &StoreLookupsConfig) -> Self {
+        Self {
+            permits: Semaphore::new(config.max_concurrent.max(1)),
+            last_pageviews: Cache::default(),
+            last_seen: Cache::default(),
+            engagement: Cache::default(),
+            locations: Cache::default(),
+            first_touches: Cache::default(),
+        }
+    }
+
+    /// Runs one store call, waiting for a permit first
+    async fn call<T>(&self, call: impl Future<Output = T>) -> T {
+        let _permit = self.permits.acquire().await.expect("lookup semaphore is never closed");
+        call.await
+    }
+
+    /// A key's value, read into the cache first if the request doesn't
+    /// know it yet
+    async fn read_through<T, F>(&self, cache: &Cache<T>, key: &str, read: F) -> Result<Option<T>, Error>
+    where
+        T: Clone + PartialEq,
+        F: Future<Output = Result<Option<T>, Error>>,
+    {
+        if let Some(current) = cache.current(key) {
+            return Ok(current);
+        }
+        let value = self.call(read).await?;
+        cache.insert_read(key, value);
+        Ok(cache.current(key).flatten())
+    }
+
+    /// Reads one key of a store the composite read didn't cover
+    async fn read_into<T, F>(&self, cache: &Cache<T>, key: Option<String>, read: impl FnOnce(String) -> F)
+    where
+        T: Clone + PartialEq,
+        F: Future<Output = Result<Option<T>, Error>>,
+    {
+        let Some(key) = key else {
+            return;
+        };
+        match self.call(read(key.clone())).await {
+            Ok(value) => cache.insert_read(&key, value),
+            // The step reads it again, or goes without
+            Err(e) => tracing::warn!("Failed to prefetch {}: {}", key, e),
+        }
+    }
+
+    /// Reads everything the event's steps will look up that the request
+    /// doesn't know yet, charging each key to `budget` in step order
+    pub async fn prefetch(&self, keys: &LookupKeys, state: &AppState, budget: &LookupBudget) {
+        fn wanted<T: Clone + PartialEq>(key: &Option<String>, cache: &Cache<T>, budget: &LookupBudget) -> Option<String> {
+            key.clone().filter(|key| !cache.contains(key)).filter(|_| budget.take().is_ok())
+        }
+        let wanted = LookupKeys {
+            last_pageview: wanted(&keys.last_pageview, &self.last_pageviews, budget),
+            last_seen: wanted(&keys.last_seen, &self.last_seen, budget),
+            engagement: wanted(&keys.engagement, &self.engagement, budget),
+            location: wanted(&keys.location, &self.locations, budget),
+            first_touch: wanted(&keys.first_touch, &self.first_touches, budget),
+        };
+        if wanted == LookupKeys::default() {
+            return;
+        }
+
+        let mut reads = Reads::default();
+        if let Some(ref reader) = state.lookup_reader {
+            match self.call(reader.read(&wanted)).await {
+                Ok(read) => reads = read,
+                Err(e) => tracing::warn!("Composite store read failed: {}", e),
+            }
+        }
+        // Keys of in-memory stores, or that the composite read missed
+        let rest = |key: &Option<String>, read: bool| key.clone().filter(|_| !read);
+        tokio::join!(
+            self.read_into(
+                &self.last_pageviews,
+                rest(&wanted.last_pageview, reads.last_pageview.is_some()),
+                |key| async move { state.last_pageview_store.get(&key).await },
+            ),
+            self.read_into(
+                &self.last_seen,
+                rest(&wanted.last_seen, reads.last_seen.is_some()),
+                |key| async move { state.last_seen_store.get(&key).await },
+            ),
+            self.read_into(
+                &self.engagement,
+                rest(&wanted.engagement, reads.engagement.is_some()),
+                |key| async move { state.engagement_store.get(&key).await },
+            ),
+            self.read_into(
+                &self.locations,
+                rest(&wanted.location, reads.location.is_some()),
+                |key| async move { state.location_store.get(&key).await },
+            ),
+            self.read_into(
+                &self.first_touches,
+                rest(&wanted.first_touch, reads.first_touch.is_some()),
+                |key| async move { state.first_touch_store.get(&key).await },
+            ),
+        );
+
+        self.last_pageviews.insert_found(wanted.last_pageview, reads.last_pageview);
+        self.last_seen.insert_found(wanted.last_seen, reads.last_seen);
+        self.engagement.insert_found(wanted.engagement, reads.engagement);
+        self.locations.insert_found(wanted.location, reads.location);
+        self.first_touches.insert_found(wanted.first_touch, reads.first_touch);
+    }
+
+    /// Writes one store's changed keys, one at a time
+    async fn write_all<T, F>(&self, changed: Vec<(String, Option<T>, T)>, write: impl Fn(String, Option<T>, T) -> F)
+    where
+        F: Future<Output = Result<(), Error>>,
+    {
+        for (key, read, current) in changed {
+            if let Err(e) = self.call(write(key.clone(), read, current)).await {
+                tracing::warn!("Failed to write {} back to its store: {}", key, e);
+            }
+        }
+    }
+
+    /// Writes what the request changed back to the stores, one conditional
+    /// write per key
+    pub async fn flush(&self, state: &AppState) {
+        tokio::join!(
+            self.write_all(self.last_pageviews.changed(), |key, _, pageview| async move {
+                state.last_pageview_store.replace(&key, pageview).await.map(drop)
+            }),
+            self.write_all(self.last_seen.changed(), |key, _, timestamp| async move {
+                state.last_seen_store.record(&key, timestamp).await.map(drop)
+            }),
+            self.write_all(self.engagement.changed(), |key, read, engagement| async move {
+                if !state.engagement_store.swap(&key, read, engagement).await? {
+                    tracing::warn!("Engagement for {} changed concurrently, dropping this request's heartbeats", key);
+                }
+                Ok(())
+            }),
+            self.write_all(self.locations.changed(), |key, _, sighting| async move {
+                state.location_store.record(&key, sighting).await.map(drop)
+            }),
+            self.write_all(self.first_touches.changed(), |key, _, touch| async move {
+                state.first_touch_store.remember(&key, &touch).await.map(drop)
+            }),
+        );
+    }
+}
+
+/// A store used through the request's [`StoreLookups`], or directly when
+/// lookups aren't shared
+pub struct Shared<'a, S> {
+    store: S,
+    lookups: Option<&'a StoreLookups>,
+}
+
+impl<'a, S> Shared<'a, S> {
+    pub fn new(store: S, lookups: Option<&'a StoreLookups>) -> Self {
+        Self { store, lookups }
+    }
+}
+
+#[async_trait]
+impl<S: LastPageviewStore> LastPageviewStore for Shared<'_, S> {
+    async fn replace(&self, key: &str, pageview: LastPageview) -> Result<Option<LastPageview>, Error> {
+        let Some(lookups) = self.lookups else {
+            return self.store.replace(key, pageview).await;
+        };
+        let previous = lookups.read_through(&lookups.last_pageviews, key, self.store.get(key)).await?;
+        lookups.last_pageviews.set(key, pageview);
+        Ok(previous)
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<LastPageview>, Error> {
+        match self.lookups {
+            Some(lookups) => lookups.read_through(&lookups.last_pageviews, key, self.store.get(key)).await,
+            None => self.store.get(key).await,
+        }
+    }
+}
+
+#[async_trait]
+impl<S: LastSeenStore> LastSeenStore for Shared<'_, S> {
+    async fn record(&self, key: &str, timestamp: i64) -> Result<Option<i64>, Error> {
+        let Some(lookups) = self.lookups else {
+            return self.store.record(key, timestamp).await;
+        };
+        let previous = lookups.read_through(&lookups.last_seen, key, self.store.get(key)).await?;
+        if previous.is_none_or(|previous| previous < timestamp) {
+            lookups.last_seen.set(key, timestamp);
+        }
+        Ok(previous)
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<i64>, Error> {
+        match self.lookups {
+            Some(lookups) => lookups.read_through(&lookups.last_seen, key, self.store.get(key)).await,
+            None => self.store.get(key).await,
+        }
+    }
+}
+
+#[async_trait]
+impl<S: EngagementStore> EngagementStore for Shared<'_, S> {
+    async fn get(&self, key: &str) -> Result<Option<Engagement>, Error> {
+        match self.lookups {
+            Some(lookups) => lookups.read_through(&lookups.engagement, key, self.store.get(key)).await,
+            None => self.store.get(key).await,
+        }
+    }
+
+    async fn swap(&self, key: &str, expected: Option<Engagement>, new: Engagement) -> Result<bool, Error> {
+        let Some(lookups) = self.lookups else {
+            return self.store.swap(key, expected, new).await;
+        };
+        if lookups.read_through(&lookups.engagement, key, self.store.get(key)).await? != expected {
+            return Ok(false);
+        }
+        lookups.engagement.set(key, new);
+        Ok(true)
+    }
+}
+
+#[async_trait]
+impl<S: LocationStore> LocationStore for Shared<'_, S> {
+    async fn record(&self, key: &str, sighting: Sighting) -> Result<Option<Sighting>, Error> {
+        let Some(lookups) = self.lookups else {
+            return self.store.record(key, sighting).await;
+        };
+        let previous = lookups.read_through(&lookups.locations, key, self.store.get(key)).await?;
+        if previous.is_none_or(|previous| previous.timestamp < sighting.timestamp) {
+            lookups.locations.set(key, sighting);
+        }
+        Ok(previous)
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Sighting>, Error> {
+        match self.lookups {
+            Some(lookups) => lookups.read_through(&lookups.locations, key, self.store.get(key)).await,
+            None => self.store.get(key).await,
+        }
+    }
+}
+
+#[async_trait]
+impl<S: FirstTouchStore> FirstTouchStore for Shared<'_, S> {
+    async fn remember(&self, key: &str, touch: &FirstTouch) -> Result<FirstTouch, Error> {
+        let Some(lookups) = self.lookups else {
+            return self.store.remember(key, touch).await;
+        };
+        match lookups.read_through(&lookups.first_touches, key, self.store.get(key)).await? {
+            Some(first) => Ok(first),
+            None => {
+                lookups.first_touches.set(key, touch.clone());
+                Ok(touch.clone())
+            }
+        }
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<FirstTouch>, Error> {
+        match self.lookups {
+            Some(lookups) => lookups.read_through(&lookups.first_touches, key, self.store.get(key)).await,
+            None => self.store.get(key).await,
+        }
+    }
+}
+
+/// The DynamoDB tables of the store-backed enrichments, read together with
+/// one `BatchGetItem`. Stores without a table are left to their own `get`.
+pub struct DynamoLookupReader {
+    client: DynamoClient,
+    last_pageview_table: Option<String>,
+    last_seen_table: Option<String>,
+    engagement_table: Option<String>,
+    location_table: Option<String>,
+    first_touch_table: Option<String>,
+}
+
+impl DynamoLookupReader {
+    /// A reader for the configured tables; `None` when no store has one
+    pub fn new(client: DynamoClient, config: &Config) -> Option<Self> {
+        let reader = Self {
+            client,
+            last_pageview_table: config.duplicate_view.table_name.clone(),
+            last_seen_table: config.last_event_gap.table_name.clone(),
+            engagement_table: config.engagement.table_name.clone(),
+            location_table: config.impossible_travel.table_name.clone(),
+            first_touch_table: config.first_touch.table_name.clone(),
+        };
+        let tables = [
+            &reader.last_pageview_table,
+            &reader.last_seen_table,
+            &reader.engagement_table,
+            &reader.location_table,
+            &reader.first_touch_table,
+        ];
+        tables.iter().any(|table| table.is_some()).then_some(reader)
+    }
+
+    /// Reads the keys whose store has a table. Keys DynamoDB leaves
+    /// unprocessed count as not read.
+    pub async fn read(&self, keys: &LookupKeys) -> Result<Reads, Error> {
+        let wanted = [
+            (&self.last_pageview_table, &keys.last_pageview),
+            (&self.last_seen_table, &keys.last_seen),
+            (&self.engagement_table, &keys.engagement),
+            (&self.location_table, &keys.location),
+            (&self.first_touch_table, &keys.first_touch),
+        ];
+        let mut by_table: HashMap<&str, Vec<&str>> = HashMap::new();
+        for (table, key) in wanted {
+            if let (Some(table), Some(key)) = (table, key) {
+                let keys = by_table.entry(table).or_default();
+                if !keys.contains(&key.as_str()) {
+                    keys.push(key);
+                }
+            }
+        }
+        if by_table.is_empty() {
+            return Ok(Reads::default());
+        }
+
+        let mut request = self.client.batch_get_item();
+        for (table, keys) in &by_table {
+            let mut items = KeysAndAttributes::builder().consistent_read(true);
+            for key in keys {
+                items = items.keys(HashMap::from([("pk".to_string(), AttributeValue::S(key.to_string()))]));
+            }
+            request = request.request_items(*table, items.build()?);
+        }
+        let output = request.send().await?;
+
+        let unprocessed: Vec<(&str, &str)> = output
+            .unprocessed_keys()
+            .into_iter()
+            .flatten()
+            .flat_map(|(table, items)| {
+                items.keys().iter().filter_map(move |key| Some((table.as_str(), key.get("pk")?.as_s().ok()?.as_str())))
+            })
+            .collect();
+        let responses = output.responses();
+        // The item for a key, `None` inside when nothing is stored, `None`
+        // outside when the key wasn't read
+        let item = |table: &Option<String>, key: &Option<String>| {
+            let (table, key) = (table.as_deref()?, key.as_deref()?);
+            if unprocessed.contains(&(table, key)) {
+                return None;
+            }
+            let items = responses.and_then(|responses| responses.get(table));
+            Some(items.into_iter().flatten().find(|item| {
+                item.get("pk").and_then(|pk| pk.as_s().ok()).is_some_and(|pk| pk == key)
+            }))
+        };
+
+        Ok(Reads {
+            last_pageview: item(&self.last_pageview_table, &keys.last_pageview)
+                .map(duplicate_view::pageview_attributes),
+            last_seen: item(&self.last_seen_table, &keys.last_seen).map(last_event_gap::last_seen_attribute),
+            engagement: item(&self.engagement_table, &keys.engagement).map(engagement::engagement_attributes),
+            location: item(&self.location_table, &keys.location).map(impossible_travel::sighting_attributes),
+            first_touch: item(&self.first_touch_table, &keys.first_touch).map(first_touch::unexpired_touch),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enrichment::duplicate_view::InMemoryLastPageviewStore;
+    use crate::enrichment::engagement::InMemoryEngagementStore;
+    use crate::enrichment::impossible_travel::InMemoryLocationStore;
+    use crate::enrichment::last_event_gap::InMemoryLastSeenStore;
+    use crate::enrichment::lookup_budget::Budgeted;
+    use crate::models::{EventContext, PageContext};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn config() -> Config {
+        let mut config = Config::default();
+        config.store_lookups.batched = true;
+        config.duplicate_view.enabled = true;
+        config.last_event_gap.enabled = true;
+        config.engagement.enabled = true;
+        config.impossible_travel.enabled = true;
+        config
+    }
+
+    fn located(lookups: &Arc<StoreLookups>) -> Request {
+        let mut request = lambda_http::http::Request::builder()
+            .header("CloudFront-Viewer-Latitude", "52.52")
+            .header("CloudFront-Viewer-Longitude", "13.405")
+            .body(lambda_http::Body::Empty)
+            .unwrap();
+        request.extensions_mut().insert(lookups.clone());
+        request
+    }
+
+    fn pageview(timestamp: i64, url: &str) -> IngestEventPayload {
+        IngestEventPayload {
+            project_id: "proj".to_string(),
+            event_type: "pageview".to_string(),
+            anonymous_id: Some("anon-1".to_string()),
+            timestamp,
+            context: Some(EventContext {
+                page: Some(PageContext {
+                    url: Some(url.to_string()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_a_request_reads_and_writes_each_key_once() {
+        // An uncapped budget around every store counts the calls reaching them
+        let counter: &'static LookupBudget = Box::leak(Box::new(LookupBudget::new(None)));
+        let last_seen: Arc<dyn LastSeenStore> = Arc::new(InMemoryLastSeenStore::default());
+        let mut state = crate::shared::test_state(config());
+        let last_pageview: Arc<dyn LastPageviewStore> = Arc::new(InMemoryLastPageviewStore::default());
+        let engagement: Arc<dyn EngagementStore> = Arc::new(InMemoryEngagementStore::default());
+        let location: Arc<dyn LocationStore> = Arc::new(InMemoryLocationStore::default());
+        state.last_pageview_store = Arc::new(Budgeted::new(last_pageview, counter));
+        state.last_seen_store = Arc::new(Budgeted::new(last_seen.clone(), counter));
+        state.engagement_store = Arc::new(Budgeted::new(engagement, counter));
+        state.location_store = Arc::new(Budgeted::new(location, counter));
+
+        let lookups = Arc::new(StoreLookups::new(&state.config.store_lookups));
+        let request = located(&lookups);
+        let mut stamped = Vec::new();
+        for (timestamp, url) in [(1_000, "https://shop.io/"), (3_000, "https://shop.io/a"), (3_500, "https://shop.io/a")] {
+            let mut events = crate::enrichment::apply(pageview(timestamp, url), &request, &state).await;
+            stamped.push(events.remove(0));
+        }
+
+        // One composite read for the first event; the rest come from the cache
+        assert_eq!(counter.used(), 4);
+        assert_eq!(stamped[0].ms_since_last_event, Some(None));
+        assert_eq!(stamped[1].ms_since_last_event, Some(Some(2_000)));
+        assert_eq!(stamped[2].is_duplicate_view, Some(true));
+        assert_eq!(last_seen.get("proj#anon-1").await.unwrap(), None);
+
+        // One write per changed key, with what the last event left;
+        // pageviews only read engagement
+        lookups.flush(&state).await;
+        assert_eq!(counter.used(), 7);
+        assert_eq!(last_seen.get("proj#anon-1").await.unwrap(), Some(3_500));
+
+        // The next request starts from the stores
+        let lookups = Arc::new(StoreLookups::new(&state.config.store_lookups));
+        let mut events = crate::enrichment::apply(pageview(4_000, "https://shop.io/b"), &located(&lookups), &state).await;
+        assert_eq!(events.remove(0).ms_since_last_event, Some(Some(500)));
+    }
+
+    #[tokio::test]
+    async fn test_reads_past_the_event_budget_are_skipped() {
+        let mut config = config();
+        config.store_lookups.max_per_event = Some(2);
+        let state = crate::shared::test_state(config);
+
+        let lookups = Arc::new(StoreLookups::new(&state.config.store_lookups));
+        let mut events = crate::enrichment::apply(pageview(1_000, "https://shop.io/"), &located(&lookups), &state).await;
+        let event = events.remove(0);
+
+        // Duplicate views and the gap fit, engagement and travel don't
+        assert_eq!(event.ms_since_last_event, Some(None));
+        assert_eq!(event.engaged_time_ms, None);
+        assert!(lookups.engagement.current("proj#anon-1").is_none());
+    }
+
+    /// Stores that answer slowly, tracking how many calls are in flight
+    #[derive(Default)]
+    struct Slow {
+        in_flight: AtomicUsize,
+        peak: AtomicUsize,
+    }
+
+    impl Slow {
+        async fn visit<T>(&self, value: T) -> Result<T, Error> {
+            let now = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.peak.fetch_max(now, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            Ok(value)
+        }
+    }
+
+    #[async_trait]
+    impl LastPageviewStore for Slow {
+        async fn replace(&self, _: &str, _: LastPageview) -> Result<Option<LastPageview>, Error> {
+            self.visit(None).await
+        }
+
+        async fn get(&self, _: &str) -> Result<Option<LastPageview>, Error> {
+            self.visit(None).await
+        }
+    }
+
+    #[async_trait]
+    impl LastSeenStore for Slow {
+        async fn record(&self, _: &str, _: i64) -> Result<Option<i64>, Error> {
+            self.visit(None).await
+        }
+
+        async fn get(&self, _: &str) -> Result<Option<i64>, Error> {
+            self.visit(None).await
+        }
+    }
+
+    #[async_trait]
+    impl EngagementStore for Slow {
+        async fn get(&self, _: &str) -> Result<Option<Engagement>, Error> {
+            self.visit(None).await
+        }
+
+        async fn swap(&self, _: &str, _: Option<Engagement>, _: Engagement) -> Result<bool, Error> {
+            self.visit(true).await
+        }
+    }
+
+    #[async_trait]
+    impl LocationStore for Slow {
+        async fn record(&self, _: &str, _: Sighting) -> Result<Option<Sighting>, Error> {
+            self.visit(None).await
+        }
+
+        async fn get(&self, _: &str) -> Result<Option<Sighting>, Error> {
+            self.visit(None).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_store_calls_of_a_request_are_capped() {
+        let slow = Arc::new(Slow::default());
+        let mut config = config();
+        config.store_lookups.max_concurrent = 2;
+        let mut state = crate::shared::test_state(config);
+        state.last_pageview_store = slow.clone();
+        state.last_seen_store = slow.clone();
+        state.engagement_store = slow.clone();
+        state.location_store = slow.clone();
+
+        let lookups = Arc::new(StoreLookups::new(&state.config.store_lookups));
+        crate::enrichment::apply(pageview(1_000, "https://shop.io/"), &located(&lookups), &state).await;
+        lookups.flush(&state).await;
+
+        assert_eq!(slow.peak.load(Ordering::SeqCst), 2);
+    }
+}
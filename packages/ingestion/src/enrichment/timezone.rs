@@ -0,0 +1,251 @@
+//! Approximate timezone inference.
+//!
+//! Many events lack an explicit timezone. When enabled, a coarse IANA zone is
+//! inferred from the IP location (CloudFront viewer headers), falling back to
+//! the region subtag of the locale, and stamped as `inferred_timezone`. An
+//! explicit `context.timezone` from the client always wins. Optionally the
+//! path taken is recorded as `geo_source`, so analysts can weigh it.
+
+use lambda_http::Request;
+
+use crate::models::{GeoSource, IngestEventPayload};
+use crate::shared::{env_flag, header_value};
+
+/// Most populous IANA zone per ISO 3166-1 country code
+const COUNTRY_TIMEZONES: &[(&str, &str)] = &[
+    ("AE", "Asia/Dubai"),
+    ("AR", "America/Argentina/Buenos_Aires"),
+    ("AT", "Europe/Vienna"),
+    ("AU", "Australia/Sydney"),
+    ("BE", "Europe/Brussels"),
+    ("BG", "Europe/Sofia"),
+    ("BR", "America/Sao_Paulo"),
+    ("CA", "America/Toronto"),
+    ("CH", "Europe/Zurich"),
+    ("CL", "America/Santiago"),
+    ("CN", "Asia/Shanghai"),
+    ("CO", "America/Bogota"),
+    ("CZ", "Europe/Prague"),
+    ("DE", "Europe/Berlin"),
+    ("DK", "Europe/Copenhagen"),
+    ("EG", "Africa/Cairo"),
+    ("ES", "Europe/Madrid"),
+    ("FI", "Europe/Helsinki"),
+    ("FR", "Europe/Paris"),
+    ("GB", "Europe/London"),
+    ("GR", "Europe/Athens"),
+    ("HK", "Asia/Hong_Kong"),
+    ("HU", "Europe/Budapest"),
+    ("ID", "Asia/Jakarta"),
+    ("IE", "Europe/Dublin"),
+    ("IL", "Asia/Jerusalem"),
+    ("IN", "Asia/Kolkata"),
+    ("IT", "Europe/Rome"),
+    ("JP", "Asia/Tokyo"),
+    ("KE", "Africa/Nairobi"),
+    ("KR", "Asia/Seoul"),
+    ("MX", "America/Mexico_City"),
+    ("MY", "Asia/Kuala_Lumpur"),
+    ("NG", "Africa/Lagos"),
+    ("NL", "Europe/Amsterdam"),
+    ("NO", "Europe/Oslo"),
+    ("NZ", "Pacific/Auckland"),
+    ("PE", "America/Lima"),
+    ("PH", "Asia/Manila"),
+    ("PK", "Asia/Karachi"),
+    ("PL", "Europe/Warsaw"),
+    ("PT", "Europe/Lisbon"),
+    ("RO", "Europe/Bucharest"),
+    ("RU", "Europe/Moscow"),
+    ("SA", "Asia/Riyadh"),
+    ("SE", "Europe/Stockholm"),
+    ("SG", "Asia/Singapore"),
+    ("TH", "Asia/Bangkok"),
+    ("TR", "Europe/Istanbul"),
+    ("TW", "Asia/Taipei"),
+    ("UA", "Europe/Kyiv"),
+    ("US", "America/New_York"),
+    ("VN", "Asia/Ho_Chi_Minh"),
+    ("ZA", "Africa/Johannesburg"),
+];
+
+/// Configuration for timezone inference
+#[derive(Debug, Clone, Default)]
+pub struct TimezoneConfig {
+    pub enabled: bool,
+    /// Stamp `geo_source` next to the inferred zone
+    pub record_geo_source: bool,
+}
+
+impl TimezoneConfig {
+    pub fn from_env() -> Self {
+        Self {
+            enabled: env_flag("TIMEZONE_INFERENCE_ENABLED"),
+            record_geo_source: env_flag("GEO_SOURCE_ENABLED"),
+        }
+    }
+}
+
+/// Looks up the representative zone for a country code
+pub fn timezone_for_country(country: &str) -> Option<&'static str> {
+    let country = country.trim().to_ascii_uppercase();
+    COUNTRY_TIMEZONES
+        .iter()
+        .find(|(code, _)| *code == country)
+        .map(|(_, zone)| *zone)
+}
+
+/// Extracts the region subtag from a BCP 47 locale ("en-US" -> "US")
+fn locale_region(locale: &str) -> Option<&str> {
+    locale
+        .split(['-', '_'])
+        .skip(1)
+        .find(|subtag| subtag.len() == 2 && subtag.chars().all(|c| c.is_ascii_alphabetic()))
+}
+
+/// Infers a timezone from IP location headers, then from the locale
+pub fn infer(payload: &IngestEventPayload, request: &Request) -> Option<(String, GeoSource)> {
+    if let Some(zone) = header_value(request, "cloudfront-viewer-time-zone") {
+        return Some((zone.to_string(), GeoSource::CdnHeader));
+    }
+
+    if let Some(zone) = header_value(request, "cloudfront-viewer-country").and_then(timezone_for_country) {
+        return Some((zone.to_string(), GeoSource::CdnHeader));
+    }
+
+    let accept_language = header_value(request, "accept-language")
+        .and_then(|value| value.split([',', ';']).next())
+        .map(str::trim);
+    let locale = payload
+        .context
+        .as_ref()
+        .and_then(|c| c.locale.as_deref())
+        .or(accept_language)?;
+
+    locale_region(locale)
+        .and_then(timezone_for_country)
+        .map(|zone| (zone.to_string(), GeoSource::Inferred))
+}
+
+/// Stamps `inferred_timezone` unless the client supplied an explicit one
+pub fn apply(payload: &mut IngestEventPayload, request: &Request, config: &TimezoneConfig) {
+    let explicit = payload
+        .context
+        .as_ref()
+        .is_some_and(|c| c.timezone.as_deref().is_some_and(|tz| !tz.is_empty()));
+    if explicit {
+        return;
+    }
+
+    let (zone, source) = infer(payload, request).unzip();
+    payload.inferred_timezone = zone;
+    if config.record_geo_source {
+        payload.geo_source = Some(source.unwrap_or(GeoSource::None));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::EventContext;
+    use lambda_http::Body;
+
+    fn request(headers: &[(&str, &str)]) -> Request {
+        let mut builder = lambda_http::http::Request::builder();
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        builder.body(Body::Empty).unwrap()
+    }
+
+    fn config() -> TimezoneConfig {
+        TimezoneConfig {
+            enabled: true,
+            record_geo_source: false,
+        }
+    }
+
+    fn payload(locale: Option<&str>, timezone: Option<&str>) -> IngestEventPayload {
+        IngestEventPayload {
+            context: Some(EventContext {
+                locale: locale.map(String::from),
+                timezone: timezone.map(String::from),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_ip_derived_timezone() {
+        let mut event = payload(Some("en-US"), None);
+        apply(&mut event, &request(&[("CloudFront-Viewer-Country", "DE")]), &config());
+        assert_eq!(event.inferred_timezone.as_deref(), Some("Europe/Berlin"));
+
+        let mut event = payload(None, None);
+        apply(
+            &mut event,
+            &request(&[("CloudFront-Viewer-Time-Zone", "America/Chicago")]),
+            &config(),
+        );
+        assert_eq!(event.inferred_timezone.as_deref(), Some("America/Chicago"));
+    }
+
+    #[test]
+    fn test_locale_derived_timezone() {
+        let mut event = payload(Some("pt-BR"), None);
+        apply(&mut event, &request(&[]), &config());
+        assert_eq!(event.inferred_timezone.as_deref(), Some("America/Sao_Paulo"));
+
+        let mut event = payload(None, None);
+        apply(&mut event, &request(&[("Accept-Language", "ja-JP,ja;q=0.9")]), &config());
+        assert_eq!(event.inferred_timezone.as_deref(), Some("Asia/Tokyo"));
+
+        // Language-only locales carry no region
+        let mut event = payload(Some("de"), None);
+        apply(&mut event, &request(&[]), &config());
+        assert_eq!(event.inferred_timezone, None);
+    }
+
+    #[test]
+    fn test_explicit_timezone_wins() {
+        let mut event = payload(Some("en-US"), Some("Europe/Lisbon"));
+        apply(&mut event, &request(&[("CloudFront-Viewer-Country", "US")]), &config());
+        assert_eq!(event.inferred_timezone, None);
+        assert_eq!(
+            event.context.unwrap().timezone.as_deref(),
+            Some("Europe/Lisbon")
+        );
+    }
+
+    #[test]
+    fn test_geo_source_recorded_per_path() {
+        let config = TimezoneConfig {
+            record_geo_source: true,
+            ..config()
+        };
+        let source = |locale: Option<&str>, headers: &[(&str, &str)]| {
+            let mut event = payload(locale, None);
+            apply(&mut event, &request(headers), &config);
+            event.geo_source
+        };
+
+        let cdn = Some(GeoSource::CdnHeader);
+        assert_eq!(source(Some("en-US"), &[("CloudFront-Viewer-Country", "DE")]), cdn);
+        assert_eq!(source(None, &[("CloudFront-Viewer-Time-Zone", "Asia/Tokyo")]), cdn);
+        assert_eq!(source(Some("pt-BR"), &[]), Some(GeoSource::Inferred));
+        assert_eq!(source(Some("de"), &[]), Some(GeoSource::None));
+
+        // Not recorded unless enabled, nor when the client sent its zone
+        let mut event = payload(Some("pt-BR"), None);
+        apply(&mut event, &request(&[]), &self::config());
+        assert_eq!(event.geo_source, None);
+
+        let mut event = payload(Some("en-US"), Some("Europe/Lisbon"));
+        apply(&mut event, &request(&[("CloudFront-Viewer-Country", "US")]), &config);
+        assert_eq!(event.geo_source, None);
+
+        let json = serde_json::to_value(GeoSource::CdnHeader).unwrap();
+        assert_eq!(json, "cdn_header");
+    }
+}
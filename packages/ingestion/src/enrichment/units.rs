@@ -0,0 +1,174 @@
+//! Property unit enforcement.
+//!
+//! Projects can declare a canonical unit for named properties (`duration` in
+//! `ms`, `revenue` in `cents`) together with conversion factors from other
+//! units. Values sent with a unit, as `"2.5s"` or `{"value": 2.5, "unit": "s"}`,
+//! are converted to the canonical unit; bare numbers are assumed canonical.
+//! Values that can't be converted are left alone and listed in
+//! `unit_violations`.
+
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::models::IngestEventPayload;
+use crate::shared::{env_flag, env_json};
+
+/// Canonical unit of one property
+#[derive(Debug, Clone, Deserialize)]
+pub struct UnitDeclaration {
+    pub unit: String,
+    /// Factor converting each accepted unit to the canonical one (`"s": 1000`)
+    #[serde(default)]
+    pub conversions: HashMap<String, f64>,
+}
+
+impl UnitDeclaration {
+    /// Converts `value` in `unit` to the canonical unit
+    fn convert(&self, value: f64, unit: &str) -> Option<f64> {
+        if unit == self.unit {
+            return Some(value);
+        }
+        self.conversions.get(unit).map(|factor| value * factor)
+    }
+}
+
+/// Configuration for unit enforcement
+#[derive(Debug, Clone, Default)]
+pub struct UnitsConfig {
+    pub enabled: bool,
+    /// Declarations keyed by project id, then property name
+    pub projects: HashMap<String, HashMap<String, UnitDeclaration>>,
+}
+
+impl UnitsConfig {
+    pub fn from_env() -> Self {
+        Self {
+            enabled: env_flag("PROPERTY_UNITS_ENABLED"),
+            projects: env_json("PROPERTY_UNITS").unwrap_or_default(),
+        }
+    }
+}
+
+/// Splits a value into its number and unit, if it carries one
+fn quantity(value: &serde_json::Value) -> Option<(f64, Option<String>)> {
+    match value {
+        serde_json::Value::Number(n) => Some((n.as_f64()?, None)),
+        serde_json::Value::String(s) => {
+            let s = s.trim();
+            let split = s
+                .find(|c: char| !(c.is_ascii_digit() || matches!(c, '.' | '-' | '+')))
+                .unwrap_or(s.len());
+            let (number, unit) = s.split_at(split);
+            let number: f64 = number.parse().ok()?;
+            let unit = unit.trim();
+            Some((number, (!unit.is_empty()).then(|| unit.to_string())))
+        }
+        serde_json::Value::Object(fields) => {
+            let number = fields.get("value")?.as_f64()?;
+            let unit = fields.get("unit")?.as_str()?.trim().to_string();
+            Some((number, Some(unit)))
+        }
+        _ => None,
+    }
+}
+
+/// Converts a number to JSON, as an integer when it is whole
+fn to_json(value: f64) -> serde_json::Value {
+    if value.fract() == 0.0 && value.abs() < i64::MAX as f64 {
+        serde_json::json!(value as i64)
+    } else {
+        serde_json::json!(value)
+    }
+}
+
+/// Converts declared properties to their canonical units
+pub fn apply(payload: &mut IngestEventPayload, config: &UnitsConfig) {
+    let Some(declarations) = config.projects.get(&payload.project_id) else {
+        return;
+    };
+    let Some(ref mut properties) = payload.properties else {
+        return;
+    };
+
+    let mut violations = Vec::new();
+    for (key, declaration) in declarations {
+        let Some(value) = properties.get_mut(key) else {
+            continue;
+        };
+
+        let converted = quantity(value).and_then(|(number, unit)| match unit {
+            None => Some(number),
+            Some(unit) => declaration.convert(number, &unit),
+        });
+
+        match converted.filter(|n| n.is_finite()) {
+            Some(number) => *value = to_json(number),
+            None => violations.push(key.clone()),
+        }
+    }
+
+    if !violations.is_empty() {
+        violations.sort();
+        payload.unit_violations = Some(violations);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> UnitsConfig {
+        UnitsConfig {
+            enabled: true,
+            projects: serde_json::from_str(
+                r#"{"proj": {"duration": {"unit": "ms", "conversions": {"s": 1000, "min": 60000}}}}"#,
+            )
+            .unwrap(),
+        }
+    }
+
+    fn payload(duration: serde_json::Value) -> IngestEventPayload {
+        IngestEventPayload {
+            project_id: "proj".to_string(),
+            properties: Some(HashMap::from([("duration".to_string(), duration)])),
+            ..Default::default()
+        }
+    }
+
+    fn duration(payload: &IngestEventPayload) -> &serde_json::Value {
+        &payload.properties.as_ref().unwrap()["duration"]
+    }
+
+    #[test]
+    fn test_seconds_converted_to_milliseconds() {
+        for sent in [
+            serde_json::json!("2.5s"),
+            serde_json::json!("2.5 s"),
+            serde_json::json!({"value": 2.5, "unit": "s"}),
+        ] {
+            let mut event = payload(sent.clone());
+            apply(&mut event, &config());
+            assert_eq!(duration(&event), &serde_json::json!(2500), "{}", sent);
+            assert_eq!(event.unit_violations, None);
+        }
+
+        // Bare numbers are already canonical
+        let mut event = payload(serde_json::json!(1200));
+        apply(&mut event, &config());
+        assert_eq!(duration(&event), &serde_json::json!(1200));
+    }
+
+    #[test]
+    fn test_invalid_values_are_flagged() {
+        for sent in [
+            serde_json::json!("2.5 fortnights"),
+            serde_json::json!("slow"),
+            serde_json::json!(true),
+        ] {
+            let mut event = payload(sent.clone());
+            apply(&mut event, &config());
+            assert_eq!(duration(&event), &sent);
+            assert_eq!(event.unit_violations, Some(vec!["duration".to_string()]));
+        }
+    }
+}
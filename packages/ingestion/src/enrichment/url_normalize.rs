@@ -0,0 +1,246 @@
+//! Page url normalization.
+//!
+//! Raw urls carry fragments, click ids and record ids, so the same page
+//! shows up under thousands of distinct urls. With
+//! `URL_NORMALIZATION_ENABLED`, `context.page.url` and `properties.url` are
+//! rewritten:
+//!
+//! - the host is lowercased (and default ports dropped)
+//! - the fragment is removed, unless `URL_STRIP_FRAGMENT` is `false`
+//! - query parameters in `URL_STRIP_QUERY_PARAMS` are removed (default:
+//!   UTM parameters and common click ids); a trailing `*` matches a prefix
+//! - path rules from `URL_PATH_RULES` collapse ids in the path, e.g.
+//!   `{"shop": [{"pattern": "^/users/\\d+", "replacement": "/users/:id"}]}`.
+//!   Rules under `"*"` apply to every project, after the project's own.
+//!
+//! Path rules also apply to `context.page.path`. Runs after campaign
+//! attribution, channel and referrer classification, which read the
+//! parameters it strips.
+
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::HashMap;
+use url::Url;
+
+use crate::models::IngestEventPayload;
+use crate::shared::{env_flag, env_json, env_list, env_or};
+
+const DEFAULT_STRIP_PARAMS: &[&str] = &[
+    "utm_*",
+    "gclid",
+    "gbraid",
+    "wbraid",
+    "fbclid",
+    "msclkid",
+    "dclid",
+    "twclid",
+    "mc_cid",
+    "mc_eid",
+    "_ga",
+    "_gl",
+];
+
+/// A path rewrite
+#[derive(Debug, Clone)]
+pub struct PathRule {
+    pub pattern: Regex,
+    pub replacement: String,
+}
+
+#[derive(Deserialize)]
+struct RawPathRule {
+    pattern: String,
+    replacement: String,
+}
+
+/// Configuration for url normalization
+#[derive(Debug, Clone)]
+pub struct UrlNormalizeConfig {
+    pub enabled: bool,
+    pub strip_fragment: bool,
+    /// Query parameters removed; `*` at the end matches a prefix
+    pub strip_params: Vec<String>,
+    /// Path rules by project id, `"*"` for all projects
+    pub path_rules: HashMap<String, Vec<PathRule>>,
+}
+
+impl Default for UrlNormalizeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            strip_fragment: true,
+            strip_params: DEFAULT_STRIP_PARAMS.iter().map(|p| p.to_string()).collect(),
+            path_rules: HashMap::new(),
+        }
+    }
+}
+
+impl UrlNormalizeConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let strip_params = env_list("URL_STRIP_QUERY_PARAMS");
+        let raw_rules: HashMap<String, Vec<RawPathRule>> = env_json("URL_PATH_RULES").unwrap_or_default();
+        Self {
+            enabled: env_flag("URL_NORMALIZATION_ENABLED"),
+            strip_fragment: env_or("URL_STRIP_FRAGMENT", defaults.strip_fragment),
+            strip_params: if strip_params.is_empty() { defaults.strip_params } else { strip_params },
+            path_rules: raw_rules
+                .into_iter()
+                .map(|(project, rules)| {
+                    let rules = rules
+                        .into_iter()
+                        .filter_map(|rule| match Regex::new(&rule.pattern) {
+                            Ok(pattern) => Some(PathRule {
+                                pattern,
+                                replacement: rule.replacement,
+                            }),
+                            Err(e) => {
+                                tracing::warn!("Ignoring URL_PATH_RULES pattern {}: {}", rule.pattern, e);
+                                None
+                            }
+                        })
+                        .collect();
+                    (project, rules)
+                })
+                .collect(),
+        }
+    }
+
+    fn strips(&self, param: &str) -> bool {
+        self.strip_params.iter().any(|pattern| match pattern.strip_suffix('*') {
+            Some(prefix) => param.starts_with(prefix),
+            None => param == pattern,
+        })
+    }
+
+    /// The project's rules, then the rules for every project
+    fn rules_for<'a>(&'a self, project_id: &str) -> impl Iterator<Item = &'a PathRule> {
+        let project = self.path_rules.get(project_id).into_iter().flatten();
+        project.chain(self.path_rules.get("*").into_iter().flatten())
+    }
+
+    /// A path with every matching rule applied in turn
+    pub fn collapse_path(&self, project_id: &str, path: &str) -> String {
+        self.rules_for(project_id).fold(path.to_string(), |path, rule| {
+            rule.pattern.replace_all(&path, rule.replacement.as_str()).into_owned()
+        })
+    }
+}
+
+/// The normalized form of a url; urls that don't parse are left as they are
+pub fn normalize(url: &str, project_id: &str, config: &UrlNormalizeConfig) -> String {
+    let Ok(mut parsed) = Url::parse(url) else {
+        return url.to_string();
+    };
+
+    if config.strip_fragment {
+        parsed.set_fragment(None);
+    }
+
+    if parsed.query().is_some() {
+        let kept: Vec<(String, String)> = parsed
+            .query_pairs()
+            .filter(|(key, _)| !config.strips(&key.to_ascii_lowercase()))
+            .map(|(key, value)| (key.into_owned(), value.into_owned()))
+            .collect();
+        if kept.is_empty() {
+            parsed.set_query(None);
+        } else {
+            parsed.query_pairs_mut().clear().extend_pairs(kept);
+        }
+    }
+
+    let path = config.collapse_path(project_id, parsed.path());
+    parsed.set_path(&path);
+    parsed.into()
+}
+
+/// Normalizes the event's page url, `properties.url` and page path
+pub fn apply(payload: &mut IngestEventPayload, config: &UrlNormalizeConfig) {
+    let project_id = payload.project_id.clone();
+    if let Some(page) = payload.context.as_mut().and_then(|c| c.page.as_mut()) {
+        if let Some(ref url) = page.url {
+            page.url = Some(normalize(url, &project_id, config));
+        }
+        if let Some(ref path) = page.path {
+            page.path = Some(config.collapse_path(&project_id, path));
+        }
+    }
+    if let Some(url) = payload.properties.as_mut().and_then(|p| p.get_mut("url")) {
+        if let Some(normalized) = url.as_str().map(|raw| normalize(raw, &project_id, config)) {
+            *url = normalized.into();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{EventContext, PageContext};
+
+    fn config() -> UrlNormalizeConfig {
+        let rules = |rules: &[(&str, &str)]| {
+            rules
+                .iter()
+                .map(|(pattern, replacement)| PathRule {
+                    pattern: Regex::new(pattern).unwrap(),
+                    replacement: replacement.to_string(),
+                })
+                .collect()
+        };
+        UrlNormalizeConfig {
+            enabled: true,
+            path_rules: HashMap::from([
+                ("shop".to_string(), rules(&[(r"^/users/\d+", "/users/:id")])),
+                ("*".to_string(), rules(&[(r"/[0-9a-f]{8}-[0-9a-f-]{27}", "/:uuid")])),
+            ]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_strips_tracking_params_and_fragment() {
+        let normalize = |url: &str| normalize(url, "shop", &config());
+        assert_eq!(
+            normalize("https://Shop.IO:443/Cart?utm_source=x&item=3&gclid=abc#reviews"),
+            "https://shop.io/Cart?item=3"
+        );
+        assert_eq!(normalize("https://shop.io/?UTM_MEDIUM=cpc&fbclid=f"), "https://shop.io/");
+        assert_eq!(normalize("/relative?utm_source=x"), "/relative?utm_source=x");
+    }
+
+    #[test]
+    fn test_path_rules_per_project() {
+        let url = "https://shop.io/users/123/orders/0b5e8a7c-3f1d-4c2a-9e6b-7d8f9a0b1c2d";
+        assert_eq!(normalize(url, "shop", &config()), "https://shop.io/users/:id/orders/:uuid");
+        assert_eq!(normalize(url, "blog", &config()), "https://shop.io/users/123/orders/:uuid");
+
+        let keep_fragment = UrlNormalizeConfig {
+            strip_fragment: false,
+            ..config()
+        };
+        assert_eq!(normalize("https://app.io/#/settings", "app", &keep_fragment), "https://app.io/#/settings");
+    }
+
+    #[test]
+    fn test_applies_to_page_context_and_properties() {
+        let mut event = IngestEventPayload {
+            project_id: "shop".to_string(),
+            properties: Some(HashMap::from([("url".to_string(), "https://shop.io/users/7?utm_source=x".into())])),
+            context: Some(EventContext {
+                page: Some(PageContext {
+                    url: Some("https://shop.io/users/7#top".to_string()),
+                    path: Some("/users/7".to_string()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        apply(&mut event, &config());
+        let page = event.context.unwrap().page.unwrap();
+        assert_eq!(page.url.as_deref(), Some("https://shop.io/users/:id"));
+        assert_eq!(page.path.as_deref(), Some("/users/:id"));
+        assert_eq!(event.properties.unwrap()["url"], "https://shop.io/users/:id");
+    }
+}
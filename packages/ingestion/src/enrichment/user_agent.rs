@@ -0,0 +1,243 @@
+//! User-agent parsing.
+//!
+//! With `USER_AGENT_PARSING_ENABLED`, `context.user_agent` is parsed once at
+//! ingestion into `context.device` (browser, OS, device type, bot flag), so
+//! consumers can group by them without each shipping a UA parser. This is a
+//! token match over the major browsers and platforms, not a full UA
+//! database; anything it doesn't recognize is left unset.
+
+use crate::enrichment::bot_score::BOT_UA_MARKERS;
+use crate::models::{DeviceContext, DeviceType, EventContext};
+
+/// Browser tokens, most specific first: Edge and Opera also claim Chrome,
+/// and everything on iOS claims Safari
+const BROWSERS: &[(&str, &str)] = &[
+    ("EdgiOS/", "Edge"),
+    ("EdgA/", "Edge"),
+    ("Edg/", "Edge"),
+    ("Edge/", "Edge"),
+    ("OPR/", "Opera"),
+    ("SamsungBrowser/", "Samsung Internet"),
+    ("FxiOS/", "Firefox"),
+    ("Firefox/", "Firefox"),
+    ("CriOS/", "Chrome"),
+    ("Chrome/", "Chrome"),
+    ("Version/", "Safari"),
+    ("MSIE ", "Internet Explorer"),
+    ("rv:", "Internet Explorer"),
+];
+
+/// Marketing names of Windows NT versions
+const WINDOWS_VERSIONS: &[(&str, &str)] = &[
+    ("10.0", "10"),
+    ("6.3", "8.1"),
+    ("6.2", "8"),
+    ("6.1", "7"),
+    ("6.0", "Vista"),
+    ("5.1", "XP"),
+];
+
+/// The dotted version right after `token`, if any
+fn version_after(user_agent: &str, token: &str) -> Option<String> {
+    let start = user_agent.find(token)? + token.len();
+    let version: String = user_agent[start..]
+        .chars()
+        .take_while(|c| c.is_ascii_digit() || *c == '.' || *c == '_')
+        .map(|c| if c == '_' { '.' } else { c })
+        .collect();
+    let version = version.trim_end_matches('.');
+    (!version.is_empty()).then(|| version.to_string())
+}
+
+fn browser(user_agent: &str) -> (Option<String>, Option<String>) {
+    let found = BROWSERS.iter().find(|(token, name)| match *name {
+        "Safari" => user_agent.contains(token) && user_agent.contains("Safari/"),
+        "Internet Explorer" => user_agent.contains(token) && user_agent.contains("Trident/"),
+        _ => user_agent.contains(token),
+    });
+    match found {
+        Some((token, name)) => (Some(name.to_string()), version_after(user_agent, token)),
+        None => (None, None),
+    }
+}
+
+fn os(user_agent: &str) -> (Option<String>, Option<String>) {
+    let (name, version) = if let Some(nt) = version_after(user_agent, "Windows NT ") {
+        let version = WINDOWS_VERSIONS
+            .iter()
+            .find(|(kernel, _)| *kernel == nt)
+            .map_or(nt, |(_, name)| name.to_string());
+        ("Windows", Some(version))
+    } else if ["iPhone", "iPad", "iPod"].iter().any(|device| user_agent.contains(device)) {
+        ("iOS", version_after(user_agent, " OS "))
+    } else if user_agent.contains("Android") {
+        ("Android", version_after(user_agent, "Android "))
+    } else if user_agent.contains("CrOS") {
+        ("Chrome OS", None)
+    } else if user_agent.contains("Mac OS X") {
+        ("macOS", version_after(user_agent, "Mac OS X "))
+    } else if user_agent.contains("Linux") {
+        ("Linux", None)
+    } else {
+        return (None, None);
+    };
+    (Some(name.to_string()), version)
+}
+
+fn device_type(user_agent: &str, os: Option<&str>, is_bot: bool) -> DeviceType {
+    if is_bot {
+        DeviceType::Bot
+    } else if user_agent.contains("iPad")
+        || user_agent.contains("Tablet")
+        || (os == Some("Android") && !user_agent.contains("Mobile"))
+    {
+        DeviceType::Tablet
+    } else if user_agent.contains("Mobi") || user_agent.contains("iPhone") || user_agent.contains("iPod") {
+        DeviceType::Mobile
+    } else if os.is_some() {
+        DeviceType::Desktop
+    } else {
+        DeviceType::Unknown
+    }
+}
+
+/// Parses a user-agent string
+pub fn parse(user_agent: &str) -> DeviceContext {
+    let lowercase = user_agent.to_ascii_lowercase();
+    let is_bot = BOT_UA_MARKERS.iter().any(|marker| lowercase.contains(marker));
+    let (browser, browser_version) = browser(user_agent);
+    let (os, os_version) = os(user_agent);
+    DeviceContext {
+        device_type: Some(device_type(user_agent, os.as_deref(), is_bot)),
+        browser,
+        browser_version,
+        os,
+        os_version,
+        is_bot: Some(is_bot),
+        ..Default::default()
+    }
+}
+
+/// Fills `context.device` from `context.user_agent`, keeping any device
+/// fields the client sent itself
+pub fn apply(context: &mut EventContext) {
+    let Some(user_agent) = context.user_agent.as_deref().filter(|ua| !ua.trim().is_empty()) else {
+        return;
+    };
+    let parsed = parse(user_agent);
+    let device = context.device.get_or_insert_with(Default::default);
+    device.browser = device.browser.take().or(parsed.browser);
+    device.browser_version = device.browser_version.take().or(parsed.browser_version);
+    device.os = device.os.take().or(parsed.os);
+    device.os_version = device.os_version.take().or(parsed.os_version);
+    device.device_type = device.device_type.take().or(parsed.device_type);
+    device.is_bot = device.is_bot.take().or(parsed.is_bot);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(user_agent: &str) -> (String, String, String, String, DeviceType) {
+        let device = parse(user_agent);
+        (
+            device.browser.unwrap_or_default(),
+            device.browser_version.unwrap_or_default(),
+            device.os.unwrap_or_default(),
+            device.os_version.unwrap_or_default(),
+            device.device_type.unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_parses_common_browsers() {
+        let cases = [
+            (
+                "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) \
+                 Chrome/120.0.6099.109 Safari/537.36",
+                ("Chrome", "120.0.6099.109", "Windows", "10", DeviceType::Desktop),
+            ),
+            (
+                "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) \
+                 Chrome/120.0.0.0 Safari/537.36 Edg/120.0.2210.77",
+                ("Edge", "120.0.2210.77", "Windows", "10", DeviceType::Desktop),
+            ),
+            (
+                "Mozilla/5.0 (iPhone; CPU iPhone OS 17_1_2 like Mac OS X) AppleWebKit/605.1.15 \
+                 (KHTML, like Gecko) Version/17.1.2 Mobile/15E148 Safari/604.1",
+                ("Safari", "17.1.2", "iOS", "17.1.2", DeviceType::Mobile),
+            ),
+            (
+                "Mozilla/5.0 (iPad; CPU OS 16_6 like Mac OS X) AppleWebKit/605.1.15 \
+                 (KHTML, like Gecko) CriOS/119.0.6045.169 Mobile/15E148 Safari/604.1",
+                ("Chrome", "119.0.6045.169", "iOS", "16.6", DeviceType::Tablet),
+            ),
+            (
+                "Mozilla/5.0 (Macintosh; Intel Mac OS X 10.15; rv:121.0) Gecko/20100101 Firefox/121.0",
+                ("Firefox", "121.0", "macOS", "10.15", DeviceType::Desktop),
+            ),
+            (
+                "Mozilla/5.0 (Linux; Android 14; Pixel 8) AppleWebKit/537.36 (KHTML, like Gecko) \
+                 Chrome/120.0.6099.43 Mobile Safari/537.36",
+                ("Chrome", "120.0.6099.43", "Android", "14", DeviceType::Mobile),
+            ),
+            (
+                "Mozilla/5.0 (Linux; Android 13; SM-X700) AppleWebKit/537.36 (KHTML, like Gecko) \
+                 SamsungBrowser/23.0 Chrome/115.0.0.0 Safari/537.36",
+                ("Samsung Internet", "23.0", "Android", "13", DeviceType::Tablet),
+            ),
+            (
+                "Mozilla/5.0 (Windows NT 6.1; Trident/7.0; rv:11.0) like Gecko",
+                ("Internet Explorer", "11.0", "Windows", "7", DeviceType::Desktop),
+            ),
+        ];
+
+        for (user_agent, (browser, version, os, os_version, device_type)) in cases {
+            assert_eq!(
+                parsed(user_agent),
+                (
+                    browser.to_string(),
+                    version.to_string(),
+                    os.to_string(),
+                    os_version.to_string(),
+                    device_type
+                ),
+                "{}",
+                user_agent
+            );
+        }
+    }
+
+    #[test]
+    fn test_bots_and_unknown_agents() {
+        let googlebot = parse("Mozilla/5.0 (compatible; Googlebot/2.1; +http://www.google.com/bot.html)");
+        assert_eq!(googlebot.is_bot, Some(true));
+        assert_eq!(googlebot.device_type, Some(DeviceType::Bot));
+
+        let unknown = parse("SomeEmbeddedClient");
+        assert_eq!(unknown.is_bot, Some(false));
+        assert_eq!(unknown.browser, None);
+        assert_eq!(unknown.device_type, Some(DeviceType::Unknown));
+    }
+
+    #[test]
+    fn test_client_device_fields_win() {
+        let mut context = EventContext {
+            user_agent: Some("Mozilla/5.0 (Linux; Android 14; Pixel 8) Chrome/120.0 Mobile".to_string()),
+            device: Some(DeviceContext {
+                os: Some("Android TV".to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        apply(&mut context);
+
+        let device = context.device.unwrap();
+        assert_eq!(device.os.as_deref(), Some("Android TV"));
+        assert_eq!(device.browser.as_deref(), Some("Chrome"));
+
+        let mut context = EventContext::default();
+        apply(&mut context);
+        assert!(context.device.is_none());
+    }
+}
@@ -0,0 +1,500 @@
+//! Per-project event-name governance.
+//!
+//! With `EVENT_NAME_POLICIES_ENABLED`, each event's name is checked against
+//! its project's policy, so the tracking plan doesn't drift as new events
+//! are invented in the frontend. Policies live in an
+//! [`EventNamePolicyStore`] (a DynamoDB table in production) and lookups,
+//! including misses, are cached per sandbox for
+//! `EVENT_NAME_POLICY_CACHE_TTL_SECS`. A policy is a JSON document:
+//!
+//! - `pattern`: a regex every name must match, e.g. `^[a-z]+(_[a-z]+)*$`
+//! - `allowed`: the approved names; any name when absent
+//! - `enforce`: reject events that break the policy with a 422 (a batch
+//!   rejects just that event, reason `event_name_not_allowed`); when
+//!   `false`, they're only reported. Defaults to `true`.
+//!
+//! Names produced by the typed endpoints ([`BUILT_IN_EVENTS`]) always pass.
+//! Projects without a policy accept any name.
+//!
+//! Whatever the policy, the first sighting of each name in a project is
+//! kept as a [`NewEventName`] audit record in an [`EventNameLog`]
+//! (`EVENT_NAMES_TABLE`), logged and counted in the `NewEventName` metric.
+//! Names already seen by a sandbox aren't looked up again.
+
+use async_trait::async_trait;
+use aws_sdk_dynamodb::operation::put_item::PutItemError;
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use lambda_http::{Body, Error, Response};
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::metrics::{MetricSet, MetricsConfig};
+use crate::models::IngestEventPayload;
+use crate::schema::Violation;
+use crate::shared::{create_response, env_flag, env_or, env_var, AppState};
+
+/// Event names of the typed endpoints, which no policy rejects
+pub const BUILT_IN_EVENTS: [&str; 11] = [
+    "pageview",
+    "identify",
+    "group",
+    "alias",
+    "web_vital",
+    "error",
+    "heartbeat",
+    "click",
+    "scroll_depth",
+    "exposure",
+    "screen",
+];
+
+/// Names a sandbox remembers as seen before starting over
+const MAX_SEEN_NAMES: usize = 10_000;
+
+/// Configuration for event-name governance
+#[derive(Debug, Clone)]
+pub struct EventNamesConfig {
+    pub enabled: bool,
+    /// DynamoDB policy table; in-memory (no policies) when unset
+    pub policy_table: Option<String>,
+    pub cache_ttl: Duration,
+    /// DynamoDB table of first sightings; in-memory when unset
+    pub names_table: Option<String>,
+}
+
+impl Default for EventNamesConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            policy_table: None,
+            cache_ttl: Duration::from_secs(300),
+            names_table: None,
+        }
+    }
+}
+
+impl EventNamesConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            enabled: env_flag("EVENT_NAME_POLICIES_ENABLED"),
+            policy_table: env_var("EVENT_NAME_POLICY_TABLE"),
+            cache_ttl: Duration::from_secs(env_or(
+                "EVENT_NAME_POLICY_CACHE_TTL_SECS",
+                defaults.cache_ttl.as_secs(),
+            )),
+            names_table: env_var("EVENT_NAMES_TABLE"),
+        }
+    }
+}
+
+fn enforced() -> bool {
+    true
+}
+
+fn pattern<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Regex>, D::Error> {
+    let Some(pattern) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    Regex::new(&pattern).map(Some).map_err(serde::de::Error::custom)
+}
+
+/// A project's event-name policy
+#[derive(Debug, Clone, Deserialize)]
+pub struct EventNamePolicy {
+    /// Naming convention every name must match
+    #[serde(default, deserialize_with = "pattern")]
+    pub pattern: Option<Regex>,
+    /// Approved names; any name when unset
+    #[serde(default)]
+    pub allowed: Option<HashSet<String>>,
+    /// Reject events that break the policy, rather than only reporting them
+    #[serde(default = "enforced")]
+    pub enforce: bool,
+}
+
+impl EventNamePolicy {
+    /// Every way `name` breaks the policy
+    pub fn violations(&self, name: &str) -> Vec<Violation> {
+        if BUILT_IN_EVENTS.contains(&name) {
+            return Vec::new();
+        }
+        let mut violations = Vec::new();
+        if let Some(pattern) = self.pattern.as_ref().filter(|pattern| !pattern.is_match(name)) {
+            violations.push(Violation {
+                path: "event".to_string(),
+                message: format!("{} does not match the naming convention {}", name, pattern),
+            });
+        }
+        if self.allowed.as_ref().is_some_and(|allowed| !allowed.contains(name)) {
+            violations.push(Violation {
+                path: "event".to_string(),
+                message: format!("{} is not an approved event name", name),
+            });
+        }
+        violations
+    }
+}
+
+/// Policy store, keyed by project
+#[async_trait]
+pub trait EventNamePolicyStore: Send + Sync {
+    async fn get(&self, project_id: &str) -> Result<Option<EventNamePolicy>, Error>;
+}
+
+/// Process-local store, used in tests and when no table is configured
+#[derive(Debug, Default)]
+pub struct InMemoryEventNamePolicyStore {
+    policies: Mutex<HashMap<String, EventNamePolicy>>,
+}
+
+impl InMemoryEventNamePolicyStore {
+    pub fn insert(&self, project_id: &str, policy: EventNamePolicy) {
+        self.policies.lock().unwrap().insert(project_id.to_string(), policy);
+    }
+}
+
+#[async_trait]
+impl EventNamePolicyStore for InMemoryEventNamePolicyStore {
+    async fn get(&self, project_id: &str) -> Result<Option<EventNamePolicy>, Error> {
+        Ok(self.policies.lock().unwrap().get(project_id).cloned())
+    }
+}
+
+/// DynamoDB-backed store
+/// Table schema: partition key `project_id` (S), attribute `policy` (S, the
+/// JSON policy)
+pub struct DynamoEventNamePolicyStore {
+    client: DynamoClient,
+    table_name: String,
+}
+
+impl DynamoEventNamePolicyStore {
+    pub fn new(client: DynamoClient, table_name: String) -> Self {
+        Self { client, table_name }
+    }
+}
+
+#[async_trait]
+impl EventNamePolicyStore for DynamoEventNamePolicyStore {
+    async fn get(&self, project_id: &str) -> Result<Option<EventNamePolicy>, Error> {
+        let output = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("project_id", AttributeValue::S(project_id.to_string()))
+            .send()
+            .await?;
+
+        let Some(policy) = output.item().and_then(|item| item.get("policy")).and_then(|v| v.as_s().ok()) else {
+            return Ok(None);
+        };
+        match serde_json::from_str(policy) {
+            Ok(policy) => Ok(Some(policy)),
+            Err(e) => {
+                tracing::warn!("Ignoring invalid event name policy for {}: {}", project_id, e);
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Audit record of the first event seen with a name
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NewEventName {
+    pub project_id: String,
+    pub event_name: String,
+    /// Epoch milliseconds
+    pub first_seen_at: i64,
+    /// Whether the name passed the project's policy
+    pub allowed: bool,
+    /// Property names of the first event, sorted
+    pub property_names: Vec<String>,
+    /// SDK that sent it, from `context.library`
+    pub library: Option<String>,
+}
+
+impl NewEventName {
+    pub fn of(payload: &IngestEventPayload, allowed: bool) -> Self {
+        let mut property_names: Vec<String> = payload.properties.iter().flatten().map(|(name, _)| name.clone()).collect();
+        property_names.sort_unstable();
+        let library = payload.context.as_ref().and_then(|c| c.library.as_ref()).and_then(|library| {
+            let name = library.name.as_deref()?;
+            Some(match library.version.as_deref() {
+                Some(version) => format!("{}/{}", name, version),
+                None => name.to_string(),
+            })
+        });
+        Self {
+            project_id: payload.project_id.clone(),
+            event_name: payload.event_type.clone(),
+            first_seen_at: chrono::Utc::now().timestamp_millis(),
+            allowed,
+            property_names,
+            library,
+        }
+    }
+}
+
+/// Where first sightings of event names are kept
+#[async_trait]
+pub trait EventNameLog: Send + Sync {
+    /// Keeps the record unless its project already has the name; returns
+    /// whether it was new
+    async fn record(&self, sighting: &NewEventName) -> Result<bool, Error>;
+}
+
+/// Process-local log, used in tests and when no table is configured
+#[derive(Debug, Default)]
+pub struct InMemoryEventNameLog {
+    pub sightings: Mutex<Vec<NewEventName>>,
+}
+
+#[async_trait]
+impl EventNameLog for InMemoryEventNameLog {
+    async fn record(&self, sighting: &NewEventName) -> Result<bool, Error> {
+        let mut sightings = self.sightings.lock().unwrap();
+        let known = sightings
+            .iter()
+            .any(|known| known.project_id == sighting.project_id && known.event_name == sighting.event_name);
+        if !known {
+            sightings.push(sighting.clone());
+        }
+        Ok(!known)
+    }
+}
+
+/// DynamoDB-backed log
+/// Table schema: partition key `pk` (S, `{project_id}#{event_name}`),
+/// attributes `project_id` (S), `event_name` (S), `first_seen_at` (N),
+/// `allowed` (BOOL) and `record` (S, the JSON record)
+pub struct DynamoEventNameLog {
+    client: DynamoClient,
+    table_name: String,
+}
+
+impl DynamoEventNameLog {
+    pub fn new(client: DynamoClient, table_name: String) -> Self {
+        Self { client, table_name }
+    }
+}
+
+#[async_trait]
+impl EventNameLog for DynamoEventNameLog {
+    async fn record(&self, sighting: &NewEventName) -> Result<bool, Error> {
+        let result = self
+            .client
+            .put_item()
+            .table_name(&self.table_name)
+            .item("pk", AttributeValue::S(format!("{}#{}", sighting.project_id, sighting.event_name)))
+            .item("project_id", AttributeValue::S(sighting.project_id.clone()))
+            .item("event_name", AttributeValue::S(sighting.event_name.clone()))
+            .item("first_seen_at", AttributeValue::N(sighting.first_seen_at.to_string()))
+            .item("allowed", AttributeValue::Bool(sighting.allowed))
+            .item("record", AttributeValue::S(serde_json::to_string(sighting)?))
+            .condition_expression("attribute_not_exists(pk)")
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => Ok(true),
+            Err(err) => match err.into_service_error() {
+                PutItemError::ConditionalCheckFailedException(_) => Ok(false),
+                other => Err(other.into()),
+            },
+        }
+    }
+}
+
+/// Cached entry: the policy (if any) and when it was fetched
+type CachedPolicy = (Option<Arc<EventNamePolicy>>, Instant);
+
+/// Recent policy lookups in front of an [`EventNamePolicyStore`], and the
+/// names this sandbox has already logged
+pub struct EventNameRegistry {
+    policies: Arc<dyn EventNamePolicyStore>,
+    log: Arc<dyn EventNameLog>,
+    entries: Mutex<HashMap<String, CachedPolicy>>,
+    seen: Mutex<HashSet<(String, String)>>,
+}
+
+impl EventNameRegistry {
+    pub fn new(policies: Arc<dyn EventNamePolicyStore>, log: Arc<dyn EventNameLog>) -> Self {
+        Self {
+            policies,
+            log,
+            entries: Mutex::new(HashMap::new()),
+            seen: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Policy of a project, from the cache while younger than `ttl`
+    pub async fn policy(&self, project_id: &str, ttl: Duration) -> Result<Option<Arc<EventNamePolicy>>, Error> {
+        if let Some((policy, fetched_at)) = self.entries.lock().unwrap().get(project_id) {
+            if fetched_at.elapsed() < ttl {
+                return Ok(policy.clone());
+            }
+        }
+
+        let policy = self.policies.get(project_id).await?.map(Arc::new);
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(project_id.to_string(), (policy.clone(), Instant::now()));
+        Ok(policy)
+    }
+
+    /// Logs the event's name if its project hasn't seen it before. Best
+    /// effort: a failed write is logged and retried with the next event.
+    pub async fn note(&self, payload: &IngestEventPayload, allowed: bool, metrics: &MetricsConfig) {
+        let key = (payload.project_id.clone(), payload.event_type.clone());
+        if self.seen.lock().unwrap().contains(&key) {
+            return;
+        }
+
+        let sighting = NewEventName::of(payload, allowed);
+        match self.log.record(&sighting).await {
+            Ok(new) => {
+                if new {
+                    tracing::info!(
+                        project_id = %sighting.project_id,
+                        event_name = %sighting.event_name,
+                        allowed,
+                        "New event name detected"
+                    );
+                    MetricSet::new(metrics)
+                        .dimension("ProjectId", sighting.project_id.as_str())
+                        .count("NewEventName", 1)
+                        .emit();
+                }
+                let mut seen = self.seen.lock().unwrap();
+                if seen.len() >= MAX_SEEN_NAMES {
+                    seen.clear();
+                }
+                seen.insert(key);
+            }
+            Err(e) => tracing::warn!("Failed to record event name {}: {}", sighting.event_name, e),
+        }
+    }
+}
+
+/// Checks an event's name against its project's policy and logs names
+/// seen for the first time. Violations are returned when the policy is
+/// enforced.
+#[tracing::instrument(name = "validate.event_name", skip_all)]
+pub async fn check(payload: &IngestEventPayload, state: &AppState) -> Result<Result<(), Vec<Violation>>, Error> {
+    let config = &state.config.event_names;
+    if !config.enabled {
+        return Ok(Ok(()));
+    }
+
+    let policy = state.event_names.policy(&payload.project_id, config.cache_ttl).await?;
+    let violations = policy
+        .as_deref()
+        .map(|policy| policy.violations(&payload.event_type))
+        .unwrap_or_default();
+    state
+        .event_names
+        .note(payload, violations.is_empty(), &state.config.metrics)
+        .await;
+
+    match policy {
+        Some(policy) if policy.enforce && !violations.is_empty() => Ok(Err(violations)),
+        _ => Ok(Ok(())),
+    }
+}
+
+/// 422 listing why the name isn't allowed
+pub fn violation_response(violations: &[Violation]) -> Response<Body> {
+    create_response(
+        422,
+        serde_json::json!({
+            "error": "Event name not allowed",
+            "violations": violations,
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::{test_state, Config};
+    use serde_json::json;
+
+    fn policy(policy: serde_json::Value) -> EventNamePolicy {
+        serde_json::from_value(policy).unwrap()
+    }
+
+    fn event(event_type: &str) -> IngestEventPayload {
+        IngestEventPayload {
+            project_id: "proj".to_string(),
+            event_type: event_type.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn governed(policy: EventNamePolicy) -> (AppState, Arc<InMemoryEventNameLog>) {
+        let policies = InMemoryEventNamePolicyStore::default();
+        policies.insert("proj", policy);
+        let log = Arc::new(InMemoryEventNameLog::default());
+        let mut state = test_state(Config {
+            event_names: EventNamesConfig {
+                enabled: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        state.event_names = Arc::new(EventNameRegistry::new(Arc::new(policies), log.clone()));
+        (state, log)
+    }
+
+    #[test]
+    fn test_names_must_follow_the_convention_and_the_allowlist() {
+        let policy = policy(json!({"pattern": "^[a-z]+(_[a-z]+)*$", "allowed": ["signup", "Checkout"]}));
+        assert!(policy.violations("signup").is_empty());
+        assert!(policy.violations("pageview").is_empty());
+
+        let messages: Vec<String> = policy.violations("Checkout").iter().map(ToString::to_string).collect();
+        assert_eq!(messages, ["event: Checkout does not match the naming convention ^[a-z]+(_[a-z]+)*$"]);
+        assert_eq!(policy.violations("Signed Up").len(), 2);
+        assert_eq!(policy.violations("signed_up")[0].message, "signed_up is not an approved event name");
+
+        let invalid: Result<EventNamePolicy, _> = serde_json::from_value(json!({"pattern": "(unclosed"}));
+        assert!(invalid.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_enforced_policies_reject_and_others_only_report() {
+        let (state, _) = governed(policy(json!({"allowed": ["signup"]})));
+        assert!(check(&event("signup"), &state).await.unwrap().is_ok());
+        assert_eq!(check(&event("debug_ping"), &state).await.unwrap().unwrap_err().len(), 1);
+
+        let (state, log) = governed(policy(json!({"allowed": ["signup"], "enforce": false})));
+        assert!(check(&event("debug_ping"), &state).await.unwrap().is_ok());
+        assert!(!log.sightings.lock().unwrap()[0].allowed);
+    }
+
+    #[tokio::test]
+    async fn test_first_sightings_are_recorded_once() {
+        let (state, log) = governed(policy(json!({})));
+        let mut signup = event("signup");
+        signup.properties = Some(HashMap::from([
+            ("plan".to_string(), json!("pro")),
+            ("coupon".to_string(), json!(null)),
+        ]));
+        for payload in [&signup, &event("signup"), &event("invite_sent")] {
+            check(payload, &state).await.unwrap().unwrap();
+        }
+
+        let sightings = log.sightings.lock().unwrap();
+        let names: Vec<_> = sightings.iter().map(|s| s.event_name.as_str()).collect();
+        assert_eq!(names, ["signup", "invite_sent"]);
+        assert_eq!(sightings[0].property_names, ["coupon", "plan"]);
+        assert!(sightings[0].allowed);
+    }
+}
@@ -1,8 +1,46 @@
 use lambda_http::{Body, Error, Request, Response};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::HashMap;
 use std::sync::Arc;
+use tracing::instrument::WithSubscriber;
+use tracing::subscriber::NoSubscriber;
 
-use crate::models::{CompressedEvent, IngestEventPayload, EventContext};
-use crate::shared::{create_error_response, create_text_response, process_events, AppState};
+use crate::auth;
+use crate::autocapture;
+use crate::backpressure;
+use crate::body;
+use crate::clock;
+use crate::consent;
+use crate::dedup;
+use crate::ecommerce;
+use crate::enrichment::{self, experiments, user_agent};
+use crate::event_names;
+use crate::idempotency::{self, Claim};
+use crate::jwt;
+use crate::limits;
+use crate::metering;
+use crate::metrics::MetricSet;
+use crate::openapi;
+use crate::rate_limit::{self, Decision, RecordedDecision};
+use crate::request_id::RequestId;
+use crate::rules;
+use crate::sanitize;
+use crate::schema;
+use crate::sink::{self, PartialWrite};
+use crate::status;
+use crate::traits;
+use crate::validation::{self, ValidationError, ValidationErrors};
+use crate::version::ApiVersion;
+use crate::models::{
+    self, AliasEvent, Batch, BatchBody, CloudEvent, CompressedEvent, EventKind, GroupEvent, IdentifyEvent,
+    ClickEvent, ErrorEvent, ExposureEvent, HeartbeatEvent, IngestEventPayload, LibraryContext, ScreenEvent,
+    ScrollDepthEvent, WebVitalEvent,
+};
+use crate::shared::{
+    client_ip, create_empty_response, create_error_response, create_response,
+    create_text_response, header_value, process_events, query_param, AppState, Config, ResponseOverride,
+};
 
 /// JWT Claims structure
 #[derive(Debug, serde::Deserialize)]
@@ -14,9 +52,14 @@ struct JwtClaims {
     // Add other JWT fields as needed
 }
 
-/// Extracts JWT token from Authorization header and decodes it
+/// Extracts JWT token from Authorization header and decodes it, or takes
+/// the claims [`jwt::verify`] checked
 /// Returns (project_id, user_id)
-fn extract_jwt_info(request: &Request) -> Result<(String, Option<String>), String> {
+pub(crate) fn extract_jwt_info(request: &Request) -> Result<(String, Option<String>), String> {
+    if let Some(verified) = request.extensions().get::<jwt::Verified>() {
+        return Ok((verified.project_id.clone(), verified.user_id.clone()));
+    }
+
     let auth_header = request
         .headers()
         .get("authorization")
@@ -27,6 +70,11 @@ fn extract_jwt_info(request: &Request) -> Result<(String, Option<String>), Strin
         .strip_prefix("Bearer ")
         .ok_or_else(|| "Invalid Authorization header format".to_string())?;
 
+    decode_jwt(token)
+}
+
+/// Decodes a JWT into (project_id, user_id)
+pub fn decode_jwt(token: &str) -> Result<(String, Option<String>), String> {
     // For now, we'll just decode the JWT payload without verification
     // In production, you should verify the JWT signature
     let parts: Vec<&str> = token.split('.').collect();
@@ -52,31 +100,46 @@ fn extract_jwt_info(request: &Request) -> Result<(String, Option<String>), Strin
     Ok((project_id, claims.user_id))
 }
 
+/// An event's problems, and its timestamp's when the API version or config
+/// rejects non-positive timestamps
+fn with_timestamp(
+    problems: Result<(), ValidationErrors>,
+    (field, timestamp): (&str, Option<i64>),
+    request: &Request,
+    config: &Config,
+) -> Result<(), ValidationErrors> {
+    let mut problems = match problems {
+        Ok(()) => ValidationErrors::default(),
+        Err(problems) => problems,
+    };
+    if ApiVersion::of(request).requires_timestamps(config) {
+        problems.extend(models::validate_timestamp(field, timestamp));
+    }
+    problems.into_result()
+}
+
 /// Enriches the event with server-side metadata
-fn enrich_event(mut payload: IngestEventPayload, request: &Request) -> IngestEventPayload {
+pub(crate) fn enrich_event(
+    mut payload: IngestEventPayload,
+    request: &Request,
+    config: &Config,
+) -> IngestEventPayload {
     let now = chrono::Utc::now().timestamp_millis();
 
-    // Ensure timestamp is set
-    if payload.timestamp == 0 {
+    // Missing (0) or nonsensical (negative) client timestamps fall back to
+    // server time; with rejection enabled they never get this far
+    if payload.timestamp <= 0 {
         payload.timestamp = now;
+        payload.timestamp_defaulted = Some(true);
     }
+    clock::clamp(&mut payload, now, &config.timestamp_bounds);
 
     // Enrich context with server-side data
-    let mut context = payload.context.unwrap_or_else(|| EventContext {
-        page: None,
-        user_agent: None,
-        locale: None,
-        screen: None,
-        ip: None,
-        received_at: None,
-        extra: std::collections::HashMap::new(),
-    });
+    let mut context = payload.context.unwrap_or_default();
 
     // Add IP address from request context
     if context.ip.is_none() {
-        if let Some(headers) = request.headers().get("x-forwarded-for") {
-            context.ip = headers.to_str().ok().map(|s| s.split(',').next().unwrap_or("").trim().to_string());
-        }
+        context.ip = client_ip(request).map(String::from);
     }
 
     // Add user agent if not present
@@ -86,14 +149,243 @@ fn enrich_event(mut payload: IngestEventPayload, request: &Request) -> IngestEve
         }
     }
 
+    if config.user_agent_parsing {
+        user_agent::apply(&mut context);
+    }
+
     // Set received timestamp
     context.received_at = Some(now);
 
+    if let Some(request_id) = RequestId::of(request) {
+        context.extra.insert("requestId".to_string(), serde_json::json!(request_id));
+    }
+
+    if config.sdk_tagging {
+        let (name, version) = sdk_identity(request, context.library.as_ref());
+        payload.sdk_name = Some(name);
+        payload.sdk_version = Some(version);
+    }
+
+    if let Some(ref region) = config.residency.ingest_region {
+        payload.region = Some(region.clone());
+    }
+
     payload.context = Some(context);
     payload
 }
 
+/// Runs the optional enrichments. Keepalive beacons on the fast path run
+/// them without spans or logs: they arrive in bursts at page unload, and
+/// tracing each one costs more than it tells.
+pub(crate) async fn enrich(
+    payload: IngestEventPayload,
+    request: &Request,
+    state: &AppState,
+) -> Vec<IngestEventPayload> {
+    let events = enrichment::apply(payload, request, state);
+    if state.config.keepalive_fast_path && is_keepalive(request) {
+        events.with_subscriber(NoSubscriber::default()).await
+    } else {
+        events.await
+    }
+}
+
+/// SDK name and version from the `X-SDK-*` headers, falling back to
+/// `context.library`; lowercased name, version without a leading `v`
+fn sdk_identity(request: &Request, library: Option<&LibraryContext>) -> (String, String) {
+    let name = header_value(request, "x-sdk-name")
+        .or_else(|| library.and_then(|l| l.name.as_deref()).map(str::trim))
+        .filter(|name| !name.is_empty())
+        .map_or_else(|| "unknown".to_string(), str::to_ascii_lowercase);
+    let version = header_value(request, "x-sdk-version")
+        .or_else(|| library.and_then(|l| l.version.as_deref()).map(str::trim))
+        .map(|version| version.trim_start_matches(['v', 'V']))
+        .filter(|version| !version.is_empty())
+        .map_or_else(|| "unknown".to_string(), String::from);
+    (name, version)
+}
+
+/// Whether the client flagged the request as an unload-time keepalive beacon,
+/// via an `X-Keepalive` header or `?keepalive=` query parameter
+pub fn is_keepalive(request: &Request) -> bool {
+    header_value(request, "x-keepalive")
+        .or_else(|| query_param(request, "keepalive"))
+        .is_some_and(|v| matches!(v, "1" | "true"))
+}
+
+/// Builds the success response; keepalive beacons get a bare 204 when the
+/// fast path is enabled since nobody reads the body at page unload.
+/// Projects with a response override get their own status and body.
+/// Validation warnings switch the default body to JSON so they can be listed,
+/// as does v2, which always answers JSON.
+fn accepted_response(
+    request: &Request,
+    config: &Config,
+    project_id: &str,
+    warnings: &[String],
+) -> Response<Body> {
+    if config.keepalive_fast_path && is_keepalive(request) {
+        return create_empty_response(204);
+    }
+
+    let version = ApiVersion::of(request);
+    let (status, body) = match config.response_overrides.get(project_id) {
+        Some(ResponseOverride { status, body }) => (*status, body.as_ref()),
+        None => (version.success_status(config), None),
+    };
+
+    // A 204 has no body, not even an override's
+    if status == 204 {
+        return create_empty_response(204);
+    }
+    if let Some(body) = body {
+        let mut body = body.clone();
+        if let (Some(fields), false) = (body.as_object_mut(), warnings.is_empty()) {
+            fields.insert("warnings".to_string(), serde_json::json!(warnings));
+        }
+        return create_response(status, body);
+    }
+
+    if warnings.is_empty() && version == ApiVersion::V1 {
+        create_text_response(status, "ACCEPTED")
+    } else {
+        create_response(
+            status,
+            serde_json::json!({ "status": "accepted", "warnings": warnings }),
+        )
+    }
+}
+
+/// Shared tail of the single-event handlers: validates the normalized
+/// event, enriches it and sends it to the stream
+pub(crate) async fn ingest(
+    mut normalized: IngestEventPayload,
+    request: &Request,
+    state: Arc<AppState>,
+) -> Result<Response<Body>, Error> {
+    // First, so everything below sees the cleaned-up event
+    if !rules::apply(&mut normalized, &state).await? {
+        let response = accepted_response(request, &state.config, &normalized.project_id, &[]);
+        return Ok(response);
+    }
+
+    if let Some(rejection) = backpressure::check(&state, &normalized.project_id, &normalized.event_type) {
+        return Ok(rejection);
+    }
+
+    if state.config.page_context_validation {
+        if let Err(e) = normalized.ensure_page_context() {
+            return Ok(create_error_response(422, &e));
+        }
+    }
+
+    sanitize::apply(&mut normalized, &state.config.sanitize);
+    if let Err(e) = limits::check(&normalized, &state.config.payload_limits) {
+        return Ok(e.response(&state.config.payload_limits));
+    }
+
+    if let Err(e) = consent::enforce(&mut normalized, &state.config.consent) {
+        return Ok(create_error_response(422, &e));
+    }
+
+    if let Err(violations) = ecommerce::normalize(&mut normalized, &state.config.ecommerce) {
+        return Ok(ecommerce::violation_response(&violations));
+    }
+
+    if let Err(violations) = traits::normalize(&mut normalized, &state.config.traits) {
+        return Ok(traits::violation_response(&violations));
+    }
+
+    if let Err(violations) = event_names::check(&normalized, &state).await? {
+        return Ok(event_names::violation_response(&violations));
+    }
+
+    if let Err(violations) = schema::check(&mut normalized, &state).await? {
+        return Ok(schema::violation_response(&violations));
+    }
+
+    let project_id = normalized.project_id.clone();
+    if let Some(rejection) = metering::check(&state, &project_id).await {
+        return Ok(rejection);
+    }
+    let decision = check_rate_limit(&state, request, &project_id, 1).await;
+    if let Some(decision) = decision.filter(|d| !d.allowed) {
+        return Ok(rate_limit::too_many_requests(&decision));
+    }
+
+    let warnings = if state.config.validation_warnings {
+        let mut warnings = normalized.warnings(&state.config.deprecated_event_names);
+        if state.config.experiments.enabled {
+            warnings.extend(experiments::warnings(&normalized, &state.config.experiments));
+        }
+        warnings
+    } else {
+        Vec::new()
+    };
+
+    // A retry of an event we already have gets the same answer, unwritten
+    let Some(claim) = dedup::claim(&normalized, state.message_ids.as_ref(), &state.config.message_dedup).await? else {
+        let response = accepted_response(request, &state.config, &project_id, &warnings);
+        return Ok(response);
+    };
+
+    let event_id = state
+        .config
+        .status
+        .enabled
+        .then(|| uuid::Uuid::new_v4().to_string());
+    normalized.event_id = event_id.clone();
+
+    let enriched = enrich_event(normalized, request, &state.config);
+    let events = enrich(enriched, request, &state).await;
+    let outcome = if events.is_empty() {
+        "dropped"
+    } else {
+        if let Err(e) = process_events(events, state.clone()).await.and_then(sink::all_written) {
+            dedup::release_all(state.message_ids.as_ref(), claim.as_slice()).await;
+            return Err(e);
+        }
+        "queued"
+    };
+
+    let mut response = accepted_response(request, &state.config, &project_id, &warnings);
+    if let Some(event_id) = event_id {
+        match state.status_store.set(&event_id, outcome).await {
+            Ok(()) => response = status::with_location(response, request, &event_id),
+            Err(e) => tracing::warn!("Failed to record status of event {}: {}", event_id, e),
+        }
+    }
+    Ok(response)
+}
+
+/// Charges `cost` events to the client's and the project's buckets, when
+/// rate limiting is on, recording the decision for the response headers
+pub(crate) async fn check_rate_limit(
+    state: &AppState,
+    request: &Request,
+    project_id: &str,
+    cost: usize,
+) -> Option<Decision> {
+    let config = &state.config.rate_limit;
+    if !config.enabled {
+        return None;
+    }
+    let decision = state.rate_limiter.check(config, project_id, client_ip(request), cost).await;
+    if let Some(recorded) = request.extensions().get::<RecordedDecision>() {
+        recorded.record(decision);
+    }
+    Some(decision)
+}
+
 /// Handler for POST /view (compressed format)
+#[utoipa::path(
+    post,
+    path = "/v2/view",
+    tag = "events",
+    request_body = CompressedEvent,
+    responses(openapi::EventResponses),
+    security(("bearer" = []), ("bearer" = [], "apiKey" = []))
+)]
 pub async fn handle_page_view(
     body: &str,
     request: &Request,
@@ -106,29 +398,49 @@ pub async fn handle_page_view(
             return Ok(create_error_response(401, &format!("Unauthorized: {}", e)));
         }
     };
+    if let Some(rejection) = auth::check_api_key(request, &project_id, &state).await? {
+        return Ok(rejection);
+    }
 
-    // Parse compressed event
-    let compressed: CompressedEvent = match serde_json::from_str(body) {
+    // Parse compressed event, borrowing its strings from a copy of the body
+    let mut bytes = body.as_bytes().to_vec();
+    let compressed: CompressedEvent = match body::parse_json_in_place(&mut bytes, &state.config.json_limits) {
         Ok(event) => event,
         Err(e) => {
-            tracing::error!("Failed to parse JSON: {} | Body: {}", e, body);
-            return Ok(create_error_response(400, &format!("Invalid JSON in request body: {}", e)));
+            tracing::error!("Failed to parse JSON: {}", e);
+            return Ok(create_error_response(400, &e));
         }
     };
 
     // Validate compressed event
-    if let Err(e) = compressed.validate() {
-        return Ok(create_error_response(400, &e));
+    let mut problems = ValidationErrors::default();
+    problems.extend(compressed.validate());
+    let version = ApiVersion::of(request);
+    if version.requires_timestamps(&state.config) {
+        problems.extend(compressed.validate_timestamp());
     }
+    if version.requires_matching_kind(&state.config) {
+        problems.extend(compressed.validate_kind(EventKind::PageView));
+    }
+    let warnings = match validation::enforce(problems.into_result(), &project_id, &state.config.validation) {
+        Ok(warnings) => warnings,
+        Err(e) => return Ok(e.response()),
+    };
 
-    let normalized = compressed.normalize(project_id, user_id);
-    let enriched = enrich_event(normalized, request);
-    process_events(vec![enriched], state).await?;
-
-    Ok(create_text_response(202, "ACCEPTED"))
+    let mut normalized = compressed.normalize(project_id, user_id);
+    validation::tag(&mut normalized, warnings);
+    ingest(normalized, request, state).await
 }
 
 /// Handler for POST /event (compressed format)
+#[utoipa::path(
+    post,
+    path = "/v2/event",
+    tag = "events",
+    request_body = CompressedEvent,
+    responses(openapi::EventResponses),
+    security(("bearer" = []), ("bearer" = [], "apiKey" = []))
+)]
 pub async fn handle_track(
     body: &str,
     request: &Request,
@@ -141,24 +453,2139 @@ pub async fn handle_track(
             return Ok(create_error_response(401, &format!("Unauthorized: {}", e)));
         }
     };
+    if let Some(rejection) = auth::check_api_key(request, &project_id, &state).await? {
+        return Ok(rejection);
+    }
 
-    // Parse compressed event
-    let compressed: CompressedEvent = match serde_json::from_str(body) {
+    // Parse compressed event, borrowing its strings from a copy of the body
+    let mut bytes = body.as_bytes().to_vec();
+    let compressed: CompressedEvent = match body::parse_json_in_place(&mut bytes, &state.config.json_limits) {
         Ok(event) => event,
         Err(e) => {
-            tracing::error!("Failed to parse JSON: {} | Body: {}", e, body);
-            return Ok(create_error_response(400, &format!("Invalid JSON in request body: {}", e)));
+            tracing::error!("Failed to parse JSON: {}", e);
+            return Ok(create_error_response(400, &e));
         }
     };
 
     // Validate compressed event
-    if let Err(e) = compressed.validate() {
-        return Ok(create_error_response(400, &e));
+    let mut problems = ValidationErrors::default();
+    problems.extend(compressed.validate());
+    let version = ApiVersion::of(request);
+    if version.requires_timestamps(&state.config) {
+        problems.extend(compressed.validate_timestamp());
+    }
+    if version.requires_matching_kind(&state.config) {
+        problems.extend(compressed.validate_kind(EventKind::Track));
+    }
+    let warnings = match validation::enforce(problems.into_result(), &project_id, &state.config.validation) {
+        Ok(warnings) => warnings,
+        Err(e) => return Ok(e.response()),
+    };
+
+    let mut normalized = compressed.normalize(project_id, user_id);
+    validation::tag(&mut normalized, warnings);
+    ingest(normalized, request, state).await
+}
+
+/// A batch event that was rejected, by position in the batch
+#[derive(Debug, Clone, serde::Serialize)]
+struct BatchError {
+    index: usize,
+    /// Stable machine-readable code
+    reason: &'static str,
+    message: String,
+    /// 1-based line of an NDJSON body
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line: Option<usize>,
+    /// Each problem with the event, when it failed validation
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    fields: Vec<ValidationError>,
+}
+
+/// Per-event outcome of a batch submitted with a `Batch-Id`
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BatchResult {
+    index: usize,
+    /// `accepted`, `duplicate`, `dropped` or `rejected`
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    event_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<&'static str>,
+}
+
+/// Batch errors sharing a reason, collapsed into one entry
+#[derive(Debug, Clone, serde::Serialize)]
+struct GroupedBatchError {
+    reason: &'static str,
+    indices: Vec<usize>,
+}
+
+/// Groups errors by reason, in order of first occurrence
+fn group_batch_errors(errors: &[BatchError]) -> Vec<GroupedBatchError> {
+    let mut groups: Vec<GroupedBatchError> = Vec::new();
+    for error in errors {
+        match groups.iter_mut().find(|group| group.reason == error.reason) {
+            Some(group) => group.indices.push(error.index),
+            None => groups.push(GroupedBatchError {
+                reason: error.reason,
+                indices: vec![error.index],
+            }),
+        }
+    }
+    groups
+}
+
+/// Handler for POST /batch (array of compressed events, an SDK envelope,
+/// or NDJSON with one event per line)
+#[utoipa::path(
+    post,
+    path = "/v2/batch",
+    tag = "events",
+    request_body = BatchBody,
+    responses(openapi::BatchResponses),
+    security(("bearer" = []), ("bearer" = [], "apiKey" = []))
+)]
+pub async fn handle_batch(
+    body: &str,
+    request: &Request,
+    state: Arc<AppState>,
+) -> Result<Response<Body>, Error> {
+    // NDJSON events are indexed by line (0-based), and lines that don't
+    // parse are rejected up front without failing the rest
+    let mut errors = Vec::new();
+    let mut lines = None;
+    let parsed = if state.config.ndjson_batches && body::is_ndjson(request) {
+        body::parse_ndjson(body, &state.config.json_limits).map(|parsed| {
+            let mut events = Vec::with_capacity(parsed.len());
+            let mut numbers = Vec::with_capacity(parsed.len());
+            for (line, value) in parsed {
+                match value {
+                    Ok(value) => {
+                        events.push(value);
+                        numbers.push(line);
+                    }
+                    Err(message) => errors.push(BatchError {
+                        index: line - 1,
+                        reason: "malformed_line",
+                        message,
+                        line: Some(line),
+                        fields: Vec::new(),
+                    }),
+                }
+            }
+            lines = Some(numbers);
+            Batch {
+                events,
+                ..Default::default()
+            }
+        })
+    } else {
+        body::parse_json::<BatchBody>(body, &state.config.json_limits)
+            .and_then(|body| body.unwrap(state.config.batch_envelope))
+    };
+    let batch = match parsed {
+        Ok(batch) => batch,
+        Err(e) => {
+            tracing::error!("Failed to parse batch: {}", e);
+            return Ok(create_error_response(400, &format!("Malformed batch: {}", e)));
+        }
+    };
+
+    // The Authorization header wins; the envelope's writeKey is the fallback
+    let auth = match batch.write_key {
+        Some(ref write_key) if request.headers().get("authorization").is_none() => {
+            jwt::verify_write_key(write_key, &state).await
+        }
+        _ => extract_jwt_info(request),
+    };
+    let (project_id, user_id) = match auth {
+        Ok(info) => info,
+        Err(e) => {
+            return Ok(create_error_response(401, &format!("Unauthorized: {}", e)));
+        }
+    };
+    if let Some(rejection) = auth::check_api_key(request, &project_id, &state).await? {
+        return Ok(rejection);
+    }
+
+    if batch.events.is_empty() && errors.is_empty() {
+        return Ok(create_error_response(400, "Batch contains no events"));
+    }
+
+    if let Some(rejection) = metering::check(&state, &project_id).await {
+        return Ok(rejection);
+    }
+    let decision = check_rate_limit(&state, request, &project_id, batch.events.len()).await;
+    if let Some(decision) = decision.filter(|d| !d.allowed) {
+        return Ok(rate_limit::too_many_requests(&decision));
+    }
+
+    let batch_key = match idempotency::batch_key(request, &project_id) {
+        Ok(key) if state.config.batch_idempotency.enabled => key,
+        Ok(_) => None,
+        Err(e) => return Ok(create_error_response(400, &e)),
+    };
+    if let Some(ref key) = batch_key {
+        match state.batch_results.claim(key).await? {
+            Claim::New => {}
+            Claim::Pending => {
+                return Ok(create_error_response(409, "Batch is already being processed"));
+            }
+            Claim::Done(stored) => {
+                let response = idempotency::replay(&stored)?;
+                return Ok(response);
+            }
+        }
+    }
+
+    let auth = (project_id, user_id);
+    let outcome = ingest_batch(request, state.clone(), batch, lines, errors, auth, batch_key.as_deref()).await;
+    if let (Err(_), Some(key)) = (&outcome, batch_key) {
+        // Let the client's retry start over rather than replaying a failure
+        if let Err(e) = state.batch_results.release(&key).await {
+            tracing::warn!("Failed to release batch {}, its claim lapses instead: {}", key, e);
+        }
+    }
+    outcome
+}
+
+/// Releases the message ids claimed for a batch that won't be written, so
+/// its retry isn't taken for a duplicate, and hands back the error
+async fn release_claims(state: &AppState, claims: Vec<(usize, String)>, error: Error) -> Error {
+    let keys: Vec<String> = claims.into_iter().map(|(_, key)| key).collect();
+    dedup::release_all(state.message_ids.as_ref(), &keys).await;
+    error
+}
+
+/// Ingests a parsed batch whose `Batch-Id`, if any, is claimed. The caller
+/// releases the claim when this fails.
+async fn ingest_batch(
+    request: &Request,
+    state: Arc<AppState>,
+    batch: Batch,
+    lines: Option<Vec<usize>>,
+    mut errors: Vec<BatchError>,
+    (project_id, user_id): (String, Option<String>),
+    batch_key: Option<&str>,
+) -> Result<Response<Body>, Error> {
+    // Shift client timestamps by the gap between the client's send time and
+    // our receive time, correcting for a skewed client clock
+    let skew = batch
+        .sent_at
+        .map_or(0, |sent_at| chrono::Utc::now().timestamp_millis() - sent_at);
+
+    let mut events = Vec::with_capacity(batch.events.len());
+    // Batch index of each event handed over, and of each accepted event
+    let mut origins = Vec::with_capacity(batch.events.len());
+    let mut written = Vec::new();
+    let mut results = Vec::new();
+    let mut claims = Vec::new();
+    let mut shed = 0;
+    for (position, raw) in batch.events.iter().enumerate() {
+        let index = lines.as_ref().map_or(position, |lines: &Vec<usize>| lines[position] - 1);
+        let compressed = CompressedEvent::deserialize(raw)
+            .map_err(|e| {
+                let mut errors = ValidationErrors::default();
+                errors.add("$", "malformed", format!("Invalid event: {}", e));
+                ("malformed_event", errors)
+            })
+            .and_then(|compressed| {
+                let (reason, mut problems) = match compressed.validate() {
+                    Ok(()) => ("invalid_timestamp", ValidationErrors::default()),
+                    Err(problems) => ("invalid_event", problems),
+                };
+                if ApiVersion::of(request).requires_timestamps(&state.config) {
+                    problems.extend(compressed.validate_timestamp());
+                }
+                match validation::enforce(problems.into_result(), &project_id, &state.config.validation) {
+                    Ok(warnings) => Ok((compressed, warnings)),
+                    Err(problems) => Err((reason, problems)),
+                }
+            });
+        let (compressed, warnings) = match compressed {
+            Ok(validated) => validated,
+            Err((reason, problems)) => {
+                errors.push(BatchError {
+                    index,
+                    reason,
+                    message: problems.to_string(),
+                    line: None,
+                    fields: problems.errors().to_vec(),
+                });
+                continue;
+            }
+        };
+
+        // An event's own sentAt is applied by normalize
+        let own_sent_at = compressed.sent_at.is_some();
+        let mut normalized = compressed.normalize(project_id.clone(), user_id.clone());
+        validation::tag(&mut normalized, warnings);
+        if batch_key.is_some() {
+            normalized.event_id = Some(uuid::Uuid::new_v4().to_string());
+        }
+        if normalized.timestamp > 0 && !own_sent_at {
+            normalized.timestamp += skew;
+        }
+
+        // Claims taken for earlier events go back if this one can't be checked
+        let kept = match rules::apply(&mut normalized, &state).await {
+            Ok(kept) => kept,
+            Err(e) => return Err(release_claims(&state, claims, e).await),
+        };
+        if !kept {
+            results.push(BatchResult {
+                index,
+                status: "dropped",
+                event_id: None,
+                reason: None,
+            });
+            continue;
+        }
+
+        // Low-priority events make way for the rest while the stream throttles
+        if backpressure::sheds(&state, &normalized.event_type) {
+            shed += 1;
+            errors.push(BatchError {
+                index,
+                reason: "overloaded",
+                message: format!("{} events are shed while the service is overloaded", normalized.event_type),
+                line: None,
+                fields: Vec::new(),
+            });
+            continue;
+        }
+
+        if state.config.page_context_validation {
+            if let Err(message) = normalized.ensure_page_context() {
+                errors.push(BatchError {
+                    index,
+                    reason: "missing_page_context",
+                    message,
+                    line: None,
+                    fields: Vec::new(),
+                });
+                continue;
+            }
+        }
+
+        sanitize::apply(&mut normalized, &state.config.sanitize);
+        if let Err(e) = limits::check(&normalized, &state.config.payload_limits) {
+            errors.push(BatchError {
+                index,
+                reason: e.reason(),
+                message: e
+                    .violations(&state.config.payload_limits)
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join("; "),
+                line: None,
+                fields: Vec::new(),
+            });
+            continue;
+        }
+
+        if let Err(message) = consent::enforce(&mut normalized, &state.config.consent) {
+            errors.push(BatchError {
+                index,
+                reason: "missing_consent",
+                message,
+                line: None,
+                fields: Vec::new(),
+            });
+            continue;
+        }
+
+        if let Err(violations) = ecommerce::normalize(&mut normalized, &state.config.ecommerce) {
+            errors.push(BatchError {
+                index,
+                reason: "invalid_ecommerce_event",
+                message: violations.iter().map(ToString::to_string).collect::<Vec<_>>().join("; "),
+                line: None,
+                fields: Vec::new(),
+            });
+            continue;
+        }
+
+        if let Err(violations) = traits::normalize(&mut normalized, &state.config.traits) {
+            errors.push(BatchError {
+                index,
+                reason: "invalid_traits",
+                message: violations.iter().map(ToString::to_string).collect::<Vec<_>>().join("; "),
+                line: None,
+                fields: Vec::new(),
+            });
+            continue;
+        }
+
+        let checked = match event_names::check(&normalized, &state).await {
+            Ok(checked) => checked,
+            Err(e) => return Err(release_claims(&state, claims, e).await),
+        };
+        if let Err(violations) = checked {
+            errors.push(BatchError {
+                index,
+                reason: "event_name_not_allowed",
+                message: violations.iter().map(ToString::to_string).collect::<Vec<_>>().join("; "),
+                line: None,
+                fields: Vec::new(),
+            });
+            continue;
+        }
+
+        let checked = match schema::check(&mut normalized, &state).await {
+            Ok(checked) => checked,
+            Err(e) => return Err(release_claims(&state, claims, e).await),
+        };
+        if let Err(violations) = checked {
+            errors.push(BatchError {
+                index,
+                reason: "schema_violation",
+                message: violations.iter().map(ToString::to_string).collect::<Vec<_>>().join("; "),
+                line: None,
+                fields: Vec::new(),
+            });
+            continue;
+        }
+
+        let event_id = normalized.event_id.clone();
+        let claim = match dedup::claim(&normalized, state.message_ids.as_ref(), &state.config.message_dedup).await {
+            Ok(claim) => claim,
+            Err(e) => return Err(release_claims(&state, claims, e).await),
+        };
+        match claim {
+            Some(claim) => claims.extend(claim.map(|key| (index, key))),
+            None => {
+                // Already ingested; the client only needs to know it landed
+                written.push(index);
+                results.push(BatchResult {
+                    index,
+                    status: "duplicate",
+                    event_id: None,
+                    reason: None,
+                });
+                continue;
+            }
+        }
+
+        let enriched = enrich_event(normalized, request, &state.config);
+        let produced = enrich(enriched, request, &state).await;
+        results.push(BatchResult {
+            index,
+            status: if produced.is_empty() { "dropped" } else { "accepted" },
+            event_id,
+            reason: None,
+        });
+        if !produced.is_empty() {
+            written.push(index);
+            origins.extend(std::iter::repeat_n(index, produced.len()));
+            events.extend(produced);
+        }
+    }
+
+    if shed > 0 {
+        backpressure::record_shed(&state, &project_id, shed);
+        // Nothing but shed events: the whole batch is worth retrying later
+        if written.is_empty() && errors.len() == shed {
+            if let Some(key) = batch_key {
+                state.batch_results.release(key).await?;
+            }
+            return Ok(backpressure::overloaded(&state.config.backpressure));
+        }
+    }
+
+    let failed = match process_events(events, state.clone()).await {
+        Ok(failed) => failed,
+        Err(e) => return Err(release_claims(&state, claims, e).await),
+    };
+
+    // An event fails if any event it produced wasn't written; the rest of
+    // the batch stands, and the client retries just the failed indices
+    if !failed.is_empty() {
+        let mut unwritten: Vec<(usize, &str)> = Vec::new();
+        for (position, reason) in &failed {
+            if !unwritten.iter().any(|(index, _)| *index == origins[*position]) {
+                unwritten.push((origins[*position], reason.as_str()));
+            }
+        }
+        let is_unwritten = |index: &usize| unwritten.iter().any(|(failed, _)| failed == index);
+        if origins.iter().all(is_unwritten) {
+            // Nothing was written after all: the same as a failed write
+            return Err(release_claims(&state, claims, Box::new(PartialWrite { failed })).await);
+        }
+        let keys: Vec<String> = claims
+            .into_iter()
+            .filter(|(index, _)| is_unwritten(index))
+            .map(|(_, key)| key)
+            .collect();
+        dedup::release_all(state.message_ids.as_ref(), &keys).await;
+        written.retain(|index| !is_unwritten(index));
+        results.retain(|result| !is_unwritten(&result.index));
+        errors.extend(unwritten.iter().map(|&(index, reason)| BatchError {
+            index,
+            reason: "write_failed",
+            message: reason.to_string(),
+            line: None,
+            fields: Vec::new(),
+        }));
+    }
+
+    let mut rejected: HashMap<&str, usize> = HashMap::new();
+    for error in &errors {
+        *rejected.entry(error.reason).or_default() += 1;
+    }
+    for (reason, count) in rejected {
+        MetricSet::new(&state.config.metrics)
+            .dimension("ProjectId", project_id.as_str())
+            .dimension("Reason", reason)
+            .count("EventsRejected", count)
+            .emit();
+    }
+
+    if lines.is_some() {
+        for error in &mut errors {
+            error.line = Some(error.index + 1);
+        }
+        errors.sort_by_key(|error| error.index);
+    }
+
+    let Some(key) = batch_key else {
+        let mut response = batch_response(request, &state.config, &project_id, &written, &errors, None);
+        if shed > 0 {
+            backpressure::with_retry_after(&mut response, &state.config.backpressure);
+        }
+        return Ok(response);
+    };
+
+    results.extend(errors.iter().map(|error| BatchResult {
+        index: error.index,
+        status: "rejected",
+        event_id: None,
+        reason: Some(error.reason),
+    }));
+    results.sort_by_key(|result| result.index);
+
+    let mut response = batch_response(request, &state.config, &project_id, &written, &errors, Some(&results));
+    if shed > 0 {
+        backpressure::with_retry_after(&mut response, &state.config.backpressure);
+    }
+    if let Some(stored) = idempotency::capture(&response) {
+        state.batch_results.complete(key, &stored).await?;
+    }
+    Ok(response)
+}
+
+/// Batch success response; rejected events are listed alongside the
+/// accepted count (per index, or grouped by reason when configured), and
+/// a batch where every event failed is a 400 (a 422 in v2). A batch some of
+/// whose events couldn't be written is a 207 listing the accepted indices,
+/// so the client can resend the `write_failed` ones. Per-event `results`
+/// are included when given, which always makes the response JSON.
+fn batch_response(
+    request: &Request,
+    config: &Config,
+    project_id: &str,
+    written: &[usize],
+    errors: &[BatchError],
+    results: Option<&[BatchResult]>,
+) -> Response<Body> {
+    if errors.is_empty() && results.is_none() {
+        return accepted_response(request, config, project_id, &[]);
     }
 
-    let normalized = compressed.normalize(project_id, user_id);
-    let enriched = enrich_event(normalized, request);
-    process_events(vec![enriched], state).await?;
+    let rejected = written.is_empty() && !errors.is_empty();
+    let partial = !rejected && errors.iter().any(|error| error.reason == "write_failed");
+    let mut body = serde_json::json!({
+        "status": if rejected { "rejected" } else if partial { "partial" } else { "accepted" },
+        "accepted": written.len(),
+    });
+    if partial {
+        let mut indices = written.to_vec();
+        indices.sort_unstable();
+        body["acceptedIndices"] = serde_json::json!(indices);
+    }
+    if !errors.is_empty() {
+        body["errors"] = if config.group_batch_errors {
+            serde_json::json!(group_batch_errors(errors))
+        } else {
+            serde_json::json!(errors)
+        };
+    }
+    if let Some(results) = results {
+        body["results"] = serde_json::json!(results);
+    }
+
+    let version = ApiVersion::of(request);
+    let status = if rejected {
+        version.rejected_batch_status()
+    } else if partial {
+        207
+    } else {
+        version.success_status(config)
+    };
+    create_response(status, body)
+}
+
+/// An event type with its own single-event endpoint (see [`handle_single`])
+trait SingleEvent: DeserializeOwned + Send {
+    /// What parse errors call it
+    const NAME: &'static str;
+    /// Throttled by `autocapture`; a throttled event is answered like a
+    /// written one
+    const AUTOCAPTURED: bool = false;
+
+    fn problems(&self) -> Result<(), ValidationErrors>;
+    /// The client's `timestamp`
+    fn client_timestamp(&self) -> Option<i64>;
+    fn into_payload(self, project_id: String, user_id: Option<String>, config: &Config) -> IngestEventPayload;
+}
+
+impl SingleEvent for IdentifyEvent {
+    const NAME: &'static str = "identify";
+
+    fn problems(&self) -> Result<(), ValidationErrors> {
+        self.validate()
+    }
+
+    fn client_timestamp(&self) -> Option<i64> {
+        self.timestamp
+    }
+
+    fn into_payload(self, project_id: String, user_id: Option<String>, _: &Config) -> IngestEventPayload {
+        self.normalize(project_id, user_id)
+    }
+}
+
+impl SingleEvent for GroupEvent {
+    const NAME: &'static str = "group";
+
+    fn problems(&self) -> Result<(), ValidationErrors> {
+        self.validate()
+    }
+
+    fn client_timestamp(&self) -> Option<i64> {
+        self.timestamp
+    }
+
+    fn into_payload(self, project_id: String, user_id: Option<String>, _: &Config) -> IngestEventPayload {
+        self.normalize(project_id, user_id)
+    }
+}
+
+impl SingleEvent for WebVitalEvent {
+    const NAME: &'static str = "web vital";
+
+    fn problems(&self) -> Result<(), ValidationErrors> {
+        self.validate()
+    }
+
+    fn client_timestamp(&self) -> Option<i64> {
+        self.timestamp
+    }
+
+    fn into_payload(self, project_id: String, user_id: Option<String>, _: &Config) -> IngestEventPayload {
+        self.normalize(project_id, user_id)
+    }
+}
+
+impl SingleEvent for ErrorEvent {
+    const NAME: &'static str = "error event";
+
+    fn problems(&self) -> Result<(), ValidationErrors> {
+        self.validate()
+    }
+
+    fn client_timestamp(&self) -> Option<i64> {
+        self.timestamp
+    }
+
+    fn into_payload(self, project_id: String, user_id: Option<String>, config: &Config) -> IngestEventPayload {
+        self.normalize(project_id, user_id, &config.error_limits)
+    }
+}
+
+impl SingleEvent for HeartbeatEvent {
+    const NAME: &'static str = "heartbeat";
+
+    fn problems(&self) -> Result<(), ValidationErrors> {
+        self.validate()
+    }
+
+    fn client_timestamp(&self) -> Option<i64> {
+        self.timestamp
+    }
+
+    fn into_payload(self, project_id: String, user_id: Option<String>, _: &Config) -> IngestEventPayload {
+        self.normalize(project_id, user_id)
+    }
+}
+
+impl SingleEvent for ClickEvent {
+    const NAME: &'static str = "click";
+    const AUTOCAPTURED: bool = true;
+
+    fn problems(&self) -> Result<(), ValidationErrors> {
+        self.validate()
+    }
+
+    fn client_timestamp(&self) -> Option<i64> {
+        self.timestamp
+    }
+
+    fn into_payload(self, project_id: String, user_id: Option<String>, _: &Config) -> IngestEventPayload {
+        self.normalize(project_id, user_id)
+    }
+}
+
+impl SingleEvent for ScrollDepthEvent {
+    const NAME: &'static str = "scroll depth";
+    const AUTOCAPTURED: bool = true;
+
+    fn problems(&self) -> Result<(), ValidationErrors> {
+        self.validate()
+    }
+
+    fn client_timestamp(&self) -> Option<i64> {
+        self.timestamp
+    }
 
-    Ok(create_text_response(202, "ACCEPTED"))
+    fn into_payload(self, project_id: String, user_id: Option<String>, _: &Config) -> IngestEventPayload {
+        self.normalize(project_id, user_id)
+    }
+}
+
+impl SingleEvent for ExposureEvent {
+    const NAME: &'static str = "exposure";
+
+    fn problems(&self) -> Result<(), ValidationErrors> {
+        self.validate()
+    }
+
+    fn client_timestamp(&self) -> Option<i64> {
+        self.timestamp
+    }
+
+    fn into_payload(self, project_id: String, user_id: Option<String>, _: &Config) -> IngestEventPayload {
+        self.normalize(project_id, user_id)
+    }
+}
+
+impl SingleEvent for ScreenEvent {
+    const NAME: &'static str = "screen";
+
+    fn problems(&self) -> Result<(), ValidationErrors> {
+        self.validate()
+    }
+
+    fn client_timestamp(&self) -> Option<i64> {
+        self.timestamp
+    }
+
+    fn into_payload(self, project_id: String, user_id: Option<String>, _: &Config) -> IngestEventPayload {
+        self.normalize(project_id, user_id)
+    }
+}
+
+impl SingleEvent for AliasEvent {
+    const NAME: &'static str = "alias";
+
+    fn problems(&self) -> Result<(), ValidationErrors> {
+        self.validate()
+    }
+
+    fn client_timestamp(&self) -> Option<i64> {
+        self.timestamp
+    }
+
+    fn into_payload(self, project_id: String, _: Option<String>, _: &Config) -> IngestEventPayload {
+        self.normalize(project_id)
+    }
+}
+
+/// Shared body of the single-event handlers: authenticates the request,
+/// parses and validates the event, then ingests it
+async fn handle_single<T: SingleEvent>(
+    body: &str,
+    request: &Request,
+    state: Arc<AppState>,
+) -> Result<Response<Body>, Error> {
+    // Extract project_id and user_id from JWT
+    let (project_id, user_id) = match extract_jwt_info(request) {
+        Ok(info) => info,
+        Err(e) => {
+            return Ok(create_error_response(401, &format!("Unauthorized: {}", e)));
+        }
+    };
+    if let Some(rejection) = auth::check_api_key(request, &project_id, &state).await? {
+        return Ok(rejection);
+    }
+
+    let event: T = match body::parse_json(body, &state.config.json_limits) {
+        Ok(event) => event,
+        Err(e) => {
+            tracing::error!("Failed to parse {}: {}", T::NAME, e);
+            return Ok(create_error_response(400, &e));
+        }
+    };
+
+    let warnings = match validation::enforce(
+        with_timestamp(event.problems(), ("timestamp", event.client_timestamp()), request, &state.config),
+        &project_id,
+        &state.config.validation,
+    ) {
+        Ok(warnings) => warnings,
+        Err(e) => return Ok(e.response()),
+    };
+
+    let mut normalized = event.into_payload(project_id, user_id, &state.config);
+    if T::AUTOCAPTURED && !autocapture::admit(&normalized, &state) {
+        let warnings: Vec<_> = warnings.errors().iter().map(ToString::to_string).collect();
+        return Ok(accepted_response(request, &state.config, &normalized.project_id, &warnings));
+    }
+    validation::tag(&mut normalized, warnings);
+    ingest(normalized, request, state).await
+}
+
+/// Handler for POST /identify
+#[utoipa::path(
+    post,
+    path = "/v2/identify",
+    tag = "events",
+    request_body = IdentifyEvent,
+    responses(openapi::EventResponses),
+    security(("bearer" = []), ("bearer" = [], "apiKey" = []))
+)]
+pub async fn handle_identify(
+    body: &str,
+    request: &Request,
+    state: Arc<AppState>,
+) -> Result<Response<Body>, Error> {
+    handle_single::<IdentifyEvent>(body, request, state).await
+}
+
+/// Handler for POST /group
+#[utoipa::path(
+    post,
+    path = "/v2/group",
+    tag = "events",
+    request_body = GroupEvent,
+    responses(openapi::EventResponses),
+    security(("bearer" = []), ("bearer" = [], "apiKey" = []))
+)]
+pub async fn handle_group(
+    body: &str,
+    request: &Request,
+    state: Arc<AppState>,
+) -> Result<Response<Body>, Error> {
+    handle_single::<GroupEvent>(body, request, state).await
+}
+
+/// Handler for POST /vitals
+#[utoipa::path(
+    post,
+    path = "/v2/vitals",
+    tag = "events",
+    request_body = WebVitalEvent,
+    responses(openapi::EventResponses),
+    security(("bearer" = []), ("bearer" = [], "apiKey" = []))
+)]
+pub async fn handle_vitals(
+    body: &str,
+    request: &Request,
+    state: Arc<AppState>,
+) -> Result<Response<Body>, Error> {
+    handle_single::<WebVitalEvent>(body, request, state).await
+}
+
+/// Handler for POST /errors
+#[utoipa::path(
+    post,
+    path = "/v2/errors",
+    tag = "events",
+    request_body = ErrorEvent,
+    responses(openapi::EventResponses),
+    security(("bearer" = []), ("bearer" = [], "apiKey" = []))
+)]
+pub async fn handle_error(
+    body: &str,
+    request: &Request,
+    state: Arc<AppState>,
+) -> Result<Response<Body>, Error> {
+    handle_single::<ErrorEvent>(body, request, state).await
+}
+
+/// Handler for POST /heartbeat. Heartbeats are meant to be routed to
+/// their own stream (`STREAM_ROUTES`) and rolled up by
+/// `packages/engagement-rollup` rather than stored as raw events.
+#[utoipa::path(
+    post,
+    path = "/v2/heartbeat",
+    tag = "events",
+    request_body = HeartbeatEvent,
+    responses(openapi::EventResponses),
+    security(("bearer" = []), ("bearer" = [], "apiKey" = []))
+)]
+pub async fn handle_heartbeat(
+    body: &str,
+    request: &Request,
+    state: Arc<AppState>,
+) -> Result<Response<Body>, Error> {
+    handle_single::<HeartbeatEvent>(body, request, state).await
+}
+
+/// Handler for POST /click. Auto-captured clicks are throttled per
+/// session (see `autocapture`); a throttled click is answered like a
+/// written one.
+#[utoipa::path(
+    post,
+    path = "/v2/click",
+    tag = "events",
+    request_body = ClickEvent,
+    responses(openapi::EventResponses),
+    security(("bearer" = []), ("bearer" = [], "apiKey" = []))
+)]
+pub async fn handle_click(
+    body: &str,
+    request: &Request,
+    state: Arc<AppState>,
+) -> Result<Response<Body>, Error> {
+    handle_single::<ClickEvent>(body, request, state).await
+}
+
+/// Handler for POST /scroll. Only a session's deeper scroll depths on a
+/// page are written (see `autocapture`).
+#[utoipa::path(
+    post,
+    path = "/v2/scroll",
+    tag = "events",
+    request_body = ScrollDepthEvent,
+    responses(openapi::EventResponses),
+    security(("bearer" = []), ("bearer" = [], "apiKey" = []))
+)]
+pub async fn handle_scroll_depth(
+    body: &str,
+    request: &Request,
+    state: Arc<AppState>,
+) -> Result<Response<Body>, Error> {
+    handle_single::<ScrollDepthEvent>(body, request, state).await
+}
+
+/// Handler for POST /exposure
+#[utoipa::path(
+    post,
+    path = "/v2/exposure",
+    tag = "events",
+    request_body = ExposureEvent,
+    responses(openapi::EventResponses),
+    security(("bearer" = []), ("bearer" = [], "apiKey" = []))
+)]
+pub async fn handle_exposure(
+    body: &str,
+    request: &Request,
+    state: Arc<AppState>,
+) -> Result<Response<Body>, Error> {
+    handle_single::<ExposureEvent>(body, request, state).await
+}
+
+/// Handler for POST /screen
+#[utoipa::path(
+    post,
+    path = "/v2/screen",
+    tag = "events",
+    request_body = ScreenEvent,
+    responses(openapi::EventResponses),
+    security(("bearer" = []), ("bearer" = [], "apiKey" = []))
+)]
+pub async fn handle_screen(
+    body: &str,
+    request: &Request,
+    state: Arc<AppState>,
+) -> Result<Response<Body>, Error> {
+    handle_single::<ScreenEvent>(body, request, state).await
+}
+
+/// Handler for POST /alias
+#[utoipa::path(
+    post,
+    path = "/v2/alias",
+    tag = "events",
+    request_body = AliasEvent,
+    responses(openapi::EventResponses),
+    security(("bearer" = []), ("bearer" = [], "apiKey" = []))
+)]
+pub async fn handle_alias(
+    body: &str,
+    request: &Request,
+    state: Arc<AppState>,
+) -> Result<Response<Body>, Error> {
+    handle_single::<AliasEvent>(body, request, state).await
+}
+
+/// Whether the request carries a structured-mode CloudEvent
+pub fn is_cloud_event(request: &Request) -> bool {
+    request
+        .headers()
+        .get("content-type")
+        .and_then(|h| h.to_str().ok())
+        .is_some_and(|ct| ct.trim_start().starts_with("application/cloudevents+json"))
+}
+
+/// Handler for POST /cloudevents (CloudEvents 1.0 structured JSON)
+#[utoipa::path(
+    post,
+    path = "/v2/cloudevents",
+    tag = "events",
+    request_body(content = CloudEvent, content_type = "application/cloudevents+json"),
+    responses(openapi::EventResponses),
+    security(("bearer" = []), ("bearer" = [], "apiKey" = []))
+)]
+pub async fn handle_cloud_event(
+    body: &str,
+    request: &Request,
+    state: Arc<AppState>,
+) -> Result<Response<Body>, Error> {
+    // Extract project_id and user_id from JWT
+    let (project_id, user_id) = match extract_jwt_info(request) {
+        Ok(info) => info,
+        Err(e) => {
+            return Ok(create_error_response(401, &format!("Unauthorized: {}", e)));
+        }
+    };
+    if let Some(rejection) = auth::check_api_key(request, &project_id, &state).await? {
+        return Ok(rejection);
+    }
+
+    // Parse CloudEvents envelope
+    let cloud_event: CloudEvent = match body::parse_json(body, &state.config.json_limits) {
+        Ok(event) => event,
+        Err(e) => {
+            tracing::error!("Failed to parse CloudEvent: {}", e);
+            return Ok(create_error_response(400, &format!("Malformed CloudEvent: {}", e)));
+        }
+    };
+
+    // Validate envelope attributes
+    let warnings = match validation::enforce(
+        with_timestamp(cloud_event.validate(), ("time", cloud_event.time_millis()), request, &state.config),
+        &project_id,
+        &state.config.validation,
+    ) {
+        Ok(warnings) => warnings,
+        Err(e) => return Ok(e.response()),
+    };
+
+    let mut normalized = cloud_event.normalize(project_id, user_id);
+    validation::tag(&mut normalized, warnings);
+    ingest(normalized, request, state).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lambda_http::RequestExt;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_keepalive_requests_take_lightweight_path() {
+        let config = Config {
+            keepalive_fast_path: true,
+            ..Default::default()
+        };
+
+        let header_request = lambda_http::http::Request::builder()
+            .header("x-keepalive", "1")
+            .body(Body::Empty)
+            .unwrap();
+        let query_request = lambda_http::http::Request::builder()
+            .body(Body::Empty)
+            .unwrap()
+            .with_query_string_parameters(HashMap::from([(
+                "keepalive".to_string(),
+                "true".to_string(),
+            )]));
+
+        for request in [header_request, query_request] {
+            let response = accepted_response(&request, &config, "proj", &[]);
+            assert_eq!(response.status(), 204);
+            assert_eq!(response.headers().len(), 1);
+            assert!(matches!(response.body(), Body::Empty));
+        }
+    }
+
+    #[test]
+    fn test_regular_requests_get_full_response() {
+        let request = lambda_http::http::Request::builder()
+            .header("x-keepalive", "1")
+            .body(Body::Empty)
+            .unwrap();
+
+        // Fast path disabled
+        let response = accepted_response(&request, &Config::default(), "proj", &[]);
+        assert_eq!(response.status(), 202);
+
+        // Not a keepalive request
+        let config = Config {
+            keepalive_fast_path: true,
+            ..Default::default()
+        };
+        let request = lambda_http::http::Request::builder()
+            .body(Body::Empty)
+            .unwrap();
+        let response = accepted_response(&request, &config, "proj", &[]);
+        assert_eq!(response.status(), 202);
+        assert_eq!(response.headers()["content-type"], "text/plain");
+    }
+
+    /// Collects what a test subscriber writes
+    #[derive(Clone, Default)]
+    struct Captured(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_keepalive_requests_skip_enrichment_tracing() {
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_span_events(tracing_subscriber::fmt::format::FmtSpan::NEW)
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+        let state = crate::shared::test_state(Config {
+            keepalive_fast_path: true,
+            ..Default::default()
+        });
+        let event = IngestEventPayload {
+            project_id: "proj".to_string(),
+            event_type: "pageview".to_string(),
+            ..Default::default()
+        };
+
+        let beacon = lambda_http::http::Request::builder()
+            .header("x-keepalive", "1")
+            .body(Body::Empty)
+            .unwrap();
+        assert_eq!(enrich(event.clone(), &beacon, &state).await.len(), 1);
+        assert!(captured.0.lock().unwrap().is_empty());
+
+        let regular = lambda_http::http::Request::builder().body(Body::Empty).unwrap();
+        assert_eq!(enrich(event, &regular, &state).await.len(), 1);
+        assert!(String::from_utf8_lossy(&captured.0.lock().unwrap()).contains("enrich"));
+    }
+
+    #[test]
+    fn test_project_response_override() {
+        let mut config = Config::default();
+        config.response_overrides.insert(
+            "enterprise".to_string(),
+            ResponseOverride {
+                status: 200,
+                body: Some(serde_json::json!({"status": "queued"})),
+            },
+        );
+        let request = lambda_http::http::Request::builder()
+            .body(Body::Empty)
+            .unwrap();
+
+        let response = accepted_response(&request, &config, "enterprise", &[]);
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["content-type"], "application/json");
+        match response.body() {
+            Body::Text(body) => assert_eq!(body, r#"{"status":"queued"}"#),
+            other => panic!("unexpected body: {:?}", other),
+        }
+
+        let response = accepted_response(&request, &config, "self-serve", &[]);
+        assert_eq!(response.status(), 202);
+        assert_eq!(response.headers()["content-type"], "text/plain");
+    }
+
+    #[test]
+    fn test_no_content_override_has_no_body() {
+        let request = lambda_http::http::Request::builder()
+            .body(Body::Empty)
+            .unwrap();
+        let mut config = Config::default();
+        config.response_overrides.insert(
+            "enterprise".to_string(),
+            ResponseOverride {
+                status: 204,
+                body: None,
+            },
+        );
+
+        let response = accepted_response(&request, &config, "enterprise", &["deprecated".to_string()]);
+        assert_eq!(response.status(), 204);
+        assert!(matches!(response.body(), Body::Empty));
+    }
+
+    #[test]
+    fn test_no_content_override_drops_its_body() {
+        let request = lambda_http::http::Request::builder()
+            .body(Body::Empty)
+            .unwrap();
+        let mut config = Config::default();
+        config.response_overrides.insert(
+            "enterprise".to_string(),
+            ResponseOverride {
+                status: 204,
+                body: Some(serde_json::json!({"status": "queued"})),
+            },
+        );
+
+        let response = accepted_response(&request, &config, "enterprise", &[]);
+        assert_eq!(response.status(), 204);
+        assert!(matches!(response.body(), Body::Empty));
+        assert!(response.headers().get("content-type").is_none());
+    }
+
+    #[test]
+    fn test_response_overrides_parse_from_json() {
+        let overrides: HashMap<String, ResponseOverride> = serde_json::from_str(
+            r#"{"acme": {"status": 200, "body": {"status": "queued"}}, "globex": {"status": 201}}"#,
+        )
+        .unwrap();
+
+        assert_eq!(overrides["acme"].status, 200);
+        assert_eq!(overrides["globex"].status, 201);
+        assert!(overrides["globex"].body.is_none());
+    }
+
+    #[test]
+    fn test_warnings_returned_alongside_accepted_event() {
+        let request = lambda_http::http::Request::builder()
+            .body(Body::Empty)
+            .unwrap();
+        let warnings = vec!["event name \"signup_clicked\" is deprecated".to_string()];
+
+        let response = accepted_response(&request, &Config::default(), "proj", &warnings);
+        assert_eq!(response.status(), 202);
+        match response.body() {
+            Body::Text(body) => {
+                let body: serde_json::Value = serde_json::from_str(body).unwrap();
+                assert_eq!(body["status"], "accepted");
+                assert_eq!(body["warnings"][0], "event name \"signup_clicked\" is deprecated");
+            }
+            other => panic!("unexpected body: {:?}", other),
+        }
+
+        // Clean events keep the plain response
+        let response = accepted_response(&request, &Config::default(), "proj", &[]);
+        assert_eq!(response.headers()["content-type"], "text/plain");
+    }
+
+    fn token(project_id: &str) -> String {
+        use base64::Engine;
+        let claims = serde_json::json!({ "projectId": project_id }).to_string();
+        let encoded = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(claims);
+        format!("e30.{}.sig", encoded)
+    }
+
+    async fn batch(body: &str, config: Config) -> (u16, serde_json::Value) {
+        let request = lambda_http::http::Request::builder()
+            .method("POST")
+            .uri("/batch")
+            .body(Body::Empty)
+            .unwrap();
+        let state = Arc::new(crate::shared::test_state(config));
+        let response = handle_batch(body, &request, state).await.unwrap();
+        let body = match response.body() {
+            Body::Text(body) => serde_json::from_str(body).unwrap(),
+            other => panic!("unexpected body: {:?}", other),
+        };
+        (response.status().as_u16(), body)
+    }
+
+    #[tokio::test]
+    async fn test_wrapped_batch_authenticates_with_write_key() {
+        let config = Config {
+            batch_envelope: true,
+            ..Default::default()
+        };
+        let body = serde_json::json!({
+            "events": [{"en": ""}, {"nope": true}],
+            "sentAt": 1_700_000_000_000_i64,
+            "writeKey": token("proj"),
+        });
+
+        // Authenticated via writeKey, then each event rejected by index
+        let (status, body) = batch(&body.to_string(), config).await;
+        assert_eq!(status, 400);
+        assert_eq!(body["accepted"], 0);
+        assert_eq!(body["errors"][0]["index"], 0);
+        assert_eq!(body["errors"][1]["index"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_bare_array_batch_and_malformed_envelope() {
+        let config = Config {
+            batch_envelope: true,
+            ..Default::default()
+        };
+
+        // Bare arrays are parsed, but still need an Authorization header
+        let (status, _) = batch(r#"[{"en": "pageview"}]"#, config.clone()).await;
+        assert_eq!(status, 401);
+
+        let (status, body) = batch(r#"{"events": {"en": "pageview"}}"#, config).await;
+        assert_eq!(status, 400);
+        assert!(body["error"].as_str().unwrap().starts_with("Malformed batch"));
+
+        // Envelopes are malformed when support is off
+        let (status, _) = batch(r#"{"events": []}"#, Config::default()).await;
+        assert_eq!(status, 400);
+    }
+
+    #[tokio::test]
+    async fn test_batch_errors_grouped_by_reason() {
+        let unnamed = serde_json::json!({"en": "", "ts": 1, "o": "https://a.io/", "r": "", "sw": 1, "sh": 1});
+        let body = serde_json::json!({
+            "events": [unnamed, {"nope": 1}, unnamed, unnamed],
+            "writeKey": token("proj"),
+        })
+        .to_string();
+
+        let config = Config {
+            batch_envelope: true,
+            ..Default::default()
+        };
+        let (_, per_index) = batch(&body, config.clone()).await;
+        assert_eq!(per_index["errors"].as_array().unwrap().len(), 4);
+        assert_eq!(per_index["errors"][1]["reason"], "malformed_event");
+
+        let grouped = Config {
+            group_batch_errors: true,
+            ..config
+        };
+        let (status, body) = batch(&body, grouped).await;
+        assert_eq!(status, 400);
+        assert_eq!(
+            body["errors"],
+            serde_json::json!([
+                {"reason": "invalid_event", "indices": [0, 2, 3]},
+                {"reason": "malformed_event", "indices": [1]},
+            ])
+        );
+    }
+
+    /// State whose events for "proj" land in a recording sink
+    fn idempotent_state() -> (Arc<AppState>, Arc<crate::sink::RecordingSink>) {
+        let mut config = Config::default();
+        config.batch_idempotency.enabled = true;
+        config.s3_parquet.projects = vec!["proj".to_string()];
+        let sink = Arc::new(crate::sink::RecordingSink::default());
+        let mut state = crate::shared::test_state(config);
+        state.parquet_sink = Some(sink.clone());
+        (Arc::new(state), sink)
+    }
+
+    async fn submit(body: &serde_json::Value, batch_id: &str, state: &Arc<AppState>) -> Response<Body> {
+        let request = lambda_http::http::Request::builder()
+            .method("POST")
+            .uri("/batch")
+            .header("Authorization", format!("Bearer {}", token("proj")))
+            .header("Batch-Id", batch_id)
+            .body(Body::Empty)
+            .unwrap();
+        handle_batch(&body.to_string(), &request, state.clone()).await.unwrap()
+    }
+
+    fn json_body(response: &Response<Body>) -> serde_json::Value {
+        match response.body() {
+            Body::Text(body) => serde_json::from_str(body).unwrap(),
+            other => panic!("unexpected body: {:?}", other),
+        }
+    }
+
+    fn pageview() -> serde_json::Value {
+        serde_json::json!({"en": "pageview", "ts": 1, "o": "https://a.io/", "r": "", "sw": 1, "sh": 1})
+    }
+
+    #[tokio::test]
+    async fn test_ndjson_batch_reports_failures_by_line() {
+        let mut config = Config {
+            ndjson_batches: true,
+            ..Default::default()
+        };
+        config.s3_parquet.projects = vec!["proj".to_string()];
+        let sink = Arc::new(crate::sink::RecordingSink::default());
+        let mut state = crate::shared::test_state(config);
+        state.parquet_sink = Some(sink.clone());
+        let state = Arc::new(state);
+
+        let unnamed = serde_json::json!({"en": "", "ts": 1, "o": "https://a.io/", "r": "", "sw": 1, "sh": 1});
+        let body = format!("{}\n{{\"en\": oops\n\n{}\n{}\n", pageview(), unnamed, pageview());
+        let request = lambda_http::http::Request::builder()
+            .method("POST")
+            .uri("/batch")
+            .header("Content-Type", "application/x-ndjson")
+            .header("Authorization", format!("Bearer {}", token("proj")))
+            .body(Body::Empty)
+            .unwrap();
+        let response = handle_batch(&body, &request, state).await.unwrap();
+        assert_eq!(response.status(), 202);
+        let body = json_body(&response);
+        assert_eq!(body["accepted"], 2);
+        assert_eq!(body["errors"][0]["line"], 2);
+        assert_eq!(body["errors"][0]["reason"], "malformed_line");
+        assert_eq!(body["errors"][1]["line"], 4);
+        assert_eq!(body["errors"][1]["index"], 3);
+        assert_eq!(body["errors"][1]["reason"], "invalid_event");
+        assert_eq!(sink.events.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_batch_fans_out_pageviews_and_tracks() {
+        let mut config = Config::default();
+        config.s3_parquet.projects = vec!["proj".to_string()];
+        let sink = Arc::new(crate::sink::RecordingSink::default());
+        let mut state = crate::shared::test_state(config);
+        state.parquet_sink = Some(sink.clone());
+
+        let mut track = pageview();
+        track["en"] = "signup_clicked".into();
+        track["type"] = "track".into();
+        let request = lambda_http::http::Request::builder()
+            .method("POST")
+            .uri("/batch")
+            .header("Authorization", format!("Bearer {}", token("proj")))
+            .body(Body::Empty)
+            .unwrap();
+        let body = serde_json::json!([pageview(), track]).to_string();
+        let response = handle_batch(&body, &request, Arc::new(state)).await.unwrap();
+
+        // One request, both events through process_events
+        assert_eq!(response.status(), 202);
+        let names: Vec<_> = sink.events.lock().unwrap().iter().map(|event| event.event_type.clone()).collect();
+        assert_eq!(names, ["pageview", "signup_clicked"]);
+    }
+
+    #[tokio::test]
+    async fn test_batch_id_first_submission_and_full_replay() {
+        let (state, sink) = idempotent_state();
+        let body = serde_json::json!([pageview(), pageview()]);
+
+        let first = submit(&body, "flush-1", &state).await;
+        assert_eq!(first.status(), 202);
+        let results = json_body(&first)["results"].clone();
+        assert_eq!(results[0]["status"], "accepted");
+        assert_eq!(results[1]["index"], 1);
+        assert!(results[0]["eventId"].is_string());
+        assert_ne!(results[0]["eventId"], results[1]["eventId"]);
+        assert_eq!(sink.events.lock().unwrap().len(), 2);
+
+        let replay = submit(&body, "flush-1", &state).await;
+        assert_eq!(replay.status(), 202);
+        assert_eq!(replay.headers()["Idempotent-Replayed"], "true");
+        assert_eq!(json_body(&replay), json_body(&first));
+        assert_eq!(sink.events.lock().unwrap().len(), 2);
+
+        // A new id is a new batch
+        submit(&body, "flush-2", &state).await;
+        assert_eq!(sink.events.lock().unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_batch_id_replay_after_partial_failure() {
+        let (state, sink) = idempotent_state();
+        let mut unnamed = pageview();
+        unnamed["en"] = serde_json::json!("");
+        let body = serde_json::json!([{"nope": 1}, pageview(), unnamed]);
+
+        let first = submit(&body, "flush-1", &state).await;
+        assert_eq!(first.status(), 202);
+        let first_body = json_body(&first);
+        assert_eq!(first_body["accepted"], 1);
+        assert_eq!(first_body["results"][0]["status"], "rejected");
+        assert_eq!(first_body["results"][0]["reason"], "malformed_event");
+        assert_eq!(first_body["results"][1]["status"], "accepted");
+        assert_eq!(first_body["results"][2]["reason"], "invalid_event");
+
+        let replay = submit(&body, "flush-1", &state).await;
+        assert_eq!(replay.status(), 202);
+        assert_eq!(json_body(&replay), first_body);
+        assert_eq!(sink.events.lock().unwrap().len(), 1);
+    }
+
+    /// Schema store whose first lookups (of `event`, if set) fail
+    #[derive(Default)]
+    struct FlakySchemaStore {
+        failures: std::sync::atomic::AtomicUsize,
+        event: Option<&'static str>,
+    }
+
+    #[async_trait::async_trait]
+    impl crate::schema::SchemaStore for FlakySchemaStore {
+        async fn get(&self, _: &str, event_name: &str) -> Result<Option<serde_json::Value>, Error> {
+            use std::sync::atomic::Ordering;
+            if self.event.is_some_and(|event| event != event_name) {
+                return Ok(None);
+            }
+            match self.failures.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1)) {
+                Ok(_) => Err("schema table unavailable".into()),
+                Err(_) => Ok(None),
+            }
+        }
+    }
+
+    /// Idempotent state whose schema lookups fail as `store` says
+    fn flaky_schema_state(store: FlakySchemaStore) -> (Arc<AppState>, Arc<crate::sink::RecordingSink>) {
+        let (state, sink) = idempotent_state();
+        let mut config = (*state.config).clone();
+        config.schemas.enabled = true;
+        config.message_dedup.enabled = true;
+        let mut state = crate::shared::test_state(config);
+        state.parquet_sink = Some(sink.clone());
+        state.schemas = Arc::new(crate::schema::SchemaRegistry::new(Arc::new(store)));
+        (Arc::new(state), sink)
+    }
+
+    #[tokio::test]
+    async fn test_batch_id_is_released_when_the_batch_errors() {
+        let (state, sink) = flaky_schema_state(FlakySchemaStore {
+            failures: 1.into(),
+            ..Default::default()
+        });
+        let body = serde_json::json!([pageview()]);
+        let request = lambda_http::http::Request::builder()
+            .method("POST")
+            .uri("/batch")
+            .header("Authorization", format!("Bearer {}", token("proj")))
+            .header("Batch-Id", "flush-1")
+            .body(Body::Empty)
+            .unwrap();
+
+        assert!(handle_batch(&body.to_string(), &request, state.clone()).await.is_err());
+        assert!(sink.events.lock().unwrap().is_empty());
+
+        // The retry isn't told the batch is still being processed
+        let retry = submit(&body, "flush-1", &state).await;
+        assert_eq!(retry.status(), 202);
+        assert_eq!(json_body(&retry)["accepted"], 1);
+        assert_eq!(sink.events.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_message_ids_are_released_when_a_later_event_errors() {
+        let (state, sink) = flaky_schema_state(FlakySchemaStore {
+            failures: 1.into(),
+            event: Some("signup"),
+        });
+        let mut first = pageview();
+        first["messageId"] = serde_json::json!("m1");
+        let mut second = pageview();
+        second["en"] = serde_json::json!("signup");
+        second["messageId"] = serde_json::json!("m2");
+        let body = serde_json::json!([first, second]);
+        let request = lambda_http::http::Request::builder()
+            .method("POST")
+            .uri("/batch")
+            .header("Authorization", format!("Bearer {}", token("proj")))
+            .header("Batch-Id", "flush-1")
+            .body(Body::Empty)
+            .unwrap();
+
+        // m1 is claimed before the schema lookup of m2 fails
+        assert!(handle_batch(&body.to_string(), &request, state.clone()).await.is_err());
+        assert!(sink.events.lock().unwrap().is_empty());
+
+        // The retry writes both instead of taking m1 for a duplicate
+        let retry = submit(&body, "flush-1", &state).await;
+        assert_eq!(retry.status(), 202);
+        assert_eq!(json_body(&retry)["accepted"], 2);
+        let message_ids: Vec<_> = sink.events.lock().unwrap().iter().map(|e| e.message_id.clone()).collect();
+        assert_eq!(message_ids, [Some("m1".to_string()), Some("m2".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn test_retried_message_ids_written_once() {
+        let (state, sink) = idempotent_state();
+        let mut config = (*state.config).clone();
+        config.message_dedup.enabled = true;
+        config.batch_idempotency.enabled = false;
+        let mut state = crate::shared::test_state(config);
+        state.parquet_sink = Some(sink.clone());
+        let state = Arc::new(state);
+
+        let mut first = pageview();
+        first["messageId"] = serde_json::json!("m1");
+        let mut second = pageview();
+        second["messageId"] = serde_json::json!("m2");
+
+        let response = submit(&serde_json::json!([first, pageview()]), "flush-1", &state).await;
+        assert_eq!(response.status(), 202);
+        assert_eq!(sink.events.lock().unwrap().len(), 2);
+
+        // The retry repeats m1 within the batch and adds m2
+        let response = submit(&serde_json::json!([first, first, second]), "flush-2", &state).await;
+        assert_eq!(response.status(), 202);
+        let events = sink.events.lock().unwrap().clone();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[2].message_id.as_deref(), Some("m2"));
+    }
+
+    /// Writes every event but purchases, which fail like throttled records
+    #[derive(Default)]
+    struct PartialSink {
+        events: std::sync::Mutex<Vec<IngestEventPayload>>,
+    }
+
+    #[async_trait::async_trait]
+    impl crate::sink::EventSink for PartialSink {
+        async fn send(&self, events: Vec<IngestEventPayload>) -> Result<(), Error> {
+            let mut failed = Vec::new();
+            for (position, event) in events.into_iter().enumerate() {
+                if event.event_type == "purchase" {
+                    failed.push((position, "ProvisionedThroughputExceededException".to_string()));
+                } else {
+                    self.events.lock().unwrap().push(event);
+                }
+            }
+            sink::all_written(failed)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_batch_reports_events_the_sink_could_not_write() {
+        let sink = Arc::new(PartialSink::default());
+        let mut state = crate::shared::test_state(Config::default());
+        state.event_sink = Some(sink.clone());
+        let state = Arc::new(state);
+        let mut purchase = pageview();
+        purchase["en"] = serde_json::json!("purchase");
+
+        let response = submit(&serde_json::json!([pageview(), purchase.clone(), pageview()]), "flush-1", &state).await;
+        assert_eq!(response.status(), 207);
+        let body = json_body(&response);
+        assert_eq!(body["status"], "partial");
+        assert_eq!(body["acceptedIndices"], serde_json::json!([0, 2]));
+        assert_eq!(body["errors"][0]["index"], 1);
+        assert_eq!(body["errors"][0]["reason"], "write_failed");
+        assert_eq!(sink.events.lock().unwrap().len(), 2);
+
+        // Nothing written is still a failed request
+        let result = handle_batch(
+            &serde_json::json!([purchase]).to_string(),
+            &lambda_http::http::Request::builder()
+                .method("POST")
+                .uri("/batch")
+                .header("Authorization", format!("Bearer {}", token("proj")))
+                .body(Body::Empty)
+                .unwrap(),
+            state,
+        )
+        .await;
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_sdk_identity_stamped() {
+        let config = Config {
+            sdk_tagging: true,
+            ..Default::default()
+        };
+        let library = |name: &str, version: &str| IngestEventPayload {
+            context: Some(crate::models::EventContext {
+                library: Some(LibraryContext {
+                    name: Some(name.to_string()),
+                    version: Some(version.to_string()),
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        // Headers win over the context
+        let request = lambda_http::http::Request::builder()
+            .header("X-SDK-Name", "Analytics-JS")
+            .header("X-SDK-Version", "v2.1.0")
+            .body(Body::Empty)
+            .unwrap();
+        let event = enrich_event(library("other", "9.9.9"), &request, &config);
+        assert_eq!(event.sdk_name.as_deref(), Some("analytics-js"));
+        assert_eq!(event.sdk_version.as_deref(), Some("2.1.0"));
+
+        let request = lambda_http::http::Request::builder()
+            .body(Body::Empty)
+            .unwrap();
+        let event = enrich_event(library("analytics-ios", "1.4.2"), &request, &config);
+        assert_eq!(event.sdk_name.as_deref(), Some("analytics-ios"));
+        assert_eq!(event.sdk_version.as_deref(), Some("1.4.2"));
+
+        let event = enrich_event(IngestEventPayload::default(), &request, &config);
+        assert_eq!(event.sdk_name.as_deref(), Some("unknown"));
+        assert_eq!(event.sdk_version.as_deref(), Some("unknown"));
+
+        // Not stamped when disabled
+        let event = enrich_event(IngestEventPayload::default(), &request, &Config::default());
+        assert_eq!(event.sdk_name, None);
+    }
+
+    #[test]
+    fn test_nonpositive_timestamps_default_to_server_time() {
+        let request = lambda_http::http::Request::builder()
+            .body(Body::Empty)
+            .unwrap();
+        let at = |timestamp: i64| IngestEventPayload {
+            timestamp,
+            ..Default::default()
+        };
+
+        for broken in [0, -1_000] {
+            let event = enrich_event(at(broken), &request, &Config::default());
+            assert!(event.timestamp > 1_600_000_000_000, "{}", broken);
+            assert_eq!(event.timestamp_defaulted, Some(true));
+        }
+
+        let event = enrich_event(at(1_700_000_000_000), &request, &Config::default());
+        assert_eq!(event.timestamp, 1_700_000_000_000);
+        assert_eq!(event.timestamp_defaulted, None);
+    }
+
+    #[test]
+    fn test_implausible_timestamps_clamped_when_bounded() {
+        let request = lambda_http::http::Request::builder()
+            .body(Body::Empty)
+            .unwrap();
+        let config = Config {
+            timestamp_bounds: clock::TimestampBounds {
+                max_past_ms: Some(86_400_000),
+                max_future_ms: Some(60_000),
+            },
+            ..Default::default()
+        };
+        let at = |timestamp: i64| IngestEventPayload {
+            timestamp,
+            ..Default::default()
+        };
+
+        // 2017
+        let event = enrich_event(at(1_490_000_000_000), &request, &config);
+        let now = chrono::Utc::now().timestamp_millis();
+        assert!(event.timestamp >= now - 86_400_000 - 5_000);
+        assert_eq!(event.timestamp_clamped, Some(true));
+
+        let event = enrich_event(at(now - 1_000), &request, &config);
+        assert_eq!(event.timestamp, now - 1_000);
+        assert_eq!(event.timestamp_clamped, None);
+    }
+
+    #[tokio::test]
+    async fn test_nonpositive_timestamps_rejected_when_configured() {
+        let state = Arc::new(crate::shared::test_state(Config {
+            reject_nonpositive_timestamps: true,
+            ..Default::default()
+        }));
+        let request = lambda_http::http::Request::builder()
+            .method("POST")
+            .uri("/event")
+            .header("Authorization", format!("Bearer {}", token("proj")))
+            .body(Body::Empty)
+            .unwrap();
+
+        for ts in [0, -5] {
+            let body = serde_json::json!({"en": "signup", "ts": ts, "o": "https://a.io/", "r": "", "sw": 1, "sh": 1});
+            let response = handle_track(&body.to_string(), &request, state.clone()).await.unwrap();
+            assert_eq!(response.status(), 422, "{}", ts);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_nonpositive_identify_and_screen_timestamps_rejected_when_configured() {
+        let (state, sink) = idempotent_state();
+        let mut config = (*state.config).clone();
+        config.reject_nonpositive_timestamps = true;
+        let mut state = crate::shared::test_state(config);
+        state.parquet_sink = Some(sink.clone());
+        let state = Arc::new(state);
+        let request = |path: &str| {
+            lambda_http::http::Request::builder()
+                .method("POST")
+                .uri(path)
+                .header("Authorization", format!("Bearer {}", token("proj")))
+                .body(Body::Empty)
+                .unwrap()
+        };
+
+        for ts in [0, -5] {
+            let identify = serde_json::json!({"userId": "u1", "traits": {"plan": "pro"}, "timestamp": ts});
+            let response = handle_identify(&identify.to_string(), &request("/identify"), state.clone()).await.unwrap();
+            assert_eq!(response.status(), 422, "{}", ts);
+            let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+            assert_eq!(body["errors"][0]["code"], "invalid_timestamp");
+
+            let screen = serde_json::json!({"name": "Home", "anonymousId": "d1", "timestamp": ts});
+            let response = handle_screen(&screen.to_string(), &request("/screen"), state.clone()).await.unwrap();
+            assert_eq!(response.status(), 422, "{}", ts);
+        }
+        assert!(sink.events.lock().unwrap().is_empty());
+
+        // Valid and absent timestamps are accepted
+        let identify = r#"{"userId": "u1", "traits": {"plan": "pro"}, "timestamp": 1700000000000}"#;
+        let response = handle_identify(identify, &request("/identify"), state.clone()).await.unwrap();
+        assert_eq!(response.status(), 202);
+        let response = handle_screen(r#"{"name": "Home", "anonymousId": "d1"}"#, &request("/screen"), state)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 202);
+        let timestamps: Vec<_> = sink.events.lock().unwrap().iter().map(|e| e.timestamp).collect();
+        assert_eq!(timestamps[0], 1_700_000_000_000);
+        assert!(timestamps[1] > 0);
+    }
+
+    #[tokio::test]
+    async fn test_dropped_experiment_assignments_are_warned_about() {
+        let (state, sink) = idempotent_state();
+        let mut config = (*state.config).clone();
+        config.validation_warnings = true;
+        config.experiments.enabled = true;
+        let mut state = crate::shared::test_state(config);
+        state.parquet_sink = Some(sink.clone());
+        let request = lambda_http::http::Request::builder()
+            .method("POST")
+            .uri("/event")
+            .header("Authorization", format!("Bearer {}", token("proj")))
+            .body(Body::Empty)
+            .unwrap();
+
+        let mut body = pageview();
+        body["en"] = serde_json::json!("checkout");
+        body["ed"] = serde_json::json!({"experiments": {"nav": "b", "pricing": [1]}});
+        let response = handle_track(&body.to_string(), &request, Arc::new(state)).await.unwrap();
+        assert_eq!(response.status(), 202);
+        assert_eq!(json_body(&response)["warnings"], serde_json::json!(["experiment \"pricing\" has a malformed variant"]));
+
+        let event = sink.events.lock().unwrap()[0].clone();
+        assert_eq!(event.properties.unwrap()["experiment_nav"], "b");
+    }
+
+    #[tokio::test]
+    async fn test_lenient_projects_accept_invalid_events_tagged() {
+        let mut config = Config::default();
+        config.s3_parquet.projects = vec!["proj".to_string()];
+        config.validation.projects.insert("proj".to_string(), crate::validation::ValidationMode::Lenient);
+        let sink = Arc::new(crate::sink::RecordingSink::default());
+        let mut state = crate::shared::test_state(config);
+        state.parquet_sink = Some(sink.clone());
+        let request = lambda_http::http::Request::builder()
+            .method("POST")
+            .uri("/identify")
+            .header("Authorization", format!("Bearer {}", token("proj")))
+            .body(Body::Empty)
+            .unwrap();
+
+        let body = r#"{"traits": {"": 1, "plan": "pro"}}"#;
+        let response = handle_identify(body, &request, Arc::new(state)).await.unwrap();
+        assert_eq!(response.status(), 202);
+
+        let event = sink.events.lock().unwrap()[0].clone();
+        assert_eq!(event.traits.unwrap().len(), 1);
+        assert_eq!(
+            event.validation_warnings,
+            Some(vec!["userId or anonymousId is required".to_string(), "Trait names must not be empty".to_string()])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_identify_reaches_the_stream_with_traits() {
+        let (state, sink) = idempotent_state();
+        let request = lambda_http::http::Request::builder()
+            .method("POST")
+            .uri("/identify")
+            .header("Authorization", format!("Bearer {}", token("proj")))
+            .body(Body::Empty)
+            .unwrap();
+
+        let body = r#"{"userId": "u1", "traits": {"email": "jane@shop.io", "plan": "pro"}}"#;
+        let response = handle_identify(body, &request, state.clone()).await.unwrap();
+        assert_eq!(response.status(), 202);
+
+        let event = sink.events.lock().unwrap()[0].clone();
+        assert_eq!(event.event_type, "identify");
+        assert_eq!(event.user_id.as_deref(), Some("u1"));
+        assert_eq!(event.traits.unwrap()["email"], "jane@shop.io");
+        assert!(event.timestamp > 0);
+
+        let response = handle_identify(r#"{"traits": {"": 1}}"#, &request, state).await.unwrap();
+        assert_eq!(response.status(), 422);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        let fields: Vec<_> = body["errors"].as_array().unwrap().iter().map(|e| e["field"].clone()).collect();
+        assert_eq!(fields, ["userId", "traits"]);
+    }
+
+    #[tokio::test]
+    async fn test_group_reaches_the_stream_with_account() {
+        let (state, sink) = idempotent_state();
+        let request = lambda_http::http::Request::builder()
+            .method("POST")
+            .uri("/group")
+            .header("Authorization", format!("Bearer {}", token("proj")))
+            .body(Body::Empty)
+            .unwrap();
+
+        let body = r#"{"userId": "u1", "groupId": "acme", "traits": {"plan": "enterprise"}}"#;
+        let response = handle_group(body, &request, state.clone()).await.unwrap();
+        assert_eq!(response.status(), 202);
+
+        let event = sink.events.lock().unwrap()[0].clone();
+        assert_eq!(event.event_type, "group");
+        assert_eq!(event.group_id.as_deref(), Some("acme"));
+        assert_eq!(event.traits.unwrap()["plan"], "enterprise");
+
+        let response = handle_group(r#"{"userId": "u1"}"#, &request, state).await.unwrap();
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_alias_reaches_the_stream() {
+        let (state, sink) = idempotent_state();
+        let request = lambda_http::http::Request::builder()
+            .method("POST")
+            .uri("/alias")
+            .header("Authorization", format!("Bearer {}", token("proj")))
+            .body(Body::Empty)
+            .unwrap();
+
+        let body = r#"{"previousId": "anon-1", "userId": "u1"}"#;
+        let response = handle_alias(body, &request, state.clone()).await.unwrap();
+        assert_eq!(response.status(), 202);
+
+        let event = sink.events.lock().unwrap()[0].clone();
+        assert_eq!(event.event_type, "alias");
+        assert_eq!(event.previous_id.as_deref(), Some("anon-1"));
+        assert_eq!(event.user_id.as_deref(), Some("u1"));
+
+        let response = handle_alias(r#"{"userId": "u1"}"#, &request, state).await.unwrap();
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_errors_reach_the_stream() {
+        let (state, sink) = idempotent_state();
+        let request = lambda_http::http::Request::builder()
+            .method("POST")
+            .uri("/errors")
+            .header("Authorization", format!("Bearer {}", token("proj")))
+            .body(Body::Empty)
+            .unwrap();
+
+        let body = r#"{"message": "boom", "release": "1.2.0", "url": "https://a.io/checkout"}"#;
+        let response = handle_error(body, &request, state.clone()).await.unwrap();
+        assert_eq!(response.status(), 202);
+
+        let event = sink.events.lock().unwrap()[0].clone();
+        assert_eq!(event.event_type, "error");
+        assert_eq!(event.properties.unwrap()["release"], "1.2.0");
+
+        let response = handle_error(r#"{"stack": "at x"}"#, &request, state).await.unwrap();
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_heartbeats_reach_the_stream() {
+        let (state, sink) = idempotent_state();
+        let request = lambda_http::http::Request::builder()
+            .method("POST")
+            .uri("/heartbeat")
+            .header("Authorization", format!("Bearer {}", token("proj")))
+            .body(Body::Empty)
+            .unwrap();
+
+        let body = r#"{"sessionId": "s1", "url": "https://a.io/pricing"}"#;
+        let response = handle_heartbeat(body, &request, state.clone()).await.unwrap();
+        assert_eq!(response.status(), 202);
+
+        let event = sink.events.lock().unwrap()[0].clone();
+        assert_eq!(event.event_type, "heartbeat");
+        assert_eq!(event.properties.unwrap()["session_id"], "s1");
+
+        let response = handle_heartbeat(r#"{"url": "https://a.io/"}"#, &request, state).await.unwrap();
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_only_deeper_scroll_depths_are_written() {
+        let (state, sink) = idempotent_state();
+        let request = lambda_http::http::Request::builder()
+            .method("POST")
+            .uri("/scroll")
+            .header("Authorization", format!("Bearer {}", token("proj")))
+            .body(Body::Empty)
+            .unwrap();
+
+        for percent in [30, 60, 40] {
+            let body = format!(r#"{{"sessionId": "s1", "url": "https://a.io/blog", "percent": {}}}"#, percent);
+            let response = handle_scroll_depth(&body, &request, state.clone()).await.unwrap();
+            assert_eq!(response.status(), 202);
+        }
+
+        let depths: Vec<_> = sink
+            .events
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|event| event.properties.as_ref().unwrap()["depth"].clone())
+            .collect();
+        assert_eq!(depths, [25, 50]);
+    }
+
+    #[tokio::test]
+    async fn test_exposures_are_written_once_per_session() {
+        let mut config = Config::default();
+        config.message_dedup.enabled = true;
+        config.s3_parquet.projects = vec!["proj".to_string()];
+        let sink = Arc::new(crate::sink::RecordingSink::default());
+        let mut state = crate::shared::test_state(config);
+        state.parquet_sink = Some(sink.clone());
+        let state = Arc::new(state);
+        let request = lambda_http::http::Request::builder()
+            .method("POST")
+            .uri("/exposure")
+            .header("Authorization", format!("Bearer {}", token("proj")))
+            .body(Body::Empty)
+            .unwrap();
+
+        for session in ["s1", "s1", "s2"] {
+            let body = format!(r#"{{"experimentKey": "onboarding", "variant": "b", "anonymousId": "a1", "sessionId": "{}"}}"#, session);
+            let response = handle_exposure(&body, &request, state.clone()).await.unwrap();
+            assert_eq!(response.status(), 202);
+        }
+
+        let events = sink.events.lock().unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].event_type, "exposure");
+        assert_eq!(events[0].properties.as_ref().unwrap()["variant"], "b");
+    }
+
+    #[tokio::test]
+    async fn test_screens_reach_the_stream_with_app_context() {
+        let (state, sink) = idempotent_state();
+        let request = lambda_http::http::Request::builder()
+            .method("POST")
+            .uri("/screen")
+            .header("Authorization", format!("Bearer {}", token("proj")))
+            .body(Body::Empty)
+            .unwrap();
+
+        let body = r#"{"name": "Home", "anonymousId": "d1", "app": {"version": "1.0.3", "osName": "Android"}}"#;
+        let response = handle_screen(body, &request, state.clone()).await.unwrap();
+        assert_eq!(response.status(), 202);
+
+        let event = sink.events.lock().unwrap()[0].clone();
+        assert_eq!(event.event_type, "screen");
+        assert_eq!(event.context.unwrap().app.unwrap().version.as_deref(), Some("1.0.3"));
+
+        let response = handle_screen(r#"{"name": "Home"}"#, &request, state).await.unwrap();
+        assert_eq!(response.status(), 422);
+    }
+
+    #[tokio::test]
+    async fn test_web_vitals_reach_the_stream() {
+        let (state, sink) = idempotent_state();
+        let request = lambda_http::http::Request::builder()
+            .method("POST")
+            .uri("/vitals")
+            .header("Authorization", format!("Bearer {}", token("proj")))
+            .body(Body::Empty)
+            .unwrap();
+
+        let body = r#"{"name": "INP", "value": 640, "navigationType": "navigate", "url": "https://a.io/"}"#;
+        let response = handle_vitals(body, &request, state.clone()).await.unwrap();
+        assert_eq!(response.status(), 202);
+
+        let event = sink.events.lock().unwrap()[0].clone();
+        assert_eq!(event.event_type, "web_vital");
+        assert_eq!(event.properties.unwrap()["rating"], "poor");
+        assert!(event.timestamp > 0);
+
+        let response = handle_vitals(r#"{"name": "INP", "url": "https://a.io/"}"#, &request, state).await.unwrap();
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_batch_events_go_through_the_projects_rules() {
+        let mut config = Config::default();
+        config.rules.enabled = true;
+        config.batch_idempotency.enabled = true;
+        config.s3_parquet.projects = vec!["proj".to_string()];
+        let store = Arc::new(crate::rules::InMemoryRuleStore::default());
+        let rules = serde_json::json!([
+            {"event": "debug_ping", "op": "drop"},
+            {"event": "pageview", "op": "rename_event", "to": "page_viewed"},
+        ]);
+        store.insert("proj", serde_json::from_value(rules).unwrap());
+        let sink = Arc::new(crate::sink::RecordingSink::default());
+        let mut state = crate::shared::test_state(config);
+        state.rules = Arc::new(crate::rules::RuleCache::new(store));
+        state.parquet_sink = Some(sink.clone());
+
+        let ping = serde_json::json!({"en": "debug_ping", "ts": 1, "o": "https://a.io/", "r": "", "sw": 1, "sh": 1});
+        let body = serde_json::json!([ping, pageview()]);
+        let response = submit(&body, "flush-1", &Arc::new(state)).await;
+        assert_eq!(response.status(), 202);
+        let results = json_body(&response)["results"].clone();
+        assert_eq!(results[0]["status"], "dropped");
+        assert_eq!(results[1]["status"], "accepted");
+
+        let events = sink.events.lock().unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event_type, "page_viewed");
+    }
+
+    /// A status table that's unavailable
+    struct UnavailableStatusStore;
+
+    #[async_trait::async_trait]
+    impl crate::status::StatusStore for UnavailableStatusStore {
+        async fn set(&self, _event_id: &str, _status: &str) -> Result<(), Error> {
+            Err("ProvisionedThroughputExceededException".into())
+        }
+
+        async fn get(&self, _event_id: &str) -> Result<Option<String>, Error> {
+            Ok(None)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_status_is_recorded_best_effort() {
+        let (state, sink) = idempotent_state();
+        let mut config = (*state.config).clone();
+        config.status.enabled = true;
+        let request = lambda_http::http::Request::builder()
+            .method("POST")
+            .uri("/identify")
+            .header("Authorization", format!("Bearer {}", token("proj")))
+            .body(Body::Empty)
+            .unwrap();
+        let body = r#"{"userId": "u1", "traits": {"plan": "pro"}}"#;
+
+        let mut recorded = crate::shared::test_state(config.clone());
+        recorded.parquet_sink = Some(sink.clone());
+        let recorded = Arc::new(recorded);
+        let response = handle_identify(body, &request, recorded.clone()).await.unwrap();
+        assert_eq!(response.status(), 202);
+        let event_id = sink.events.lock().unwrap()[0].event_id.clone().unwrap();
+        assert_eq!(response.headers()["location"], format!("/status/{}", event_id));
+        assert_eq!(recorded.status_store.get(&event_id).await.unwrap().as_deref(), Some("queued"));
+
+        // The event is written either way; only the Location is left out
+        let mut unrecorded = crate::shared::test_state(config);
+        unrecorded.parquet_sink = Some(sink.clone());
+        unrecorded.status_store = Arc::new(UnavailableStatusStore);
+        let response = handle_identify(body, &request, Arc::new(unrecorded)).await.unwrap();
+        assert_eq!(response.status(), 202);
+        assert!(response.headers().get("location").is_none());
+        assert_eq!(sink.events.lock().unwrap().len(), 2);
+    }
 }
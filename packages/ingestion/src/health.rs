@@ -0,0 +1,207 @@
+//! Liveness, readiness and deep health checks.
+//!
+//! `GET /livez` answers 200 whenever the process can serve requests at all.
+//! `GET /readyz` answers 503 for [`UNHEALTHY_FOR`] after a failed stream
+//! write, as observed by `process_events`, and 200 otherwise. A sandbox
+//! that stops getting traffic would never see a write succeed again, so
+//! the failure expires rather than waiting for one. Both are body-less and
+//! cheap enough for tight probe intervals.
+//!
+//! `GET /health` is for canaries: it calls every configured sink (see
+//! [`EventSink::check`]) and reports each result with the build's version
+//! and git sha. It answers 503 when the event sink is unreachable, and 200
+//! with `"status": "degraded"` when only a secondary sink is.
+
+use lambda_http::{Body, Response};
+use serde_json::{json, Map, Value};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::shared::{create_empty_response, create_response, AppState};
+use crate::sink::kinesis::KinesisSink;
+use crate::sink::EventSink;
+
+/// Longest a single sink check may take before it counts as failed
+const CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// How long a failed stream write keeps `/readyz` unready
+pub const UNHEALTHY_FOR: Duration = Duration::from_secs(30);
+
+/// When the most recent stream write failed, unless one has succeeded since
+#[derive(Debug, Default)]
+pub struct SinkHealth(Mutex<Option<Instant>>);
+
+impl SinkHealth {
+    pub fn record(&self, healthy: bool) {
+        *self.0.lock().unwrap() = (!healthy).then(Instant::now);
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.0
+            .lock()
+            .unwrap()
+            .is_none_or(|failed_at| failed_at.elapsed() >= UNHEALTHY_FOR)
+    }
+}
+
+/// Answers a probe; readiness reflects the sink
+pub async fn handle_probe(probe: &str, state: Arc<AppState>) -> Response<Body> {
+    if probe == "health" {
+        return handle_health(state).await;
+    }
+    let ready = probe == "livez" || state.sink_health.is_healthy();
+    create_empty_response(if ready { 200 } else { 503 })
+}
+
+/// Checks every configured sink concurrently
+async fn handle_health(state: Arc<AppState>) -> Response<Body> {
+    let primary: Arc<dyn EventSink> = match state.event_sink {
+        Some(ref sink) => sink.clone(),
+        None => Arc::new(KinesisSink::new(state.clone())),
+    };
+    let sinks = [
+        ("events", Some(primary)),
+        ("parquet", state.parquet_sink.clone()),
+        ("deadLetter", state.dead_letter_sink.clone()),
+        ("fallback", state.fallback_sink.clone()),
+        ("eventBus", state.event_bus_sink.clone()),
+    ];
+
+    let mut checks = tokio::task::JoinSet::new();
+    for (name, sink) in sinks {
+        if let Some(sink) = sink {
+            checks.spawn(async move {
+                let started = Instant::now();
+                let result = match tokio::time::timeout(CHECK_TIMEOUT, sink.check()).await {
+                    Ok(result) => result.map_err(|e| e.to_string()),
+                    Err(_) => Err(format!("No answer within {:?}", CHECK_TIMEOUT)),
+                };
+                (name, result, started.elapsed())
+            });
+        }
+    }
+    let results = checks.join_all().await;
+
+    let (status_code, report) = health_report(&results);
+    create_response(status_code, report)
+}
+
+/// Status code and body for sink check results
+fn health_report(results: &[(&str, Result<(), String>, Duration)]) -> (u16, Value) {
+    let mut checks = Map::new();
+    for (name, result, elapsed) in results {
+        let mut check = json!({
+            "status": if result.is_ok() { "ok" } else { "error" },
+            "latencyMs": (elapsed.as_secs_f64() * 1000.0 * 10.0).round() / 10.0,
+        });
+        if let Err(e) = result {
+            tracing::warn!("Health check of the {} sink failed: {}", name, e);
+            check["error"] = json!(e);
+        }
+        checks.insert(name.to_string(), check);
+    }
+
+    let failed = |name: Option<&str>| {
+        results
+            .iter()
+            .any(|(n, result, _)| name.is_none_or(|name| *n == name) && result.is_err())
+    };
+    let (status_code, status) = if failed(Some("events")) {
+        (503, "unhealthy")
+    } else if failed(None) {
+        (200, "degraded")
+    } else {
+        (200, "ok")
+    };
+    let report = json!({
+        "status": status,
+        "version": env!("CARGO_PKG_VERSION"),
+        "gitSha": env!("GIT_SHA"),
+        "checks": checks,
+    });
+    (status_code, report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::function_handler;
+    use crate::shared::{test_state, Config};
+    use crate::sink::RecordingSink;
+
+    fn get(path: &str) -> lambda_http::Request {
+        lambda_http::http::Request::builder()
+            .method("GET")
+            .uri(path)
+            .body(Body::Empty)
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_livez_ignores_sink_while_readyz_reflects_it() {
+        let state = Arc::new(test_state(Config {
+            health_probes: true,
+            ..Default::default()
+        }));
+
+        let status = |response: Response<Body>| response.status().as_u16();
+        assert_eq!(status(function_handler(get("/livez"), state.clone()).await.unwrap()), 200);
+        assert_eq!(status(function_handler(get("/readyz"), state.clone()).await.unwrap()), 200);
+
+        state.sink_health.record(false);
+        assert_eq!(status(function_handler(get("/livez"), state.clone()).await.unwrap()), 200);
+        assert_eq!(status(function_handler(get("/readyz"), state.clone()).await.unwrap()), 503);
+
+        state.sink_health.record(true);
+        let response = function_handler(get("/prod/readyz"), state).await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert!(matches!(response.body(), Body::Empty));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_failed_writes_stop_counting_after_a_while() {
+        let health = SinkHealth::default();
+        health.record(false);
+        assert!(!health.is_healthy());
+
+        tokio::time::advance(UNHEALTHY_FOR - Duration::from_secs(1)).await;
+        assert!(!health.is_healthy());
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert!(health.is_healthy());
+
+        // Another failure starts the window over
+        health.record(false);
+        assert!(!health.is_healthy());
+    }
+
+    #[tokio::test]
+    async fn test_health_reports_sinks_and_build() {
+        let mut state = test_state(Config {
+            health_probes: true,
+            ..Default::default()
+        });
+        state.event_sink = Some(Arc::new(RecordingSink::default()));
+
+        let response = function_handler(get("/prod/health"), Arc::new(state)).await.unwrap();
+        assert_eq!(response.status(), 200);
+        let body: Value = serde_json::from_slice(response.body().as_ref()).unwrap();
+        assert_eq!(body["status"], "ok");
+        assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(body["checks"]["events"]["status"], "ok");
+        assert!(body["checks"].get("fallback").is_none());
+    }
+
+    #[test]
+    fn test_health_status_follows_the_event_sink() {
+        let ok = |name| (name, Ok(()), Duration::ZERO);
+        let failed = |name| (name, Err("AccessDenied".to_string()), Duration::ZERO);
+
+        let (status_code, report) = health_report(&[ok("events"), failed("fallback")]);
+        assert_eq!((status_code, &report["status"]), (200, &json!("degraded")));
+        assert_eq!(report["checks"]["fallback"]["error"], "AccessDenied");
+
+        let (status_code, report) = health_report(&[failed("events"), ok("fallback")]);
+        assert_eq!((status_code, &report["status"]), (503, &json!("unhealthy")));
+    }
+}
@@ -0,0 +1,325 @@
+//! Idempotent batch submission.
+//!
+//! A `/batch` request carrying a `Batch-Id` header claims that id (scoped to
+//! the project) in a [`BatchResultStore`] before ingesting anything, and
+//! stores its response once done. A replay of the same id gets the stored
+//! response back, per-index results and event ids included, without
+//! re-ingesting any event; a replay racing the original gets a 409.
+//!
+//! The claim starts as a short pending lease (`BATCH_IDEMPOTENCY_PENDING_SECS`,
+//! the function timeout by default) and only becomes the long-lived stored
+//! response once the batch is written. If anything fails, the claim is
+//! released so the client's retry is processed afresh; if the sandbox dies
+//! mid-batch (a timeout), the lease lapses and the next retry takes it over.
+
+use async_trait::async_trait;
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use lambda_http::{Body, Error, Request, Response};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::shared::{create_response, env_flag, env_or, env_var};
+
+/// Longest accepted `Batch-Id`
+const MAX_BATCH_ID_LEN: usize = 128;
+
+/// Configuration for idempotent batches
+#[derive(Debug, Clone)]
+pub struct IdempotencyConfig {
+    pub enabled: bool,
+    /// DynamoDB table backing the store; in-memory when unset
+    pub table_name: Option<String>,
+    /// How long stored results can be replayed
+    pub ttl_secs: i64,
+    /// How long a claim for a batch still being processed holds
+    pub pending_ttl_secs: i64,
+}
+
+impl Default for IdempotencyConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            table_name: None,
+            ttl_secs: 24 * 60 * 60,
+            pending_ttl_secs: 60,
+        }
+    }
+}
+
+impl IdempotencyConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            enabled: env_flag("BATCH_IDEMPOTENCY_ENABLED"),
+            table_name: env_var("BATCH_IDEMPOTENCY_TABLE"),
+            ttl_secs: env_or("BATCH_IDEMPOTENCY_TTL_SECS", defaults.ttl_secs),
+            pending_ttl_secs: env_or("BATCH_IDEMPOTENCY_PENDING_SECS", defaults.pending_ttl_secs),
+        }
+    }
+}
+
+/// Response of a completed batch, as returned to the client
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredResponse {
+    pub status: u16,
+    /// JSON body
+    pub body: String,
+}
+
+/// Outcome of claiming a batch id
+#[derive(Debug, PartialEq)]
+pub enum Claim {
+    /// First submission; the caller processes the batch
+    New,
+    /// Another submission of this id is still being processed, and its
+    /// lease hasn't lapsed
+    Pending,
+    Done(StoredResponse),
+}
+
+/// Per-batch result store
+#[async_trait]
+pub trait BatchResultStore: Send + Sync {
+    /// Takes a pending lease on the id, unless one is held or the batch is
+    /// done
+    async fn claim(&self, key: &str) -> Result<Claim, Error>;
+    /// Stores the response for replay, replacing the pending lease
+    async fn complete(&self, key: &str, response: &StoredResponse) -> Result<(), Error>;
+    /// Forgets a claim whose batch failed, so it can be retried
+    async fn release(&self, key: &str) -> Result<(), Error>;
+}
+
+/// A claimed batch id in the in-memory store
+#[derive(Debug)]
+enum Entry {
+    /// Being processed, until the lease lapses at this instant
+    Pending(Instant),
+    Done(StoredResponse),
+}
+
+/// Process-local store, used in tests and when no table is configured
+#[derive(Debug)]
+pub struct InMemoryBatchResultStore {
+    entries: Mutex<HashMap<String, Entry>>,
+    pending_ttl: Duration,
+}
+
+impl InMemoryBatchResultStore {
+    pub fn new(config: &IdempotencyConfig) -> Self {
+        Self {
+            entries: Mutex::default(),
+            pending_ttl: Duration::from_secs(config.pending_ttl_secs.max(0) as u64),
+        }
+    }
+}
+
+impl Default for InMemoryBatchResultStore {
+    fn default() -> Self {
+        Self::new(&IdempotencyConfig::default())
+    }
+}
+
+#[async_trait]
+impl BatchResultStore for InMemoryBatchResultStore {
+    async fn claim(&self, key: &str) -> Result<Claim, Error> {
+        let mut entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+        Ok(match entries.get(key) {
+            Some(Entry::Done(response)) => Claim::Done(response.clone()),
+            Some(Entry::Pending(until)) if *until > now => Claim::Pending,
+            _ => {
+                entries.insert(key.to_string(), Entry::Pending(now + self.pending_ttl));
+                Claim::New
+            }
+        })
+    }
+
+    async fn complete(&self, key: &str, response: &StoredResponse) -> Result<(), Error> {
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), Entry::Done(response.clone()));
+        Ok(())
+    }
+
+    async fn release(&self, key: &str) -> Result<(), Error> {
+        self.entries.lock().unwrap().remove(key);
+        Ok(())
+    }
+}
+
+/// DynamoDB-backed store
+/// Table schema: partition key `pk` (S), attributes `status` (N) and `body`
+/// (S) once complete, TTL attribute `expires_at` (N). A pending claim
+/// expires after the pending lease; a complete one after the full TTL.
+pub struct DynamoBatchResultStore {
+    client: DynamoClient,
+    table_name: String,
+    ttl_secs: i64,
+    pending_ttl_secs: i64,
+}
+
+impl DynamoBatchResultStore {
+    pub fn new(client: DynamoClient, table_name: String, config: &IdempotencyConfig) -> Self {
+        Self {
+            client,
+            table_name,
+            ttl_secs: config.ttl_secs,
+            pending_ttl_secs: config.pending_ttl_secs,
+        }
+    }
+
+    fn expires_at(&self, ttl_secs: i64) -> AttributeValue {
+        AttributeValue::N((chrono::Utc::now().timestamp() + ttl_secs).to_string())
+    }
+}
+
+#[async_trait]
+impl BatchResultStore for DynamoBatchResultStore {
+    async fn claim(&self, key: &str) -> Result<Claim, Error> {
+        let now = AttributeValue::N(chrono::Utc::now().timestamp().to_string());
+        let claimed = self
+            .client
+            .put_item()
+            .table_name(&self.table_name)
+            .item("pk", AttributeValue::S(key.to_string()))
+            .item("expires_at", self.expires_at(self.pending_ttl_secs))
+            // TTL deletion lags, so a lapsed lease is taken over here
+            .condition_expression(
+                "attribute_not_exists(pk) OR (attribute_not_exists(#status) AND expires_at <= :now)",
+            )
+            .expression_attribute_names("#status", "status")
+            .expression_attribute_values(":now", now)
+            .send()
+            .await;
+        match claimed {
+            Ok(_) => return Ok(Claim::New),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_conditional_check_failed_exception()) => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        let output = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("pk", AttributeValue::S(key.to_string()))
+            .consistent_read(true)
+            .send()
+            .await?;
+        let item = output.item();
+        let status = item
+            .and_then(|item| item.get("status"))
+            .and_then(|v| v.as_n().ok())
+            .and_then(|n| n.parse().ok());
+        let body = item.and_then(|item| item.get("body")).and_then(|v| v.as_s().ok());
+
+        Ok(match (status, body) {
+            (Some(status), Some(body)) => Claim::Done(StoredResponse {
+                status,
+                body: body.clone(),
+            }),
+            _ => Claim::Pending,
+        })
+    }
+
+    async fn complete(&self, key: &str, response: &StoredResponse) -> Result<(), Error> {
+        self.client
+            .put_item()
+            .table_name(&self.table_name)
+            .item("pk", AttributeValue::S(key.to_string()))
+            .item("status", AttributeValue::N(response.status.to_string()))
+            .item("body", AttributeValue::S(response.body.clone()))
+            .item("expires_at", self.expires_at(self.ttl_secs))
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    async fn release(&self, key: &str) -> Result<(), Error> {
+        self.client
+            .delete_item()
+            .table_name(&self.table_name)
+            .key("pk", AttributeValue::S(key.to_string()))
+            .send()
+            .await?;
+        Ok(())
+    }
+}
+
+/// Store key for a request's `Batch-Id`, scoped to the project.
+/// `Err` when the header is present but unusable.
+pub fn batch_key(request: &Request, project_id: &str) -> Result<Option<String>, String> {
+    let Some(value) = request.headers().get("batch-id") else {
+        return Ok(None);
+    };
+    let batch_id = value.to_str().map_err(|_| "Batch-Id must be ASCII".to_string())?.trim();
+    if batch_id.is_empty() || batch_id.len() > MAX_BATCH_ID_LEN {
+        return Err(format!("Batch-Id must be 1-{} characters", MAX_BATCH_ID_LEN));
+    }
+    Ok(Some(format!("{}#{}", project_id, batch_id)))
+}
+
+/// Captures a JSON batch response for storage
+pub fn capture(response: &Response<Body>) -> Option<StoredResponse> {
+    match response.body() {
+        Body::Text(body) => Some(StoredResponse {
+            status: response.status().as_u16(),
+            body: body.clone(),
+        }),
+        _ => None,
+    }
+}
+
+/// Rebuilds a stored response, marked as a replay
+pub fn replay(stored: &StoredResponse) -> Result<Response<Body>, Error> {
+    let mut response = create_response(stored.status, serde_json::from_str(&stored.body)?);
+    response
+        .headers_mut()
+        .insert("Idempotent-Replayed", "true".parse().expect("valid header value"));
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_in_memory_claim_lifecycle() {
+        let store = InMemoryBatchResultStore::default();
+        let stored = StoredResponse {
+            status: 202,
+            body: "{}".to_string(),
+        };
+
+        assert_eq!(store.claim("p#b").await.unwrap(), Claim::New);
+        assert_eq!(store.claim("p#b").await.unwrap(), Claim::Pending);
+
+        store.release("p#b").await.unwrap();
+        assert_eq!(store.claim("p#b").await.unwrap(), Claim::New);
+
+        store.complete("p#b", &stored).await.unwrap();
+        assert_eq!(store.claim("p#b").await.unwrap(), Claim::Done(stored));
+    }
+
+    #[tokio::test]
+    async fn test_lapsed_pending_claims_are_taken_over() {
+        let store = InMemoryBatchResultStore::new(&IdempotencyConfig {
+            pending_ttl_secs: 0,
+            ..Default::default()
+        });
+        let stored = StoredResponse {
+            status: 202,
+            body: "{}".to_string(),
+        };
+
+        // A sandbox that timed out never released its claim
+        assert_eq!(store.claim("p#b").await.unwrap(), Claim::New);
+        assert_eq!(store.claim("p#b").await.unwrap(), Claim::New);
+
+        // A completed batch outlives the pending lease
+        store.complete("p#b", &stored).await.unwrap();
+        assert_eq!(store.claim("p#b").await.unwrap(), Claim::Done(stored));
+    }
+}
@@ -0,0 +1,473 @@
+//! Verified JWT bearer tokens.
+//!
+//! By default the bearer token's claims are read without checking its
+//! signature, so anyone can name any project. With `JWT_AUTH_ENABLED`,
+//! every bearer token must instead be a JWT signed by `JWT_ISSUER` with a
+//! key from its JWKS (`JWT_JWKS_URL`, `{issuer}/.well-known/jwks.json` by
+//! default), unexpired, and for `JWT_AUDIENCE` when that's set. The project
+//! comes from its `JWT_PROJECT_CLAIM` claim (`projectId`) and the user from
+//! `JWT_USER_CLAIM` (`sub`), so an authenticated web app can hand its
+//! users' browsers a token from its own identity provider instead of
+//! exposing an API key; a verified token stands in for the API key (see
+//! [`auth::check_api_key`](crate::auth::check_api_key)).
+//!
+//! The key set is cached per sandbox for `JWT_JWKS_CACHE_TTL_SECS`, and
+//! fetched again early when a token names a key it doesn't hold (the
+//! issuer rotated), at most once a minute. The algorithm has to be one the
+//! key is for, so a token can't pick a weaker one.
+//!
+//! Tokens sent some other way, as a `/batch` envelope's `writeKey`, Segment
+//! basic auth or a pixel URL's `k`, have to verify just the same (see
+//! [`verify_write_key`]); without `JWT_AUTH_ENABLED` they're read as before.
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper_rustls::HttpsConnector;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use jsonwebtoken::jwk::{Jwk, JwkSet};
+use jsonwebtoken::{decode, decode_header, DecodingKey, Validation};
+use lambda_http::{Error, Request};
+use serde_json::{Map, Value};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::handlers::decode_jwt;
+use crate::shared::{env_flag, env_or, env_var, header_value, AppState};
+
+/// Shortest time between fetches for keys the cached set doesn't hold
+const MIN_REFETCH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Configuration for JWT verification
+#[derive(Debug, Clone)]
+pub struct JwtConfig {
+    pub enabled: bool,
+    /// Required `iss` claim
+    pub issuer: Option<String>,
+    /// Where the issuer's signing keys are published
+    pub jwks_url: Option<String>,
+    /// Required `aud` claim, when set
+    pub audience: Option<String>,
+    /// Claim holding the project id
+    pub project_claim: String,
+    /// Claim holding the user id
+    pub user_claim: String,
+    pub jwks_cache_ttl: Duration,
+}
+
+impl Default for JwtConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            issuer: None,
+            jwks_url: None,
+            audience: None,
+            project_claim: "projectId".to_string(),
+            user_claim: "sub".to_string(),
+            jwks_cache_ttl: Duration::from_secs(3600),
+        }
+    }
+}
+
+impl JwtConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let issuer = env_var("JWT_ISSUER");
+        Self {
+            enabled: env_flag("JWT_AUTH_ENABLED"),
+            jwks_url: env_var("JWT_JWKS_URL").or_else(|| {
+                issuer
+                    .as_ref()
+                    .map(|issuer| format!("{}/.well-known/jwks.json", issuer.trim_end_matches('/')))
+            }),
+            issuer,
+            audience: env_var("JWT_AUDIENCE"),
+            project_claim: env_var("JWT_PROJECT_CLAIM").unwrap_or(defaults.project_claim),
+            user_claim: env_var("JWT_USER_CLAIM").unwrap_or(defaults.user_claim),
+            jwks_cache_ttl: Duration::from_secs(env_or(
+                "JWT_JWKS_CACHE_TTL_SECS",
+                defaults.jwks_cache_ttl.as_secs(),
+            )),
+        }
+    }
+}
+
+/// Request extension holding the claims of a verified bearer token
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Verified {
+    pub project_id: String,
+    pub user_id: Option<String>,
+}
+
+/// Where the issuer's key set comes from
+#[async_trait]
+pub trait JwksSource: Send + Sync {
+    async fn fetch(&self) -> Result<JwkSet, Error>;
+}
+
+/// A fixed key set, used in tests and when verification is off
+#[derive(Debug)]
+pub struct StaticJwks(pub JwkSet);
+
+impl Default for StaticJwks {
+    fn default() -> Self {
+        Self(JwkSet { keys: Vec::new() })
+    }
+}
+
+#[async_trait]
+impl JwksSource for StaticJwks {
+    async fn fetch(&self) -> Result<JwkSet, Error> {
+        Ok(self.0.clone())
+    }
+}
+
+/// Fetches the key set over HTTPS
+pub struct HttpJwks {
+    http: Client<HttpsConnector<HttpConnector>, Full<Bytes>>,
+    url: String,
+}
+
+impl HttpJwks {
+    pub fn new(url: String) -> Result<Self, Error> {
+        let connector = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_native_roots()?
+            .https_only()
+            .enable_http1()
+            .build();
+        Ok(Self {
+            http: Client::builder(TokioExecutor::new()).build(connector),
+            url,
+        })
+    }
+}
+
+#[async_trait]
+impl JwksSource for HttpJwks {
+    async fn fetch(&self) -> Result<JwkSet, Error> {
+        let request = http::Request::get(&self.url).body(Full::new(Bytes::new()))?;
+        let response = self.http.request(request).await?;
+        let status = response.status();
+        let body = response.into_body().collect().await?.to_bytes();
+        if !status.is_success() {
+            return Err(format!("Fetching {} failed with {}", self.url, status).into());
+        }
+        Ok(serde_json::from_slice(&body)?)
+    }
+}
+
+/// The issuer's key set, fetched from a [`JwksSource`] when stale
+pub struct JwksCache {
+    source: Arc<dyn JwksSource>,
+    cached: Mutex<Option<(JwkSet, Instant)>>,
+}
+
+impl JwksCache {
+    pub fn new(source: Arc<dyn JwksSource>) -> Self {
+        Self {
+            source,
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// The key with id `kid` (or the only key, for tokens naming none),
+    /// from the cache while younger than `ttl`
+    pub async fn key(&self, kid: Option<&str>, ttl: Duration) -> Result<Option<Jwk>, Error> {
+        if let Some((keys, fetched_at)) = &*self.cached.lock().unwrap() {
+            let key = find(keys, kid);
+            let age = fetched_at.elapsed();
+            if age < ttl && (key.is_some() || age < MIN_REFETCH_INTERVAL) {
+                return Ok(key);
+            }
+        }
+
+        let keys = self.source.fetch().await?;
+        let key = find(&keys, kid);
+        *self.cached.lock().unwrap() = Some((keys, Instant::now()));
+        Ok(key)
+    }
+}
+
+fn find(keys: &JwkSet, kid: Option<&str>) -> Option<Jwk> {
+    match kid {
+        Some(kid) => keys.find(kid).cloned(),
+        None if keys.keys.len() == 1 => keys.keys.first().cloned(),
+        None => None,
+    }
+}
+
+/// Verifies the bearer token, marking the request [`Verified`] with its
+/// claims when it holds. Requests without a bearer token pass through.
+pub async fn verify(request: &mut Request, state: &AppState) -> Result<(), String> {
+    let config = &state.config.jwt;
+    if !config.enabled {
+        return Ok(());
+    }
+    let Some(token) = header_value(request, "authorization").and_then(|h| h.strip_prefix("Bearer ")) else {
+        return Ok(());
+    };
+    let verified = verify_token(token.trim(), config, &state.jwks).await?;
+    request.extensions_mut().insert(verified);
+    Ok(())
+}
+
+/// (project_id, user_id) of a token that didn't come as a bearer token,
+/// verified like one when JWT auth is on
+pub async fn verify_write_key(token: &str, state: &AppState) -> Result<(String, Option<String>), String> {
+    let config = &state.config.jwt;
+    if !config.enabled {
+        return decode_jwt(token);
+    }
+    let verified = verify_token(token.trim(), config, &state.jwks).await?;
+    Ok((verified.project_id, verified.user_id))
+}
+
+async fn verify_token(token: &str, config: &JwtConfig, jwks: &JwksCache) -> Result<Verified, String> {
+    let header = decode_header(token).map_err(|_| "Invalid JWT format")?;
+    let jwk = jwks
+        .key(header.kid.as_deref(), config.jwks_cache_ttl)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to fetch the JWKS: {}", e);
+            "Signing keys unavailable"
+        })?
+        .ok_or("Unknown signing key")?;
+    if jwk.common.key_algorithm.is_some_and(|alg| alg.to_string() != format!("{:?}", header.alg)) {
+        return Err("Token algorithm doesn't match its key".to_string());
+    }
+    let key = DecodingKey::from_jwk(&jwk).map_err(|_| "Unusable signing key")?;
+
+    let mut validation = Validation::new(header.alg);
+    if let Some(issuer) = &config.issuer {
+        validation.set_issuer(&[issuer]);
+    }
+    match &config.audience {
+        Some(audience) => validation.set_audience(&[audience]),
+        None => validation.validate_aud = false,
+    }
+    let claims = decode::<Map<String, Value>>(token, &key, &validation)
+        .map_err(|e| {
+            tracing::warn!("Rejecting bearer token: {}", e);
+            "Invalid token"
+        })?
+        .claims;
+
+    let claim = |name: &str| claims.get(name).and_then(Value::as_str).map(str::to_string);
+    Ok(Verified {
+        project_id: claim(&config.project_claim)
+            .ok_or_else(|| format!("Token has no {} claim", config.project_claim))?,
+        user_id: claim(&config.user_claim),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::check_api_key;
+    use crate::router::function_handler;
+    use crate::shared::{test_state, Config};
+    use base64::Engine;
+    use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+    use lambda_http::Body;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const SECRET: &[u8] = b"issuer-signing-secret";
+
+    fn jwks(kid: &str) -> JwkSet {
+        let k = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(SECRET);
+        serde_json::from_value(serde_json::json!({ "keys": [{ "kty": "oct", "kid": kid, "alg": "HS256", "k": k }] }))
+            .unwrap()
+    }
+
+    fn token(kid: &str, claims: Value) -> String {
+        let header = Header {
+            kid: Some(kid.to_string()),
+            ..Header::new(Algorithm::HS256)
+        };
+        encode(&header, &claims, &EncodingKey::from_secret(SECRET)).unwrap()
+    }
+
+    fn claims(project_id: &str) -> Value {
+        let exp = chrono::Utc::now().timestamp() + 600;
+        serde_json::json!({ "iss": "https://id.example.com", "aud": "analytics", "exp": exp, "projectId": project_id, "sub": "u-1" })
+    }
+
+    fn config() -> JwtConfig {
+        JwtConfig {
+            enabled: true,
+            issuer: Some("https://id.example.com".to_string()),
+            audience: Some("analytics".to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// Counts fetches of a fixed key set
+    struct CountingJwks(JwkSet, AtomicUsize);
+
+    #[async_trait]
+    impl JwksSource for CountingJwks {
+        async fn fetch(&self) -> Result<JwkSet, Error> {
+            self.1.fetch_add(1, Ordering::SeqCst);
+            Ok(self.0.clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_verifies_signature_issuer_audience_and_expiry() {
+        let jwks = JwksCache::new(Arc::new(StaticJwks(jwks("k1"))));
+        let verified = verify_token(&token("k1", claims("proj")), &config(), &jwks).await.unwrap();
+        assert_eq!(
+            verified,
+            Verified {
+                project_id: "proj".to_string(),
+                user_id: Some("u-1".to_string()),
+            }
+        );
+
+        let reject = |token: String| {
+            let jwks = &jwks;
+            async move { verify_token(&token, &config(), jwks).await.unwrap_err() }
+        };
+        let mut other_issuer = claims("proj");
+        other_issuer["iss"] = "https://evil.example.com".into();
+        assert_eq!(reject(token("k1", other_issuer)).await, "Invalid token");
+        let mut other_audience = claims("proj");
+        other_audience["aud"] = "billing".into();
+        assert_eq!(reject(token("k1", other_audience)).await, "Invalid token");
+        let mut expired = claims("proj");
+        expired["exp"] = (chrono::Utc::now().timestamp() - 3600).into();
+        assert_eq!(reject(token("k1", expired)).await, "Invalid token");
+        assert_eq!(reject(token("k2", claims("proj"))).await, "Unknown signing key");
+
+        // The unsigned tokens accepted without verification
+        let unsigned = format!(
+            "e30.{}.sig",
+            base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(claims("proj").to_string())
+        );
+        assert_eq!(reject(unsigned).await, "Invalid JWT format");
+    }
+
+    #[tokio::test]
+    async fn test_unknown_keys_refetch_the_set_at_most_once_a_minute() {
+        let source = Arc::new(CountingJwks(jwks("k1"), AtomicUsize::new(0)));
+        let jwks = JwksCache::new(source.clone());
+        let ttl = Duration::from_secs(3600);
+
+        assert!(jwks.key(Some("k1"), ttl).await.unwrap().is_some());
+        assert!(jwks.key(Some("k1"), ttl).await.unwrap().is_some());
+        assert!(jwks.key(Some("k2"), ttl).await.unwrap().is_none());
+        assert_eq!(source.1.load(Ordering::SeqCst), 1);
+
+        // A stale set is fetched again
+        assert!(jwks.key(Some("k1"), Duration::ZERO).await.unwrap().is_some());
+        assert_eq!(source.1.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_verified_tokens_replace_api_keys() {
+        let mut state = test_state(Config {
+            jwt: config(),
+            api_keys: crate::auth::ApiKeyConfig {
+                enabled: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        state.jwks = Arc::new(JwksCache::new(Arc::new(StaticJwks(jwks("k1")))));
+        let state = Arc::new(state);
+
+        let request = |token: String| {
+            lambda_http::http::Request::builder()
+                .header("Authorization", format!("Bearer {}", token))
+                .body(Body::Empty)
+                .unwrap()
+        };
+        let status = |mut request: Request| {
+            let state = state.clone();
+            async move {
+                verify(&mut request, &state).await?;
+                Ok::<_, String>(
+                    check_api_key(&request, "proj", &state)
+                        .await
+                        .unwrap()
+                        .map(|response| response.status().as_u16()),
+                )
+            }
+        };
+
+        assert_eq!(status(request(token("k1", claims("proj")))).await, Ok(None));
+        // A token for another project doesn't vouch for this one
+        assert_eq!(status(request(token("k1", claims("other")))).await, Ok(Some(401)));
+
+        let mut forged = claims("proj");
+        forged["exp"] = (chrono::Utc::now().timestamp() - 3600).into();
+        assert_eq!(status(request(token("k1", forged))).await, Err("Invalid token".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_write_keys_have_to_verify_too() {
+        use lambda_http::RequestExt;
+
+        let mut config = Config {
+            jwt: config(),
+            batch_envelope: true,
+            segment_compat: true,
+            pixel_tracking: true,
+            ..Default::default()
+        };
+        config.s3_parquet.projects = vec!["proj".to_string()];
+        let sink = Arc::new(crate::sink::RecordingSink::default());
+        let mut state = test_state(config);
+        state.jwks = Arc::new(JwksCache::new(Arc::new(StaticJwks(jwks("k1")))));
+        state.parquet_sink = Some(sink.clone());
+        let state = Arc::new(state);
+        let send = |request: Request| {
+            let state = state.clone();
+            async move { function_handler(request, state).await.unwrap().status().as_u16() }
+        };
+
+        // Unsigned, so anyone could have written it
+        let forged = format!(
+            "e30.{}.sig",
+            base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(claims("proj").to_string())
+        );
+        let batch = |write_key: &str| {
+            let pageview = serde_json::json!({"en": "pageview", "ts": 1, "o": "https://a.io/", "r": "", "sw": 1, "sh": 1});
+            let body = serde_json::json!({ "events": [pageview], "writeKey": write_key });
+            lambda_http::http::Request::builder()
+                .method("POST")
+                .uri("/batch")
+                .body(Body::Text(body.to_string()))
+                .unwrap()
+        };
+        assert_eq!(send(batch(&forged)).await, 401);
+
+        let basic = base64::engine::general_purpose::STANDARD.encode(format!("{}:", forged));
+        let segment = lambda_http::http::Request::builder()
+            .method("POST")
+            .uri("/v1/track")
+            .header("Authorization", format!("Basic {}", basic))
+            .body(Body::Text(serde_json::json!({ "event": "signup", "userId": "u-1" }).to_string()))
+            .unwrap();
+        assert_eq!(send(segment).await, 401);
+
+        let params = [("k", forged.as_str()), ("en", "email_open")]
+            .iter()
+            .map(|(name, value)| (name.to_string(), vec![value.to_string()]))
+            .collect::<std::collections::HashMap<_, _>>();
+        let pixel = lambda_http::http::Request::builder()
+            .method("GET")
+            .uri("/pixel.gif")
+            .body(Body::Empty)
+            .unwrap()
+            .with_query_string_parameters(params);
+        assert_eq!(send(pixel).await, 401);
+        assert!(sink.events.lock().unwrap().is_empty());
+
+        // One the issuer signed still works
+        assert_eq!(send(batch(&token("k1", claims("proj")))).await, 202);
+        assert_eq!(sink.events.lock().unwrap().len(), 1);
+    }
+}
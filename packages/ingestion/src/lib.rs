@@ -1,4 +1,60 @@
 // Re-export modules for testing
+pub mod admin;
+pub mod aggregation;
+pub mod auth;
+pub mod autocapture;
+pub mod avro;
+pub mod aws_json;
+pub mod backpressure;
+pub mod beacon;
+pub mod body;
+pub mod buffer;
+pub mod canary;
+pub mod clock;
+pub mod config_source;
+pub mod consent;
+pub mod dedup;
+pub mod deletion;
+pub mod ecommerce;
+pub mod encryption;
+pub mod event_names;
+pub mod metering;
+pub mod metrics;
+pub mod middleware;
 pub mod models;
+pub mod negotiation;
+pub mod offline;
+pub mod openapi;
 pub mod handlers;
+pub mod health;
+pub mod idempotency;
+pub mod jwt;
+pub mod limits;
+pub mod origin;
+pub mod partitioning;
+pub mod payload_quarantine;
+pub mod pixel;
+pub mod projection;
+pub mod proto;
+pub mod put_records;
+pub mod rate_limit;
+pub mod request_id;
+pub mod residency;
+pub mod retry;
+pub mod router;
+pub mod routes;
+pub mod routing;
+pub mod rules;
+pub mod sanitize;
+pub mod schema;
+pub mod segment;
 pub mod shared;
+pub mod shutdown;
+pub mod signing;
+pub mod sink;
+pub mod status;
+pub mod telemetry;
+pub mod traits;
+pub mod validation;
+pub mod version;
+pub mod enrichment;
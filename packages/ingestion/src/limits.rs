@@ -0,0 +1,290 @@
+//! Per-event size limits.
+//!
+//! [`JsonLimits`](crate::body::JsonLimits) bounds the request body; these
+//! bound each event in it, so one event can't carry thousands of
+//! properties, megabyte strings or deeply nested blobs into the stream.
+//! With `PAYLOAD_LIMITS_ENABLED`, `properties`, `traits` and
+//! `traits_set_once` are checked for their key count
+//! (`PAYLOAD_MAX_PROPERTIES`), the length of each string value
+//! (`PAYLOAD_MAX_VALUE_BYTES`) and their nesting depth
+//! (`PAYLOAD_MAX_PROPERTY_DEPTH`), failing with a 422 listing every
+//! violation. An event whose serialized form exceeds
+//! `PAYLOAD_MAX_EVENT_BYTES` fails with a 413, well before it would break
+//! Kinesis' 1 MB record limit.
+//!
+//! Frontend errors (POST /errors) are cut down instead, by [`ErrorLimits`]:
+//! a runaway stack or breadcrumb trail shouldn't cost the error itself.
+
+use lambda_http::{Body, Response};
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::models::IngestEventPayload;
+use crate::schema::Violation;
+use crate::shared::{create_response, env_flag, env_or};
+
+/// Configuration for per-event limits
+#[derive(Debug, Clone)]
+pub struct PayloadLimits {
+    pub enabled: bool,
+    /// Most keys in each of `properties`, `traits` and `traits_set_once`
+    pub max_properties: usize,
+    /// Longest string value, in bytes, at any depth
+    pub max_value_bytes: usize,
+    /// Deepest nesting of objects and arrays below a property
+    pub max_depth: usize,
+    /// Largest serialized event, in bytes
+    pub max_event_bytes: usize,
+}
+
+impl Default for PayloadLimits {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_properties: 256,
+            max_value_bytes: 8 * 1024,
+            max_depth: 5,
+            // Leaves room for enrichment under the 1 MiB record limit
+            max_event_bytes: 900 * 1024,
+        }
+    }
+}
+
+impl PayloadLimits {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            enabled: env_flag("PAYLOAD_LIMITS_ENABLED"),
+            max_properties: env_or("PAYLOAD_MAX_PROPERTIES", defaults.max_properties),
+            max_value_bytes: env_or("PAYLOAD_MAX_VALUE_BYTES", defaults.max_value_bytes),
+            max_depth: env_or("PAYLOAD_MAX_PROPERTY_DEPTH", defaults.max_depth),
+            max_event_bytes: env_or("PAYLOAD_MAX_EVENT_BYTES", defaults.max_event_bytes),
+        }
+    }
+}
+
+/// Size limits of frontend error events, applied by truncation
+#[derive(Debug, Clone)]
+pub struct ErrorLimits {
+    /// Longest message, in bytes, of the error and of each breadcrumb
+    pub max_message_bytes: usize,
+    /// Longest stack trace, in bytes
+    pub max_stack_bytes: usize,
+    /// Most breadcrumbs kept, the latest ones
+    pub max_breadcrumbs: usize,
+}
+
+impl Default for ErrorLimits {
+    fn default() -> Self {
+        Self {
+            max_message_bytes: 1024,
+            // Within the default PAYLOAD_MAX_VALUE_BYTES
+            max_stack_bytes: 8 * 1024,
+            max_breadcrumbs: 50,
+        }
+    }
+}
+
+impl ErrorLimits {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            max_message_bytes: env_or("ERROR_MAX_MESSAGE_BYTES", defaults.max_message_bytes),
+            max_stack_bytes: env_or("ERROR_MAX_STACK_BYTES", defaults.max_stack_bytes),
+            max_breadcrumbs: env_or("ERROR_MAX_BREADCRUMBS", defaults.max_breadcrumbs),
+        }
+    }
+}
+
+/// Why an event was refused
+#[derive(Debug, Clone, PartialEq)]
+pub enum LimitError {
+    /// The serialized event, in bytes
+    TooLarge(usize),
+    /// Properties or traits over their limits
+    Exceeded(Vec<Violation>),
+}
+
+impl LimitError {
+    /// Stable machine-readable code, for batch results
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Self::TooLarge(_) => "payload_too_large",
+            Self::Exceeded(_) => "limit_exceeded",
+        }
+    }
+
+    /// Every violation, including the overall size
+    pub fn violations(&self, limits: &PayloadLimits) -> Vec<Violation> {
+        match self {
+            Self::TooLarge(size) => vec![Violation {
+                path: "$".to_string(),
+                message: format!("event is {} bytes, over the limit of {}", size, limits.max_event_bytes),
+            }],
+            Self::Exceeded(violations) => violations.clone(),
+        }
+    }
+
+    /// 413 or 422 with the violations
+    pub fn response(&self, limits: &PayloadLimits) -> Response<Body> {
+        let (status, error) = match self {
+            Self::TooLarge(_) => (413, "Event exceeds maximum size"),
+            Self::Exceeded(_) => (422, "Event exceeds payload limits"),
+        };
+        create_response(
+            status,
+            serde_json::json!({ "error": error, "violations": self.violations(limits) }),
+        )
+    }
+}
+
+/// Checks a normalized event against the limits
+#[tracing::instrument(name = "validate.limits", skip_all)]
+pub fn check(payload: &IngestEventPayload, limits: &PayloadLimits) -> Result<(), LimitError> {
+    if !limits.enabled {
+        return Ok(());
+    }
+
+    let mut violations = Vec::new();
+    for (name, fields) in [
+        ("properties", &payload.properties),
+        ("traits", &payload.traits),
+        ("traits_set_once", &payload.traits_set_once),
+    ] {
+        if let Some(fields) = fields {
+            check_fields(name, fields, limits, &mut violations);
+        }
+    }
+    if !violations.is_empty() {
+        return Err(LimitError::Exceeded(violations));
+    }
+
+    let mut size = ByteCount(0);
+    let _ = serde_json::to_writer(&mut size, payload);
+    let ByteCount(size) = size;
+    if size > limits.max_event_bytes {
+        return Err(LimitError::TooLarge(size));
+    }
+    Ok(())
+}
+
+/// Counts what's written to it, to size an event without buffering it
+struct ByteCount(usize);
+
+impl std::io::Write for ByteCount {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn check_fields(
+    name: &str,
+    fields: &HashMap<String, Value>,
+    limits: &PayloadLimits,
+    violations: &mut Vec<Violation>,
+) {
+    if fields.len() > limits.max_properties {
+        violations.push(Violation {
+            path: name.to_string(),
+            message: format!("has {} keys, over the limit of {}", fields.len(), limits.max_properties),
+        });
+    }
+    let mut keys: Vec<_> = fields.keys().collect();
+    keys.sort();
+    for key in keys {
+        check_value(&format!("{}.{}", name, key), &fields[key], 0, limits, violations);
+    }
+}
+
+fn check_value(path: &str, value: &Value, depth: usize, limits: &PayloadLimits, violations: &mut Vec<Violation>) {
+    let nested = match value {
+        Value::String(s) if s.len() > limits.max_value_bytes => {
+            violations.push(Violation {
+                path: path.to_string(),
+                message: format!("is {} bytes, over the limit of {}", s.len(), limits.max_value_bytes),
+            });
+            return;
+        }
+        Value::Array(_) | Value::Object(_) if depth >= limits.max_depth => {
+            violations.push(Violation {
+                path: path.to_string(),
+                message: format!("nests deeper than {} levels", limits.max_depth),
+            });
+            return;
+        }
+        Value::Array(items) => items
+            .iter()
+            .enumerate()
+            .map(|(index, item)| (format!("{}[{}]", path, index), item))
+            .collect(),
+        Value::Object(fields) => fields
+            .iter()
+            .map(|(key, item)| (format!("{}.{}", path, key), item))
+            .collect(),
+        _ => Vec::new(),
+    };
+    for (path, item) in nested {
+        check_value(&path, item, depth + 1, limits, violations);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn limits() -> PayloadLimits {
+        PayloadLimits {
+            enabled: true,
+            max_properties: 3,
+            max_value_bytes: 8,
+            max_depth: 2,
+            max_event_bytes: 1024,
+        }
+    }
+
+    fn event(properties: Value) -> IngestEventPayload {
+        IngestEventPayload {
+            project_id: "proj".to_string(),
+            event_type: "signup".to_string(),
+            properties: serde_json::from_value(properties).unwrap(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_within_limits() {
+        let payload = event(json!({"plan": "pro", "seats": 3, "tags": [{"id": 1}]}));
+        assert_eq!(check(&payload, &limits()), Ok(()));
+        assert_eq!(check(&event(json!({"a": 1, "b": 2, "c": 3, "d": 4})), &PayloadLimits::default()), Ok(()));
+    }
+
+    #[test]
+    fn test_every_violation_is_listed() {
+        let payload = event(json!({
+            "a": 1,
+            "b": 2,
+            "note": "far too long",
+            "deep": {"one": {"two": {"three": true}}},
+        }));
+        let Err(LimitError::Exceeded(violations)) = check(&payload, &limits()) else {
+            panic!("expected violations");
+        };
+        let paths: Vec<_> = violations.iter().map(|v| v.path.as_str()).collect();
+        assert_eq!(paths, ["properties", "properties.deep.one.two", "properties.note"]);
+        assert_eq!(violations[2].message, "is 12 bytes, over the limit of 8");
+    }
+
+    #[test]
+    fn test_oversized_event_is_413() {
+        let many = (0..200).map(|i| i.to_string()).collect::<Vec<_>>();
+        let error = check(&event(json!({"ids": many})), &limits()).unwrap_err();
+        assert!(matches!(error, LimitError::TooLarge(size) if size > 1024));
+        assert_eq!(error.response(&limits()).status(), 413);
+    }
+}
@@ -1,82 +1,427 @@
-use lambda_http::{run, service_fn, Body, Error, Request, Response};
+use lambda_http::tower::ServiceExt;
+use lambda_http::{run, service_fn, Error};
 use std::sync::Arc;
+use tokio::sync::Semaphore;
 use aws_sdk_kinesis::Client as KinesisClient;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use aws_sdk_kms::Client as KmsClient;
+use aws_sdk_s3::Client as S3Client;
+use aws_sdk_sqs::Client as SqsClient;
 
-mod models;
-mod handlers;
-mod shared;
+use ingestion::enrichment::duplicate_view::{
+    DynamoLastPageviewStore, InMemoryLastPageviewStore, LastPageviewStore,
+};
+use ingestion::enrichment::engagement::{
+    DynamoEngagementStore, EngagementStore, InMemoryEngagementStore,
+};
+use ingestion::enrichment::first_touch::{DynamoFirstTouchStore, FirstTouchStore, InMemoryFirstTouchStore};
+use ingestion::enrichment::geoip::{GeoIpLookup, MmdbGeoIp};
+use ingestion::enrichment::impossible_travel::{
+    DynamoLocationStore, InMemoryLocationStore, LocationStore,
+};
+use ingestion::enrichment::last_event_gap::{
+    DynamoLastSeenStore, InMemoryLastSeenStore, LastSeenStore,
+};
+use ingestion::enrichment::sequence::SessionSequences;
+use ingestion::enrichment::store_lookups::DynamoLookupReader;
+use ingestion::admin::ConfigCache;
+use ingestion::autocapture::AutocaptureThrottle;
+use ingestion::backpressure::ShardPressure;
+use ingestion::buffer::{self, EventBuffer};
+use ingestion::canary;
+use ingestion::config_source::{ConfigSources, RemoteConfig};
+use ingestion::auth::{ApiKeyCache, ApiKeyStore, DynamoApiKeyStore, InMemoryApiKeyStore};
+use ingestion::dedup::{DynamoMessageIdStore, InMemoryMessageIdStore, MessageIdStore};
+use ingestion::deletion::{DeletionQueue, SqsDeletionQueue};
+use ingestion::encryption::{DataKeyCache, DataKeySource, KmsDataKeySource, StaticDataKeySource};
+use ingestion::event_names::{
+    DynamoEventNameLog, DynamoEventNamePolicyStore, EventNameLog, EventNamePolicyStore, EventNameRegistry,
+    InMemoryEventNameLog, InMemoryEventNamePolicyStore,
+};
+use ingestion::health::SinkHealth;
+use ingestion::idempotency::{BatchResultStore, DynamoBatchResultStore, InMemoryBatchResultStore};
+use ingestion::jwt::{HttpJwks, JwksCache, JwksSource, StaticJwks};
+use ingestion::metering::{self, DynamoUsageStore, InMemoryUsageStore, UsageMeter, UsageStore};
+use ingestion::rate_limit::{DynamoAllowanceStore, RateLimiter};
+use ingestion::router;
+use ingestion::routing::StreamClients;
+use ingestion::rules::{DynamoRuleStore, InMemoryRuleStore, RuleCache, RuleStore};
+use ingestion::schema::{DynamoSchemaStore, InMemorySchemaStore, SchemaRegistry, SchemaStore};
+use ingestion::shared::{env_var, AppState, ColdStartTracker, Config};
+use ingestion::shutdown;
+use ingestion::sink::s3_dead_letter::{DeadLetterConfig, S3DeadLetterSink};
+use ingestion::sink::s3_fallback::S3FallbackSink;
+use ingestion::sink::s3_parquet::S3ParquetSink;
+use ingestion::sink::sqs_dead_letter::SqsDeadLetterSink;
+use ingestion::aws_json::AwsJsonClient;
+use ingestion::offline::{self, OfflineConfig};
+use ingestion::payload_quarantine::{KinesisPayloadQuarantine, PayloadQuarantine, PayloadQuarantineConfig, S3PayloadQuarantine};
+use ingestion::sink::eventbridge::{EventBridgeConfig, EventBridgeSink};
+use ingestion::sink::firehose::FirehoseSink;
+use ingestion::sink::local::LocalSink;
+use ingestion::sink::shadow::ShadowConfig;
+use ingestion::sink::sns::SnsSink;
+use ingestion::sink::sqs::SqsSink;
+use ingestion::sink::{EventSink, SinkConfig, SinkKind};
+use ingestion::telemetry::{self, TelemetryConfig};
+use ingestion::status::{DynamoStatusStore, InMemoryStatusStore, StatusStore};
 
-use shared::{AppState, create_response, create_error_response};
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    // Initialize logging and tracing
+    let tracer_provider = telemetry::init(&TelemetryConfig::from_env());
+
+    // Load AWS configuration
+    let config = aws_config::load_from_env().await;
+    let kinesis_client = KinesisClient::new(&config);
+    let dynamodb_client = DynamoClient::new(&config);
 
-/// Main Lambda handler
-async fn function_handler(event: Request, state: Arc<AppState>) -> Result<Response<Body>, Error> {
-    // Handle OPTIONS for CORS preflight
-    if event.method() == "OPTIONS" {
-        return Ok(create_response(200, serde_json::json!({})));
+    // Remote values override the environment, so fetch them before loading
+    let remote_config = RemoteConfig::new(&ConfigSources::from_env(), &config)?;
+    if let Some(ref remote) = remote_config {
+        remote.apply().await;
     }
+    let app_config = Config::from_env();
+    app_config.validate()?;
+    let app_config = Arc::new(app_config);
+    let offline = OfflineConfig::from_env();
 
-    // Extract path
-    let path = event.uri().path();
+    // Only an extension gets SIGTERM, the cue to flush what the sandbox holds
+    let extension = match shutdown::needed(&app_config) {
+        true => shutdown::register_extension().await.unwrap_or_else(|e| {
+            tracing::error!("Buffered events and usage won't be flushed on shutdown: {}", e);
+            None
+        }),
+        false => None,
+    };
 
-    // Parse request body
-    let body = event.body();
-    let body_str = match body {
-        Body::Text(s) => {
-            tracing::debug!("Received text body: {}", s);
-            s
-        }
-        Body::Binary(b) => {
-            let decoded = std::str::from_utf8(b)?;
-            tracing::debug!("Received binary body (decoded): {}", decoded);
-            decoded
-        }
-        Body::Empty => {
-            tracing::warn!("Received empty body");
-            return Ok(create_error_response(400, "Missing request body"));
-        }
+    // Get environment variables
+    let event_sink: Option<Arc<dyn EventSink>> = match app_config.event_sink {
+        _ if offline.enabled => Some(Arc::new(LocalSink::new(
+            offline.output.clone().map(Into::into),
+            &app_config.field_projection,
+        ))),
+        SinkConfig { kind: SinkKind::Sqs, queue_url: Some(ref queue_url), .. } => Some(Arc::new(
+            SqsSink::new(SqsClient::new(&config), queue_url.clone(), &app_config.field_projection),
+        )),
+        SinkConfig { kind: SinkKind::Firehose, delivery_stream: Some(ref delivery_stream), .. } => Some(Arc::new(
+            FirehoseSink::new(
+                AwsJsonClient::new(&config, "firehose", "Firehose_20150804")?,
+                delivery_stream.clone(),
+                &app_config.field_projection,
+            ),
+        )),
+        SinkConfig { kind: SinkKind::Sns, topic_arn: Some(ref topic_arn), .. } => Some(Arc::new(SnsSink::new(
+            AwsJsonClient::new(&config, "sns", "")?,
+            topic_arn.clone(),
+            &app_config.field_projection,
+        ))),
+        #[cfg(feature = "kafka")]
+        SinkConfig { kind: SinkKind::Kafka, ref kafka, .. } => Some(Arc::new(
+            ingestion::sink::kafka::KafkaSink::new(kafka, &config, &app_config.field_projection)?,
+        )),
+        _ => None,
+    };
+    let stream_name = match event_sink {
+        Some(_) => env_var("STREAM_NAME").unwrap_or_default(),
+        None => env_var("STREAM_NAME").expect("STREAM_NAME environment variable not set"),
     };
 
-    // Route based on path
-    match path {
-        p if p.ends_with("/view") => {
-            handlers::handle_page_view(body_str, &event, state.clone()).await
-        }
-        p if p.ends_with("/event") => {
-            handlers::handle_track(body_str, &event, state.clone()).await
-        }
-        _ => Ok(create_error_response(404, "Not found")),
+    match event_sink {
+        Some(_) if offline.enabled => tracing::info!("Initialized offline, writing to {:?}", offline.output),
+        Some(_) => tracing::info!("Initialized with event sink: {:?}", app_config.event_sink.kind),
+        None => tracing::info!("Initialized with Kinesis stream: {}", stream_name),
     }
-}
 
-#[tokio::main]
-async fn main() -> Result<(), Error> {
-    // Initialize tracing
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"))
-        )
-        .json()
-        .init();
+    let last_seen_store: Arc<dyn LastSeenStore> = match app_config.last_event_gap.table_name {
+        Some(ref table) => Arc::new(DynamoLastSeenStore::new(dynamodb_client.clone(), table.clone())),
+        None => Arc::new(InMemoryLastSeenStore::default()),
+    };
 
-    // Load AWS configuration
-    let config = aws_config::load_from_env().await;
-    let kinesis_client = KinesisClient::new(&config);
+    let location_store: Arc<dyn LocationStore> = match app_config.impossible_travel.table_name {
+        Some(ref table) => Arc::new(DynamoLocationStore::new(dynamodb_client.clone(), table.clone())),
+        None => Arc::new(InMemoryLocationStore::default()),
+    };
 
-    // Get environment variables
-    let stream_name = std::env::var("STREAM_NAME")
-        .expect("STREAM_NAME environment variable not set");
+    let first_touch_store: Arc<dyn FirstTouchStore> = match app_config.first_touch.table_name {
+        Some(ref table) => Arc::new(DynamoFirstTouchStore::new(
+            dynamodb_client.clone(),
+            table.clone(),
+            &app_config.first_touch,
+        )),
+        None => Arc::new(InMemoryFirstTouchStore::default()),
+    };
+
+    let parquet_sink: Option<Arc<dyn EventSink>> = match app_config.s3_parquet.bucket {
+        Some(ref bucket) if !app_config.s3_parquet.projects.is_empty() => Some(Arc::new(
+            S3ParquetSink::new(
+                S3Client::new(&config),
+                bucket.clone(),
+                &app_config.s3_parquet,
+                &app_config.field_projection,
+            ),
+        )),
+        _ => None,
+    };
+
+    let last_pageview_store: Arc<dyn LastPageviewStore> = match app_config.duplicate_view.table_name {
+        Some(ref table) => Arc::new(DynamoLastPageviewStore::new(dynamodb_client.clone(), table.clone())),
+        None => Arc::new(InMemoryLastPageviewStore::default()),
+    };
+
+    let engagement_store: Arc<dyn EngagementStore> = match app_config.engagement.table_name {
+        Some(ref table) => Arc::new(DynamoEngagementStore::new(dynamodb_client.clone(), table.clone())),
+        None => Arc::new(InMemoryEngagementStore::default()),
+    };
+
+    let lookup_reader = DynamoLookupReader::new(dynamodb_client.clone(), &app_config).map(Arc::new);
+
+    let dead_letter_sink: Option<Arc<dyn EventSink>> = match app_config.dead_letter {
+        DeadLetterConfig { queue_url: Some(ref queue_url), .. } => Some(Arc::new(
+            SqsDeadLetterSink::new(
+                SqsClient::new(&config),
+                queue_url.clone(),
+                &app_config.field_projection,
+                &app_config.residency,
+            ),
+        )),
+        DeadLetterConfig { bucket: Some(ref bucket), .. } => Some(Arc::new(
+            S3DeadLetterSink::new(
+                S3Client::new(&config),
+                bucket.clone(),
+                &app_config.dead_letter,
+                &app_config.field_projection,
+            ),
+        )),
+        _ => None,
+    };
+
+    let fallback_sink: Option<Arc<dyn EventSink>> = match app_config.fallback.bucket {
+        Some(ref bucket) => Some(Arc::new(
+            S3FallbackSink::new(
+                S3Client::new(&config),
+                bucket.clone(),
+                &app_config.fallback,
+                &app_config.field_projection,
+            ),
+        )),
+        None => None,
+    };
+
+    let event_bus_sink: Option<Arc<dyn EventSink>> = match app_config.event_bus {
+        EventBridgeConfig { event_bus: Some(ref event_bus), ref source } => Some(Arc::new(EventBridgeSink::new(
+            AwsJsonClient::new(&config, "events", "AWSEvents")?,
+            event_bus.clone(),
+            source.clone(),
+            &app_config.field_projection,
+        ))),
+        _ => None,
+    };
+
+    // A shadow stream is written through the stream clients instead
+    let shadow_sink: Option<Arc<dyn EventSink>> = match app_config.shadow {
+        ShadowConfig { stream_name: None, queue_url: Some(ref queue_url), .. } => {
+            Some(Arc::new(SqsSink::new(SqsClient::new(&config), queue_url.clone(), &app_config.field_projection)))
+        }
+        _ => None,
+    };
+
+    let deletion_queue: Option<Arc<dyn DeletionQueue>> = app_config
+        .deletion
+        .queue_url
+        .clone()
+        .map(|queue_url| Arc::new(SqsDeletionQueue::new(SqsClient::new(&config), queue_url)) as Arc<dyn DeletionQueue>);
 
-    tracing::info!("Initialized with Kinesis stream: {}", stream_name);
+    let payload_quarantine: Option<Arc<dyn PayloadQuarantine>> = match app_config.payload_quarantine {
+        PayloadQuarantineConfig { stream_name: Some(ref stream), .. } => Some(Arc::new(
+            KinesisPayloadQuarantine::new(kinesis_client.clone(), stream.clone()),
+        )),
+        PayloadQuarantineConfig { bucket: Some(ref bucket), .. } => Some(Arc::new(
+            S3PayloadQuarantine::new(S3Client::new(&config), bucket.clone(), &app_config.payload_quarantine),
+        )),
+        _ => None,
+    };
+
+    let status_store: Arc<dyn StatusStore> = match app_config.status.table_name {
+        Some(ref table) => Arc::new(DynamoStatusStore::new(dynamodb_client.clone(), table.clone())),
+        None => Arc::new(InMemoryStatusStore::default()),
+    };
+
+    let batch_results: Arc<dyn BatchResultStore> = match app_config.batch_idempotency.table_name {
+        Some(ref table) => Arc::new(DynamoBatchResultStore::new(
+            dynamodb_client.clone(),
+            table.clone(),
+            &app_config.batch_idempotency,
+        )),
+        None => Arc::new(InMemoryBatchResultStore::new(&app_config.batch_idempotency)),
+    };
+
+    let message_ids: Arc<dyn MessageIdStore> = match app_config.message_dedup.table_name {
+        Some(ref table) => Arc::new(DynamoMessageIdStore::new(
+            dynamodb_client.clone(),
+            table.clone(),
+            &app_config.message_dedup,
+        )),
+        None => Arc::new(InMemoryMessageIdStore::new(&app_config.message_dedup)),
+    };
+
+    let api_key_store: Arc<dyn ApiKeyStore> = match app_config.api_keys.table_name {
+        Some(ref table) => Arc::new(DynamoApiKeyStore::new(dynamodb_client.clone(), table.clone())),
+        None => Arc::new(InMemoryApiKeyStore::default()),
+    };
+
+    let jwks_source: Arc<dyn JwksSource> = match app_config.jwt.jwks_url {
+        Some(ref url) => Arc::new(HttpJwks::new(url.clone())?),
+        None => Arc::new(StaticJwks::default()),
+    };
+
+    let schema_store: Arc<dyn SchemaStore> = match app_config.schemas.table_name {
+        Some(ref table) => Arc::new(DynamoSchemaStore::new(dynamodb_client.clone(), table.clone())),
+        None => Arc::new(InMemorySchemaStore::default()),
+    };
+
+    let event_name_policies: Arc<dyn EventNamePolicyStore> = match app_config.event_names.policy_table {
+        Some(ref table) => Arc::new(DynamoEventNamePolicyStore::new(dynamodb_client.clone(), table.clone())),
+        None => Arc::new(InMemoryEventNamePolicyStore::default()),
+    };
+    let event_name_log: Arc<dyn EventNameLog> = match app_config.event_names.names_table {
+        Some(ref table) => Arc::new(DynamoEventNameLog::new(dynamodb_client.clone(), table.clone())),
+        None => Arc::new(InMemoryEventNameLog::default()),
+    };
+
+    let rule_store: Arc<dyn RuleStore> = match app_config.rules.table_name {
+        Some(ref table) => Arc::new(DynamoRuleStore::new(dynamodb_client.clone(), table.clone())),
+        None => Arc::new(InMemoryRuleStore::default()),
+    };
+
+    let data_key_source: Arc<dyn DataKeySource> = match app_config.field_encryption.kms_key_id {
+        Some(_) => Arc::new(KmsDataKeySource::new(KmsClient::new(&config))),
+        None => Arc::new(StaticDataKeySource),
+    };
+
+    // Routed streams in other regions get their own clients
+    let streams = app_config
+        .stream_routing
+        .routes
+        .iter()
+        .filter_map(|route| Some((route.stream.clone(), route.region.clone()?)))
+        .fold(StreamClients::new(stream_name, kinesis_client), |streams, (stream, region)| {
+            let regional_config = aws_sdk_kinesis::config::Builder::from(&config)
+                .region(aws_sdk_kinesis::config::Region::new(region))
+                .build();
+            streams.with_stream(stream, KinesisClient::from_conf(regional_config))
+        });
+
+    let regional_kinesis = app_config
+        .residency
+        .streams
+        .iter()
+        .map(|(zone, stream)| {
+            let regional_config = aws_sdk_kinesis::config::Builder::from(&config)
+                .region(aws_sdk_kinesis::config::Region::new(stream.region.clone()))
+                .build();
+            (zone.clone(), KinesisClient::from_conf(regional_config))
+        })
+        .collect();
+
+    // Read once per sandbox; a missing layer disables the lookup, not the function
+    let geoip: Option<Arc<dyn GeoIpLookup>> = if app_config.geoip.enabled {
+        match MmdbGeoIp::open(&app_config.geoip.database_path) {
+            Ok(database) => Some(Arc::new(database)),
+            Err(e) => {
+                tracing::error!("Failed to open GeoIP database {}: {}", app_config.geoip.database_path, e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let rate_limiter = match app_config.rate_limit.table_name {
+        Some(ref table) => RateLimiter::with_allowances(Arc::new(DynamoAllowanceStore::new(
+            dynamodb_client.clone(),
+            table.clone(),
+            &app_config.rate_limit,
+        ))),
+        None => RateLimiter::default(),
+    };
+
+    let usage_store: Arc<dyn UsageStore> = match app_config.metering.table_name {
+        Some(ref table) => Arc::new(DynamoUsageStore::new(dynamodb_client.clone(), table.clone())),
+        None => Arc::new(InMemoryUsageStore::default()),
+    };
+
+    let enrichment_permits = Arc::new(Semaphore::new(app_config.enrichment_max_concurrency));
 
     let state = Arc::new(AppState {
-        kinesis_client,
-        stream_name,
+        streams,
+        enrichment_permits,
+        geoip,
+        config_cache: Arc::new(ConfigCache::new(app_config.clone(), Config::from_env).with_remote(remote_config)),
+        config: app_config,
+        last_seen_store,
+        location_store,
+        first_touch_store,
+        last_pageview_store,
+        engagement_store,
+        lookup_reader,
+        status_store,
+        batch_results,
+        message_ids,
+        api_keys: Arc::new(ApiKeyCache::new(api_key_store)),
+        jwks: Arc::new(JwksCache::new(jwks_source)),
+        schemas: Arc::new(SchemaRegistry::new(schema_store)),
+        event_names: Arc::new(EventNameRegistry::new(event_name_policies, event_name_log)),
+        rules: Arc::new(RuleCache::new(rule_store)),
+        data_keys: Arc::new(DataKeyCache::new(data_key_source)),
+        cold_start: Arc::new(ColdStartTracker::default()),
+        session_sequences: Arc::new(SessionSequences::default()),
+        autocapture: Arc::new(AutocaptureThrottle::default()),
+        rate_limiter: Arc::new(rate_limiter),
+        usage: Arc::new(UsageMeter::new(usage_store)),
+        sink_health: Arc::new(SinkHealth::default()),
+        shard_pressure: Arc::new(ShardPressure::default()),
+        regional_kinesis,
+        parquet_sink,
+        dead_letter_sink,
+        event_sink,
+        fallback_sink,
+        event_bus_sink,
+        shadow_sink,
+        deletion_queue,
+        payload_quarantine,
+        event_buffer: Arc::new(EventBuffer::default()),
     });
 
+    if state.config.event_buffer.enabled {
+        tokio::spawn(buffer::flush_periodically(state.clone()));
+    }
+
+    if shutdown::needed(&state.config) {
+        tokio::spawn(shutdown::flush_on_shutdown(state.clone(), extension));
+    }
+
+    if state.config.metering.enabled {
+        tokio::spawn(metering::flush_periodically(state.clone()));
+    }
+
+    if state.config.canary.enabled {
+        tokio::spawn(canary::emit_periodically(state.clone()));
+    }
+
+    if let (true, Some(port)) = (offline.enabled, offline.port) {
+        return offline::serve(state, port).await;
+    }
+
+    let service = router::service(state);
     run(service_fn(move |event| {
-        let state = state.clone();
-        async move { function_handler(event, state).await }
+        let service = service.clone();
+        let tracer_provider = tracer_provider.clone();
+        async move {
+            let response = service.oneshot(event).await;
+            telemetry::flush(tracer_provider).await;
+            response
+        }
     }))
     .await
 }
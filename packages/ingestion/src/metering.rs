@@ -0,0 +1,436 @@
+//! Usage metering and monthly quotas.
+//!
+//! With `USAGE_METERING_ENABLED`, every event handed to the sink counts
+//! towards its project's usage for the calendar month (UTC). Counts are
+//! kept in the sandbox and added to DynamoDB atomic counters
+//! (`USAGE_TABLE`) every `USAGE_FLUSH_INTERVAL_MS`, by the request that
+//! finds a flush due or by [`flush_periodically`], so the table sees one
+//! write per project per interval rather than per request.
+//!
+//! Projects are on a plan (`QUOTA_PROJECT_PLANS`, `QUOTA_DEFAULT_PLAN` for
+//! the rest) whose monthly event quota comes from `QUOTA_PLANS`, e.g.
+//! `{"free": 100000}`. Once a project's usage reaches its quota, requests
+//! for it get a 429 with a `quota_exceeded` body until the month turns
+//! over. Projects without a plan are metered but never capped.
+//!
+//! Usage is as of the last time this sandbox flushed or read the counter,
+//! plus what it has counted since, so the fleet can overshoot a quota by
+//! what other sandboxes counted within an interval. Counts not yet flushed
+//! are flushed when the sandbox shuts down (see `shutdown`). If the table
+//! can't be reached, requests are let through.
+//!
+//! Events of projects pinned to a residency zone aren't counted, so their
+//! usage never lands in the home region's table.
+
+use async_trait::async_trait;
+use aws_sdk_dynamodb::types::{AttributeValue, ReturnValue};
+use aws_sdk_dynamodb::Client as DynamoClient;
+use chrono::{DateTime, Datelike, TimeZone, Utc};
+use lambda_http::{Body, Error, Response};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::shared::{create_response, env_flag, env_json, env_or, env_var, AppState};
+
+/// Configuration for usage metering and quotas
+#[derive(Debug, Clone)]
+pub struct MeteringConfig {
+    pub enabled: bool,
+    /// DynamoDB table holding the monthly counters
+    pub table_name: Option<String>,
+    /// Monthly event quota by plan
+    pub plans: HashMap<String, u64>,
+    /// Plan by project id
+    pub project_plans: HashMap<String, String>,
+    /// Plan of projects not in `project_plans`
+    pub default_plan: Option<String>,
+    /// How often counts are added to the table
+    pub flush_interval: Duration,
+}
+
+impl Default for MeteringConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            table_name: None,
+            plans: HashMap::new(),
+            project_plans: HashMap::new(),
+            default_plan: None,
+            flush_interval: Duration::from_secs(10),
+        }
+    }
+}
+
+impl MeteringConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            enabled: env_flag("USAGE_METERING_ENABLED"),
+            table_name: env_var("USAGE_TABLE"),
+            plans: env_json("QUOTA_PLANS").unwrap_or_default(),
+            project_plans: env_json("QUOTA_PROJECT_PLANS").unwrap_or_default(),
+            default_plan: env_var("QUOTA_DEFAULT_PLAN"),
+            flush_interval: Duration::from_millis(env_or(
+                "USAGE_FLUSH_INTERVAL_MS",
+                defaults.flush_interval.as_millis() as u64,
+            )),
+        }
+    }
+
+    /// The project's monthly event quota, if its plan has one
+    pub fn quota(&self, project_id: &str) -> Option<u64> {
+        let plan = self
+            .project_plans
+            .get(project_id)
+            .or(self.default_plan.as_ref())?;
+        self.plans.get(plan).copied()
+    }
+}
+
+/// Calendar month (UTC) usage is counted in, as `YYYY-MM`
+pub fn month(now: DateTime<Utc>) -> String {
+    now.format("%Y-%m").to_string()
+}
+
+/// Start of the month after `now`'s, when quotas reset
+fn next_month(now: DateTime<Utc>) -> DateTime<Utc> {
+    let (year, month) = match now.month() {
+        12 => (now.year() + 1, 1),
+        month => (now.year(), month + 1),
+    };
+    Utc.with_ymd_and_hms(year, month, 1, 0, 0, 0).unwrap()
+}
+
+/// Monthly event counters, by project
+#[async_trait]
+pub trait UsageStore: Send + Sync {
+    /// Adds `count` to the project's usage for `month`, returning the new total
+    async fn add(&self, project_id: &str, month: &str, count: u64) -> Result<u64, Error>;
+
+    /// The project's usage for `month`
+    async fn get(&self, project_id: &str, month: &str) -> Result<u64, Error>;
+}
+
+/// In-memory implementation of [`UsageStore`], for tests and local runs
+#[derive(Debug, Default)]
+pub struct InMemoryUsageStore {
+    counts: Mutex<HashMap<(String, String), u64>>,
+}
+
+#[async_trait]
+impl UsageStore for InMemoryUsageStore {
+    async fn add(&self, project_id: &str, month: &str, count: u64) -> Result<u64, Error> {
+        let mut counts = self.counts.lock().unwrap();
+        let total = counts.entry((project_id.to_string(), month.to_string())).or_insert(0);
+        *total += count;
+        Ok(*total)
+    }
+
+    async fn get(&self, project_id: &str, month: &str) -> Result<u64, Error> {
+        let counts = self.counts.lock().unwrap();
+        Ok(counts
+            .get(&(project_id.to_string(), month.to_string()))
+            .copied()
+            .unwrap_or(0))
+    }
+}
+
+/// DynamoDB-backed usage counters
+/// Table schema: partition key `pk` (S, `projectId#YYYY-MM`)
+pub struct DynamoUsageStore {
+    client: DynamoClient,
+    table_name: String,
+}
+
+impl DynamoUsageStore {
+    pub fn new(client: DynamoClient, table_name: String) -> Self {
+        Self { client, table_name }
+    }
+}
+
+fn events_attribute(item: Option<&HashMap<String, AttributeValue>>) -> u64 {
+    item.and_then(|item| item.get("events"))
+        .and_then(|value| value.as_n().ok())
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(0)
+}
+
+#[async_trait]
+impl UsageStore for DynamoUsageStore {
+    async fn add(&self, project_id: &str, month: &str, count: u64) -> Result<u64, Error> {
+        let output = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("pk", AttributeValue::S(format!("{}#{}", project_id, month)))
+            .update_expression("ADD events :count SET projectId = :project, #month = :month")
+            .expression_attribute_names("#month", "month")
+            .expression_attribute_values(":count", AttributeValue::N(count.to_string()))
+            .expression_attribute_values(":project", AttributeValue::S(project_id.to_string()))
+            .expression_attribute_values(":month", AttributeValue::S(month.to_string()))
+            .return_values(ReturnValue::UpdatedNew)
+            .send()
+            .await?;
+        Ok(events_attribute(output.attributes()))
+    }
+
+    async fn get(&self, project_id: &str, month: &str) -> Result<u64, Error> {
+        let output = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("pk", AttributeValue::S(format!("{}#{}", project_id, month)))
+            .send()
+            .await?;
+        Ok(events_attribute(output.item()))
+    }
+}
+
+/// A project's stored usage for a month, as last seen by this sandbox
+#[derive(Debug)]
+struct Known {
+    events: u64,
+    seen_at: Instant,
+}
+
+/// This sandbox's view of usage: counts not yet flushed, and the stored
+/// totals they add to
+pub struct UsageMeter {
+    store: Arc<dyn UsageStore>,
+    pending: Mutex<HashMap<(String, String), u64>>,
+    known: Mutex<HashMap<(String, String), Known>>,
+    flushed_at: Mutex<Instant>,
+}
+
+impl UsageMeter {
+    pub fn new(store: Arc<dyn UsageStore>) -> Self {
+        Self {
+            store,
+            pending: Mutex::new(HashMap::new()),
+            known: Mutex::new(HashMap::new()),
+            flushed_at: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Counts accepted events towards the project's usage for `month`
+    pub fn record(&self, project_id: &str, month: &str, count: u64) {
+        let mut pending = self.pending.lock().unwrap();
+        *pending.entry((project_id.to_string(), month.to_string())).or_default() += count;
+    }
+
+    /// Whether the counts are due to be flushed
+    pub fn due(&self, config: &MeteringConfig) -> bool {
+        self.flushed_at.lock().unwrap().elapsed() >= config.flush_interval
+    }
+
+    /// The project's usage for `month`: the stored total, read again once
+    /// it's older than a flush interval, plus counts not yet flushed
+    pub async fn used(&self, project_id: &str, month: &str, config: &MeteringConfig) -> Result<u64, Error> {
+        let key = (project_id.to_string(), month.to_string());
+        let cached = self
+            .known
+            .lock()
+            .unwrap()
+            .get(&key)
+            .filter(|known| known.seen_at.elapsed() < config.flush_interval)
+            .map(|known| known.events);
+        let stored = match cached {
+            Some(events) => events,
+            None => {
+                let events = self.store.get(project_id, month).await?;
+                self.remember(key.clone(), events);
+                events
+            }
+        };
+        let pending = self.pending.lock().unwrap().get(&key).copied().unwrap_or(0);
+        Ok(stored + pending)
+    }
+
+    /// Adds the pending counts to the store. Counts that fail to flush are
+    /// kept for the next attempt.
+    pub async fn flush(&self) {
+        *self.flushed_at.lock().unwrap() = Instant::now();
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        for ((project_id, month), count) in pending {
+            match self.store.add(&project_id, &month, count).await {
+                Ok(events) => self.remember((project_id, month), events),
+                Err(e) => {
+                    tracing::warn!("Failed to flush usage for {}: {}", project_id, e);
+                    *self.pending.lock().unwrap().entry((project_id, month)).or_default() += count;
+                }
+            }
+        }
+    }
+
+    fn remember(&self, key: (String, String), events: u64) {
+        let known = Known {
+            events,
+            seen_at: Instant::now(),
+        };
+        self.known.lock().unwrap().insert(key, known);
+    }
+}
+
+/// Counts events handed to the sink, by project, flushing when due
+pub async fn record(counts: &HashMap<String, usize>, state: &AppState) {
+    let config = &state.config.metering;
+    if !config.enabled {
+        return;
+    }
+    let month = month(Utc::now());
+    for (project_id, count) in counts {
+        state.usage.record(project_id, &month, *count as u64);
+    }
+    if state.usage.due(config) {
+        state.usage.flush().await;
+    }
+}
+
+/// 429 for a project that has used its quota for the month, when metering
+/// is on and the project's plan has one
+pub async fn check(state: &AppState, project_id: &str) -> Option<Response<Body>> {
+    let config = &state.config.metering;
+    if !config.enabled {
+        return None;
+    }
+    let quota = config.quota(project_id)?;
+    let now = Utc::now();
+    let used = match state.usage.used(project_id, &month(now), config).await {
+        Ok(used) => used,
+        Err(e) => {
+            tracing::warn!("Failed to read usage for {}: {}", project_id, e);
+            return None;
+        }
+    };
+    (used >= quota).then(|| quota_exceeded(quota, used, now))
+}
+
+fn quota_exceeded(quota: u64, used: u64, now: DateTime<Utc>) -> Response<Body> {
+    let resets_at = next_month(now);
+    let mut response = create_response(
+        429,
+        serde_json::json!({
+            "error": "quota_exceeded",
+            "message": "Monthly event quota exceeded",
+            "quota": quota,
+            "used": used,
+            "resetsAt": resets_at.to_rfc3339(),
+        }),
+    );
+    let retry_after = (resets_at - now).num_seconds().max(1);
+    response.headers_mut().insert("Retry-After", retry_after.into());
+    response
+}
+
+/// Flushes the counts once due, for as long as the sandbox lives
+pub async fn flush_periodically(state: Arc<AppState>) {
+    loop {
+        tokio::time::sleep(state.config.metering.flush_interval).await;
+        let state = state.with_current_config().await;
+        if state.usage.due(&state.config.metering) {
+            state.usage.flush().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::function_handler;
+    use crate::shared::{test_state, Config};
+
+    fn config() -> MeteringConfig {
+        MeteringConfig {
+            enabled: true,
+            plans: HashMap::from([("free".to_string(), 2), ("pro".to_string(), 100)]),
+            project_plans: HashMap::from([("big".to_string(), "pro".to_string())]),
+            default_plan: Some("free".to_string()),
+            flush_interval: Duration::from_secs(3600),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_quota_comes_from_the_projects_plan() {
+        assert_eq!(config().quota("big"), Some(100));
+        assert_eq!(config().quota("small"), Some(2));
+        let unplanned = MeteringConfig {
+            default_plan: None,
+            ..config()
+        };
+        assert_eq!(unplanned.quota("small"), None);
+    }
+
+    #[test]
+    fn test_quotas_reset_at_the_start_of_next_month() {
+        let december = Utc.with_ymd_and_hms(2025, 12, 31, 23, 0, 0).unwrap();
+        assert_eq!(month(december), "2025-12");
+        assert_eq!(next_month(december), Utc.with_ymd_and_hms(2026, 1, 1, 0, 0, 0).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_sandboxes_add_to_the_same_counter() {
+        let store = Arc::new(InMemoryUsageStore::default());
+        let sandboxes = [UsageMeter::new(store.clone()), UsageMeter::new(store.clone())];
+
+        sandboxes[0].record("proj", "2026-10", 3);
+        assert_eq!(sandboxes[0].used("proj", "2026-10", &config()).await.unwrap(), 3);
+        assert_eq!(store.get("proj", "2026-10").await.unwrap(), 0);
+
+        sandboxes[0].flush().await;
+        sandboxes[1].record("proj", "2026-10", 2);
+        sandboxes[1].flush().await;
+        assert_eq!(store.get("proj", "2026-10").await.unwrap(), 5);
+        assert_eq!(sandboxes[1].used("proj", "2026-10", &config()).await.unwrap(), 5);
+
+        // The first sandbox sees the second's counts once its own are stale
+        let stale = MeteringConfig {
+            flush_interval: Duration::ZERO,
+            ..config()
+        };
+        assert_eq!(sandboxes[0].used("proj", "2026-10", &config()).await.unwrap(), 3);
+        assert_eq!(sandboxes[0].used("proj", "2026-10", &stale).await.unwrap(), 5);
+    }
+
+    fn track() -> lambda_http::Request {
+        use base64::Engine;
+        let claims = serde_json::json!({ "projectId": "proj" }).to_string();
+        let token = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(claims);
+        lambda_http::http::Request::builder()
+            .method("POST")
+            .uri("/event")
+            .header("Authorization", format!("Bearer e30.{}.sig", token))
+            .body(Body::Text(
+                r#"{"en":"signup","ts":1,"o":"https://a.io/","r":"","sw":1,"sh":1}"#.to_string(),
+            ))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_requests_over_quota_are_rejected() {
+        let mut config = Config {
+            metering: self::config(),
+            ..Default::default()
+        };
+        config.s3_parquet.projects = vec!["proj".to_string()];
+        let mut state = test_state(config);
+        state.parquet_sink = Some(Arc::new(crate::sink::RecordingSink::default()));
+        let state = Arc::new(state);
+
+        for _ in 0..2 {
+            let response = function_handler(track(), state.clone()).await.unwrap();
+            assert_eq!(response.status(), 202);
+        }
+
+        let response = function_handler(track(), state.clone()).await.unwrap();
+        assert_eq!(response.status(), 429);
+        let body: serde_json::Value = match response.body() {
+            Body::Text(text) => serde_json::from_str(text).unwrap(),
+            _ => panic!("expected a text body"),
+        };
+        assert_eq!(body["error"], "quota_exceeded");
+        assert_eq!(body["quota"], 2);
+        assert_eq!(body["used"], 2);
+    }
+}
@@ -0,0 +1,185 @@
+//! CloudWatch metrics in the Embedded Metric Format.
+//!
+//! With `EMF_METRICS_ENABLED`, metrics are printed to stdout as EMF
+//! documents, which CloudWatch Logs turns into metrics under
+//! `EMF_NAMESPACE` (default `ProductAnalytics/Ingestion`) with no API
+//! calls on the request path:
+//!
+//! - `Requests`, `RequestLatency`, `PayloadBytes` by `Route`, and
+//!   `RejectedRequests` by `Route` and `Status` for 4xx/5xx responses
+//! - `EventsAccepted` by `ProjectId`
+//! - `EventsRejected` by `ProjectId` and `Reason` for batch events
+//! - `EventsShed` by `ProjectId` under backpressure
+//! - `StreamWriteLatency`, `StreamRecords`, `StreamFailedRecords` by `Stream`
+
+use serde_json::{json, Map, Value};
+
+use crate::shared::{env_flag, env_or};
+
+/// Endpoints reported as themselves; anything else is `other`
+const ROUTES: [&str; 15] = [
+    "view", "event", "identify", "group", "alias", "batch", "cloudevents", "track", "page", "pixel.gif",
+    "livez", "readyz", "health", "refresh-config", "status",
+];
+
+/// The `Route` dimension of a request path, kept to a fixed set so a
+/// stray path can't mint new metrics
+pub fn route_name(path: &str) -> &'static str {
+    let mut segments = path.trim_end_matches('/').rsplit('/');
+    let last = segments.next().unwrap_or_default();
+    let route = match segments.next() {
+        Some("status") => "status",
+        _ => last,
+    };
+    ROUTES.into_iter().find(|known| *known == route).unwrap_or("other")
+}
+
+/// Configuration for EMF metrics
+#[derive(Debug, Clone)]
+pub struct MetricsConfig {
+    pub enabled: bool,
+    pub namespace: String,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            namespace: "ProductAnalytics/Ingestion".to_string(),
+        }
+    }
+}
+
+impl MetricsConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            enabled: env_flag("EMF_METRICS_ENABLED"),
+            namespace: env_or("EMF_NAMESPACE", defaults.namespace),
+        }
+    }
+}
+
+/// CloudWatch unit of a metric
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    Count,
+    Milliseconds,
+    Bytes,
+}
+
+impl Unit {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Count => "Count",
+            Self::Milliseconds => "Milliseconds",
+            Self::Bytes => "Bytes",
+        }
+    }
+}
+
+/// Metrics sharing one set of dimensions, emitted as one EMF document
+#[derive(Debug)]
+pub struct MetricSet<'a> {
+    config: &'a MetricsConfig,
+    dimensions: Vec<(&'static str, String)>,
+    metrics: Vec<(&'static str, f64, Unit)>,
+}
+
+impl<'a> MetricSet<'a> {
+    pub fn new(config: &'a MetricsConfig) -> Self {
+        Self {
+            config,
+            dimensions: Vec::new(),
+            metrics: Vec::new(),
+        }
+    }
+
+    pub fn dimension(mut self, name: &'static str, value: impl Into<String>) -> Self {
+        self.dimensions.push((name, value.into()));
+        self
+    }
+
+    pub fn metric(mut self, name: &'static str, value: f64, unit: Unit) -> Self {
+        self.metrics.push((name, value, unit));
+        self
+    }
+
+    pub fn count(self, name: &'static str, value: usize) -> Self {
+        self.metric(name, value as f64, Unit::Count)
+    }
+
+    /// The EMF document for these metrics at `timestamp` (ms)
+    pub fn document(&self, timestamp: i64) -> Value {
+        let mut document = Map::new();
+        document.insert(
+            "_aws".to_string(),
+            json!({
+                "Timestamp": timestamp,
+                "CloudWatchMetrics": [{
+                    "Namespace": self.config.namespace,
+                    "Dimensions": [self.dimensions.iter().map(|(name, _)| name).collect::<Vec<_>>()],
+                    "Metrics": self.metrics.iter()
+                        .map(|(name, _, unit)| json!({"Name": name, "Unit": unit.as_str()}))
+                        .collect::<Vec<_>>(),
+                }],
+            }),
+        );
+        for (name, value) in &self.dimensions {
+            document.insert(name.to_string(), json!(value));
+        }
+        for (name, value, _) in &self.metrics {
+            document.insert(name.to_string(), json!(value));
+        }
+        Value::Object(document)
+    }
+
+    /// Prints the document, when metrics are enabled
+    pub fn emit(self) {
+        if self.config.enabled && !self.metrics.is_empty() {
+            println!("{}", self.document(chrono::Utc::now().timestamp_millis()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_route_names() {
+        assert_eq!(route_name("/prod/batch"), "batch");
+        assert_eq!(route_name("/v1/track/"), "track");
+        assert_eq!(route_name("/prod/status/evt_123"), "status");
+        assert_eq!(route_name("/prod/wp-login.php"), "other");
+    }
+
+    #[test]
+    fn test_emf_document() {
+        let config = MetricsConfig::default();
+        let document = MetricSet::new(&config)
+            .dimension("Route", "batch")
+            .count("Requests", 1)
+            .metric("RequestLatency", 12.5, Unit::Milliseconds)
+            .document(1_700_000_000_000);
+        assert_eq!(
+            document,
+            json!({
+                "_aws": {
+                    "Timestamp": 1_700_000_000_000_i64,
+                    "CloudWatchMetrics": [{
+                        "Namespace": "ProductAnalytics/Ingestion",
+                        "Dimensions": [["Route"]],
+                        "Metrics": [
+                            {"Name": "Requests", "Unit": "Count"},
+                            {"Name": "RequestLatency", "Unit": "Milliseconds"},
+                        ],
+                    }],
+                },
+                "Route": "batch",
+                "Requests": 1.0,
+                "RequestLatency": 12.5,
+            })
+        );
+    }
+}
@@ -0,0 +1,356 @@
+//! The middleware every request passes through on its way to the router.
+//!
+//! The Lambda service is a tower stack, outermost first:
+//!
+//! - context: the current config, whether this is a cold start, the
+//!   resolved route and any beacon query credentials, for the layers below
+//! - logging: the request id and span, request metrics and `Server-Timing`
+//! - panics: a panicking handler answers 500 instead of failing the
+//!   invocation, which API Gateway would turn into a bare 502
+//! - store lookups: the request's shared enrichment store lookups, written
+//!   back once it is answered, when `STORE_LOOKUPS_BATCHED` (see
+//!   `enrichment::store_lookups`)
+//! - negotiation: the response in the format the client asked for, when
+//!   `CONTENT_NEGOTIATION_ENABLED` (see `negotiation`)
+//! - CORS: answers preflights, and allows the origins of the request's API
+//!   key when `API_KEY_PROJECT_ORIGINS` is on
+//! - auth: JWT and request signatures, 401 when they don't verify
+//! - quarantine: the bodies of requests answered 400 or 422, when a
+//!   quarantine is configured (see `payload_quarantine`)
+//! - rate limits: the `X-RateLimit-*` headers for whatever decision the
+//!   handler recorded (only handlers know a request's project and cost)
+//! - body limit: 413 for a body past `max_body_bytes`
+//!
+//! A new cross-cutting concern is another layer here, not another step in
+//! the router or the handlers.
+
+use lambda_http::tower::layer::layer_fn;
+use lambda_http::tower::util::BoxCloneService;
+use lambda_http::tower::{service_fn, Layer, ServiceBuilder, ServiceExt};
+use lambda_http::{Body, Error, Request, Response};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::Instrument;
+
+use crate::auth;
+use crate::beacon;
+use crate::enrichment::store_lookups::StoreLookups;
+use crate::jwt;
+use crate::metrics::{self, MetricSet, Unit};
+use crate::negotiation::ResponseFormat;
+use crate::origin;
+use crate::payload_quarantine::RejectedPayload;
+use crate::rate_limit::RecordedDecision;
+use crate::request_id::{self, RequestId};
+use crate::routes::{self, Resolved};
+use crate::shared::{create_error_response, create_response, AppState, ColdStart};
+use crate::signing;
+use crate::telemetry;
+
+/// A request handler, or a stack of middleware around one
+pub type HttpService = BoxCloneService<Request, Response<Body>, Error>;
+
+/// The request's state, with the config current when it arrived; `fallback`
+/// outside the stack
+pub fn current_state(request: &Request, fallback: &Arc<AppState>) -> Arc<AppState> {
+    request.extensions().get::<Arc<AppState>>().cloned().unwrap_or_else(|| fallback.clone())
+}
+
+/// A layer running `f` around the rest of the stack
+fn layer<F, Fut>(state: &Arc<AppState>, f: F) -> impl Layer<HttpService, Service = HttpService>
+where
+    F: Fn(Request, Arc<AppState>, HttpService) -> Fut + Clone + Send + Sync + 'static,
+    Fut: Future<Output = Result<Response<Body>, Error>> + Send + 'static,
+{
+    let state = state.clone();
+    layer_fn(move |next: HttpService| {
+        let (f, state) = (f.clone(), state.clone());
+        BoxCloneService::new(service_fn(move |request: Request| {
+            let state = current_state(&request, &state);
+            f(request, state, next.clone())
+        }))
+    })
+}
+
+/// `router` behind the middleware stack
+pub fn stack(state: &Arc<AppState>, router: HttpService) -> HttpService {
+    let service = ServiceBuilder::new()
+        .layer(layer(state, context))
+        .layer(layer(state, logging))
+        .layer(layer(state, catch_panics))
+        .layer(layer(state, share_store_lookups))
+        .layer(layer(state, negotiate))
+        .layer(layer(state, cors))
+        .layer(layer(state, authenticate))
+        .layer(layer(state, quarantine_rejections))
+        .layer(layer(state, rate_limit_headers))
+        .layer(layer(state, limit_body))
+        .service(router);
+    BoxCloneService::new(service)
+}
+
+async fn context(mut request: Request, state: Arc<AppState>, next: HttpService) -> Result<Response<Body>, Error> {
+    let state = state.with_current_config().await;
+
+    // Cleared by the first request this sandbox serves, whatever its route
+    request.extensions_mut().insert(ColdStart(state.cold_start.take()));
+    let resolved = routes::resolve(request.method().as_str(), request.uri().path());
+    if let Resolved::Found(matched) = &resolved {
+        request.extensions_mut().insert(matched.version);
+        request.extensions_mut().insert(matched.params.clone());
+    }
+    request.extensions_mut().insert(resolved);
+
+    if state.config.beacon_support {
+        beacon::promote_query_credentials(&mut request);
+    }
+
+    request.extensions_mut().insert(state);
+    next.oneshot(request).await
+}
+
+async fn logging(mut request: Request, state: Arc<AppState>, next: HttpService) -> Result<Response<Body>, Error> {
+    let started = Instant::now();
+    let request_id = RequestId::from_request(&request);
+    let route = metrics::route_name(request.uri().path());
+    let span = tracing::info_span!(
+        "ingest",
+        request_id = %request_id.0,
+        http.route = route,
+        otel.kind = "server",
+    );
+    telemetry::continue_trace(&span, request.headers());
+    request.extensions_mut().insert(request_id.clone());
+
+    let cold_start = request.extensions().get::<ColdStart>().is_some_and(|cold| cold.0);
+    let method = request.method().clone();
+    let payload_bytes = match request.body() {
+        Body::Text(s) => s.len(),
+        Body::Binary(b) => b.len(),
+        Body::Empty => 0,
+    };
+    let mut response = next.oneshot(request).instrument(span.clone()).await?;
+    span.in_scope(|| tracing::debug!("{} {} answered {}", method, route, response.status()));
+
+    MetricSet::new(&state.config.metrics)
+        .dimension("Route", route)
+        .count("Requests", 1)
+        .metric("RequestLatency", started.elapsed().as_secs_f64() * 1000.0, Unit::Milliseconds)
+        .metric("PayloadBytes", payload_bytes as f64, Unit::Bytes)
+        .emit();
+    if response.status().as_u16() >= 400 {
+        MetricSet::new(&state.config.metrics)
+            .dimension("Route", route)
+            .dimension("Status", response.status().as_str())
+            .count("RejectedRequests", 1)
+            .emit();
+    }
+
+    if state.config.cold_start_tracking {
+        let timing = server_timing(cold_start, started.elapsed().as_secs_f64() * 1000.0);
+        if let Ok(value) = timing.parse() {
+            response.headers_mut().insert("Server-Timing", value);
+        }
+    }
+
+    Ok(request_id::stamp(response, &request_id))
+}
+
+/// Formats the `Server-Timing` header value
+fn server_timing(cold_start: bool, duration_ms: f64) -> String {
+    format!(
+        "cold-start;desc=\"{}\", total;dur={:.1}",
+        cold_start, duration_ms
+    )
+}
+
+async fn catch_panics(request: Request, state: Arc<AppState>, next: HttpService) -> Result<Response<Body>, Error> {
+    // A task of its own, so the panic unwinds no further than its join handle
+    let handled = tokio::spawn(next.oneshot(request).in_current_span()).await;
+    match handled {
+        Ok(response) => response,
+        Err(e) if e.is_panic() => {
+            let panic = e.into_panic();
+            let message = panic
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("(no message)");
+            tracing::error!("Request handler panicked: {}", message);
+            MetricSet::new(&state.config.metrics).count("HandlerPanics", 1).emit();
+            Ok(create_error_response(500, "Internal server error"))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn share_store_lookups(mut request: Request, state: Arc<AppState>, next: HttpService) -> Result<Response<Body>, Error> {
+    if !state.config.store_lookups.batched {
+        return next.oneshot(request).await;
+    }
+    let lookups = Arc::new(StoreLookups::new(&state.config.store_lookups));
+    request.extensions_mut().insert(lookups.clone());
+    let response = next.oneshot(request).await;
+    // A failed request is retried, and its events shouldn't count as seen
+    if response.as_ref().is_ok_and(|response| !response.status().is_server_error()) {
+        lookups.flush(&state).await;
+    }
+    response
+}
+
+async fn negotiate(request: Request, state: Arc<AppState>, next: HttpService) -> Result<Response<Body>, Error> {
+    if !state.config.content_negotiation {
+        return next.oneshot(request).await;
+    }
+    let format = ResponseFormat::requested(&request);
+    Ok(format.apply(next.oneshot(request).await?))
+}
+
+async fn cors(request: Request, state: Arc<AppState>, next: HttpService) -> Result<Response<Body>, Error> {
+    let api_keys = &state.config.api_keys;
+    let project_origins = api_keys.enabled && api_keys.project_origins;
+
+    // Preflights carry no credentials, so they're answered before auth, and
+    // the actual request is checked instead
+    if request.method() == "OPTIONS" {
+        let response = create_response(200, serde_json::json!({}));
+        return Ok(match project_origins {
+            true => origin::with_allowed_origin(response, origin::request_origin(&request).as_deref()),
+            false => response,
+        });
+    }
+    if !project_origins {
+        return next.oneshot(request).await;
+    }
+    let allowed = auth::approved_origin(&request, &state).await?;
+    let response = next.oneshot(request).await?;
+    Ok(origin::with_allowed_origin(response, allowed.as_deref()))
+}
+
+async fn authenticate(mut request: Request, state: Arc<AppState>, next: HttpService) -> Result<Response<Body>, Error> {
+    // Signatures cover the body as sent, so they're checked before anything
+    // reads it, against the project of the (verified) bearer token
+    let verified = match jwt::verify(&mut request, &state).await {
+        Ok(()) => signing::verify(&mut request, &state.config.signing),
+        Err(e) => Err(e),
+    };
+    match verified {
+        Ok(()) => next.oneshot(request).await,
+        Err(e) => Ok(create_error_response(401, &format!("Unauthorized: {}", e))),
+    }
+}
+
+async fn quarantine_rejections(request: Request, state: Arc<AppState>, next: HttpService) -> Result<Response<Body>, Error> {
+    let Some(ref quarantine) = state.payload_quarantine else {
+        return next.oneshot(request).await;
+    };
+    let captured = RejectedPayload::capture(&request);
+    let response = next.oneshot(request).await?;
+    if let Some(rejected) = captured.rejected_by(&response) {
+        // Never kept in plaintext when it should have been encrypted
+        let written = match rejected.encrypt(&state).await {
+            Ok(rejected) => quarantine.write(&rejected).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = written {
+            tracing::warn!("Failed to quarantine a rejected payload: {}", e);
+        }
+    }
+    Ok(response)
+}
+
+async fn rate_limit_headers(mut request: Request, state: Arc<AppState>, next: HttpService) -> Result<Response<Body>, Error> {
+    let recorded = RecordedDecision::default();
+    request.extensions_mut().insert(recorded.clone());
+    let response = next.oneshot(request).await?;
+    Ok(match recorded.get() {
+        Some(decision) => crate::rate_limit::with_headers(response, &state.config.rate_limit, &decision),
+        None => response,
+    })
+}
+
+async fn limit_body(request: Request, state: Arc<AppState>, next: HttpService) -> Result<Response<Body>, Error> {
+    // Checked again on decoding and parse, but only here does an oversized
+    // body get its 413
+    let max_body_bytes = state.config.json_limits.max_body_bytes;
+    let body_bytes = match request.body() {
+        Body::Text(s) => s.len(),
+        Body::Binary(b) => b.len(),
+        Body::Empty => 0,
+    };
+    if body_bytes > max_body_bytes {
+        return Ok(create_error_response(
+            413,
+            &format!("Request body exceeds maximum size of {} bytes", max_body_bytes),
+        ));
+    }
+    next.oneshot(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::{test_state, Config};
+
+    fn panicking() -> HttpService {
+        BoxCloneService::new(service_fn(|request: Request| async move {
+            if request.uri().path() == "/panic" {
+                panic!("handler bug");
+            }
+            Ok(create_response(202, serde_json::json!({})))
+        }))
+    }
+
+    fn get(path: &str) -> Request {
+        lambda_http::http::Request::builder()
+            .method("GET")
+            .uri(path)
+            .body(Body::Empty)
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_panics_become_500s_with_a_request_id() {
+        let state = Arc::new(test_state(Config::default()));
+        let service = stack(&state, panicking());
+
+        let response = service.clone().oneshot(get("/panic")).await.unwrap();
+        assert_eq!(response.status(), 500);
+        assert!(response.headers().contains_key("x-request-id"));
+        let body: serde_json::Value = serde_json::from_slice(response.body().as_ref()).unwrap();
+        assert_eq!(body["error"], "Internal server error");
+
+        // The stack still serves requests afterwards
+        let response = service.oneshot(get("/fine")).await.unwrap();
+        assert_eq!(response.status(), 202);
+    }
+
+    #[tokio::test]
+    async fn test_store_lookups_are_written_back_unless_the_request_fails() {
+        let mut config = Config::default();
+        config.store_lookups.batched = true;
+        config.last_event_gap.enabled = true;
+        let state = Arc::new(test_state(config));
+        // Enriches one event, and answers 503 on /fail
+        let enriching = BoxCloneService::new(service_fn(|request: Request| async move {
+            let state = request.extensions().get::<Arc<AppState>>().cloned().unwrap();
+            let event = crate::models::IngestEventPayload {
+                project_id: "proj".to_string(),
+                anonymous_id: Some("anon-1".to_string()),
+                timestamp: request.uri().path().len() as i64,
+                ..Default::default()
+            };
+            crate::enrichment::apply(event, &request, &state).await;
+            let status = if request.uri().path() == "/fail" { 503 } else { 202 };
+            Ok(create_response(status, serde_json::json!({})))
+        }));
+        let service = stack(&state, enriching);
+
+        service.clone().oneshot(get("/fail")).await.unwrap();
+        assert_eq!(state.last_seen_store.get("proj#anon-1").await.unwrap(), None);
+
+        service.oneshot(get("/ok")).await.unwrap();
+        assert_eq!(state.last_seen_store.get("proj#anon-1").await.unwrap(), Some(3));
+    }
+}
@@ -1,18 +1,42 @@
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
 use std::collections::HashMap;
+use utoipa::ToSchema;
+
+use crate::enrichment::engagement::HEARTBEAT;
+use crate::limits::ErrorLimits;
+use crate::sanitize::truncate;
+use crate::validation::{invalid, ValidationErrors};
+
+// The normalized event and the tracking API's messages live in
+// `analytics-core`, shared with the Rust tracking client
+pub use analytics_core::event::{
+    AppContext, CampaignContext, Consent, DeviceContext, DeviceType, EventContext, FieldEncryption, FirstTouch,
+    GeoContext, GeoSource, IngestEventPayload, LibraryContext, NetworkType, PageContext, ScreenContext,
+};
+pub use analytics_core::message::SentAt;
 
 /// Compressed event payload (Vercel Analytics format)
-/// POST /view and POST /event both use this format
+/// POST /view and POST /event both use this format. Strings are borrowed
+/// from the request body where the parser allows it.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct CompressedEvent {
+#[derive(ToSchema)]
+pub struct CompressedEvent<'a> {
     /// Event name (e.g., "pageview", "button_clicked", "webvital")
-    pub en: String,
+    #[serde(borrow)]
+    #[schema(value_type = String)]
+    pub en: Cow<'a, str>,
     /// Unix timestamp in milliseconds
     pub ts: i64,
     /// Origin (full page URL)
-    pub o: String,
+    #[serde(borrow)]
+    #[schema(value_type = String)]
+    pub o: Cow<'a, str>,
     /// Referrer URL
-    pub r: String,
+    #[serde(borrow)]
+    #[schema(value_type = String)]
+    pub r: Cow<'a, str>,
     /// Screen width in pixels
     pub sw: u32,
     /// Screen height in pixels
@@ -20,128 +44,1218 @@ pub struct CompressedEvent {
     /// Optional event data (custom properties)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub ed: Option<HashMap<String, serde_json::Value>>,
+    /// Optional explicit discriminator ("pageview" or "track")
+    #[serde(rename = "type", borrow, default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub kind: Option<Cow<'a, str>>,
+    /// Consent the user gave
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub consent: Option<Consent>,
+    /// Client-generated id, constant across retries of the same event
+    #[serde(rename = "messageId", default, skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<String>,
+    /// Client's position of the event in its session
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<u64>,
+    /// Client clock when the event was sent, used for skew correction
+    #[serde(rename = "sentAt", default, skip_serializing_if = "Option::is_none")]
+    pub sent_at: Option<SentAt>,
+}
+
+/// Kind of event an endpoint accepts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    PageView,
+    Track,
+}
+
+impl EventKind {
+    /// Parses an explicit `type` discriminator
+    pub fn from_discriminator(value: &str) -> Option<Self> {
+        match value {
+            "pageview" | "page" | "view" => Some(Self::PageView),
+            "track" | "event" => Some(Self::Track),
+            _ => None,
+        }
+    }
+
+    /// Endpoint path the kind belongs to
+    pub fn endpoint(&self) -> &'static str {
+        match self {
+            Self::PageView => "/view",
+            Self::Track => "/event",
+        }
+    }
 }
 
-/// Internal normalized event structure
+/// CloudEvents 1.0 envelope (structured JSON mode)
+/// POST /cloudevents, or any route with `Content-Type: application/cloudevents+json`
 #[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct IngestEventPayload {
-    pub project_id: String,
+#[derive(ToSchema)]
+pub struct CloudEvent {
+    pub specversion: String,
+    /// Event type, mapped onto `event_type`
+    #[serde(rename = "type")]
     pub event_type: String,
-    pub timestamp: i64,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub user_id: Option<String>,
+    /// Producer URI, kept as `context.source`
+    pub source: String,
+    pub id: String,
+    /// RFC 3339 occurrence time
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub anonymous_id: Option<String>,
+    pub time: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub properties: Option<HashMap<String, serde_json::Value>>,
+    pub datacontenttype: Option<String>,
+    /// Event payload, mapped onto `properties`
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub context: Option<EventContext>,
+    pub data: Option<serde_json::Value>,
 }
 
-/// Event context structure
+/// Body of POST /identify: attaches traits to a user
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(ToSchema)]
 #[serde(rename_all = "camelCase")]
-pub struct EventContext {
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub page: Option<PageContext>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub user_agent: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub locale: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub screen: Option<ScreenContext>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub ip: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub received_at: Option<i64>,
-    #[serde(flatten)]
-    pub extra: HashMap<String, serde_json::Value>,
+pub struct IdentifyEvent {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anonymous_id: Option<String>,
+    /// User traits (email, plan, ...)
+    #[serde(default)]
+    pub traits: HashMap<String, serde_json::Value>,
+    /// Unix timestamp in milliseconds; server time when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub consent: Option<Consent>,
+    /// Client-generated id, constant across retries of the same event
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<String>,
+    /// Client's position of the event in its session
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<u64>,
+    /// Client clock when the event was sent, used for skew correction
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sent_at: Option<SentAt>,
 }
 
+/// Body of POST /group: associates a user with an account
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(ToSchema)]
 #[serde(rename_all = "camelCase")]
-pub struct PageContext {
-    #[serde(skip_serializing_if = "Option::is_none")]
+pub struct GroupEvent {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anonymous_id: Option<String>,
+    /// Account (company, workspace, ...) id
+    pub group_id: String,
+    /// Account traits (name, plan, employees, ...)
+    #[serde(default)]
+    pub traits: HashMap<String, serde_json::Value>,
+    /// Unix timestamp in milliseconds; server time when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub consent: Option<Consent>,
+    /// Client-generated id, constant across retries of the same event
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<String>,
+    /// Client's position of the event in its session
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<u64>,
+    /// Client clock when the event was sent, used for skew correction
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sent_at: Option<SentAt>,
+}
+
+/// Body of POST /alias: links a previous (usually anonymous) id to a user
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AliasEvent {
+    /// Id the user was known by before, e.g. the pre-login anonymousId
+    pub previous_id: String,
+    pub user_id: String,
+    /// Unix timestamp in milliseconds; server time when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub consent: Option<Consent>,
+    /// Client-generated id, constant across retries of the same event
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<String>,
+    /// Client's position of the event in its session
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<u64>,
+    /// Client clock when the event was sent, used for skew correction
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sent_at: Option<SentAt>,
+}
+
+/// Body of POST /vitals: one Core Web Vitals measurement, as reported by
+/// the `web-vitals` library
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct WebVitalEvent {
+    pub name: WebVitalMetric,
+    /// Milliseconds, or unitless for CLS
+    pub value: f64,
+    /// Derived from the metric's thresholds when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rating: Option<WebVitalRating>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub navigation_type: Option<NavigationType>,
+    /// Page the metric was measured on
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anonymous_id: Option<String>,
+    /// Unix timestamp in milliseconds; server time when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub consent: Option<Consent>,
+    /// Client-generated id, constant across retries of the same event
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<String>,
+    /// Client's position of the event in its session
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<u64>,
+    /// Client clock when the event was sent, used for skew correction
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sent_at: Option<SentAt>,
+}
+
+/// Web Vitals metric
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(ToSchema)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum WebVitalMetric {
+    Lcp,
+    Cls,
+    Inp,
+    Ttfb,
+}
+
+impl WebVitalMetric {
+    /// Upper bounds of "good" and "needs-improvement", per web.dev
+    fn thresholds(&self) -> (f64, f64) {
+        match self {
+            Self::Lcp => (2500.0, 4000.0),
+            Self::Cls => (0.1, 0.25),
+            Self::Inp => (200.0, 500.0),
+            Self::Ttfb => (800.0, 1800.0),
+        }
+    }
+
+    /// Rates a value against the metric's thresholds
+    pub fn rate(&self, value: f64) -> WebVitalRating {
+        let (good, needs_improvement) = self.thresholds();
+        if value <= good {
+            WebVitalRating::Good
+        } else if value <= needs_improvement {
+            WebVitalRating::NeedsImprovement
+        } else {
+            WebVitalRating::Poor
+        }
+    }
+}
+
+/// How a Web Vitals value compares to the metric's thresholds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum WebVitalRating {
+    Good,
+    NeedsImprovement,
+    Poor,
+}
+
+/// How the page was reached
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum NavigationType {
+    Navigate,
+    Reload,
+    BackForward,
+    BackForwardCache,
+    Prerender,
+    Restore,
+}
+
+/// Body of POST /errors: an uncaught frontend error
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorEvent {
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stack: Option<String>,
+    /// Where the error was thrown
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<ErrorSource>,
+    /// Version of the app that threw, to match source maps and deploys
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub release: Option<String>,
+    /// What happened before the error, oldest first
+    #[serde(default)]
+    pub breadcrumbs: Vec<Breadcrumb>,
+    /// Page the error happened on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub url: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub title: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub path: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub referrer: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anonymous_id: Option<String>,
+    /// Unix timestamp in milliseconds; server time when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub consent: Option<Consent>,
+    /// Client-generated id, constant across retries of the same event
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<String>,
+    /// Client's position of the event in its session
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<u64>,
+    /// Client clock when the event was sent, used for skew correction
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sent_at: Option<SentAt>,
+}
+
+/// Script location of an error
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(ToSchema)]
+pub struct ErrorSource {
+    pub file: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub line: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub column: Option<u32>,
+}
+
+/// One step leading up to an error (a click, a request, a log line)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(ToSchema)]
+pub struct Breadcrumb {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+    pub message: String,
+    /// Unix timestamp in milliseconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<HashMap<String, serde_json::Value>>,
 }
 
+/// Body of POST /heartbeat: a ping the SDK sends every few seconds while
+/// a page is open
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(ToSchema)]
 #[serde(rename_all = "camelCase")]
-pub struct ScreenContext {
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub width: Option<u32>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub height: Option<u32>,
+pub struct HeartbeatEvent {
+    pub session_id: String,
+    /// Page the session is on
+    pub url: String,
+    /// Whether the user was active (page visible and focused) since the
+    /// previous ping; open but idle pages still count toward time on page
+    #[serde(default = "engaged_by_default")]
+    pub engaged: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anonymous_id: Option<String>,
+    /// Unix timestamp in milliseconds; server time when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<i64>,
+    /// Client clock when the event was sent, used for skew correction
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sent_at: Option<SentAt>,
+}
+
+fn engaged_by_default() -> bool {
+    true
+}
+
+/// Body of POST /click: an auto-captured click on a page element
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ClickEvent {
+    pub session_id: String,
+    /// Page the click was on
+    pub url: String,
+    /// CSS selector of the clicked element
+    pub selector: String,
+    /// The element's text; only its hash is kept
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    /// Link target, for anchors
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub href: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anonymous_id: Option<String>,
+    /// Unix timestamp in milliseconds; server time when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<i64>,
+    /// Client clock when the event was sent, used for skew correction
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sent_at: Option<SentAt>,
+}
+
+/// Body of POST /scroll: how far down a page the session has scrolled
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ScrollDepthEvent {
+    pub session_id: String,
+    /// Page being scrolled
+    pub url: String,
+    /// Share of the page seen so far, 0 to 100
+    pub percent: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anonymous_id: Option<String>,
+    /// Unix timestamp in milliseconds; server time when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<i64>,
+    /// Client clock when the event was sent, used for skew correction
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sent_at: Option<SentAt>,
+}
+
+/// Scroll depths reported, in percent; a scroll counts toward the deepest
+/// bucket it reached
+pub const SCROLL_DEPTH_BUCKETS: [u8; 5] = [0, 25, 50, 75, 100];
+
+/// Longest CSS selector kept on a click
+const MAX_SELECTOR_LENGTH: usize = 1024;
+
+/// Body of POST /exposure: a user was shown a variant of an experiment
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ExposureEvent {
+    pub experiment_key: String,
+    pub variant: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anonymous_id: Option<String>,
+    /// Session exposures are deduplicated in; the user's id when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    /// Unix timestamp in milliseconds; server time when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub consent: Option<Consent>,
+    /// Client clock when the event was sent, used for skew correction
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sent_at: Option<SentAt>,
+}
+
+/// Body of POST /screen: a mobile app screen view
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ScreenEvent {
+    /// Screen name, e.g. `Checkout`
+    pub name: String,
+    #[serde(default)]
+    pub properties: HashMap<String, serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anonymous_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app: Option<AppContext>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+    /// IANA timezone of the device
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub screen: Option<ScreenContext>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub library: Option<LibraryContext>,
+    /// Unix timestamp in milliseconds; server time when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub consent: Option<Consent>,
+    /// Client-generated id, constant across retries of the same event
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<String>,
+    /// Client's position of the event in its session
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<u64>,
+    /// Client clock when the event was sent, used for skew correction
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sent_at: Option<SentAt>,
+}
+
+/// Body of POST /batch: a bare array of compressed events, or an SDK
+/// envelope wrapping them. Events stay raw so one bad event doesn't fail
+/// the whole batch.
+#[derive(Debug, Clone, Deserialize)]
+#[derive(ToSchema)]
+#[serde(untagged)]
+pub enum BatchBody {
+    Events(Vec<serde_json::Value>),
+    Envelope(BatchEnvelope),
 }
 
-impl CompressedEvent {
+/// `{"events": [...], "sentAt": ..., "writeKey": ...}` envelope
+#[derive(Debug, Clone, Deserialize)]
+#[derive(ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchEnvelope {
+    pub events: Vec<serde_json::Value>,
+    /// Client clock when the batch was sent, used for skew correction
+    #[serde(default)]
+    pub sent_at: Option<SentAt>,
+    /// Credentials for SDKs that can't set an Authorization header
+    #[serde(default)]
+    pub write_key: Option<String>,
+}
+
+/// Unwrapped batch contents
+#[derive(Debug, Clone, Default)]
+pub struct Batch {
+    pub events: Vec<serde_json::Value>,
+    pub sent_at: Option<i64>,
+    pub write_key: Option<String>,
+}
+
+impl CompressedEvent<'_> {
     /// Validates that the event has required fields
-    pub fn validate(&self) -> Result<(), String> {
+    pub fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
         if self.en.is_empty() {
-            return Err("en (event name) is required".to_string());
+            errors.add("en", "required", "en (event name) is required");
         }
         if self.o.is_empty() {
-            return Err("o (origin) is required".to_string());
+            errors.add("o", "required", "o (origin) is required");
         }
-        Ok(())
+        errors.extend(validate_sent_at(&self.sent_at));
+        errors.into_result()
     }
 
-    /// Normalizes to internal event format
-    /// Note: project_id should be extracted from JWT token, not payload
-    pub fn normalize(&self, project_id: String, user_id: Option<String>) -> IngestEventPayload {
-        let mut properties = HashMap::new();
+    /// Validates that the client clock produced a usable timestamp
+    pub fn validate_timestamp(&self) -> Result<(), ValidationErrors> {
+        validate_timestamp("ts", Some(self.ts))
+    }
 
-        // Add URL and referrer to properties
-        properties.insert("url".to_string(), serde_json::json!(self.o));
-        if !self.r.is_empty() {
-            properties.insert("referrer".to_string(), serde_json::json!(self.r));
+    /// Validates that an explicit `type` discriminator agrees with the endpoint
+    pub fn validate_kind(&self, endpoint: EventKind) -> Result<(), ValidationErrors> {
+        match self.kind.as_deref() {
+            None => Ok(()),
+            Some(kind) if EventKind::from_discriminator(kind) == Some(endpoint) => Ok(()),
+            Some(kind) => invalid(
+                "type",
+                "endpoint_mismatch",
+                format!("type \"{}\" does not match the {} endpoint", kind, endpoint.endpoint()),
+            ),
         }
+    }
 
-        // Add screen dimensions to properties
-        properties.insert("screen_width".to_string(), serde_json::json!(self.sw));
-        properties.insert("screen_height".to_string(), serde_json::json!(self.sh));
+    /// Normalizes to internal event format, moving the event's strings and
+    /// data into the payload rather than copying them
+    /// Note: project_id should be extracted from JWT token, not payload
+    pub fn normalize(self, project_id: String, user_id: Option<String>) -> IngestEventPayload {
+        let timestamp = skew_corrected(self.ts, &self.sent_at);
+        let url = self.o.into_owned();
+        let referrer = (!self.r.is_empty()).then(|| self.r.into_owned());
 
-        // Merge in custom event data if present
-        if let Some(ref ed) = self.ed {
-            for (key, value) in ed {
-                properties.insert(key.clone(), value.clone());
-            }
+        // URL, referrer and screen dimensions, unless custom event data
+        // already sets them
+        let mut properties = self.ed.unwrap_or_default();
+        properties.reserve(4);
+        properties.entry("url".to_string()).or_insert_with(|| url.clone().into());
+        if let Some(ref referrer) = referrer {
+            properties.entry("referrer".to_string()).or_insert_with(|| referrer.clone().into());
         }
+        properties.entry("screen_width".to_string()).or_insert(self.sw.into());
+        properties.entry("screen_height".to_string()).or_insert(self.sh.into());
 
         // Build context with screen info
         let context = EventContext {
             page: Some(PageContext {
-                url: Some(self.o.clone()),
+                url: Some(url),
                 title: None,
                 path: None,
-                referrer: if !self.r.is_empty() { Some(self.r.clone()) } else { None },
+                referrer,
             }),
             user_agent: None, // Will be set from HTTP header
             locale: None,
+            timezone: None,
             screen: Some(ScreenContext {
                 width: Some(self.sw),
                 height: Some(self.sh),
             }),
             ip: None,         // Will be set from HTTP header
             received_at: None, // Will be set by handler
+            library: None,
+            device: None,
+            app: None,
+            geo: None,
+            is_bot: None,
+            campaign: None,
             extra: HashMap::new(),
         };
 
         IngestEventPayload {
             project_id,
-            event_type: self.en.clone(),
-            timestamp: self.ts,
+            event_type: self.en.into_owned(),
+            timestamp,
             user_id,
             anonymous_id: None, // No longer used
             properties: Some(properties),
             context: Some(context),
+            consent: self.consent,
+            message_id: self.message_id,
+            sequence: self.sequence,
+            ..Default::default()
+        }
+    }
+}
+
+/// Checks that a client timestamp, when sent, is a usable one; absent
+/// timestamps are server time
+pub fn validate_timestamp(field: &str, timestamp: Option<i64>) -> Result<(), ValidationErrors> {
+    match timestamp {
+        Some(timestamp) if timestamp <= 0 => invalid(
+            field,
+            "invalid_timestamp",
+            format!("{} must be a positive epoch timestamp, got {}", field, timestamp),
+        ),
+        _ => Ok(()),
+    }
+}
+
+/// Checks that an optional `sentAt` parses
+fn validate_sent_at(sent_at: &Option<SentAt>) -> Result<(), ValidationErrors> {
+    match sent_at.as_ref().map(SentAt::millis) {
+        Some(Err(message)) => invalid("sentAt", "invalid_timestamp", message),
+        _ => Ok(()),
+    }
+}
+
+/// Checks that an event names its user one way or the other
+fn validate_ids(user_id: &Option<String>, anonymous_id: &Option<String>, errors: &mut ValidationErrors) {
+    let present = |id: &Option<String>| id.as_deref().is_some_and(|id| !id.trim().is_empty());
+    if !present(user_id) && !present(anonymous_id) {
+        errors.add("userId", "required", "userId or anonymousId is required");
+    }
+}
+
+/// A client timestamp corrected for the skew `sent_at` reveals. Missing
+/// (non-positive) timestamps stay missing for the handler to default.
+fn skew_corrected(timestamp: i64, sent_at: &Option<SentAt>) -> i64 {
+    match sent_at.as_ref().and_then(|sent_at| sent_at.skew().ok()) {
+        Some(skew) if timestamp > 0 => timestamp + skew,
+        _ => timestamp,
+    }
+}
+
+impl BatchBody {
+    /// Unwraps the batch; envelopes are refused unless `allow_envelope`
+    pub fn unwrap(self, allow_envelope: bool) -> Result<Batch, String> {
+        match self {
+            Self::Events(events) => Ok(Batch {
+                events,
+                ..Default::default()
+            }),
+            Self::Envelope(_) if !allow_envelope => {
+                Err("Batch must be a JSON array of events".to_string())
+            }
+            Self::Envelope(envelope) => Ok(Batch {
+                events: envelope.events,
+                sent_at: envelope.sent_at.map(|sent_at| sent_at.millis()).transpose()?,
+                write_key: envelope.write_key.filter(|key| !key.is_empty()),
+            }),
+        }
+    }
+}
+
+impl CloudEvent {
+    /// Validates the envelope against the CloudEvents 1.0 required attributes
+    pub fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        if self.specversion != "1.0" {
+            errors.add(
+                "specversion",
+                "unsupported",
+                format!("Unsupported CloudEvents specversion: {}", self.specversion),
+            );
+        }
+        if self.event_type.is_empty() {
+            errors.add("type", "required", "type is required");
+        }
+        if self.source.is_empty() {
+            errors.add("source", "required", "source is required");
+        }
+        if self.id.is_empty() {
+            errors.add("id", "required", "id is required");
+        }
+        if let Some(ref time) = self.time {
+            if chrono::DateTime::parse_from_rfc3339(time).is_err() {
+                errors.add(
+                    "time",
+                    "invalid_timestamp",
+                    format!("time is not a valid RFC 3339 timestamp: {}", time),
+                );
+            }
+        }
+        if self.data.as_ref().is_some_and(|data| !data.is_object() && !data.is_null()) {
+            errors.add("data", "invalid_type", "data must be a JSON object");
+        }
+        errors.into_result()
+    }
+
+    /// `time` in epoch milliseconds, when sent as RFC 3339
+    pub fn time_millis(&self) -> Option<i64> {
+        self.time
+            .as_deref()
+            .and_then(|time| chrono::DateTime::parse_from_rfc3339(time).ok())
+            .map(|time| time.timestamp_millis())
+    }
+
+    /// Normalizes to internal event format
+    /// Note: project_id should be extracted from JWT token, not payload
+    pub fn normalize(&self, project_id: String, user_id: Option<String>) -> IngestEventPayload {
+        let properties = match self.data {
+            Some(serde_json::Value::Object(ref data)) => data.clone().into_iter().collect(),
+            _ => HashMap::new(),
+        };
+
+        let timestamp = self.time_millis().unwrap_or(0); // Will be set by handler
+
+        let mut extra = HashMap::new();
+        extra.insert("source".to_string(), serde_json::json!(self.source));
+        extra.insert("cloudEventId".to_string(), serde_json::json!(self.id));
+
+        IngestEventPayload {
+            project_id,
+            event_type: self.event_type.clone(),
+            timestamp,
+            user_id,
+            properties: Some(properties),
+            message_id: Some(self.id.clone()),
+            context: Some(EventContext {
+                extra,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+}
+
+impl IdentifyEvent {
+    /// Validates the identify call
+    pub fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        validate_ids(&self.user_id, &self.anonymous_id, &mut errors);
+        if self.traits.keys().any(|key| key.is_empty()) {
+            errors.add("traits", "empty_key", "Trait names must not be empty");
+        }
+        errors.extend(validate_sent_at(&self.sent_at));
+        errors.into_result()
+    }
+
+    /// Normalizes to internal event format. The body's `userId` wins over
+    /// the token's.
+    /// Note: project_id should be extracted from JWT token, not payload
+    pub fn normalize(&self, project_id: String, user_id: Option<String>) -> IngestEventPayload {
+        let non_empty = |id: &Option<String>| id.clone().filter(|id| !id.trim().is_empty());
+        IngestEventPayload {
+            project_id,
+            event_type: "identify".to_string(),
+            timestamp: skew_corrected(self.timestamp.unwrap_or(0), &self.sent_at), // 0 is set by handler
+            user_id: non_empty(&self.user_id).or(user_id),
+            anonymous_id: non_empty(&self.anonymous_id),
+            traits: Some(self.traits.clone()),
+            consent: self.consent.clone(),
+            message_id: self.message_id.clone(),
+            sequence: self.sequence,
+            ..Default::default()
+        }
+    }
+}
+
+impl GroupEvent {
+    /// Validates the group call
+    pub fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        validate_ids(&self.user_id, &self.anonymous_id, &mut errors);
+        if self.group_id.trim().is_empty() {
+            errors.add("groupId", "required", "groupId is required");
+        }
+        if self.traits.keys().any(|key| key.is_empty()) {
+            errors.add("traits", "empty_key", "Trait names must not be empty");
+        }
+        errors.extend(validate_sent_at(&self.sent_at));
+        errors.into_result()
+    }
+
+    /// Normalizes to internal event format, with the account's traits as
+    /// `traits`. The body's `userId` wins over the token's.
+    /// Note: project_id should be extracted from JWT token, not payload
+    pub fn normalize(&self, project_id: String, user_id: Option<String>) -> IngestEventPayload {
+        let non_empty = |id: &Option<String>| id.clone().filter(|id| !id.trim().is_empty());
+        IngestEventPayload {
+            project_id,
+            event_type: "group".to_string(),
+            timestamp: skew_corrected(self.timestamp.unwrap_or(0), &self.sent_at), // 0 is set by handler
+            user_id: non_empty(&self.user_id).or(user_id),
+            anonymous_id: non_empty(&self.anonymous_id),
+            group_id: Some(self.group_id.trim().to_string()),
+            traits: Some(self.traits.clone()),
+            consent: self.consent.clone(),
+            message_id: self.message_id.clone(),
+            sequence: self.sequence,
+            ..Default::default()
+        }
+    }
+}
+
+impl AliasEvent {
+    /// Validates the alias call
+    pub fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        if self.previous_id.trim().is_empty() {
+            errors.add("previousId", "required", "previousId is required");
+        }
+        if self.user_id.trim().is_empty() {
+            errors.add("userId", "required", "userId is required");
+        } else if self.previous_id.trim() == self.user_id.trim() {
+            errors.add("userId", "conflict", "previousId and userId must differ");
+        }
+        errors.extend(validate_sent_at(&self.sent_at));
+        errors.into_result()
+    }
+
+    /// Normalizes to internal event format. Both ids come from the body;
+    /// aliasing is explicit, so the token's user isn't used.
+    /// Note: project_id should be extracted from JWT token, not payload
+    pub fn normalize(&self, project_id: String) -> IngestEventPayload {
+        IngestEventPayload {
+            project_id,
+            event_type: "alias".to_string(),
+            timestamp: skew_corrected(self.timestamp.unwrap_or(0), &self.sent_at), // 0 is set by handler
+            user_id: Some(self.user_id.trim().to_string()),
+            previous_id: Some(self.previous_id.trim().to_string()),
+            consent: self.consent.clone(),
+            message_id: self.message_id.clone(),
+            sequence: self.sequence,
+            ..Default::default()
+        }
+    }
+}
+
+impl WebVitalEvent {
+    /// Validates the measurement
+    pub fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        if !self.value.is_finite() || self.value < 0.0 {
+            errors.add("value", "out_of_range", "value must be a non-negative number");
+        }
+        if url::Url::parse(&self.url).is_err() {
+            errors.add("url", "invalid_url", "url must be an absolute URL");
+        }
+        errors.extend(validate_sent_at(&self.sent_at));
+        errors.into_result()
+    }
+
+    /// Normalizes to a `web_vital` event, with the measurement as
+    /// properties and the page as `context.page`
+    /// Note: project_id should be extracted from JWT token, not payload
+    pub fn normalize(&self, project_id: String, user_id: Option<String>) -> IngestEventPayload {
+        let rating = self.rating.unwrap_or_else(|| self.name.rate(self.value));
+        let mut properties = HashMap::from([
+            ("metric".to_string(), serde_json::json!(self.name)),
+            ("value".to_string(), serde_json::json!(self.value)),
+            ("rating".to_string(), serde_json::json!(rating)),
+            ("url".to_string(), serde_json::json!(self.url)),
+        ]);
+        if let Some(navigation_type) = self.navigation_type {
+            properties.insert("navigation_type".to_string(), serde_json::json!(navigation_type));
+        }
+
+        IngestEventPayload {
+            project_id,
+            event_type: "web_vital".to_string(),
+            timestamp: skew_corrected(self.timestamp.unwrap_or(0), &self.sent_at), // 0 is set by handler
+            user_id,
+            anonymous_id: self.anonymous_id.clone().filter(|id| !id.trim().is_empty()),
+            properties: Some(properties),
+            context: Some(EventContext {
+                page: Some(PageContext {
+                    url: Some(self.url.clone()),
+                    path: url::Url::parse(&self.url).ok().map(|url| url.path().to_string()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            consent: self.consent.clone(),
+            message_id: self.message_id.clone(),
+            sequence: self.sequence,
+            ..Default::default()
+        }
+    }
+}
+
+impl ErrorEvent {
+    /// Validates the error report
+    pub fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        if self.message.trim().is_empty() {
+            errors.add("message", "required", "message is required");
+        }
+        if self.source.as_ref().is_some_and(|source| source.file.trim().is_empty()) {
+            errors.add("source.file", "required", "source.file must not be empty");
+        }
+        errors.extend(validate_sent_at(&self.sent_at));
+        errors.into_result()
+    }
+
+    /// Cuts the report down to `limits`. Returns whether anything was cut.
+    pub fn truncate(&mut self, limits: &ErrorLimits) -> bool {
+        let cut = |value: &mut String, max_bytes: usize| {
+            let before = value.len();
+            truncate(value, max_bytes);
+            value.len() < before
+        };
+
+        let mut truncated = cut(&mut self.message, limits.max_message_bytes);
+        if let Some(ref mut stack) = self.stack {
+            truncated |= cut(stack, limits.max_stack_bytes);
+        }
+        if self.breadcrumbs.len() > limits.max_breadcrumbs {
+            self.breadcrumbs.drain(..self.breadcrumbs.len() - limits.max_breadcrumbs);
+            truncated = true;
+        }
+        for breadcrumb in &mut self.breadcrumbs {
+            truncated |= cut(&mut breadcrumb.message, limits.max_message_bytes);
+        }
+        truncated
+    }
+
+    /// Normalizes to an `error` event, with the report as properties and
+    /// the page, when known, as `context.page`. Reports over `limits` are
+    /// truncated and flagged `truncated: true`.
+    /// Note: project_id should be extracted from JWT token, not payload
+    pub fn normalize(mut self, project_id: String, user_id: Option<String>, limits: &ErrorLimits) -> IngestEventPayload {
+        let truncated = self.truncate(limits);
+        let mut properties = HashMap::from([("message".to_string(), serde_json::json!(self.message))]);
+        if let Some(ref stack) = self.stack {
+            properties.insert("stack".to_string(), serde_json::json!(stack));
+        }
+        if let Some(ref source) = self.source {
+            properties.insert("source_file".to_string(), serde_json::json!(source.file));
+            if let Some(line) = source.line {
+                properties.insert("source_line".to_string(), serde_json::json!(line));
+            }
+            if let Some(column) = source.column {
+                properties.insert("source_column".to_string(), serde_json::json!(column));
+            }
+        }
+        if let Some(ref release) = self.release {
+            properties.insert("release".to_string(), serde_json::json!(release));
+        }
+        if !self.breadcrumbs.is_empty() {
+            properties.insert("breadcrumbs".to_string(), serde_json::json!(self.breadcrumbs));
+        }
+        if let Some(ref url) = self.url {
+            properties.insert("url".to_string(), serde_json::json!(url));
+        }
+        if truncated {
+            properties.insert("truncated".to_string(), serde_json::json!(true));
+        }
+
+        let context = self.url.as_ref().map(|url| EventContext {
+            page: Some(PageContext {
+                url: Some(url.clone()),
+                path: url::Url::parse(url).ok().map(|url| url.path().to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+
+        IngestEventPayload {
+            project_id,
+            event_type: "error".to_string(),
+            timestamp: skew_corrected(self.timestamp.unwrap_or(0), &self.sent_at), // 0 is set by handler
+            user_id,
+            anonymous_id: self.anonymous_id.filter(|id| !id.trim().is_empty()),
+            properties: Some(properties),
+            context,
+            consent: self.consent,
+            message_id: self.message_id,
+            sequence: self.sequence,
+            ..Default::default()
+        }
+    }
+}
+
+impl HeartbeatEvent {
+    /// Validates the ping
+    pub fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        if self.session_id.trim().is_empty() {
+            errors.add("sessionId", "required", "sessionId is required");
+        }
+        if url::Url::parse(&self.url).is_err() {
+            errors.add("url", "invalid_url", "url must be an absolute URL");
+        }
+        errors.extend(validate_sent_at(&self.sent_at));
+        errors.into_result()
+    }
+
+    /// Normalizes to a `heartbeat` event, with the session and page as
+    /// properties and the page as `context.page`
+    /// Note: project_id should be extracted from JWT token, not payload
+    pub fn normalize(&self, project_id: String, user_id: Option<String>) -> IngestEventPayload {
+        let properties = HashMap::from([
+            ("session_id".to_string(), serde_json::json!(self.session_id.trim())),
+            ("url".to_string(), serde_json::json!(self.url)),
+            ("engaged".to_string(), serde_json::json!(self.engaged)),
+        ]);
+
+        IngestEventPayload {
+            project_id,
+            event_type: HEARTBEAT.to_string(),
+            timestamp: skew_corrected(self.timestamp.unwrap_or(0), &self.sent_at), // 0 is set by handler
+            user_id,
+            anonymous_id: self.anonymous_id.clone().filter(|id| !id.trim().is_empty()),
+            properties: Some(properties),
+            context: Some(EventContext {
+                page: Some(PageContext {
+                    url: Some(self.url.clone()),
+                    path: url::Url::parse(&self.url).ok().map(|url| url.path().to_string()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+}
+
+impl ClickEvent {
+    /// Validates the click
+    pub fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        if self.session_id.trim().is_empty() {
+            errors.add("sessionId", "required", "sessionId is required");
+        }
+        if url::Url::parse(&self.url).is_err() {
+            errors.add("url", "invalid_url", "url must be an absolute URL");
+        }
+        if self.selector.trim().is_empty() {
+            errors.add("selector", "required", "selector is required");
+        } else if self.selector.len() > MAX_SELECTOR_LENGTH {
+            errors.add(
+                "selector",
+                "too_long",
+                format!("selector must be at most {} bytes", MAX_SELECTOR_LENGTH),
+            );
+        }
+        errors.extend(validate_sent_at(&self.sent_at));
+        errors.into_result()
+    }
+
+    /// Normalizes to a `click` event, with the session, page, selector,
+    /// `href` and a hash of the element's text as properties
+    /// Note: project_id should be extracted from JWT token, not payload
+    pub fn normalize(&self, project_id: String, user_id: Option<String>) -> IngestEventPayload {
+        let mut properties = HashMap::from([
+            ("session_id".to_string(), serde_json::json!(self.session_id.trim())),
+            ("url".to_string(), serde_json::json!(self.url)),
+            ("selector".to_string(), serde_json::json!(self.selector.trim())),
+        ]);
+        if let Some(text) = self.text.as_deref().map(str::trim).filter(|text| !text.is_empty()) {
+            let digest = Sha256::digest(text.as_bytes());
+            properties.insert("text_hash".to_string(), serde_json::json!(hex::encode(&digest[..16])));
+        }
+        if let Some(href) = self.href.as_deref().map(str::trim).filter(|href| !href.is_empty()) {
+            properties.insert("href".to_string(), serde_json::json!(href));
+        }
+
+        IngestEventPayload {
+            project_id,
+            event_type: "click".to_string(),
+            timestamp: skew_corrected(self.timestamp.unwrap_or(0), &self.sent_at), // 0 is set by handler
+            user_id,
+            anonymous_id: self.anonymous_id.clone().filter(|id| !id.trim().is_empty()),
+            properties: Some(properties),
+            context: Some(EventContext {
+                page: Some(PageContext {
+                    url: Some(self.url.clone()),
+                    path: url::Url::parse(&self.url).ok().map(|url| url.path().to_string()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+}
+
+impl ScrollDepthEvent {
+    /// Validates the scroll report
+    pub fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        if self.session_id.trim().is_empty() {
+            errors.add("sessionId", "required", "sessionId is required");
+        }
+        if url::Url::parse(&self.url).is_err() {
+            errors.add("url", "invalid_url", "url must be an absolute URL");
+        }
+        if !(0.0..=100.0).contains(&self.percent) {
+            errors.add("percent", "out_of_range", "percent must be between 0 and 100");
+        }
+        errors.extend(validate_sent_at(&self.sent_at));
+        errors.into_result()
+    }
+
+    /// The deepest bucket the scroll reached
+    pub fn bucket(&self) -> u8 {
+        SCROLL_DEPTH_BUCKETS
+            .into_iter()
+            .rev()
+            .find(|bucket| self.percent >= f64::from(*bucket))
+            .unwrap_or(0)
+    }
+
+    /// Normalizes to a `scroll_depth` event, with the session, page and
+    /// depth bucket as properties
+    /// Note: project_id should be extracted from JWT token, not payload
+    pub fn normalize(&self, project_id: String, user_id: Option<String>) -> IngestEventPayload {
+        let properties = HashMap::from([
+            ("session_id".to_string(), serde_json::json!(self.session_id.trim())),
+            ("url".to_string(), serde_json::json!(self.url)),
+            ("depth".to_string(), serde_json::json!(self.bucket())),
+        ]);
+
+        IngestEventPayload {
+            project_id,
+            event_type: "scroll_depth".to_string(),
+            timestamp: skew_corrected(self.timestamp.unwrap_or(0), &self.sent_at), // 0 is set by handler
+            user_id,
+            anonymous_id: self.anonymous_id.clone().filter(|id| !id.trim().is_empty()),
+            properties: Some(properties),
+            context: Some(EventContext {
+                page: Some(PageContext {
+                    url: Some(self.url.clone()),
+                    path: url::Url::parse(&self.url).ok().map(|url| url.path().to_string()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+}
+
+impl ExposureEvent {
+    /// Validates the exposure
+    pub fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        validate_ids(&self.user_id, &self.anonymous_id, &mut errors);
+        if self.experiment_key.trim().is_empty() {
+            errors.add("experimentKey", "required", "experimentKey is required");
+        }
+        if self.variant.trim().is_empty() {
+            errors.add("variant", "required", "variant is required");
+        }
+        errors.extend(validate_sent_at(&self.sent_at));
+        errors.into_result()
+    }
+
+    /// Normalizes to an `exposure` event. Its `messageId` is derived from
+    /// the experiment, variant and session, so with `MESSAGE_DEDUP_ENABLED`
+    /// only a session's first exposure to a variant is written. The body's
+    /// `userId` wins over the token's.
+    /// Note: project_id should be extracted from JWT token, not payload
+    pub fn normalize(&self, project_id: String, user_id: Option<String>) -> IngestEventPayload {
+        let non_empty = |id: &Option<String>| id.as_deref().map(str::trim).filter(|id| !id.is_empty()).map(String::from);
+        let user_id = non_empty(&self.user_id).or(user_id);
+        let anonymous_id = non_empty(&self.anonymous_id);
+        let session_id = non_empty(&self.session_id);
+        let experiment_key = self.experiment_key.trim();
+        let variant = self.variant.trim();
+
+        let session = session_id.as_deref().or(anonymous_id.as_deref()).or(user_id.as_deref()).unwrap_or_default();
+        let digest = Sha256::new()
+            .chain_update(experiment_key.as_bytes())
+            .chain_update(b"\0")
+            .chain_update(variant.as_bytes())
+            .chain_update(b"\0")
+            .chain_update(session.as_bytes())
+            .finalize();
+
+        let mut properties = HashMap::from([
+            ("experiment_key".to_string(), serde_json::json!(experiment_key)),
+            ("variant".to_string(), serde_json::json!(variant)),
+        ]);
+        if let Some(ref session_id) = session_id {
+            properties.insert("session_id".to_string(), serde_json::json!(session_id));
+        }
+
+        IngestEventPayload {
+            project_id,
+            event_type: "exposure".to_string(),
+            timestamp: skew_corrected(self.timestamp.unwrap_or(0), &self.sent_at), // 0 is set by handler
+            user_id,
+            anonymous_id,
+            properties: Some(properties),
+            consent: self.consent.clone(),
+            message_id: Some(format!("exposure-{}", hex::encode(&digest[..16]))),
+            ..Default::default()
+        }
+    }
+}
+
+impl ScreenEvent {
+    /// Validates the screen view
+    pub fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        validate_ids(&self.user_id, &self.anonymous_id, &mut errors);
+        if self.name.trim().is_empty() {
+            errors.add("name", "required", "name is required");
+        }
+        if self.properties.keys().any(|key| key.is_empty()) {
+            errors.add("properties", "empty_key", "Property names must not be empty");
+        }
+        errors.extend(validate_sent_at(&self.sent_at));
+        errors.into_result()
+    }
+
+    /// Normalizes to a `screen` event, with the name as `screen_name` and
+    /// the app in `context.app`. The body's `userId` wins over the token's.
+    /// Note: project_id should be extracted from JWT token, not payload
+    pub fn normalize(&self, project_id: String, user_id: Option<String>) -> IngestEventPayload {
+        let non_empty = |id: &Option<String>| id.clone().filter(|id| !id.trim().is_empty());
+        let mut properties = self.properties.clone();
+        properties.insert("screen_name".to_string(), serde_json::json!(self.name.trim()));
+
+        IngestEventPayload {
+            project_id,
+            event_type: "screen".to_string(),
+            timestamp: skew_corrected(self.timestamp.unwrap_or(0), &self.sent_at), // 0 is set by handler
+            user_id: non_empty(&self.user_id).or(user_id),
+            anonymous_id: non_empty(&self.anonymous_id),
+            properties: Some(properties),
+            context: Some(EventContext {
+                app: self.app.clone(),
+                locale: self.locale.clone(),
+                timezone: self.timezone.clone(),
+                screen: self.screen.clone(),
+                library: self.library.clone(),
+                ..Default::default()
+            }),
+            consent: self.consent.clone(),
+            message_id: self.message_id.clone(),
+            sequence: self.sequence,
+            ..Default::default()
         }
     }
 }
@@ -206,4 +1320,494 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_deserialize_cloud_event() {
+        let json = r#"{
+  "specversion": "1.0",
+  "type": "com.partner.order.created",
+  "source": "https://partner.example.com/orders",
+  "id": "A234-1234-1234",
+  "time": "2026-01-02T10:00:00Z",
+  "datacontenttype": "application/json",
+  "data": {
+    "orderId": "o-42",
+    "total": 1999
+  }
+}"#;
+
+        let event: CloudEvent = serde_json::from_str(json).expect("valid CloudEvent");
+        assert!(event.validate().is_ok());
+
+        let normalized = event.normalize("proj".to_string(), None);
+        assert_eq!(normalized.event_type, "com.partner.order.created");
+        assert_eq!(normalized.timestamp, 1767348000000);
+        assert_eq!(normalized.properties.unwrap()["orderId"], "o-42");
+        let context = normalized.context.unwrap();
+        assert_eq!(context.extra["source"], "https://partner.example.com/orders");
+        assert_eq!(context.extra["cloudEventId"], "A234-1234-1234");
+    }
+
+    #[test]
+    fn test_invalid_cloud_event() {
+        // Missing required `source`
+        let json = r#"{"specversion": "1.0", "type": "order.created", "id": "1"}"#;
+        assert!(serde_json::from_str::<CloudEvent>(json).is_err());
+
+        let json = r#"{"specversion": "0.3", "type": "order.created", "source": "/orders", "id": "1"}"#;
+        let event: CloudEvent = serde_json::from_str(json).unwrap();
+        assert_eq!(event.validate().unwrap_err().to_string(), "Unsupported CloudEvents specversion: 0.3");
+
+        let json = r#"{"specversion": "1.0", "type": "order.created", "source": "/orders", "id": "1", "data": [1, 2]}"#;
+        let event: CloudEvent = serde_json::from_str(json).unwrap();
+        assert_eq!(event.validate().unwrap_err().to_string(), "data must be a JSON object");
+    }
+
+    #[test]
+    fn test_matching_event_kind() {
+        let json = r#"{"type":"pageview","en":"pageview","ts":1,"o":"https://example.com/","r":"","sw":1920,"sh":1080}"#;
+        let event: CompressedEvent = serde_json::from_str(json).unwrap();
+        assert!(event.validate_kind(EventKind::PageView).is_ok());
+
+        let json = r#"{"type":"track","en":"signup","ts":1,"o":"https://example.com/","r":"","sw":1920,"sh":1080}"#;
+        let event: CompressedEvent = serde_json::from_str(json).unwrap();
+        assert!(event.validate_kind(EventKind::Track).is_ok());
+
+        // No discriminator is always accepted
+        let json = r#"{"en":"signup","ts":1,"o":"https://example.com/","r":"","sw":1920,"sh":1080}"#;
+        let event: CompressedEvent = serde_json::from_str(json).unwrap();
+        assert!(event.validate_kind(EventKind::PageView).is_ok());
+        assert!(event.validate_kind(EventKind::Track).is_ok());
+    }
+
+    #[test]
+    fn test_mismatched_event_kind() {
+        let json = r#"{"type":"track","en":"signup","ts":1,"o":"https://example.com/","r":"","sw":1920,"sh":1080}"#;
+        let event: CompressedEvent = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            event.validate_kind(EventKind::PageView).unwrap_err().to_string(),
+            "type \"track\" does not match the /view endpoint"
+        );
+
+        let json = r#"{"type":"pageview","en":"pageview","ts":1,"o":"https://example.com/","r":"","sw":1920,"sh":1080}"#;
+        let event: CompressedEvent = serde_json::from_str(json).unwrap();
+        assert!(event.validate_kind(EventKind::Track).is_err());
+
+        let json = r#"{"type":"identify","en":"pageview","ts":1,"o":"https://example.com/","r":"","sw":1920,"sh":1080}"#;
+        let event: CompressedEvent = serde_json::from_str(json).unwrap();
+        assert!(event.validate_kind(EventKind::PageView).is_err());
+    }
+
+    #[test]
+    fn test_normalize_moves_fields_and_event_data_wins() {
+        let json = r#"{"en":"signup","ts":1,"o":"https://example.com/","r":"https://ref.io/","sw":1920,"sh":1080,"ed":{"url":"/custom","plan":"pro"}}"#;
+        let compressed: CompressedEvent = serde_json::from_str(json).unwrap();
+        let event = compressed.normalize("proj".to_string(), None);
+
+        let properties = event.properties.unwrap();
+        assert_eq!(properties["url"], "/custom");
+        assert_eq!(properties["referrer"], "https://ref.io/");
+        assert_eq!(properties["plan"], "pro");
+        assert_eq!(properties["screen_width"], 1920);
+        let page = event.context.unwrap().page.unwrap();
+        assert_eq!(page.url.as_deref(), Some("https://example.com/"));
+        assert_eq!(page.referrer.as_deref(), Some("https://ref.io/"));
+    }
+
+    #[test]
+    fn test_page_context_derived_from_top_level_url() {
+        let mut event = IngestEventPayload {
+            event_type: "pageview".to_string(),
+            properties: Some(HashMap::from([(
+                "url".to_string(),
+                serde_json::json!("https://example.com/docs/intro?ref=nav"),
+            )])),
+            ..Default::default()
+        };
+
+        assert!(event.ensure_page_context().is_ok());
+        let page = event.context.unwrap().page.unwrap();
+        assert_eq!(page.url.as_deref(), Some("https://example.com/docs/intro?ref=nav"));
+        assert_eq!(page.path.as_deref(), Some("/docs/intro"));
+    }
+
+    #[test]
+    fn test_page_context_missing_url_is_rejected() {
+        let mut event = IngestEventPayload {
+            event_type: "pageview".to_string(),
+            properties: Some(HashMap::new()),
+            ..Default::default()
+        };
+        assert_eq!(event.ensure_page_context().unwrap_err(), "pageview requires a url");
+
+        // Non-pageviews are left alone
+        let mut event = IngestEventPayload {
+            event_type: "signup".to_string(),
+            ..Default::default()
+        };
+        assert!(event.ensure_page_context().is_ok());
+        assert!(event.context.is_none());
+    }
+
+    #[test]
+    fn test_warnings_for_deprecated_name_and_unusual_properties() {
+        let event = IngestEventPayload {
+            event_type: "signup_clicked".to_string(),
+            properties: Some(HashMap::from([
+                ("plan".to_string(), serde_json::json!("pro")),
+                ("Button Label".to_string(), serde_json::json!("Sign up")),
+                ("coupon".to_string(), serde_json::Value::Null),
+            ])),
+            ..Default::default()
+        };
+
+        let warnings = event.warnings(&["signup_clicked".to_string()]);
+        assert_eq!(
+            warnings,
+            vec![
+                "event name \"signup_clicked\" is deprecated",
+                "property \"Button Label\" has an unusual name",
+                "property \"coupon\" is null",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_clean_event_has_no_warnings() {
+        let compressed: CompressedEvent = serde_json::from_str(
+            r#"{"en":"signup","ts":1,"o":"https://example.com/","r":"","sw":1920,"sh":1080,"ed":{"plan":"pro"}}"#,
+        )
+        .unwrap();
+        let event = compressed.normalize("proj".to_string(), None);
+
+        assert!(event.warnings(&["signup_clicked".to_string()]).is_empty());
+    }
+
+    #[test]
+    fn test_batch_envelope_is_unwrapped() {
+        let body: BatchBody = serde_json::from_str(
+            r#"{"events": [{"en": "pageview"}, {"en": "signup"}], "sentAt": "2024-01-01T00:00:00Z", "writeKey": "wk"}"#,
+        )
+        .unwrap();
+
+        let batch = body.unwrap(true).unwrap();
+        assert_eq!(batch.events.len(), 2);
+        assert_eq!(batch.sent_at, Some(1_704_067_200_000));
+        assert_eq!(batch.write_key.as_deref(), Some("wk"));
+    }
+
+    #[test]
+    fn test_bare_array_batch_still_supported() {
+        let body: BatchBody = serde_json::from_str(r#"[{"en": "pageview"}]"#).unwrap();
+
+        let batch = body.unwrap(false).unwrap();
+        assert_eq!(batch.events.len(), 1);
+        assert_eq!(batch.sent_at, None);
+        assert_eq!(batch.write_key, None);
+    }
+
+    #[test]
+    fn test_malformed_batch_envelopes() {
+        assert!(serde_json::from_str::<BatchBody>(r#"{"events": 5}"#).is_err());
+        assert!(serde_json::from_str::<BatchBody>(r#"{"sentAt": 1}"#).is_err());
+
+        let bad_time: BatchBody =
+            serde_json::from_str(r#"{"events": [], "sentAt": "yesterday"}"#).unwrap();
+        assert!(bad_time.unwrap(true).is_err());
+
+        // Envelopes are refused when the option is off
+        let envelope: BatchBody = serde_json::from_str(r#"{"events": []}"#).unwrap();
+        assert!(envelope.unwrap(false).is_err());
+    }
+
+    #[test]
+    fn test_web_vital_validation_and_normalization() {
+        let vital: WebVitalEvent = serde_json::from_str(
+            r#"{"name": "LCP", "value": 3120.5, "navigationType": "back-forward-cache", "url": "https://shop.io/cart?x=1"}"#,
+        )
+        .unwrap();
+        assert!(vital.validate().is_ok());
+
+        let event = vital.normalize("proj".to_string(), Some("u1".to_string()));
+        assert_eq!(event.event_type, "web_vital");
+        let properties = event.properties.unwrap();
+        assert_eq!(properties["metric"], "LCP");
+        assert_eq!(properties["value"], 3120.5);
+        assert_eq!(properties["rating"], "needs-improvement");
+        assert_eq!(properties["navigation_type"], "back-forward-cache");
+        assert_eq!(event.context.unwrap().page.unwrap().path.as_deref(), Some("/cart"));
+
+        // The client's rating wins over the thresholds
+        let cls: WebVitalEvent =
+            serde_json::from_str(r#"{"name": "CLS", "value": 0.3, "rating": "good", "url": "https://shop.io/"}"#).unwrap();
+        assert_eq!(cls.normalize("proj".to_string(), None).properties.unwrap()["rating"], "good");
+        assert_eq!(WebVitalMetric::Cls.rate(0.3), WebVitalRating::Poor);
+
+        let relative: WebVitalEvent = serde_json::from_str(r#"{"name": "INP", "value": 80, "url": "/cart"}"#).unwrap();
+        assert!(relative.validate().is_err());
+        let negative: WebVitalEvent = serde_json::from_str(r#"{"name": "TTFB", "value": -1, "url": "https://a.io/"}"#).unwrap();
+        assert!(negative.validate().is_err());
+        assert!(serde_json::from_str::<WebVitalEvent>(r#"{"name": "FID", "value": 1, "url": "https://a.io/"}"#).is_err());
+    }
+
+    #[test]
+    fn test_click_keeps_only_a_hash_of_the_element_text() {
+        let click: ClickEvent = serde_json::from_str(
+            r#"{"sessionId": "s1", "url": "https://shop.io/", "selector": "nav > a.pricing", "text": " Pricing ", "href": "/pricing"}"#,
+        )
+        .unwrap();
+        assert!(click.validate().is_ok());
+
+        let event = click.normalize("proj".to_string(), None);
+        assert_eq!(event.event_type, "click");
+        let properties = event.properties.unwrap();
+        assert_eq!(properties["selector"], "nav > a.pricing");
+        assert_eq!(properties["href"], "/pricing");
+        assert_eq!(properties["text_hash"].as_str().unwrap().len(), 32);
+        assert!(!properties.values().any(|value| value == "Pricing"));
+
+        let blank: ClickEvent =
+            serde_json::from_str(r#"{"sessionId": "s1", "url": "https://shop.io/", "selector": " "}"#).unwrap();
+        assert!(blank.validate().is_err());
+    }
+
+    #[test]
+    fn test_scroll_depth_buckets() {
+        let scroll = |percent: f64| ScrollDepthEvent {
+            session_id: "s1".to_string(),
+            url: "https://shop.io/".to_string(),
+            percent,
+            anonymous_id: None,
+            timestamp: None,
+            sent_at: None,
+        };
+        let buckets: Vec<u8> = [0.0, 24.9, 25.0, 61.0, 99.9, 100.0].map(|p| scroll(p).bucket()).to_vec();
+        assert_eq!(buckets, [0, 0, 25, 50, 75, 100]);
+        assert_eq!(scroll(61.0).normalize("proj".to_string(), None).properties.unwrap()["depth"], 50);
+        assert!(scroll(101.0).validate().is_err());
+        assert!(scroll(f64::NAN).validate().is_err());
+    }
+
+    #[test]
+    fn test_error_validation_and_truncation() {
+        let error: ErrorEvent = serde_json::from_str(
+            r#"{
+                "message": "TypeError: x is undefined",
+                "stack": "TypeError: x is undefined\n    at render (app.js:10:5)",
+                "source": {"file": "https://shop.io/app.js", "line": 10, "column": 5},
+                "release": "2026.10.1",
+                "breadcrumbs": [
+                    {"category": "ui.click", "message": "button#buy"},
+                    {"category": "fetch", "message": "GET /api/cart", "data": {"status": 500}}
+                ],
+                "url": "https://shop.io/cart"
+            }"#,
+        )
+        .unwrap();
+        assert!(error.validate().is_ok());
+
+        let event = error.clone().normalize("proj".to_string(), None, &ErrorLimits::default());
+        assert_eq!(event.event_type, "error");
+        let properties = event.properties.unwrap();
+        assert_eq!(properties["source_line"], 10);
+        assert_eq!(properties["release"], "2026.10.1");
+        assert_eq!(properties["breadcrumbs"][1]["data"]["status"], 500);
+        assert!(!properties.contains_key("truncated"));
+        assert_eq!(event.context.unwrap().page.unwrap().path.as_deref(), Some("/cart"));
+
+        let limits = ErrorLimits {
+            max_message_bytes: 9,
+            max_stack_bytes: 1024,
+            max_breadcrumbs: 1,
+        };
+        let properties = error.normalize("proj".to_string(), None, &limits).properties.unwrap();
+        assert_eq!(properties["message"], "TypeError");
+        assert_eq!(properties["breadcrumbs"].as_array().unwrap().len(), 1);
+        assert_eq!(properties["breadcrumbs"][0]["message"], "GET /api/");
+        assert_eq!(properties["truncated"], true);
+
+        let blank: ErrorEvent = serde_json::from_str(r#"{"message": " "}"#).unwrap();
+        assert!(blank.validate().is_err());
+    }
+
+    #[test]
+    fn test_heartbeat_validation_and_normalization() {
+        let heartbeat: HeartbeatEvent =
+            serde_json::from_str(r#"{"sessionId": "s1", "url": "https://shop.io/cart?step=2"}"#).unwrap();
+        assert!(heartbeat.validate().is_ok());
+
+        let event = heartbeat.normalize("proj".to_string(), None);
+        assert_eq!(event.event_type, "heartbeat");
+        let properties = event.properties.unwrap();
+        assert_eq!(properties["session_id"], "s1");
+        assert_eq!(properties["engaged"], true);
+        assert_eq!(event.context.unwrap().page.unwrap().path.as_deref(), Some("/cart"));
+
+        let idle: HeartbeatEvent =
+            serde_json::from_str(r#"{"sessionId": "s1", "url": "https://shop.io/", "engaged": false}"#).unwrap();
+        assert_eq!(idle.normalize("proj".to_string(), None).properties.unwrap()["engaged"], false);
+        let anonymous: HeartbeatEvent = serde_json::from_str(r#"{"sessionId": "", "url": "https://shop.io/"}"#).unwrap();
+        assert!(anonymous.validate().is_err());
+    }
+
+    #[test]
+    fn test_exposure_validation_and_session_message_ids() {
+        let exposure = |body: &str| serde_json::from_str::<ExposureEvent>(body).unwrap();
+        let first = exposure(r#"{"experimentKey": "checkout-v2", "variant": "b", "anonymousId": "a1", "sessionId": "s1"}"#);
+        assert!(first.validate().is_ok());
+
+        let event = first.normalize("proj".to_string(), None);
+        assert_eq!(event.event_type, "exposure");
+        let properties = event.properties.clone().unwrap();
+        assert_eq!(properties["experiment_key"], "checkout-v2");
+        assert_eq!(properties["variant"], "b");
+
+        // Same session and variant, same id; another session or variant, another
+        let again = exposure(r#"{"experimentKey": " checkout-v2", "variant": "b", "anonymousId": "a1", "sessionId": "s1"}"#);
+        let other_session = exposure(r#"{"experimentKey": "checkout-v2", "variant": "b", "anonymousId": "a1", "sessionId": "s2"}"#);
+        let other_variant = exposure(r#"{"experimentKey": "checkout-v2", "variant": "a", "anonymousId": "a1", "sessionId": "s1"}"#);
+        let message_id = |exposure: ExposureEvent| exposure.normalize("proj".to_string(), None).message_id.unwrap();
+        assert_eq!(message_id(again), event.message_id.clone().unwrap());
+        assert_ne!(message_id(other_session), event.message_id.clone().unwrap());
+        assert_ne!(message_id(other_variant), event.message_id.unwrap());
+
+        assert!(exposure(r#"{"experimentKey": "x", "variant": "b"}"#).validate().is_err());
+        assert!(exposure(r#"{"experimentKey": "", "variant": "b", "userId": "u1"}"#).validate().is_err());
+    }
+
+    #[test]
+    fn test_screen_validation_and_app_context() {
+        let screen: ScreenEvent = serde_json::from_str(
+            r#"{
+                "name": "Checkout",
+                "anonymousId": "device-1",
+                "properties": {"step": 2},
+                "app": {
+                    "name": "Shop", "version": "4.2.0", "build": "4210", "deviceModel": "iPhone15,2",
+                    "osName": "iOS", "osVersion": "18.1", "networkType": "cellular", "carrier": "T-Mobile"
+                },
+                "locale": "de-DE"
+            }"#,
+        )
+        .unwrap();
+        assert!(screen.validate().is_ok());
+
+        let event = screen.normalize("proj".to_string(), None);
+        assert_eq!(event.event_type, "screen");
+        let properties = event.properties.clone().unwrap();
+        assert_eq!(properties["screen_name"], "Checkout");
+        assert_eq!(properties["step"], 2);
+        let context = event.context.unwrap();
+        let app = context.app.unwrap();
+        assert_eq!(app.device_model.as_deref(), Some("iPhone15,2"));
+        assert_eq!(app.network_type, Some(NetworkType::Cellular));
+        assert_eq!(context.locale.as_deref(), Some("de-DE"));
+
+        // Unrecognized network types don't fail the event
+        let app: AppContext = serde_json::from_str(r#"{"networkType": "satellite"}"#).unwrap();
+        assert_eq!(app.network_type, Some(NetworkType::Unknown));
+
+        let unnamed: ScreenEvent = serde_json::from_str(r#"{"name": " ", "anonymousId": "d1"}"#).unwrap();
+        assert!(unnamed.validate().is_err());
+    }
+
+    #[test]
+    fn test_identify_validation_and_normalization() {
+        let identify: IdentifyEvent = serde_json::from_str(
+            r#"{"anonymousId": "anon-1", "traits": {"email": "a@b.io", "plan": "pro"}}"#,
+        )
+        .unwrap();
+        assert!(identify.validate().is_ok());
+
+        let event = identify.normalize("proj".to_string(), Some("u1".to_string()));
+        assert_eq!(event.event_type, "identify");
+        assert_eq!(event.user_id.as_deref(), Some("u1"));
+        assert_eq!(event.anonymous_id.as_deref(), Some("anon-1"));
+        assert_eq!(event.traits.unwrap()["plan"], "pro");
+        assert_eq!(event.timestamp, 0);
+
+        let no_ids: IdentifyEvent = serde_json::from_str(r#"{"userId": " ", "traits": {}}"#).unwrap();
+        assert!(no_ids.validate().is_err());
+        assert!(serde_json::from_str::<IdentifyEvent>(r#"{"userId": "u1", "traits": []}"#).is_err());
+    }
+
+    #[test]
+    fn test_group_validation_and_normalization() {
+        let group: GroupEvent = serde_json::from_str(
+            r#"{"userId": "u1", "groupId": "acme", "traits": {"name": "Acme", "employees": 50}}"#,
+        )
+        .unwrap();
+        assert!(group.validate().is_ok());
+
+        let event = group.normalize("proj".to_string(), Some("token-user".to_string()));
+        assert_eq!(event.event_type, "group");
+        assert_eq!(event.user_id.as_deref(), Some("u1"));
+        assert_eq!(event.group_id.as_deref(), Some("acme"));
+        assert_eq!(event.traits.unwrap()["employees"], 50);
+
+        let no_group: GroupEvent = serde_json::from_str(r#"{"userId": "u1", "groupId": ""}"#).unwrap();
+        assert_eq!(no_group.validate().unwrap_err().to_string(), "groupId is required");
+        let no_user: GroupEvent = serde_json::from_str(r#"{"groupId": "acme"}"#).unwrap();
+        assert!(no_user.validate().is_err());
+        let nothing: GroupEvent = serde_json::from_str(r#"{"groupId": " ", "traits": {"": 1}}"#).unwrap();
+        let fields: Vec<_> = nothing.validate().unwrap_err().errors().iter().map(|e| (e.field.clone(), e.code)).collect();
+        assert_eq!(
+            fields,
+            [
+                ("userId".to_string(), "required"),
+                ("groupId".to_string(), "required"),
+                ("traits".to_string(), "empty_key"),
+            ]
+        );
+        assert!(serde_json::from_str::<GroupEvent>(r#"{"userId": "u1"}"#).is_err());
+    }
+
+    #[test]
+    fn test_alias_validation_and_normalization() {
+        let alias: AliasEvent =
+            serde_json::from_str(r#"{"previousId": "anon-1", "userId": "u1"}"#).unwrap();
+        assert!(alias.validate().is_ok());
+
+        let event = alias.normalize("proj".to_string());
+        assert_eq!(event.event_type, "alias");
+        assert_eq!(event.previous_id.as_deref(), Some("anon-1"));
+        assert_eq!(event.user_id.as_deref(), Some("u1"));
+
+        let same: AliasEvent = serde_json::from_str(r#"{"previousId": "u1", "userId": "u1"}"#).unwrap();
+        assert!(same.validate().is_err());
+        let blank: AliasEvent = serde_json::from_str(r#"{"previousId": " ", "userId": "u1"}"#).unwrap();
+        assert_eq!(blank.validate().unwrap_err().to_string(), "previousId is required");
+        assert!(serde_json::from_str::<AliasEvent>(r#"{"previousId": "anon-1"}"#).is_err());
+    }
+
+    #[test]
+    fn test_sent_at_corrects_client_clock_skew() {
+        // A client clock a year slow: sent "now minus a year", event a minute before that
+        let year = 365 * 86_400_000;
+        let now = chrono::Utc::now().timestamp_millis();
+        let body = serde_json::json!({
+            "en": "pageview", "ts": now - year - 60_000, "sentAt": now - year,
+            "o": "https://shop.io", "r": "", "sw": 1, "sh": 1,
+        });
+        let event = CompressedEvent::deserialize(&body).unwrap();
+        assert!(event.validate().is_ok());
+        let corrected = event.normalize("proj".to_string(), None).timestamp;
+        assert!((corrected - (now - 60_000)).abs() < 5_000, "{}", corrected);
+
+        let identify: IdentifyEvent = serde_json::from_str(
+            r#"{"userId": "u1", "timestamp": 1488326399000, "sentAt": "2017-03-01T00:00:00Z"}"#,
+        )
+        .unwrap();
+        let corrected = identify.normalize("proj".to_string(), None).timestamp;
+        assert!((corrected - (now - 1_000)).abs() < 5_000, "{}", corrected);
+
+        // No timestamp stays missing for the handler to default
+        let identify: IdentifyEvent =
+            serde_json::from_str(r#"{"userId": "u1", "sentAt": 1000}"#).unwrap();
+        assert_eq!(identify.normalize("proj".to_string(), None).timestamp, 0);
+
+        let invalid: AliasEvent =
+            serde_json::from_str(r#"{"previousId": "a", "userId": "u1", "sentAt": "yesterday"}"#).unwrap();
+        assert!(invalid.validate().unwrap_err().to_string().starts_with("sentAt is not a valid timestamp"));
+    }
 }
@@ -0,0 +1,180 @@
+//! Response content negotiation.
+//!
+//! With `CONTENT_NEGOTIATION_ENABLED`, responses follow what the client
+//! asks for rather than each endpoint's default (the bare `ACCEPTED` of v1,
+//! JSON everywhere else):
+//!
+//! - `Accept: application/json` gets JSON, `{"status": "accepted"}` for
+//!   the bare acknowledgement
+//! - `Accept: text/plain` gets the minimal text body: `ACCEPTED` or
+//!   `REJECTED`, or an error's message; bodies with more to say than that,
+//!   like a batch's per-event results, stay JSON
+//! - `Prefer: return=minimal`, or `?return=minimal` for clients that can't
+//!   set headers (`sendBeacon`, pixels), turns any success into a bare 204,
+//!   for clients that never read the response
+//!
+//! Anything else in `Accept`, like `*/*`, keeps the endpoint's default, and
+//! `q` weights pick between the two types when both are listed.
+
+use lambda_http::http::header::{CONTENT_TYPE, VARY};
+use lambda_http::http::HeaderValue;
+use lambda_http::{Body, Request, Response};
+
+use crate::shared::{header_value, query_param};
+
+/// How the client wants to be answered
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ResponseFormat {
+    /// Whatever the endpoint answers
+    #[default]
+    Default,
+    Json,
+    Text,
+    /// No body at all
+    NoContent,
+}
+
+impl ResponseFormat {
+    /// The format a request asks for
+    pub fn requested(request: &Request) -> Self {
+        let minimal = header_value(request, "prefer")
+            .is_some_and(|prefer| prefer.split(',').any(|p| p.trim().eq_ignore_ascii_case("return=minimal")))
+            || query_param(request, "return") == Some("minimal");
+        if minimal {
+            return Self::NoContent;
+        }
+        header_value(request, "accept").map_or(Self::Default, Self::from_accept)
+    }
+
+    /// The most preferred of the types we can choose between
+    fn from_accept(accept: &str) -> Self {
+        let mut preferred = (Self::Default, 0.0);
+        for range in accept.split(',') {
+            let mut params = range.split(';');
+            let format = match params.next().unwrap_or_default().trim().to_ascii_lowercase().as_str() {
+                "application/json" => Self::Json,
+                "text/plain" => Self::Text,
+                _ => continue,
+            };
+            let weight = params
+                .find_map(|param| param.trim().strip_prefix("q=")?.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            if weight > preferred.1 {
+                preferred = (format, weight);
+            }
+        }
+        preferred.0
+    }
+
+    /// Reshapes a response into this format, where it can be
+    pub fn apply(self, mut response: Response<Body>) -> Response<Body> {
+        if self == Self::Default {
+            return response;
+        }
+        response.headers_mut().append(VARY, HeaderValue::from_static("Accept"));
+
+        let success = response.status().is_success();
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        let Body::Text(text) = response.body() else {
+            return response;
+        };
+
+        let reshaped = match self {
+            Self::NoContent if success => {
+                *response.status_mut() = lambda_http::http::StatusCode::NO_CONTENT;
+                response.headers_mut().remove(CONTENT_TYPE);
+                *response.body_mut() = Body::Empty;
+                return response;
+            }
+            Self::Text if content_type.starts_with("application/json") => minimal_text(text),
+            Self::Json if content_type.starts_with("text/plain") => {
+                let field = if success { "status" } else { "error" };
+                let value = if success { text.to_ascii_lowercase() } else { text.clone() };
+                Some((serde_json::json!({ field: value }).to_string(), "application/json"))
+            }
+            _ => None,
+        };
+        if let Some((body, content_type)) = reshaped {
+            response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+            *response.body_mut() = Body::Text(body);
+        }
+        response
+    }
+}
+
+/// A JSON body's error message or status, when that's all it says
+fn minimal_text(json: &str) -> Option<(String, &'static str)> {
+    let serde_json::Value::Object(body) = serde_json::from_str(json).ok()? else {
+        return None;
+    };
+    if let Some(error) = body.get("error").and_then(|e| e.as_str()) {
+        return Some((error.to_string(), "text/plain"));
+    }
+    let status = body.get("status").and_then(|s| s.as_str())?;
+    let has_detail = body.iter().any(|(key, value)| match key.as_str() {
+        "status" | "accepted" => false,
+        _ => !(value.is_null() || value.as_array().is_some_and(Vec::is_empty)),
+    });
+    (!has_detail).then(|| (status.to_ascii_uppercase(), "text/plain"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::{create_error_response, create_response, create_text_response};
+
+    fn request(headers: &[(&str, &str)]) -> Request {
+        let mut builder = lambda_http::http::Request::builder().method("POST").uri("/event");
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        builder.body(Body::Empty).unwrap()
+    }
+
+    fn body(response: &Response<Body>) -> &str {
+        std::str::from_utf8(response.body().as_ref()).unwrap()
+    }
+
+    #[test]
+    fn test_accept_picks_the_format() {
+        let format = |accept: &str| ResponseFormat::requested(&request(&[("Accept", accept)]));
+        assert_eq!(format("application/json"), ResponseFormat::Json);
+        assert_eq!(format("text/plain, application/json;q=0.5"), ResponseFormat::Text);
+        assert_eq!(format("text/plain;q=0.2, application/json"), ResponseFormat::Json);
+        assert_eq!(format("*/*"), ResponseFormat::Default);
+        assert_eq!(ResponseFormat::requested(&request(&[])), ResponseFormat::Default);
+
+        let minimal = request(&[("Accept", "application/json"), ("Prefer", "respond-async, return=minimal")]);
+        assert_eq!(ResponseFormat::requested(&minimal), ResponseFormat::NoContent);
+    }
+
+    #[test]
+    fn test_responses_are_reshaped() {
+        let response = ResponseFormat::Json.apply(create_text_response(202, "ACCEPTED"));
+        assert_eq!(response.headers()["content-type"], "application/json");
+        assert_eq!(body(&response), r#"{"status":"accepted"}"#);
+        assert_eq!(response.headers()["vary"], "Accept");
+
+        let response = ResponseFormat::Text.apply(create_error_response(422, "en (event name) is required"));
+        assert_eq!(response.status(), 422);
+        assert_eq!(response.headers()["content-type"], "text/plain");
+        assert_eq!(body(&response), "en (event name) is required");
+
+        let accepted = create_response(202, serde_json::json!({"status": "accepted", "warnings": []}));
+        assert_eq!(body(&ResponseFormat::Text.apply(accepted)), "ACCEPTED");
+        let partial = create_response(202, serde_json::json!({"status": "accepted", "errors": [{"index": 1}]}));
+        assert!(body(&ResponseFormat::Text.apply(partial)).starts_with('{'));
+
+        let response = ResponseFormat::NoContent.apply(create_response(202, serde_json::json!({"status": "accepted"})));
+        assert_eq!(response.status(), 204);
+        assert!(!response.headers().contains_key("content-type"));
+        assert!(matches!(response.body(), Body::Empty));
+        let response = ResponseFormat::NoContent.apply(create_error_response(400, "Missing request body"));
+        assert_eq!(response.status(), 400);
+    }
+}
@@ -0,0 +1,102 @@
+//! Local development without AWS.
+//!
+//! With `OFFLINE_MODE` events go to a [`LocalSink`](crate::sink::local::LocalSink)
+//! (`OFFLINE_OUTPUT`, or stdout) instead of Kinesis, `STREAM_NAME` is
+//! optional, and stores without a table are already in memory, so no AWS
+//! credentials are needed. The binary still runs under `cargo lambda watch`;
+//! with `OFFLINE_PORT` it serves plain HTTP on localhost instead, for SDKs
+//! pointed straight at it.
+
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::body::Incoming;
+use hyper_util::rt::TokioIo;
+use lambda_http::tower::ServiceExt;
+use lambda_http::{Body, Error, RequestExt};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::middleware::HttpService;
+use crate::router;
+use crate::shared::{env_flag, env_opt, AppState};
+
+/// Configuration for offline mode
+#[derive(Debug, Clone, Default)]
+pub struct OfflineConfig {
+    pub enabled: bool,
+    /// File events are appended to; stdout when unset
+    pub output: Option<String>,
+    /// Port of the local HTTP server; the Lambda runtime when unset
+    pub port: Option<u16>,
+}
+
+impl OfflineConfig {
+    pub fn from_env() -> Self {
+        Self {
+            enabled: env_flag("OFFLINE_MODE"),
+            output: env_opt("OFFLINE_OUTPUT"),
+            port: env_opt("OFFLINE_PORT"),
+        }
+    }
+}
+
+/// A hyper request as the Lambda request the service expects
+pub fn to_lambda_request(parts: http::request::Parts, body: Bytes) -> lambda_http::Request {
+    let mut query: HashMap<String, Vec<String>> = HashMap::new();
+    for (name, value) in url::form_urlencoded::parse(parts.uri.query().unwrap_or_default().as_bytes()) {
+        query.entry(name.into_owned()).or_default().push(value.into_owned());
+    }
+    let body = match String::from_utf8(body.to_vec()) {
+        Ok(text) if text.is_empty() => Body::Empty,
+        Ok(text) => Body::Text(text),
+        Err(e) => Body::Binary(e.into_bytes()),
+    };
+    lambda_http::Request::from_parts(parts, body).with_query_string_parameters(query)
+}
+
+async fn handle(request: hyper::Request<Incoming>, service: HttpService) -> Result<hyper::Response<Full<Bytes>>, Error> {
+    let (parts, body) = request.into_parts();
+    let body = body.collect().await?.to_bytes();
+    let response = service.oneshot(to_lambda_request(parts, body)).await?;
+    let (parts, body) = response.into_parts();
+    Ok(hyper::Response::from_parts(parts, Full::new(Bytes::copy_from_slice(body.as_ref()))))
+}
+
+/// Serves the handler over plain HTTP on localhost until the process ends
+pub async fn serve(state: Arc<AppState>, port: u16) -> Result<(), Error> {
+    let listener = tokio::net::TcpListener::bind(("127.0.0.1", port)).await?;
+    tracing::info!("Serving offline on http://127.0.0.1:{}", port);
+    let lambda_service = router::service(state);
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let lambda_service = lambda_service.clone();
+        tokio::spawn(async move {
+            let service = hyper::service::service_fn(move |request| handle(request, lambda_service.clone()));
+            if let Err(e) = hyper::server::conn::http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                tracing::warn!("Offline connection failed: {}", e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::query_param;
+
+    #[test]
+    fn test_converts_query_and_body() {
+        let (parts, _) = http::Request::post("/prod/event?token=abc&x=1&x=2").body(()).unwrap().into_parts();
+        let request = to_lambda_request(parts, Bytes::from_static(br#"{"a":1}"#));
+        assert_eq!(query_param(&request, "token"), Some("abc"));
+        assert_eq!(request.query_string_parameters_ref().unwrap().all("x"), Some(vec!["1", "2"]));
+        assert!(matches!(request.body(), Body::Text(text) if text == r#"{"a":1}"#));
+
+        let (parts, _) = http::Request::post("/prod/event").body(()).unwrap().into_parts();
+        let request = to_lambda_request(parts, Bytes::from_static(&[0x1f, 0x8b, 0xff]));
+        assert!(matches!(request.body(), Body::Binary(bytes) if bytes.len() == 3));
+    }
+}
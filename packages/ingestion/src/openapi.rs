@@ -0,0 +1,202 @@
+//! The API's OpenAPI 3 description, served at `GET /openapi.json`.
+//!
+//! Generated from the code rather than written by hand, so it can't drift
+//! from what's parsed: request bodies are the types in `models` (and
+//! `analytics-core`'s, with its `openapi` feature) deriving utoipa's
+//! `ToSchema`, and each handler's `#[utoipa::path]` names its route, body
+//! and responses. A new event endpoint is documented by annotating its
+//! handler and listing it in [`ApiDoc`]; the tests fail until it is.
+//!
+//! Event endpoints are described under `/v2`, whose validation and
+//! responses are the stricter ones; they're also served unversioned and
+//! under `/v1` (see `version`). The document is built once per sandbox and
+//! served without credentials or origin checks, like the health probes.
+
+use lambda_http::{Body, Response};
+use serde::Serialize;
+use std::sync::LazyLock;
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{IntoResponses, Modify, OpenApi, ToSchema};
+
+use crate::handlers;
+use crate::segment;
+use crate::shared::create_response;
+use crate::validation::ValidationError;
+
+/// Body of an accepted event
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Accepted {
+    /// `accepted`
+    pub status: String,
+    /// Non-fatal problems with the payload, for SDK developers to fix
+    pub warnings: Vec<String>,
+}
+
+/// Body of an accepted batch that not every event of made it into
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchAccepted {
+    /// `accepted`, `partial` (some couldn't be written and can be resent)
+    /// or `rejected`
+    pub status: String,
+    /// How many events were accepted
+    pub accepted: usize,
+    /// Positions of the accepted events, on a partial write
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub accepted_indices: Option<Vec<usize>>,
+    /// The rejected events, each with its `index`, a machine-readable
+    /// `reason` and a `message`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub errors: Option<Vec<serde_json::Value>>,
+}
+
+/// Body of every error response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ErrorBody {
+    pub error: String,
+}
+
+/// Body of a 422 for a payload that failed validation
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Invalid {
+    /// Every problem, joined
+    pub error: String,
+    pub errors: Vec<ValidationError>,
+}
+
+/// Responses of the single-event endpoints
+#[derive(IntoResponses)]
+pub enum EventResponses {
+    /// The event was accepted, or dropped on purpose (sampled out,
+    /// filtered, throttled)
+    #[response(status = 202)]
+    Accepted(Accepted),
+    /// The body isn't JSON of the endpoint's shape
+    #[response(status = 400)]
+    BadRequest(ErrorBody),
+    /// Missing or invalid credentials
+    #[response(status = 401)]
+    Unauthorized(ErrorBody),
+    /// The API key or origin isn't allowed for the project
+    #[response(status = 403)]
+    Forbidden(ErrorBody),
+    /// The payload failed validation
+    #[response(status = 422)]
+    Invalid(Invalid),
+    /// Over the project's rate limit; retry after `Retry-After`
+    #[response(status = 429)]
+    TooManyRequests(ErrorBody),
+}
+
+/// Responses of `POST /v2/batch`
+#[derive(IntoResponses)]
+pub enum BatchResponses {
+    /// Every event was accepted
+    #[response(status = 202)]
+    Accepted(BatchAccepted),
+    /// Some events couldn't be written; resend those not in
+    /// `acceptedIndices`
+    #[response(status = 207)]
+    Partial(BatchAccepted),
+    /// The body isn't a batch
+    #[response(status = 400)]
+    BadRequest(ErrorBody),
+    /// Missing or invalid credentials
+    #[response(status = 401)]
+    Unauthorized(ErrorBody),
+    /// Every event was rejected
+    #[response(status = 422)]
+    Rejected(BatchAccepted),
+    /// Over the project's rate limit; retry after `Retry-After`
+    #[response(status = 429)]
+    TooManyRequests(ErrorBody),
+}
+
+/// Adds the security schemes the paths refer to: the SDK token
+/// (`bearer`), the project API key some projects require alongside it
+/// (`apiKey`) and Segment write keys (`writeKey`)
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        let bearer = HttpBuilder::new().scheme(HttpAuthScheme::Bearer).bearer_format("JWT");
+        components.add_security_scheme("bearer", SecurityScheme::Http(bearer.build()));
+        let api_key = ApiKey::Header(ApiKeyValue::new("X-API-Key"));
+        components.add_security_scheme("apiKey", SecurityScheme::ApiKey(api_key));
+        let write_key = HttpBuilder::new().scheme(HttpAuthScheme::Basic);
+        components.add_security_scheme("writeKey", SecurityScheme::Http(write_key.build()));
+    }
+}
+
+/// The API's description
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Product analytics ingestion API",
+        description = "Event collection for the browser, mobile and server SDKs. Event endpoints are \
+                       also served unversioned and under `/v1`, with the looser v1 validation."
+    ),
+    paths(
+        handlers::handle_page_view,
+        handlers::handle_track,
+        handlers::handle_identify,
+        handlers::handle_group,
+        handlers::handle_alias,
+        handlers::handle_vitals,
+        handlers::handle_error,
+        handlers::handle_heartbeat,
+        handlers::handle_click,
+        handlers::handle_scroll_depth,
+        handlers::handle_exposure,
+        handlers::handle_screen,
+        handlers::handle_cloud_event,
+        handlers::handle_batch,
+        segment::handle,
+    ),
+    modifiers(&SecuritySchemes),
+    tags(
+        (name = "events", description = "Browser and mobile SDK events"),
+        (name = "segment", description = "Segment-compatible tracking API"),
+    )
+)]
+pub struct ApiDoc;
+
+static DOCUMENT: LazyLock<serde_json::Value> =
+    LazyLock::new(|| serde_json::to_value(ApiDoc::openapi()).expect("the OpenAPI document serializes"));
+
+/// Handler for GET /openapi.json
+pub fn handle_openapi() -> Response<Body> {
+    create_response(200, DOCUMENT.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routes::EVENT_ENDPOINTS;
+
+    #[test]
+    fn test_documents_every_event_endpoint() {
+        let paths = &DOCUMENT["paths"];
+        for (name, _) in EVENT_ENDPOINTS {
+            assert!(paths[format!("/v2/{}", name)]["post"].is_object(), "/v2/{} isn't documented", name);
+        }
+        assert!(paths["/v1/{call}"]["post"].is_object());
+    }
+
+    #[test]
+    fn test_bodies_are_the_models() {
+        let schemas = &DOCUMENT["components"]["schemas"];
+        let view = &DOCUMENT["paths"]["/v2/view"]["post"];
+        assert_eq!(
+            view["requestBody"]["content"]["application/json"]["schema"]["$ref"],
+            "#/components/schemas/CompressedEvent"
+        );
+        for field in ["en", "ts", "o", "r", "sw", "sh"] {
+            assert!(schemas["CompressedEvent"]["required"].as_array().unwrap().contains(&field.into()));
+        }
+        assert_eq!(schemas["WebVitalMetric"]["enum"], serde_json::json!(["LCP", "CLS", "INP", "TTFB"]));
+        assert!(schemas["Message"]["properties"]["type"].is_object());
+        assert!(view["responses"]["422"].is_object());
+    }
+}
@@ -0,0 +1,123 @@
+//! Server-side origin enforcement.
+//!
+//! CORS only stops browsers from reading responses; any other client can
+//! still POST from anywhere. When enforcement is enabled, every non-preflight
+//! request must carry an `Origin` (or, failing that, a `Referer`) whose
+//! origin is on the allowlist, otherwise it is rejected with a 403.
+//!
+//! Projects can also carry their own allowlist on their API key record (see
+//! [`auth`](crate::auth)); responses then echo only an approved origin in
+//! `Access-Control-Allow-Origin` instead of `*`.
+
+use lambda_http::{Body, Request, Response};
+
+use crate::shared::{env_flag, env_list, header_value};
+
+/// Configuration for origin enforcement
+#[derive(Debug, Clone, Default)]
+pub struct OriginPolicy {
+    pub enforce: bool,
+    /// Allowed origins, e.g. `https://app.example.com` or `https://*.example.com`
+    pub allowed: Vec<String>,
+}
+
+impl OriginPolicy {
+    pub fn from_env() -> Self {
+        Self {
+            enforce: env_flag("ENFORCE_ALLOWED_ORIGINS"),
+            allowed: normalize(env_list("ALLOWED_ORIGINS")),
+        }
+    }
+
+    /// Whether `origin` (`scheme://host[:port]`) matches an allowlist entry
+    pub fn allows(&self, origin: &str) -> bool {
+        origin_allowed(&self.allowed, origin)
+    }
+
+    /// Whether the request may proceed; always true when not enforcing
+    pub fn permits(&self, request: &Request) -> bool {
+        if !self.enforce {
+            return true;
+        }
+        request_origin(request).is_some_and(|origin| self.allows(&origin))
+    }
+}
+
+/// Normalizes allowlist entries for [`origin_allowed`]
+pub fn normalize(origins: Vec<String>) -> Vec<String> {
+    origins
+        .into_iter()
+        .map(|origin| origin.trim_end_matches('/').to_ascii_lowercase())
+        .collect()
+}
+
+/// Whether `origin` matches an entry of a normalized allowlist, e.g.
+/// `https://app.example.com` or `https://*.example.com`
+pub fn origin_allowed(allowed: &[String], origin: &str) -> bool {
+    let origin = origin.trim_end_matches('/').to_ascii_lowercase();
+    allowed.iter().any(|allowed| {
+        if allowed == &origin {
+            return true;
+        }
+        // `https://*.example.com` matches any subdomain, not the apex
+        match allowed.split_once("://*.") {
+            Some((scheme, domain)) => origin
+                .strip_prefix(scheme)
+                .and_then(|rest| rest.strip_prefix("://"))
+                .and_then(|host| host.strip_suffix(domain))
+                .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.')),
+            None => false,
+        }
+    })
+}
+
+/// Replaces the wildcard `Access-Control-Allow-Origin` with `origin`, or
+/// drops it when there is none, and marks the response as varying by origin
+pub fn with_allowed_origin(mut response: Response<Body>, origin: Option<&str>) -> Response<Body> {
+    let headers = response.headers_mut();
+    match origin.and_then(|o| o.parse().ok()) {
+        Some(value) => headers.insert("Access-Control-Allow-Origin", value),
+        None => headers.remove("Access-Control-Allow-Origin"),
+    };
+    headers.append("Vary", "Origin".parse().expect("valid header value"));
+    response
+}
+
+/// The request's origin, from `Origin` or else the origin part of `Referer`
+pub fn request_origin(request: &Request) -> Option<String> {
+    if let Some(origin) = header_value(request, "origin").filter(|o| *o != "null") {
+        return Some(origin.to_string());
+    }
+
+    let referer = url::Url::parse(header_value(request, "referer")?).ok()?;
+    let origin = referer.origin();
+    origin.is_tuple().then(|| origin.ascii_serialization())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> OriginPolicy {
+        OriginPolicy {
+            enforce: true,
+            allowed: vec![
+                "https://app.example.com".to_string(),
+                "https://*.example.org".to_string(),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_allowlist_matching() {
+        let policy = policy();
+        assert!(policy.allows("https://app.example.com"));
+        assert!(policy.allows("HTTPS://APP.EXAMPLE.COM/"));
+        assert!(policy.allows("https://shop.example.org"));
+
+        assert!(!policy.allows("http://app.example.com"));
+        assert!(!policy.allows("https://app.example.com.evil.io"));
+        assert!(!policy.allows("https://example.org"));
+        assert!(!policy.allows("https://evilexample.org"));
+    }
+}
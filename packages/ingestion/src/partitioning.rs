@@ -0,0 +1,206 @@
+//! Stream partition keys.
+//!
+//! Partitioning by project keeps each project's events in order, but puts a
+//! big tenant's whole volume on one shard. `PARTITION_KEY_STRATEGY` picks
+//! the key instead:
+//!
+//! - `project` (default): the project id
+//! - `session`: the project and session (a `session_id` property, else the
+//!   visitor), so only a session's events stay in order
+//! - `user`: the project and user (else anonymous) id
+//! - `random`: a fresh key per event, for even spread with no ordering
+//! - `round_robin`: the project id as key, with an explicit hash key
+//!   cycling through `PARTITION_ROUND_ROBIN_SLOTS` evenly spaced points of
+//!   the hash range, so consecutive events land on different shards
+//!
+//! `PARTITION_KEY_OVERRIDES` sets the strategy per project, e.g.
+//! `{"big-tenant": "user"}`. Events without the ids a strategy needs fall
+//! back to the project id.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::enrichment::duplicate_view::session_key;
+use crate::models::IngestEventPayload;
+use crate::shared::{env_json, env_opt, env_or};
+
+/// Next round-robin slot, shared by every request this sandbox serves
+static NEXT_SLOT: AtomicU64 = AtomicU64::new(0);
+
+/// How an event's partition key is chosen
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PartitionStrategy {
+    #[default]
+    Project,
+    Session,
+    User,
+    Random,
+    RoundRobin,
+}
+
+impl std::str::FromStr for PartitionStrategy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().replace('-', "_").as_str() {
+            "project" => Ok(Self::Project),
+            "session" => Ok(Self::Session),
+            "user" => Ok(Self::User),
+            "random" => Ok(Self::Random),
+            "round_robin" | "explicit_hash_key" => Ok(Self::RoundRobin),
+            other => Err(format!("unknown partition key strategy \"{}\"", other)),
+        }
+    }
+}
+
+/// Configuration for partition keys
+#[derive(Debug, Clone)]
+pub struct PartitionConfig {
+    pub strategy: PartitionStrategy,
+    /// Strategies by project id
+    pub overrides: HashMap<String, PartitionStrategy>,
+    /// Points of the hash range `round_robin` cycles through
+    pub round_robin_slots: u32,
+}
+
+impl Default for PartitionConfig {
+    fn default() -> Self {
+        Self {
+            strategy: PartitionStrategy::Project,
+            overrides: HashMap::new(),
+            round_robin_slots: 64,
+        }
+    }
+}
+
+impl PartitionConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let parse = |value: &str| {
+            value
+                .parse()
+                .map_err(|e| tracing::warn!("Ignoring partition key strategy: {}", e))
+                .ok()
+        };
+        let overrides: HashMap<String, String> = env_json("PARTITION_KEY_OVERRIDES").unwrap_or_default();
+        Self {
+            strategy: env_opt::<String>("PARTITION_KEY_STRATEGY")
+                .and_then(|value| parse(&value))
+                .unwrap_or(defaults.strategy),
+            overrides: overrides
+                .into_iter()
+                .filter_map(|(project, value)| Some((project, parse(&value)?)))
+                .collect(),
+            round_robin_slots: env_or("PARTITION_ROUND_ROBIN_SLOTS", defaults.round_robin_slots).max(1),
+        }
+    }
+
+    /// The strategy for a project
+    pub fn strategy_for(&self, project_id: &str) -> PartitionStrategy {
+        self.overrides.get(project_id).copied().unwrap_or(self.strategy)
+    }
+}
+
+/// Where a record goes: its partition key and, optionally, an explicit
+/// hash key that overrides the key's hash
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PartitionKey {
+    pub key: String,
+    pub explicit_hash_key: Option<String>,
+}
+
+/// The midpoint of slot `slot` of `slots` equal parts of the 128-bit hash
+/// range, in decimal as Kinesis expects
+pub fn slot_hash_key(slot: u64, slots: u32) -> String {
+    let width = u128::MAX / u128::from(slots.max(1));
+    (width * u128::from(slot % u64::from(slots.max(1))) + width / 2).to_string()
+}
+
+/// A deterministic key for the event: the strategy's key, or the project
+/// for `random` and `round_robin`, which have none
+pub fn stable_key(event: &IngestEventPayload, config: &PartitionConfig) -> String {
+    let scoped = |id: Option<&str>| id.map(|id| format!("{}#{}", event.project_id, id));
+    let key = match config.strategy_for(&event.project_id) {
+        PartitionStrategy::Session => session_key(event),
+        PartitionStrategy::User => scoped(event.user_id.as_deref().or(event.anonymous_id.as_deref())),
+        _ => None,
+    };
+    key.unwrap_or_else(|| event.project_id.clone())
+}
+
+/// The event's partition key
+pub fn partition_key(event: &IngestEventPayload, config: &PartitionConfig) -> PartitionKey {
+    match config.strategy_for(&event.project_id) {
+        PartitionStrategy::Random => PartitionKey {
+            key: uuid::Uuid::new_v4().to_string(),
+            explicit_hash_key: None,
+        },
+        PartitionStrategy::RoundRobin => PartitionKey {
+            key: event.project_id.clone(),
+            explicit_hash_key: Some(slot_hash_key(
+                NEXT_SLOT.fetch_add(1, Ordering::Relaxed),
+                config.round_robin_slots,
+            )),
+        },
+        _ => PartitionKey {
+            key: stable_key(event, config),
+            explicit_hash_key: None,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(project_id: &str) -> IngestEventPayload {
+        IngestEventPayload {
+            project_id: project_id.to_string(),
+            user_id: Some("u-1".to_string()),
+            anonymous_id: Some("a-1".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_strategies_and_overrides() {
+        let config = PartitionConfig {
+            strategy: PartitionStrategy::Session,
+            overrides: HashMap::from([
+                ("big".to_string(), PartitionStrategy::User),
+                ("huge".to_string(), PartitionStrategy::Random),
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(partition_key(&event("small"), &config).key, "small#a-1");
+        assert_eq!(partition_key(&event("big"), &config).key, "big#u-1");
+        let (first, second) = (partition_key(&event("huge"), &config), partition_key(&event("huge"), &config));
+        assert_ne!(first.key, second.key);
+        assert_eq!(stable_key(&event("huge"), &config), "huge");
+
+        let anonymous = IngestEventPayload {
+            project_id: "big".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(partition_key(&anonymous, &config).key, "big");
+        assert_eq!(partition_key(&event("p"), &PartitionConfig::default()).key, "p");
+        assert_eq!("explicit-hash-key".parse(), Ok(PartitionStrategy::RoundRobin));
+    }
+
+    #[test]
+    fn test_round_robin_spreads_across_the_hash_range() {
+        let config = PartitionConfig {
+            strategy: PartitionStrategy::RoundRobin,
+            round_robin_slots: 4,
+            ..Default::default()
+        };
+        let keys: std::collections::HashSet<_> = (0..8)
+            .map(|_| partition_key(&event("p"), &config).explicit_hash_key.unwrap())
+            .collect();
+        assert_eq!(keys.len(), 4);
+
+        assert_eq!(slot_hash_key(0, 2), (u128::MAX / 4).to_string());
+        assert_eq!(slot_hash_key(3, 2), slot_hash_key(1, 2));
+        assert!(slot_hash_key(1, 2).parse::<u128>().unwrap() > u128::MAX / 2);
+    }
+}
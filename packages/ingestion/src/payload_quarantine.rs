@@ -0,0 +1,333 @@
+//! Quarantine of rejected payloads.
+//!
+//! A request answered 400 (unparseable) or 422 (invalid) is normally gone
+//! once the client has its error. With `PAYLOAD_QUARANTINE_STREAM` or
+//! `PAYLOAD_QUARANTINE_BUCKET` set, its raw body is kept instead, with the
+//! rejection reason and what's known about the sender, as one
+//! [`RejectedPayload`] per request: a record on the stream, or an object
+//! under `PAYLOAD_QUARANTINE_PREFIX` in the bucket. That's enough to tell
+//! which SDK version sends broken events and to replay what can be fixed.
+//!
+//! Not to be confused with `QUARANTINE_STREAM_NAME`, which takes events
+//! accepted in lenient validation mode (see `validation`). Events a batch
+//! rejects one by one, while accepting the rest, aren't quarantined.
+//! Writing is best effort: a failure is logged, and the client gets its
+//! rejection all the same.
+//!
+//! A rejected body can hold anything field-level encryption would have
+//! encrypted, and can't be searched for those fields, so with
+//! `FIELD_ENCRYPTION_KMS_KEY_ID` set the whole body is encrypted the same
+//! way (see `encryption`), with `encryption` naming `body`. Only bodies
+//! known to come from a project left out by `FIELD_ENCRYPTION_PROJECTS`
+//! are kept in plaintext, and a body that can't be encrypted isn't kept.
+
+use async_trait::async_trait;
+use aws_sdk_kinesis::primitives::Blob;
+use aws_sdk_kinesis::Client as KinesisClient;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::Client as S3Client;
+use base64::Engine;
+use lambda_http::{Body, Error, Request, Response};
+use serde::Serialize;
+use std::sync::Mutex;
+
+use crate::encryption;
+use crate::handlers;
+use crate::models::FieldEncryption;
+use crate::request_id::RequestId;
+use crate::shared::{env_or, env_var, header_value, AppState};
+
+/// Statuses whose request bodies are quarantined
+const QUARANTINED_STATUSES: [u16; 2] = [400, 422];
+
+/// Configuration for the payload quarantine
+#[derive(Debug, Clone)]
+pub struct PayloadQuarantineConfig {
+    /// Stream rejected payloads are written to
+    pub stream_name: Option<String>,
+    /// Bucket rejected payloads are written to, when there's no stream
+    pub bucket: Option<String>,
+    pub prefix: String,
+}
+
+impl Default for PayloadQuarantineConfig {
+    fn default() -> Self {
+        Self {
+            stream_name: None,
+            bucket: None,
+            prefix: "rejected".to_string(),
+        }
+    }
+}
+
+impl PayloadQuarantineConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            stream_name: env_var("PAYLOAD_QUARANTINE_STREAM").filter(|stream| !stream.is_empty()),
+            bucket: env_var("PAYLOAD_QUARANTINE_BUCKET").filter(|bucket| !bucket.is_empty()),
+            prefix: env_or("PAYLOAD_QUARANTINE_PREFIX", defaults.prefix),
+        }
+    }
+}
+
+/// A rejected request, as quarantined
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RejectedPayload {
+    /// Epoch milliseconds
+    pub received_at: i64,
+    pub request_id: Option<String>,
+    pub method: String,
+    pub path: String,
+    /// From the bearer token, when it could be read
+    pub project_id: Option<String>,
+    pub status: u16,
+    /// The response's `error` message
+    pub reason: String,
+    /// The response's per-field `errors`, when it listed any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub errors: Option<serde_json::Value>,
+    pub content_type: Option<String>,
+    pub content_encoding: Option<String>,
+    pub user_agent: Option<String>,
+    /// The body as received: text, or base64 when binary; sealed whole
+    /// when `encryption` is set
+    pub body: String,
+    pub body_base64: bool,
+    /// How `body` was encrypted, when field-level encryption is on
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<FieldEncryption>,
+}
+
+impl RejectedPayload {
+    /// What's known of a request before it's handled; the body is copied,
+    /// since the handler consumes the request
+    pub fn capture(request: &Request) -> Self {
+        let (body, body_base64) = match request.body() {
+            Body::Text(text) => (text.clone(), false),
+            Body::Binary(bytes) => (base64::engine::general_purpose::STANDARD.encode(bytes), true),
+            Body::Empty => (String::new(), false),
+        };
+        let header = |name| header_value(request, name).map(String::from);
+        Self {
+            received_at: chrono::Utc::now().timestamp_millis(),
+            request_id: request.extensions().get::<RequestId>().map(|id| id.0.clone()),
+            method: request.method().to_string(),
+            path: request.uri().path().to_string(),
+            project_id: handlers::extract_jwt_info(request).ok().map(|(project_id, _)| project_id),
+            status: 0,
+            reason: String::new(),
+            errors: None,
+            content_type: header("content-type"),
+            content_encoding: header("content-encoding"),
+            user_agent: header("user-agent"),
+            body,
+            body_base64,
+            encryption: None,
+        }
+    }
+
+    /// The captured request with the response's reason, if the response
+    /// rejected it with a status whose body is kept
+    pub fn rejected_by(self, response: &Response<Body>) -> Option<Self> {
+        let status = response.status().as_u16();
+        if !QUARANTINED_STATUSES.contains(&status) {
+            return None;
+        }
+        let rejection: serde_json::Value = serde_json::from_slice(response.body().as_ref()).unwrap_or_default();
+        let reason = match rejection.get("error").and_then(|error| error.as_str()) {
+            Some(error) => error.to_string(),
+            None => String::from_utf8_lossy(response.body().as_ref()).into_owned(),
+        };
+        Some(Self {
+            status,
+            reason,
+            errors: rejection.get("errors").cloned(),
+            ..self
+        })
+    }
+
+    /// The payload with its body encrypted, if field-level encryption
+    /// covers its project or the project isn't known
+    pub async fn encrypt(mut self, state: &AppState) -> Result<Self, Error> {
+        let config = &state.config.field_encryption;
+        if self.project_id.as_ref().is_some_and(|project_id| !config.applies_to(project_id)) {
+            return Ok(self);
+        }
+        let project_id = self.project_id.clone().unwrap_or_default();
+        let body = serde_json::Value::String(std::mem::take(&mut self.body));
+        match encryption::seal(&body, &project_id, "body", state).await? {
+            Some((sealed, envelope)) => {
+                self.body = sealed;
+                self.encryption = Some(envelope);
+            }
+            None => self.body = body.as_str().unwrap_or_default().to_string(),
+        }
+        Ok(self)
+    }
+}
+
+/// Where rejected payloads are kept
+#[async_trait]
+pub trait PayloadQuarantine: Send + Sync {
+    async fn write(&self, rejected: &RejectedPayload) -> Result<(), Error>;
+}
+
+/// Writes rejected payloads to a Kinesis stream, keyed by project
+pub struct KinesisPayloadQuarantine {
+    client: KinesisClient,
+    stream_name: String,
+}
+
+impl KinesisPayloadQuarantine {
+    pub fn new(client: KinesisClient, stream_name: String) -> Self {
+        Self { client, stream_name }
+    }
+}
+
+#[async_trait]
+impl PayloadQuarantine for KinesisPayloadQuarantine {
+    async fn write(&self, rejected: &RejectedPayload) -> Result<(), Error> {
+        self.client
+            .put_record()
+            .stream_name(&self.stream_name)
+            .partition_key(rejected.project_id.as_deref().unwrap_or("unknown"))
+            .data(Blob::new(serde_json::to_vec(rejected)?))
+            .send()
+            .await?;
+        Ok(())
+    }
+}
+
+/// Writes each rejected payload to S3 as a JSON object
+pub struct S3PayloadQuarantine {
+    client: S3Client,
+    bucket: String,
+    prefix: String,
+}
+
+impl S3PayloadQuarantine {
+    pub fn new(client: S3Client, bucket: String, config: &PayloadQuarantineConfig) -> Self {
+        Self {
+            client,
+            bucket,
+            prefix: config.prefix.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl PayloadQuarantine for S3PayloadQuarantine {
+    async fn write(&self, rejected: &RejectedPayload) -> Result<(), Error> {
+        let now = chrono::Utc::now();
+        let key = format!(
+            "{}/dt={}/{}-{}.json",
+            self.prefix,
+            now.format("%Y-%m-%d"),
+            now.timestamp_millis(),
+            uuid::Uuid::new_v4()
+        );
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .content_type("application/json")
+            .body(ByteStream::from(serde_json::to_vec(rejected)?))
+            .send()
+            .await?;
+        Ok(())
+    }
+}
+
+/// Keeps rejected payloads in memory, for tests
+#[derive(Default)]
+pub struct InMemoryPayloadQuarantine {
+    pub payloads: Mutex<Vec<RejectedPayload>>,
+}
+
+#[async_trait]
+impl PayloadQuarantine for InMemoryPayloadQuarantine {
+    async fn write(&self, rejected: &RejectedPayload) -> Result<(), Error> {
+        self.payloads.lock().unwrap().push(rejected.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::function_handler;
+    use crate::shared::{test_state, Config};
+    use std::sync::Arc;
+
+    fn post(path: &str, body: &str) -> Request {
+        let claims = serde_json::json!({ "projectId": "proj" }).to_string();
+        let token = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(claims);
+        lambda_http::http::Request::builder()
+            .method("POST")
+            .uri(path)
+            .header("Authorization", format!("Bearer e30.{}.sig", token))
+            .header("User-Agent", "tracker/2.3.0")
+            .body(Body::Text(body.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_rejected_bodies_are_quarantined_with_their_reason() {
+        let quarantine = Arc::new(InMemoryPayloadQuarantine::default());
+        let mut state = test_state(Config::default());
+        state.payload_quarantine = Some(quarantine.clone());
+        let state = Arc::new(state);
+
+        let response = function_handler(post("/event", "{not json"), state.clone()).await.unwrap();
+        assert_eq!(response.status(), 400);
+        let invalid = r#"{"en":"","ts":1,"o":"","r":"","sw":1,"sh":1}"#;
+        let response = function_handler(post("/event", invalid), state).await.unwrap();
+        assert_eq!(response.status(), 422);
+
+        let payloads = quarantine.payloads.lock().unwrap();
+        assert_eq!(payloads.len(), 2);
+        assert_eq!(payloads[0].body, "{not json");
+        assert_eq!(payloads[0].project_id.as_deref(), Some("proj"));
+        assert_eq!(payloads[0].user_agent.as_deref(), Some("tracker/2.3.0"));
+        assert!(payloads[0].request_id.is_some());
+        assert_eq!(payloads[1].status, 422);
+        assert!(payloads[1].errors.as_ref().is_some_and(|errors| errors.is_array()));
+    }
+
+    #[tokio::test]
+    async fn test_bodies_are_encrypted_with_field_encryption() {
+        let quarantine = Arc::new(InMemoryPayloadQuarantine::default());
+        let mut config = Config::default();
+        config.field_encryption.kms_key_id = Some("alias/analytics".to_string());
+        let mut state = test_state(config);
+        state.payload_quarantine = Some(quarantine.clone());
+        let state = Arc::new(state);
+
+        let body = r#"{"en": "signup", "p": {"email": "jane@example.com"#;
+        let response = function_handler(post("/event", body), state).await.unwrap();
+        assert_eq!(response.status(), 400);
+
+        let payloads = quarantine.payloads.lock().unwrap();
+        assert!(!payloads[0].body.contains("jane@example.com"));
+        let envelope = payloads[0].encryption.as_ref().unwrap();
+        assert_eq!(envelope.fields, ["body"]);
+        // The test data keys aren't wrapped, so the "encrypted" key is the key
+        let data_key = base64::engine::general_purpose::STANDARD.decode(&envelope.encrypted_data_key).unwrap();
+        let decrypted = encryption::decrypt(&payloads[0].body, &data_key, "proj").unwrap();
+        assert_eq!(decrypted, body);
+    }
+
+    #[test]
+    fn test_accepted_and_unauthorized_requests_are_not_kept() {
+        let captured = RejectedPayload::capture(&post("/event", "{}"));
+        for status in [202, 401, 413, 500] {
+            let response = lambda_http::http::Response::builder()
+                .status(status)
+                .body(Body::Empty)
+                .unwrap();
+            assert_eq!(captured.clone().rejected_by(&response), None);
+        }
+    }
+}
@@ -0,0 +1,174 @@
+//! 1x1 GIF pixel tracking.
+//!
+//! With `PIXEL_TRACKING_ENABLED`, `GET /pixel.gif` records an event from its
+//! query string, for email opens and pages where JavaScript is blocked:
+//!
+//! - `k`: project token, since an `<img>` can't send an Authorization header
+//! - `en`: event name (default `pageview`)
+//! - `uid` / `aid`: user and anonymous ids
+//! - `u` / `r`: page url and referrer (default: the `Referer` header)
+//! - `ts`: client time in epoch milliseconds (default: server time)
+//! - anything else becomes a string property
+//!
+//! The answer is always the transparent GIF (with the status code of the
+//! outcome) and is never cached, so every open is counted. The route skips
+//! origin enforcement, because email clients send no origin.
+
+use lambda_http::{Body, Error, Request, Response, RequestExt};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::auth;
+use crate::handlers::ingest;
+use crate::jwt;
+use crate::models::{EventContext, IngestEventPayload, PageContext};
+use crate::shared::{header_value, AppState};
+
+/// Parameters with a meaning of their own, not copied into properties
+const RESERVED_PARAMS: &[&str] = &["k", "en", "uid", "aid", "u", "r", "ts"];
+
+/// Smallest transparent GIF
+pub const TRANSPARENT_GIF: [u8; 43] = [
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00,
+    0x00, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+/// The pixel, uncacheable
+pub fn gif_response(status_code: u16) -> Response<Body> {
+    Response::builder()
+        .status(status_code)
+        .header("Content-Type", "image/gif")
+        .header("Cache-Control", "no-store, no-cache, must-revalidate, max-age=0")
+        .header("Pragma", "no-cache")
+        .header("Expires", "0")
+        .body(Body::Binary(TRANSPARENT_GIF.to_vec()))
+        .unwrap()
+}
+
+/// Builds the event from the query string.
+/// Note: project_id should be extracted from the token, not the query
+pub fn normalize(request: &Request, project_id: String, user_id: Option<String>) -> IngestEventPayload {
+    let params = request.query_string_parameters_ref();
+    let param = |name: &str| {
+        params
+            .and_then(|params| params.first(name))
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(String::from)
+    };
+
+    let properties: HashMap<String, Value> = params
+        .map(|params| {
+            params
+                .iter()
+                .filter(|(name, _)| !RESERVED_PARAMS.contains(name))
+                .map(|(name, value)| (name.to_string(), Value::from(value)))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let url = param("u").or_else(|| header_value(request, "referer").map(String::from));
+    IngestEventPayload {
+        project_id,
+        event_type: param("en").unwrap_or_else(|| "pageview".to_string()),
+        timestamp: param("ts").and_then(|ts| ts.parse().ok()).unwrap_or(0), // Will be set by handler
+        user_id: param("uid").or(user_id),
+        anonymous_id: param("aid"),
+        properties: Some(properties),
+        context: Some(EventContext {
+            page: Some(PageContext {
+                url,
+                referrer: param("r"),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+/// Handler for GET /pixel.gif
+pub async fn handle_pixel(request: &Request, state: Arc<AppState>) -> Result<Response<Body>, Error> {
+    let auth = match request.query_string_parameters_ref().and_then(|params| params.first("k")) {
+        Some(token) => jwt::verify_write_key(token, &state).await,
+        None => Err("Missing k parameter".to_string()),
+    };
+    let (project_id, user_id) = match auth {
+        Ok(info) => info,
+        Err(e) => {
+            tracing::warn!("Rejecting pixel: {}", e);
+            return Ok(gif_response(401));
+        }
+    };
+    if let Some(rejection) = auth::check_api_key(request, &project_id, &state).await? {
+        return Ok(gif_response(rejection.status().as_u16()));
+    }
+
+    let normalized = normalize(request, project_id, user_id);
+    let response = ingest(normalized, request, state).await?;
+    let status = response.status().as_u16();
+    Ok(gif_response(if status < 300 { 200 } else { status }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::function_handler;
+    use crate::shared::{test_state, Config};
+    use crate::sink::RecordingSink;
+    use base64::Engine;
+
+    fn pixel(params: &[(&str, &str)]) -> Request {
+        let params: HashMap<String, Vec<String>> = params
+            .iter()
+            .map(|(name, value)| (name.to_string(), vec![value.to_string()]))
+            .collect();
+        lambda_http::http::Request::builder()
+            .method("GET")
+            .uri("/prod/pixel.gif")
+            .header("Referer", "https://mail.example.com/")
+            .body(Body::Empty)
+            .unwrap()
+            .with_query_string_parameters(params)
+    }
+
+    #[tokio::test]
+    async fn test_pixel_records_event_and_answers_with_uncached_gif() {
+        let mut config = Config {
+            pixel_tracking: true,
+            ..Default::default()
+        };
+        config.s3_parquet.projects = vec!["proj".to_string()];
+        let sink = Arc::new(RecordingSink::default());
+        let mut state = test_state(config);
+        state.parquet_sink = Some(sink.clone());
+        let state = Arc::new(state);
+
+        let claims = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(r#"{"projectId":"proj"}"#);
+        let token = format!("e30.{}.sig", claims);
+        let request = pixel(&[("k", &token), ("en", "email_open"), ("uid", "u1"), ("campaign", "spring")]);
+
+        let response = function_handler(request, state.clone()).await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["content-type"], "image/gif");
+        assert!(response.headers()["cache-control"].to_str().unwrap().contains("no-store"));
+        assert!(matches!(response.body(), Body::Binary(gif) if gif.starts_with(b"GIF89a")));
+
+        let event = sink.events.lock().unwrap()[0].clone();
+        assert_eq!(event.event_type, "email_open");
+        assert_eq!(event.user_id.as_deref(), Some("u1"));
+        let properties = event.properties.unwrap();
+        assert_eq!(properties["campaign"], "spring");
+        assert!(!properties.contains_key("k"));
+        let page = event.context.unwrap().page.unwrap();
+        assert_eq!(page.url.as_deref(), Some("https://mail.example.com/"));
+
+        // Still a GIF without a token, just not counted
+        let response = function_handler(pixel(&[("en", "email_open")]), state).await.unwrap();
+        assert_eq!(response.status(), 401);
+        assert_eq!(response.headers()["content-type"], "image/gif");
+        assert_eq!(sink.events.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,212 @@
+//! Output field projection.
+//!
+//! For data minimization, a project can declare the only fields allowed to
+//! reach the stream. Entries are dotted paths into the serialized record
+//! (`userId`, `properties.plan`, `context.page.url`); everything else is
+//! dropped just before serialization. The fields every consumer relies on
+//! are always kept.
+//!
+//! Every sink serializes through [`FieldProjection`], so the allowlist
+//! holds for the bus, queues and buckets as well as the stream.
+
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::io::Write;
+
+use crate::models::IngestEventPayload;
+use crate::shared::env_json;
+
+/// Fields kept regardless of the allowlist; without `encryption`, kept
+/// encrypted values couldn't be decrypted
+pub const REQUIRED_FIELDS: [&str; 4] = ["projectId", "eventType", "timestamp", "encryption"];
+
+/// Per-project field allowlists; projects without one are stored unchanged
+#[derive(Debug, Clone, Default)]
+pub struct FieldProjection {
+    pub projects: HashMap<String, Vec<String>>,
+}
+
+impl FieldProjection {
+    pub fn from_env() -> Self {
+        Self {
+            projects: env_json("OUTPUT_FIELD_ALLOWLIST").unwrap_or_default(),
+        }
+    }
+
+    /// Applies the project's allowlist to a serialized record
+    pub fn apply(&self, project_id: &str, record: Value) -> Value {
+        match self.projects.get(project_id) {
+            Some(allowed) => {
+                let paths = REQUIRED_FIELDS
+                    .iter()
+                    .copied()
+                    .chain(allowed.iter().map(String::as_str));
+                project(record, paths)
+            }
+            None => record,
+        }
+    }
+
+    fn is_projected(&self, event: &IngestEventPayload) -> bool {
+        self.projects.contains_key(&event.project_id)
+    }
+
+    /// An event as the JSON its project allows
+    pub fn to_value(&self, event: &IngestEventPayload) -> serde_json::Result<Value> {
+        Ok(self.apply(&event.project_id, serde_json::to_value(event)?))
+    }
+
+    /// Serializes an event with its project's allowlist applied
+    pub fn to_vec(&self, event: &IngestEventPayload) -> serde_json::Result<Vec<u8>> {
+        // Straight to bytes, unless the record is projected
+        if self.is_projected(event) {
+            serde_json::to_vec(&self.to_value(event)?)
+        } else {
+            serde_json::to_vec(event)
+        }
+    }
+
+    /// [`to_vec`](Self::to_vec), as a string
+    pub fn to_string(&self, event: &IngestEventPayload) -> serde_json::Result<String> {
+        if self.is_projected(event) {
+            serde_json::to_string(&self.to_value(event)?)
+        } else {
+            serde_json::to_string(event)
+        }
+    }
+
+    /// [`to_vec`](Self::to_vec), into a writer
+    pub fn to_writer(&self, writer: impl Write, event: &IngestEventPayload) -> serde_json::Result<()> {
+        if self.is_projected(event) {
+            serde_json::to_writer(writer, &self.to_value(event)?)
+        } else {
+            serde_json::to_writer(writer, event)
+        }
+    }
+}
+
+/// Keeps only the values at the given dotted paths
+pub fn project<'a>(record: Value, paths: impl IntoIterator<Item = &'a str>) -> Value {
+    let mut projected = Map::new();
+    let Value::Object(source) = record else {
+        return record;
+    };
+
+    for path in paths {
+        let segments: Vec<&str> = path.split('.').collect();
+        copy_path(&source, &mut projected, &segments);
+    }
+
+    Value::Object(projected)
+}
+
+fn copy_path(source: &Map<String, Value>, target: &mut Map<String, Value>, path: &[&str]) {
+    let Some((key, rest)) = path.split_first() else {
+        return;
+    };
+    let Some(value) = source.get(*key) else {
+        return;
+    };
+
+    match value {
+        _ if rest.is_empty() => {
+            target.insert(key.to_string(), value.clone());
+        }
+        Value::Object(child) => {
+            let entry = target
+                .entry(key.to_string())
+                .or_insert_with(|| Value::Object(Map::new()));
+            if let Value::Object(child_target) = entry {
+                copy_path(child, child_target, rest);
+            }
+        }
+        // Path continues into a non-object; nothing to keep
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::EventContext;
+
+    fn event() -> IngestEventPayload {
+        IngestEventPayload {
+            project_id: "strict".to_string(),
+            event_type: "signup".to_string(),
+            timestamp: 1_000,
+            user_id: Some("u1".to_string()),
+            properties: Some(HashMap::from([
+                ("plan".to_string(), serde_json::json!("pro")),
+                ("email".to_string(), serde_json::json!("jane@example.com")),
+            ])),
+            context: Some(EventContext {
+                ip: Some("203.0.113.7".to_string()),
+                locale: Some("en-US".to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_non_approved_fields_are_stripped() {
+        let projection = FieldProjection {
+            projects: HashMap::from([(
+                "strict".to_string(),
+                vec!["properties.plan".to_string(), "context.locale".to_string()],
+            )]),
+        };
+
+        let record = serde_json::to_value(event()).unwrap();
+        let projected = projection.apply("strict", record);
+
+        assert_eq!(
+            projected,
+            serde_json::json!({
+                "projectId": "strict",
+                "eventType": "signup",
+                "timestamp": 1_000,
+                "properties": {"plan": "pro"},
+                "context": {"locale": "en-US"},
+            })
+        );
+    }
+
+    #[test]
+    fn test_projects_without_allowlist_are_untouched() {
+        let projection = FieldProjection::default();
+        let record = serde_json::to_value(event()).unwrap();
+
+        assert_eq!(projection.apply("strict", record.clone()), record);
+    }
+
+    #[test]
+    fn test_serializers_apply_the_allowlist() {
+        let projection = FieldProjection {
+            projects: HashMap::from([("strict".to_string(), vec!["userId".to_string()])]),
+        };
+        let expected = serde_json::json!({
+            "projectId": "strict",
+            "eventType": "signup",
+            "timestamp": 1_000,
+            "userId": "u1",
+        });
+
+        assert_eq!(projection.to_value(&event()).unwrap(), expected);
+        let bytes = projection.to_vec(&event()).unwrap();
+        assert_eq!(serde_json::from_slice::<Value>(&bytes).unwrap(), expected);
+        let string = projection.to_string(&event()).unwrap();
+        assert_eq!(serde_json::from_str::<Value>(&string).unwrap(), expected);
+        let mut written = Vec::new();
+        projection.to_writer(&mut written, &event()).unwrap();
+        assert_eq!(written, bytes);
+
+        // Unprojected events serialize as they are
+        let open = IngestEventPayload {
+            project_id: "open".to_string(),
+            ..event()
+        };
+        assert_eq!(projection.to_vec(&open).unwrap(), serde_json::to_vec(&open).unwrap());
+    }
+}
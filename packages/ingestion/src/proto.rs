@@ -0,0 +1,164 @@
+//! Protobuf request bodies.
+//!
+//! `proto/events.proto` defines a compact, typed wire format for clients
+//! that send a lot of events. With `PROTOBUF_BODIES_ENABLED`, an
+//! `application/x-protobuf` body is decoded as the message its route takes
+//! (`PageViewEvent` on /view, `TrackEvent` on /event, `EventBatch` on
+//! /batch) and converted to the compressed events the JSON routes accept,
+//! so it goes through the same validation and normalization into
+//! [`IngestEventPayload`](crate::models::IngestEventPayload).
+
+use lambda_http::Request;
+use prost::Message;
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::models::{CompressedEvent, SentAt};
+use crate::shared::header_value;
+
+/// Types generated from `proto/events.proto`
+pub mod v1 {
+    include!(concat!(env!("OUT_DIR"), "/analytics.ingest.v1.rs"));
+}
+
+use v1::property_value::Kind;
+
+/// Whether the body is a protobuf message
+pub fn is_protobuf(request: &Request) -> bool {
+    header_value(request, "content-type").is_some_and(|ct| {
+        let ct = ct.trim_start().to_ascii_lowercase();
+        ct.starts_with("application/x-protobuf") || ct.starts_with("application/protobuf")
+    })
+}
+
+/// Properties as event data; unset values and non-finite numbers are dropped
+fn event_data(properties: HashMap<String, v1::PropertyValue>) -> Option<HashMap<String, Value>> {
+    if properties.is_empty() {
+        return None;
+    }
+    let data = properties
+        .into_iter()
+        .filter_map(|(key, value)| {
+            let value = match value.kind? {
+                Kind::StringValue(s) => Value::String(s),
+                Kind::NumberValue(n) => Value::Number(serde_json::Number::from_f64(n)?),
+                Kind::BoolValue(b) => Value::Bool(b),
+                Kind::IntValue(i) => Value::from(i),
+            };
+            Some((key, value))
+        })
+        .collect();
+    Some(data)
+}
+
+impl From<v1::TrackEvent> for CompressedEvent<'static> {
+    fn from(event: v1::TrackEvent) -> Self {
+        Self {
+            en: event.name.into(),
+            ts: event.timestamp,
+            o: event.url.into(),
+            r: event.referrer.into(),
+            sw: event.screen_width,
+            sh: event.screen_height,
+            ed: event_data(event.properties),
+            kind: None,
+            consent: None,
+            message_id: event.message_id,
+            sequence: event.sequence,
+            sent_at: event.sent_at.map(SentAt::Millis),
+        }
+    }
+}
+
+impl From<v1::PageViewEvent> for CompressedEvent<'static> {
+    fn from(event: v1::PageViewEvent) -> Self {
+        Self {
+            en: "pageview".into(),
+            ts: event.timestamp,
+            o: event.url.into(),
+            r: event.referrer.into(),
+            sw: event.screen_width,
+            sh: event.screen_height,
+            ed: event_data(event.properties),
+            kind: None,
+            consent: None,
+            message_id: event.message_id,
+            sequence: event.sequence,
+            sent_at: event.sent_at.map(SentAt::Millis),
+        }
+    }
+}
+
+/// Decodes a protobuf body as the message `path` takes and re-encodes it as
+/// the JSON body of that route. Fails with the status to answer with.
+pub fn to_json(path: &str, body: &[u8]) -> Result<Vec<u8>, (u16, String)> {
+    let invalid = |e: prost::DecodeError| (400, format!("Invalid protobuf in request body: {}", e));
+    let json = if path.ends_with("/view") {
+        serde_json::to_vec(&CompressedEvent::from(v1::PageViewEvent::decode(body).map_err(invalid)?))
+    } else if path.ends_with("/event") {
+        serde_json::to_vec(&CompressedEvent::from(v1::TrackEvent::decode(body).map_err(invalid)?))
+    } else if path.ends_with("/batch") {
+        let batch = v1::EventBatch::decode(body).map_err(invalid)?;
+        let events: Vec<CompressedEvent> = batch.events.into_iter().map(CompressedEvent::from).collect();
+        serde_json::to_vec(&events)
+    } else {
+        return Err((415, "Protobuf bodies are accepted on /view, /event and /batch".to_string()));
+    };
+    json.map_err(|e| (500, e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(kind: Kind) -> v1::PropertyValue {
+        v1::PropertyValue { kind: Some(kind) }
+    }
+
+    #[test]
+    fn test_track_event_converts_with_typed_properties() {
+        let event = v1::TrackEvent {
+            name: "level_completed".to_string(),
+            timestamp: 1_700_000_000_000,
+            url: "app://game/levels".to_string(),
+            properties: HashMap::from([
+                ("level".to_string(), value(Kind::IntValue(7))),
+                ("score".to_string(), value(Kind::NumberValue(98.5))),
+                ("hard".to_string(), value(Kind::BoolValue(true))),
+                ("nan".to_string(), value(Kind::NumberValue(f64::NAN))),
+                ("unset".to_string(), v1::PropertyValue { kind: None }),
+            ]),
+            message_id: Some("m-1".to_string()),
+            ..Default::default()
+        };
+        let json = to_json("/event", &event.encode_to_vec()).unwrap();
+        let compressed: CompressedEvent = serde_json::from_slice(&json).unwrap();
+        assert_eq!(compressed.en, "level_completed");
+        assert_eq!(compressed.ts, 1_700_000_000_000);
+        assert_eq!(compressed.message_id.as_deref(), Some("m-1"));
+        let data = compressed.ed.unwrap();
+        assert_eq!(data.len(), 3);
+        assert_eq!(data["level"], 7);
+        assert_eq!(data["score"], 98.5);
+        assert_eq!(data["hard"], true);
+    }
+
+    #[test]
+    fn test_routes_pick_their_message() {
+        let pageview = v1::PageViewEvent {
+            url: "https://shop.io/".to_string(),
+            ..Default::default()
+        };
+        let json: Value = serde_json::from_slice(&to_json("/view", &pageview.encode_to_vec()).unwrap()).unwrap();
+        assert_eq!(json["en"], "pageview");
+
+        let batch = v1::EventBatch {
+            events: vec![Default::default(), Default::default()],
+        };
+        let json: Value = serde_json::from_slice(&to_json("/batch", &batch.encode_to_vec()).unwrap()).unwrap();
+        assert_eq!(json.as_array().unwrap().len(), 2);
+
+        assert_eq!(to_json("/identify", &[]).unwrap_err().0, 415);
+        assert_eq!(to_json("/event", &[0xff]).unwrap_err().0, 400);
+    }
+}
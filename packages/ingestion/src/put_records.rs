@@ -0,0 +1,225 @@
+//! Batched stream writes.
+//!
+//! Events bound for one stream are written with `PutRecords`, split into
+//! requests within the API limits (500 records and 5 MiB, partition keys
+//! included). A request can partially fail; only its failed records are
+//! sent again, with the usual backoff and batch-wide budget from
+//! [`retry`](crate::retry). Throttled attempts are tallied on the budget,
+//! for [`backpressure`](crate::backpressure).
+
+use aws_sdk_kinesis::error::DisplayErrorContext;
+use aws_sdk_kinesis::primitives::Blob;
+use aws_sdk_kinesis::types::PutRecordsRequestEntry;
+use aws_sdk_kinesis::Client as KinesisClient;
+use lambda_http::Error;
+use std::ops::Range;
+use std::sync::Mutex;
+
+use crate::models::IngestEventPayload;
+use crate::retry::{self, RetryBudget, RetryConfig};
+use crate::partitioning::PartitionKey;
+
+/// Most records one `PutRecords` request may carry
+pub const MAX_RECORDS_PER_REQUEST: usize = 500;
+/// Most bytes (data plus partition keys) one `PutRecords` request may carry
+pub const MAX_BYTES_PER_REQUEST: usize = 5 * 1024 * 1024;
+/// Error code of a record refused for exceeding its shard's throughput
+const THROTTLED: &str = "ProvisionedThroughputExceededException";
+
+/// An event, its serialized form and where it goes, before it becomes a
+/// [`Record`]
+pub struct Serialized<'a> {
+    pub event: &'a IngestEventPayload,
+    pub key: PartitionKey,
+    pub data: Vec<u8>,
+}
+
+/// A stream record and the events it carries: one, or several when
+/// [aggregated](crate::aggregation)
+pub struct Record<'a> {
+    pub events: Vec<&'a IngestEventPayload>,
+    entry: PutRecordsRequestEntry,
+}
+
+impl<'a> Record<'a> {
+    pub fn new(serialized: Serialized<'a>) -> Result<Self, Error> {
+        Self::with_events(vec![serialized.event], &serialized.key, serialized.data)
+    }
+
+    /// A record carrying several events under one partition key
+    pub fn with_events(events: Vec<&'a IngestEventPayload>, key: &PartitionKey, data: Vec<u8>) -> Result<Self, Error> {
+        let entry = PutRecordsRequestEntry::builder()
+            .partition_key(&key.key)
+            .set_explicit_hash_key(key.explicit_hash_key.clone())
+            .data(Blob::new(data))
+            .build()?;
+        Ok(Self { events, entry })
+    }
+
+    pub fn data(&self) -> &[u8] {
+        self.entry.data().as_ref()
+    }
+
+    /// Size counted against the request limit
+    fn size(&self) -> usize {
+        self.entry.data().as_ref().len() + self.entry.partition_key().len()
+    }
+}
+
+/// A record that could not be written, by index, with the last error
+pub type Failure = (usize, String);
+
+/// Splits records of the given sizes into request-sized runs, keeping order
+pub fn chunks(sizes: &[usize]) -> Vec<Range<usize>> {
+    let mut chunks = Vec::new();
+    let (mut start, mut bytes) = (0, 0);
+    for (index, &size) in sizes.iter().enumerate() {
+        let full = index - start == MAX_RECORDS_PER_REQUEST || bytes + size > MAX_BYTES_PER_REQUEST;
+        if full && index > start {
+            chunks.push(start..index);
+            (start, bytes) = (index, 0);
+        }
+        bytes += size;
+    }
+    if start < sizes.len() {
+        chunks.push(start..sizes.len());
+    }
+    chunks
+}
+
+/// Writes records to a stream, returning those still failing once retries
+/// are exhausted
+pub async fn put_all(
+    client: &KinesisClient,
+    stream_name: &str,
+    records: &[Record<'_>],
+    config: &RetryConfig,
+    budget: &mut RetryBudget,
+) -> Vec<Failure> {
+    let sizes: Vec<usize> = records.iter().map(Record::size).collect();
+    let mut failures = Vec::new();
+    for chunk in chunks(&sizes) {
+        let start = chunk.start;
+        let failed = put_chunk(client, stream_name, &records[chunk], config, budget).await;
+        failures.extend(failed.into_iter().map(|(index, reason)| (start + index, reason)));
+    }
+    failures
+}
+
+/// Writes events to a stream as JSON, keyed by project, failing if any
+/// record still fails once retries are exhausted. For consumers that
+/// re-emit derived events.
+pub async fn put_events(
+    client: &KinesisClient,
+    stream_name: &str,
+    events: &[IngestEventPayload],
+    config: &RetryConfig,
+) -> Result<(), Error> {
+    let mut records = Vec::with_capacity(events.len());
+    for event in events {
+        let key = PartitionKey {
+            key: event.project_id.clone(),
+            explicit_hash_key: None,
+        };
+        let data = serde_json::to_vec(event)?;
+        records.push(Record::new(Serialized { event, key, data })?);
+    }
+    let mut budget = RetryBudget::new(config.budget);
+    let failures = put_all(client, stream_name, &records, config, &mut budget).await;
+    match failures.first() {
+        None => Ok(()),
+        Some((_, reason)) => {
+            Err(format!("Failed to write {} of {} records: {}", failures.len(), records.len(), reason).into())
+        }
+    }
+}
+
+/// Writes one request's worth of records, retrying only the failed ones
+async fn put_chunk(
+    client: &KinesisClient,
+    stream_name: &str,
+    records: &[Record<'_>],
+    config: &RetryConfig,
+    budget: &mut RetryBudget,
+) -> Vec<Failure> {
+    let pending = Mutex::new((0..records.len()).map(|i| (i, String::new())).collect::<Vec<_>>());
+    // Record attempts and how many were throttled, for backpressure
+    let attempts = Mutex::new((0, 0));
+
+    let _: Result<(), ()> = retry::with_retries(config, budget, || {
+        let (pending, attempts) = (&pending, &attempts);
+        async move {
+            let indices: Vec<usize> = pending.lock().unwrap().iter().map(|(i, _)| *i).collect();
+            let entries = indices.iter().map(|&i| records[i].entry.clone()).collect();
+
+            let result = client
+                .put_records()
+                .stream_name(stream_name)
+                .set_records(Some(entries))
+                .send()
+                .await;
+
+            let (failed, throttled): (Vec<Failure>, usize) = match result {
+                Ok(output) => {
+                    let failed: Vec<Failure> = indices
+                        .iter()
+                        .zip(output.records())
+                        .filter_map(|(&i, entry)| {
+                            let code = entry.error_code()?;
+                            Some((i, format!("{}: {}", code, entry.error_message().unwrap_or_default())))
+                        })
+                        .collect();
+                    let throttled = output
+                        .records()
+                        .iter()
+                        .filter(|entry| entry.error_code() == Some(THROTTLED))
+                        .count();
+                    (failed, throttled)
+                }
+                Err(e) => {
+                    let throttled = e
+                        .as_service_error()
+                        .is_some_and(|e| e.is_provisioned_throughput_exceeded_exception());
+                    let reason = DisplayErrorContext(&e).to_string();
+                    let failed = indices.iter().map(|&i| (i, reason.clone())).collect();
+                    (failed, if throttled { indices.len() } else { 0 })
+                }
+            };
+            {
+                let mut attempts = attempts.lock().unwrap();
+                attempts.0 += indices.len();
+                attempts.1 += throttled;
+            }
+
+            let done = failed.is_empty();
+            *pending.lock().unwrap() = failed;
+            if done { Ok(()) } else { Err(()) }
+        }
+    })
+    .await;
+
+    let (attempted, throttled) = attempts.into_inner().unwrap();
+    budget.record_attempts(attempted, throttled);
+    pending.into_inner().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunks_respect_record_count() {
+        assert_eq!(chunks(&[]), Vec::<Range<usize>>::new());
+        assert_eq!(chunks(&[10; 3]), vec![0..3]);
+        assert_eq!(chunks(&[10; 1001]), vec![0..500, 500..1000, 1000..1001]);
+    }
+
+    #[test]
+    fn test_chunks_respect_request_bytes() {
+        let mib = 1024 * 1024;
+
+        // Five 1 MiB records fill a request exactly; the sixth starts another
+        assert_eq!(chunks(&[mib; 6]), vec![0..5, 5..6]);
+        assert_eq!(chunks(&[3 * mib, 2 * mib + 1, 10]), vec![0..1, 1..3]);
+    }
+}
@@ -0,0 +1,507 @@
+//! Per-project and per-IP rate limiting.
+//!
+//! Each project draws from a token bucket (`burst` tokens, refilled at
+//! `per_second`); an event costs one token, and a batch costs its event
+//! count, capped at `burst` so a large batch can still get through on a
+//! full bucket. With `RATE_LIMIT_PER_IP_PER_SECOND` set, each client IP
+//! also draws from its own bucket first. Requests that find too few tokens
+//! get a 429 with `Retry-After`. Optionally, every response for a limited
+//! project carries `X-RateLimit-Limit`/`-Remaining`/`-Reset` so SDKs can
+//! throttle themselves.
+//!
+//! Buckets live in process memory, per Lambda sandbox, which bounds each
+//! sandbox but not the fleet. With `RATE_LIMIT_TABLE` set, a key also
+//! draws on a shared allowance of `per_second` × `RATE_LIMIT_WINDOW_SECS`
+//! per window in DynamoDB. Sandboxes lease it `RATE_LIMIT_LEASE_SIZE`
+//! tokens at a time, so the local bucket stays the fast path and the table
+//! sees one write per lease rather than per request. If the table can't be
+//! reached, requests are let through on the local bucket alone.
+
+use async_trait::async_trait;
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use lambda_http::{Body, Error, Response};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::shared::{create_error_response, env_flag, env_opt, env_or, env_var};
+
+/// Configuration for rate limiting
+#[derive(Debug, Clone)]
+pub struct RateLimitConfig {
+    pub enabled: bool,
+    /// Tokens added per second
+    pub per_second: f64,
+    /// Bucket capacity
+    pub burst: f64,
+    /// Return `X-RateLimit-*` headers
+    pub headers: bool,
+    /// Tokens added per second to each client IP's bucket; no per-IP limit when unset
+    pub per_ip_per_second: Option<f64>,
+    /// Capacity of each client IP's bucket
+    pub per_ip_burst: f64,
+    /// DynamoDB table holding the shared allowances
+    pub table_name: Option<String>,
+    /// Length of a shared allowance window
+    pub window_secs: u64,
+    /// Tokens a sandbox takes from the shared allowance at a time
+    pub lease_size: u64,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            per_second: 100.0,
+            burst: 200.0,
+            headers: false,
+            per_ip_per_second: None,
+            per_ip_burst: 20.0,
+            table_name: None,
+            window_secs: 60,
+            lease_size: 10,
+        }
+    }
+}
+
+impl RateLimitConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            enabled: env_flag("RATE_LIMIT_ENABLED"),
+            per_second: env_or("RATE_LIMIT_PER_SECOND", defaults.per_second).max(f64::MIN_POSITIVE),
+            burst: env_or("RATE_LIMIT_BURST", defaults.burst).max(1.0),
+            headers: env_flag("RATE_LIMIT_HEADERS_ENABLED"),
+            per_ip_per_second: env_opt::<f64>("RATE_LIMIT_PER_IP_PER_SECOND").map(|rate| rate.max(f64::MIN_POSITIVE)),
+            per_ip_burst: env_or("RATE_LIMIT_PER_IP_BURST", defaults.per_ip_burst).max(1.0),
+            table_name: env_var("RATE_LIMIT_TABLE"),
+            window_secs: env_or("RATE_LIMIT_WINDOW_SECS", defaults.window_secs).max(1),
+            lease_size: env_or("RATE_LIMIT_LEASE_SIZE", defaults.lease_size).max(1),
+        }
+    }
+
+    /// The limits for client IP buckets, when configured
+    fn per_ip(&self) -> Option<Self> {
+        self.per_ip_per_second.map(|per_second| Self {
+            per_second,
+            burst: self.per_ip_burst,
+            ..self.clone()
+        })
+    }
+
+    /// Tokens one key may take from the shared allowance per window
+    fn window_limit(&self) -> u64 {
+        ((self.per_second * self.window_secs as f64).ceil() as u64).max(1)
+    }
+}
+
+/// Outcome of a rate-limit check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decision {
+    pub allowed: bool,
+    /// Bucket capacity
+    pub limit: u64,
+    /// Whole tokens left after this request
+    pub remaining: u64,
+    /// Seconds until the bucket is full again
+    pub reset_secs: u64,
+    /// Seconds until this request would have enough tokens (0 when allowed)
+    pub retry_after_secs: u64,
+}
+
+/// Request extension where the handler that checked a request records the
+/// decision, for the middleware to add its headers to whatever the response
+/// turns out to be
+#[derive(Debug, Clone, Default)]
+pub struct RecordedDecision(Arc<Mutex<Option<Decision>>>);
+
+impl RecordedDecision {
+    pub fn record(&self, decision: Decision) {
+        *self.0.lock().unwrap() = Some(decision);
+    }
+
+    pub fn get(&self) -> Option<Decision> {
+        *self.0.lock().unwrap()
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Shared allowance leased by this sandbox for one window
+#[derive(Debug)]
+struct Lease {
+    window: u64,
+    tokens: u64,
+}
+
+/// Fleet-wide allowances, counted per key and window
+#[async_trait]
+pub trait AllowanceStore: Send + Sync {
+    /// Takes `amount` from the key's allowance for `window` if that keeps
+    /// its use within `limit`, returning whether it did
+    async fn take(&self, key: &str, window: u64, amount: u64, limit: u64) -> Result<bool, Error>;
+}
+
+/// In-memory implementation of [`AllowanceStore`], for tests
+#[derive(Debug, Default)]
+pub struct InMemoryAllowanceStore {
+    used: Mutex<HashMap<(String, u64), u64>>,
+}
+
+#[async_trait]
+impl AllowanceStore for InMemoryAllowanceStore {
+    async fn take(&self, key: &str, window: u64, amount: u64, limit: u64) -> Result<bool, Error> {
+        let mut used = self.used.lock().unwrap();
+        let used = used.entry((key.to_string(), window)).or_insert(0);
+        if *used + amount > limit {
+            return Ok(false);
+        }
+        *used += amount;
+        Ok(true)
+    }
+}
+
+/// DynamoDB-backed allowances
+/// Table schema: partition key `pk` (S, `key#window`), TTL attribute `expires_at` (N)
+pub struct DynamoAllowanceStore {
+    client: DynamoClient,
+    table_name: String,
+    window_secs: u64,
+}
+
+impl DynamoAllowanceStore {
+    pub fn new(client: DynamoClient, table_name: String, config: &RateLimitConfig) -> Self {
+        Self {
+            client,
+            table_name,
+            window_secs: config.window_secs,
+        }
+    }
+}
+
+#[async_trait]
+impl AllowanceStore for DynamoAllowanceStore {
+    async fn take(&self, key: &str, window: u64, amount: u64, limit: u64) -> Result<bool, Error> {
+        let Some(ceiling) = limit.checked_sub(amount) else {
+            return Ok(false);
+        };
+        let taken = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("pk", AttributeValue::S(format!("{}#{}", key, window)))
+            .update_expression("SET used = if_not_exists(used, :zero) + :amount, expires_at = :expires")
+            .condition_expression("attribute_not_exists(used) OR used <= :ceiling")
+            .expression_attribute_values(":zero", AttributeValue::N("0".to_string()))
+            .expression_attribute_values(":amount", AttributeValue::N(amount.to_string()))
+            .expression_attribute_values(":ceiling", AttributeValue::N(ceiling.to_string()))
+            .expression_attribute_values(":expires", AttributeValue::N((window + 2 * self.window_secs).to_string()))
+            .send()
+            .await;
+        match taken {
+            Ok(_) => Ok(true),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_conditional_check_failed_exception()) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Token buckets by project and client IP
+#[derive(Default)]
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<String, Bucket>>,
+    leases: Mutex<HashMap<String, Lease>>,
+    allowances: Option<Arc<dyn AllowanceStore>>,
+}
+
+impl RateLimiter {
+    /// A limiter that also draws on fleet-wide allowances
+    pub fn with_allowances(allowances: Arc<dyn AllowanceStore>) -> Self {
+        Self {
+            allowances: Some(allowances),
+            ..Default::default()
+        }
+    }
+
+    /// Takes `cost` tokens from the client IP's bucket, when per-IP limits
+    /// are configured, then from the project's. The first to deny decides.
+    pub async fn check(
+        &self,
+        config: &RateLimitConfig,
+        project_id: &str,
+        ip: Option<&str>,
+        cost: usize,
+    ) -> Decision {
+        if let (Some(ip_config), Some(ip)) = (config.per_ip(), ip) {
+            let decision = self.check_key(&ip_config, &format!("ip#{}", ip), cost).await;
+            if !decision.allowed {
+                return decision;
+            }
+        }
+        self.check_key(config, project_id, cost).await
+    }
+
+    async fn check_key(&self, config: &RateLimitConfig, key: &str, cost: usize) -> Decision {
+        let now = chrono::Utc::now().timestamp().max(0) as u64;
+        self.check_key_at(config, key, cost, now).await
+    }
+
+    /// [`Self::check_key`] at `now`, in seconds since the epoch
+    async fn check_key_at(&self, config: &RateLimitConfig, key: &str, cost: usize, now: u64) -> Decision {
+        let decision = self.check_at(config, key, cost, Instant::now());
+        if !decision.allowed {
+            return decision;
+        }
+        let Some(ref allowances) = self.allowances else {
+            return decision;
+        };
+
+        let window = now - now % config.window_secs;
+        let cost = (cost as f64).min(config.burst).ceil() as u64;
+        if self.take_leased(key, window, cost) {
+            return decision;
+        }
+
+        let amount = cost.max(config.lease_size);
+        match allowances.take(key, window, amount, config.window_limit()).await {
+            Ok(true) => {
+                let mut leases = self.leases.lock().unwrap();
+                let lease = leases.entry(key.to_string()).or_insert(Lease { window, tokens: 0 });
+                if lease.window != window {
+                    *lease = Lease { window, tokens: 0 };
+                }
+                lease.tokens += amount - cost;
+                decision
+            }
+            Ok(false) => Decision {
+                allowed: false,
+                remaining: 0,
+                retry_after_secs: window + config.window_secs - now,
+                ..decision
+            },
+            Err(e) => {
+                tracing::warn!("Failed to take shared rate limit allowance for {}: {}", key, e);
+                decision
+            }
+        }
+    }
+
+    /// Spends `cost` from this sandbox's lease for the window, if it covers it
+    fn take_leased(&self, key: &str, window: u64, cost: u64) -> bool {
+        let mut leases = self.leases.lock().unwrap();
+        match leases.get_mut(key) {
+            Some(lease) if lease.window == window && lease.tokens >= cost => {
+                lease.tokens -= cost;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn check_at(
+        &self,
+        config: &RateLimitConfig,
+        project_id: &str,
+        cost: usize,
+        now: Instant,
+    ) -> Decision {
+        let cost = (cost as f64).min(config.burst);
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(project_id.to_string()).or_insert(Bucket {
+            tokens: config.burst,
+            updated: now,
+        });
+
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * config.per_second).min(config.burst);
+        bucket.updated = now;
+
+        let allowed = bucket.tokens >= cost;
+        if allowed {
+            bucket.tokens -= cost;
+        }
+        let seconds = |tokens: f64| (tokens.max(0.0) / config.per_second).ceil() as u64;
+
+        Decision {
+            allowed,
+            limit: config.burst as u64,
+            remaining: bucket.tokens.floor() as u64,
+            reset_secs: seconds(config.burst - bucket.tokens),
+            retry_after_secs: if allowed { 0 } else { seconds(cost - bucket.tokens) },
+        }
+    }
+}
+
+/// Adds the `X-RateLimit-*` headers when configured
+pub fn with_headers(
+    mut response: Response<Body>,
+    config: &RateLimitConfig,
+    decision: &Decision,
+) -> Response<Body> {
+    if !config.headers {
+        return response;
+    }
+    let headers = response.headers_mut();
+    for (name, value) in [
+        ("X-RateLimit-Limit", decision.limit),
+        ("X-RateLimit-Remaining", decision.remaining),
+        ("X-RateLimit-Reset", decision.reset_secs),
+    ] {
+        headers.insert(name, value.into());
+    }
+    response
+}
+
+/// 429 for a request the bucket couldn't cover; the `X-RateLimit-*`
+/// headers come from the middleware
+pub fn too_many_requests(decision: &Decision) -> Response<Body> {
+    let mut response = create_error_response(429, "Rate limit exceeded");
+    response
+        .headers_mut()
+        .insert("Retry-After", decision.retry_after_secs.max(1).into());
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::function_handler;
+    use crate::shared::{test_state, AppState, Config};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn config() -> RateLimitConfig {
+        RateLimitConfig {
+            enabled: true,
+            per_second: 1.0,
+            burst: 2.0,
+            headers: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_bucket_refills_over_time() {
+        let limiter = RateLimiter::default();
+        let start = Instant::now();
+
+        assert!(limiter.check_at(&config(), "proj", 2, start).allowed);
+        let denied = limiter.check_at(&config(), "proj", 1, start);
+        assert!(!denied.allowed);
+        assert_eq!(denied.retry_after_secs, 1);
+
+        // Other projects have their own bucket
+        assert!(limiter.check_at(&config(), "other", 1, start).allowed);
+
+        let later = limiter.check_at(&config(), "proj", 1, start + Duration::from_millis(1500));
+        assert!(later.allowed);
+        assert_eq!(later.remaining, 0);
+        assert_eq!(later.reset_secs, 2);
+    }
+
+    #[tokio::test]
+    async fn test_client_ip_bucket_checked_first() {
+        let config = RateLimitConfig {
+            per_ip_per_second: Some(1.0),
+            per_ip_burst: 1.0,
+            ..config()
+        };
+        let limiter = RateLimiter::default();
+
+        assert!(limiter.check(&config, "proj", Some("10.0.0.1"), 1).await.allowed);
+        let denied = limiter.check(&config, "proj", Some("10.0.0.1"), 1).await;
+        assert!(!denied.allowed);
+        assert_eq!(denied.limit, 1);
+
+        // Another client still has room, and so does the project
+        let other = limiter.check(&config, "proj", Some("10.0.0.2"), 1).await;
+        assert!(other.allowed);
+        assert_eq!(other.remaining, 0);
+        assert!(!limiter.check(&config, "proj", None, 1).await.allowed);
+    }
+
+    #[tokio::test]
+    async fn test_sandboxes_share_the_window_allowance() {
+        let config = RateLimitConfig {
+            enabled: true,
+            per_second: 0.1,
+            burst: 10.0,
+            window_secs: 30,
+            lease_size: 2,
+            ..Default::default()
+        };
+        let store = Arc::new(InMemoryAllowanceStore::default());
+        let sandboxes = [
+            RateLimiter::with_allowances(store.clone()),
+            RateLimiter::with_allowances(store.clone()),
+        ];
+
+        let now = 1_700_000_020;
+        let check = |sandbox: usize, now: u64| sandboxes[sandbox].check_key_at(&config, "proj", 1, now);
+
+        // 3 tokens per window, leased 2 at a time: the first sandbox leases
+        // twice as much as it spends, the second can't get a full lease
+        assert!(check(0, now).await.allowed);
+        let denied = check(1, now).await;
+        assert!(!denied.allowed);
+        assert_eq!(denied.retry_after_secs, 20);
+        assert!(check(0, now).await.allowed);
+        assert!(!check(0, now).await.allowed);
+
+        // The next window starts afresh
+        assert!(check(1, now + 20).await.allowed);
+    }
+
+    fn track(project_id: &str) -> lambda_http::Request {
+        use base64::Engine;
+        let claims = serde_json::json!({ "projectId": project_id }).to_string();
+        let token = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(claims);
+        lambda_http::http::Request::builder()
+            .method("POST")
+            .uri("/event")
+            .header("Authorization", format!("Bearer e30.{}.sig", token))
+            .body(Body::Text(
+                r#"{"en":"signup","ts":1,"o":"https://a.io/","r":"","sw":1,"sh":1}"#.to_string(),
+            ))
+            .unwrap()
+    }
+
+    fn state() -> Arc<AppState> {
+        let mut config = Config {
+            rate_limit: config(),
+            ..Default::default()
+        };
+        config.s3_parquet.projects = vec!["proj".to_string()];
+        let mut state = test_state(config);
+        state.parquet_sink = Some(Arc::new(crate::sink::RecordingSink::default()));
+        Arc::new(state)
+    }
+
+    #[tokio::test]
+    async fn test_headers_track_remaining_tokens() {
+        let state = state();
+        let header = |response: &Response<Body>, name: &str| {
+            response.headers()[name].to_str().unwrap().to_string()
+        };
+
+        let first = function_handler(track("proj"), state.clone()).await.unwrap();
+        assert_eq!(first.status(), 202);
+        assert_eq!(header(&first, "X-RateLimit-Limit"), "2");
+        assert_eq!(header(&first, "X-RateLimit-Remaining"), "1");
+
+        let second = function_handler(track("proj"), state.clone()).await.unwrap();
+        assert_eq!(second.status(), 202);
+        assert_eq!(header(&second, "X-RateLimit-Remaining"), "0");
+
+        let limited = function_handler(track("proj"), state.clone()).await.unwrap();
+        assert_eq!(limited.status(), 429);
+        assert_eq!(header(&limited, "X-RateLimit-Remaining"), "0");
+        assert_eq!(header(&limited, "X-RateLimit-Reset"), "2");
+        assert_eq!(header(&limited, "Retry-After"), "1");
+    }
+}
@@ -0,0 +1,100 @@
+//! Request correlation IDs.
+//!
+//! Every request gets an ID: the client's `X-Request-Id` when it's a sane
+//! token, otherwise a fresh UUID. It's a field of the request span (so it
+//! appears on every log line), is echoed in the `X-Request-Id` response
+//! header and in error bodies as `requestId`, and is stamped into
+//! `context.extra.requestId` on each event, so a failed ingest a customer
+//! reports can be followed to the logs and the stream record.
+
+use lambda_http::{Body, Request, Response};
+
+use crate::shared::header_value;
+
+/// The correlation header, read from requests and set on responses
+pub const HEADER: &str = "x-request-id";
+
+/// Longest client-supplied ID that is kept
+const MAX_LEN: usize = 128;
+
+/// Request extension holding the request's correlation ID
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    /// The client's ID if it's short and made of token characters, so it
+    /// can't forge log fields or headers; otherwise a new one
+    pub fn from_request(request: &Request) -> Self {
+        let supplied = header_value(request, HEADER).filter(|id| {
+            !id.is_empty()
+                && id.len() <= MAX_LEN
+                && id.bytes().all(|b| b.is_ascii_alphanumeric() || b"-_.:".contains(&b))
+        });
+        Self(supplied.map_or_else(|| uuid::Uuid::new_v4().to_string(), String::from))
+    }
+
+    /// The ID of a request, when the router assigned one
+    pub fn of(request: &Request) -> Option<&str> {
+        request.extensions().get::<RequestId>().map(|id| id.0.as_str())
+    }
+}
+
+/// Echoes the ID in the response header and, for JSON error bodies, as
+/// `requestId` next to `error`
+pub fn stamp(mut response: Response<Body>, id: &RequestId) -> Response<Body> {
+    if response.status().as_u16() >= 400 {
+        if let Body::Text(text) = response.body() {
+            if let Ok(serde_json::Value::Object(mut body)) = serde_json::from_str(text) {
+                if body.contains_key("error") {
+                    body.insert("requestId".to_string(), serde_json::json!(id.0));
+                    *response.body_mut() = Body::Text(serde_json::Value::Object(body).to_string());
+                }
+            }
+        }
+    }
+    if let Ok(value) = id.0.parse() {
+        let headers = response.headers_mut();
+        headers.insert(HEADER, value);
+        headers.insert("access-control-expose-headers", http::HeaderValue::from_static("X-Request-Id"));
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::create_error_response;
+
+    fn request(id: Option<&str>) -> Request {
+        let mut builder = lambda_http::http::Request::builder().uri("/prod/event");
+        if let Some(id) = id {
+            builder = builder.header(HEADER, id);
+        }
+        builder.body(Body::Empty).unwrap()
+    }
+
+    #[test]
+    fn test_accepts_or_generates_ids() {
+        assert_eq!(RequestId::from_request(&request(Some("req-123_a.b:c"))).0, "req-123_a.b:c");
+
+        for rejected in [None, Some(""), Some("bad id"), Some("a\"b")] {
+            let id = RequestId::from_request(&request(rejected)).0;
+            assert!(uuid::Uuid::parse_str(&id).is_ok(), "{:?}", rejected);
+        }
+        let long = "a".repeat(MAX_LEN + 1);
+        assert_ne!(RequestId::from_request(&request(Some(&long))).0, long);
+    }
+
+    #[test]
+    fn test_stamps_errors_and_headers() {
+        let id = RequestId("req-1".to_string());
+        let response = stamp(create_error_response(400, "Invalid JSON"), &id);
+        assert_eq!(response.headers()[HEADER], "req-1");
+        let body: serde_json::Value = serde_json::from_slice(response.body().as_ref()).unwrap();
+        assert_eq!(body, serde_json::json!({"error": "Invalid JSON", "requestId": "req-1"}));
+
+        let response = stamp(crate::shared::create_response(202, serde_json::json!({"ok": true})), &id);
+        assert_eq!(response.headers()[HEADER], "req-1");
+        assert_eq!(response.body().as_ref(), br#"{"ok":true}"#);
+    }
+}
@@ -0,0 +1,137 @@
+//! Per-project data residency.
+//!
+//! Projects can be pinned to a residency zone (`"eu"`); their events then go
+//! to that zone's Kinesis stream instead of the default one. A pinned project
+//! whose zone has no stream configured fails closed: its events are refused
+//! rather than written somewhere they must not be stored.
+//!
+//! The ingest API can also be deployed in several regions, each writing to
+//! its own stream, so clients routed to the nearest region (latency-based
+//! DNS) are answered there. `INGEST_REGION` stamps each event with the
+//! deployment's region, and `packages/region-replicator` forwards a
+//! regional stream's events to the primary region's stream, and from there
+//! to the primary data lake, leaving out those of projects pinned to a zone.
+
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::shared::{env_json, env_var};
+
+/// Stream that holds a residency zone's events
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RegionalStream {
+    /// AWS region of the stream, e.g. `eu-central-1`
+    pub region: String,
+    pub stream_name: String,
+}
+
+/// Configuration for data residency routing
+#[derive(Debug, Clone, Default)]
+pub struct ResidencyConfig {
+    /// Residency zone by project id
+    pub project_zones: HashMap<String, String>,
+    /// Stream by residency zone
+    pub streams: HashMap<String, RegionalStream>,
+    /// Region stamped on events, for deployments outside the primary region
+    pub ingest_region: Option<String>,
+}
+
+impl ResidencyConfig {
+    pub fn from_env() -> Self {
+        Self {
+            project_zones: env_json("PROJECT_DATA_RESIDENCY").unwrap_or_default(),
+            streams: env_json("RESIDENCY_STREAMS").unwrap_or_default(),
+            ingest_region: env_var("INGEST_REGION").filter(|region| !region.is_empty()),
+        }
+    }
+
+    /// Residency zone a project's events must be written to; `None` for the
+    /// default stream. Errors when the zone has no stream configured.
+    pub fn zone_for(&self, project_id: &str) -> Result<Option<&str>, String> {
+        let Some(zone) = self.project_zones.get(project_id) else {
+            return Ok(None);
+        };
+        if !self.streams.contains_key(zone) {
+            return Err(format!(
+                "No stream configured for residency zone \"{}\" of project {}",
+                zone, project_id
+            ));
+        }
+        Ok(Some(zone))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> ResidencyConfig {
+        ResidencyConfig {
+            project_zones: HashMap::from([
+                ("acme-eu".to_string(), "eu".to_string()),
+                ("globex-ch".to_string(), "ch".to_string()),
+            ]),
+            streams: serde_json::from_str(
+                r#"{"eu": {"region": "eu-central-1", "streamName": "events-eu"}}"#,
+            )
+            .unwrap(),
+            ingest_region: None,
+        }
+    }
+
+    #[test]
+    fn test_eu_project_routes_to_eu_stream() {
+        let config = config();
+        assert_eq!(config.zone_for("acme-eu"), Ok(Some("eu")));
+        assert_eq!(config.streams["eu"].stream_name, "events-eu");
+    }
+
+    #[test]
+    fn test_default_project_uses_default_stream() {
+        assert_eq!(config().zone_for("startup"), Ok(None));
+    }
+
+    #[test]
+    fn test_unconfigured_zone_fails_closed() {
+        assert!(config().zone_for("globex-ch").is_err());
+    }
+
+    #[test]
+    fn test_events_are_stamped_with_the_ingest_region() {
+        use crate::handlers::enrich_event;
+        use crate::models::IngestEventPayload;
+        use crate::shared::Config;
+
+        let request = lambda_http::http::Request::builder()
+            .body(lambda_http::Body::Empty)
+            .unwrap();
+        let mut config = Config::default();
+        let event = enrich_event(IngestEventPayload::default(), &request, &config);
+        assert_eq!(event.region, None);
+
+        config.residency.ingest_region = Some("eu-central-1".to_string());
+        let event = enrich_event(IngestEventPayload::default(), &request, &config);
+        assert_eq!(event.region.as_deref(), Some("eu-central-1"));
+    }
+
+    #[tokio::test]
+    async fn test_process_events_refuses_unroutable_project() {
+        use crate::models::IngestEventPayload;
+        use crate::shared::{process_events, test_state, Config};
+        use std::sync::Arc;
+
+        // Zone configured but no regional client built for it
+        let state = Arc::new(test_state(Config {
+            residency: config(),
+            ..Default::default()
+        }));
+        let event = IngestEventPayload {
+            project_id: "acme-eu".to_string(),
+            ..Default::default()
+        };
+
+        let error = process_events(vec![event], state).await.unwrap_err();
+        assert!(error.to_string().contains("residency zone"));
+    }
+}
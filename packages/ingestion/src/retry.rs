@@ -0,0 +1,205 @@
+//! Retries for stream writes under a shared time budget.
+//!
+//! Every record in a batch may be retried with jittered exponential backoff
+//! (for shard throttling in particular), but all
+//! of them draw from one [`RetryBudget`]. Once the budget is spent, further
+//! failures are returned immediately (and dead-lettered by the caller), so a
+//! large failing batch can't retry its way past the Lambda deadline.
+
+use std::future::Future;
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::shared::env_or;
+
+/// Configuration for write retries
+#[derive(Debug, Clone)]
+pub struct RetryConfig {
+    /// Attempts per record, including the first
+    pub max_attempts: u32,
+    /// Backoff before the first retry; doubles on each further retry
+    pub base_delay: Duration,
+    /// Sleep a random fraction of each backoff, so throttled writers don't
+    /// retry in lockstep
+    pub jitter: bool,
+    /// Total time all records of one batch may spend retrying
+    pub budget: Duration,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(50),
+            jitter: true,
+            budget: Duration::from_secs(2),
+        }
+    }
+}
+
+impl RetryConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            max_attempts: env_or("RETRY_MAX_ATTEMPTS", defaults.max_attempts).max(1),
+            base_delay: Duration::from_millis(env_or(
+                "RETRY_BASE_DELAY_MS",
+                defaults.base_delay.as_millis() as u64,
+            )),
+            jitter: env_or("RETRY_JITTER", defaults.jitter),
+            budget: Duration::from_millis(env_or(
+                "RETRY_BUDGET_MS",
+                defaults.budget.as_millis() as u64,
+            )),
+        }
+    }
+}
+
+/// Retry time left for the current batch, and how many of its record
+/// attempts were throttled so far
+#[derive(Debug)]
+pub struct RetryBudget {
+    remaining: Duration,
+    attempts: usize,
+    throttled: usize,
+}
+
+impl RetryBudget {
+    pub fn new(total: Duration) -> Self {
+        Self {
+            remaining: total,
+            attempts: 0,
+            throttled: 0,
+        }
+    }
+
+    /// Counts record attempts, `throttled` of them throttled
+    pub fn record_attempts(&mut self, attempts: usize, throttled: usize) {
+        self.attempts += attempts;
+        self.throttled += throttled;
+    }
+
+    /// Record attempts and throttled attempts since the last call
+    pub fn take_attempts(&mut self) -> (usize, usize) {
+        (std::mem::take(&mut self.attempts), std::mem::take(&mut self.throttled))
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.remaining.is_zero()
+    }
+
+    fn spend(&mut self, elapsed: Duration) {
+        self.remaining = self.remaining.saturating_sub(elapsed);
+    }
+}
+
+/// Backoff before the given retry (1-based): exponential, and with jitter
+/// anywhere between zero and that
+fn backoff(config: &RetryConfig, attempt: u32) -> Duration {
+    let delay = config.base_delay.saturating_mul(1 << (attempt - 1).min(16));
+    if config.jitter {
+        delay.mul_f64(fastrand::f64())
+    } else {
+        delay
+    }
+}
+
+/// Runs `operation`, retrying failures with backoff while attempts and the
+/// shared budget last. Time spent on retries (backoff plus the retried call)
+/// is charged to the budget; the first attempt is free.
+pub async fn with_retries<T, E, F, Fut>(
+    config: &RetryConfig,
+    budget: &mut RetryBudget,
+    mut operation: F,
+) -> Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut result = operation().await;
+
+    for attempt in 1..config.max_attempts {
+        if result.is_ok() || budget.is_exhausted() {
+            break;
+        }
+
+        let started = Instant::now();
+        tokio::time::sleep(backoff(config, attempt).min(budget.remaining)).await;
+        result = operation().await;
+        budget.spend(started.elapsed());
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[tokio::test(start_paused = true)]
+    async fn test_budget_caps_total_retry_attempts() {
+        let config = RetryConfig {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(10),
+            jitter: false,
+            budget: Duration::from_millis(45),
+        };
+        let mut budget = RetryBudget::new(config.budget);
+        let attempts = AtomicU32::new(0);
+
+        // Ten always-failing records would make 50 attempts without a budget
+        for _ in 0..10 {
+            let result: Result<(), &str> = with_retries(&config, &mut budget, || async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err("throttled")
+            })
+            .await;
+            assert!(result.is_err());
+        }
+
+        // 10ms + 20ms + 15ms (capped) of retries for the first record, then
+        // only first attempts for the other nine
+        assert!(budget.is_exhausted());
+        assert_eq!(attempts.load(Ordering::SeqCst), 4 + 9);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_success_stops_retrying() {
+        let config = RetryConfig {
+            base_delay: Duration::from_millis(1),
+            ..Default::default()
+        };
+        let mut budget = RetryBudget::new(config.budget);
+        let attempts = AtomicU32::new(0);
+
+        let result: Result<u32, &str> = with_retries(&config, &mut budget, || async {
+            match attempts.fetch_add(1, Ordering::SeqCst) {
+                0 => Err("throttled"),
+                n => Ok(n),
+            }
+        })
+        .await;
+
+        assert_eq!(result, Ok(1));
+        assert!(!budget.is_exhausted());
+    }
+
+    #[test]
+    fn test_jittered_backoff_stays_within_exponential_bound() {
+        let config = RetryConfig {
+            base_delay: Duration::from_millis(100),
+            ..Default::default()
+        };
+
+        for attempt in 1..=4 {
+            let bound = Duration::from_millis(100 << (attempt - 1));
+            let delays: Vec<_> = (0..50).map(|_| backoff(&config, attempt)).collect();
+            assert!(delays.iter().all(|d| *d <= bound));
+            assert!(delays.iter().any(|d| *d != delays[0]));
+        }
+
+        let config = RetryConfig { jitter: false, ..config };
+        assert_eq!(backoff(&config, 3), Duration::from_millis(400));
+    }
+}
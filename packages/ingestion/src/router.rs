@@ -0,0 +1,493 @@
+//! Request routing: endpoints to their handlers, behind the middleware
+//! stack
+
+use lambda_http::tower::util::BoxCloneService;
+use lambda_http::tower::{service_fn, ServiceExt};
+use lambda_http::{Body, Error, Request, Response};
+use std::borrow::Cow;
+use std::sync::Arc;
+
+use crate::admin;
+use crate::body;
+use crate::deletion;
+use crate::handlers;
+use crate::health;
+use crate::middleware::{self, HttpService};
+use crate::openapi;
+use crate::pixel;
+use crate::proto;
+use crate::routes::{Endpoint, Resolved};
+use crate::segment;
+use crate::status;
+use crate::shared::{create_error_response, AppState};
+
+/// The Lambda service: [`route`] behind the middleware stack
+pub fn service(state: Arc<AppState>) -> HttpService {
+    let fallback = state.clone();
+    let router = service_fn(move |request: Request| {
+        let state = middleware::current_state(&request, &fallback);
+        async move { route(&request, state).await }
+    });
+    middleware::stack(&state, BoxCloneService::new(router))
+}
+
+/// Main Lambda handler, for a single request
+pub async fn function_handler(event: Request, state: Arc<AppState>) -> Result<Response<Body>, Error> {
+    service(state).oneshot(event).await
+}
+
+/// Routes a request to its handler
+async fn route(event: &Request, state: Arc<AppState>) -> Result<Response<Body>, Error> {
+    let resolved = event.extensions().get::<Resolved>().unwrap_or(&Resolved::NotFound);
+    let matched = match resolved {
+        Resolved::Found(matched) => Some(matched),
+        _ => None,
+    };
+    let endpoint = matched.map(|matched| matched.endpoint);
+    let param = |name: &str| matched.and_then(|matched| matched.params.get(name));
+
+    match endpoint {
+        // Probes come from infrastructure, not browsers, so skip origin checks
+        Some(Endpoint::Probe(probe)) if state.config.health_probes => {
+            return Ok(health::handle_probe(probe, state).await);
+        }
+        Some(Endpoint::Probe(_)) => return Ok(create_error_response(404, "Not found")),
+        // Public documentation, fetched by tooling rather than browsers
+        Some(Endpoint::OpenApi) => return Ok(openapi::handle_openapi()),
+        // Operator calls, authorized by the admin token rather than a JWT
+        Some(Endpoint::RefreshConfig) => return admin::handle_refresh(event, &state).await,
+        Some(Endpoint::DeleteUser) => {
+            let user_id = param("userId").unwrap_or_default().to_string();
+            return deletion::handle_deletion(event, user_id, &state).await;
+        }
+        // Embedded in emails, which send no origin
+        Some(Endpoint::Pixel) if state.config.pixel_tracking => {
+            return pixel::handle_pixel(event, state).await;
+        }
+        Some(Endpoint::Pixel) => return Ok(create_error_response(404, "Not found")),
+        _ => {}
+    }
+
+    if !state.config.origin_policy.permits(event) {
+        tracing::warn!("Rejecting request from disallowed origin");
+        return Ok(create_error_response(403, "Origin not allowed"));
+    }
+    if let Some(rejection) = resolved.rejection() {
+        return Ok(rejection);
+    }
+
+    // Body-less lookups
+    if endpoint == Some(Endpoint::Status) {
+        return status::handle_status(param("eventId").unwrap_or_default(), state).await;
+    }
+
+    // Extract path
+    let path = event.uri().path();
+
+    // Parse request body
+    let mut body: Cow<[u8]> = match event.body() {
+        Body::Text(s) => Cow::Borrowed(s.as_bytes()),
+        Body::Binary(b) => Cow::Borrowed(b.as_slice()),
+        Body::Empty => {
+            tracing::warn!("Received empty body");
+            return Ok(create_error_response(400, "Missing request body"));
+        }
+    };
+
+    // Chunk framing wraps the (possibly compressed) body, so it comes off first
+    if state.config.chunked_body_checks {
+        match body::complete_body(event, &body) {
+            Ok(Some(decoded)) => body = Cow::Owned(decoded),
+            Ok(None) => {}
+            Err(e) => {
+                tracing::warn!("Rejecting incomplete body: {}", e);
+                return Ok(create_error_response(400, &e));
+            }
+        }
+    }
+
+    if state.config.decompress_bodies {
+        match body::decompress(event, &body, state.config.json_limits.max_body_bytes) {
+            Ok(Some(decoded)) => body = Cow::Owned(decoded),
+            Ok(None) => {}
+            Err((status, e)) => {
+                tracing::warn!("Rejecting compressed body: {}", e);
+                return Ok(create_error_response(status, &e));
+            }
+        }
+    }
+
+    if state.config.msgpack_bodies && body::is_msgpack(event) {
+        match body::msgpack_to_json(&body) {
+            Ok(json) => body = Cow::Owned(json),
+            Err(e) => {
+                tracing::warn!("Rejecting MessagePack body: {}", e);
+                return Ok(create_error_response(400, &e));
+            }
+        }
+    }
+
+    if state.config.protobuf_bodies && proto::is_protobuf(event) {
+        match proto::to_json(path, &body) {
+            Ok(json) => body = Cow::Owned(json),
+            Err((status, e)) => {
+                tracing::warn!("Rejecting protobuf body: {}", e);
+                return Ok(create_error_response(status, &e));
+            }
+        }
+    }
+
+    let body_str = std::str::from_utf8(&body)?;
+    tracing::debug!("Received body: {}", body_str);
+
+    // Before the native routes, which `/v1/identify` and `/v1/batch` would also match
+    if let Some(call) = segment::route(path).filter(|_| state.config.segment_compat) {
+        return segment::handle(call, body_str, event, state.clone()).await;
+    }
+
+    // The handlers read the version themselves
+    match endpoint {
+        _ if state.config.cloudevents_enabled
+            && (endpoint == Some(Endpoint::CloudEvents) || handlers::is_cloud_event(event)) =>
+        {
+            handlers::handle_cloud_event(body_str, event, state.clone()).await
+        }
+        Some(Endpoint::View) => {
+            handlers::handle_page_view(body_str, event, state.clone()).await
+        }
+        Some(Endpoint::Event) => {
+            handlers::handle_track(body_str, event, state.clone()).await
+        }
+        Some(Endpoint::Identify) => {
+            handlers::handle_identify(body_str, event, state.clone()).await
+        }
+        Some(Endpoint::Group) => {
+            handlers::handle_group(body_str, event, state.clone()).await
+        }
+        Some(Endpoint::Alias) => {
+            handlers::handle_alias(body_str, event, state.clone()).await
+        }
+        Some(Endpoint::Vitals) => {
+            handlers::handle_vitals(body_str, event, state.clone()).await
+        }
+        Some(Endpoint::Errors) => {
+            handlers::handle_error(body_str, event, state.clone()).await
+        }
+        Some(Endpoint::Heartbeat) => {
+            handlers::handle_heartbeat(body_str, event, state.clone()).await
+        }
+        Some(Endpoint::Click) => {
+            handlers::handle_click(body_str, event, state.clone()).await
+        }
+        Some(Endpoint::ScrollDepth) => {
+            handlers::handle_scroll_depth(body_str, event, state.clone()).await
+        }
+        Some(Endpoint::Exposure) => {
+            handlers::handle_exposure(body_str, event, state.clone()).await
+        }
+        Some(Endpoint::Screen) => {
+            handlers::handle_screen(body_str, event, state.clone()).await
+        }
+        Some(Endpoint::Batch) => {
+            handlers::handle_batch(body_str, event, state.clone()).await
+        }
+        _ => Ok(create_error_response(404, "Not found")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::origin::OriginPolicy;
+    use crate::shared::{test_state, Config};
+
+    fn preflight() -> Request {
+        lambda_http::http::Request::builder()
+            .method("OPTIONS")
+            .uri("/view")
+            .body(Body::Empty)
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_first_request_is_cold_then_warm() {
+        let state = Arc::new(test_state(Config {
+            cold_start_tracking: true,
+            ..Default::default()
+        }));
+
+        let first = function_handler(preflight(), state.clone()).await.unwrap();
+        let second = function_handler(preflight(), state.clone()).await.unwrap();
+        let third = function_handler(preflight(), state).await.unwrap();
+
+        let timing = |response: &Response<Body>| {
+            response.headers()["server-timing"].to_str().unwrap().to_string()
+        };
+        assert!(timing(&first).starts_with("cold-start;desc=\"true\""));
+        assert!(timing(&second).starts_with("cold-start;desc=\"false\""));
+        assert!(timing(&third).starts_with("cold-start;desc=\"false\""));
+    }
+
+    #[tokio::test]
+    async fn test_server_timing_omitted_when_disabled() {
+        let state = Arc::new(test_state(Config::default()));
+
+        let response = function_handler(preflight(), state).await.unwrap();
+        assert!(!response.headers().contains_key("server-timing"));
+    }
+
+    #[tokio::test]
+    async fn test_echoes_the_request_id() {
+        let state = Arc::new(test_state(Config::default()));
+
+        let response = function_handler(post_from(&[("X-Request-Id", "support-42")]), state.clone()).await.unwrap();
+        assert_eq!(response.headers()["x-request-id"], "support-42");
+        let body: serde_json::Value = serde_json::from_slice(response.body().as_ref()).unwrap();
+        assert_eq!(body["requestId"], "support-42");
+
+        let response = function_handler(preflight(), state).await.unwrap();
+        assert!(response.headers().contains_key("x-request-id"));
+    }
+
+    fn post_from(headers: &[(&str, &str)]) -> Request {
+        let mut builder = lambda_http::http::Request::builder()
+            .method("POST")
+            .uri("/unknown");
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        builder.body(Body::Text("{}".to_string())).unwrap()
+    }
+
+    fn enforcing_state() -> Arc<AppState> {
+        Arc::new(test_state(Config {
+            origin_policy: OriginPolicy {
+                enforce: true,
+                allowed: vec!["https://app.example.com".to_string()],
+            },
+            ..Default::default()
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_allowed_origin_passes_through() {
+        let state = enforcing_state();
+
+        // Past the origin check, so routing answers 404 for the unknown path
+        let by_origin = post_from(&[("Origin", "https://app.example.com")]);
+        let response = function_handler(by_origin, state.clone()).await.unwrap();
+        assert_eq!(response.status(), 404);
+
+        let by_referer = post_from(&[("Referer", "https://app.example.com/pricing?x=1")]);
+        let response = function_handler(by_referer, state).await.unwrap();
+        assert_eq!(response.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_disallowed_origin_is_forbidden() {
+        let state = enforcing_state();
+
+        for headers in [
+            vec![("Origin", "https://evil.example.net")],
+            vec![("Referer", "https://evil.example.net/page")],
+            vec![],
+        ] {
+            let response = function_handler(post_from(&headers), state.clone()).await.unwrap();
+            assert_eq!(response.status(), 403, "{:?}", headers);
+        }
+
+        // Preflights are left to CORS
+        let response = function_handler(preflight(), state).await.unwrap();
+        assert_eq!(response.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_chunked_and_truncated_bodies() {
+        let state = Arc::new(test_state(Config {
+            chunked_body_checks: true,
+            ..Default::default()
+        }));
+        let post = |headers: &[(&str, &str)], body: &str| {
+            let mut builder = lambda_http::http::Request::builder()
+                .method("POST")
+                .uri("/batch");
+            for (name, value) in headers {
+                builder = builder.header(*name, *value);
+            }
+            builder.body(Body::Text(body.to_string())).unwrap()
+        };
+        let error = |response: &Response<Body>| match response.body() {
+            Body::Text(body) => body.clone(),
+            other => panic!("unexpected body: {:?}", other),
+        };
+
+        // A decoded chunked body reaches the handler, which then wants auth
+        let chunked = post(&[("Transfer-Encoding", "chunked")], "3\r\n[{}\r\n1\r\n]\r\n0\r\n\r\n");
+        let response = function_handler(chunked, state.clone()).await.unwrap();
+        assert_eq!(response.status(), 401);
+
+        let truncated = post(&[("Transfer-Encoding", "chunked")], "3\r\n[{}\r\n5\r\n]");
+        let response = function_handler(truncated, state.clone()).await.unwrap();
+        assert_eq!(response.status(), 400);
+        assert!(error(&response).contains("incomplete"));
+
+        let short = post(&[("Content-Length", "100")], "[{}]");
+        let response = function_handler(short, state).await.unwrap();
+        assert_eq!(response.status(), 400);
+        assert!(error(&response).contains("incomplete"));
+    }
+
+    #[tokio::test]
+    async fn test_compressed_bodies_are_decoded_before_routing() {
+        use std::io::Write;
+
+        let state = Arc::new(test_state(Config {
+            decompress_bodies: true,
+            ..Default::default()
+        }));
+        let post = |body: Vec<u8>| {
+            lambda_http::http::Request::builder()
+                .method("POST")
+                .uri("/batch")
+                .header("Content-Encoding", "gzip")
+                .body(Body::Binary(body))
+                .unwrap()
+        };
+
+        // Decoded, so the handler gets far enough to ask for auth
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(b"[{}]").unwrap();
+        let response = function_handler(post(gzip.finish().unwrap()), state.clone()).await.unwrap();
+        assert_eq!(response.status(), 401);
+
+        let response = function_handler(post(b"[{}]".to_vec()), state).await.unwrap();
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_msgpack_bodies_are_transcoded_before_routing() {
+        let state = Arc::new(test_state(Config {
+            msgpack_bodies: true,
+            ..Default::default()
+        }));
+        let post = |body: Vec<u8>| {
+            lambda_http::http::Request::builder()
+                .method("POST")
+                .uri("/batch")
+                .header("Content-Type", "application/msgpack")
+                .body(Body::Binary(body))
+                .unwrap()
+        };
+
+        // Transcoded, so the handler gets far enough to ask for auth
+        let packed = rmp_serde::to_vec_named(&serde_json::json!([{"en": "pageview"}])).unwrap();
+        let response = function_handler(post(packed), state.clone()).await.unwrap();
+        assert_eq!(response.status(), 401);
+
+        let response = function_handler(post(vec![0xc1]), state).await.unwrap();
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_protobuf_bodies_are_converted_before_routing() {
+        use prost::Message;
+
+        let state = Arc::new(test_state(Config {
+            protobuf_bodies: true,
+            ..Default::default()
+        }));
+        let post = |uri: &str, body: Vec<u8>| {
+            lambda_http::http::Request::builder()
+                .method("POST")
+                .uri(uri)
+                .header("Content-Type", "application/x-protobuf")
+                .body(Body::Binary(body))
+                .unwrap()
+        };
+
+        // Converted, so the handler gets far enough to ask for auth
+        let event = proto::v1::TrackEvent {
+            name: "level_completed".to_string(),
+            ..Default::default()
+        };
+        let response = function_handler(post("/event", event.encode_to_vec()), state.clone()).await.unwrap();
+        assert_eq!(response.status(), 401);
+
+        let response = function_handler(post("/identify", event.encode_to_vec()), state).await.unwrap();
+        assert_eq!(response.status(), 415);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_body_is_413() {
+        let mut config = Config::default();
+        config.json_limits.max_body_bytes = 16;
+        let state = Arc::new(test_state(config));
+        let request = lambda_http::http::Request::builder()
+            .method("POST")
+            .uri("/batch")
+            .body(Body::Text(format!("[{{\"en\": \"{}\"}}]", "x".repeat(32))))
+            .unwrap();
+
+        let response = function_handler(request, state).await.unwrap();
+        assert_eq!(response.status(), 413);
+    }
+
+    #[tokio::test]
+    async fn test_versions_share_handlers_but_not_semantics() {
+        use base64::Engine;
+        let claims = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(r#"{"projectId":"proj"}"#);
+        let mut config = Config::default();
+        config.s3_parquet.projects = vec!["proj".to_string()];
+        let mut state = test_state(config);
+        state.parquet_sink = Some(Arc::new(crate::sink::RecordingSink::default()));
+        let state = Arc::new(state);
+        let track = |path: &str, ts: i64| {
+            lambda_http::http::Request::builder()
+                .method("POST")
+                .uri(path)
+                .header("Authorization", format!("Bearer e30.{}.sig", claims))
+                .body(Body::Text(
+                    serde_json::json!({"en": "signup", "ts": ts, "o": "https://a.io/", "r": "", "sw": 1, "sh": 1})
+                        .to_string(),
+                ))
+                .unwrap()
+        };
+
+        let v1 = function_handler(track("/event", 0), state.clone()).await.unwrap();
+        assert_eq!(v1.status(), 202);
+        assert_eq!(v1.body().as_ref(), b"ACCEPTED");
+        assert_eq!(function_handler(track("/v1/event", 0), state.clone()).await.unwrap().status(), 202);
+
+        // v2 requires a timestamp, and answers JSON
+        let v2 = function_handler(track("/v2/event", 0), state.clone()).await.unwrap();
+        assert_eq!(v2.status(), 422);
+        let v2 = function_handler(track("/v2/event", 1_700_000_000_000), state.clone()).await.unwrap();
+        assert_eq!(v2.status(), 202);
+        let body: serde_json::Value = serde_json::from_slice(v2.body().as_ref()).unwrap();
+        assert_eq!(body["status"], "accepted");
+
+        let v3 = function_handler(track("/v3/event", 0), state).await.unwrap();
+        assert_eq!(v3.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_wrong_method_or_path_is_rejected_before_the_body() {
+        let state = Arc::new(test_state(Config::default()));
+        let request = |method: &str, uri: &str| {
+            lambda_http::http::Request::builder()
+                .method(method)
+                .uri(uri)
+                .body(Body::Text("{}".to_string()))
+                .unwrap()
+        };
+
+        let response = function_handler(request("GET", "/view"), state.clone()).await.unwrap();
+        assert_eq!(response.status(), 405);
+        assert_eq!(response.headers()["allow"], "POST");
+
+        for uri in ["/preview", "/a/b/view", "/view/extra"] {
+            let response = function_handler(request("POST", uri), state.clone()).await.unwrap();
+            assert_eq!(response.status(), 404, "{}", uri);
+        }
+    }
+}
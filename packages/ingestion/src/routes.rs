@@ -0,0 +1,254 @@
+//! The API's routes: a method and a path template each, like
+//! `DELETE /users/{userId}`.
+//!
+//! A path matches a template segment by segment, as sent or after an API
+//! Gateway stage prefix (`/prod/view`), which is never taken for a version
+//! segment. Event endpoints are served unversioned and under `/v1` and
+//! `/v2` (see `version`); any other version is a 404. A path matching a
+//! route under another method is a 405 listing the methods it takes, and
+//! `{param}` segments are percent-decoded (and never blank) into [`PathParams`], which the
+//! router attaches to the request alongside its [`ApiVersion`].
+//!
+//! Not to be confused with stream routing, in `routing`.
+
+use lambda_http::{Body, Request, Response};
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+use crate::shared::create_error_response;
+use crate::version::{is_version, ApiVersion};
+
+/// What a route leads to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endpoint {
+    /// `livez`, `readyz` or `health`
+    Probe(&'static str),
+    OpenApi,
+    RefreshConfig,
+    DeleteUser,
+    Pixel,
+    Status,
+    View,
+    Event,
+    Identify,
+    Group,
+    Alias,
+    Vitals,
+    Errors,
+    Heartbeat,
+    Click,
+    ScrollDepth,
+    Exposure,
+    Screen,
+    CloudEvents,
+    Batch,
+    /// A Segment-compatible call only, `track` or `page`
+    Segment(&'static str),
+}
+
+/// Event endpoints, served under every version
+pub(crate) const EVENT_ENDPOINTS: [(&str, Endpoint); 14] = [
+    ("view", Endpoint::View),
+    ("event", Endpoint::Event),
+    ("identify", Endpoint::Identify),
+    ("group", Endpoint::Group),
+    ("alias", Endpoint::Alias),
+    ("vitals", Endpoint::Vitals),
+    ("errors", Endpoint::Errors),
+    ("heartbeat", Endpoint::Heartbeat),
+    ("click", Endpoint::Click),
+    ("scroll", Endpoint::ScrollDepth),
+    ("exposure", Endpoint::Exposure),
+    ("screen", Endpoint::Screen),
+    ("cloudevents", Endpoint::CloudEvents),
+    ("batch", Endpoint::Batch),
+];
+
+/// A method and path template
+#[derive(Debug, Clone)]
+struct Route {
+    method: &'static str,
+    segments: Vec<String>,
+    endpoint: Endpoint,
+    version: ApiVersion,
+}
+
+impl Route {
+    fn new(method: &'static str, template: &str, endpoint: Endpoint) -> Self {
+        Self {
+            method,
+            segments: template.split('/').filter(|s| !s.is_empty()).map(String::from).collect(),
+            endpoint,
+            version: ApiVersion::V1,
+        }
+    }
+
+    /// The path's parameters, if it fits the template
+    fn matches(&self, path: &[&str]) -> Option<PathParams> {
+        if path.len() != self.segments.len() {
+            return None;
+        }
+        let mut params = PathParams::default();
+        for (template, segment) in self.segments.iter().zip(path) {
+            match template.strip_prefix('{').and_then(|name| name.strip_suffix('}')) {
+                Some(name) => {
+                    let value = percent_encoding::percent_decode_str(segment).decode_utf8().ok()?;
+                    if value.trim().is_empty() {
+                        return None;
+                    }
+                    params.0.insert(name.to_string(), value.into_owned());
+                }
+                None if template == segment => {}
+                None => return None,
+            }
+        }
+        Some(params)
+    }
+}
+
+static ROUTES: LazyLock<Vec<Route>> = LazyLock::new(|| {
+    let mut routes = vec![
+        Route::new("GET", "/livez", Endpoint::Probe("livez")),
+        Route::new("GET", "/readyz", Endpoint::Probe("readyz")),
+        Route::new("GET", "/health", Endpoint::Probe("health")),
+        Route::new("GET", "/openapi.json", Endpoint::OpenApi),
+        Route::new("POST", "/admin/refresh-config", Endpoint::RefreshConfig),
+        Route::new("DELETE", "/users/{userId}", Endpoint::DeleteUser),
+        Route::new("GET", "/pixel.gif", Endpoint::Pixel),
+        Route::new("GET", "/status/{eventId}", Endpoint::Status),
+        Route::new("POST", "/v1/track", Endpoint::Segment("track")),
+        Route::new("POST", "/v1/page", Endpoint::Segment("page")),
+    ];
+    for (name, endpoint) in EVENT_ENDPOINTS {
+        for (prefix, version) in [("", ApiVersion::V1), ("/v1", ApiVersion::V1), ("/v2", ApiVersion::V2)] {
+            routes.push(Route {
+                version,
+                ..Route::new("POST", &format!("{}/{}", prefix, name), endpoint)
+            });
+        }
+    }
+    routes
+});
+
+/// Parameters taken from a request's path
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PathParams(HashMap<String, String>);
+
+impl PathParams {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(String::as_str)
+    }
+
+    /// The parameters the router attached to a request
+    pub fn of(request: &Request) -> Option<&Self> {
+        request.extensions().get::<Self>()
+    }
+}
+
+/// The route a request resolved to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Matched {
+    pub endpoint: Endpoint,
+    pub version: ApiVersion,
+    pub params: PathParams,
+}
+
+/// Where a request is going
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolved {
+    Found(Matched),
+    /// The path exists under these methods only
+    MethodNotAllowed(Vec<&'static str>),
+    NotFound,
+}
+
+impl Resolved {
+    /// The response for a request that didn't resolve to a route
+    pub fn rejection(&self) -> Option<Response<Body>> {
+        match self {
+            Self::Found(_) => None,
+            Self::MethodNotAllowed(allowed) => {
+                let mut response = create_error_response(405, "Method not allowed");
+                if let Ok(value) = allowed.join(", ").parse() {
+                    response.headers_mut().insert("Allow", value);
+                }
+                Some(response)
+            }
+            Self::NotFound => Some(create_error_response(404, "Not found")),
+        }
+    }
+}
+
+/// Resolves a method and path to its route
+pub fn resolve(method: &str, path: &str) -> Resolved {
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    let unstaged = match segments.split_first() {
+        Some((stage, rest)) if !is_version(stage) => Some(rest),
+        _ => None,
+    };
+
+    let mut allowed = Vec::new();
+    for path in std::iter::once(&segments[..]).chain(unstaged) {
+        for route in ROUTES.iter() {
+            let Some(params) = route.matches(path) else {
+                continue;
+            };
+            if route.method == method {
+                return Resolved::Found(Matched {
+                    endpoint: route.endpoint,
+                    version: route.version,
+                    params,
+                });
+            }
+            if !allowed.contains(&route.method) {
+                allowed.push(route.method);
+            }
+        }
+    }
+    match allowed.is_empty() {
+        true => Resolved::NotFound,
+        false => Resolved::MethodNotAllowed(allowed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn endpoint(method: &str, path: &str) -> Option<(Endpoint, ApiVersion)> {
+        match resolve(method, path) {
+            Resolved::Found(matched) => Some((matched.endpoint, matched.version)),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_resolves_versions_and_stage_prefixes() {
+        assert_eq!(endpoint("POST", "/view"), Some((Endpoint::View, ApiVersion::V1)));
+        assert_eq!(endpoint("POST", "/prod/view"), Some((Endpoint::View, ApiVersion::V1)));
+        assert_eq!(endpoint("POST", "/v1/view"), Some((Endpoint::View, ApiVersion::V1)));
+        assert_eq!(endpoint("POST", "/prod/v2/batch/"), Some((Endpoint::Batch, ApiVersion::V2)));
+        assert_eq!(endpoint("POST", "/v3/view"), None);
+        assert_eq!(endpoint("POST", "/a/b/view"), None);
+        assert_eq!(endpoint("POST", "/preview"), None);
+        assert_eq!(endpoint("GET", "/prod/health"), Some((Endpoint::Probe("health"), ApiVersion::V1)));
+    }
+
+    #[test]
+    fn test_wrong_methods_are_405_with_allow() {
+        assert_eq!(resolve("GET", "/view"), Resolved::MethodNotAllowed(vec!["POST"]));
+        let response = resolve("POST", "/status/abc").rejection().unwrap();
+        assert_eq!(response.status(), 405);
+        assert_eq!(response.headers()["allow"], "GET");
+        assert_eq!(resolve("GET", "/nothing/here").rejection().unwrap().status(), 404);
+    }
+
+    #[test]
+    fn test_path_params_are_decoded() {
+        let Resolved::Found(matched) = resolve("DELETE", "/prod/users/jane%40shop.io") else {
+            panic!("not found");
+        };
+        assert_eq!(matched.endpoint, Endpoint::DeleteUser);
+        assert_eq!(matched.params.get("userId"), Some("jane@shop.io"));
+    }
+}
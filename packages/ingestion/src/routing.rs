@@ -0,0 +1,133 @@
+//! Stream routing by project and event type.
+//!
+//! `STREAM_ROUTES` is a JSON list of routes, checked in order, that send
+//! matching events to a stream other than `STREAM_NAME`:
+//!
+//! ```json
+//! [
+//!   {"project": "acme", "stream": "events-acme"},
+//!   {"eventType": "autocapture", "stream": "events-autocapture", "region": "us-west-2"}
+//! ]
+//! ```
+//!
+//! A route with both `project` and `eventType` needs both to match. Streams
+//! in another region name it; a client for each is built at cold start, so
+//! a route added by a config reload can only target the default region.
+//! Residency zones take precedence over routes, and routes over the bot
+//! stream, so an isolated tenant's bot traffic stays isolated too.
+
+use aws_sdk_kinesis::Client as KinesisClient;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::models::IngestEventPayload;
+use crate::shared::env_json;
+
+/// One routing rule
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamRoute {
+    #[serde(default)]
+    pub project: Option<String>,
+    #[serde(default)]
+    pub event_type: Option<String>,
+    pub stream: String,
+    /// AWS region of the stream, when not the function's own
+    #[serde(default)]
+    pub region: Option<String>,
+}
+
+impl StreamRoute {
+    fn matches(&self, event: &IngestEventPayload) -> bool {
+        let project = self.project.as_ref().is_none_or(|p| *p == event.project_id);
+        let event_type = self.event_type.as_ref().is_none_or(|t| *t == event.event_type);
+        project && event_type && (self.project.is_some() || self.event_type.is_some())
+    }
+}
+
+/// Configuration for stream routing
+#[derive(Debug, Clone, Default)]
+pub struct StreamRouting {
+    pub routes: Vec<StreamRoute>,
+}
+
+impl StreamRouting {
+    pub fn from_env() -> Self {
+        Self {
+            routes: env_json("STREAM_ROUTES").unwrap_or_default(),
+        }
+    }
+
+    /// Stream of the first route the event matches
+    pub fn stream_for(&self, event: &IngestEventPayload) -> Option<&str> {
+        self.routes
+            .iter()
+            .find(|route| route.matches(event))
+            .map(|route| route.stream.as_str())
+    }
+}
+
+/// Kinesis clients by stream name, with the default stream's client used
+/// for streams without their own
+#[derive(Debug, Clone)]
+pub struct StreamClients {
+    default_stream: String,
+    default_client: KinesisClient,
+    clients: HashMap<String, KinesisClient>,
+}
+
+impl StreamClients {
+    pub fn new(default_stream: String, default_client: KinesisClient) -> Self {
+        Self {
+            default_stream,
+            default_client,
+            clients: HashMap::new(),
+        }
+    }
+
+    /// Adds a client for a stream in another region
+    pub fn with_stream(mut self, stream: String, client: KinesisClient) -> Self {
+        self.clients.insert(stream, client);
+        self
+    }
+
+    pub fn default_stream(&self) -> &str {
+        &self.default_stream
+    }
+
+    pub fn client(&self, stream: &str) -> &KinesisClient {
+        self.clients.get(stream).unwrap_or(&self.default_client)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(project_id: &str, event_type: &str) -> IngestEventPayload {
+        IngestEventPayload {
+            project_id: project_id.to_string(),
+            event_type: event_type.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_first_matching_route_wins() {
+        let routing = StreamRouting {
+            routes: serde_json::from_str(
+                r#"[
+                    {"project": "acme", "eventType": "autocapture", "stream": "acme-autocapture"},
+                    {"project": "acme", "stream": "events-acme"},
+                    {"eventType": "autocapture", "stream": "events-autocapture"},
+                    {"stream": "matches-nothing"}
+                ]"#,
+            )
+            .unwrap(),
+        };
+        assert_eq!(routing.stream_for(&event("acme", "autocapture")), Some("acme-autocapture"));
+        assert_eq!(routing.stream_for(&event("acme", "pageview")), Some("events-acme"));
+        assert_eq!(routing.stream_for(&event("globex", "autocapture")), Some("events-autocapture"));
+        assert_eq!(routing.stream_for(&event("globex", "pageview")), None);
+    }
+}
@@ -0,0 +1,271 @@
+//! Per-project transformation rules.
+//!
+//! With `RULES_ENABLED`, each event runs through the rules configured for
+//! its project before anything else looks at it, so badly named client
+//! events can be cleaned up without an SDK release. Rules live in a
+//! [`RuleStore`] (a DynamoDB table in production) and lookups, including
+//! misses, are cached per sandbox for `RULES_CACHE_TTL_SECS`.
+//!
+//! A project's rules are a JSON list applied in order, each to events of
+//! its `event` type (every event when absent), matched against the type as
+//! earlier rules left it:
+//!
+//! - `{"op": "drop"}`: discards the event; it's still answered as accepted
+//! - `{"op": "rename_event", "to": ...}`
+//! - `{"op": "rename_property", "from": ..., "to": ...}`: replaces any
+//!   existing `to`
+//! - `{"op": "set_property", "name": ..., "value": ...}`
+//!
+//! e.g. `[{"event": "Signed Up", "op": "rename_event", "to": "signup"}]`.
+
+use async_trait::async_trait;
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use lambda_http::Error;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::models::IngestEventPayload;
+use crate::shared::{env_flag, env_or, env_var, AppState};
+
+/// Configuration for transformation rules
+#[derive(Debug, Clone)]
+pub struct RulesConfig {
+    pub enabled: bool,
+    /// DynamoDB rules table; in-memory (no rules) when unset
+    pub table_name: Option<String>,
+    pub cache_ttl: Duration,
+}
+
+impl Default for RulesConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            table_name: None,
+            cache_ttl: Duration::from_secs(300),
+        }
+    }
+}
+
+impl RulesConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            enabled: env_flag("RULES_ENABLED"),
+            table_name: env_var("RULES_TABLE"),
+            cache_ttl: Duration::from_secs(env_or(
+                "RULES_CACHE_TTL_SECS",
+                defaults.cache_ttl.as_secs(),
+            )),
+        }
+    }
+}
+
+/// What a rule does
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Operation {
+    Drop,
+    RenameEvent { to: String },
+    RenameProperty { from: String, to: String },
+    SetProperty { name: String, value: Value },
+}
+
+/// One rule
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Rule {
+    /// Event type the rule applies to; every event when unset
+    pub event: Option<String>,
+    #[serde(flatten)]
+    pub operation: Operation,
+}
+
+/// Runs rules over an event in order. Returns `false` when it should be
+/// dropped.
+pub fn transform(payload: &mut IngestEventPayload, rules: &[Rule]) -> bool {
+    for rule in rules {
+        if rule.event.as_ref().is_some_and(|event| *event != payload.event_type) {
+            continue;
+        }
+        match rule.operation {
+            Operation::Drop => return false,
+            Operation::RenameEvent { ref to } => payload.event_type = to.clone(),
+            Operation::RenameProperty { ref from, ref to } => {
+                if let Some(properties) = payload.properties.as_mut() {
+                    if let Some(value) = properties.remove(from) {
+                        properties.insert(to.clone(), value);
+                    }
+                }
+            }
+            Operation::SetProperty { ref name, ref value } => {
+                payload
+                    .properties
+                    .get_or_insert_with(HashMap::new)
+                    .insert(name.clone(), value.clone());
+            }
+        }
+    }
+    true
+}
+
+/// Rule store, keyed by project
+#[async_trait]
+pub trait RuleStore: Send + Sync {
+    async fn get(&self, project_id: &str) -> Result<Option<Vec<Rule>>, Error>;
+}
+
+/// Process-local store, used in tests and when no table is configured
+#[derive(Debug, Default)]
+pub struct InMemoryRuleStore {
+    rules: Mutex<HashMap<String, Vec<Rule>>>,
+}
+
+impl InMemoryRuleStore {
+    pub fn insert(&self, project_id: &str, rules: Vec<Rule>) {
+        self.rules.lock().unwrap().insert(project_id.to_string(), rules);
+    }
+}
+
+#[async_trait]
+impl RuleStore for InMemoryRuleStore {
+    async fn get(&self, project_id: &str) -> Result<Option<Vec<Rule>>, Error> {
+        Ok(self.rules.lock().unwrap().get(project_id).cloned())
+    }
+}
+
+/// DynamoDB-backed store
+/// Table schema: partition key `project_id` (S), attribute `rules` (S, the
+/// JSON list of rules)
+pub struct DynamoRuleStore {
+    client: DynamoClient,
+    table_name: String,
+}
+
+impl DynamoRuleStore {
+    pub fn new(client: DynamoClient, table_name: String) -> Self {
+        Self { client, table_name }
+    }
+}
+
+#[async_trait]
+impl RuleStore for DynamoRuleStore {
+    async fn get(&self, project_id: &str) -> Result<Option<Vec<Rule>>, Error> {
+        let output = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("project_id", AttributeValue::S(project_id.to_string()))
+            .send()
+            .await?;
+
+        let Some(rules) = output.item().and_then(|item| item.get("rules")).and_then(|v| v.as_s().ok()) else {
+            return Ok(None);
+        };
+        match serde_json::from_str(rules) {
+            Ok(rules) => Ok(Some(rules)),
+            Err(e) => {
+                tracing::warn!("Ignoring invalid rules for {}: {}", project_id, e);
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Cached entry: the rules (if any) and when they were fetched
+type CachedRules = (Option<Arc<Vec<Rule>>>, Instant);
+
+/// Recent lookups in front of a [`RuleStore`]
+pub struct RuleCache {
+    store: Arc<dyn RuleStore>,
+    entries: Mutex<HashMap<String, CachedRules>>,
+}
+
+impl RuleCache {
+    pub fn new(store: Arc<dyn RuleStore>) -> Self {
+        Self {
+            store,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Rules of a project, from the cache while younger than `ttl`
+    pub async fn lookup(&self, project_id: &str, ttl: Duration) -> Result<Option<Arc<Vec<Rule>>>, Error> {
+        if let Some((rules, fetched_at)) = self.entries.lock().unwrap().get(project_id) {
+            if fetched_at.elapsed() < ttl {
+                return Ok(rules.clone());
+            }
+        }
+
+        let rules = self.store.get(project_id).await?.map(Arc::new);
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(project_id.to_string(), (rules.clone(), Instant::now()));
+        Ok(rules)
+    }
+}
+
+/// Runs the project's rules over an event. Returns `false` when it should
+/// be dropped.
+#[tracing::instrument(name = "transform.rules", skip_all)]
+pub async fn apply(payload: &mut IngestEventPayload, state: &AppState) -> Result<bool, Error> {
+    let config = &state.config.rules;
+    if !config.enabled {
+        return Ok(true);
+    }
+
+    let Some(rules) = state.rules.lookup(&payload.project_id, config.cache_ttl).await? else {
+        return Ok(true);
+    };
+    Ok(transform(payload, &rules))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn rules(rules: Value) -> Vec<Rule> {
+        serde_json::from_value(rules).unwrap()
+    }
+
+    fn event(event_type: &str, properties: Value) -> IngestEventPayload {
+        IngestEventPayload {
+            project_id: "proj".to_string(),
+            event_type: event_type.to_string(),
+            properties: serde_json::from_value(properties).unwrap(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_rules_apply_in_order() {
+        let rules = rules(json!([
+            {"event": "Signed Up", "op": "rename_event", "to": "signup"},
+            {"event": "signup", "op": "rename_property", "from": "Plan Name", "to": "plan"},
+            {"op": "set_property", "name": "cleaned", "value": true},
+        ]));
+
+        let mut signup = event("Signed Up", json!({"Plan Name": "pro", "plan": "stale"}));
+        assert!(transform(&mut signup, &rules));
+        assert_eq!(signup.event_type, "signup");
+        assert_eq!(json!(signup.properties), json!({"plan": "pro", "cleaned": true}));
+
+        let mut pageview = event("pageview", Value::Null);
+        assert!(transform(&mut pageview, &rules));
+        assert_eq!(json!(pageview.properties), json!({"cleaned": true}));
+    }
+
+    #[test]
+    fn test_drops_matching_events() {
+        let rules = rules(json!([{"event": "debug_ping", "op": "drop"}]));
+        assert!(!transform(&mut event("debug_ping", Value::Null), &rules));
+        assert!(transform(&mut event("pageview", Value::Null), &rules));
+
+        let invalid: Result<Vec<Rule>, _> = serde_json::from_value(json!([{"op": "explode"}]));
+        assert!(invalid.is_err());
+    }
+}
@@ -0,0 +1,315 @@
+//! Property sanitization.
+//!
+//! With `SANITIZE_ENABLED`, every normalized event's `properties`, `traits`
+//! and `traits_set_once` are cleaned up before validation, whatever SDK
+//! sent them:
+//!
+//! - top-level keys are normalized to snake_case (`firstName`,
+//!   `First Name` and `first-name` all become `first_name`); a leading `$`
+//!   is kept. Unless `SANITIZE_SNAKE_CASE_KEYS` is `false`.
+//! - values that look like PII are removed, at any depth. The built-in
+//!   detectors (`SANITIZE_PII_DETECTORS`, default all of `email`,
+//!   `credit_card`, `ssn`) are joined by any `SANITIZE_PII_PATTERNS`
+//!   regexes.
+//! - keys named in `SANITIZE_PII_KEYS` (after snake-casing, e.g.
+//!   `password`) are removed whatever their value.
+//! - strings are truncated to `SANITIZE_MAX_STRING_BYTES`.
+
+use regex::Regex;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+use crate::models::IngestEventPayload;
+use crate::shared::{env_flag, env_list, env_or};
+
+static EMAIL: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)\b[a-z0-9._%+-]+@[a-z0-9.-]+\.[a-z]{2,}\b").unwrap());
+static CARD_NUMBER: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\b\d(?:[ -]?\d){12,18}\b").unwrap());
+static SSN: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\b\d{3}-\d{2}-\d{4}\b").unwrap());
+
+/// A built-in PII detector
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Detector {
+    Email,
+    /// Card-like digit runs that pass the Luhn check
+    CreditCard,
+    /// US social security numbers, `123-45-6789`
+    Ssn,
+}
+
+impl std::str::FromStr for Detector {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "email" => Ok(Self::Email),
+            "credit_card" => Ok(Self::CreditCard),
+            "ssn" => Ok(Self::Ssn),
+            other => Err(format!("unknown PII detector \"{}\"", other)),
+        }
+    }
+}
+
+impl Detector {
+    fn matches(&self, value: &str) -> bool {
+        match self {
+            Self::Email => EMAIL.is_match(value),
+            Self::CreditCard => CARD_NUMBER.find_iter(value).any(|m| luhn_valid(m.as_str())),
+            Self::Ssn => SSN.is_match(value),
+        }
+    }
+}
+
+/// Whether the digits in `number` pass the Luhn checksum
+fn luhn_valid(number: &str) -> bool {
+    let digits: Vec<u32> = number.chars().filter_map(|c| c.to_digit(10)).collect();
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &d)| match (i % 2 == 1, d * 2) {
+            (true, doubled) if doubled > 9 => doubled - 9,
+            (true, doubled) => doubled,
+            (false, _) => d,
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+/// Configuration for sanitization
+#[derive(Debug, Clone)]
+pub struct SanitizeConfig {
+    pub enabled: bool,
+    pub snake_case_keys: bool,
+    pub detectors: Vec<Detector>,
+    /// Extra patterns; a string value matching any is removed
+    pub pii_patterns: Vec<Regex>,
+    /// Keys removed whatever their value
+    pub pii_keys: Vec<String>,
+    /// Longest string kept, in bytes
+    pub max_string_bytes: usize,
+}
+
+impl Default for SanitizeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            snake_case_keys: true,
+            detectors: vec![Detector::Email, Detector::CreditCard, Detector::Ssn],
+            pii_patterns: Vec::new(),
+            pii_keys: Vec::new(),
+            max_string_bytes: 1024,
+        }
+    }
+}
+
+impl SanitizeConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let detectors = env_list("SANITIZE_PII_DETECTORS");
+        Self {
+            enabled: env_flag("SANITIZE_ENABLED"),
+            snake_case_keys: env_or("SANITIZE_SNAKE_CASE_KEYS", defaults.snake_case_keys),
+            detectors: if detectors.is_empty() {
+                defaults.detectors
+            } else {
+                detectors
+                    .iter()
+                    .filter_map(|name| {
+                        name.parse()
+                            .map_err(|e| tracing::warn!("Ignoring SANITIZE_PII_DETECTORS entry: {}", e))
+                            .ok()
+                    })
+                    .collect()
+            },
+            pii_patterns: env_list("SANITIZE_PII_PATTERNS")
+                .iter()
+                .filter_map(|pattern| {
+                    Regex::new(pattern)
+                        .map_err(|e| tracing::warn!("Ignoring SANITIZE_PII_PATTERNS entry {}: {}", pattern, e))
+                        .ok()
+                })
+                .collect(),
+            pii_keys: env_list("SANITIZE_PII_KEYS").iter().map(|key| snake_case(key)).collect(),
+            max_string_bytes: env_or("SANITIZE_MAX_STRING_BYTES", defaults.max_string_bytes),
+        }
+    }
+
+    fn is_pii(&self, value: &str) -> bool {
+        self.detectors.iter().any(|detector| detector.matches(value))
+            || self.pii_patterns.iter().any(|pattern| pattern.is_match(value))
+    }
+}
+
+/// `firstName`, `First Name` and `first-name` as `first_name`. A leading
+/// `$` (Mixpanel's reserved keys) is kept.
+pub fn snake_case(key: &str) -> String {
+    let (prefix, key) = match key.strip_prefix('$') {
+        Some(rest) => ("$", rest),
+        None => ("", key),
+    };
+
+    let mut snake = String::with_capacity(key.len() + 4);
+    let mut previous: Option<char> = None;
+    for c in key.chars() {
+        if c.is_uppercase() {
+            if previous.is_some_and(|p| p.is_lowercase() || p.is_ascii_digit()) {
+                snake.push('_');
+            }
+            snake.extend(c.to_lowercase());
+        } else if c.is_alphanumeric() {
+            snake.push(c);
+        } else if !snake.is_empty() && !snake.ends_with('_') {
+            snake.push('_');
+        }
+        previous = Some(c);
+    }
+    let snake = snake.trim_end_matches('_');
+    if snake.is_empty() {
+        return format!("{}{}", prefix, key);
+    }
+    format!("{}{}", prefix, snake)
+}
+
+/// Longest prefix of `value` within `max_bytes`, on a char boundary
+pub(crate) fn truncate(value: &mut String, max_bytes: usize) {
+    if value.len() > max_bytes {
+        let end = (0..=max_bytes).rev().find(|&i| value.is_char_boundary(i)).unwrap_or(0);
+        value.truncate(end);
+    }
+}
+
+/// Cleans one value in place; `false` when it should be removed
+fn clean_value(value: &mut Value, config: &SanitizeConfig) -> bool {
+    match value {
+        Value::String(s) => {
+            if config.is_pii(s) {
+                return false;
+            }
+            truncate(s, config.max_string_bytes);
+        }
+        Value::Array(items) => items.retain_mut(|item| clean_value(item, config)),
+        Value::Object(fields) => fields.retain(|_, item| clean_value(item, config)),
+        _ => {}
+    }
+    true
+}
+
+fn clean_fields(fields: &mut HashMap<String, Value>, config: &SanitizeConfig) -> usize {
+    let before = fields.len();
+    let mut keys: Vec<_> = fields.keys().cloned().collect();
+    keys.sort();
+
+    let mut cleaned = HashMap::with_capacity(fields.len());
+    for key in keys {
+        let mut value = fields.remove(&key).unwrap_or_default();
+        let key = if config.snake_case_keys { snake_case(&key) } else { key };
+        if config.pii_keys.contains(&key) || !clean_value(&mut value, config) {
+            continue;
+        }
+        // On a clash (`firstName` and `first_name`) the first key in order wins
+        cleaned.entry(key).or_insert(value);
+    }
+    *fields = cleaned;
+    before - fields.len()
+}
+
+/// Sanitizes the event's properties and traits
+pub fn apply(payload: &mut IngestEventPayload, config: &SanitizeConfig) {
+    if !config.enabled {
+        return;
+    }
+    let dropped: usize = [
+        payload.properties.as_mut(),
+        payload.traits.as_mut(),
+        payload.traits_set_once.as_mut(),
+    ]
+    .into_iter()
+    .flatten()
+    .map(|fields| clean_fields(fields, config))
+    .sum();
+    if dropped > 0 {
+        tracing::debug!("Removed {} keys from {} event", dropped, payload.event_type);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn config() -> SanitizeConfig {
+        SanitizeConfig {
+            enabled: true,
+            pii_keys: vec!["password".to_string()],
+            max_string_bytes: 8,
+            ..Default::default()
+        }
+    }
+
+    fn sanitized(properties: Value, config: &SanitizeConfig) -> Value {
+        let mut payload = IngestEventPayload {
+            properties: serde_json::from_value(properties).unwrap(),
+            ..Default::default()
+        };
+        apply(&mut payload, config);
+        serde_json::to_value(payload.properties.unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_snake_case() {
+        assert_eq!(snake_case("firstName"), "first_name");
+        assert_eq!(snake_case("First Name"), "first_name");
+        assert_eq!(snake_case("plan-tier"), "plan_tier");
+        assert_eq!(snake_case("utm.source"), "utm_source");
+        assert_eq!(snake_case("already_snake"), "already_snake");
+        assert_eq!(snake_case("$set_once"), "$set_once");
+        assert_eq!(snake_case("HTTPStatus"), "httpstatus");
+        assert_eq!(snake_case("--"), "--");
+    }
+
+    #[test]
+    fn test_pii_values_and_keys_removed() {
+        let properties = json!({
+            "Contact": "jane@shop.io",
+            "card": "4111 1111 1111 1111",
+            "order": "4111 1111 1111 1112",
+            "ssn": "123-45-6789",
+            "Password": "hunter2",
+            "tags": ["ok", "bob@shop.io"],
+            "nested": {"email": "x@y.io", "plan": "pro"},
+        });
+        assert_eq!(
+            sanitized(properties, &config()),
+            json!({
+                "order": "4111 111",
+                "tags": ["ok"],
+                "nested": {"plan": "pro"},
+            })
+        );
+    }
+
+    #[test]
+    fn test_custom_patterns_and_detector_selection() {
+        let config = SanitizeConfig {
+            detectors: vec![Detector::Ssn],
+            pii_patterns: vec![Regex::new(r"^\+?\d{10,}$").unwrap()],
+            max_string_bytes: 64,
+            ..config()
+        };
+        let properties = json!({"phone": "+14155550100", "email": "jane@shop.io", "firstName": "Jane"});
+        assert_eq!(
+            sanitized(properties, &config),
+            json!({"email": "jane@shop.io", "first_name": "Jane"})
+        );
+        assert_eq!("CREDIT_CARD".parse::<Detector>(), Ok(Detector::CreditCard));
+    }
+
+    #[test]
+    fn test_truncation_respects_char_boundaries() {
+        let mut value = "héllo wörld".to_string();
+        truncate(&mut value, 2);
+        assert_eq!(value, "h");
+    }
+}
@@ -0,0 +1,417 @@
+//! Per-project event schemas.
+//!
+//! With `SCHEMA_VALIDATION_ENABLED`, an event's `properties` are checked
+//! against the schema registered for its project and event name, so a
+//! string `price` can't reach a numeric Parquet column. Schemas live in a
+//! [`SchemaStore`] (a DynamoDB table in production) and lookups, including
+//! misses, are cached per sandbox for `SCHEMA_CACHE_TTL_SECS`. Events
+//! without a registered schema pass unchecked.
+//!
+//! Schemas are a subset of JSON Schema: `type` (a name or a list of names),
+//! `enum`, `properties`, `required`, `additionalProperties` (boolean),
+//! `items`, `minimum`/`maximum` and `minLength`/`maxLength`. Unknown keywords
+//! are ignored. Violating events are rejected with a 422 listing every
+//! violation, or with `SCHEMA_VALIDATION_LENIENT` kept and stamped
+//! `schema_violation: true`.
+
+use async_trait::async_trait;
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use lambda_http::{Body, Error, Response};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::models::IngestEventPayload;
+use crate::shared::{create_response, env_flag, env_or, env_var, AppState};
+
+/// Configuration for schema validation
+#[derive(Debug, Clone)]
+pub struct SchemaConfig {
+    pub enabled: bool,
+    /// DynamoDB schema table; in-memory (no schemas) when unset
+    pub table_name: Option<String>,
+    pub cache_ttl: Duration,
+    /// Keep violating events, flagged, instead of rejecting them
+    pub lenient: bool,
+}
+
+impl Default for SchemaConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            table_name: None,
+            cache_ttl: Duration::from_secs(300),
+            lenient: false,
+        }
+    }
+}
+
+impl SchemaConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            enabled: env_flag("SCHEMA_VALIDATION_ENABLED"),
+            table_name: env_var("SCHEMA_TABLE"),
+            cache_ttl: Duration::from_secs(env_or(
+                "SCHEMA_CACHE_TTL_SECS",
+                defaults.cache_ttl.as_secs(),
+            )),
+            lenient: env_flag("SCHEMA_VALIDATION_LENIENT"),
+        }
+    }
+}
+
+/// One way a value fails its schema
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Violation {
+    /// Dotted path from `properties`, e.g. `properties.items[0].price`
+    pub path: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+/// The JSON type name of a value, as used by `type`
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(n) if n.is_i64() || n.is_u64() => "integer",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn type_matches(expected: &str, value: &Value) -> bool {
+    let actual = type_name(value);
+    expected == actual || (expected == "number" && actual == "integer")
+}
+
+/// Checks a value against a schema, collecting every violation
+pub fn validate(schema: &Value, value: &Value, path: &str, violations: &mut Vec<Violation>) {
+    let Some(schema) = schema.as_object() else {
+        return;
+    };
+    let mut violation = |message: String| {
+        violations.push(Violation {
+            path: path.to_string(),
+            message,
+        })
+    };
+
+    let expected: Vec<&str> = match schema.get("type") {
+        Some(Value::String(name)) => vec![name.as_str()],
+        Some(Value::Array(names)) => names.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    };
+    if !expected.is_empty() && !expected.iter().any(|name| type_matches(name, value)) {
+        violation(format!("expected {}, got {}", expected.join(" or "), type_name(value)));
+        return;
+    }
+
+    if let Some(allowed) = schema.get("enum").and_then(Value::as_array) {
+        if !allowed.contains(value) {
+            violation(format!("{} is not one of the allowed values", value));
+        }
+    }
+
+    let bound = |keyword: &str| schema.get(keyword).and_then(Value::as_f64);
+    if let Some(number) = value.as_f64() {
+        if let Some(minimum) = bound("minimum").filter(|minimum| number < *minimum) {
+            violation(format!("{} is below the minimum of {}", number, minimum));
+        }
+        if let Some(maximum) = bound("maximum").filter(|maximum| number > *maximum) {
+            violation(format!("{} is above the maximum of {}", number, maximum));
+        }
+    }
+    if let Some(string) = value.as_str() {
+        let length = string.chars().count() as f64;
+        if let Some(min_length) = bound("minLength").filter(|min| length < *min) {
+            violation(format!("shorter than {} characters", min_length));
+        }
+        if let Some(max_length) = bound("maxLength").filter(|max| length > *max) {
+            violation(format!("longer than {} characters", max_length));
+        }
+    }
+
+    if let Value::Object(fields) = value {
+        validate_object(schema, fields, path, violations);
+    }
+
+    if let (Value::Array(items), Some(item_schema)) = (value, schema.get("items")) {
+        for (index, item) in items.iter().enumerate() {
+            validate(item_schema, item, &format!("{}[{}]", path, index), violations);
+        }
+    }
+}
+
+fn validate_object(schema: &Map<String, Value>, fields: &Map<String, Value>, path: &str, violations: &mut Vec<Violation>) {
+    let properties = schema.get("properties").and_then(Value::as_object);
+    let required = schema.get("required").and_then(Value::as_array);
+
+    for name in required.into_iter().flatten().filter_map(Value::as_str) {
+        if !fields.contains_key(name) {
+            violations.push(Violation {
+                path: format!("{}.{}", path, name),
+                message: "is required".to_string(),
+            });
+        }
+    }
+
+    let closed = schema.get("additionalProperties") == Some(&Value::Bool(false));
+    for (name, field) in fields {
+        let field_path = format!("{}.{}", path, name);
+        match properties.and_then(|properties| properties.get(name)) {
+            Some(field_schema) => validate(field_schema, field, &field_path, violations),
+            None if closed => violations.push(Violation {
+                path: field_path,
+                message: "is not allowed by the schema".to_string(),
+            }),
+            None => {}
+        }
+    }
+}
+
+/// Schema store, keyed by project and event name
+#[async_trait]
+pub trait SchemaStore: Send + Sync {
+    async fn get(&self, project_id: &str, event_name: &str) -> Result<Option<Value>, Error>;
+}
+
+/// Process-local store, used in tests and when no table is configured
+#[derive(Debug, Default)]
+pub struct InMemorySchemaStore {
+    schemas: Mutex<HashMap<(String, String), Value>>,
+}
+
+impl InMemorySchemaStore {
+    pub fn insert(&self, project_id: &str, event_name: &str, schema: Value) {
+        self.schemas
+            .lock()
+            .unwrap()
+            .insert((project_id.to_string(), event_name.to_string()), schema);
+    }
+}
+
+#[async_trait]
+impl SchemaStore for InMemorySchemaStore {
+    async fn get(&self, project_id: &str, event_name: &str) -> Result<Option<Value>, Error> {
+        let key = (project_id.to_string(), event_name.to_string());
+        Ok(self.schemas.lock().unwrap().get(&key).cloned())
+    }
+}
+
+/// DynamoDB-backed store
+/// Table schema: partition key `pk` (S, `{project_id}#{event_name}`),
+/// attribute `schema` (S, the JSON Schema document)
+pub struct DynamoSchemaStore {
+    client: DynamoClient,
+    table_name: String,
+}
+
+impl DynamoSchemaStore {
+    pub fn new(client: DynamoClient, table_name: String) -> Self {
+        Self { client, table_name }
+    }
+}
+
+#[async_trait]
+impl SchemaStore for DynamoSchemaStore {
+    async fn get(&self, project_id: &str, event_name: &str) -> Result<Option<Value>, Error> {
+        let output = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("pk", AttributeValue::S(format!("{}#{}", project_id, event_name)))
+            .send()
+            .await?;
+
+        let Some(schema) = output.item().and_then(|item| item.get("schema")).and_then(|v| v.as_s().ok()) else {
+            return Ok(None);
+        };
+        match serde_json::from_str(schema) {
+            Ok(schema) => Ok(Some(schema)),
+            Err(e) => {
+                tracing::warn!("Ignoring invalid schema for {}/{}: {}", project_id, event_name, e);
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Cached entry: the schema (if any) and when it was fetched
+type CachedSchema = (Option<Arc<Value>>, Instant);
+
+/// Recent lookups in front of a [`SchemaStore`]
+pub struct SchemaRegistry {
+    store: Arc<dyn SchemaStore>,
+    entries: Mutex<HashMap<(String, String), CachedSchema>>,
+}
+
+impl SchemaRegistry {
+    pub fn new(store: Arc<dyn SchemaStore>) -> Self {
+        Self {
+            store,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Schema for an event, from the cache while younger than `ttl`
+    pub async fn lookup(&self, project_id: &str, event_name: &str, ttl: Duration) -> Result<Option<Arc<Value>>, Error> {
+        let key = (project_id.to_string(), event_name.to_string());
+        if let Some((schema, fetched_at)) = self.entries.lock().unwrap().get(&key) {
+            if fetched_at.elapsed() < ttl {
+                return Ok(schema.clone());
+            }
+        }
+
+        let schema = self.store.get(project_id, event_name).await?.map(Arc::new);
+        self.entries.lock().unwrap().insert(key, (schema.clone(), Instant::now()));
+        Ok(schema)
+    }
+}
+
+/// Validates an event's properties against its registered schema. In
+/// lenient mode violations only flag the event; otherwise they're returned.
+#[tracing::instrument(name = "validate.schema", skip_all)]
+pub async fn check(payload: &mut IngestEventPayload, state: &AppState) -> Result<Result<(), Vec<Violation>>, Error> {
+    let config = &state.config.schemas;
+    if !config.enabled {
+        return Ok(Ok(()));
+    }
+
+    let Some(schema) = state
+        .schemas
+        .lookup(&payload.project_id, &payload.event_type, config.cache_ttl)
+        .await?
+    else {
+        return Ok(Ok(()));
+    };
+
+    let properties = Value::Object(payload.properties.clone().unwrap_or_default().into_iter().collect());
+    let mut violations = Vec::new();
+    validate(&schema, &properties, "properties", &mut violations);
+    if violations.is_empty() {
+        return Ok(Ok(()));
+    }
+
+    if config.lenient {
+        tracing::info!("Flagging {} with {} schema violations", payload.event_type, violations.len());
+        payload.schema_violation = Some(true);
+        return Ok(Ok(()));
+    }
+    Ok(Err(violations))
+}
+
+/// 422 listing every violation
+pub fn violation_response(violations: &[Violation]) -> Response<Body> {
+    create_response(
+        422,
+        serde_json::json!({
+            "error": "Event does not match its schema",
+            "violations": violations,
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::{test_state, Config};
+    use serde_json::json;
+
+    fn violations(schema: Value, value: Value) -> Vec<String> {
+        let mut violations = Vec::new();
+        validate(&schema, &value, "properties", &mut violations);
+        violations.iter().map(ToString::to_string).collect()
+    }
+
+    fn checkout_schema() -> Value {
+        json!({
+            "type": "object",
+            "required": ["price", "currency"],
+            "additionalProperties": false,
+            "properties": {
+                "price": {"type": "number", "minimum": 0},
+                "currency": {"type": "string", "enum": ["EUR", "USD"]},
+                "coupon": {"type": ["string", "null"], "maxLength": 8},
+                "items": {"type": "array", "items": {"type": "object", "properties": {"sku": {"type": "string"}}}}
+            }
+        })
+    }
+
+    #[test]
+    fn test_valid_properties_pass() {
+        let value = json!({"price": 12, "currency": "EUR", "coupon": null, "items": [{"sku": "a-1"}]});
+        assert!(violations(checkout_schema(), value).is_empty());
+    }
+
+    #[test]
+    fn test_every_violation_is_reported_with_its_path() {
+        let value = json!({"price": "12.00", "coupon": "SPRING2025", "items": [{"sku": 7}], "extra": true});
+        assert_eq!(
+            violations(checkout_schema(), value),
+            [
+                "properties.currency: is required",
+                "properties.coupon: longer than 8 characters",
+                "properties.extra: is not allowed by the schema",
+                "properties.items[0].sku: expected string, got integer",
+                "properties.price: expected number, got string",
+            ]
+        );
+        assert_eq!(
+            violations(json!({"type": "integer", "minimum": 1}), json!(0)),
+            ["properties: 0 is below the minimum of 1"]
+        );
+        assert_eq!(
+            violations(json!({"type": "integer"}), json!(1.5)),
+            ["properties: expected integer, got number"]
+        );
+    }
+
+    async fn checked(lenient: bool, properties: Value) -> (IngestEventPayload, Result<(), Vec<Violation>>) {
+        let store = InMemorySchemaStore::default();
+        store.insert("proj", "checkout", checkout_schema());
+        let mut state = test_state(Config {
+            schemas: SchemaConfig {
+                enabled: true,
+                lenient,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        state.schemas = Arc::new(SchemaRegistry::new(Arc::new(store)));
+
+        let mut payload = IngestEventPayload {
+            project_id: "proj".to_string(),
+            event_type: "checkout".to_string(),
+            properties: serde_json::from_value(properties).unwrap(),
+            ..Default::default()
+        };
+        let result = check(&mut payload, &state).await.unwrap();
+        (payload, result)
+    }
+
+    #[tokio::test]
+    async fn test_strict_rejects_and_lenient_flags() {
+        let (_, result) = checked(false, json!({"price": "free", "currency": "EUR"})).await;
+        assert_eq!(result.unwrap_err()[0].path, "properties.price");
+
+        let (payload, result) = checked(true, json!({"price": "free", "currency": "EUR"})).await;
+        assert!(result.is_ok());
+        assert_eq!(payload.schema_violation, Some(true));
+
+        let (payload, result) = checked(false, json!({"price": 5, "currency": "USD"})).await;
+        assert!(result.is_ok());
+        assert_eq!(payload.schema_violation, None);
+    }
+}
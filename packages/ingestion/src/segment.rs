@@ -0,0 +1,332 @@
+//! Segment-compatible API under `/v1`.
+//!
+//! With `SEGMENT_COMPAT_ENABLED`, `POST /v1/track`, `/v1/page`,
+//! `/v1/identify` and `/v1/batch` accept Segment's HTTP Tracking API
+//! payloads, so analytics.js and the Segment server SDKs can be pointed at
+//! this Lambda unchanged. The write key (the HTTP Basic username, or
+//! `writeKey` in the body) is a project token, as for `/batch` envelopes.
+//! `messageId` deduplicates retries (see [`crate::dedup`]), and `timestamp`s are shifted by
+//! the client's clock skew derived from `sentAt`.
+//!
+//! Batches may also carry `group` and `alias` messages. Invalid messages are
+//! skipped and listed in the response; answers are Segment-shaped
+//! (`{"success": true}`).
+
+use analytics_core::Message;
+use base64::Engine;
+use lambda_http::{Body, Error, Request, Response};
+use serde::Deserialize;
+use serde_json::Value;
+use std::sync::Arc;
+
+use crate::auth;
+use crate::body;
+use crate::consent;
+use crate::dedup;
+use crate::event_names;
+use crate::handlers::{check_rate_limit, enrich, enrich_event};
+use crate::jwt;
+use crate::limits;
+use crate::metering;
+use crate::models::{EventContext, SentAt};
+use crate::openapi;
+use crate::rate_limit;
+use crate::sanitize;
+use crate::schema;
+use crate::shared::{create_error_response, create_response, header_value, process_events, AppState};
+use crate::sink;
+use crate::traits;
+
+/// Segment call a `/v1` path maps to
+pub fn route(path: &str) -> Option<&'static str> {
+    let (_, call) = path.rsplit_once("/v1/")?;
+    match call.trim_end_matches('/') {
+        "track" => Some("track"),
+        "page" => Some("page"),
+        "identify" => Some("identify"),
+        "batch" => Some("batch"),
+        _ => None,
+    }
+}
+
+/// Body of `POST /v1/batch`. Messages stay raw so one bad message doesn't
+/// fail the whole batch.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SegmentBatch {
+    pub batch: Vec<Value>,
+    #[serde(default)]
+    pub sent_at: Option<SentAt>,
+    /// Defaults for messages without their own context
+    #[serde(default)]
+    pub context: Option<EventContext>,
+    #[serde(default)]
+    pub write_key: Option<String>,
+}
+
+/// Write key from `Authorization: Basic base64(writeKey:)`
+pub(crate) fn basic_write_key(request: &Request) -> Option<String> {
+    let encoded = header_value(request, "authorization")?.strip_prefix("Basic ")?;
+    let decoded = base64::engine::general_purpose::STANDARD.decode(encoded.trim()).ok()?;
+    let credentials = String::from_utf8(decoded).ok()?;
+    let write_key = credentials.split(':').next().unwrap_or_default();
+    (!write_key.is_empty()).then(|| write_key.to_string())
+}
+
+/// Handler for `POST /v1/{track,page,identify,batch}`
+#[utoipa::path(
+    post,
+    path = "/v1/{call}",
+    tag = "segment",
+    params(("call" = String, Path, description = "`track` or `page`")),
+    request_body = Message,
+    responses(openapi::EventResponses),
+    security(("writeKey" = []))
+)]
+pub async fn handle(
+    call: &str,
+    body: &str,
+    request: &Request,
+    state: Arc<AppState>,
+) -> Result<Response<Body>, Error> {
+    let batch = if call == "batch" {
+        body::parse_json::<SegmentBatch>(body, &state.config.json_limits)
+    } else {
+        body::parse_json::<Value>(body, &state.config.json_limits).map(|mut message| {
+            if let Some(fields) = message.as_object_mut() {
+                fields.insert("type".to_string(), Value::from(call));
+            }
+            // The message's own `sentAt` is applied like a batch member's
+            let write_key = message.get("writeKey").and_then(Value::as_str).map(String::from);
+            SegmentBatch {
+                batch: vec![message],
+                sent_at: None,
+                context: None,
+                write_key,
+            }
+        })
+    };
+    let batch = match batch {
+        Ok(batch) => batch,
+        Err(e) => return Ok(create_error_response(400, &e)),
+    };
+
+    let auth = match basic_write_key(request).or(batch.write_key.clone()) {
+        Some(write_key) => jwt::verify_write_key(&write_key, &state).await,
+        None => Err("Missing write key".to_string()),
+    };
+    let project_id = match auth {
+        Ok((project_id, _)) => project_id,
+        Err(e) => return Ok(create_error_response(401, &format!("Unauthorized: {}", e))),
+    };
+    if let Some(rejection) = auth::check_api_key(request, &project_id, &state).await? {
+        return Ok(rejection);
+    }
+
+    if batch.batch.is_empty() {
+        return Ok(create_error_response(400, "Batch contains no messages"));
+    }
+    if let Some(rejection) = metering::check(&state, &project_id).await {
+        return Ok(rejection);
+    }
+    let decision = check_rate_limit(&state, request, &project_id, batch.batch.len()).await;
+    if let Some(decision) = decision.filter(|d| !d.allowed) {
+        return Ok(rate_limit::too_many_requests(&decision));
+    }
+
+    let now = chrono::Utc::now().timestamp_millis();
+    let batch_skew = match batch.sent_at.as_ref().map(SentAt::millis).transpose() {
+        Ok(sent_at) => sent_at.map_or(0, |sent_at| now - sent_at),
+        Err(e) => return Ok(create_error_response(400, &e)),
+    };
+
+    let mut events = Vec::with_capacity(batch.batch.len());
+    let mut accepted = 0;
+    let mut errors = Vec::new();
+    let mut claims = Vec::new();
+    for (index, raw) in batch.batch.into_iter().enumerate() {
+        let normalized = serde_json::from_value::<Message>(raw)
+            .map_err(|e| format!("Invalid message: {}", e))
+            .and_then(|mut message| {
+                if message.context.is_none() {
+                    message.context = batch.context.clone();
+                }
+                let skew = match message.sent_at.as_ref().map(SentAt::millis).transpose()? {
+                    Some(sent_at) => now - sent_at,
+                    None => batch_skew,
+                };
+                let mut normalized = message.normalize(project_id.clone(), skew)?;
+                consent::enforce(&mut normalized, &state.config.consent)?;
+                Ok(normalized)
+            });
+        let mut normalized = match normalized {
+            Ok(normalized) => normalized,
+            Err(message) => {
+                errors.push(serde_json::json!({ "index": index, "message": message }));
+                continue;
+            }
+        };
+        sanitize::apply(&mut normalized, &state.config.sanitize);
+        if let Err(e) = limits::check(&normalized, &state.config.payload_limits) {
+            let violations = e.violations(&state.config.payload_limits);
+            errors.push(serde_json::json!({ "index": index, "violations": violations }));
+            continue;
+        }
+        if let Err(violations) = traits::normalize(&mut normalized, &state.config.traits) {
+            errors.push(serde_json::json!({ "index": index, "violations": violations }));
+            continue;
+        }
+        if let Err(violations) = event_names::check(&normalized, &state).await? {
+            errors.push(serde_json::json!({ "index": index, "violations": violations }));
+            continue;
+        }
+        if let Err(violations) = schema::check(&mut normalized, &state).await? {
+            errors.push(serde_json::json!({ "index": index, "violations": violations }));
+            continue;
+        }
+        match dedup::claim(&normalized, state.message_ids.as_ref(), &state.config.message_dedup).await? {
+            Some(claim) => claims.extend(claim),
+            None => {
+                accepted += 1;
+                continue;
+            }
+        }
+
+        let enriched = enrich_event(normalized, request, &state.config);
+        let produced = enrich(enriched, request, &state).await;
+        if !produced.is_empty() {
+            accepted += 1;
+            events.extend(produced);
+        }
+    }
+
+    if accepted == 0 && !errors.is_empty() {
+        let body = serde_json::json!({ "success": false, "errors": errors });
+        return Ok(create_response(400, body));
+    }
+
+    if let Err(e) = process_events(events, state.clone()).await.and_then(sink::all_written) {
+        dedup::release_all(state.message_ids.as_ref(), &claims).await;
+        return Err(e);
+    }
+
+    let mut body = serde_json::json!({ "success": true });
+    if !errors.is_empty() {
+        body["accepted"] = Value::from(accepted);
+        body["errors"] = Value::from(errors);
+    }
+    Ok(create_response(200, body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::function_handler;
+    use crate::shared::{test_state, Config};
+    use crate::sink::RecordingSink;
+
+    fn state() -> (Arc<AppState>, Arc<RecordingSink>) {
+        let mut config = Config {
+            segment_compat: true,
+            ..Default::default()
+        };
+        config.s3_parquet.projects = vec!["proj".to_string()];
+        let sink = Arc::new(RecordingSink::default());
+        let mut state = test_state(config);
+        state.parquet_sink = Some(sink.clone());
+        (Arc::new(state), sink)
+    }
+
+    fn post(path: &str, body: Value) -> Request {
+        let claims = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .encode(r#"{"projectId":"proj"}"#);
+        let write_key = format!("e30.{}.sig", claims);
+        let basic = base64::engine::general_purpose::STANDARD.encode(format!("{}:", write_key));
+        lambda_http::http::Request::builder()
+            .method("POST")
+            .uri(path)
+            .header("Authorization", format!("Basic {}", basic))
+            .body(Body::Text(body.to_string()))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_routes() {
+        assert_eq!(route("/prod/v1/track"), Some("track"));
+        assert_eq!(route("/v1/batch"), Some("batch"));
+        assert_eq!(route("/v1/screen"), None);
+        assert_eq!(route("/batch"), None);
+    }
+
+    #[tokio::test]
+    async fn test_track_with_basic_auth_and_skew_correction() {
+        let (state, sink) = state();
+        let body = serde_json::json!({
+            "userId": "u1",
+            "event": "Order Completed",
+            "properties": {"revenue": 42},
+            "messageId": "msg-1",
+            "timestamp": "2024-01-01T00:00:00Z",
+            "sentAt": "2024-01-01T00:00:10Z",
+        });
+
+        let response = function_handler(post("/v1/track", body), state).await.unwrap();
+        assert_eq!(response.status(), 200);
+
+        let event = sink.events.lock().unwrap()[0].clone();
+        assert_eq!(event.event_type, "Order Completed");
+        assert_eq!(event.message_id.as_deref(), Some("msg-1"));
+        assert_eq!(event.properties.unwrap()["revenue"], 42);
+        // Sent 10s after it happened, so it happened 10s before receipt
+        let now = chrono::Utc::now().timestamp_millis();
+        assert!((now - 10_000 - event.timestamp).abs() < 5_000);
+    }
+
+    #[tokio::test]
+    async fn test_batch_of_mixed_messages() {
+        let (state, sink) = state();
+        let body = serde_json::json!({
+            "batch": [
+                {
+                    "type": "page",
+                    "anonymousId": "a1",
+                    "name": "Home",
+                    "properties": {"url": "https://shop.io/"},
+                },
+                {"type": "identify", "userId": "u1", "traits": {"email": "jane@shop.io"}},
+                {"type": "track", "userId": "u1"},
+                {"type": "screen", "userId": "u1"},
+            ],
+        });
+
+        let response = function_handler(post("/v1/batch", body), state).await.unwrap();
+        assert_eq!(response.status(), 200);
+        let body: Value = match response.body() {
+            Body::Text(text) => serde_json::from_str(text).unwrap(),
+            other => panic!("unexpected body: {:?}", other),
+        };
+        assert_eq!(body["accepted"], 2);
+        assert_eq!(body["errors"][0]["message"], "event is required");
+        assert_eq!(body["errors"][1]["index"], 3);
+
+        let events = sink.events.lock().unwrap().clone();
+        assert_eq!(events[0].event_type, "pageview");
+        let page = events[0].context.as_ref().unwrap().page.as_ref().unwrap();
+        assert_eq!(page.url.as_deref(), Some("https://shop.io/"));
+        assert_eq!(events[0].properties.as_ref().unwrap()["name"], "Home");
+        assert_eq!(events[1].traits.as_ref().unwrap()["email"], "jane@shop.io");
+    }
+
+    #[tokio::test]
+    async fn test_missing_write_key_is_unauthorized() {
+        let (state, _) = state();
+        let request = lambda_http::http::Request::builder()
+            .method("POST")
+            .uri("/v1/identify")
+            .body(Body::Text(r#"{"userId": "u1"}"#.to_string()))
+            .unwrap();
+
+        let response = function_handler(request, state).await.unwrap();
+        assert_eq!(response.status(), 401);
+    }
+}
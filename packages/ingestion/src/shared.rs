@@ -1,27 +1,778 @@
-use lambda_http::{Body, Response};
-use std::sync::Arc;
+use lambda_http::http::StatusCode;
+use lambda_http::{Body, Request, RequestExt, Response};
+use serde::Deserialize;
+use tracing::Instrument;
+use tokio::sync::Semaphore;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock, RwLock};
+use std::time::Instant;
 use aws_sdk_kinesis::Client as KinesisClient;
+use crate::admin::{AdminConfig, ConfigCache};
+use crate::aggregation::AggregationConfig;
+use crate::auth::{ApiKeyCache, ApiKeyConfig};
+use crate::autocapture::{AutocaptureConfig, AutocaptureThrottle};
+use crate::avro::RecordEncodingConfig;
+use crate::backpressure::{BackpressureConfig, ShardPressure};
+use crate::body::JsonLimits;
+use crate::buffer::{self, BufferConfig, EventBuffer};
+use crate::clock::TimestampBounds;
+use crate::consent::ConsentConfig;
+use crate::dedup::{DedupConfig, MessageIdStore};
+use crate::encryption::{self, DataKeyCache, FieldEncryptionConfig};
+use crate::enrichment::bot_filter::BotFilterConfig;
+use crate::enrichment::bot_score::BotScoreConfig;
+use crate::enrichment::campaign::CampaignConfig;
+use crate::enrichment::channel::ChannelConfig;
+use crate::enrichment::cohort::CohortConfig;
+use crate::enrichment::experiments::ExperimentsConfig;
+use crate::enrichment::reporting_day::ReportingDayConfig;
+use crate::enrichment::retention::RetentionConfig;
+use crate::enrichment::sampling::SamplingConfig;
+use crate::enrichment::sequence::{SequenceConfig, SessionSequences};
+use crate::enrichment::legacy_traits::LegacyTraitsConfig;
+use crate::enrichment::shard_hint::ShardHintConfig;
+use crate::enrichment::store_lookups::{DynamoLookupReader, StoreLookupsConfig};
+use crate::enrichment::company_domain::CompanyDomainConfig;
+use crate::enrichment::daily_visitor::DailyVisitorConfig;
+use crate::enrichment::duplicate_view::{DuplicateViewConfig, LastPageviewStore};
+use crate::enrichment::engagement::{EngagementConfig, EngagementStore};
+use crate::enrichment::geoip::{GeoIpConfig, GeoIpLookup};
+use crate::enrichment::hash_route::HashRouteConfig;
+use crate::enrichment::identity_hash::IdentityHashConfig;
+use crate::enrichment::ip_privacy::IpPrivacyConfig;
+use crate::enrichment::impossible_travel::{ImpossibleTravelConfig, LocationStore};
+use crate::enrichment::first_touch::{FirstTouchConfig, FirstTouchStore};
+use crate::enrichment::privacy_signals::PrivacySignalConfig;
+use crate::enrichment::referrer::ReferrerConfig;
+use crate::enrichment::last_event_gap::{LastEventGapConfig, LastSeenStore};
+use crate::enrichment::timezone::TimezoneConfig;
+use crate::enrichment::units::UnitsConfig;
+use crate::enrichment::url_normalize::UrlNormalizeConfig;
+use crate::health::SinkHealth;
+use crate::idempotency::{BatchResultStore, IdempotencyConfig};
+use crate::limits::{ErrorLimits, PayloadLimits};
+use crate::canary::CanaryConfig;
+use crate::metering::{self, MeteringConfig, UsageMeter};
+use crate::metrics::{MetricSet, MetricsConfig};
 use crate::models::IngestEventPayload;
+use crate::origin::OriginPolicy;
+use crate::projection::FieldProjection;
+use crate::rate_limit::{RateLimitConfig, RateLimiter};
+use crate::residency::ResidencyConfig;
+use crate::partitioning::PartitionConfig;
+use crate::payload_quarantine::{PayloadQuarantine, PayloadQuarantineConfig};
+use crate::sanitize::SanitizeConfig;
+use crate::ecommerce::EcommerceConfig;
+use crate::event_names::{EventNameRegistry, EventNamesConfig};
+use crate::rules::{RuleCache, RulesConfig};
+use crate::schema::{SchemaConfig, SchemaRegistry};
+use crate::shutdown::ShutdownConfig;
+use crate::signing::SigningConfig;
+use crate::jwt::{JwksCache, JwtConfig};
+use crate::routing::{StreamClients, StreamRouting};
+use crate::retry::RetryConfig;
+use crate::sink::eventbridge::EventBridgeConfig;
+use crate::sink::s3_dead_letter::DeadLetterConfig;
+use crate::sink::s3_fallback::FallbackConfig;
+use crate::sink::s3_parquet::S3ParquetConfig;
+use crate::sink::shadow::{self, ShadowConfig};
+use crate::sink::kinesis::KinesisSink;
+use crate::put_records::Failure;
+use crate::sink::{all_written, EventSink, PartialWrite, SinkConfig};
+use crate::status::{StatusConfig, StatusStore};
+use crate::traits::TraitsConfig;
+use crate::validation::ValidationConfig;
+use crate::deletion::{DeletionConfig, DeletionQueue};
 
 /// Application state shared across Lambda invocations
 #[derive(Clone)]
 pub struct AppState {
-    pub kinesis_client: KinesisClient,
-    pub stream_name: String,
+    /// Kinesis clients by stream, and the default stream
+    pub streams: StreamClients,
+    /// Snapshot of `config_cache` taken when the request started
+    pub config: Arc<Config>,
+    pub config_cache: Arc<ConfigCache>,
+    pub last_seen_store: Arc<dyn LastSeenStore>,
+    pub location_store: Arc<dyn LocationStore>,
+    pub first_touch_store: Arc<dyn FirstTouchStore>,
+    pub last_pageview_store: Arc<dyn LastPageviewStore>,
+    pub engagement_store: Arc<dyn EngagementStore>,
+    /// Reads the enrichment stores kept in DynamoDB in one call, when any is
+    pub lookup_reader: Option<Arc<DynamoLookupReader>>,
+    pub status_store: Arc<dyn StatusStore>,
+    pub batch_results: Arc<dyn BatchResultStore>,
+    /// Recently seen `messageId`s
+    pub message_ids: Arc<dyn MessageIdStore>,
+    pub api_keys: Arc<ApiKeyCache>,
+    /// The JWT issuer's signing keys
+    pub jwks: Arc<JwksCache>,
+    pub schemas: Arc<SchemaRegistry>,
+    /// Event-name policies and the names already logged
+    pub event_names: Arc<EventNameRegistry>,
+    pub rules: Arc<RuleCache>,
+    /// Data keys for field encryption
+    pub data_keys: Arc<DataKeyCache>,
+    /// Bounds concurrent CPU-heavy enrichment (UA/GeoIP parsing)
+    pub enrichment_permits: Arc<Semaphore>,
+    /// GeoIP database, when enabled and loaded
+    pub geoip: Option<Arc<dyn GeoIpLookup>>,
+    pub cold_start: Arc<ColdStartTracker>,
+    /// Next sequence number of each recent session
+    pub session_sequences: Arc<SessionSequences>,
+    /// What auto-capture has let through per recent session
+    pub autocapture: Arc<AutocaptureThrottle>,
+    pub rate_limiter: Arc<RateLimiter>,
+    /// Events accepted per project this month
+    pub usage: Arc<UsageMeter>,
+    /// Outcome of the latest stream write, for readiness
+    pub sink_health: Arc<SinkHealth>,
+    /// Recent stream throttling, for load shedding
+    pub shard_pressure: Arc<ShardPressure>,
+    /// Kinesis clients for residency zones, keyed by zone
+    pub regional_kinesis: HashMap<String, KinesisClient>,
+    /// Direct-to-S3 sink for low-volume projects, when configured
+    pub parquet_sink: Option<Arc<dyn EventSink>>,
+    /// Destination for records that exhausted their retries, when configured
+    pub dead_letter_sink: Option<Arc<dyn EventSink>>,
+    /// Sink replacing the Kinesis streams, when `EVENT_SINK` names one
+    pub event_sink: Option<Arc<dyn EventSink>>,
+    /// Last resort when the event sink and dead-letter sink both fail
+    pub fallback_sink: Option<Arc<dyn EventSink>>,
+    /// EventBridge bus events are also published to, when configured
+    pub event_bus_sink: Option<Arc<dyn EventSink>>,
+    /// Queue every write is shadowed to, when configured (shadow streams
+    /// are written through `streams`)
+    pub shadow_sink: Option<Arc<dyn EventSink>>,
+    /// Where user deletion requests are queued, when configured
+    pub deletion_queue: Option<Arc<dyn DeletionQueue>>,
+    /// Where the bodies of rejected requests are kept, when configured
+    pub payload_quarantine: Option<Arc<dyn PayloadQuarantine>>,
+    /// Events waiting for a batched write, when buffering is on
+    pub event_buffer: Arc<EventBuffer>,
+}
+
+impl AppState {
+    /// This state with the cache's current config, if it has moved on
+    pub async fn with_current_config(self: &Arc<Self>) -> Arc<Self> {
+        let config = self.config_cache.current().await;
+        if Arc::ptr_eq(&config, &self.config) {
+            return self.clone();
+        }
+        Arc::new(Self {
+            config,
+            ..(**self).clone()
+        })
+    }
+}
+
+/// Tracks whether this sandbox has served a request yet
+#[derive(Debug)]
+pub struct ColdStartTracker(AtomicBool);
+
+impl Default for ColdStartTracker {
+    fn default() -> Self {
+        Self(AtomicBool::new(true))
+    }
+}
+
+impl ColdStartTracker {
+    /// Returns `true` exactly once, for the first request after process init
+    pub fn take(&self) -> bool {
+        self.0.swap(false, Ordering::AcqRel)
+    }
+}
+
+/// Request extension recording whether the invocation was a cold start
+#[derive(Debug, Clone, Copy)]
+pub struct ColdStart(pub bool);
+
+/// Builds state with in-memory stores and an offline Kinesis client; the
+/// LocalStack tests swap in real clients and stores
+#[cfg(any(test, feature = "localstack"))]
+pub fn test_state(config: Config) -> AppState {
+    use crate::auth::InMemoryApiKeyStore;
+    use crate::enrichment::duplicate_view::InMemoryLastPageviewStore;
+    use crate::enrichment::engagement::InMemoryEngagementStore;
+    use crate::enrichment::impossible_travel::InMemoryLocationStore;
+    use crate::enrichment::first_touch::InMemoryFirstTouchStore;
+    use crate::enrichment::last_event_gap::InMemoryLastSeenStore;
+    use crate::dedup::InMemoryMessageIdStore;
+    use crate::encryption::StaticDataKeySource;
+    use crate::idempotency::InMemoryBatchResultStore;
+    use crate::metering::InMemoryUsageStore;
+    use crate::rules::InMemoryRuleStore;
+    use crate::schema::InMemorySchemaStore;
+    use crate::event_names::{InMemoryEventNameLog, InMemoryEventNamePolicyStore};
+    use crate::status::InMemoryStatusStore;
+
+    let kinesis_config = aws_sdk_kinesis::Config::builder()
+        .behavior_version(aws_sdk_kinesis::config::BehaviorVersion::latest())
+        .region(aws_sdk_kinesis::config::Region::new("us-east-1"))
+        .build();
+
+    let config = Arc::new(config);
+    AppState {
+        streams: StreamClients::new("test-stream".to_string(), KinesisClient::from_conf(kinesis_config)),
+        enrichment_permits: Arc::new(Semaphore::new(config.enrichment_max_concurrency)),
+        geoip: None,
+        config_cache: Arc::new(ConfigCache::new(config.clone(), Config::default)),
+        config,
+        last_seen_store: Arc::new(InMemoryLastSeenStore::default()),
+        location_store: Arc::new(InMemoryLocationStore::default()),
+        first_touch_store: Arc::new(InMemoryFirstTouchStore::default()),
+        last_pageview_store: Arc::new(InMemoryLastPageviewStore::default()),
+        engagement_store: Arc::new(InMemoryEngagementStore::default()),
+        lookup_reader: None,
+        status_store: Arc::new(InMemoryStatusStore::default()),
+        batch_results: Arc::new(InMemoryBatchResultStore::default()),
+        message_ids: Arc::new(InMemoryMessageIdStore::default()),
+        api_keys: Arc::new(ApiKeyCache::new(Arc::new(InMemoryApiKeyStore::default()))),
+        jwks: Arc::new(JwksCache::new(Arc::new(crate::jwt::StaticJwks::default()))),
+        schemas: Arc::new(SchemaRegistry::new(Arc::new(InMemorySchemaStore::default()))),
+        event_names: Arc::new(EventNameRegistry::new(
+            Arc::new(InMemoryEventNamePolicyStore::default()),
+            Arc::new(InMemoryEventNameLog::default()),
+        )),
+        rules: Arc::new(RuleCache::new(Arc::new(InMemoryRuleStore::default()))),
+        data_keys: Arc::new(DataKeyCache::new(Arc::new(StaticDataKeySource))),
+        cold_start: Arc::new(ColdStartTracker::default()),
+        session_sequences: Arc::new(SessionSequences::default()),
+        autocapture: Arc::new(AutocaptureThrottle::default()),
+        rate_limiter: Arc::new(RateLimiter::default()),
+        usage: Arc::new(UsageMeter::new(Arc::new(InMemoryUsageStore::default()))),
+        sink_health: Arc::new(SinkHealth::default()),
+        shard_pressure: Arc::new(ShardPressure::default()),
+        regional_kinesis: HashMap::new(),
+        parquet_sink: None,
+        dead_letter_sink: None,
+        event_sink: None,
+        fallback_sink: None,
+        event_bus_sink: None,
+        shadow_sink: None,
+        deletion_queue: None,
+        payload_quarantine: None,
+        event_buffer: Arc::new(EventBuffer::default()),
+    }
+}
+
+/// Per-project override of the success response contract
+#[derive(Debug, Clone, Deserialize)]
+pub struct ResponseOverride {
+    pub status: u16,
+    /// JSON body returned verbatim; the default text body when absent.
+    /// A 204 has neither.
+    #[serde(default)]
+    pub body: Option<serde_json::Value>,
+}
+
+/// Runtime configuration, loaded once at cold start
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub json_limits: JsonLimits,
+    /// Per-event property, value and size limits
+    pub payload_limits: PayloadLimits,
+    /// Truncation of frontend error events
+    pub error_limits: ErrorLimits,
+    /// Key normalization, PII scrubbing and truncation of properties
+    pub sanitize: SanitizeConfig,
+    /// KMS envelope encryption of sensitive property values
+    pub field_encryption: FieldEncryptionConfig,
+    /// Accept CloudEvents envelopes on /cloudevents or by content type
+    pub cloudevents_enabled: bool,
+    /// Reject events whose `type` discriminator disagrees with the endpoint
+    pub reject_kind_mismatch: bool,
+    /// Answer keepalive beacons with a bare 204
+    pub keepalive_fast_path: bool,
+    /// Shape responses by `Accept` and `Prefer: return=minimal`
+    pub content_negotiation: bool,
+    /// Require (and derive) `context.page.url`/`path` on pageviews
+    pub page_context_validation: bool,
+    /// Stamp `cold_start` on events and report it in `Server-Timing`
+    pub cold_start_tracking: bool,
+    /// Serve body-less `GET /livez` and `GET /readyz` probes
+    pub health_probes: bool,
+    /// Status code for accepted events
+    pub success_status: u16,
+    /// Success response overrides keyed by project id
+    pub response_overrides: HashMap<String, ResponseOverride>,
+    /// Maximum concurrent CPU-heavy enrichment steps, at least 1
+    pub enrichment_max_concurrency: usize,
+    /// Caps, and sharing across a request, of store-backed enrichment
+    /// lookups
+    pub store_lookups: StoreLookupsConfig,
+    /// Return non-fatal validation warnings in the success body
+    pub validation_warnings: bool,
+    /// Event names that still work but produce a warning
+    pub deprecated_event_names: Vec<String>,
+    /// Accept `{"events": [...]}` envelopes on /batch, not just bare arrays
+    pub batch_envelope: bool,
+    /// Accept `application/x-ndjson` bodies on /batch, one event per line
+    pub ndjson_batches: bool,
+    /// Collapse identical batch errors into `{"reason", "indices"}` entries
+    pub group_batch_errors: bool,
+    /// Stamp `sdk_name`/`sdk_version` from headers or `context.library`
+    pub sdk_tagging: bool,
+    /// Parse `context.user_agent` into `context.device`
+    pub user_agent_parsing: bool,
+    /// Decode leftover chunk framing and reject truncated bodies
+    pub chunked_body_checks: bool,
+    /// Decode gzip/br/deflate `Content-Encoding`, capped at the JSON body limit
+    pub decompress_bodies: bool,
+    /// Accept `application/msgpack` bodies, transcoded to JSON before parsing
+    pub msgpack_bodies: bool,
+    /// Accept `application/x-protobuf` bodies in the `proto/events.proto` format
+    pub protobuf_bodies: bool,
+    /// Serve the Segment-compatible `/v1` routes
+    pub segment_compat: bool,
+    /// Serve `GET /pixel.gif`
+    pub pixel_tracking: bool,
+    /// Accept `token`/`api_key` query parameters from `navigator.sendBeacon`
+    pub beacon_support: bool,
+    /// Reject client timestamps <= 0 instead of defaulting them to server time
+    pub reject_nonpositive_timestamps: bool,
+    /// How far from server time corrected timestamps may be
+    pub timestamp_bounds: TimestampBounds,
+    /// Server-side `Origin`/`Referer` allowlist
+    pub origin_policy: OriginPolicy,
+    /// `X-API-Key` checks against the projects table
+    pub api_keys: ApiKeyConfig,
+    /// HMAC request signatures required of some projects
+    pub signing: SigningConfig,
+    /// Bearer tokens verified against an issuer's JWKS
+    pub jwt: JwtConfig,
+    pub rate_limit: RateLimitConfig,
+    /// Monthly usage counters and plan quotas
+    pub metering: MeteringConfig,
+    /// Synthetic events measuring the pipeline end to end
+    pub canary: CanaryConfig,
+    /// Per-project allowlist of fields written to the stream
+    pub field_projection: FieldProjection,
+    /// JSON or Glue-registered Avro stream records
+    pub record_encoding: RecordEncodingConfig,
+    /// Pack events into KPL aggregated records
+    pub aggregation: AggregationConfig,
+    /// Partition key strategy, with per-project overrides
+    pub partitioning: PartitionConfig,
+    /// Per-project residency zones and their streams
+    pub residency: ResidencyConfig,
+    /// Streams for events of particular projects or event types
+    pub stream_routing: StreamRouting,
+    /// Event ids, `Location` headers and the status resource
+    pub status: StatusConfig,
+    pub batch_idempotency: IdempotencyConfig,
+    /// Skip events whose `messageId` was already ingested
+    pub message_dedup: DedupConfig,
+    /// CloudWatch EMF metrics
+    pub metrics: MetricsConfig,
+    /// Per-record retries and the batch-wide retry budget
+    pub retry: RetryConfig,
+    pub dead_letter: DeadLetterConfig,
+    /// Where accepted events are written
+    pub event_sink: SinkConfig,
+    /// Second destination every write is mirrored to while migrating
+    pub shadow: ShadowConfig,
+    /// Micro-batching of stream writes across invocations
+    pub event_buffer: BufferConfig,
+    /// How long the shutdown flush of buffered state may take
+    pub shutdown: ShutdownConfig,
+    /// Shedding of low-priority events while the stream is throttling
+    pub backpressure: BackpressureConfig,
+    /// Emergency S3 bucket for when the event sink is down
+    pub fallback: FallbackConfig,
+    /// Custom EventBridge bus for other teams' subscriptions
+    pub event_bus: EventBridgeConfig,
+    pub bot_score: BotScoreConfig,
+    pub bot_filter: BotFilterConfig,
+    pub last_event_gap: LastEventGapConfig,
+    pub timezone: TimezoneConfig,
+    pub geoip: GeoIpConfig,
+    pub ip_privacy: IpPrivacyConfig,
+    pub privacy_signals: PrivacySignalConfig,
+    pub consent: ConsentConfig,
+    /// Strict or lenient handling of invalid events, by project
+    pub validation: ValidationConfig,
+    /// Where the bodies of rejected requests are kept
+    pub payload_quarantine: PayloadQuarantineConfig,
+    pub schemas: SchemaConfig,
+    /// Per-project naming conventions and approved event names
+    pub event_names: EventNamesConfig,
+    pub ecommerce: EcommerceConfig,
+    /// Reserved trait spellings and formats of identify events
+    pub traits: TraitsConfig,
+    /// Per-session throttling of clicks and scroll depths
+    pub autocapture: AutocaptureConfig,
+    pub rules: RulesConfig,
+    pub campaign: CampaignConfig,
+    pub channel: ChannelConfig,
+    /// Uses `channel`'s search-engine and social-network lists
+    pub referrer: ReferrerConfig,
+    pub hash_route: HashRouteConfig,
+    pub url_normalize: UrlNormalizeConfig,
+    pub impossible_travel: ImpossibleTravelConfig,
+    pub first_touch: FirstTouchConfig,
+    pub identity_hash: IdentityHashConfig,
+    pub daily_visitor: DailyVisitorConfig,
+    pub units: UnitsConfig,
+    /// Flat `experiment_<name>` properties from A/B assignments
+    pub experiments: ExperimentsConfig,
+    pub company_domain: CompanyDomainConfig,
+    pub duplicate_view: DuplicateViewConfig,
+    pub engagement: EngagementConfig,
+    pub cohort: CohortConfig,
+    pub sampling: SamplingConfig,
+    /// Per-session sequence numbers for events without one
+    pub sequence: SequenceConfig,
+    pub retention: RetentionConfig,
+    /// Local dates in each project's reporting timezone
+    pub reporting_day: ReportingDayConfig,
+    pub legacy_traits: LegacyTraitsConfig,
+    pub shard_hint: ShardHintConfig,
+    pub s3_parquet: S3ParquetConfig,
+    pub admin: AdminConfig,
+    pub deletion: DeletionConfig,
+}
+
+impl Config {
+    /// Loads configuration from environment variables
+    pub fn from_env() -> Self {
+        Self {
+            json_limits: JsonLimits::from_env(),
+            payload_limits: PayloadLimits::from_env(),
+            error_limits: ErrorLimits::from_env(),
+            sanitize: SanitizeConfig::from_env(),
+            field_encryption: FieldEncryptionConfig::from_env(),
+            cloudevents_enabled: env_flag("CLOUDEVENTS_ENABLED"),
+            reject_kind_mismatch: env_flag("REJECT_EVENT_TYPE_MISMATCH"),
+            keepalive_fast_path: env_flag("KEEPALIVE_FAST_PATH_ENABLED"),
+            content_negotiation: env_flag("CONTENT_NEGOTIATION_ENABLED"),
+            page_context_validation: env_flag("PAGE_CONTEXT_VALIDATION_ENABLED"),
+            cold_start_tracking: env_flag("COLD_START_TRACKING_ENABLED"),
+            health_probes: env_flag("HEALTH_PROBES_ENABLED"),
+            success_status: env_var("SUCCESS_STATUS_CODE")
+                .and_then(|value| parse_success_status("SUCCESS_STATUS_CODE", &value))
+                .unwrap_or(202),
+            response_overrides: valid_overrides(
+                env_json("PROJECT_RESPONSE_OVERRIDES").unwrap_or_default(),
+            ),
+            // At least one, or every enrichment would wait for a permit forever
+            enrichment_max_concurrency: env_or(
+                "ENRICHMENT_MAX_CONCURRENCY",
+                default_enrichment_concurrency(),
+            )
+            .max(1),
+            store_lookups: StoreLookupsConfig::from_env(),
+            validation_warnings: env_flag("VALIDATION_WARNINGS_ENABLED"),
+            deprecated_event_names: env_list("DEPRECATED_EVENT_NAMES"),
+            batch_envelope: env_flag("BATCH_ENVELOPE_ENABLED"),
+            ndjson_batches: env_flag("NDJSON_BATCH_ENABLED"),
+            group_batch_errors: env_flag("BATCH_ERRORS_GROUPED"),
+            sdk_tagging: env_flag("SDK_TAGGING_ENABLED"),
+            user_agent_parsing: env_flag("USER_AGENT_PARSING_ENABLED"),
+            chunked_body_checks: env_flag("CHUNKED_BODY_HANDLING_ENABLED"),
+            decompress_bodies: env_flag("REQUEST_DECOMPRESSION_ENABLED"),
+            msgpack_bodies: env_flag("MSGPACK_BODIES_ENABLED"),
+            protobuf_bodies: env_flag("PROTOBUF_BODIES_ENABLED"),
+            segment_compat: env_flag("SEGMENT_COMPAT_ENABLED"),
+            pixel_tracking: env_flag("PIXEL_TRACKING_ENABLED"),
+            beacon_support: env_flag("BEACON_SUPPORT_ENABLED"),
+            reject_nonpositive_timestamps: env_flag("REJECT_NONPOSITIVE_TIMESTAMPS"),
+            timestamp_bounds: TimestampBounds::from_env(),
+            origin_policy: OriginPolicy::from_env(),
+            api_keys: ApiKeyConfig::from_env(),
+            signing: SigningConfig::from_env(),
+            jwt: JwtConfig::from_env(),
+            rate_limit: RateLimitConfig::from_env(),
+            metering: MeteringConfig::from_env(),
+            canary: CanaryConfig::from_env(),
+            field_projection: FieldProjection::from_env(),
+            record_encoding: RecordEncodingConfig::from_env(),
+            aggregation: AggregationConfig::from_env(),
+            partitioning: PartitionConfig::from_env(),
+            residency: ResidencyConfig::from_env(),
+            stream_routing: StreamRouting::from_env(),
+            status: StatusConfig::from_env(),
+            batch_idempotency: IdempotencyConfig::from_env(),
+            message_dedup: DedupConfig::from_env(),
+            retry: RetryConfig::from_env(),
+            metrics: MetricsConfig::from_env(),
+            dead_letter: DeadLetterConfig::from_env(),
+            event_sink: SinkConfig::from_env(),
+            shadow: ShadowConfig::from_env(),
+            event_buffer: BufferConfig::from_env(),
+            shutdown: ShutdownConfig::from_env(),
+            backpressure: BackpressureConfig::from_env(),
+            fallback: FallbackConfig::from_env(),
+            event_bus: EventBridgeConfig::from_env(),
+            bot_score: BotScoreConfig::from_env(),
+            bot_filter: BotFilterConfig::from_env(),
+            last_event_gap: LastEventGapConfig::from_env(),
+            timezone: TimezoneConfig::from_env(),
+            geoip: GeoIpConfig::from_env(),
+            ip_privacy: IpPrivacyConfig::from_env(),
+            privacy_signals: PrivacySignalConfig::from_env(),
+            consent: ConsentConfig::from_env(),
+            validation: ValidationConfig::from_env(),
+            payload_quarantine: PayloadQuarantineConfig::from_env(),
+            schemas: SchemaConfig::from_env(),
+            event_names: EventNamesConfig::from_env(),
+            ecommerce: EcommerceConfig::from_env(),
+            traits: TraitsConfig::from_env(),
+            autocapture: AutocaptureConfig::from_env(),
+            rules: RulesConfig::from_env(),
+            campaign: CampaignConfig::from_env(),
+            channel: ChannelConfig::from_env(),
+            referrer: ReferrerConfig::from_env(),
+            hash_route: HashRouteConfig::from_env(),
+            url_normalize: UrlNormalizeConfig::from_env(),
+            impossible_travel: ImpossibleTravelConfig::from_env(),
+            first_touch: FirstTouchConfig::from_env(),
+            identity_hash: IdentityHashConfig::from_env(),
+            daily_visitor: DailyVisitorConfig::from_env(),
+            units: UnitsConfig::from_env(),
+            experiments: ExperimentsConfig::from_env(),
+            company_domain: CompanyDomainConfig::from_env(),
+            duplicate_view: DuplicateViewConfig::from_env(),
+            engagement: EngagementConfig::from_env(),
+            cohort: CohortConfig::from_env(),
+            sampling: SamplingConfig::from_env(),
+            sequence: SequenceConfig::from_env(),
+            retention: RetentionConfig::from_env(),
+            reporting_day: ReportingDayConfig::from_env(),
+            legacy_traits: LegacyTraitsConfig::from_env(),
+            shard_hint: ShardHintConfig::from_env(),
+            s3_parquet: S3ParquetConfig::from_env(),
+            admin: AdminConfig::from_env(),
+            deletion: DeletionConfig::from_env(),
+        }
+    }
+
+    /// Fails on settings that can't be run safely, so startup (or a reload)
+    /// stops instead of serving with them
+    pub fn validate(&self) -> Result<(), String> {
+        self.identity_hash.validate()?;
+        self.daily_visitor.validate()?;
+        self.cohort.validate()?;
+        self.event_sink.validate()?;
+        Ok(())
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            json_limits: JsonLimits::default(),
+            payload_limits: PayloadLimits::default(),
+            error_limits: ErrorLimits::default(),
+            sanitize: SanitizeConfig::default(),
+            field_encryption: FieldEncryptionConfig::default(),
+            cloudevents_enabled: false,
+            reject_kind_mismatch: false,
+            keepalive_fast_path: false,
+            content_negotiation: false,
+            page_context_validation: false,
+            cold_start_tracking: false,
+            health_probes: false,
+            success_status: 202,
+            response_overrides: HashMap::new(),
+            enrichment_max_concurrency: default_enrichment_concurrency(),
+            store_lookups: StoreLookupsConfig::default(),
+            validation_warnings: false,
+            deprecated_event_names: Vec::new(),
+            batch_envelope: false,
+            ndjson_batches: false,
+            group_batch_errors: false,
+            sdk_tagging: false,
+            user_agent_parsing: false,
+            chunked_body_checks: false,
+            decompress_bodies: false,
+            msgpack_bodies: false,
+            protobuf_bodies: false,
+            segment_compat: false,
+            pixel_tracking: false,
+            beacon_support: false,
+            reject_nonpositive_timestamps: false,
+            timestamp_bounds: TimestampBounds::default(),
+            origin_policy: OriginPolicy::default(),
+            api_keys: ApiKeyConfig::default(),
+            signing: SigningConfig::default(),
+            jwt: JwtConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            metering: MeteringConfig::default(),
+            canary: CanaryConfig::default(),
+            field_projection: FieldProjection::default(),
+            record_encoding: RecordEncodingConfig::default(),
+            aggregation: AggregationConfig::default(),
+            partitioning: PartitionConfig::default(),
+            residency: ResidencyConfig::default(),
+            stream_routing: StreamRouting::default(),
+            status: StatusConfig::default(),
+            batch_idempotency: IdempotencyConfig::default(),
+            message_dedup: DedupConfig::default(),
+            retry: RetryConfig::default(),
+            metrics: MetricsConfig::default(),
+            dead_letter: DeadLetterConfig::default(),
+            event_sink: SinkConfig::default(),
+            shadow: ShadowConfig::default(),
+            event_buffer: BufferConfig::default(),
+            shutdown: ShutdownConfig::default(),
+            backpressure: BackpressureConfig::default(),
+            fallback: FallbackConfig::default(),
+            event_bus: EventBridgeConfig::default(),
+            bot_score: BotScoreConfig::default(),
+            bot_filter: BotFilterConfig::default(),
+            last_event_gap: LastEventGapConfig::default(),
+            timezone: TimezoneConfig::default(),
+            geoip: GeoIpConfig::default(),
+            ip_privacy: IpPrivacyConfig::default(),
+            privacy_signals: PrivacySignalConfig::default(),
+            consent: ConsentConfig::default(),
+            validation: ValidationConfig::default(),
+            payload_quarantine: PayloadQuarantineConfig::default(),
+            schemas: SchemaConfig::default(),
+            event_names: EventNamesConfig::default(),
+            ecommerce: EcommerceConfig::default(),
+            traits: TraitsConfig::default(),
+            autocapture: AutocaptureConfig::default(),
+            rules: RulesConfig::default(),
+            campaign: CampaignConfig::default(),
+            channel: ChannelConfig::default(),
+            referrer: ReferrerConfig::default(),
+            hash_route: HashRouteConfig::default(),
+            url_normalize: UrlNormalizeConfig::default(),
+            impossible_travel: ImpossibleTravelConfig::default(),
+            first_touch: FirstTouchConfig::default(),
+            identity_hash: IdentityHashConfig::default(),
+            daily_visitor: DailyVisitorConfig::default(),
+            units: UnitsConfig::default(),
+            experiments: ExperimentsConfig::default(),
+            company_domain: CompanyDomainConfig::default(),
+            duplicate_view: DuplicateViewConfig::default(),
+            engagement: EngagementConfig::default(),
+            cohort: CohortConfig::default(),
+            sampling: SamplingConfig::default(),
+            sequence: SequenceConfig::default(),
+            retention: RetentionConfig::default(),
+            reporting_day: ReportingDayConfig::default(),
+            legacy_traits: LegacyTraitsConfig::default(),
+            shard_hint: ShardHintConfig::default(),
+            s3_parquet: S3ParquetConfig::default(),
+            admin: AdminConfig::default(),
+            deletion: DeletionConfig::default(),
+        }
+    }
+}
+
+/// A configured success status, which has to be a 2xx; anything else is
+/// ignored with a warning rather than failing every response
+fn parse_success_status(name: &str, value: &str) -> Option<u16> {
+    match value.trim().parse() {
+        Ok(status) if is_success_status(status) => Some(status),
+        _ => {
+            tracing::warn!("Ignoring {} {:?}, not a 2xx status", name, value);
+            None
+        }
+    }
+}
+
+fn is_success_status(status: u16) -> bool {
+    StatusCode::from_u16(status).is_ok_and(|status| status.is_success())
+}
+
+/// Response overrides without those whose status isn't a 2xx
+fn valid_overrides(overrides: HashMap<String, ResponseOverride>) -> HashMap<String, ResponseOverride> {
+    overrides
+        .into_iter()
+        .filter(|(project_id, response)| {
+            let valid = is_success_status(response.status);
+            if !valid {
+                tracing::warn!(
+                    "Ignoring the response override of {}, {} is not a 2xx status",
+                    project_id,
+                    response.status
+                );
+            }
+            valid
+        })
+        .collect()
+}
+
+/// One enrichment per available core
+fn default_enrichment_concurrency() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// Values from remote config sources, taking precedence over the environment
+static ENV_OVERRIDES: LazyLock<RwLock<HashMap<String, String>>> = LazyLock::new(Default::default);
+
+/// Replaces the remote overrides of environment variables (see `config_source`)
+pub fn set_env_overrides(values: HashMap<String, String>) {
+    *ENV_OVERRIDES.write().unwrap() = values;
+}
+
+/// Reads a config variable: its remote override if there is one, otherwise
+/// the environment. Every `from_env` reads through this.
+pub fn env_var(key: &str) -> Option<String> {
+    if let Some(value) = ENV_OVERRIDES.read().unwrap().get(key) {
+        return Some(value.clone());
+    }
+    std::env::var(key).ok()
+}
+
+/// Reads a boolean flag from the environment ("1" or "true" enable it)
+pub fn env_flag(key: &str) -> bool {
+    env_var(key)
+        .map(|v| matches!(v.trim().to_ascii_lowercase().as_str(), "1" | "true"))
+        .unwrap_or(false)
+}
+
+/// Reads and parses an optional environment variable, ignoring invalid values
+pub fn env_opt<T: std::str::FromStr>(key: &str) -> Option<T> {
+    env_var(key).and_then(|v| v.trim().parse().ok())
+}
+
+/// Reads and parses an environment variable, falling back to `default`
+pub fn env_or<T: std::str::FromStr>(key: &str, default: T) -> T {
+    env_opt(key).unwrap_or(default)
+}
+
+/// Reads a comma-separated list from the environment
+pub fn env_list(key: &str) -> Vec<String> {
+    env_var(key)
+        .map(|v| {
+            v.split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Reads a JSON-encoded environment variable, logging (and ignoring) invalid values
+pub fn env_json<T: serde::de::DeserializeOwned>(key: &str) -> Option<T> {
+    let raw = env_var(key)?;
+    match serde_json::from_str(&raw) {
+        Ok(value) => Some(value),
+        Err(e) => {
+            tracing::warn!("Ignoring invalid {}: {}", key, e);
+            None
+        }
+    }
 }
 
 /// CORS headers for JSON responses
 pub const JSON_RESPONSE_HEADERS: [(&str, &str); 3] = [
     ("Content-Type", "application/json"),
     ("Access-Control-Allow-Origin", "*"),
-    ("Access-Control-Allow-Headers", "Content-Type, X-API-Key"),
+    ("Access-Control-Allow-Headers", "Content-Type, X-API-Key, X-Request-Id, X-SDK-Name, X-SDK-Version"),
 ];
 
 /// CORS headers for text responses
 pub const TEXT_RESPONSE_HEADERS: [(&str, &str); 3] = [
     ("Content-Type", "text/plain"),
     ("Access-Control-Allow-Origin", "*"),
-    ("Access-Control-Allow-Headers", "Content-Type, X-API-Key"),
+    ("Access-Control-Allow-Headers", "Content-Type, X-API-Key, X-Request-Id, X-SDK-Name, X-SDK-Version"),
 ];
 
 /// Creates a success response with JSON body
@@ -52,6 +803,48 @@ pub fn create_text_response(status_code: u16, body: &str) -> Response<Body> {
         .unwrap()
 }
 
+/// Minimal headers for the keepalive fast path
+pub const MINIMAL_RESPONSE_HEADERS: [(&str, &str); 1] = [("Access-Control-Allow-Origin", "*")];
+
+/// Creates a body-less response with only the headers a browser needs
+pub fn create_empty_response(status_code: u16) -> Response<Body> {
+    let mut response = Response::builder()
+        .status(status_code);
+
+    for (key, value) in MINIMAL_RESPONSE_HEADERS.iter() {
+        response = response.header(*key, *value);
+    }
+
+    response
+        .body(Body::Empty)
+        .unwrap()
+}
+
+/// Reads a query string parameter from the request
+pub fn query_param<'a>(request: &'a Request, name: &str) -> Option<&'a str> {
+    request
+        .query_string_parameters_ref()
+        .and_then(|params| params.first(name))
+}
+
+/// Reads a header value as a trimmed, non-empty string
+pub fn header_value<'a>(request: &'a Request, name: &str) -> Option<&'a str> {
+    request
+        .headers()
+        .get(name)
+        .and_then(|h| h.to_str().ok())
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+}
+
+/// The client's address: the first hop of `X-Forwarded-For`
+pub fn client_ip(request: &Request) -> Option<&str> {
+    header_value(request, "x-forwarded-for")
+        .and_then(|forwarded| forwarded.split(',').next())
+        .map(str::trim)
+        .filter(|ip| !ip.is_empty())
+}
+
 /// Creates an error response
 pub fn create_error_response(status_code: u16, message: &str) -> Response<Body> {
     create_response(
@@ -62,35 +855,279 @@ pub fn create_error_response(status_code: u16, message: &str) -> Response<Body>
     )
 }
 
-/// Sends events to Kinesis Stream for fan-out processing
-/// Kinesis consumers will handle:
-/// 1. Firehose → S3 with native Parquet conversion
-/// 2. Lambda → ClickHouse for real-time analytics
-/// 3. Lambda → DynamoDB for fast key-value queries
+/// Residency zones of the events' projects, by project id
+///
+/// Resolved before anything is written, so a project pinned to a zone
+/// without a stream fails closed instead of partially leaking.
+pub fn residency_zones<'a>(
+    events: &[IngestEventPayload],
+    state: &'a AppState,
+) -> Result<HashMap<String, &'a str>, lambda_http::Error> {
+    let mut zones = HashMap::new();
+    for event in events {
+        if let Some(zone) = state.config.residency.zone_for(&event.project_id)? {
+            if !state.regional_kinesis.contains_key(zone) {
+                return Err(format!("No Kinesis client for residency zone \"{}\"", zone).into());
+            }
+            zones.insert(event.project_id.clone(), zone);
+        }
+    }
+    Ok(zones)
+}
+
+/// Hands accepted events to the configured sink (see [`sink`](crate::sink)),
+/// low-volume projects to the Parquet sink first and everything to the
+/// fallback sink if that fails, then publishes them to the event bus. With
+/// buffering on, the sink write waits for a batch (see [`buffer`]).
+///
+/// Returns the events the sink could not write, by position in `events`,
+/// when it wrote the rest (see [`PartialWrite`]); any other failure is an
+/// error, and none of the events can be taken as written.
 pub async fn process_events(
-    events: Vec<IngestEventPayload>,
+    mut events: Vec<IngestEventPayload>,
     state: Arc<AppState>,
+) -> Result<Vec<Failure>, lambda_http::Error> {
+    let zones = residency_zones(&events, &state)?;
+    encryption::apply(&mut events, &state).await?;
+    let project_ids: Vec<String> = if state.config.metrics.enabled || state.config.metering.enabled {
+        events.iter().map(|event| event.project_id.clone()).collect()
+    } else {
+        Vec::new()
+    };
+    let published = state.event_bus_sink.as_ref().map(|sink| (sink, events.clone()));
+
+    // Low-volume projects bypass the stream entirely
+    let mut positions: Vec<usize> = (0..events.len()).collect();
+    if let Some(ref sink) = state.parquet_sink {
+        let (low_volume, rest): (Vec<_>, Vec<_>) = events
+            .into_iter()
+            .zip(positions)
+            .partition(|(event, _)| {
+                !zones.contains_key(&event.project_id)
+                    && state.config.s3_parquet.routes(&event.project_id)
+            });
+        (events, positions) = rest.into_iter().unzip();
+        sink.send(low_volume.into_iter().map(|(event, _)| event).collect()).await?;
+    }
+
+    let mut failed = Vec::new();
+    if !events.is_empty() {
+        if state.config.event_buffer.enabled {
+            // Written by whichever request or flush finds the buffer due
+            if let Some(due) = state.event_buffer.push(events, &state.config.event_buffer) {
+                buffer::write(due, &state).await;
+            }
+        } else if let Err(e) = write_events(events, &state).await {
+            let Some(partial) = e.downcast_ref::<PartialWrite>() else {
+                return Err(e);
+            };
+            failed = partial
+                .failed
+                .iter()
+                .map(|(position, reason)| (positions[*position], reason.clone()))
+                .collect();
+        }
+    }
+
+    let unwritten: HashSet<usize> = failed.iter().map(|(position, _)| *position).collect();
+    let mut accepted: HashMap<String, usize> = HashMap::new();
+    for (position, project_id) in project_ids.into_iter().enumerate() {
+        if !unwritten.contains(&position) {
+            *accepted.entry(project_id).or_default() += 1;
+        }
+    }
+    // Pinned projects' usage stays out of the home region's counters
+    let metered: HashMap<String, usize> = accepted
+        .iter()
+        .filter(|(project_id, _)| !zones.contains_key(*project_id))
+        .map(|(project_id, count)| (project_id.clone(), *count))
+        .collect();
+    metering::record(&metered, &state).await;
+    for (project_id, count) in accepted {
+        MetricSet::new(&state.config.metrics)
+            .dimension("ProjectId", project_id)
+            .count("EventsAccepted", count)
+            .emit();
+    }
+
+    // Best effort: the events are already written. The bus is in the home
+    // region, so pinned projects' events are never published.
+    if let Some((sink, events)) = published {
+        let events: Vec<_> = events
+            .into_iter()
+            .enumerate()
+            .filter(|(position, event)| !unwritten.contains(position) && !zones.contains_key(&event.project_id))
+            .map(|(_, event)| event)
+            .collect();
+        if let Err(e) = sink.send(events).await {
+            tracing::warn!("Failed to publish events to the event bus: {}", e);
+        }
+    }
+    Ok(failed)
+}
+
+/// Writes events to a sink other than Kinesis, except that those of pinned
+/// projects still go to their zone's stream. A side that fails outright
+/// fails just its events, so the client retries only those.
+async fn write_beside_zones(
+    sink: &dyn EventSink,
+    events: Vec<IngestEventPayload>,
+    zones: &HashMap<String, &str>,
+    state: &Arc<AppState>,
 ) -> Result<(), lambda_http::Error> {
-    if events.is_empty() {
-        return Ok(());
+    if events.iter().all(|event| !zones.contains_key(&event.project_id)) {
+        let result = sink.send(events).await;
+        state.sink_health.record(result.is_ok());
+        return result;
     }
 
-    tracing::info!("Sending {} events to Kinesis Stream", events.len());
+    let (pinned, rest): (Vec<_>, Vec<_>) = events
+        .into_iter()
+        .enumerate()
+        .partition(|(_, event)| zones.contains_key(&event.project_id));
+    let (pinned_positions, pinned): (Vec<usize>, Vec<_>) = pinned.into_iter().unzip();
+    let (rest_positions, rest): (Vec<usize>, Vec<_>) = rest.into_iter().unzip();
+    let zone_streams = KinesisSink::new(state.clone());
+    let (rest_result, pinned_result) = tokio::join!(sink.send(rest), zone_streams.send(pinned));
+    state.sink_health.record(rest_result.is_ok());
 
-    // Send events to Kinesis Stream
-    // Use projectId as partition key for even distribution
-    for event in &events {
-        let record_data = serde_json::to_vec(event)?;
+    let mut failed = Vec::new();
+    for (result, positions) in [(rest_result, rest_positions), (pinned_result, pinned_positions)] {
+        let Err(e) = result else {
+            continue;
+        };
+        match e.downcast_ref::<PartialWrite>() {
+            Some(partial) => failed.extend(
+                partial
+                    .failed
+                    .iter()
+                    .map(|(position, reason)| (positions[*position], reason.clone())),
+            ),
+            None => failed.extend(positions.into_iter().map(|position| (position, e.to_string()))),
+        }
+    }
+    failed.sort_by_key(|(position, _)| *position);
+    all_written(failed)
+}
 
-        state.kinesis_client
-            .put_record()
-            .stream_name(&state.stream_name)
-            .partition_key(&event.project_id) // Ensures events from same project go to same shard
-            .data(aws_sdk_kinesis::primitives::Blob::new(record_data))
-            .send()
-            .await?;
+/// Writes events to the configured sink, or the fallback sink if that fails
+pub async fn write_events(events: Vec<IngestEventPayload>, state: &Arc<AppState>) -> Result<(), lambda_http::Error> {
+    let zones = residency_zones(&events, state)?;
+    // The bucket is in the home region, so pinned projects never fall back
+    let fallback = state
+        .fallback_sink
+        .as_ref()
+        .filter(|_| events.iter().all(|event| !zones.contains_key(&event.project_id)))
+        .map(|sink| (sink, events.clone()));
+    // Pinned projects never leave their zone, shadow or not
+    let shadow = shadow::sink(state).map(|sink| {
+        let events: Vec<_> = events.iter().filter(|event| !zones.contains_key(&event.project_id)).cloned().collect();
+        (sink, events)
+    });
+    let span = tracing::info_span!("sink.write", events = events.len());
+    let primary = async {
+        let started = Instant::now();
+        let result = match state.event_sink {
+            Some(ref sink) => write_beside_zones(sink.as_ref(), events, &zones, state).await,
+            None => KinesisSink::new(state.clone()).send(events).await,
+        };
+        (result, started.elapsed())
+    }
+    .instrument(span);
+    let result = match shadow {
+        Some((sink, shadowed)) if !shadowed.is_empty() => {
+            let shadowed_count = shadowed.len();
+            let ((result, latency), shadow_result) = tokio::join!(primary, shadow::write(sink, shadowed));
+            shadow::compare(&state.config.metrics, shadowed_count, (result.is_ok(), latency), shadow_result);
+            result
+        }
+        _ => primary.await.0,
+    };
+    match (result, fallback) {
+        (Ok(()), _) => Ok(()),
+        (Err(e), Some((sink, events))) => {
+            tracing::error!("Event sink failed, writing to the fallback bucket: {}", e);
+            sink.send(events).await
+        }
+        (Err(e), None) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_pinned_events_keep_their_stream_with_another_sink() {
+        let mut config = Config::default();
+        config.retry.max_attempts = 1;
+        config.residency.project_zones.insert("eu-tenant".to_string(), "eu".to_string());
+        config.residency.streams =
+            serde_json::from_str(r#"{"eu": {"region": "eu-central-1", "streamName": "events-eu"}}"#).unwrap();
+        let mut state = test_state(config);
+        // The test clients have no credentials, so the zone's write fails
+        state.regional_kinesis.insert("eu".to_string(), state.streams.client("events-eu").clone());
+        let queue = Arc::new(crate::sink::RecordingSink::default());
+        state.event_sink = Some(queue.clone());
+        let event = |project_id: &str| IngestEventPayload {
+            project_id: project_id.to_string(),
+            event_type: "pageview".to_string(),
+            ..Default::default()
+        };
+
+        let events = vec![event("eu-tenant"), event("p"), event("q")];
+        let error = write_events(events, &Arc::new(state)).await.unwrap_err();
+        let failed = &error.downcast_ref::<PartialWrite>().unwrap().failed;
+        assert_eq!(failed.iter().map(|(position, _)| *position).collect::<Vec<_>>(), [0]);
+        let queued: Vec<_> = queue.events.lock().unwrap().iter().map(|e| e.project_id.clone()).collect();
+        assert_eq!(queued, ["p", "q"]);
     }
 
-    tracing::info!("Successfully sent {} events to Kinesis Stream", events.len());
-    Ok(())
+    #[tokio::test]
+    async fn test_pinned_events_are_not_published_or_metered() {
+        let mut config = Config::default();
+        config.event_buffer.enabled = true;
+        config.metering.enabled = true;
+        config.residency.project_zones.insert("eu-tenant".to_string(), "eu".to_string());
+        config.residency.streams =
+            serde_json::from_str(r#"{"eu": {"region": "eu-central-1", "streamName": "events-eu"}}"#).unwrap();
+        let mut state = test_state(config);
+        state.regional_kinesis.insert("eu".to_string(), state.streams.client("events-eu").clone());
+        let bus = Arc::new(crate::sink::RecordingSink::default());
+        state.event_bus_sink = Some(bus.clone());
+        let state = Arc::new(state);
+        let event = |project_id: &str| IngestEventPayload {
+            project_id: project_id.to_string(),
+            event_type: "pageview".to_string(),
+            ..Default::default()
+        };
+
+        // Buffered, so accepted without a write the test can't make
+        let failed = process_events(vec![event("eu-tenant"), event("p")], state.clone()).await.unwrap();
+        assert!(failed.is_empty());
+        let published: Vec<_> = bus.events.lock().unwrap().iter().map(|e| e.project_id.clone()).collect();
+        assert_eq!(published, ["p"]);
+
+        let month = metering::month(chrono::Utc::now());
+        let used = |project_id| state.usage.used(project_id, &month, &state.config.metering);
+        assert_eq!(used("p").await.unwrap(), 1);
+        assert_eq!(used("eu-tenant").await.unwrap(), 0);
+    }
+
+    #[test]
+    fn test_success_statuses_must_be_2xx() {
+        assert_eq!(parse_success_status("SUCCESS_STATUS_CODE", " 200 "), Some(200));
+        assert_eq!(parse_success_status("SUCCESS_STATUS_CODE", "999"), None);
+        assert_eq!(parse_success_status("SUCCESS_STATUS_CODE", "404"), None);
+        assert_eq!(parse_success_status("SUCCESS_STATUS_CODE", "abc"), None);
+
+        let overrides: HashMap<String, ResponseOverride> = serde_json::from_value(serde_json::json!({
+            "ok": {"status": 204},
+            "broken": {"status": 999},
+        }))
+        .unwrap();
+        let overrides = valid_overrides(overrides);
+        assert_eq!(overrides.len(), 1);
+        assert_eq!(overrides["ok"].status, 204);
+    }
 }
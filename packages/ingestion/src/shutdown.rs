@@ -0,0 +1,290 @@
+//! Flushing sandbox-local state before the sandbox shuts down.
+//!
+//! Two features hold data in the sandbox between invocations: the event
+//! buffer (`EVENT_BUFFER_ENABLED`, see `buffer`) and the usage counts
+//! (`USAGE_METERING_ENABLED`, see `metering`). Either makes
+//! [`register_extension`] register the function as an internal Lambda
+//! extension during init. Lambda delivers the `SHUTDOWN` event of internal
+//! extensions as SIGTERM to the runtime process, so [`flush_on_shutdown`]
+//! waits for it, writes out the buffered events, adds the pending counts to
+//! the usage table, then exits.
+//!
+//! A registered extension has to ask for its next event, with the
+//! `Lambda-Extension-Identifier` it was given, before Lambda considers init
+//! done, so [`flush_on_shutdown`] also keeps polling `event/next` and
+//! flushes just the same if a `SHUTDOWN` event comes back that way.
+//!
+//! Lambda allows 500 ms between SIGTERM and SIGKILL when only internal
+//! extensions are registered; the flush gives up after
+//! `SHUTDOWN_FLUSH_TIMEOUT_MS` (450 by default) so the exit still happens
+//! in time. Whatever wasn't written by then is lost, as it would be
+//! without the extension.
+
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use lambda_http::Error;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::buffer;
+use crate::shared::{env_or, env_var, AppState, Config};
+
+/// Name the function registers as an extension under
+const EXTENSION_NAME: &str = "ingestion-shutdown";
+
+/// Header carrying the id Lambda assigns an extension at registration
+const EXTENSION_ID_HEADER: &str = "Lambda-Extension-Identifier";
+
+/// Configuration for the shutdown flush
+#[derive(Debug, Clone)]
+pub struct ShutdownConfig {
+    /// How long the flush may take before the process exits anyway
+    pub flush_timeout: Duration,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            flush_timeout: Duration::from_millis(450),
+        }
+    }
+}
+
+impl ShutdownConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            flush_timeout: Duration::from_millis(env_or(
+                "SHUTDOWN_FLUSH_TIMEOUT_MS",
+                defaults.flush_timeout.as_millis() as u64,
+            )),
+        }
+    }
+}
+
+/// Whether anything is kept in the sandbox that shutdown has to flush
+pub fn needed(config: &Config) -> bool {
+    config.event_buffer.enabled || config.metering.enabled
+}
+
+/// The function as registered with the Extensions API
+pub struct Extension {
+    runtime_api: String,
+    /// What Lambda identifies the extension by on later calls
+    id: String,
+}
+
+impl Extension {
+    /// Registers as an extension subscribed to no events, which is what
+    /// makes Lambda send SIGTERM before shutdown. Internal extensions can't
+    /// subscribe to `SHUTDOWN` itself.
+    pub async fn register(runtime_api: &str) -> Result<Self, Error> {
+        let request = http::Request::post(format!("http://{}/2020-01-01/extension/register", runtime_api))
+            .header("Lambda-Extension-Name", EXTENSION_NAME)
+            .body(Full::new(Bytes::from_static(br#"{"events":[]}"#)))?;
+        let response = Client::builder(TokioExecutor::new()).build_http().request(request).await?;
+        if !response.status().is_success() {
+            return Err(format!("Extension registration failed with {}", response.status()).into());
+        }
+        let id = response
+            .headers()
+            .get(EXTENSION_ID_HEADER)
+            .and_then(|id| id.to_str().ok())
+            .ok_or("Extension registration returned no identifier")?;
+        Ok(Self {
+            runtime_api: runtime_api.to_string(),
+            id: id.to_string(),
+        })
+    }
+
+    /// Waits for the next event, returning its type
+    pub async fn next_event(&self) -> Result<String, Error> {
+        let request = http::Request::get(format!("http://{}/2020-01-01/extension/event/next", self.runtime_api))
+            .header(EXTENSION_ID_HEADER, &self.id)
+            .body(Full::new(Bytes::new()))?;
+        let response = Client::builder(TokioExecutor::new()).build_http().request(request).await?;
+        if !response.status().is_success() {
+            return Err(format!("Extension event poll failed with {}", response.status()).into());
+        }
+        let event: serde_json::Value = serde_json::from_slice(&response.into_body().collect().await?.to_bytes())?;
+        Ok(event["eventType"].as_str().unwrap_or_default().to_string())
+    }
+
+    /// Polls for events until one is `SHUTDOWN`
+    pub async fn until_shutdown(&self) -> Result<(), Error> {
+        loop {
+            if self.next_event().await? == "SHUTDOWN" {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Registers the function as an internal extension (see
+/// [`Extension::register`]). Has to happen during init; outside Lambda
+/// there's nothing to register with.
+pub async fn register_extension() -> Result<Option<Extension>, Error> {
+    match env_var("AWS_LAMBDA_RUNTIME_API") {
+        Some(runtime_api) => Extension::register(&runtime_api).await.map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Writes out everything the sandbox holds: buffered events first, since
+/// writing them can count usage, then the usage counts. Returns `false`
+/// if that took longer than the flush timeout.
+pub async fn flush(state: &Arc<AppState>) -> bool {
+    let flush = async {
+        if state.config.event_buffer.enabled {
+            buffer::flush(state, true).await;
+        }
+        if state.config.metering.enabled {
+            state.usage.flush().await;
+        }
+    };
+    tokio::time::timeout(state.config.shutdown.flush_timeout, flush).await.is_ok()
+}
+
+/// Flushes the sandbox when Lambda shuts it down, then exits. Polls the
+/// extension's events meanwhile, which also lets init finish.
+pub async fn flush_on_shutdown(state: Arc<AppState>, extension: Option<Extension>) -> Result<(), Error> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate())?;
+    let shutdown_event = async {
+        if let Some(extension) = extension {
+            match extension.until_shutdown().await {
+                Ok(()) => return,
+                Err(e) => tracing::warn!("Stopped polling extension events: {}", e),
+            }
+        }
+        std::future::pending().await
+    };
+    tokio::select! {
+        _ = terminate.recv() => {}
+        () = shutdown_event => {}
+    }
+    let state = state.with_current_config().await;
+    tracing::info!("Shutting down, flushing {} buffered events", state.event_buffer.len());
+    if !flush(&state).await {
+        tracing::error!(
+            "Shutdown flush timed out, losing {} buffered events",
+            state.event_buffer.len()
+        );
+    }
+    std::process::exit(0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::BufferConfig;
+    use crate::metering::{self, MeteringConfig, UsageMeter, UsageStore};
+    use crate::models::IngestEventPayload;
+    use crate::shared::test_state;
+    use crate::sink::EventSink;
+    use async_trait::async_trait;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct RecordingSink {
+        events: Mutex<Vec<IngestEventPayload>>,
+    }
+
+    #[async_trait]
+    impl EventSink for RecordingSink {
+        async fn send(&self, events: Vec<IngestEventPayload>) -> Result<(), Error> {
+            self.events.lock().unwrap().extend(events);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_flushes_buffered_events_and_usage() {
+        let sink = Arc::new(RecordingSink::default());
+        let usage = Arc::new(metering::InMemoryUsageStore::default());
+        let mut state = test_state(Config {
+            event_buffer: BufferConfig {
+                enabled: true,
+                max_events: 100,
+                max_age: Duration::from_secs(3600),
+                ..Default::default()
+            },
+            metering: MeteringConfig {
+                enabled: true,
+                flush_interval: Duration::from_secs(3600),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        state.event_sink = Some(sink.clone());
+        state.usage = Arc::new(UsageMeter::new(usage.clone()));
+        let state = Arc::new(state);
+
+        let event = IngestEventPayload {
+            project_id: "proj".to_string(),
+            event_type: "signup".to_string(),
+            ..Default::default()
+        };
+        assert!(state.event_buffer.push(vec![event], &state.config.event_buffer).is_none());
+        state.usage.record("proj", "2026-10", 1);
+
+        assert!(flush(&state).await);
+        assert_eq!(sink.events.lock().unwrap().len(), 1);
+        assert!(state.event_buffer.is_empty());
+        assert_eq!(usage.get("proj", "2026-10").await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_extension_polls_next_event_with_its_identifier() {
+        use hyper::body::Incoming;
+        use hyper_util::rt::TokioIo;
+
+        // A Runtime API that sends one INVOKE then SHUTDOWN
+        let listener = tokio::net::TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let runtime_api = listener.local_addr().unwrap().to_string();
+        // Path and extension id of every request
+        type Call = (String, Option<String>);
+        let calls: Arc<Mutex<Vec<Call>>> = Arc::default();
+        let seen = calls.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let seen = seen.clone();
+                let service = hyper::service::service_fn(move |request: http::Request<Incoming>| {
+                    let id = request.headers().get(EXTENSION_ID_HEADER).map(|id| id.to_str().unwrap().to_string());
+                    let mut seen = seen.lock().unwrap();
+                    seen.push((request.uri().path().to_string(), id));
+                    let body = match seen.len() {
+                        1 => "{}",
+                        2 => r#"{"eventType": "INVOKE"}"#,
+                        _ => r#"{"eventType": "SHUTDOWN"}"#,
+                    };
+                    let response = http::Response::builder()
+                        .header(EXTENSION_ID_HEADER, "ext-1")
+                        .body(Full::new(Bytes::from_static(body.as_bytes())));
+                    async move { response }
+                });
+                tokio::spawn(hyper::server::conn::http1::Builder::new().serve_connection(TokioIo::new(stream), service));
+            }
+        });
+
+        let extension = Extension::register(&runtime_api).await.unwrap();
+        extension.until_shutdown().await.unwrap();
+
+        let next = "/2020-01-01/extension/event/next".to_string();
+        let calls = calls.lock().unwrap();
+        assert_eq!(calls[0], ("/2020-01-01/extension/register".to_string(), None));
+        assert_eq!(calls[1..], [(next.clone(), Some("ext-1".to_string())), (next, Some("ext-1".to_string()))]);
+    }
+
+    #[test]
+    fn test_only_needed_when_something_is_kept() {
+        assert!(!needed(&Config::default()));
+        let mut config = Config::default();
+        config.metering.enabled = true;
+        assert!(needed(&config));
+    }
+}
@@ -0,0 +1,255 @@
+//! Signed payloads.
+//!
+//! A static API key proves who sent a request, not that its body is the
+//! one they sent or that it isn't a recording. With `SIGNING_ENABLED`,
+//! projects listed in `SIGNING_SECRETS` (`{"proj": ["secret", ...]}`, more
+//! than one while rotating) must sign every request:
+//! `X-Signature-Timestamp` holds the epoch seconds it was signed at and
+//! `X-Signature: sha256={hex}` the HMAC-SHA256 under one of the project's
+//! secrets of `{timestamp}.{body}` (the scheme `packages/webhook-forwarder`
+//! signs deliveries with). Timestamps more than `SIGNING_TOLERANCE_SECS`
+//! away from our clock are rejected, so a captured request can't be
+//! replayed later.
+//!
+//! Signatures cover the body as sent, and are checked before it's decoded
+//! or parsed, against the project of the bearer token or Segment write key
+//! in `Authorization`. Requests naming their project only in the body
+//! (a `/batch` envelope's `writeKey`) can't be checked that early, so a
+//! signing project's requests are rejected there by [`require`].
+
+use hmac::{Hmac, Mac};
+use lambda_http::{Body, Request, Response};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::handlers::decode_jwt;
+use crate::jwt::Verified;
+use crate::segment::basic_write_key;
+use crate::shared::{create_error_response, env_flag, env_json, env_or, header_value};
+
+/// Configuration for payload signing
+#[derive(Debug, Clone)]
+pub struct SigningConfig {
+    pub enabled: bool,
+    /// Signing secrets by project id
+    pub secrets: HashMap<String, Vec<String>>,
+    /// Largest difference between the signature's timestamp and our clock
+    pub tolerance: Duration,
+}
+
+impl Default for SigningConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            secrets: HashMap::new(),
+            tolerance: Duration::from_secs(300),
+        }
+    }
+}
+
+impl SigningConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            enabled: env_flag("SIGNING_ENABLED"),
+            secrets: env_json("SIGNING_SECRETS").unwrap_or_default(),
+            tolerance: Duration::from_secs(env_or("SIGNING_TOLERANCE_SECS", defaults.tolerance.as_secs())),
+        }
+    }
+
+    /// Secrets of a project that signs its requests
+    fn secrets_of(&self, project_id: &str) -> Option<&[String]> {
+        self.secrets
+            .get(project_id)
+            .map(Vec::as_slice)
+            .filter(|secrets| !secrets.is_empty())
+    }
+}
+
+/// Request extension naming the project whose signature the request carries
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signed(pub String);
+
+/// The `X-Signature` value for a body signed at `timestamp`
+pub fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    format!("sha256={}", hex::encode(mac(secret, timestamp, body).finalize().into_bytes()))
+}
+
+fn mac(secret: &str, timestamp: i64, body: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    mac
+}
+
+/// Project named by the `Authorization` header
+fn header_project(request: &Request) -> Option<String> {
+    if let Some(verified) = request.extensions().get::<Verified>() {
+        return Some(verified.project_id.clone());
+    }
+    let token = match header_value(request, "authorization")?.strip_prefix("Bearer ") {
+        Some(token) => token.trim().to_string(),
+        None => basic_write_key(request)?,
+    };
+    decode_jwt(&token).ok().map(|(project_id, _)| project_id)
+}
+
+/// Checks the signature of a request whose header names a signing project,
+/// marking it [`Signed`] when it holds
+pub fn verify(request: &mut Request, config: &SigningConfig) -> Result<(), String> {
+    if !config.enabled {
+        return Ok(());
+    }
+    let Some(project_id) = header_project(request) else {
+        return Ok(());
+    };
+    let Some(secrets) = config.secrets_of(&project_id) else {
+        return Ok(());
+    };
+
+    let signature = header_value(request, "x-signature").ok_or("Missing X-Signature header")?;
+    let timestamp: i64 = header_value(request, "x-signature-timestamp")
+        .ok_or("Missing X-Signature-Timestamp header")?
+        .parse()
+        .map_err(|_| "X-Signature-Timestamp must be epoch seconds")?;
+    let skew = (chrono::Utc::now().timestamp() - timestamp).unsigned_abs();
+    if skew > config.tolerance.as_secs() {
+        return Err("Signature timestamp is outside the allowed window".to_string());
+    }
+    let digest = signature
+        .strip_prefix("sha256=")
+        .and_then(|digest| hex::decode(digest).ok())
+        .ok_or("X-Signature must be sha256={hex}")?;
+
+    let body = match request.body() {
+        Body::Text(text) => text.as_bytes(),
+        Body::Binary(bytes) => bytes.as_slice(),
+        Body::Empty => &[],
+    };
+    // Compared in constant time
+    if !secrets
+        .iter()
+        .any(|secret| mac(secret, timestamp, body).verify_slice(&digest).is_ok())
+    {
+        tracing::warn!("Rejecting request with a bad signature for {}", project_id);
+        return Err("Invalid signature".to_string());
+    }
+
+    request.extensions_mut().insert(Signed(project_id));
+    Ok(())
+}
+
+/// Rejects a request for a signing project that didn't carry its
+/// signature, e.g. one naming its project only in the body
+pub fn require(request: &Request, project_id: &str, config: &SigningConfig) -> Option<Response<Body>> {
+    if !config.enabled || config.secrets_of(project_id).is_none() {
+        return None;
+    }
+    match request.extensions().get::<Signed>() {
+        Some(Signed(signed)) if signed == project_id => None,
+        _ => Some(create_error_response(401, "Unauthorized: requests for this project must be signed")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::function_handler;
+    use crate::shared::{test_state, Config};
+    use base64::Engine;
+    use std::sync::Arc;
+
+    fn token(project_id: &str) -> String {
+        let claims = serde_json::json!({ "projectId": project_id }).to_string();
+        format!("e30.{}.sig", base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(claims))
+    }
+
+    fn config() -> Config {
+        Config {
+            signing: SigningConfig {
+                enabled: true,
+                secrets: HashMap::from([("proj".to_string(), vec!["old".to_string(), "new".to_string()])]),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn post(path: &str, project_id: &str, body: &str, signature: Option<(i64, String)>) -> Request {
+        let mut builder = lambda_http::http::Request::builder()
+            .method("POST")
+            .uri(path)
+            .header("Authorization", format!("Bearer {}", token(project_id)));
+        if let Some((timestamp, signature)) = signature {
+            builder = builder
+                .header("X-Signature-Timestamp", timestamp.to_string())
+                .header("X-Signature", signature);
+        }
+        builder.body(Body::Text(body.to_string())).unwrap()
+    }
+
+    const VIEW: &str = r#"{"en":"pageview","ts":1700000000000,"o":"https://a.io/","r":"","sw":1,"sh":1}"#;
+
+    #[test]
+    fn test_signs_like_the_webhook_forwarder() {
+        assert_eq!(
+            sign("whsec_test", 1_700_000_000, br#"{"eventType":"signup"}"#),
+            "sha256=a576dc24bf9207504f4d7cd06366e0773ec711a2d84d23806121be2cca9cc345"
+        );
+    }
+
+    #[test]
+    fn test_verifies_signing_projects_only() {
+        let config = config().signing;
+        let now = chrono::Utc::now().timestamp();
+
+        // Either secret of the rotation works
+        for secret in ["old", "new"] {
+            let mut request = post("/view", "proj", VIEW, Some((now, sign(secret, now, VIEW.as_bytes()))));
+            assert_eq!(verify(&mut request, &config), Ok(()));
+            assert_eq!(request.extensions().get::<Signed>(), Some(&Signed("proj".to_string())));
+        }
+
+        let mut unsigned = post("/view", "other", VIEW, None);
+        assert_eq!(verify(&mut unsigned, &config), Ok(()));
+        assert!(unsigned.extensions().get::<Signed>().is_none());
+
+        let reject = |project_id: &str, signature: Option<(i64, String)>| {
+            verify(&mut post("/view", project_id, VIEW, signature), &config).unwrap_err()
+        };
+        assert_eq!(reject("proj", None), "Missing X-Signature header");
+        assert_eq!(reject("proj", Some((now, sign("wrong", now, VIEW.as_bytes())))), "Invalid signature");
+        assert_eq!(reject("proj", Some((now, sign("new", now, b"{}")))), "Invalid signature");
+        assert_eq!(
+            reject("proj", Some((now - 600, sign("new", now - 600, VIEW.as_bytes())))),
+            "Signature timestamp is outside the allowed window"
+        );
+        assert_eq!(reject("proj", Some((now, "deadbeef".to_string()))), "X-Signature must be sha256={hex}");
+    }
+
+    #[tokio::test]
+    async fn test_unsigned_requests_of_signing_projects_are_rejected() {
+        let state = Arc::new(test_state(Config {
+            batch_envelope: true,
+            ..config()
+        }));
+        let now = chrono::Utc::now().timestamp();
+
+        let response = function_handler(post("/view", "proj", VIEW, Some((now, sign("new", now, b"{}")))), state.clone())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 401);
+
+        // The writeKey names the project only once the body is parsed
+        let envelope = format!(r#"{{"events": [{}], "writeKey": "{}"}}"#, VIEW, token("proj"));
+        let request = lambda_http::http::Request::builder()
+            .method("POST")
+            .uri("/batch")
+            .body(Body::Text(envelope))
+            .unwrap();
+        let response = function_handler(request, state).await.unwrap();
+        assert_eq!(response.status(), 401);
+    }
+}
@@ -0,0 +1,188 @@
+//! EventBridge sink for other teams' consumers.
+//!
+//! With `EVENT_BUS_NAME` set, events are also published to that custom bus
+//! once the primary sink has taken them, so other teams can subscribe with
+//! rules instead of reading the raw stream. Each event is one entry whose
+//! `detail-type` is the event type, `source` is `EVENT_BUS_SOURCE` (default
+//! `product-analytics.ingestion`) and `detail` is the normalized event,
+//! projected to its project's allowed fields.
+//! Publishing is best effort: the events are already written, so a failure
+//! is logged rather than failing the request. Events of projects pinned to
+//! a residency zone are never published, as the bus is in the home region.
+
+use async_trait::async_trait;
+use lambda_http::Error;
+use serde_json::{json, Value};
+
+use super::EventSink;
+use crate::aws_json::AwsJsonClient;
+use crate::models::IngestEventPayload;
+use crate::projection::FieldProjection;
+use crate::shared::{env_opt, env_or};
+
+/// Most entries one `PutEvents` request may carry
+const MAX_ENTRIES_PER_REQUEST: usize = 10;
+/// Most entry bytes one `PutEvents` request may carry
+const MAX_BYTES_PER_REQUEST: usize = 256 * 1024;
+
+/// Configuration for the EventBridge sink
+#[derive(Debug, Clone)]
+pub struct EventBridgeConfig {
+    /// Bus name or ARN; nothing is published when unset
+    pub event_bus: Option<String>,
+    pub source: String,
+}
+
+impl Default for EventBridgeConfig {
+    fn default() -> Self {
+        Self {
+            event_bus: None,
+            source: "product-analytics.ingestion".to_string(),
+        }
+    }
+}
+
+impl EventBridgeConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            event_bus: env_opt("EVENT_BUS_NAME"),
+            source: env_or("EVENT_BUS_SOURCE", defaults.source),
+        }
+    }
+}
+
+/// `PutEvents` entries for the events, grouped into request-sized batches
+pub fn batches(
+    events: &[IngestEventPayload],
+    event_bus: &str,
+    source: &str,
+    projection: &FieldProjection,
+) -> Result<Vec<Vec<Value>>, Error> {
+    let mut batches: Vec<Vec<Value>> = Vec::new();
+    let mut bytes = 0;
+    for event in events {
+        let detail = projection.to_string(event)?;
+        // EventBridge counts the source, detail-type and detail
+        let size = source.len() + event.event_type.len() + detail.len();
+        let entry = json!({
+            "EventBusName": event_bus,
+            "Source": source,
+            "DetailType": event.event_type,
+            "Detail": detail,
+            "Time": event.timestamp / 1000,
+        });
+        let full = batches
+            .last()
+            .is_none_or(|batch| batch.len() == MAX_ENTRIES_PER_REQUEST || bytes + size > MAX_BYTES_PER_REQUEST);
+        if full {
+            batches.push(Vec::new());
+            bytes = 0;
+        }
+        bytes += size;
+        batches.last_mut().expect("batch just pushed").push(entry);
+    }
+    Ok(batches)
+}
+
+/// Publishes events to an EventBridge bus
+pub struct EventBridgeSink {
+    client: AwsJsonClient,
+    event_bus: String,
+    source: String,
+    projection: FieldProjection,
+}
+
+impl EventBridgeSink {
+    pub fn new(client: AwsJsonClient, event_bus: String, source: String, projection: &FieldProjection) -> Self {
+        Self {
+            client,
+            event_bus,
+            source,
+            projection: projection.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl EventSink for EventBridgeSink {
+    async fn send(&self, events: Vec<IngestEventPayload>) -> Result<(), Error> {
+        for batch in batches(&events, &self.event_bus, &self.source, &self.projection)? {
+            let output = self.client.call("PutEvents", &json!({ "Entries": batch })).await?;
+            let failed = output["FailedEntryCount"].as_u64().unwrap_or_default();
+            if failed > 0 {
+                let first = output["Entries"]
+                    .as_array()
+                    .and_then(|entries| entries.iter().find(|entry| entry.get("ErrorCode").is_some()))
+                    .cloned()
+                    .unwrap_or_default();
+                return Err(format!(
+                    "Failed to publish {} events to {}: {}: {}",
+                    failed,
+                    self.event_bus,
+                    first["ErrorCode"].as_str().unwrap_or_default(),
+                    first["ErrorMessage"].as_str().unwrap_or_default()
+                )
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    async fn check(&self) -> Result<(), Error> {
+        self.client.call("DescribeEventBus", &json!({ "Name": self.event_bus })).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(event_type: &str) -> IngestEventPayload {
+        IngestEventPayload {
+            project_id: "proj".to_string(),
+            event_type: event_type.to_string(),
+            timestamp: 1_700_000_000_123,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_entries_and_batches() {
+        let events: Vec<_> = (0..23).map(|_| event("signup")).collect();
+        let batches = batches(&events, "analytics", "product-analytics.ingestion", &FieldProjection::default()).unwrap();
+        assert_eq!(batches.iter().map(Vec::len).collect::<Vec<_>>(), [10, 10, 3]);
+
+        let entry = &batches[0][0];
+        assert_eq!(entry["EventBusName"], "analytics");
+        assert_eq!(entry["DetailType"], "signup");
+        assert_eq!(entry["Time"], 1_700_000_000);
+        let detail: IngestEventPayload = serde_json::from_str(entry["Detail"].as_str().unwrap()).unwrap();
+        assert_eq!(detail.project_id, "proj");
+
+        // Two 150 KiB events can't share a request
+        let large = event(&"x".repeat(150 * 1024));
+        let sizes: Vec<_> = super::batches(&[large.clone(), large], "b", "s", &FieldProjection::default())
+            .unwrap()
+            .iter()
+            .map(Vec::len)
+            .collect();
+        assert_eq!(sizes, [1, 1]);
+    }
+
+    #[test]
+    fn test_details_are_projected() {
+        let projection = FieldProjection {
+            projects: std::collections::HashMap::from([("proj".to_string(), vec![])]),
+        };
+        let signup = IngestEventPayload {
+            user_id: Some("u1".to_string()),
+            ..event("signup")
+        };
+
+        let batches = batches(&[signup], "analytics", "s", &projection).unwrap();
+        let detail: Value = serde_json::from_str(batches[0][0]["Detail"].as_str().unwrap()).unwrap();
+        assert_eq!(detail, json!({"projectId": "proj", "eventType": "signup", "timestamp": 1_700_000_000_123i64}));
+    }
+}
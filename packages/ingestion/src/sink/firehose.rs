@@ -0,0 +1,164 @@
+//! Firehose direct-put sink, for accounts without Kinesis Data Streams.
+//!
+//! With `EVENT_SINK=firehose` every accepted event becomes one record on the
+//! delivery stream `FIREHOSE_DELIVERY_STREAM`, its data the event's projected
+//! JSON and a newline, sent with `PutRecordBatch`. As with the `sqs` sink,
+//! stream routing, partitioning, record encoding and aggregation don't
+//! apply; residency zones still need their streams.
+
+use async_trait::async_trait;
+use base64::Engine;
+use lambda_http::Error;
+use serde_json::{json, Value};
+
+use super::{all_written, EventSink};
+use crate::aws_json::AwsJsonClient;
+use crate::models::IngestEventPayload;
+use crate::projection::FieldProjection;
+
+/// Most records one `PutRecordBatch` request may carry
+const MAX_RECORDS_PER_REQUEST: usize = 500;
+/// Most data bytes one `PutRecordBatch` request may carry
+const MAX_BYTES_PER_REQUEST: usize = 4 * 1024 * 1024;
+
+/// Serializes events into newline-terminated records grouped into
+/// request-sized batches
+pub fn batches(events: &[IngestEventPayload], projection: &FieldProjection) -> Result<Vec<Vec<Vec<u8>>>, Error> {
+    let mut batches: Vec<Vec<Vec<u8>>> = Vec::new();
+    let mut bytes = 0;
+    for event in events {
+        let mut data = projection.to_string(event)?.into_bytes();
+        data.push(b'\n');
+        let full = batches.last().is_none_or(|batch| {
+            batch.len() == MAX_RECORDS_PER_REQUEST || bytes + data.len() > MAX_BYTES_PER_REQUEST
+        });
+        if full {
+            batches.push(Vec::new());
+            bytes = 0;
+        }
+        bytes += data.len();
+        batches.last_mut().expect("batch just pushed").push(data);
+    }
+    Ok(batches)
+}
+
+/// Failed records of a `PutRecordBatch` response, by position in the batch
+fn failures(output: &Value) -> Vec<(usize, String)> {
+    let Some(responses) = output["RequestResponses"].as_array() else {
+        return Vec::new();
+    };
+    responses
+        .iter()
+        .enumerate()
+        .filter_map(|(position, response)| {
+            let code = response.get("ErrorCode")?.as_str()?;
+            let message = response["ErrorMessage"].as_str().unwrap_or_default();
+            Some((position, format!("{}: {}", code, message)))
+        })
+        .collect()
+}
+
+/// Puts events on a Firehose delivery stream
+pub struct FirehoseSink {
+    client: AwsJsonClient,
+    delivery_stream: String,
+    projection: FieldProjection,
+}
+
+impl FirehoseSink {
+    pub fn new(client: AwsJsonClient, delivery_stream: String, projection: &FieldProjection) -> Self {
+        Self {
+            client,
+            delivery_stream,
+            projection: projection.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl EventSink for FirehoseSink {
+    async fn send(&self, events: Vec<IngestEventPayload>) -> Result<(), Error> {
+        let mut failed = Vec::new();
+        let mut offset = 0;
+        for batch in batches(&events, &self.projection)? {
+            let count = batch.len();
+            let records: Vec<Value> = batch
+                .iter()
+                .map(|data| json!({ "Data": base64::engine::general_purpose::STANDARD.encode(data) }))
+                .collect();
+            let input = json!({ "DeliveryStreamName": self.delivery_stream, "Records": records });
+            match self.client.call("PutRecordBatch", &input).await {
+                Ok(output) => failed.extend(
+                    failures(&output)
+                        .into_iter()
+                        .map(|(position, reason)| (offset + position, reason)),
+                ),
+                // The other batches are still sent; these are retried
+                Err(e) => failed.extend((offset..offset + count).map(|position| (position, e.to_string()))),
+            }
+            offset += count;
+        }
+
+        if !failed.is_empty() {
+            tracing::error!(
+                "Failed to put {} of {} records on {}: {}",
+                failed.len(),
+                events.len(),
+                self.delivery_stream,
+                failed[0].1
+            );
+        }
+        all_written(failed)
+    }
+
+    async fn check(&self) -> Result<(), Error> {
+        let input = json!({ "DeliveryStreamName": self.delivery_stream, "Limit": 1 });
+        self.client.call("DescribeDeliveryStream", &input).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(name: &str) -> IngestEventPayload {
+        IngestEventPayload {
+            project_id: "proj".to_string(),
+            event_type: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_batches_respect_record_count_and_size() {
+        let batches = |events: &[IngestEventPayload]| batches(events, &FieldProjection::default());
+        let events: Vec<_> = (0..1_203).map(|_| event("signup")).collect();
+        let sizes: Vec<_> = batches(&events).unwrap().iter().map(Vec::len).collect();
+        assert_eq!(sizes, [500, 500, 203]);
+
+        // Three 1.5 MiB events can't share a request
+        let large = event(&"x".repeat(1536 * 1024));
+        let sizes: Vec<_> = batches(&[large.clone(), large.clone(), large]).unwrap().iter().map(Vec::len).collect();
+        assert_eq!(sizes, [2, 1]);
+
+        let record = &batches(&events).unwrap()[0][0];
+        assert_eq!(record.last(), Some(&b'\n'));
+        let body: IngestEventPayload = serde_json::from_slice(record).unwrap();
+        assert_eq!(body.event_type, "signup");
+    }
+
+    #[test]
+    fn test_failed_records_are_found_by_position() {
+        let output = json!({
+            "FailedPutCount": 1,
+            "RequestResponses": [
+                {"RecordId": "a"},
+                {"ErrorCode": "ServiceUnavailableException", "ErrorMessage": "Slow down."},
+                {"RecordId": "c"},
+            ],
+        });
+        assert_eq!(failures(&output), [(1, "ServiceUnavailableException: Slow down.".to_string())]);
+        assert!(failures(&json!({"FailedPutCount": 0})).is_empty());
+    }
+}
@@ -0,0 +1,228 @@
+//! Kafka sink, for deployments standardized on MSK or self-hosted Kafka.
+//!
+//! Built with the `kafka` feature and selected with `EVENT_SINK=kafka`.
+//! Each event is one message on `KAFKA_TOPIC`, keyed by project so a
+//! project's events stay in order within a partition, its value the
+//! event's projected JSON. With `KAFKA_IAM_AUTH` the producer authenticates to MSK
+//! with IAM: SASL/OAUTHBEARER, the token a presigned `kafka-cluster:Connect`
+//! request made with the function's credentials, refreshed by librdkafka
+//! before it expires.
+
+use async_trait::async_trait;
+use aws_credential_types::provider::{ProvideCredentials, SharedCredentialsProvider};
+use aws_credential_types::Credentials;
+use aws_sigv4::http_request::{sign, SignableBody, SignableRequest, SignatureLocation, SigningSettings};
+use aws_sigv4::sign::v4;
+use base64::Engine;
+use lambda_http::Error;
+use rdkafka::client::OAuthToken;
+use rdkafka::producer::{FutureProducer, FutureRecord, Producer};
+use rdkafka::{ClientConfig, ClientContext};
+use std::time::{Duration, SystemTime};
+
+use super::{EventSink, KafkaConfig};
+use crate::models::IngestEventPayload;
+use crate::projection::FieldProjection;
+
+/// How long an IAM auth token is valid
+const TOKEN_LIFETIME: Duration = Duration::from_secs(900);
+
+/// Produces events to a Kafka topic
+pub struct KafkaSink {
+    producer: FutureProducer<IamAuth>,
+    topic: String,
+    projection: FieldProjection,
+}
+
+impl KafkaSink {
+    pub fn new(config: &KafkaConfig, aws: &aws_config::SdkConfig, projection: &FieldProjection) -> Result<Self, Error> {
+        let mut client_config = ClientConfig::new();
+        client_config
+            .set("bootstrap.servers", config.bootstrap_servers.join(","))
+            .set("message.timeout.ms", config.timeout_ms.to_string());
+        let auth = if config.iam_auth {
+            client_config
+                .set("security.protocol", "SASL_SSL")
+                .set("sasl.mechanism", "OAUTHBEARER");
+            Some(IamAuth::Enabled {
+                credentials: aws.credentials_provider().ok_or("No AWS credentials provider")?,
+                region: aws.region().ok_or("No AWS region")?.to_string(),
+                runtime: tokio::runtime::Handle::current(),
+            })
+        } else {
+            None
+        };
+        Ok(Self {
+            producer: client_config.create_with_context(auth.unwrap_or(IamAuth::Disabled))?,
+            topic: config.topic.clone(),
+            projection: projection.clone(),
+        })
+    }
+}
+
+#[async_trait]
+impl EventSink for KafkaSink {
+    async fn send(&self, events: Vec<IngestEventPayload>) -> Result<(), Error> {
+        // Enqueue everything before waiting, so the producer can batch
+        let mut deliveries = Vec::with_capacity(events.len());
+        for event in &events {
+            let payload = self.projection.to_vec(event)?;
+            let record = FutureRecord::to(&self.topic).key(&event.project_id).payload(&payload);
+            let delivery = self
+                .producer
+                .send_result(record)
+                .map_err(|(e, _)| format!("Failed to enqueue event for {}: {}", self.topic, e))?;
+            deliveries.push(delivery);
+        }
+
+        let mut failures = Vec::new();
+        for delivery in deliveries {
+            match delivery.await {
+                Ok(Ok(_)) => {}
+                Ok(Err((e, _))) => failures.push(e.to_string()),
+                Err(_) => failures.push("delivery canceled".to_string()),
+            }
+        }
+        if let Some(reason) = failures.first() {
+            return Err(format!(
+                "Failed to write {} of {} events to {}: {}",
+                failures.len(),
+                events.len(),
+                self.topic,
+                reason
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    async fn check(&self) -> Result<(), Error> {
+        // Metadata fetches block, so keep them off the runtime's workers
+        let producer = self.producer.clone();
+        let topic = self.topic.clone();
+        tokio::task::spawn_blocking(move || {
+            let metadata = producer.client().fetch_metadata(Some(&topic), Duration::from_secs(2))?;
+            match metadata.topics().first().and_then(|t| t.error()) {
+                Some(e) => Err(format!("Topic {} unavailable: {:?}", topic, e).into()),
+                None => Ok(()),
+            }
+        })
+        .await?
+    }
+}
+
+/// Producer context that supplies MSK IAM tokens when enabled
+pub enum IamAuth {
+    Disabled,
+    Enabled {
+        credentials: SharedCredentialsProvider,
+        region: String,
+        /// Runtime for fetching credentials from librdkafka's thread
+        runtime: tokio::runtime::Handle,
+    },
+}
+
+impl ClientContext for IamAuth {
+    const ENABLE_REFRESH_OAUTH_TOKEN: bool = true;
+
+    fn generate_oauth_token(&self, _config: Option<&str>) -> Result<OAuthToken, Box<dyn std::error::Error>> {
+        let IamAuth::Enabled {
+            credentials,
+            region,
+            runtime,
+        } = self
+        else {
+            return Err("OAUTHBEARER needs KAFKA_IAM_AUTH".into());
+        };
+        let now = SystemTime::now();
+        let credentials = runtime.block_on(credentials.provide_credentials())?;
+        let token = auth_token(&credentials, region, now)?;
+        let expires = now + TOKEN_LIFETIME;
+        Ok(OAuthToken {
+            token,
+            principal_name: String::new(),
+            lifetime_ms: expires.duration_since(SystemTime::UNIX_EPOCH)?.as_millis() as i64,
+        })
+    }
+}
+
+/// The MSK IAM token: a presigned `kafka-cluster:Connect` URL, base64url
+/// encoded without padding
+pub fn auth_token(credentials: &Credentials, region: &str, time: SystemTime) -> Result<String, Box<dyn std::error::Error>> {
+    let identity = credentials.clone().into();
+    let url = format!("https://kafka.{}.amazonaws.com/?Action=kafka-cluster%3AConnect", region);
+    let mut settings = SigningSettings::default();
+    settings.signature_location = SignatureLocation::QueryParams;
+    settings.expires_in = Some(TOKEN_LIFETIME);
+    let params = v4::SigningParams::builder()
+        .identity(&identity)
+        .region(region)
+        .name("kafka-cluster")
+        .time(time)
+        .settings(settings)
+        .build()?
+        .into();
+    let signable = SignableRequest::new("GET", &url, std::iter::empty(), SignableBody::UnsignedPayload)?;
+    let (instructions, _) = sign(signable, &params)?.into_parts();
+    let mut request = http::Request::get(&url).body(())?;
+    instructions.apply_to_request_http1x(&mut request);
+    let signed = format!("{}&User-Agent=product-analytics-ingestion", request.uri());
+    Ok(base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(signed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_auth_token_is_a_presigned_connect_url() {
+        let credentials = Credentials::new("AKID", "secret", None, None, "test");
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let token = auth_token(&credentials, "us-east-1", time).unwrap();
+        let url = String::from_utf8(base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(token).unwrap()).unwrap();
+        assert!(url.starts_with("https://kafka.us-east-1.amazonaws.com/?Action=kafka-cluster%3AConnect"));
+        assert!(url.contains("X-Amz-Credential=AKID%2F20231114%2Fus-east-1%2Fkafka-cluster%2Faws4_request"));
+        assert!(url.contains("X-Amz-Expires=900"));
+        assert!(url.contains("X-Amz-Signature="));
+    }
+
+    #[tokio::test]
+    async fn test_messages_are_projected() {
+        use rdkafka::consumer::{BaseConsumer, Consumer};
+        use rdkafka::mocking::MockCluster;
+        use rdkafka::{Message, Offset, TopicPartitionList};
+
+        let cluster = MockCluster::new(1).unwrap();
+        cluster.create_topic("events", 1, 1).unwrap();
+        let config = KafkaConfig {
+            bootstrap_servers: vec![cluster.bootstrap_servers()],
+            ..Default::default()
+        };
+        let projection = FieldProjection {
+            projects: std::collections::HashMap::from([("proj".to_string(), vec!["userId".to_string()])]),
+        };
+        let sink = KafkaSink::new(&config, &aws_config::SdkConfig::builder().build(), &projection).unwrap();
+        let event = IngestEventPayload {
+            project_id: "proj".to_string(),
+            user_id: Some("u1".to_string()),
+            anonymous_id: Some("a1".to_string()),
+            ..Default::default()
+        };
+        sink.send(vec![event]).await.unwrap();
+
+        let consumer: BaseConsumer = ClientConfig::new()
+            .set("bootstrap.servers", cluster.bootstrap_servers())
+            .set("group.id", "test")
+            .create()
+            .unwrap();
+        let mut partitions = TopicPartitionList::new();
+        partitions.add_partition_offset("events", 0, Offset::Beginning).unwrap();
+        consumer.assign(&partitions).unwrap();
+        let message = consumer.poll(Duration::from_secs(10)).unwrap().unwrap();
+
+        assert_eq!(message.key(), Some(&b"proj"[..]));
+        let value: serde_json::Value = serde_json::from_slice(message.payload().unwrap()).unwrap();
+        assert_eq!(value["userId"], "u1");
+        assert!(value.get("anonymousId").is_none());
+    }
+}
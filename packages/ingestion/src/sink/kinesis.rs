@@ -0,0 +1,271 @@
+//! The Kinesis stream sink, the default destination.
+//!
+//! Events are grouped by stream (residency zone, then the quarantine stream
+//! for leniently validated events, then stream routes, then the bot stream,
+//! then `STREAM_NAME`), serialized and encoded, optionally
+//! aggregated, and written with `PutRecords`. Records that still fail go to
+//! the dead-letter sink when one is configured; without one, the send fails
+//! with a [`PartialWrite`] naming the events they carried, once every
+//! stream has been written. The dead-letter sink is in the home region, so
+//! failed records of a residency zone are always returned that way, for
+//! the client to retry into the zone. Kinesis consumers handle:
+//! 1. Firehose → S3 with native Parquet conversion, or Lambda → S3 Parquet
+//!    with our own schema and file sizing (`packages/parquet-writer`)
+//! 2. Lambda → ClickHouse for real-time analytics (`packages/clickhouse-writer`)
+//! 3. Lambda → DynamoDB for fast key-value queries (`packages/aggregator`)
+//! 4. Lambda → sessions stream with `session_start`/`session_end` events
+//!    (`packages/sessionizer`)
+//! 5. Lambda → resolved stream with `resolvedUserId` from an identity graph
+//!    (`packages/identity-resolver`)
+
+use async_trait::async_trait;
+use aws_sdk_kinesis::error::DisplayErrorContext;
+use aws_sdk_kinesis::types::StreamStatus;
+use aws_sdk_kinesis::Client as KinesisClient;
+use lambda_http::Error;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+
+use super::{EventSink, PartialWrite};
+use crate::aggregation;
+use crate::metrics::{MetricSet, Unit};
+use crate::models::IngestEventPayload;
+use crate::partitioning;
+use crate::put_records::{self, Record, Serialized};
+use crate::retry::RetryBudget;
+use crate::shared::{residency_zones, AppState, Config};
+use crate::validation;
+
+/// An event's record data: projected to the project's fields and encoded
+/// as configured
+pub(crate) fn record_data(event: &IngestEventPayload, config: &Config) -> Result<Vec<u8>, Error> {
+    if config.record_encoding.writes_json() {
+        return Ok(config.field_projection.to_vec(event)?);
+    }
+    let record = config.field_projection.to_value(event)?;
+    Ok(config.record_encoding.encode(&record)?)
+}
+
+/// Writes events to the Kinesis streams of a request's state
+pub struct KinesisSink {
+    state: Arc<AppState>,
+}
+
+impl KinesisSink {
+    pub fn new(state: Arc<AppState>) -> Self {
+        Self { state }
+    }
+}
+
+#[async_trait]
+impl EventSink for KinesisSink {
+    async fn send(&self, events: Vec<IngestEventPayload>) -> Result<(), Error> {
+        let state = &self.state;
+        if events.is_empty() {
+            return Ok(());
+        }
+
+        tracing::info!("Sending {} events to Kinesis Stream", events.len());
+        let zones = residency_zones(&events, state)?;
+
+        // Group by destination stream, keeping each project's events in order
+        let bot_stream = state.config.bot_filter.bot_stream();
+        let quarantine_stream = state.config.validation.quarantine_stream.as_deref();
+        let mut by_stream: HashMap<(Option<&str>, &str), Vec<Serialized>> = HashMap::new();
+        for event in &events {
+            let record_data = record_data(event, &state.config)?;
+            let zone = zones.get(&event.project_id).copied();
+            let is_bot = event.context.as_ref().is_some_and(|c| c.is_bot == Some(true));
+            let route = quarantine_stream
+                .filter(|_| validation::is_quarantined(event))
+                .or_else(|| state.config.stream_routing.stream_for(event));
+            let stream_name = match (zone, route, bot_stream) {
+                (Some(zone), _, _) => state.config.residency.streams[zone].stream_name.as_str(),
+                (None, Some(route), _) => route,
+                (None, None, Some(bot_stream)) if is_bot => bot_stream,
+                (None, None, _) => state.streams.default_stream(),
+            };
+            by_stream.entry((zone, stream_name)).or_default().push(Serialized {
+                event,
+                key: partitioning::partition_key(event, &state.config.partitioning),
+                data: record_data,
+            });
+        }
+        let by_stream = by_stream
+            .into_iter()
+            .map(|(stream, serialized)| {
+                let records = if state.config.aggregation.enabled {
+                    aggregation::aggregate(serialized, &state.config.aggregation)?
+                } else {
+                    serialized
+                        .into_iter()
+                        .map(Record::new)
+                        .collect::<Result<_, _>>()?
+                };
+                Ok((stream, records))
+            })
+            .collect::<Result<HashMap<_, Vec<Record>>, Error>>()?;
+
+        // Partitioned by the configured strategy (see `partitioning`)
+        let mut budget = RetryBudget::new(state.config.retry.budget);
+        let mut dead_letters = Vec::new();
+        // Records only borrow their events, so failures are traced back to
+        // positions in `events` by address
+        let address = |event: &IngestEventPayload| event as *const IngestEventPayload as usize;
+        let positions: HashMap<usize, usize> = events
+            .iter()
+            .enumerate()
+            .map(|(position, event)| (address(event), position))
+            .collect();
+        let mut failed = Vec::new();
+        for ((zone, stream_name), records) in &by_stream {
+            let client = match zone {
+                Some(zone) => &state.regional_kinesis[*zone],
+                None => state.streams.client(stream_name),
+            };
+
+            let started = Instant::now();
+            let failures =
+                put_records::put_all(client, stream_name, records, &state.config.retry, &mut budget).await;
+            MetricSet::new(&state.config.metrics)
+                .dimension("Stream", *stream_name)
+                .metric("StreamWriteLatency", started.elapsed().as_secs_f64() * 1000.0, Unit::Milliseconds)
+                .count("StreamRecords", records.len())
+                .count("StreamFailedRecords", failures.len())
+                .emit();
+
+            state.sink_health.record(failures.is_empty());
+            let (attempts, throttled) = budget.take_attempts();
+            state.shard_pressure.record(attempts, throttled, &state.config.backpressure);
+            if let Some((_, reason)) = failures.first() {
+                // Pinned records never leave their zone, not even to dead-letter
+                if state.dead_letter_sink.is_none() || zone.is_some() {
+                    tracing::error!(
+                        "Failed to write {} of {} records to {}: {}",
+                        failures.len(),
+                        records.len(),
+                        stream_name,
+                        reason
+                    );
+                    // The other streams are still written; the caller learns
+                    // which events didn't make it
+                    failed.extend(failures.iter().flat_map(|(index, reason)| {
+                        records[*index]
+                            .events
+                            .iter()
+                            .map(|&event| (positions[&address(event)], reason.clone()))
+                    }));
+                    continue;
+                }
+                tracing::warn!("Dead-lettering {} events after failed writes: {}", failures.len(), reason);
+                dead_letters.extend(
+                    failures
+                        .iter()
+                        .flat_map(|(index, _)| records[*index].events.iter().map(|&event| event.clone())),
+                );
+            }
+        }
+
+        if let Some(ref sink) = state.dead_letter_sink {
+            sink.send(dead_letters).await?;
+        }
+        if !failed.is_empty() {
+            failed.sort_by_key(|(position, _)| *position);
+            return Err(Box::new(PartialWrite { failed }));
+        }
+
+        tracing::info!("Successfully sent {} events to Kinesis Stream", events.len());
+        Ok(())
+    }
+
+    /// Describes every stream events can be routed to
+    async fn check(&self) -> Result<(), Error> {
+        let state = &self.state;
+        let mut streams: Vec<(&str, &KinesisClient)> = Vec::new();
+        let home = std::iter::once(state.streams.default_stream())
+            .chain(state.config.stream_routing.routes.iter().map(|route| route.stream.as_str()))
+            .chain(state.config.bot_filter.bot_stream())
+            .chain(state.config.validation.quarantine_stream.as_deref());
+        for stream in home.filter(|stream| !stream.is_empty()) {
+            streams.push((stream, state.streams.client(stream)));
+        }
+        for (zone, regional) in &state.config.residency.streams {
+            if let Some(client) = state.regional_kinesis.get(zone) {
+                streams.push((&regional.stream_name, client));
+            }
+        }
+        streams.sort_by_key(|(stream, _)| *stream);
+        streams.dedup_by_key(|(stream, _)| *stream);
+
+        for (stream, client) in streams {
+            let summary = client
+                .describe_stream_summary()
+                .stream_name(stream)
+                .send()
+                .await
+                .map_err(|e| format!("Stream {} unreachable: {}", stream, DisplayErrorContext(e)))?;
+            let status = summary
+                .stream_description_summary()
+                .map(|summary| summary.stream_status().clone());
+            if let Some(status @ (StreamStatus::Creating | StreamStatus::Deleting)) = status {
+                return Err(format!("Stream {} is {}", stream, status.as_str()).into());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::projection::FieldProjection;
+    use crate::shared::test_state;
+    use crate::sink::{PartialWrite, RecordingSink};
+
+    #[tokio::test]
+    async fn test_pinned_failures_are_not_dead_lettered() {
+        let mut config = Config::default();
+        config.retry.max_attempts = 1;
+        config.residency.project_zones.insert("eu-tenant".to_string(), "eu".to_string());
+        config.residency.streams =
+            serde_json::from_str(r#"{"eu": {"region": "eu-central-1", "streamName": "events-eu"}}"#).unwrap();
+        let mut state = test_state(config);
+        // The test clients have no credentials, so every write fails
+        state.regional_kinesis.insert("eu".to_string(), state.streams.client("events-eu").clone());
+        let dead_letters = Arc::new(RecordingSink::default());
+        state.dead_letter_sink = Some(dead_letters.clone());
+        let event = |project_id: &str| IngestEventPayload {
+            project_id: project_id.to_string(),
+            event_type: "pageview".to_string(),
+            ..Default::default()
+        };
+
+        let sink = KinesisSink::new(Arc::new(state));
+        let error = sink.send(vec![event("p"), event("eu-tenant")]).await.unwrap_err();
+        let failed = &error.downcast_ref::<PartialWrite>().unwrap().failed;
+        assert_eq!(failed.iter().map(|(position, _)| *position).collect::<Vec<_>>(), [1]);
+        let dead_lettered: Vec<_> = dead_letters.events.lock().unwrap().iter().map(|e| e.project_id.clone()).collect();
+        assert_eq!(dead_lettered, ["p"]);
+    }
+
+    #[test]
+    fn test_record_data_is_projected() {
+        let config = Config {
+            field_projection: FieldProjection {
+                projects: HashMap::from([("proj".to_string(), vec!["userId".to_string()])]),
+            },
+            ..Default::default()
+        };
+        let event = IngestEventPayload {
+            project_id: "proj".to_string(),
+            user_id: Some("u1".to_string()),
+            anonymous_id: Some("a1".to_string()),
+            ..Default::default()
+        };
+
+        let record: serde_json::Value = serde_json::from_slice(&record_data(&event, &config).unwrap()).unwrap();
+        assert_eq!(record["userId"], "u1");
+        assert!(record.get("anonymousId").is_none());
+    }
+}
@@ -0,0 +1,81 @@
+//! Local sink for offline development.
+//!
+//! Appends each event as a line of JSON to a file, or prints it to stdout,
+//! so SDK integrations can be checked without AWS credentials or a stream.
+
+use async_trait::async_trait;
+use lambda_http::Error;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use super::s3_dead_letter::encode;
+use super::EventSink;
+use crate::models::IngestEventPayload;
+use crate::projection::FieldProjection;
+
+/// Writes events as NDJSON to a file or stdout
+#[derive(Debug, Default)]
+pub struct LocalSink {
+    /// Appended to; stdout when unset
+    path: Option<PathBuf>,
+    /// Keeps concurrent requests' lines whole
+    lock: Mutex<()>,
+    /// Shows what the configured allowlists let through
+    projection: FieldProjection,
+}
+
+impl LocalSink {
+    pub fn new(path: Option<PathBuf>, projection: &FieldProjection) -> Self {
+        Self {
+            path,
+            lock: Mutex::new(()),
+            projection: projection.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl EventSink for LocalSink {
+    async fn send(&self, events: Vec<IngestEventPayload>) -> Result<(), Error> {
+        let lines = encode(&events, &self.projection)?;
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        match self.path {
+            Some(ref path) => std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?
+                .write_all(&lines)?,
+            None => std::io::stdout().lock().write_all(&lines)?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_appends_ndjson_to_file() {
+        let path = std::env::temp_dir().join(format!("local-sink-{}.ndjson", uuid::Uuid::new_v4()));
+        let projection = FieldProjection {
+            projects: std::collections::HashMap::from([("p".to_string(), vec![])]),
+        };
+        let sink = LocalSink::new(Some(path.clone()), &projection);
+        let event = IngestEventPayload {
+            project_id: "p".to_string(),
+            user_id: Some("u1".to_string()),
+            ..Default::default()
+        };
+        sink.send(vec![event.clone()]).await.unwrap();
+        sink.send(vec![event]).await.unwrap();
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(written.lines().count(), 2);
+        let first: IngestEventPayload = serde_json::from_str(written.lines().next().unwrap()).unwrap();
+        assert_eq!(first.project_id, "p");
+        assert_eq!(first.user_id, None);
+    }
+}
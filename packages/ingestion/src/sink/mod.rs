@@ -0,0 +1,247 @@
+//! Destinations for accepted events.
+//!
+//! `process_events` hands what it accepted to one sink, picked by
+//! `EVENT_SINK`:
+//!
+//! - `kinesis` (default): the Kinesis streams, see [`kinesis`]
+//! - `sqs`: the queue at `EVENT_SINK_QUEUE_URL`, see [`sqs`]
+//! - `firehose`: the delivery stream `FIREHOSE_DELIVERY_STREAM`, see
+//!   [`firehose`]
+//! - `sns`: the topic `EVENT_SINK_TOPIC_ARN`, see [`sns`]
+//! - `kafka`: the topic `KAFKA_TOPIC` on `KAFKA_BOOTSTRAP_SERVERS`, in
+//!   builds with the `kafka` feature (see `sink::kafka`)
+//!
+//! An unknown `EVENT_SINK`, or one missing its destination, fails startup.
+//! Whichever is chosen, events of projects pinned to a residency zone are
+//! still written to their zone's stream. `OFFLINE_MODE` overrides all of
+//! them with a [`local`] sink.
+//!
+//! The S3 Parquet, dead-letter, fallback and EventBridge sinks sit
+//! alongside whichever is chosen, and a [`shadow`] stream can be written
+//! next to it while migrating.
+
+use async_trait::async_trait;
+use lambda_http::Error;
+
+use crate::models::IngestEventPayload;
+use crate::put_records::Failure;
+use crate::shared::{env_flag, env_list, env_opt, env_or};
+
+pub mod eventbridge;
+pub mod firehose;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod kinesis;
+pub mod local;
+pub mod s3_dead_letter;
+pub mod s3_fallback;
+pub mod s3_parquet;
+pub mod shadow;
+pub mod sns;
+pub mod sqs;
+pub mod sqs_dead_letter;
+
+/// A destination that accepted events are handed to
+#[async_trait]
+pub trait EventSink: Send + Sync {
+    async fn send(&self, events: Vec<IngestEventPayload>) -> Result<(), Error>;
+
+    /// Makes a cheap read-only call showing the destination is reachable,
+    /// for `GET /health`; sinks with nothing to reach report healthy
+    async fn check(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Error of a sink that wrote some of the events it was sent but not
+/// others, so callers can tell the client which ones to retry
+#[derive(Debug)]
+pub struct PartialWrite {
+    /// The events not written, by position among those sent, with the reason
+    pub failed: Vec<Failure>,
+}
+
+impl std::fmt::Display for PartialWrite {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = self.failed.first().map_or("", |(_, reason)| reason.as_str());
+        write!(f, "Failed to write {} events: {}", self.failed.len(), reason)
+    }
+}
+
+impl std::error::Error for PartialWrite {}
+
+/// `Ok` if every event was written, else the failures as one error
+pub fn all_written(failed: Vec<Failure>) -> Result<(), Error> {
+    if failed.is_empty() {
+        return Ok(());
+    }
+    Err(Box::new(PartialWrite { failed }))
+}
+
+/// Which sink accepted events are written to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SinkKind {
+    #[default]
+    Kinesis,
+    Sqs,
+    Firehose,
+    Sns,
+    Kafka,
+}
+
+impl std::str::FromStr for SinkKind {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "kinesis" => Ok(Self::Kinesis),
+            "sqs" => Ok(Self::Sqs),
+            "firehose" => Ok(Self::Firehose),
+            "sns" => Ok(Self::Sns),
+            "kafka" if cfg!(feature = "kafka") => Ok(Self::Kafka),
+            "kafka" => Err("the kafka sink needs a build with the `kafka` feature".to_string()),
+            other => Err(format!("unknown event sink \"{}\"", other)),
+        }
+    }
+}
+
+/// Configuration for the event sink
+#[derive(Debug, Clone, Default)]
+pub struct SinkConfig {
+    pub kind: SinkKind,
+    /// Why `EVENT_SINK` names no sink of this build; fails `validate`
+    pub invalid_kind: Option<String>,
+    /// Queue the `sqs` sink sends to
+    pub queue_url: Option<String>,
+    /// Delivery stream the `firehose` sink puts records on
+    pub delivery_stream: Option<String>,
+    /// Topic the `sns` sink publishes to
+    pub topic_arn: Option<String>,
+    pub kafka: KafkaConfig,
+}
+
+/// Configuration for the `kafka` sink
+#[derive(Debug, Clone)]
+pub struct KafkaConfig {
+    pub bootstrap_servers: Vec<String>,
+    pub topic: String,
+    /// Authenticate to MSK with the function's IAM role
+    pub iam_auth: bool,
+    /// How long librdkafka may take to deliver a message
+    pub timeout_ms: u64,
+}
+
+impl Default for KafkaConfig {
+    fn default() -> Self {
+        Self {
+            bootstrap_servers: Vec::new(),
+            topic: "events".to_string(),
+            iam_auth: false,
+            timeout_ms: 5_000,
+        }
+    }
+}
+
+impl KafkaConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            bootstrap_servers: env_list("KAFKA_BOOTSTRAP_SERVERS"),
+            topic: env_or("KAFKA_TOPIC", defaults.topic),
+            iam_auth: env_flag("KAFKA_IAM_AUTH"),
+            timeout_ms: env_or("KAFKA_TIMEOUT_MS", defaults.timeout_ms),
+        }
+    }
+}
+
+impl SinkConfig {
+    pub fn from_env() -> Self {
+        let kind = env_opt::<String>("EVENT_SINK").map(|value| value.parse::<SinkKind>());
+        Self {
+            kind: kind.clone().and_then(Result::ok).unwrap_or_default(),
+            invalid_kind: kind.and_then(Result::err),
+            queue_url: env_opt("EVENT_SINK_QUEUE_URL"),
+            delivery_stream: env_opt("FIREHOSE_DELIVERY_STREAM"),
+            topic_arn: env_opt("EVENT_SINK_TOPIC_ARN"),
+            kafka: KafkaConfig::from_env(),
+        }
+    }
+
+    /// Fails on a sink that doesn't exist or has nowhere to write, rather
+    /// than quietly writing to Kinesis instead
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(ref e) = self.invalid_kind {
+            return Err(format!("Invalid EVENT_SINK: {}", e));
+        }
+        let missing = match self.kind {
+            SinkKind::Kinesis => None,
+            SinkKind::Sqs => self.queue_url.is_none().then_some("EVENT_SINK_QUEUE_URL"),
+            SinkKind::Firehose => self.delivery_stream.is_none().then_some("FIREHOSE_DELIVERY_STREAM"),
+            SinkKind::Sns => self.topic_arn.is_none().then_some("EVENT_SINK_TOPIC_ARN"),
+            SinkKind::Kafka => self.kafka.bootstrap_servers.is_empty().then_some("KAFKA_BOOTSTRAP_SERVERS"),
+        };
+        match missing {
+            Some(variable) => {
+                let name = format!("{:?}", self.kind).to_ascii_lowercase();
+                Err(format!("EVENT_SINK={} needs {}", name, variable))
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+/// Keeps everything it is sent, for assertions
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct RecordingSink {
+    pub events: std::sync::Mutex<Vec<IngestEventPayload>>,
+}
+
+#[cfg(test)]
+#[async_trait]
+impl EventSink for RecordingSink {
+    async fn send(&self, events: Vec<IngestEventPayload>) -> Result<(), Error> {
+        self.events.lock().unwrap().extend(events);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sink_kinds() {
+        assert_eq!("SQS".parse(), Ok(SinkKind::Sqs));
+        assert_eq!(" kinesis".parse(), Ok(SinkKind::Kinesis));
+        assert_eq!("firehose".parse(), Ok(SinkKind::Firehose));
+        assert_eq!("sns".parse(), Ok(SinkKind::Sns));
+        assert!("kinesis-firehose".parse::<SinkKind>().is_err());
+        assert_eq!("kafka".parse::<SinkKind>().is_ok(), cfg!(feature = "kafka"));
+    }
+
+    #[test]
+    fn test_unusable_sinks_fail_validation() {
+        assert!(SinkConfig::default().validate().is_ok());
+
+        let unknown = SinkConfig {
+            invalid_kind: "kinesis-firehose".parse::<SinkKind>().err(),
+            ..Default::default()
+        };
+        assert!(unknown.validate().unwrap_err().contains("unknown event sink"));
+
+        let mut firehose = SinkConfig {
+            kind: SinkKind::Firehose,
+            ..Default::default()
+        };
+        assert!(firehose.validate().unwrap_err().contains("FIREHOSE_DELIVERY_STREAM"));
+        firehose.delivery_stream = Some("events".to_string());
+        assert!(firehose.validate().is_ok());
+
+        let sqs = SinkConfig {
+            kind: SinkKind::Sqs,
+            ..Default::default()
+        };
+        assert!(sqs.validate().unwrap_err().contains("EVENT_SINK_QUEUE_URL"));
+    }
+}
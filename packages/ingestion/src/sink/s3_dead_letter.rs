@@ -0,0 +1,151 @@
+//! Dead-letter sink for records the stream wouldn't take.
+//!
+//! Records that still fail after their retries (or once the batch's retry
+//! budget is spent) are written to S3 as one NDJSON object per batch, so
+//! they can be inspected and replayed instead of failing the request.
+
+use async_trait::async_trait;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::Client as S3Client;
+use lambda_http::Error;
+
+use super::EventSink;
+use crate::models::IngestEventPayload;
+use crate::projection::FieldProjection;
+use crate::shared::{env_or, env_var};
+
+/// Configuration for the dead-letter sink
+#[derive(Debug, Clone)]
+pub struct DeadLetterConfig {
+    /// Destination bucket; failures are returned to the client when neither
+    /// this nor `queue_url` is set
+    pub bucket: Option<String>,
+    pub prefix: String,
+    /// SQS queue to send failed records to instead of the bucket
+    pub queue_url: Option<String>,
+}
+
+impl Default for DeadLetterConfig {
+    fn default() -> Self {
+        Self {
+            bucket: None,
+            prefix: "dead-letter".to_string(),
+            queue_url: None,
+        }
+    }
+}
+
+impl DeadLetterConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            bucket: env_var("DEAD_LETTER_BUCKET"),
+            prefix: env_or("DEAD_LETTER_PREFIX", defaults.prefix),
+            queue_url: env_var("DLQ_QUEUE_URL"),
+        }
+    }
+}
+
+/// Encodes events as newline-delimited JSON, projected to their projects'
+/// allowed fields
+pub fn encode(events: &[IngestEventPayload], projection: &FieldProjection) -> Result<Vec<u8>, Error> {
+    let mut buffer = Vec::new();
+    for event in events {
+        projection.to_writer(&mut buffer, event)?;
+        buffer.push(b'\n');
+    }
+    Ok(buffer)
+}
+
+/// Checks that a sink's bucket exists and is reachable
+pub async fn check_bucket(client: &S3Client, bucket: &str) -> Result<(), Error> {
+    client
+        .head_bucket()
+        .bucket(bucket)
+        .send()
+        .await
+        .map_err(|e| format!("Bucket {} unreachable: {}", bucket, aws_sdk_s3::error::DisplayErrorContext(e)))?;
+    Ok(())
+}
+
+/// Writes failed records to S3
+pub struct S3DeadLetterSink {
+    client: S3Client,
+    bucket: String,
+    prefix: String,
+    projection: FieldProjection,
+}
+
+impl S3DeadLetterSink {
+    pub fn new(client: S3Client, bucket: String, config: &DeadLetterConfig, projection: &FieldProjection) -> Self {
+        Self {
+            client,
+            bucket,
+            prefix: config.prefix.clone(),
+            projection: projection.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl EventSink for S3DeadLetterSink {
+    async fn send(&self, events: Vec<IngestEventPayload>) -> Result<(), Error> {
+        if events.is_empty() {
+            return Ok(());
+        }
+
+        let now = chrono::Utc::now();
+        let key = format!(
+            "{}/dt={}/{}-{}.ndjson",
+            self.prefix,
+            now.format("%Y-%m-%d"),
+            now.timestamp_millis(),
+            uuid::Uuid::new_v4()
+        );
+
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .content_type("application/x-ndjson")
+            .body(ByteStream::from(encode(&events, &self.projection)?))
+            .send()
+            .await?;
+
+        tracing::warn!("Dead-lettered {} events to s3://{}/{}", events.len(), self.bucket, key);
+        Ok(())
+    }
+
+    async fn check(&self) -> Result<(), Error> {
+        check_bucket(&self.client, &self.bucket).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_lines_are_projected() {
+        let projection = FieldProjection {
+            projects: HashMap::from([("strict".to_string(), vec!["userId".to_string()])]),
+        };
+        let event = |project_id: &str| IngestEventPayload {
+            project_id: project_id.to_string(),
+            user_id: Some("u1".to_string()),
+            anonymous_id: Some("a1".to_string()),
+            ..Default::default()
+        };
+
+        let ndjson = encode(&[event("strict"), event("open")], &projection).unwrap();
+        let lines: Vec<serde_json::Value> = serde_json::Deserializer::from_slice(&ndjson)
+            .into_iter()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["userId"], "u1");
+        assert!(lines[0].get("anonymousId").is_none());
+        assert_eq!(lines[1]["anonymousId"], "a1");
+    }
+}
@@ -0,0 +1,175 @@
+//! Emergency S3 fallback for when the event sink is down.
+//!
+//! If the event sink still fails after its retries, and the dead-letter
+//! sink (if any) fails too, `process_events` hands the events here instead
+//! of failing the request. They're buffered by project and day and each
+//! group is written as one gzipped NDJSON object under
+//! `{prefix}/project={project}/dt={day}/`, so an outage loses nothing and
+//! replay can go project by project. Events the sink took before it failed
+//! are written again, so replay should dedupe on `eventId`. Batches with a
+//! residency-pinned project never fall back, as the bucket is in the home
+//! region.
+
+use async_trait::async_trait;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::Client as S3Client;
+use flate2::write::GzEncoder;
+use lambda_http::Error;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use super::s3_dead_letter::{check_bucket, encode};
+use super::EventSink;
+use crate::models::IngestEventPayload;
+use crate::projection::FieldProjection;
+use crate::shared::{env_opt, env_or};
+
+/// Configuration for the fallback sink
+#[derive(Debug, Clone)]
+pub struct FallbackConfig {
+    /// Fallback bucket; failures are returned to the client when unset
+    pub bucket: Option<String>,
+    pub prefix: String,
+}
+
+impl Default for FallbackConfig {
+    fn default() -> Self {
+        Self {
+            bucket: None,
+            prefix: "fallback".to_string(),
+        }
+    }
+}
+
+impl FallbackConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            bucket: env_opt("FALLBACK_BUCKET"),
+            prefix: env_or("FALLBACK_PREFIX", defaults.prefix),
+        }
+    }
+}
+
+/// Events grouped by project and UTC day of their timestamp, in key order
+pub fn buffer(events: Vec<IngestEventPayload>) -> BTreeMap<(String, String), Vec<IngestEventPayload>> {
+    let mut buffer: BTreeMap<(String, String), Vec<IngestEventPayload>> = BTreeMap::new();
+    for event in events {
+        let day = chrono::DateTime::from_timestamp_millis(event.timestamp)
+            .unwrap_or_else(chrono::Utc::now)
+            .format("%Y-%m-%d")
+            .to_string();
+        buffer.entry((event.project_id.clone(), day)).or_default().push(event);
+    }
+    buffer
+}
+
+/// Gzipped NDJSON
+pub fn compress(events: &[IngestEventPayload], projection: &FieldProjection) -> Result<Vec<u8>, Error> {
+    let mut gzip = GzEncoder::new(Vec::new(), flate2::Compression::default());
+    gzip.write_all(&encode(events, projection)?)?;
+    Ok(gzip.finish()?)
+}
+
+/// Writes events to the fallback bucket
+pub struct S3FallbackSink {
+    client: S3Client,
+    bucket: String,
+    prefix: String,
+    projection: FieldProjection,
+}
+
+impl S3FallbackSink {
+    pub fn new(client: S3Client, bucket: String, config: &FallbackConfig, projection: &FieldProjection) -> Self {
+        Self {
+            client,
+            bucket,
+            prefix: config.prefix.clone(),
+            projection: projection.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl EventSink for S3FallbackSink {
+    async fn send(&self, events: Vec<IngestEventPayload>) -> Result<(), Error> {
+        let now = chrono::Utc::now().timestamp_millis();
+        for ((project_id, day), events) in buffer(events) {
+            let key = format!(
+                "{}/project={}/dt={}/{}-{}.ndjson.gz",
+                self.prefix,
+                project_id,
+                day,
+                now,
+                uuid::Uuid::new_v4()
+            );
+
+            self.client
+                .put_object()
+                .bucket(&self.bucket)
+                .key(&key)
+                .content_type("application/x-ndjson")
+                .content_encoding("gzip")
+                .body(ByteStream::from(compress(&events, &self.projection)?))
+                .send()
+                .await?;
+
+            tracing::error!("Wrote {} events to fallback s3://{}/{}", events.len(), self.bucket, key);
+        }
+        Ok(())
+    }
+
+    async fn check(&self) -> Result<(), Error> {
+        check_bucket(&self.client, &self.bucket).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn event(project_id: &str, timestamp: i64) -> IngestEventPayload {
+        IngestEventPayload {
+            project_id: project_id.to_string(),
+            timestamp,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_buffers_by_project_and_day() {
+        let day = 86_400_000;
+        let events = vec![event("b", 0), event("a", day + 1), event("a", day + 2), event("a", 0)];
+        let buffer = buffer(events);
+        let keys: Vec<_> = buffer.keys().map(|(p, d)| format!("{}/{}", p, d)).collect();
+        assert_eq!(keys, ["a/1970-01-01", "a/1970-01-02", "b/1970-01-01"]);
+        assert_eq!(buffer[&("a".to_string(), "1970-01-02".to_string())].len(), 2);
+
+        let mut ndjson = String::new();
+        let compressed = compress(&[event("a", 1), event("a", 2)], &FieldProjection::default()).unwrap();
+        flate2::read::GzDecoder::new(&compressed[..])
+            .read_to_string(&mut ndjson)
+            .unwrap();
+        assert_eq!(ndjson.lines().count(), 2);
+    }
+
+    #[test]
+    fn test_fallback_objects_are_projected() {
+        let projection = FieldProjection {
+            projects: std::collections::HashMap::from([("a".to_string(), vec![])]),
+        };
+        let tagged = IngestEventPayload {
+            user_id: Some("u1".to_string()),
+            ..event("a", 1)
+        };
+
+        let mut ndjson = String::new();
+        flate2::read::GzDecoder::new(&compress(&[tagged], &projection).unwrap()[..])
+            .read_to_string(&mut ndjson)
+            .unwrap();
+        let line: serde_json::Value = serde_json::from_str(ndjson.trim()).unwrap();
+        assert!(line.get("userId").is_none());
+        assert_eq!(line["projectId"], "a");
+    }
+}
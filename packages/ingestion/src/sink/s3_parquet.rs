@@ -0,0 +1,383 @@
+//! Direct-to-S3 Parquet sink for low-volume projects.
+//!
+//! Projects too small to justify a Kinesis shard can be routed here instead.
+//! Events accumulate in a buffer that lives as long as the warm container and
+//! are written to S3 as one Parquet object per project once the buffer is
+//! full or its oldest event passes the deadline. The deadline is checked on
+//! each send, since a frozen Lambda can't run timers; events still buffered
+//! when the container is reclaimed are lost, so only route projects that can
+//! tolerate that.
+
+use arrow_array::{ArrayRef, Int64Array, RecordBatch, StringArray};
+use arrow_schema::{DataType, Field, Schema};
+use async_trait::async_trait;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::Client as S3Client;
+use lambda_http::Error;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::s3_dead_letter::check_bucket;
+use super::EventSink;
+use crate::models::IngestEventPayload;
+use crate::projection::FieldProjection;
+use crate::shared::{env_list, env_or, env_var};
+
+/// Configuration for the S3 Parquet sink
+#[derive(Debug, Clone)]
+pub struct S3ParquetConfig {
+    /// Projects written to S3 instead of Kinesis
+    pub projects: Vec<String>,
+    /// Destination bucket; the sink is disabled when unset
+    pub bucket: Option<String>,
+    /// Key prefix for written objects
+    pub prefix: String,
+    /// Flush once this many events are buffered
+    pub max_events: usize,
+    /// Flush once the oldest buffered event is this old
+    pub max_age: Duration,
+    /// Event types that flush the buffer as soon as they arrive
+    pub priority_events: Vec<String>,
+}
+
+/// How urgently a buffered event should reach S3
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Waits for the size or age threshold
+    Normal,
+    /// Flushes the buffer immediately
+    High,
+}
+
+impl Default for S3ParquetConfig {
+    fn default() -> Self {
+        Self {
+            projects: Vec::new(),
+            bucket: None,
+            prefix: "events".to_string(),
+            max_events: 1000,
+            max_age: Duration::from_secs(60),
+            priority_events: Vec::new(),
+        }
+    }
+}
+
+impl S3ParquetConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            projects: env_list("S3_PARQUET_PROJECTS"),
+            bucket: env_var("S3_PARQUET_BUCKET"),
+            prefix: env_or("S3_PARQUET_PREFIX", defaults.prefix),
+            max_events: env_or("S3_PARQUET_MAX_EVENTS", defaults.max_events),
+            max_age: Duration::from_secs(env_or("S3_PARQUET_MAX_AGE_SECS", defaults.max_age.as_secs())),
+            priority_events: env_list("S3_PARQUET_PRIORITY_EVENTS"),
+        }
+    }
+
+    /// Whether a project's events go to this sink
+    pub fn routes(&self, project_id: &str) -> bool {
+        self.projects.iter().any(|p| p == project_id)
+    }
+
+    /// Priority of an event, from its type
+    pub fn priority(&self, event: &IngestEventPayload) -> Priority {
+        priority(&self.priority_events, event)
+    }
+}
+
+fn priority(priority_events: &[String], event: &IngestEventPayload) -> Priority {
+    if priority_events.contains(&event.event_type) {
+        Priority::High
+    } else {
+        Priority::Normal
+    }
+}
+
+/// Events waiting for the next flush
+#[derive(Debug, Default)]
+pub struct ParquetBuffer {
+    events: Vec<IngestEventPayload>,
+    oldest: Option<Instant>,
+}
+
+impl ParquetBuffer {
+    /// Adds events and returns the whole buffer if it is due for a flush.
+    /// A [`Priority::High`] push is always due.
+    pub fn push(
+        &mut self,
+        events: Vec<IngestEventPayload>,
+        priority: Priority,
+        max_events: usize,
+        max_age: Duration,
+    ) -> Option<Vec<IngestEventPayload>> {
+        if events.is_empty() {
+            return None;
+        }
+        self.oldest.get_or_insert_with(Instant::now);
+        self.events.extend(events);
+
+        let due = priority == Priority::High
+            || self.events.len() >= max_events
+            || self.oldest.is_some_and(|oldest| oldest.elapsed() >= max_age);
+        if !due {
+            return None;
+        }
+
+        self.oldest = None;
+        Some(std::mem::take(&mut self.events))
+    }
+}
+
+/// Encodes events as a Snappy-compressed Parquet file. Core fields get their
+/// own columns; `event` holds the full JSON record so nothing is lost. Both
+/// come from the projected record, so a field left out of a project's
+/// allowlist is left out of its column too.
+pub fn encode(events: &[IngestEventPayload], projection: &FieldProjection) -> Result<Vec<u8>, Error> {
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("project_id", DataType::Utf8, false),
+        Field::new("event_type", DataType::Utf8, false),
+        Field::new("timestamp", DataType::Int64, false),
+        Field::new("user_id", DataType::Utf8, true),
+        Field::new("anonymous_id", DataType::Utf8, true),
+        Field::new("event", DataType::Utf8, false),
+    ]));
+
+    let records = events
+        .iter()
+        .map(|event| projection.to_value(event))
+        .collect::<Result<Vec<_>, _>>()?;
+    let field = |name: &'static str| records.iter().map(move |record| record[name].as_str());
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from_iter_values(events.iter().map(|e| e.project_id.as_str()))),
+        Arc::new(StringArray::from_iter_values(events.iter().map(|e| e.event_type.as_str()))),
+        Arc::new(Int64Array::from_iter_values(events.iter().map(|e| e.timestamp))),
+        Arc::new(StringArray::from_iter(field("userId"))),
+        Arc::new(StringArray::from_iter(field("anonymousId"))),
+        Arc::new(StringArray::from_iter_values(records.iter().map(Value::to_string))),
+    ];
+    let batch = RecordBatch::try_new(schema.clone(), columns)?;
+
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+    let mut buffer = Vec::new();
+    let mut writer = ArrowWriter::try_new(&mut buffer, schema, Some(properties))?;
+    writer.write(&batch)?;
+    writer.close()?;
+
+    Ok(buffer)
+}
+
+/// Buffers events and flushes them to S3 as Parquet
+pub struct S3ParquetSink {
+    client: S3Client,
+    bucket: String,
+    prefix: String,
+    max_events: usize,
+    max_age: Duration,
+    priority_events: Vec<String>,
+    projection: FieldProjection,
+    buffer: Mutex<ParquetBuffer>,
+}
+
+impl S3ParquetSink {
+    pub fn new(client: S3Client, bucket: String, config: &S3ParquetConfig, projection: &FieldProjection) -> Self {
+        Self {
+            client,
+            bucket,
+            prefix: config.prefix.clone(),
+            max_events: config.max_events,
+            max_age: config.max_age,
+            priority_events: config.priority_events.clone(),
+            projection: projection.clone(),
+            buffer: Mutex::new(ParquetBuffer::default()),
+        }
+    }
+
+    /// Writes one Parquet object per project
+    async fn flush(&self, events: Vec<IngestEventPayload>) -> Result<(), Error> {
+        let mut by_project: HashMap<String, Vec<IngestEventPayload>> = HashMap::new();
+        for event in events {
+            by_project.entry(event.project_id.clone()).or_default().push(event);
+        }
+
+        let date = chrono::Utc::now().format("%Y-%m-%d");
+        for (project_id, events) in by_project {
+            let key = format!(
+                "{}/project_id={}/dt={}/{}.parquet",
+                self.prefix,
+                project_id,
+                date,
+                uuid::Uuid::new_v4()
+            );
+
+            self.client
+                .put_object()
+                .bucket(&self.bucket)
+                .key(&key)
+                .content_type("application/vnd.apache.parquet")
+                .body(ByteStream::from(encode(&events, &self.projection)?))
+                .send()
+                .await?;
+
+            tracing::info!("Wrote {} events to s3://{}/{}", events.len(), self.bucket, key);
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl EventSink for S3ParquetSink {
+    async fn send(&self, events: Vec<IngestEventPayload>) -> Result<(), Error> {
+        let priority = events
+            .iter()
+            .map(|e| priority(&self.priority_events, e))
+            .max()
+            .unwrap_or(Priority::Normal);
+        let due = self
+            .buffer
+            .lock()
+            .unwrap()
+            .push(events, priority, self.max_events, self.max_age);
+
+        match due {
+            Some(events) => self.flush(events).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn check(&self) -> Result<(), Error> {
+        check_bucket(&self.client, &self.bucket).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::Array;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    fn event(project_id: &str, timestamp: i64) -> IngestEventPayload {
+        IngestEventPayload {
+            project_id: project_id.to_string(),
+            event_type: "pageview".to_string(),
+            timestamp,
+            user_id: (timestamp % 2 == 0).then(|| "u1".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_buffer_flushes_on_size_or_deadline() {
+        let mut buffer = ParquetBuffer::default();
+        let max_age = Duration::from_secs(3600);
+
+        let normal = Priority::Normal;
+
+        assert!(buffer.push(vec![event("tiny", 1)], normal, 3, max_age).is_none());
+        assert!(buffer.push(vec![event("tiny", 2)], normal, 3, max_age).is_none());
+        let flushed = buffer.push(vec![event("tiny", 3)], normal, 3, max_age).unwrap();
+        assert_eq!(flushed.len(), 3);
+
+        // A zero deadline flushes immediately
+        let flushed = buffer.push(vec![event("tiny", 4)], normal, 100, Duration::ZERO).unwrap();
+        assert_eq!(flushed.len(), 1);
+    }
+
+    #[test]
+    fn test_high_priority_event_forces_early_flush() {
+        let config = S3ParquetConfig {
+            priority_events: vec!["purchase".to_string()],
+            ..Default::default()
+        };
+        let purchase = IngestEventPayload {
+            event_type: "purchase".to_string(),
+            ..event("tiny", 3)
+        };
+        assert_eq!(config.priority(&event("tiny", 1)), Priority::Normal);
+        assert_eq!(config.priority(&purchase), Priority::High);
+
+        let mut buffer = ParquetBuffer::default();
+        let max_age = Duration::from_secs(3600);
+        let push = |buffer: &mut ParquetBuffer, event| {
+            let priority = config.priority(&event);
+            buffer.push(vec![event], priority, 100, max_age)
+        };
+
+        assert!(push(&mut buffer, event("tiny", 1)).is_none());
+        assert!(push(&mut buffer, event("tiny", 2)).is_none());
+        let flushed = push(&mut buffer, purchase).unwrap();
+        assert_eq!(flushed.iter().map(|e| e.timestamp).collect::<Vec<_>>(), [1, 2, 3]);
+
+        // The buffer starts over at normal priority
+        assert!(push(&mut buffer, event("tiny", 4)).is_none());
+    }
+
+    #[test]
+    fn test_buffered_events_encode_to_valid_parquet() {
+        let events: Vec<_> = (1..=5).map(|ts| event("tiny", ts)).collect();
+
+        let bytes = encode(&events, &FieldProjection::default()).unwrap();
+        assert_eq!(&bytes[..4], b"PAR1");
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(bytes::Bytes::from(bytes))
+            .unwrap()
+            .build()
+            .unwrap();
+        let batches: Vec<RecordBatch> = reader.collect::<Result<_, _>>().unwrap();
+        assert_eq!(batches.iter().map(RecordBatch::num_rows).sum::<usize>(), 5);
+
+        let batch = &batches[0];
+        let timestamps = batch
+            .column_by_name("timestamp")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .unwrap();
+        assert_eq!(timestamps.values(), &[1, 2, 3, 4, 5]);
+
+        let user_ids = batch.column_by_name("user_id").unwrap();
+        assert_eq!(user_ids.null_count(), 3);
+
+        let records = batch
+            .column_by_name("event")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        let first: IngestEventPayload = serde_json::from_str(records.value(0)).unwrap();
+        assert_eq!(first.project_id, "tiny");
+    }
+
+    #[test]
+    fn test_columns_and_records_are_projected() {
+        let projection = FieldProjection {
+            projects: HashMap::from([("tiny".to_string(), vec![])]),
+        };
+        let bytes = encode(&[event("tiny", 2)], &projection).unwrap();
+
+        let mut reader = ParquetRecordBatchReaderBuilder::try_new(bytes::Bytes::from(bytes))
+            .unwrap()
+            .build()
+            .unwrap();
+        let batch = reader.next().unwrap().unwrap();
+        assert_eq!(batch.column_by_name("user_id").unwrap().null_count(), 1);
+
+        let records = batch
+            .column_by_name("event")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        let record: Value = serde_json::from_str(records.value(0)).unwrap();
+        assert_eq!(record, serde_json::json!({"projectId": "tiny", "eventType": "pageview", "timestamp": 2}));
+    }
+}
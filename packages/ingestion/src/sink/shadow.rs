@@ -0,0 +1,227 @@
+//! Shadow writes, for migrating to a new stream without a hard cutover.
+//!
+//! With `SHADOW_STREAM_NAME` (or `SHADOW_QUEUE_URL`), every batch written
+//! to the event sink is also written to the shadow stream (or queue), at
+//! the same time. The shadow stream gets the same records, encoded and
+//! aggregated as configured, but partitioned by its own
+//! `SHADOW_PARTITION_KEY_STRATEGY` (the primary's by default), so a new
+//! partitioning scheme can be tried on real traffic. It has to be in the
+//! function's region.
+//!
+//! The shadow write never affects the request: its failures are logged and
+//! counted, not retried beyond the usual retries, dead-lettered or sent to
+//! the fallback bucket. Each write emits `ShadowWrites` with an `Outcome`
+//! dimension (`match`, `primary_only`, `shadow_only` or `both_failed`) and
+//! both sides' latency, so the two can be compared before cutting over.
+//! Projects pinned to a residency zone are never shadowed.
+
+use async_trait::async_trait;
+use lambda_http::Error;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::kinesis::record_data;
+use super::EventSink;
+use crate::aggregation;
+use crate::metrics::{MetricSet, MetricsConfig, Unit};
+use crate::models::IngestEventPayload;
+use crate::partitioning::{self, PartitionConfig};
+use crate::put_records::{self, Record, Serialized};
+use crate::retry::RetryBudget;
+use crate::shared::{env_opt, env_var, AppState};
+
+/// Configuration for shadow writes
+#[derive(Debug, Clone, Default)]
+pub struct ShadowConfig {
+    /// Stream every batch is also written to
+    pub stream_name: Option<String>,
+    /// Queue every batch is also sent to, when there's no shadow stream
+    pub queue_url: Option<String>,
+    /// Partitioning of the shadow stream
+    pub partitioning: PartitionConfig,
+}
+
+impl ShadowConfig {
+    pub fn from_env() -> Self {
+        let mut partitioning = PartitionConfig::from_env();
+        if let Some(strategy) = env_opt::<String>("SHADOW_PARTITION_KEY_STRATEGY") {
+            match strategy.parse() {
+                Ok(strategy) => {
+                    partitioning.strategy = strategy;
+                    partitioning.overrides.clear();
+                }
+                Err(e) => tracing::warn!("Ignoring SHADOW_PARTITION_KEY_STRATEGY: {}", e),
+            }
+        }
+        Self {
+            stream_name: env_var("SHADOW_STREAM_NAME"),
+            queue_url: env_var("SHADOW_QUEUE_URL"),
+            partitioning,
+        }
+    }
+}
+
+/// Writes events to the shadow stream of a request's state
+pub struct ShadowStreamSink {
+    state: Arc<AppState>,
+}
+
+impl ShadowStreamSink {
+    pub fn new(state: Arc<AppState>) -> Self {
+        Self { state }
+    }
+}
+
+#[async_trait]
+impl EventSink for ShadowStreamSink {
+    async fn send(&self, events: Vec<IngestEventPayload>) -> Result<(), Error> {
+        let config = &self.state.config;
+        let Some(ref stream_name) = config.shadow.stream_name else {
+            return Ok(());
+        };
+        let serialized = events
+            .iter()
+            .map(|event| {
+                Ok(Serialized {
+                    event,
+                    key: partitioning::partition_key(event, &config.shadow.partitioning),
+                    data: record_data(event, config)?,
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        let records = if config.aggregation.enabled {
+            aggregation::aggregate(serialized, &config.aggregation)?
+        } else {
+            serialized.into_iter().map(Record::new).collect::<Result<_, _>>()?
+        };
+
+        let client = self.state.streams.client(stream_name);
+        let mut budget = RetryBudget::new(config.retry.budget);
+        let failures = put_records::put_all(client, stream_name, &records, &config.retry, &mut budget).await;
+        match failures.first() {
+            None => Ok(()),
+            Some((_, reason)) => {
+                Err(format!("Failed to write {} of {} records: {}", failures.len(), records.len(), reason).into())
+            }
+        }
+    }
+}
+
+/// The shadow destination for a request's state, if any
+pub fn sink(state: &Arc<AppState>) -> Option<Arc<dyn EventSink>> {
+    match state.config.shadow.stream_name {
+        Some(_) => Some(Arc::new(ShadowStreamSink::new(state.clone()))),
+        None => state.shadow_sink.clone(),
+    }
+}
+
+/// Writes events to the shadow, returning whether that worked and how
+/// long it took
+pub async fn write(sink: Arc<dyn EventSink>, events: Vec<IngestEventPayload>) -> (bool, Duration) {
+    let count = events.len();
+    let started = Instant::now();
+    let result = sink.send(events).await;
+    if let Err(ref e) = result {
+        tracing::warn!("Shadow write of {} events failed: {}", count, e);
+    }
+    (result.is_ok(), started.elapsed())
+}
+
+/// How the two writes of a batch compared
+pub fn outcome(primary: bool, shadow: bool) -> &'static str {
+    match (primary, shadow) {
+        (true, true) => "match",
+        (true, false) => "primary_only",
+        (false, true) => "shadow_only",
+        (false, false) => "both_failed",
+    }
+}
+
+/// Emits the comparison of a batch's primary and shadow writes
+pub fn compare(
+    config: &MetricsConfig,
+    events: usize,
+    primary: (bool, Duration),
+    shadow: (bool, Duration),
+) {
+    MetricSet::new(config)
+        .dimension("Outcome", outcome(primary.0, shadow.0))
+        .count("ShadowWrites", events)
+        .metric("PrimaryWriteLatency", primary.1.as_secs_f64() * 1000.0, Unit::Milliseconds)
+        .metric("ShadowWriteLatency", shadow.1.as_secs_f64() * 1000.0, Unit::Milliseconds)
+        .emit();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::{test_state, write_events, Config};
+    use crate::sink::RecordingSink;
+
+    /// Fails every write
+    struct DownSink;
+
+    #[async_trait]
+    impl EventSink for DownSink {
+        async fn send(&self, _: Vec<IngestEventPayload>) -> Result<(), Error> {
+            Err("stream unavailable".into())
+        }
+    }
+
+    fn event(project_id: &str) -> IngestEventPayload {
+        IngestEventPayload {
+            project_id: project_id.to_string(),
+            event_type: "pageview".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_outcomes() {
+        assert_eq!(outcome(true, true), "match");
+        assert_eq!(outcome(true, false), "primary_only");
+        assert_eq!(outcome(false, true), "shadow_only");
+        assert_eq!(outcome(false, false), "both_failed");
+    }
+
+    #[tokio::test]
+    async fn test_shadow_failures_dont_fail_the_write() {
+        let mut state = test_state(Config::default());
+        let primary = Arc::new(RecordingSink::default());
+        state.event_sink = Some(primary.clone());
+        state.shadow_sink = Some(Arc::new(DownSink));
+        let state = Arc::new(state);
+
+        write_events(vec![event("p")], &state).await.unwrap();
+        assert_eq!(primary.events.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_both_sides_get_the_batch_but_pinned_projects_stay_home() {
+        let mut config = Config::default();
+        config.retry.max_attempts = 1;
+        config.residency.project_zones.insert("eu-tenant".to_string(), "eu".to_string());
+        config.residency.streams =
+            serde_json::from_str(r#"{"eu": {"region": "eu-central-1", "streamName": "events-eu"}}"#).unwrap();
+        let mut state = test_state(config);
+        let (primary, shadow) = (Arc::new(RecordingSink::default()), Arc::new(RecordingSink::default()));
+        state.event_sink = Some(primary.clone());
+        state.shadow_sink = Some(shadow.clone());
+        state.regional_kinesis.insert("eu".to_string(), state.streams.client("events-eu").clone());
+        let state = Arc::new(state);
+
+        // The pinned event goes to its zone's stream, which the test can't reach
+        let error = write_events(vec![event("p"), event("eu-tenant"), event("q")], &state).await.unwrap_err();
+        let failed = &error.downcast_ref::<crate::sink::PartialWrite>().unwrap().failed;
+        assert_eq!(failed.iter().map(|(position, _)| *position).collect::<Vec<_>>(), [1]);
+        assert_eq!(primary.events.lock().unwrap().len(), 2);
+        let shadowed: Vec<_> = shadow.events.lock().unwrap().iter().map(|e| e.project_id.clone()).collect();
+        assert_eq!(shadowed, ["p", "q"]);
+
+        // Failing the primary write still fails the request, shadowed or not
+        let mut down = (*state).clone();
+        down.event_sink = Some(Arc::new(DownSink));
+        assert!(write_events(vec![event("p")], &Arc::new(down)).await.is_err());
+        assert_eq!(shadow.events.lock().unwrap().len(), 3);
+    }
+}
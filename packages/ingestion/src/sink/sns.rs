@@ -0,0 +1,127 @@
+//! SNS sink, for fanning events out to subscribers instead of a stream.
+//!
+//! With `EVENT_SINK=sns` every accepted event becomes one message on the
+//! standard topic `EVENT_SINK_TOPIC_ARN`, its body the event's projected
+//! JSON, sent with `PublishBatch`. As with the `sqs` sink, stream routing,
+//! partitioning, record encoding and aggregation don't apply; residency
+//! zones still need their streams.
+
+use async_trait::async_trait;
+use lambda_http::Error;
+
+use super::sqs_dead_letter::batches;
+use super::{all_written, EventSink};
+use crate::aws_json::AwsJsonClient;
+use crate::models::IngestEventPayload;
+use crate::projection::FieldProjection;
+
+/// Version of the SNS query API
+const API_VERSION: &str = "2010-03-31";
+
+/// Text of the first `<name>` element in `xml`
+fn element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let start = xml.find(&format!("<{}>", name))? + name.len() + 2;
+    let end = start + xml[start..].find(&format!("</{}>", name))?;
+    Some(&xml[start..end])
+}
+
+/// Failed entries of a `PublishBatch` response, by entry id
+fn failures(response: &str) -> Vec<(usize, String)> {
+    let Some(failed) = element(response, "Failed") else {
+        return Vec::new();
+    };
+    failed
+        .split("<member>")
+        .filter_map(|member| {
+            let id = element(member, "Id")?.parse().ok()?;
+            let code = element(member, "Code").unwrap_or_default();
+            let message = element(member, "Message").unwrap_or_default();
+            Some((id, format!("{}: {}", code, message)))
+        })
+        .collect()
+}
+
+/// Publishes events to an SNS topic
+pub struct SnsSink {
+    client: AwsJsonClient,
+    topic_arn: String,
+    projection: FieldProjection,
+}
+
+impl SnsSink {
+    pub fn new(client: AwsJsonClient, topic_arn: String, projection: &FieldProjection) -> Self {
+        Self {
+            client,
+            topic_arn,
+            projection: projection.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl EventSink for SnsSink {
+    async fn send(&self, events: Vec<IngestEventPayload>) -> Result<(), Error> {
+        let mut failed = Vec::new();
+        let mut offset = 0;
+        for batch in batches(&events, &self.projection)? {
+            let count = batch.len();
+            let mut params = vec![("TopicArn".to_string(), self.topic_arn.clone())];
+            for (index, body) in batch.into_iter().enumerate() {
+                // Entry ids are the event's position in the batch
+                let member = format!("PublishBatchRequestEntries.member.{}", index + 1);
+                params.push((format!("{}.Id", member), (offset + index).to_string()));
+                params.push((format!("{}.Message", member), body));
+            }
+            match self.client.call_query("PublishBatch", API_VERSION, &params).await {
+                Ok(response) => failed.extend(failures(&response)),
+                // The other batches are still sent; these are retried
+                Err(e) => failed.extend((offset..offset + count).map(|position| (position, e.to_string()))),
+            }
+            offset += count;
+        }
+
+        if !failed.is_empty() {
+            tracing::error!(
+                "Failed to publish {} of {} events to {}: {}",
+                failed.len(),
+                events.len(),
+                self.topic_arn,
+                failed[0].1
+            );
+        }
+        all_written(failed)
+    }
+
+    async fn check(&self) -> Result<(), Error> {
+        let params = [("TopicArn".to_string(), self.topic_arn.clone())];
+        self.client.call_query("GetTopicAttributes", API_VERSION, &params).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_failed_entries_are_found_by_id() {
+        let response = r#"<PublishBatchResponse xmlns="http://sns.amazonaws.com/doc/2010-03-31/">
+  <PublishBatchResult>
+    <Successful>
+      <member><Id>10</Id><MessageId>a1</MessageId></member>
+    </Successful>
+    <Failed>
+      <member><Id>11</Id><Code>InternalError</Code><Message>Try again</Message><SenderFault>false</SenderFault></member>
+      <member><Id>13</Id><Code>Throttled</Code><Message>Rate exceeded</Message><SenderFault>false</SenderFault></member>
+    </Failed>
+  </PublishBatchResult>
+</PublishBatchResponse>"#;
+        assert_eq!(
+            failures(response),
+            [(11, "InternalError: Try again".to_string()), (13, "Throttled: Rate exceeded".to_string())]
+        );
+
+        let response = "<PublishBatchResult><Successful><member><Id>0</Id></member></Successful><Failed/></PublishBatchResult>";
+        assert!(failures(response).is_empty());
+    }
+}
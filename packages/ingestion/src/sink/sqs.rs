@@ -0,0 +1,84 @@
+//! SQS sink, for accounts without Kinesis.
+//!
+//! With `EVENT_SINK=sqs` every accepted event becomes one message on
+//! `EVENT_SINK_QUEUE_URL`, its body the event's projected JSON, sent with
+//! `SendMessageBatch`. Stream routing, partitioning, record encoding and
+//! aggregation don't apply; residency zones still need their streams.
+
+use async_trait::async_trait;
+use aws_sdk_sqs::types::{QueueAttributeName, SendMessageBatchRequestEntry};
+use aws_sdk_sqs::Client as SqsClient;
+use lambda_http::Error;
+
+use super::sqs_dead_letter::batches;
+use super::EventSink;
+use crate::models::IngestEventPayload;
+use crate::projection::FieldProjection;
+
+/// Sends events to an SQS queue
+pub struct SqsSink {
+    client: SqsClient,
+    queue_url: String,
+    projection: FieldProjection,
+}
+
+impl SqsSink {
+    pub fn new(client: SqsClient, queue_url: String, projection: &FieldProjection) -> Self {
+        Self {
+            client,
+            queue_url,
+            projection: projection.clone(),
+        }
+    }
+
+    pub fn queue_url(&self) -> &str {
+        &self.queue_url
+    }
+}
+
+#[async_trait]
+impl EventSink for SqsSink {
+    async fn send(&self, events: Vec<IngestEventPayload>) -> Result<(), Error> {
+        for batch in batches(&events, &self.projection)? {
+            let entries = batch
+                .into_iter()
+                .enumerate()
+                .map(|(index, body)| {
+                    SendMessageBatchRequestEntry::builder()
+                        .id(index.to_string())
+                        .message_body(body)
+                        .build()
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let output = self
+                .client
+                .send_message_batch()
+                .queue_url(&self.queue_url)
+                .set_entries(Some(entries))
+                .send()
+                .await?;
+
+            if let Some(failed) = output.failed().first() {
+                return Err(format!(
+                    "Failed to send {} events to SQS: {}: {}",
+                    output.failed().len(),
+                    failed.code(),
+                    failed.message().unwrap_or_default()
+                )
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    async fn check(&self) -> Result<(), Error> {
+        self.client
+            .get_queue_attributes()
+            .queue_url(&self.queue_url)
+            .attribute_names(QueueAttributeName::QueueArn)
+            .send()
+            .await?;
+        Ok(())
+    }
+}
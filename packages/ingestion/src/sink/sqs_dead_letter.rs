@@ -0,0 +1,167 @@
+//! Dead-letter sink backed by SQS.
+//!
+//! An alternative to [`S3DeadLetterSink`](super::s3_dead_letter::S3DeadLetterSink)
+//! for deployments that replay failures with a queue consumer: each failed
+//! record becomes one message whose body is the event's projected JSON, sent with
+//! `SendMessageBatch`. Selected by setting `DLQ_QUEUE_URL`.
+//!
+//! The queue is in the home region, so events of projects pinned to a
+//! residency zone are refused rather than sent there; the stream sink
+//! returns those failures to the client instead.
+
+use async_trait::async_trait;
+use aws_sdk_sqs::Client as SqsClient;
+use lambda_http::Error;
+
+use super::sqs::SqsSink;
+use super::EventSink;
+use crate::models::IngestEventPayload;
+use crate::projection::FieldProjection;
+use crate::residency::ResidencyConfig;
+
+/// Most messages one `SendMessageBatch` request may carry
+const MAX_MESSAGES_PER_REQUEST: usize = 10;
+/// Most payload bytes one `SendMessageBatch` request may carry
+const MAX_BYTES_PER_REQUEST: usize = 256 * 1024;
+
+/// Serializes events into message bodies grouped into request-sized batches
+pub fn batches(events: &[IngestEventPayload], projection: &FieldProjection) -> Result<Vec<Vec<String>>, Error> {
+    let mut batches: Vec<Vec<String>> = Vec::new();
+    let mut bytes = 0;
+    for event in events {
+        let body = projection.to_string(event)?;
+        let full = batches.last().is_none_or(|batch| {
+            batch.len() == MAX_MESSAGES_PER_REQUEST || bytes + body.len() > MAX_BYTES_PER_REQUEST
+        });
+        if full {
+            batches.push(Vec::new());
+            bytes = 0;
+        }
+        bytes += body.len();
+        batches.last_mut().expect("batch just pushed").push(body);
+    }
+    Ok(batches)
+}
+
+/// Sends failed records to an SQS queue
+pub struct SqsDeadLetterSink {
+    queue: SqsSink,
+    residency: ResidencyConfig,
+}
+
+impl SqsDeadLetterSink {
+    pub fn new(
+        client: SqsClient,
+        queue_url: String,
+        projection: &FieldProjection,
+        residency: &ResidencyConfig,
+    ) -> Self {
+        Self {
+            queue: SqsSink::new(client, queue_url, projection),
+            residency: residency.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl EventSink for SqsDeadLetterSink {
+    async fn send(&self, events: Vec<IngestEventPayload>) -> Result<(), Error> {
+        // Pinned events never leave their zone, not even to dead-letter
+        if let Some(pinned) = events
+            .iter()
+            .find(|event| self.residency.project_zones.contains_key(&event.project_id))
+        {
+            return Err(format!(
+                "Refusing to dead-letter events of project {} outside its residency zone",
+                pinned.project_id
+            )
+            .into());
+        }
+
+        let count = events.len();
+        self.queue
+            .send(events)
+            .await
+            .map_err(|e| format!("Failed to dead-letter events: {}", e))?;
+
+        if count > 0 {
+            tracing::warn!("Dead-lettered {} events to {}", count, self.queue.queue_url());
+        }
+        Ok(())
+    }
+
+    async fn check(&self) -> Result<(), Error> {
+        self.queue.check().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(name: &str) -> IngestEventPayload {
+        IngestEventPayload {
+            project_id: "proj".to_string(),
+            event_type: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_batches_respect_message_count_and_size() {
+        let batches = |events: &[IngestEventPayload]| batches(events, &FieldProjection::default());
+        let events: Vec<_> = (0..23).map(|_| event("signup")).collect();
+        let sizes: Vec<_> = batches(&events).unwrap().iter().map(Vec::len).collect();
+        assert_eq!(sizes, [10, 10, 3]);
+
+        // Two 150 KiB events can't share a request
+        let large = event(&"x".repeat(150 * 1024));
+        let sizes: Vec<_> = batches(&[large.clone(), large]).unwrap().iter().map(Vec::len).collect();
+        assert_eq!(sizes, [1, 1]);
+
+        assert!(batches(&[]).unwrap().is_empty());
+
+        let body: IngestEventPayload = serde_json::from_str(&batches(&events).unwrap()[0][0]).unwrap();
+        assert_eq!(body.event_type, "signup");
+    }
+
+    #[tokio::test]
+    async fn test_pinned_events_are_refused() {
+        let client = SqsClient::from_conf(
+            aws_sdk_sqs::Config::builder()
+                .behavior_version(aws_sdk_sqs::config::BehaviorVersion::latest())
+                .region(aws_sdk_sqs::config::Region::new("us-east-1"))
+                .build(),
+        );
+        let residency = ResidencyConfig {
+            project_zones: std::collections::HashMap::from([("eu-tenant".to_string(), "eu".to_string())]),
+            ..Default::default()
+        };
+        let sink = SqsDeadLetterSink::new(client, "dlq".to_string(), &FieldProjection::default(), &residency);
+
+        let pinned = IngestEventPayload {
+            project_id: "eu-tenant".to_string(),
+            ..event("signup")
+        };
+        let error = sink.send(vec![event("signup"), pinned]).await.unwrap_err();
+        assert!(error.to_string().contains("residency zone"), "{}", error);
+        // Nothing to send is not an error
+        sink.send(Vec::new()).await.unwrap();
+    }
+
+    #[test]
+    fn test_bodies_are_projected() {
+        let projection = FieldProjection {
+            projects: std::collections::HashMap::from([("proj".to_string(), vec!["userId".to_string()])]),
+        };
+        let signup = IngestEventPayload {
+            user_id: Some("u1".to_string()),
+            anonymous_id: Some("a1".to_string()),
+            ..event("signup")
+        };
+
+        let body: serde_json::Value = serde_json::from_str(&batches(&[signup], &projection).unwrap()[0][0]).unwrap();
+        assert_eq!(body["userId"], "u1");
+        assert!(body.get("anonymousId").is_none());
+    }
+}
@@ -0,0 +1,186 @@
+//! Asynchronous processing status.
+//!
+//! When enabled, every accepted single event gets a generated `eventId`, its
+//! state is recorded in a [`StatusStore`], and the 202 carries a `Location`
+//! header pointing at `GET /status/{eventId}`. Ingestion records `queued`
+//! once the event is written (or `dropped` when an enrichment discarded
+//! it). Nothing downstream updates the table, so `queued` is the last
+//! state an event reaches. Recording is best effort: the event is already
+//! written, so a failed write to the table is logged and the response goes
+//! out without a `Location`.
+
+use async_trait::async_trait;
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use lambda_http::{Body, Error, Request, Response};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::shared::{create_error_response, create_response, env_flag, env_var, AppState};
+
+/// Configuration for status tracking
+#[derive(Debug, Clone, Default)]
+pub struct StatusConfig {
+    pub enabled: bool,
+    /// DynamoDB table backing the store; in-memory when unset
+    pub table_name: Option<String>,
+}
+
+impl StatusConfig {
+    pub fn from_env() -> Self {
+        Self {
+            enabled: env_flag("STATUS_TRACKING_ENABLED"),
+            table_name: env_var("EVENT_STATUS_TABLE"),
+        }
+    }
+}
+
+/// Per-event processing state store
+#[async_trait]
+pub trait StatusStore: Send + Sync {
+    async fn set(&self, event_id: &str, status: &str) -> Result<(), Error>;
+    async fn get(&self, event_id: &str) -> Result<Option<String>, Error>;
+}
+
+/// Process-local store, used in tests and when no table is configured
+#[derive(Debug, Default)]
+pub struct InMemoryStatusStore {
+    entries: Mutex<HashMap<String, String>>,
+}
+
+#[async_trait]
+impl StatusStore for InMemoryStatusStore {
+    async fn set(&self, event_id: &str, status: &str) -> Result<(), Error> {
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(event_id.to_string(), status.to_string());
+        Ok(())
+    }
+
+    async fn get(&self, event_id: &str) -> Result<Option<String>, Error> {
+        Ok(self.entries.lock().unwrap().get(event_id).cloned())
+    }
+}
+
+/// DynamoDB-backed store
+/// Table schema: partition key `pk` (S), attribute `status` (S)
+pub struct DynamoStatusStore {
+    client: DynamoClient,
+    table_name: String,
+}
+
+impl DynamoStatusStore {
+    pub fn new(client: DynamoClient, table_name: String) -> Self {
+        Self { client, table_name }
+    }
+}
+
+#[async_trait]
+impl StatusStore for DynamoStatusStore {
+    async fn set(&self, event_id: &str, status: &str) -> Result<(), Error> {
+        self.client
+            .put_item()
+            .table_name(&self.table_name)
+            .item("pk", AttributeValue::S(event_id.to_string()))
+            .item("status", AttributeValue::S(status.to_string()))
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    async fn get(&self, event_id: &str) -> Result<Option<String>, Error> {
+        let output = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("pk", AttributeValue::S(event_id.to_string()))
+            .send()
+            .await?;
+
+        Ok(output
+            .item()
+            .and_then(|item| item.get("status"))
+            .and_then(|status| status.as_s().ok())
+            .cloned())
+    }
+}
+
+/// Status resource for an event, next to the endpoint that accepted it
+/// (`/prod/view` -> `/prod/status/{id}`)
+pub fn location(request: &Request, event_id: &str) -> String {
+    let path = request.uri().path();
+    let base = path.rsplit_once('/').map_or("", |(base, _)| base);
+    format!("{}/status/{}", base, event_id)
+}
+
+/// Points the response at the event's status resource
+pub fn with_location(mut response: Response<Body>, request: &Request, event_id: &str) -> Response<Body> {
+    if let Ok(value) = location(request, event_id).parse() {
+        response.headers_mut().insert("Location", value);
+    }
+    response
+}
+
+/// Handler for GET /status/{eventId}
+pub async fn handle_status(event_id: &str, state: Arc<AppState>) -> Result<Response<Body>, Error> {
+    if !state.config.status.enabled {
+        return Ok(create_error_response(404, "Not found"));
+    }
+
+    match state.status_store.get(event_id).await? {
+        Some(status) => Ok(create_response(
+            200,
+            serde_json::json!({ "eventId": event_id, "status": status }),
+        )),
+        None => Ok(create_error_response(404, "Unknown event id")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::{create_text_response, test_state, Config};
+
+    fn request(method: &str, path: &str) -> Request {
+        lambda_http::http::Request::builder()
+            .method(method)
+            .uri(path)
+            .body(Body::Empty)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_location_header_points_at_status_resource() {
+        let accepted = create_text_response(202, "ACCEPTED");
+        let response = with_location(accepted, &request("POST", "/prod/view"), "abc-123");
+
+        assert_eq!(response.status(), 202);
+        assert_eq!(response.headers()["location"], "/prod/status/abc-123");
+
+        let response = with_location(create_text_response(202, "ACCEPTED"), &request("POST", "/event"), "x");
+        assert_eq!(response.headers()["location"], "/status/x");
+    }
+
+    #[tokio::test]
+    async fn test_status_lookup_of_known_id() {
+        let state = Arc::new(test_state(Config {
+            status: StatusConfig {
+                enabled: true,
+                table_name: None,
+            },
+            ..Default::default()
+        }));
+        state.status_store.set("abc-123", "queued").await.unwrap();
+
+        let response = handle_status("abc-123", state.clone()).await.unwrap();
+        assert_eq!(response.status(), 200);
+        match response.body() {
+            Body::Text(body) => assert_eq!(body, r#"{"eventId":"abc-123","status":"queued"}"#),
+            other => panic!("unexpected body: {:?}", other),
+        }
+
+        let response = handle_status("nope", state).await.unwrap();
+        assert_eq!(response.status(), 404);
+    }
+}
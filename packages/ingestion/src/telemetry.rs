@@ -0,0 +1,225 @@
+//! Logging and OpenTelemetry tracing.
+//!
+//! Logs are always JSON on stdout. With `OTEL_TRACING_ENABLED`, spans also
+//! go to an OpenTelemetry layer and are exported over OTLP/HTTP to the
+//! endpoint in the standard `OTEL_EXPORTER_OTLP_*` variables (by default
+//! `localhost:4318`, where the ADOT collector layer listens and forwards to
+//! X-Ray). Each request's span continues the trace in its
+//! `X-Amzn-Trace-Id` header, trace ids carry X-Ray's timestamp prefix, and
+//! the spans are flushed before the invocation returns, as the sandbox may
+//! be frozen right after. Stages show up as child spans: `parse`,
+//! `validate.*`, `enrich.*` and `sink.write`.
+
+use opentelemetry::propagation::{Extractor, Injector, TextMapPropagator};
+use opentelemetry::trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState, TracerProvider as _};
+use opentelemetry::Context;
+use opentelemetry_sdk::trace::{IdGenerator, SdkTracerProvider};
+use opentelemetry_sdk::Resource;
+use std::sync::LazyLock;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+
+use crate::shared::{env_flag, env_or};
+
+/// The X-Ray trace header
+pub const TRACE_HEADER: &str = "x-amzn-trace-id";
+
+static FIELDS: LazyLock<[String; 1]> = LazyLock::new(|| [TRACE_HEADER.to_string()]);
+
+/// Configuration for tracing
+#[derive(Debug, Clone)]
+pub struct TelemetryConfig {
+    pub enabled: bool,
+    pub service_name: String,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            service_name: "ingestion".to_string(),
+        }
+    }
+}
+
+impl TelemetryConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            enabled: env_flag("OTEL_TRACING_ENABLED"),
+            service_name: env_or("OTEL_SERVICE_NAME", defaults.service_name),
+        }
+    }
+}
+
+/// Installs the global subscriber, returning the tracer provider to flush
+/// when tracing is enabled
+pub fn init(config: &TelemetryConfig) -> Option<SdkTracerProvider> {
+    let filter = tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"));
+    let registry = tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer().json());
+
+    let exporter = match opentelemetry_otlp::SpanExporter::builder().with_http().build() {
+        Ok(exporter) if config.enabled => exporter,
+        Ok(_) => {
+            registry.init();
+            return None;
+        }
+        Err(e) => {
+            registry.init();
+            tracing::error!("Tracing disabled, failed to build the OTLP exporter: {}", e);
+            return None;
+        }
+    };
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_id_generator(XrayIdGenerator)
+        .with_resource(Resource::builder().with_service_name(config.service_name.clone()).build())
+        .build();
+    opentelemetry::global::set_text_map_propagator(XrayPropagator);
+    registry
+        .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("ingestion")))
+        .init();
+    Some(provider)
+}
+
+/// Exports the invocation's spans before the sandbox can freeze
+pub async fn flush(provider: Option<SdkTracerProvider>) {
+    let Some(provider) = provider else {
+        return;
+    };
+    let flushed = tokio::task::spawn_blocking(move || provider.force_flush()).await;
+    if let Ok(Err(e)) = flushed {
+        tracing::warn!("Failed to flush spans: {}", e);
+    }
+}
+
+/// Makes `span` continue the trace in the request's `X-Amzn-Trace-Id`
+pub fn continue_trace(span: &tracing::Span, headers: &http::HeaderMap) {
+    let cx = opentelemetry::global::get_text_map_propagator(|propagator| propagator.extract(&Headers(headers)));
+    if cx.span().span_context().is_valid() {
+        let _ = span.set_parent(cx);
+    }
+}
+
+struct Headers<'a>(&'a http::HeaderMap);
+
+impl Extractor for Headers<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
+
+/// Trace ids whose first 32 bits are the epoch second, as X-Ray requires
+#[derive(Debug)]
+pub struct XrayIdGenerator;
+
+impl IdGenerator for XrayIdGenerator {
+    fn new_trace_id(&self) -> TraceId {
+        let seconds = chrono::Utc::now().timestamp() as u128 & 0xffff_ffff;
+        TraceId::from(seconds << 96 | (fastrand::u128(..) >> 32))
+    }
+
+    fn new_span_id(&self) -> SpanId {
+        SpanId::from(fastrand::u64(1..))
+    }
+}
+
+/// Reads and writes `X-Amzn-Trace-Id`:
+/// `Root=1-{8 hex time}-{24 hex};Parent={16 hex};Sampled={0|1}`
+#[derive(Debug)]
+pub struct XrayPropagator;
+
+/// The span context in an `X-Amzn-Trace-Id` value
+pub fn parse_trace_header(value: &str) -> Option<SpanContext> {
+    let (mut trace_id, mut parent, mut sampled) = (None, None, false);
+    for part in value.split(';') {
+        match part.trim().split_once('=') {
+            Some(("Root", root)) => {
+                let mut pieces = root.splitn(3, '-');
+                if pieces.next() != Some("1") {
+                    return None;
+                }
+                let (time, random) = (pieces.next()?, pieces.next()?);
+                if time.len() != 8 || random.len() != 24 {
+                    return None;
+                }
+                trace_id = TraceId::from_hex(&format!("{}{}", time, random)).ok();
+            }
+            Some(("Parent", id)) => parent = SpanId::from_hex(id).ok(),
+            Some(("Sampled", flag)) => sampled = flag == "1",
+            _ => {}
+        }
+    }
+    let flags = if sampled { TraceFlags::SAMPLED } else { TraceFlags::default() };
+    Some(SpanContext::new(trace_id?, parent?, flags, true, TraceState::default()))
+}
+
+impl TextMapPropagator for XrayPropagator {
+    fn inject_context(&self, cx: &Context, injector: &mut dyn Injector) {
+        let span = cx.span();
+        let context = span.span_context();
+        if !context.is_valid() {
+            return;
+        }
+        let trace_id = context.trace_id().to_string();
+        injector.set(
+            TRACE_HEADER,
+            format!(
+                "Root=1-{}-{};Parent={};Sampled={}",
+                &trace_id[..8],
+                &trace_id[8..],
+                context.span_id(),
+                u8::from(context.is_sampled())
+            ),
+        );
+    }
+
+    fn extract_with_context(&self, cx: &Context, extractor: &dyn Extractor) -> Context {
+        match extractor.get(TRACE_HEADER).and_then(parse_trace_header) {
+            Some(context) => cx.with_remote_span_context(context),
+            None => cx.clone(),
+        }
+    }
+
+    fn fields(&self) -> opentelemetry::propagation::text_map_propagator::FieldIter<'_> {
+        opentelemetry::propagation::text_map_propagator::FieldIter::new(FIELDS.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    const HEADER: &str = "Root=1-5759e988-bd862e3fe1be46a994272793;Parent=53995c3f42cd8ad8;Sampled=1";
+
+    #[test]
+    fn test_round_trips_the_xray_header() {
+        let context = parse_trace_header(HEADER).unwrap();
+        assert_eq!(context.trace_id().to_string(), "5759e988bd862e3fe1be46a994272793");
+        assert_eq!(context.span_id().to_string(), "53995c3f42cd8ad8");
+        assert!(context.is_sampled() && context.is_remote());
+
+        let mut headers = HashMap::new();
+        XrayPropagator.inject_context(&Context::new().with_remote_span_context(context), &mut headers);
+        assert_eq!(headers[TRACE_HEADER], HEADER);
+
+        assert!(parse_trace_header("Root=1-5759e988-bd862e3fe1be46a994272793").is_none());
+        assert!(parse_trace_header("Root=2-5759e988-bd862e3fe1be46a994272793;Parent=53995c3f42cd8ad8").is_none());
+    }
+
+    #[test]
+    fn test_trace_ids_start_with_the_time() {
+        let trace_id = XrayIdGenerator.new_trace_id().to_string();
+        let seconds = u32::from_str_radix(&trace_id[..8], 16).unwrap() as i64;
+        assert!((chrono::Utc::now().timestamp() - seconds).abs() < 5);
+    }
+}
@@ -0,0 +1,267 @@
+//! Reserved trait normalization for identify events.
+//!
+//! SDKs and hand-written integrations spell the same trait many ways
+//! (`email`, `Email`, `e-mail`, `emailAddress`), and each spelling would
+//! become its own column in the user-profile store. With
+//! `TRAIT_NORMALIZATION_ENABLED`, an identify event's reserved traits are
+//! renamed to one lowercase snake_case key each (see [`RESERVED_TRAITS`]),
+//! matching names case-insensitively and ignoring `-`, `_` and spaces. When
+//! several spellings are sent, the canonical one wins, then the first in
+//! name order. Other traits keep their names.
+//!
+//! Traits with a known format are checked and stored in normalized form:
+//!
+//! - `email`: one `@`, a dotted domain, no whitespace; stored lowercase
+//! - `phone`: 7 to 15 digits, optionally after a `+`, ignoring spaces,
+//!   dashes, dots and parentheses; stored as the digits with the `+`
+//! - `website`, `avatar`: absolute `http` or `https` URLs
+//!
+//! An identify with a malformed one is rejected with a 422 listing every
+//! violation (a batch rejects just that event, reason `invalid_traits`).
+//! Events other than identify pass untouched.
+
+use lambda_http::{Body, Response};
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::models::IngestEventPayload;
+use crate::schema::Violation;
+use crate::shared::{create_response, env_flag};
+
+/// Reserved traits by canonical name, with the other spellings they're
+/// known by (compared lowercase, without separators)
+pub const RESERVED_TRAITS: [(&str, &[&str]); 16] = [
+    ("email", &["mail", "emailaddress"]),
+    ("phone", &["phonenumber", "mobile", "telephone"]),
+    ("website", &["url", "homepage"]),
+    ("avatar", &["avatarurl", "picture"]),
+    ("name", &["fullname"]),
+    ("first_name", &["firstname", "givenname"]),
+    ("last_name", &["lastname", "surname", "familyname"]),
+    ("username", &["login"]),
+    ("created_at", &["createdat", "signupdate"]),
+    ("company", &["companyname"]),
+    ("title", &["jobtitle"]),
+    ("gender", &[]),
+    ("birthday", &["dateofbirth", "dob"]),
+    ("age", &[]),
+    ("address", &[]),
+    ("description", &[]),
+];
+
+/// Configuration for trait normalization
+#[derive(Debug, Clone, Default)]
+pub struct TraitsConfig {
+    pub enabled: bool,
+}
+
+impl TraitsConfig {
+    pub fn from_env() -> Self {
+        Self {
+            enabled: env_flag("TRAIT_NORMALIZATION_ENABLED"),
+        }
+    }
+}
+
+/// The canonical name of a reserved trait, if `key` spells one
+pub fn canonical_name(key: &str) -> Option<&'static str> {
+    let compact: String = key
+        .chars()
+        .filter(|c| !matches!(c, '-' | '_' | ' '))
+        .flat_map(char::to_lowercase)
+        .collect();
+    RESERVED_TRAITS.iter().find_map(|&(name, aliases)| {
+        (compact == name.replace('_', "") || aliases.contains(&compact.as_str())).then_some(name)
+    })
+}
+
+/// Renames reserved traits and normalizes their values in place
+pub fn normalize(payload: &mut IngestEventPayload, config: &TraitsConfig) -> Result<(), Vec<Violation>> {
+    if !config.enabled || payload.event_type != "identify" {
+        return Ok(());
+    }
+    let Some(traits) = payload.traits.take() else {
+        return Ok(());
+    };
+
+    // Canonical spellings first, then by name, so the winner is stable
+    let mut entries: Vec<(String, Value)> = traits.into_iter().collect();
+    entries.sort_by_cached_key(|(key, _)| (canonical_name(key) != Some(key.as_str()), key.clone()));
+    let mut normalized: HashMap<String, Value> = HashMap::with_capacity(entries.len());
+    let mut violations = Vec::new();
+    for (key, value) in entries {
+        let Some(name) = canonical_name(&key) else {
+            normalized.insert(key, value);
+            continue;
+        };
+        if normalized.contains_key(name) {
+            continue;
+        }
+        match normalize_value(name, value) {
+            Ok(value) => {
+                normalized.insert(name.to_string(), value);
+            }
+            Err(message) => violations.push(Violation {
+                path: format!("traits.{}", key),
+                message: message.to_string(),
+            }),
+        }
+    }
+
+    payload.traits = Some(normalized);
+    if !violations.is_empty() {
+        return Err(violations);
+    }
+    Ok(())
+}
+
+/// A reserved trait's value in normalized form; nulls clear the trait and
+/// are kept as they are
+fn normalize_value(name: &str, value: Value) -> Result<Value, &'static str> {
+    if value.is_null() {
+        return Ok(value);
+    }
+    match name {
+        "email" => value
+            .as_str()
+            .and_then(email)
+            .map(Value::String)
+            .ok_or("must be an email address"),
+        "phone" => phone(&value)
+            .map(Value::String)
+            .ok_or("must be a phone number of 7 to 15 digits"),
+        "website" | "avatar" => value
+            .as_str()
+            .and_then(web_url)
+            .map(Value::String)
+            .ok_or("must be an http or https URL"),
+        _ => Ok(value),
+    }
+}
+
+fn email(value: &str) -> Option<String> {
+    let email = value.trim().to_lowercase();
+    let (local, domain) = email.split_once('@')?;
+    let valid = !local.is_empty()
+        && !domain.contains('@')
+        && domain.split('.').count() > 1
+        && domain.split('.').all(|label| !label.is_empty())
+        && !email.chars().any(char::is_whitespace);
+    valid.then_some(email)
+}
+
+fn phone(value: &Value) -> Option<String> {
+    let number = match value {
+        Value::String(number) => number.trim().to_string(),
+        Value::Number(number) if number.is_u64() => number.to_string(),
+        _ => return None,
+    };
+    let (plus, rest) = match number.strip_prefix('+') {
+        Some(rest) => ("+", rest),
+        None => ("", number.as_str()),
+    };
+    let mut digits = String::with_capacity(rest.len());
+    for c in rest.chars() {
+        match c {
+            '0'..='9' => digits.push(c),
+            ' ' | '-' | '.' | '(' | ')' => {}
+            _ => return None,
+        }
+    }
+    (7..=15).contains(&digits.len()).then(|| format!("{}{}", plus, digits))
+}
+
+fn web_url(value: &str) -> Option<String> {
+    let parsed = url::Url::parse(value.trim()).ok()?;
+    let web = matches!(parsed.scheme(), "http" | "https") && parsed.host_str().is_some_and(|host| !host.is_empty());
+    web.then(|| parsed.to_string())
+}
+
+/// The 422 for an identify with malformed traits
+pub fn violation_response(violations: &[Violation]) -> Response<Body> {
+    create_response(
+        422,
+        serde_json::json!({
+            "error": "Invalid traits",
+            "violations": violations,
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn identify(traits: Value) -> IngestEventPayload {
+        IngestEventPayload {
+            project_id: "proj".to_string(),
+            event_type: "identify".to_string(),
+            traits: serde_json::from_value(traits).unwrap(),
+            ..Default::default()
+        }
+    }
+
+    fn enabled() -> TraitsConfig {
+        TraitsConfig { enabled: true }
+    }
+
+    #[test]
+    fn test_spellings_of_reserved_traits_share_one_key() {
+        for key in ["email", "Email", "e-mail", "E_Mail", "emailAddress"] {
+            assert_eq!(canonical_name(key), Some("email"), "{}", key);
+        }
+        assert_eq!(canonical_name("firstName"), Some("first_name"));
+        assert_eq!(canonical_name("Created At"), Some("created_at"));
+        assert_eq!(canonical_name("plan"), None);
+    }
+
+    #[test]
+    fn test_normalizes_reserved_traits_and_keeps_the_rest() {
+        let mut event = identify(json!({
+            "Email": " Ada@Example.COM ",
+            "Phone Number": "+1 (555) 010-9999",
+            "firstName": "Ada",
+            "Website": "https://ada.dev",
+            "Plan": "pro",
+        }));
+        assert_eq!(normalize(&mut event, &enabled()), Ok(()));
+        assert_eq!(
+            json!(event.traits),
+            json!({
+                "email": "ada@example.com",
+                "phone": "+15550109999",
+                "first_name": "Ada",
+                "website": "https://ada.dev/",
+                "Plan": "pro",
+            })
+        );
+    }
+
+    #[test]
+    fn test_canonical_spelling_wins() {
+        let mut event = identify(json!({"e-mail": "old@a.io", "email": "new@a.io", "Email": "other@a.io"}));
+        assert_eq!(normalize(&mut event, &enabled()), Ok(()));
+        assert_eq!(json!(event.traits), json!({"email": "new@a.io"}));
+    }
+
+    #[test]
+    fn test_lists_every_malformed_trait() {
+        let mut event = identify(json!({"email": "nobody", "phone": "call me", "avatar": "ftp://a.io/x.png"}));
+        let violations = normalize(&mut event, &enabled()).unwrap_err();
+        let mut paths: Vec<_> = violations.iter().map(|v| v.path.as_str()).collect();
+        paths.sort_unstable();
+        assert_eq!(paths, ["traits.avatar", "traits.email", "traits.phone"]);
+    }
+
+    #[test]
+    fn test_other_events_and_disabled_config_pass_untouched() {
+        let mut event = identify(json!({"Email": "nobody"}));
+        assert_eq!(normalize(&mut event, &TraitsConfig::default()), Ok(()));
+        assert_eq!(json!(event.traits), json!({"Email": "nobody"}));
+
+        event.event_type = "signup".to_string();
+        assert_eq!(normalize(&mut event, &enabled()), Ok(()));
+        assert_eq!(json!(event.traits), json!({"Email": "nobody"}));
+    }
+}
@@ -0,0 +1,236 @@
+//! Structured validation errors.
+//!
+//! Payload checks collect every problem they find rather than stopping at
+//! the first, each with the path of the field at fault (as the client sent
+//! it, e.g. `en` or `source.file`), a stable code and a message. A request
+//! failing them gets a 422:
+//!
+//! ```json
+//! {"error": "en (event name) is required; o (origin) is required",
+//!  "errors": [{"field": "en", "code": "required", "message": "en (event name) is required"}, ...]}
+//! ```
+//!
+//! `error` is every message joined, as the single message used to be.
+//!
+//! That's strict mode. Projects can be lenient instead (`VALIDATION_MODE`
+//! for every project, `VALIDATION_PROJECT_MODES` per project, e.g.
+//! `{"marketing-site": "lenient"}`): an invalid event is accepted, with
+//! empty property and trait names dropped, a missing name replaced by
+//! `unknown` and the problems listed in its `validation_warnings`. Tagged
+//! events go to `QUARANTINE_STREAM_NAME` when set rather than the stream
+//! they'd otherwise be routed to, unless pinned to a residency zone.
+//! Payloads that can't be parsed at all are rejected either way.
+
+use lambda_http::{Body, Response};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::models::IngestEventPayload;
+use crate::shared::{create_response, env_json, env_or, env_var};
+
+/// What happens to events failing validation
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ValidationMode {
+    /// Rejected with a 422
+    #[default]
+    Strict,
+    /// Accepted and tagged with their problems
+    Lenient,
+}
+
+impl std::str::FromStr for ValidationMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "strict" => Ok(Self::Strict),
+            "lenient" => Ok(Self::Lenient),
+            other => Err(format!("unknown validation mode \"{}\"", other)),
+        }
+    }
+}
+
+/// Configuration for validation modes
+#[derive(Debug, Clone, Default)]
+pub struct ValidationConfig {
+    pub default: ValidationMode,
+    pub projects: HashMap<String, ValidationMode>,
+    /// Stream leniently accepted events go to
+    pub quarantine_stream: Option<String>,
+}
+
+impl ValidationConfig {
+    pub fn from_env() -> Self {
+        Self {
+            default: env_or("VALIDATION_MODE", ValidationMode::Strict),
+            projects: env_json("VALIDATION_PROJECT_MODES").unwrap_or_default(),
+            quarantine_stream: env_var("QUARANTINE_STREAM_NAME").filter(|stream| !stream.is_empty()),
+        }
+    }
+
+    pub fn mode_for(&self, project_id: &str) -> ValidationMode {
+        self.projects.get(project_id).copied().unwrap_or(self.default)
+    }
+}
+
+/// One problem with a payload
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+pub struct ValidationError {
+    /// Path of the offending field, `$` for the payload as a whole
+    pub field: String,
+    /// Stable machine-readable code, e.g. `required`
+    #[schema(value_type = String)]
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// Every problem found with a payload, in the order they were checked
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct ValidationErrors(Vec<ValidationError>);
+
+impl ValidationErrors {
+    /// Records a problem with `field`
+    pub fn add(&mut self, field: impl Into<String>, code: &'static str, message: impl Into<String>) {
+        self.0.push(ValidationError {
+            field: field.into(),
+            code,
+            message: message.into(),
+        });
+    }
+
+    /// Records the problems of another check
+    pub fn extend(&mut self, other: Result<(), ValidationErrors>) {
+        if let Err(other) = other {
+            self.0.extend(other.0);
+        }
+    }
+
+    pub fn errors(&self) -> &[ValidationError] {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// `Ok` when nothing was found
+    pub fn into_result(self) -> Result<(), Self> {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+
+    /// 422 listing every problem
+    pub fn response(&self) -> Response<Body> {
+        create_response(422, serde_json::json!({ "error": self.to_string(), "errors": self }))
+    }
+}
+
+impl std::fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let messages: Vec<_> = self.0.iter().map(|error| error.message.as_str()).collect();
+        f.write_str(&messages.join("; "))
+    }
+}
+
+/// Applies the project's mode to a payload's problems: strict mode fails
+/// with them, lenient mode passes them on as warnings for [`tag`]
+pub fn enforce(
+    result: Result<(), ValidationErrors>,
+    project_id: &str,
+    config: &ValidationConfig,
+) -> Result<ValidationErrors, ValidationErrors> {
+    match (result, config.mode_for(project_id)) {
+        (Ok(()), _) => Ok(ValidationErrors::default()),
+        (Err(errors), ValidationMode::Strict) => Err(errors),
+        (Err(errors), ValidationMode::Lenient) => Ok(errors),
+    }
+}
+
+/// Coerces what can be of a leniently accepted event and tags it with its
+/// problems; events without any are left alone
+pub fn tag(event: &mut IngestEventPayload, warnings: ValidationErrors) {
+    if warnings.is_empty() {
+        return;
+    }
+    if event.event_type.trim().is_empty() {
+        event.event_type = "unknown".to_string();
+    }
+    for map in [&mut event.properties, &mut event.traits].into_iter().flatten() {
+        map.remove("");
+    }
+    event.validation_warnings = Some(warnings.0.iter().map(ToString::to_string).collect());
+}
+
+/// Whether an event was accepted despite failing validation
+pub fn is_quarantined(event: &IngestEventPayload) -> bool {
+    event.validation_warnings.is_some()
+}
+
+/// A single problem, as a failed check
+pub fn invalid(field: impl Into<String>, code: &'static str, message: impl Into<String>) -> Result<(), ValidationErrors> {
+    let mut errors = ValidationErrors::default();
+    errors.add(field, code, message);
+    Err(errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collects_every_problem_into_one_response() {
+        let mut errors = ValidationErrors::default();
+        assert_eq!(errors.clone().into_result(), Ok(()));
+        errors.add("en", "required", "en (event name) is required");
+        errors.extend(Ok(()));
+        errors.extend(invalid("sentAt", "invalid_timestamp", "sentAt is not a valid timestamp: soon"));
+        assert_eq!(errors.errors().len(), 2);
+
+        let response = errors.response();
+        assert_eq!(response.status(), 422);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "error": "en (event name) is required; sentAt is not a valid timestamp: soon",
+                "errors": [
+                    {"field": "en", "code": "required", "message": "en (event name) is required"},
+                    {"field": "sentAt", "code": "invalid_timestamp", "message": "sentAt is not a valid timestamp: soon"},
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn test_lenient_projects_get_tagged_events() {
+        let config = ValidationConfig {
+            projects: HashMap::from([("site".to_string(), ValidationMode::Lenient)]),
+            ..Default::default()
+        };
+        let problem = || invalid("en", "required", "en (event name) is required");
+        assert!(enforce(problem(), "warehouse", &config).is_err());
+        assert!(enforce(Ok(()), "warehouse", &config).unwrap().is_empty());
+
+        let warnings = enforce(problem(), "site", &config).unwrap();
+        let mut event = IngestEventPayload {
+            properties: Some(HashMap::from([("".to_string(), 1.into()), ("plan".to_string(), "pro".into())])),
+            ..Default::default()
+        };
+        tag(&mut event, warnings);
+        assert_eq!(event.event_type, "unknown");
+        assert_eq!(event.properties.as_ref().unwrap().len(), 1);
+        assert_eq!(event.validation_warnings, Some(vec!["en (event name) is required".to_string()]));
+        assert!(is_quarantined(&event));
+    }
+}
@@ -0,0 +1,69 @@
+//! API versions.
+//!
+//! Endpoints are served under a version segment, `/v1/view` or `/v2/view`,
+//! with the handlers shared between versions. A path without one (after
+//! any stage prefix, like `/prod/view`) is v1, and an unknown version is a
+//! 404 (see `routes`). The router records the version as a request
+//! extension for the handlers to read.
+//!
+//! v1 keeps the original semantics, with the stricter checks behind their
+//! flags. v2 always applies them and answers differently:
+//!
+//! - `ts` must be a positive epoch timestamp and an explicit `type` must
+//!   match the endpoint (`REJECT_NONPOSITIVE_TIMESTAMPS` and
+//!   `REJECT_EVENT_TYPE_MISMATCH` in v1)
+//! - accepted events get a 202 with a JSON body, whatever `SUCCESS_STATUS`
+//!   says (project response overrides still apply)
+//! - a batch where every event failed is a 422 rather than a 400
+
+use lambda_http::Request;
+
+use crate::shared::Config;
+
+/// A version of the ingestion API
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ApiVersion {
+    #[default]
+    V1,
+    V2,
+}
+
+impl ApiVersion {
+    /// The version the router recorded for a request
+    pub fn of(request: &Request) -> Self {
+        request.extensions().get::<Self>().copied().unwrap_or_default()
+    }
+
+    /// Whether `ts` must be positive
+    pub fn requires_timestamps(self, config: &Config) -> bool {
+        self == Self::V2 || config.reject_nonpositive_timestamps
+    }
+
+    /// Whether an explicit `type` must match the endpoint
+    pub fn requires_matching_kind(self, config: &Config) -> bool {
+        self == Self::V2 || config.reject_kind_mismatch
+    }
+
+    /// Status of an accepted request without a project override
+    pub fn success_status(self, config: &Config) -> u16 {
+        match self {
+            Self::V1 => config.success_status,
+            Self::V2 => 202,
+        }
+    }
+
+    /// Status of a batch where every event failed
+    pub fn rejected_batch_status(self) -> u16 {
+        match self {
+            Self::V1 => 400,
+            Self::V2 => 422,
+        }
+    }
+}
+
+/// Whether a path segment looks like a version, `v` and a number
+pub(crate) fn is_version(segment: &str) -> bool {
+    segment
+        .strip_prefix('v')
+        .is_some_and(|number| !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit()))
+}
@@ -0,0 +1,221 @@
+//! End-to-end tests of the Kinesis path against LocalStack.
+//!
+//! Each test starts a LocalStack container with Kinesis and DynamoDB, sends
+//! requests through `function_handler` as API Gateway would, and reads back
+//! what landed in the stream. They need Docker, so they're behind the
+//! `localstack` feature:
+//!
+//! ```sh
+//! cargo test --features localstack --test localstack
+//! ```
+#![cfg(feature = "localstack")]
+
+use aws_config::{BehaviorVersion, SdkConfig};
+use aws_credential_types::Credentials;
+use aws_sdk_dynamodb::types::{AttributeDefinition, BillingMode, KeySchemaElement, KeyType, ScalarAttributeType};
+use aws_sdk_dynamodb::Client as DynamoClient;
+use aws_sdk_kinesis::types::{ShardIteratorType, StreamStatus};
+use aws_sdk_kinesis::Client as KinesisClient;
+use base64::Engine;
+use ingestion::dedup::{DedupConfig, DynamoMessageIdStore};
+use ingestion::models::IngestEventPayload;
+use ingestion::router::function_handler;
+use ingestion::routing::StreamClients;
+use ingestion::shared::{test_state, AppState, Config};
+use lambda_http::{Body, Request};
+use std::sync::Arc;
+use std::time::Duration;
+use testcontainers_modules::localstack::LocalStack;
+use testcontainers_modules::testcontainers::runners::AsyncRunner;
+use testcontainers_modules::testcontainers::{ContainerAsync, ImageExt};
+
+const STREAM: &str = "events";
+
+/// A running LocalStack; stopped when dropped
+struct Aws {
+    _container: ContainerAsync<LocalStack>,
+    config: SdkConfig,
+}
+
+impl Aws {
+    async fn start() -> Self {
+        let container = LocalStack::default()
+            .with_env_var("SERVICES", "kinesis,dynamodb")
+            .start()
+            .await
+            .expect("LocalStack should start (is Docker running?)");
+        let host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(4566).await.unwrap();
+        let config = aws_config::defaults(BehaviorVersion::latest())
+            .region("us-east-1")
+            .credentials_provider(Credentials::new("test", "test", None, None, "localstack"))
+            .endpoint_url(format!("http://{}:{}", host, port))
+            .load()
+            .await;
+        Self {
+            _container: container,
+            config,
+        }
+    }
+
+    fn kinesis(&self) -> KinesisClient {
+        KinesisClient::new(&self.config)
+    }
+
+    fn dynamo(&self) -> DynamoClient {
+        DynamoClient::new(&self.config)
+    }
+
+    /// Creates the stream with one shard and waits until it's active
+    async fn create_stream(&self) {
+        let kinesis = self.kinesis();
+        kinesis.create_stream().stream_name(STREAM).shard_count(1).send().await.unwrap();
+        for _ in 0..50 {
+            let summary = kinesis.describe_stream_summary().stream_name(STREAM).send().await.unwrap();
+            if summary.stream_description_summary().map(|s| s.stream_status()) == Some(&StreamStatus::Active) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("Stream {} never became active", STREAM);
+    }
+
+    /// Creates a table keyed by `pk` (S)
+    async fn create_table(&self, name: &str) {
+        self.dynamo()
+            .create_table()
+            .table_name(name)
+            .attribute_definitions(
+                AttributeDefinition::builder()
+                    .attribute_name("pk")
+                    .attribute_type(ScalarAttributeType::S)
+                    .build()
+                    .unwrap(),
+            )
+            .key_schema(KeySchemaElement::builder().attribute_name("pk").key_type(KeyType::Hash).build().unwrap())
+            .billing_mode(BillingMode::PayPerRequest)
+            .send()
+            .await
+            .unwrap();
+    }
+
+    /// State writing to the stream, with the tests' stores swapped in
+    fn state(&self, config: Config, customize: impl FnOnce(&mut AppState)) -> Arc<AppState> {
+        let mut state = AppState {
+            streams: StreamClients::new(STREAM.to_string(), self.kinesis()),
+            ..test_state(config)
+        };
+        customize(&mut state);
+        Arc::new(state)
+    }
+
+    /// Every event in the stream, in order
+    async fn events(&self) -> Vec<IngestEventPayload> {
+        let kinesis = self.kinesis();
+        let shards = kinesis.list_shards().stream_name(STREAM).send().await.unwrap();
+        let shard = shards.shards()[0].shard_id().to_string();
+        let iterator = kinesis
+            .get_shard_iterator()
+            .stream_name(STREAM)
+            .shard_id(shard)
+            .shard_iterator_type(ShardIteratorType::TrimHorizon)
+            .send()
+            .await
+            .unwrap();
+        let records = kinesis
+            .get_records()
+            .shard_iterator(iterator.shard_iterator().unwrap())
+            .send()
+            .await
+            .unwrap();
+        records
+            .records()
+            .iter()
+            .map(|record| serde_json::from_slice(record.data().as_ref()).unwrap())
+            .collect()
+    }
+}
+
+fn token(project_id: &str) -> String {
+    let claims = serde_json::json!({ "projectId": project_id }).to_string();
+    let encoded = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(claims);
+    format!("e30.{}.sig", encoded)
+}
+
+fn post(path: &str, body: serde_json::Value) -> Request {
+    lambda_http::http::Request::builder()
+        .method("POST")
+        .uri(path)
+        .header("Authorization", format!("Bearer {}", token("proj")))
+        .header("Content-Type", "application/json")
+        .body(Body::Text(body.to_string()))
+        .unwrap()
+}
+
+fn pageview(url: &str) -> serde_json::Value {
+    serde_json::json!({"en": "pageview", "ts": 1_700_000_000_000_i64, "o": url, "r": "", "sw": 1280, "sh": 720})
+}
+
+#[tokio::test]
+async fn test_pageviews_land_in_the_stream() {
+    let aws = Aws::start().await;
+    aws.create_stream().await;
+    let state = aws.state(Config::default(), |_| {});
+
+    let response = function_handler(post("/view", pageview("https://a.io/pricing")), state).await.unwrap();
+    assert_eq!(response.status(), 202);
+
+    let events = aws.events().await;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].project_id, "proj");
+    assert_eq!(events[0].event_type, "pageview");
+    let page = events[0].context.as_ref().and_then(|c| c.page.as_ref()).unwrap();
+    assert_eq!(page.path.as_deref(), Some("/pricing"));
+}
+
+#[tokio::test]
+async fn test_batches_land_in_order() {
+    let aws = Aws::start().await;
+    aws.create_stream().await;
+    let state = aws.state(Config::default(), |_| {});
+
+    let batch = serde_json::json!([pageview("https://a.io/1"), pageview("https://a.io/2"), pageview("https://a.io/3")]);
+    let response = function_handler(post("/batch", batch), state).await.unwrap();
+    assert_eq!(response.status(), 202);
+
+    let paths: Vec<_> = aws
+        .events()
+        .await
+        .into_iter()
+        .filter_map(|event| event.context?.page?.path)
+        .collect();
+    assert_eq!(paths, ["/1", "/2", "/3"]);
+}
+
+#[tokio::test]
+async fn test_retried_messages_are_written_once() {
+    let aws = Aws::start().await;
+    aws.create_stream().await;
+    aws.create_table("message-ids").await;
+    let config = Config {
+        message_dedup: DedupConfig {
+            enabled: true,
+            table_name: Some("message-ids".to_string()),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let store = DynamoMessageIdStore::new(aws.dynamo(), "message-ids".to_string(), &config.message_dedup);
+    let state = aws.state(config, |state| state.message_ids = Arc::new(store));
+
+    let mut retried = pageview("https://a.io/checkout");
+    retried["messageId"] = "m-1".into();
+    for _ in 0..2 {
+        let response = function_handler(post("/view", retried.clone()), state.clone()).await.unwrap();
+        assert_eq!(response.status(), 202);
+    }
+
+    let events = aws.events().await;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].message_id.as_deref(), Some("m-1"));
+}
@@ -0,0 +1,10 @@
+#!/bin/bash
+set -e
+
+echo "Building loadgen..."
+
+# Runs from an operator's machine or a one-off task, not as a Lambda
+cargo build --release
+
+echo "Build complete! Binary location:"
+echo "target/release/loadgen"
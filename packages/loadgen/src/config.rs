@@ -0,0 +1,147 @@
+//! Load generator configuration.
+
+use ingestion::shared::{env_list, env_opt, env_or, env_var};
+use std::time::Duration;
+
+/// Where generated events are sent
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TargetKind {
+    /// The ingest API's `POST /batch`, exercising the whole pipeline
+    #[default]
+    Http,
+    /// The stream, bypassing the ingest API, to load the consumers only
+    Kinesis,
+}
+
+impl std::str::FromStr for TargetKind {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "http" => Ok(Self::Http),
+            "kinesis" => Ok(Self::Kinesis),
+            other => Err(format!("unknown load target \"{}\"", other)),
+        }
+    }
+}
+
+/// Configuration for a load run
+#[derive(Debug, Clone)]
+pub struct LoadConfig {
+    pub target: TargetKind,
+    /// Base URL of the ingest API, for the HTTP target
+    pub url: String,
+    /// Sent as `X-API-Key` when the ingest API requires keys
+    pub api_key: Option<String>,
+    /// Stream written to by the Kinesis target
+    pub stream_name: String,
+    /// Projects the sessions are spread over
+    pub projects: Vec<String>,
+    /// Sessions in progress at once; each ends after a few events and is
+    /// replaced by a new one
+    pub sessions: usize,
+    /// Event types and their relative weights; `pageview` is sent as a
+    /// pageview, anything else as a track event
+    pub mix: Vec<(String, u32)>,
+    /// Events per second at the start of the run
+    pub start_rps: f64,
+    /// Events per second reached at the end of the ramp and held after
+    pub peak_rps: f64,
+    pub ramp: Duration,
+    /// Length of the whole run, ramp included
+    pub duration: Duration,
+    /// Most events per request
+    pub batch_size: usize,
+    /// Most requests in flight
+    pub concurrency: usize,
+    /// Seed for reproducible traffic; random when unset
+    pub seed: Option<u64>,
+}
+
+impl Default for LoadConfig {
+    fn default() -> Self {
+        Self {
+            target: TargetKind::Http,
+            url: String::new(),
+            api_key: None,
+            stream_name: String::new(),
+            projects: vec!["loadgen".to_string()],
+            sessions: 1000,
+            mix: vec![
+                ("pageview".to_string(), 70),
+                ("button_clicked".to_string(), 20),
+                ("signup".to_string(), 5),
+                ("order_completed".to_string(), 5),
+            ],
+            start_rps: 10.0,
+            peak_rps: 100.0,
+            ramp: Duration::from_secs(60),
+            duration: Duration::from_secs(300),
+            batch_size: 25,
+            concurrency: 32,
+            seed: None,
+        }
+    }
+}
+
+impl LoadConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let projects = env_list("LOADGEN_PROJECTS");
+        let mix = parse_mix(&env_list("LOADGEN_EVENT_MIX"));
+        Self {
+            target: env_or("LOADGEN_TARGET", defaults.target),
+            url: env_var("LOADGEN_URL").unwrap_or_default(),
+            api_key: env_var("LOADGEN_API_KEY").filter(|key| !key.is_empty()),
+            stream_name: env_var("LOADGEN_STREAM_NAME")
+                .or_else(|| env_var("STREAM_NAME"))
+                .unwrap_or_default(),
+            projects: if projects.is_empty() { defaults.projects } else { projects },
+            sessions: env_or("LOADGEN_SESSIONS", defaults.sessions).max(1),
+            mix: if mix.is_empty() { defaults.mix } else { mix },
+            start_rps: env_or("LOADGEN_START_RPS", defaults.start_rps).max(0.0),
+            peak_rps: env_or("LOADGEN_PEAK_RPS", defaults.peak_rps).max(0.0),
+            ramp: env_opt("LOADGEN_RAMP_SECS").map_or(defaults.ramp, Duration::from_secs),
+            duration: env_opt("LOADGEN_DURATION_SECS").map_or(defaults.duration, Duration::from_secs),
+            batch_size: env_or("LOADGEN_BATCH_SIZE", defaults.batch_size).clamp(1, 500),
+            concurrency: env_or("LOADGEN_CONCURRENCY", defaults.concurrency).max(1),
+            seed: env_opt("LOADGEN_SEED"),
+        }
+    }
+}
+
+/// Parses `type:weight` entries, e.g. `pageview:70`, skipping invalid ones
+pub fn parse_mix(entries: &[String]) -> Vec<(String, u32)> {
+    entries
+        .iter()
+        .filter_map(|entry| {
+            let parsed = entry
+                .split_once(':')
+                .and_then(|(event_type, weight)| Some((event_type.trim(), weight.trim().parse::<u32>().ok()?)))
+                .filter(|(event_type, weight)| !event_type.is_empty() && *weight > 0);
+            if parsed.is_none() {
+                tracing::warn!("Ignoring event mix entry {}, expected type:weight", entry);
+            }
+            parsed.map(|(event_type, weight)| (event_type.to_string(), weight))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parses_the_event_mix() {
+        let entries: Vec<String> = ["pageview:60", " signup : 5 ", "broken", "zero:0", ":3"]
+            .iter()
+            .map(|entry| entry.to_string())
+            .collect();
+        assert_eq!(
+            parse_mix(&entries),
+            [("pageview".to_string(), 60), ("signup".to_string(), 5)]
+        );
+        assert_eq!("Kinesis".parse(), Ok(TargetKind::Kinesis));
+        assert!("kafka".parse::<TargetKind>().is_err());
+    }
+}
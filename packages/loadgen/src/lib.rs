@@ -0,0 +1,16 @@
+//! Synthetic load for the ingest pipeline.
+//!
+//! Generates pageview and track traffic from simulated visitor sessions
+//! ([`traffic`]) at a rate ramping up to a peak ([`run`]), and sends it to
+//! the ingest API's `POST /batch` or straight to the Kinesis stream
+//! ([`target`]), to validate shard sizing and consumer lag before a launch.
+//! Every generated event has a `loadgen: true` property, so it can be told
+//! apart from real traffic; point it at projects reserved for load tests.
+
+pub mod config;
+pub mod run;
+pub mod target;
+pub mod traffic;
+
+/// Errors of the load generator, as in the ingestion crate
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
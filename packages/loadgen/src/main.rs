@@ -0,0 +1,57 @@
+use std::sync::Arc;
+
+use aws_sdk_kinesis::Client as KinesisClient;
+use ingestion::partitioning::PartitionConfig;
+use ingestion::retry::RetryConfig;
+use loadgen::config::{LoadConfig, TargetKind};
+use loadgen::run::run;
+use loadgen::target::{HttpTarget, KinesisTarget, Target};
+use loadgen::traffic::Traffic;
+use loadgen::Error;
+
+/// Runs synthetic load as configured by the `LOADGEN_*` environment
+/// variables
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    // Initialize tracing
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"))
+        )
+        .init();
+
+    let config = LoadConfig::from_env();
+    let target: Arc<dyn Target> = match config.target {
+        TargetKind::Http => {
+            if config.url.is_empty() {
+                return Err("LOADGEN_URL environment variable not set".into());
+            }
+            Arc::new(HttpTarget::new(&config.url, config.api_key.clone())?)
+        }
+        TargetKind::Kinesis => {
+            if config.stream_name.is_empty() {
+                return Err("LOADGEN_STREAM_NAME environment variable not set".into());
+            }
+            let aws = aws_config::load_from_env().await;
+            Arc::new(KinesisTarget::new(
+                KinesisClient::new(&aws),
+                config.stream_name.clone(),
+                PartitionConfig::from_env(),
+                RetryConfig::from_env(),
+            ))
+        }
+    };
+
+    tracing::info!(
+        "Ramping from {} to {} events/s over {}s, for {}s against {:?}",
+        config.start_rps,
+        config.peak_rps,
+        config.ramp.as_secs(),
+        config.duration.as_secs(),
+        config.target
+    );
+    let report = run(Traffic::new(&config), target, &config).await;
+    tracing::info!("Done: {} events sent, {} failed", report.sent, report.failed);
+    Ok(())
+}
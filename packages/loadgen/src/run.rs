@@ -0,0 +1,192 @@
+//! A load run.
+//!
+//! Every tick, the events due at the current target rate are generated and
+//! sent in batches of `LOADGEN_BATCH_SIZE`, with at most
+//! `LOADGEN_CONCURRENCY` requests in flight. The rate ramps linearly from
+//! `LOADGEN_START_RPS` to `LOADGEN_PEAK_RPS` over `LOADGEN_RAMP_SECS` and
+//! is then held until the end of the run. When every request slot is busy
+//! the generator waits, so a saturated target shows up as an achieved rate
+//! below the target one rather than as unbounded memory. Progress (target
+//! and achieved rate, failures, latency) is logged every
+//! [`REPORT_INTERVAL`].
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::time::Instant;
+
+use crate::config::LoadConfig;
+use crate::target::Target;
+use crate::traffic::Traffic;
+
+/// How often events are generated
+const TICK: Duration = Duration::from_millis(100);
+/// How often progress is logged
+pub const REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Target events per second, `elapsed` into the run
+pub fn rps_at(config: &LoadConfig, elapsed: Duration) -> f64 {
+    if elapsed >= config.ramp {
+        return config.peak_rps;
+    }
+    let progress = elapsed.as_secs_f64() / config.ramp.as_secs_f64();
+    config.start_rps + (config.peak_rps - config.start_rps) * progress
+}
+
+/// Counters shared with the requests in flight
+#[derive(Debug, Default)]
+struct Stats {
+    sent: AtomicU64,
+    failed: AtomicU64,
+    /// Request latencies in milliseconds, since the last report
+    latencies: Mutex<Vec<u64>>,
+}
+
+impl Stats {
+    /// Median and 99th percentile latency since the last call
+    fn take_latencies(&self) -> (u64, u64) {
+        let mut latencies = std::mem::take(&mut *self.latencies.lock().unwrap());
+        latencies.sort_unstable();
+        let percentile = |p: usize| latencies.get((latencies.len() * p / 100).min(latencies.len().saturating_sub(1)));
+        (percentile(50).copied().unwrap_or_default(), percentile(99).copied().unwrap_or_default())
+    }
+}
+
+/// What a run did
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Report {
+    /// Events the target took
+    pub sent: u64,
+    /// Events of failed requests
+    pub failed: u64,
+}
+
+/// Generates and sends traffic for the configured duration
+pub async fn run(mut traffic: Traffic, target: Arc<dyn Target>, config: &LoadConfig) -> Report {
+    let stats = Arc::new(Stats::default());
+    let slots = Arc::new(Semaphore::new(config.concurrency));
+    let started = Instant::now();
+    let mut ticks = tokio::time::interval(TICK);
+    let mut due = 0.0;
+    let (mut last_report, mut reported_sent) = (started, 0);
+
+    loop {
+        let now = ticks.tick().await;
+        let elapsed = now - started;
+        if elapsed >= config.duration {
+            break;
+        }
+        due += rps_at(config, elapsed) * TICK.as_secs_f64();
+        let count = due.floor() as usize;
+        due -= count as f64;
+
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as i64;
+        let mut events: Vec<_> = (0..count).map(|_| traffic.next_event(timestamp)).collect();
+        while !events.is_empty() {
+            let batch: Vec<_> = events.drain(..config.batch_size.min(events.len())).collect();
+            let Ok(slot) = slots.clone().acquire_owned().await else {
+                break;
+            };
+            let (target, stats) = (target.clone(), stats.clone());
+            tokio::spawn(async move {
+                let (size, sent_at) = (batch.len() as u64, Instant::now());
+                match target.send(batch).await {
+                    Ok(()) => stats.sent.fetch_add(size, Ordering::Relaxed),
+                    Err(e) => {
+                        tracing::warn!("Request failed: {}", e);
+                        stats.failed.fetch_add(size, Ordering::Relaxed)
+                    }
+                };
+                stats.latencies.lock().unwrap().push(sent_at.elapsed().as_millis() as u64);
+                drop(slot);
+            });
+        }
+
+        if now - last_report >= REPORT_INTERVAL {
+            let sent = stats.sent.load(Ordering::Relaxed);
+            let (p50, p99) = stats.take_latencies();
+            tracing::info!(
+                "{}s: target {:.0} events/s, achieved {:.0}, {} sent, {} failed, latency p50 {}ms p99 {}ms",
+                elapsed.as_secs(),
+                rps_at(config, elapsed),
+                (sent - reported_sent) as f64 / (now - last_report).as_secs_f64(),
+                sent,
+                stats.failed.load(Ordering::Relaxed),
+                p50,
+                p99
+            );
+            (last_report, reported_sent) = (now, sent);
+        }
+    }
+
+    // Wait for the requests still in flight
+    let _ = slots.acquire_many(config.concurrency as u32).await;
+    Report {
+        sent: stats.sent.load(Ordering::Relaxed),
+        failed: stats.failed.load(Ordering::Relaxed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+    use async_trait::async_trait;
+    use ingestion::models::IngestEventPayload;
+
+    /// Fails every third request, tracking the largest batch
+    #[derive(Default)]
+    struct FakeTarget {
+        requests: AtomicU64,
+        largest: AtomicU64,
+    }
+
+    #[async_trait]
+    impl Target for FakeTarget {
+        async fn send(&self, events: Vec<IngestEventPayload>) -> Result<(), Error> {
+            self.largest.fetch_max(events.len() as u64, Ordering::Relaxed);
+            tokio::time::sleep(Duration::from_millis(30)).await;
+            if self.requests.fetch_add(1, Ordering::Relaxed) % 3 == 2 {
+                return Err("503 Service Unavailable".into());
+            }
+            Ok(())
+        }
+    }
+
+    fn config() -> LoadConfig {
+        LoadConfig {
+            start_rps: 100.0,
+            peak_rps: 300.0,
+            ramp: Duration::from_secs(10),
+            duration: Duration::from_secs(20),
+            batch_size: 10,
+            seed: Some(1),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_rate_ramps_then_holds() {
+        let config = config();
+        assert_eq!(rps_at(&config, Duration::ZERO), 100.0);
+        assert_eq!(rps_at(&config, Duration::from_secs(5)), 200.0);
+        assert_eq!(rps_at(&config, Duration::from_secs(15)), 300.0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_sends_the_ramped_volume_in_batches() {
+        let config = config();
+        let target = Arc::new(FakeTarget::default());
+        let report = run(Traffic::new(&config), target.clone(), &config).await;
+
+        // 2_000 events over the ramp, 3_000 after, less the last tick's
+        let total = report.sent + report.failed;
+        assert!((4_900..=5_000).contains(&total), "{} events", total);
+        assert!(report.failed > 0 && report.sent > report.failed);
+        assert_eq!(target.largest.load(Ordering::Relaxed), 10);
+    }
+}
@@ -0,0 +1,172 @@
+//! Where generated events are sent.
+//!
+//! The HTTP target posts each project's events to the ingest API's
+//! `POST /batch` as compressed events, with a bearer token naming the
+//! project (the ingest API reads the project from the token's claims
+//! without checking its signature) and the `X-API-Key` when configured.
+//! The Kinesis target writes the events as the ingest API would, with its
+//! partition keys (`PARTITION_KEY_STRATEGY`) and retries, so the load on
+//! the shards matches production's.
+
+use async_trait::async_trait;
+use aws_sdk_kinesis::Client as KinesisClient;
+use base64::Engine;
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper_rustls::HttpsConnector;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use ingestion::models::{CompressedEvent, IngestEventPayload};
+use ingestion::partitioning::{self, PartitionConfig};
+use ingestion::put_records::{self, Record, Serialized};
+use ingestion::retry::{RetryBudget, RetryConfig};
+use std::collections::BTreeMap;
+
+use crate::Error;
+
+/// Where events are sent; a trait so runs can be tested without AWS
+#[async_trait]
+pub trait Target: Send + Sync {
+    async fn send(&self, events: Vec<IngestEventPayload>) -> Result<(), Error>;
+}
+
+/// The ingest API's `POST /batch`
+pub struct HttpTarget {
+    http: Client<HttpsConnector<HttpConnector>, Full<Bytes>>,
+    url: String,
+    api_key: Option<String>,
+}
+
+impl HttpTarget {
+    /// A target for the ingest API at `url`
+    pub fn new(url: &str, api_key: Option<String>) -> Result<Self, Error> {
+        let connector = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_native_roots()?
+            .https_or_http()
+            .enable_http1()
+            .build();
+        Ok(Self {
+            http: Client::builder(TokioExecutor::new()).build(connector),
+            url: format!("{}/batch", url.trim_end_matches('/')),
+            api_key,
+        })
+    }
+}
+
+/// A bearer token for a project
+pub fn token(project_id: &str) -> String {
+    let claims = serde_json::json!({ "projectId": project_id }).to_string();
+    let encoded = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(claims);
+    format!("e30.{}.loadgen", encoded)
+}
+
+/// The compressed form of a generated event, as the tracker sends it
+pub fn compress(event: IngestEventPayload) -> CompressedEvent<'static> {
+    let context = event.context.unwrap_or_default();
+    let page = context.page.unwrap_or_default();
+    let screen = context.screen;
+    let kind = if event.event_type == "pageview" { "pageview" } else { "track" };
+    CompressedEvent {
+        en: event.event_type.into(),
+        ts: event.timestamp,
+        o: page.url.unwrap_or_default().into(),
+        r: page.referrer.unwrap_or_default().into(),
+        sw: screen.as_ref().and_then(|screen| screen.width).unwrap_or_default(),
+        sh: screen.as_ref().and_then(|screen| screen.height).unwrap_or_default(),
+        ed: event.properties,
+        kind: Some(kind.into()),
+        consent: None,
+        message_id: None,
+        sequence: event.sequence,
+        sent_at: None,
+    }
+}
+
+#[async_trait]
+impl Target for HttpTarget {
+    async fn send(&self, events: Vec<IngestEventPayload>) -> Result<(), Error> {
+        let mut by_project: BTreeMap<String, Vec<CompressedEvent>> = BTreeMap::new();
+        for event in events {
+            by_project.entry(event.project_id.clone()).or_default().push(compress(event));
+        }
+
+        for (project_id, events) in by_project {
+            let mut request = http::Request::post(&self.url)
+                .header("content-type", "application/json")
+                .header("authorization", format!("Bearer {}", token(&project_id)));
+            if let Some(ref key) = self.api_key {
+                request = request.header("x-api-key", key);
+            }
+            let request = request.body(Full::new(Bytes::from(serde_json::to_vec(&events)?)))?;
+            let response = self.http.request(request).await?;
+            let status = response.status();
+            if !status.is_success() {
+                let body = response.into_body().collect().await?.to_bytes();
+                return Err(format!("Ingest API answered {}: {}", status, String::from_utf8_lossy(&body)).into());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The stream, written directly
+pub struct KinesisTarget {
+    client: KinesisClient,
+    stream_name: String,
+    partitioning: PartitionConfig,
+    retry: RetryConfig,
+}
+
+impl KinesisTarget {
+    pub fn new(client: KinesisClient, stream_name: String, partitioning: PartitionConfig, retry: RetryConfig) -> Self {
+        Self {
+            client,
+            stream_name,
+            partitioning,
+            retry,
+        }
+    }
+}
+
+#[async_trait]
+impl Target for KinesisTarget {
+    async fn send(&self, events: Vec<IngestEventPayload>) -> Result<(), Error> {
+        let mut records = Vec::with_capacity(events.len());
+        for event in &events {
+            let key = partitioning::partition_key(event, &self.partitioning);
+            let data = serde_json::to_vec(event)?;
+            records.push(Record::new(Serialized { event, key, data })?);
+        }
+        let mut budget = RetryBudget::new(self.retry.budget);
+        let failures = put_records::put_all(&self.client, &self.stream_name, &records, &self.retry, &mut budget).await;
+        match failures.first() {
+            None => Ok(()),
+            Some((_, reason)) => Err(format!("{} of {} records failed: {}", failures.len(), records.len(), reason).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ingestion::handlers::decode_jwt;
+
+    #[test]
+    fn test_compressed_events_pass_the_ingest_apis_checks() {
+        let event: IngestEventPayload = serde_json::from_value(serde_json::json!({
+            "projectId": "p",
+            "eventType": "signup",
+            "timestamp": 1_700_000_000_000_i64,
+            "properties": {"session_id": "s1", "loadgen": true},
+            "context": {"page": {"url": "https://p.loadgen.test/signup"}, "screen": {"width": 390, "height": 844}},
+        }))
+        .unwrap();
+
+        let compressed = compress(event);
+        assert_eq!(compressed.validate(), Ok(()));
+        assert_eq!(compressed.kind.as_deref(), Some("track"));
+        assert_eq!((compressed.sw, compressed.sh), (390, 844));
+        assert_eq!(decode_jwt(&token("p")), Ok(("p".to_string(), None)));
+    }
+}
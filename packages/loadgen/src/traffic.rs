@@ -0,0 +1,225 @@
+//! Simulated visitor sessions.
+//!
+//! A fixed number of sessions are in progress at once, each on one project,
+//! screen size and (for a share of them) signed-in user. Each event goes to
+//! a random session, its type picked from the configured mix: a session
+//! always opens with a pageview, pageviews walk it to another page of a
+//! small site with the previous page as referrer, and other events happen
+//! on the current page. E-commerce events carry a valid cart, so they pass
+//! the ingest API's validation. A session ends after a few to a few dozen
+//! events and is replaced by a new one.
+
+use ingestion::ecommerce;
+use ingestion::models::{EventContext, IngestEventPayload, PageContext, ScreenContext};
+use serde_json::json;
+use std::collections::HashMap;
+
+use crate::config::LoadConfig;
+
+/// Pages of the simulated site
+const PATHS: [&str; 8] = [
+    "/",
+    "/pricing",
+    "/docs",
+    "/docs/getting-started",
+    "/blog",
+    "/blog/launch",
+    "/signup",
+    "/about",
+];
+
+/// Screen sizes of the simulated visitors' devices
+const SCREENS: [(u32, u32); 4] = [(1920, 1080), (1440, 900), (390, 844), (412, 915)];
+
+/// Share of sessions with a signed-in user, in percent
+const SIGNED_IN_PERCENT: u8 = 30;
+
+#[derive(Debug, Clone)]
+struct Session {
+    project_id: String,
+    id: String,
+    anonymous_id: String,
+    user_id: Option<String>,
+    screen: (u32, u32),
+    /// Current page's path, none before the first pageview
+    page: Option<&'static str>,
+    /// Events left before the session ends
+    remaining: u32,
+}
+
+/// Generator of a run's events
+pub struct Traffic {
+    rng: fastrand::Rng,
+    projects: Vec<String>,
+    mix: Vec<(String, u32)>,
+    sessions: Vec<Session>,
+    /// Sessions started so far, numbering the next one
+    started: u64,
+}
+
+impl Traffic {
+    pub fn new(config: &LoadConfig) -> Self {
+        let mut traffic = Self {
+            rng: config.seed.map_or_else(fastrand::Rng::new, fastrand::Rng::with_seed),
+            projects: config.projects.clone(),
+            mix: config.mix.clone(),
+            sessions: Vec::with_capacity(config.sessions),
+            started: 0,
+        };
+        for _ in 0..config.sessions.max(1) {
+            let session = traffic.start_session();
+            traffic.sessions.push(session);
+        }
+        traffic
+    }
+
+    fn start_session(&mut self) -> Session {
+        self.started += 1;
+        let visitor = self.rng.u64(..);
+        Session {
+            project_id: self.projects[self.rng.usize(..self.projects.len())].clone(),
+            id: format!("loadgen-session-{}", self.started),
+            anonymous_id: format!("loadgen-anon-{:016x}", visitor),
+            user_id: (self.rng.u8(..100) < SIGNED_IN_PERCENT).then(|| format!("loadgen-user-{}", visitor % 10_000)),
+            screen: SCREENS[self.rng.usize(..SCREENS.len())],
+            page: None,
+            remaining: self.rng.u32(3..40),
+        }
+    }
+
+    fn pick_type(&mut self) -> String {
+        let total: u32 = self.mix.iter().map(|(_, weight)| weight).sum();
+        let mut pick = self.rng.u32(..total.max(1));
+        for (event_type, weight) in &self.mix {
+            if pick < *weight {
+                return event_type.clone();
+            }
+            pick -= weight;
+        }
+        "pageview".to_string()
+    }
+
+    /// The next event, happening at `timestamp` (epoch milliseconds)
+    pub fn next_event(&mut self, timestamp: i64) -> IngestEventPayload {
+        let index = self.rng.usize(..self.sessions.len());
+        let mut event_type = self.pick_type();
+        if self.sessions[index].page.is_none() {
+            event_type = "pageview".to_string();
+        }
+        let path = PATHS[self.rng.usize(..PATHS.len())];
+        let cart = ecommerce::EVENT_TYPES.contains(&event_type.as_str()).then(|| self.cart());
+
+        let session = &mut self.sessions[index];
+        let host = format!("https://{}.loadgen.test", session.project_id);
+        let url = |path: &str| format!("{}{}", host, path);
+        let mut referrer = None;
+        if event_type == "pageview" {
+            referrer = session.page.replace(path).map(url);
+        }
+        let path = session.page.unwrap_or(path);
+        let mut properties = HashMap::from([
+            ("session_id".to_string(), json!(session.id)),
+            ("loadgen".to_string(), json!(true)),
+        ]);
+        properties.extend(cart.unwrap_or_default());
+
+        let event = IngestEventPayload {
+            project_id: session.project_id.clone(),
+            event_type,
+            timestamp,
+            user_id: session.user_id.clone(),
+            anonymous_id: Some(session.anonymous_id.clone()),
+            properties: Some(properties),
+            context: Some(EventContext {
+                page: Some(PageContext {
+                    url: Some(url(path)),
+                    path: Some(path.to_string()),
+                    referrer,
+                    title: None,
+                }),
+                screen: Some(ScreenContext {
+                    width: Some(session.screen.0),
+                    height: Some(session.screen.1),
+                }),
+                received_at: Some(timestamp),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        session.remaining -= 1;
+        if session.remaining == 0 {
+            self.sessions[index] = self.start_session();
+        }
+        event
+    }
+
+    /// Properties of a valid cart of one to three products
+    fn cart(&mut self) -> HashMap<String, serde_json::Value> {
+        let products: Vec<_> = (0..self.rng.usize(1..=3))
+            .map(|_| {
+                json!({
+                    "sku": format!("sku-{}", self.rng.u32(1..=50)),
+                    "price": f64::from(self.rng.u32(100..10_000)) / 100.0,
+                    "quantity": self.rng.u32(1..=3),
+                })
+            })
+            .collect();
+        HashMap::from([
+            ("products".to_string(), json!(products)),
+            ("currency".to_string(), json!("EUR")),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> LoadConfig {
+        LoadConfig {
+            projects: vec!["a".to_string(), "b".to_string()],
+            sessions: 20,
+            seed: Some(7),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_sessions_open_with_a_pageview_and_walk_the_site() {
+        let mut traffic = Traffic::new(&config());
+        let events: Vec<_> = (0..2_000).map(|i| traffic.next_event(1_700_000_000_000 + i)).collect();
+
+        let mut seen: HashMap<String, Vec<&IngestEventPayload>> = HashMap::new();
+        for event in &events {
+            let session = event.properties.as_ref().unwrap()["session_id"].as_str().unwrap().to_string();
+            seen.entry(session).or_default().push(event);
+        }
+        assert!(seen.len() > 20);
+        for session in seen.values() {
+            assert_eq!(session[0].event_type, "pageview");
+            assert!(session[0].context.as_ref().unwrap().page.as_ref().unwrap().referrer.is_none());
+            assert!(session.iter().all(|event| event.project_id == session[0].project_id));
+        }
+
+        let pageviews = events.iter().filter(|event| event.event_type == "pageview").count();
+        assert!((1_300..1_600).contains(&pageviews), "{} pageviews", pageviews);
+        let order = events.iter().find(|event| event.event_type == "order_completed").unwrap();
+        let mut order = order.clone();
+        let validation = ecommerce::EcommerceConfig { enabled: true };
+        assert_eq!(ecommerce::normalize(&mut order, &validation), Ok(()));
+    }
+
+    #[test]
+    fn test_a_seed_makes_traffic_reproducible() {
+        let types = |seed| {
+            let mut traffic = Traffic::new(&LoadConfig {
+                seed: Some(seed),
+                ..config()
+            });
+            (0..100).map(|i| traffic.next_event(i).event_type).collect::<Vec<_>>()
+        };
+        assert_eq!(types(1), types(1));
+        assert_ne!(types(1), types(2));
+    }
+}
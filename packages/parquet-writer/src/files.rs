@@ -0,0 +1,201 @@
+//! Grouping a batch into partitioned files.
+//!
+//! Rows are buffered per partition, `project_id=…/dt=YYYY-MM-DD/hr=HH`,
+//! taken from the time the ingest API received the event (like Firehose's
+//! arrival-time prefixes), so a late client timestamp never adds a file to
+//! an old partition. A partition's rows become one file, split at
+//! `PARQUET_MAX_ROWS_PER_FILE`. File size is otherwise set by how much a
+//! Lambda batch holds: raise the event source mapping's `BatchSize` and
+//! `MaximumBatchingWindowInSeconds` for fewer, larger files.
+
+use aws_lambda_events::event::kinesis::KinesisEventRecord;
+use chrono::DateTime;
+use ingestion::aggregation;
+use ingestion::models::IngestEventPayload;
+use ingestion::shared::{env_or, env_var};
+use std::collections::BTreeMap;
+
+use crate::schema::EventRow;
+
+/// Configuration for the writer
+#[derive(Debug, Clone)]
+pub struct ParquetWriterConfig {
+    /// Destination bucket
+    pub bucket: String,
+    /// Key prefix for written objects
+    pub prefix: String,
+    /// Most rows per file; larger partitions are split
+    pub max_rows_per_file: usize,
+    /// Most rows per row group within a file
+    pub row_group_rows: usize,
+}
+
+impl Default for ParquetWriterConfig {
+    fn default() -> Self {
+        Self {
+            bucket: String::new(),
+            prefix: "events".to_string(),
+            max_rows_per_file: 500_000,
+            row_group_rows: 100_000,
+        }
+    }
+}
+
+impl ParquetWriterConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            bucket: env_var("PARQUET_BUCKET").unwrap_or_default(),
+            prefix: env_or("PARQUET_PREFIX", defaults.prefix),
+            max_rows_per_file: env_or("PARQUET_MAX_ROWS_PER_FILE", defaults.max_rows_per_file).max(1),
+            row_group_rows: env_or("PARQUET_ROW_GROUP_ROWS", defaults.row_group_rows).max(1),
+        }
+    }
+}
+
+/// A file's place in the bucket
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Partition {
+    pub project_id: String,
+    /// `YYYY-MM-DD`
+    pub dt: String,
+    /// `HH`
+    pub hr: String,
+}
+
+impl Partition {
+    /// The partition of a row, from its `received_at` in UTC whatever the
+    /// project's reporting timezone; its local day is the `local_date`
+    /// column
+    pub fn of(row: &EventRow) -> Self {
+        let time = DateTime::from_timestamp_millis(row.received_at).unwrap_or_default();
+        Self {
+            project_id: row.project_id.clone(),
+            dt: time.format("%Y-%m-%d").to_string(),
+            hr: time.format("%H").to_string(),
+        }
+    }
+
+    /// Hive-style key prefix; the project id is percent-encoded so it can't
+    /// add path segments
+    pub fn path(&self) -> String {
+        format!("{}/dt={}/hr={}", project_path(&self.project_id), self.dt, self.hr)
+    }
+}
+
+/// Key prefix of every partition of a project, e.g. `project_id=p`
+pub fn project_path(project_id: &str) -> String {
+    let project: String = url::form_urlencoded::byte_serialize(project_id.as_bytes()).collect();
+    format!("project_id={}", project)
+}
+
+/// Rows of one file, with the stream records they came from
+#[derive(Debug)]
+pub struct PendingFile {
+    pub partition: Partition,
+    /// Index in the batch of the earliest record with rows here
+    pub first_record: usize,
+    pub first_sequence: String,
+    pub last_sequence: String,
+    pub rows: Vec<EventRow>,
+}
+
+impl PendingFile {
+    /// The object key. It's named after the records it holds, so writing
+    /// the same records again (a retried batch) replaces the file rather
+    /// than adding a copy.
+    pub fn key(&self, prefix: &str) -> String {
+        format!(
+            "{}/{}/{}-{}.parquet",
+            prefix.trim_end_matches('/'),
+            self.partition.path(),
+            self.first_sequence,
+            self.last_sequence
+        )
+    }
+}
+
+/// Rows of one stream record. Events without an id get one derived from
+/// the record's sequence number, so a rewritten file repeats the same ids.
+/// Records that aren't JSON events can't become rows and are skipped.
+pub fn rows(data: &[u8], sequence_number: &str, arrived_at: i64) -> Vec<EventRow> {
+    aggregation::decode(data)
+        .into_iter()
+        .enumerate()
+        .filter_map(|(index, data)| match serde_json::from_slice::<IngestEventPayload>(&data) {
+            Ok(event) => Some(EventRow::from_event(event, format!("{}-{}", sequence_number, index), arrived_at)),
+            Err(e) => {
+                tracing::warn!("Skipping user record {} of {}: {}", index, sequence_number, e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Buffers a batch into files by partition, in partition order
+pub fn plan(records: &[KinesisEventRecord], max_rows_per_file: usize) -> Vec<PendingFile> {
+    let mut partitions: BTreeMap<Partition, Vec<PendingFile>> = BTreeMap::new();
+    for (index, record) in records.iter().enumerate() {
+        let sequence = record.kinesis.sequence_number.as_deref().unwrap_or_default();
+        let arrived_at = record.kinesis.approximate_arrival_timestamp.0.timestamp_millis();
+        for row in rows(&record.kinesis.data.0, sequence, arrived_at) {
+            let partition = Partition::of(&row);
+            let files = partitions.entry(partition.clone()).or_default();
+            if files.last().is_none_or(|file| file.rows.len() >= max_rows_per_file) {
+                files.push(PendingFile {
+                    partition,
+                    first_record: index,
+                    first_sequence: sequence.to_string(),
+                    last_sequence: String::new(),
+                    rows: Vec::new(),
+                });
+            }
+            let file = files.last_mut().expect("file just pushed");
+            file.last_sequence = sequence.to_string();
+            file.rows.push(row);
+        }
+    }
+    partitions.into_values().flatten().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn record(sequence: &str, received_at: i64) -> KinesisEventRecord {
+        let data = json!({
+            "projectId": "p/1",
+            "eventType": "click",
+            "timestamp": 0,
+            "context": {"receivedAt": received_at},
+        });
+        let data = aws_lambda_events::encodings::Base64Data(serde_json::to_vec(&data).unwrap());
+        serde_json::from_value(json!({
+            "kinesis": {"sequenceNumber": sequence, "data": data, "approximateArrivalTimestamp": 1_700_000_000.0},
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_plans_files_per_partition() {
+        // 2023-11-14T22:13:20Z and an hour later
+        let (ten, eleven) = (1_700_000_000_000, 1_700_003_600_000);
+        let records = [record("1", ten), record("2", eleven), record("3", ten), record("4", ten)];
+
+        let files = plan(&records, 2);
+
+        let summary: Vec<_> = files
+            .iter()
+            .map(|file| (file.key("events/"), file.first_record, file.rows.len()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("events/project_id=p%2F1/dt=2023-11-14/hr=22/1-3.parquet".to_string(), 0, 2),
+                ("events/project_id=p%2F1/dt=2023-11-14/hr=22/4-4.parquet".to_string(), 3, 1),
+                ("events/project_id=p%2F1/dt=2023-11-14/hr=23/2-2.parquet".to_string(), 1, 1),
+            ]
+        );
+    }
+}
@@ -0,0 +1,131 @@
+//! The Kinesis batch handler and its checkpointing.
+//!
+//! Every planned file is encoded and written, even after one fails. If any
+//! failed, the earliest record with rows in a failed file is reported as
+//! the batch item failure: every record before it is entirely in written
+//! files, so Lambda checkpoints them and retries from there. Files written
+//! on the retry that hold the same records get the same keys and replace
+//! the earlier copies; a retried batch that splits differently can still
+//! repeat rows under another key, so readers deduplicate on `event_id`.
+
+use aws_lambda_events::event::kinesis::KinesisEvent;
+use aws_lambda_events::event::streams::{KinesisBatchItemFailure, KinesisEventResponse};
+
+use crate::files::{plan, ParquetWriterConfig};
+use crate::schema::encode;
+use crate::store::ObjectStore;
+
+/// Writes a batch, reporting where to resume if a file fails
+pub async fn handle(event: KinesisEvent, store: &dyn ObjectStore, config: &ParquetWriterConfig) -> KinesisEventResponse {
+    let mut resume_at: Option<usize> = None;
+    let mut written = 0;
+    for file in plan(&event.records, config.max_rows_per_file) {
+        let key = file.key(&config.prefix);
+        let result = match encode(&file.rows, config.row_group_rows) {
+            Ok(body) => store.put(&key, body).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(()) => written += file.rows.len(),
+            Err(e) => {
+                tracing::error!("Failed to write {} rows to {}: {}", file.rows.len(), key, e);
+                resume_at = Some(resume_at.map_or(file.first_record, |at| at.min(file.first_record)));
+            }
+        }
+    }
+
+    if let Some(index) = resume_at {
+        let sequence = event.records[index].kinesis.sequence_number.clone();
+        return KinesisEventResponse {
+            batch_item_failures: vec![KinesisBatchItemFailure { item_identifier: sequence }],
+        };
+    }
+    tracing::info!("Wrote {} rows from {} records", written, event.records.len());
+    KinesisEventResponse {
+        batch_item_failures: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use lambda_runtime::Error;
+    use serde_json::json;
+    use std::sync::Mutex;
+
+    /// Records writes, failing keys that contain `fail`
+    #[derive(Default)]
+    struct FakeStore {
+        fail: Option<&'static str>,
+        keys: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl ObjectStore for FakeStore {
+        async fn put(&self, key: &str, _body: Vec<u8>) -> Result<(), Error> {
+            if self.fail.is_some_and(|fail| key.contains(fail)) {
+                return Err("SlowDown".into());
+            }
+            self.keys.lock().unwrap().push(key.to_string());
+            Ok(())
+        }
+    }
+
+    fn batch(records: &[(&str, &str)]) -> KinesisEvent {
+        let records: Vec<_> = records
+            .iter()
+            .enumerate()
+            .map(|(index, (project, data))| {
+                let data = data.replace("{project}", project);
+                let data = aws_lambda_events::encodings::Base64Data(data.into_bytes());
+                json!({
+                    "kinesis": {
+                        "sequenceNumber": index.to_string(),
+                        "data": data,
+                        "approximateArrivalTimestamp": 1_700_000_000.0,
+                    },
+                })
+            })
+            .collect();
+        serde_json::from_value(json!({ "Records": records })).unwrap()
+    }
+
+    const EVENT: &str = r#"{"projectId":"{project}","eventType":"click","timestamp":1}"#;
+
+    #[tokio::test]
+    async fn test_writes_a_file_per_partition() {
+        let store = FakeStore::default();
+        let event = batch(&[("a", EVENT), ("b", EVENT), ("a", "garbage"), ("a", EVENT)]);
+
+        let response = handle(event, &store, &ParquetWriterConfig::default()).await;
+
+        assert!(response.batch_item_failures.is_empty());
+        assert_eq!(
+            *store.keys.lock().unwrap(),
+            [
+                "events/project_id=a/dt=2023-11-14/hr=22/0-3.parquet",
+                "events/project_id=b/dt=2023-11-14/hr=22/1-1.parquet",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_file_resumes_from_its_earliest_record() {
+        let store = FakeStore {
+            fail: Some("project_id=b"),
+            ..Default::default()
+        };
+        let event = batch(&[("a", EVENT), ("a", EVENT), ("b", EVENT), ("c", EVENT), ("b", EVENT)]);
+
+        let response = handle(event, &store, &ParquetWriterConfig::default()).await;
+
+        assert_eq!(
+            response.batch_item_failures,
+            [KinesisBatchItemFailure {
+                item_identifier: Some("2".to_string()),
+            }]
+        );
+        assert_eq!(store.keys.lock().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,13 @@
+//! Kinesis → S3 Parquet consumer.
+//!
+//! Consumes the ingest stream in Lambda batches and writes partitioned
+//! Parquet files to S3, as an alternative to Firehose's format conversion
+//! that keeps the schema and file sizing in our hands. [`files`] groups a
+//! batch by partition, [`schema`] defines the columns and how they evolve,
+//! and [`handler`] writes the files and reports where to resume when one
+//! fails. The event source mapping must enable `ReportBatchItemFailures`.
+
+pub mod files;
+pub mod handler;
+pub mod schema;
+pub mod store;
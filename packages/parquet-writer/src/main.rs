@@ -0,0 +1,33 @@
+use lambda_runtime::{run, service_fn, Error, LambdaEvent};
+use std::sync::Arc;
+
+use aws_lambda_events::event::kinesis::KinesisEvent;
+use aws_sdk_s3::Client as S3Client;
+use parquet_writer::files::ParquetWriterConfig;
+use parquet_writer::handler::handle;
+use parquet_writer::store::S3ObjectStore;
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    // Initialize tracing
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"))
+        )
+        .json()
+        .init();
+
+    let config = Arc::new(ParquetWriterConfig::from_env());
+    if config.bucket.is_empty() {
+        return Err("PARQUET_BUCKET environment variable not set".into());
+    }
+    let aws = aws_config::load_from_env().await;
+    let store = Arc::new(S3ObjectStore::new(S3Client::new(&aws), config.bucket.clone()));
+
+    run(service_fn(move |event: LambdaEvent<KinesisEvent>| {
+        let (store, config) = (store.clone(), config.clone());
+        async move { Ok::<_, Error>(handle(event.payload, store.as_ref(), &config).await) }
+    }))
+    .await
+}
@@ -0,0 +1,221 @@
+//! The Parquet file schema and its evolution.
+//!
+//! The schema is spelled out here rather than inferred from the events, so
+//! every file of a version has exactly the same columns whatever the batch
+//! happened to contain. It evolves by appending nullable columns and
+//! bumping [`SCHEMA_VERSION`]; columns are never renamed, retyped or
+//! removed, so a table over all files (Athena, Glue, Spark) reads old files
+//! with the new columns as null. Each file records its version in the
+//! `schema_version` key-value metadata. Anything without a column of its
+//! own is kept in the `properties` and `context` JSON strings.
+
+use arrow_array::{ArrayRef, BooleanArray, Int32Array, RecordBatch, StringArray, TimestampMillisecondArray};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use ingestion::models::IngestEventPayload;
+use lambda_runtime::Error;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use parquet::format::KeyValue;
+use std::sync::Arc;
+
+/// Version of [`schema`]; bump it whenever a column is appended
+pub const SCHEMA_VERSION: u32 = 3;
+
+/// The columns of a file, in order
+pub fn schema() -> SchemaRef {
+    let timestamp = DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into()));
+    Arc::new(Schema::new(vec![
+        Field::new("event_id", DataType::Utf8, false),
+        Field::new("project_id", DataType::Utf8, false),
+        Field::new("event_type", DataType::Utf8, false),
+        Field::new("timestamp", timestamp.clone(), false),
+        Field::new("received_at", timestamp, false),
+        Field::new("user_id", DataType::Utf8, true),
+        Field::new("anonymous_id", DataType::Utf8, true),
+        Field::new("session_id", DataType::Utf8, true),
+        Field::new("page_url", DataType::Utf8, true),
+        Field::new("page_path", DataType::Utf8, true),
+        Field::new("page_referrer", DataType::Utf8, true),
+        Field::new("country", DataType::Utf8, true),
+        Field::new("is_bot", DataType::Boolean, true),
+        Field::new("properties", DataType::Utf8, true),
+        Field::new("context", DataType::Utf8, true),
+        // Version 2
+        Field::new("region", DataType::Utf8, true),
+        // Version 3
+        Field::new("local_date", DataType::Utf8, true),
+        Field::new("local_hour", DataType::Int32, true),
+    ]))
+}
+
+/// One row of a file
+#[derive(Debug, Clone, PartialEq)]
+pub struct EventRow {
+    pub event_id: String,
+    pub project_id: String,
+    pub event_type: String,
+    /// Milliseconds since the epoch
+    pub timestamp: i64,
+    /// Milliseconds since the epoch; the arrival time when the ingest API
+    /// didn't stamp one
+    pub received_at: i64,
+    pub user_id: Option<String>,
+    pub anonymous_id: Option<String>,
+    pub session_id: Option<String>,
+    pub page_url: Option<String>,
+    pub page_path: Option<String>,
+    pub page_referrer: Option<String>,
+    pub country: Option<String>,
+    pub is_bot: Option<bool>,
+    /// `properties` as a JSON string
+    pub properties: Option<String>,
+    /// `context` as a JSON string
+    pub context: Option<String>,
+    /// Region that ingested the event; null before version 2
+    pub region: Option<String>,
+    /// `YYYY-MM-DD` in the project's reporting timezone, when ingestion
+    /// stamped one; null before version 3
+    pub local_date: Option<String>,
+    /// Hour of `local_date`, 0 to 23
+    pub local_hour: Option<i32>,
+}
+
+impl EventRow {
+    /// The row for an event; `fallback_id` is used when the event has
+    /// neither an `eventId` nor a `messageId`, and `arrived_at` when it has
+    /// no `receivedAt`
+    pub fn from_event(event: IngestEventPayload, fallback_id: String, arrived_at: i64) -> Self {
+        let context = event.context.as_ref();
+        let page = context.and_then(|c| c.page.as_ref());
+        let session_id = event
+            .properties
+            .as_ref()
+            .and_then(|properties| properties.get("session_id"))
+            .and_then(|session| session.as_str())
+            .map(String::from);
+
+        Self {
+            event_id: event.event_id.or(event.message_id).unwrap_or(fallback_id),
+            project_id: event.project_id,
+            event_type: event.event_type,
+            timestamp: event.timestamp,
+            received_at: context.and_then(|c| c.received_at).unwrap_or(arrived_at),
+            user_id: event.user_id,
+            anonymous_id: event.anonymous_id,
+            session_id,
+            page_url: page.and_then(|p| p.url.clone()),
+            page_path: page.and_then(|p| p.path.clone()),
+            page_referrer: page.and_then(|p| p.referrer.clone()),
+            country: context.and_then(|c| c.geo.as_ref()).and_then(|g| g.country.clone()),
+            is_bot: context.and_then(|c| c.is_bot),
+            properties: event.properties.and_then(|properties| serde_json::to_string(&properties).ok()),
+            context: event.context.and_then(|context| serde_json::to_string(&context).ok()),
+            region: event.region,
+            local_date: event.local_date,
+            local_hour: event.local_hour.map(i32::from),
+        }
+    }
+}
+
+fn strings<'a>(rows: &'a [EventRow], column: impl Fn(&'a EventRow) -> Option<&'a str>) -> ArrayRef {
+    Arc::new(StringArray::from_iter(rows.iter().map(column)))
+}
+
+fn timestamps(rows: &[EventRow], column: impl Fn(&EventRow) -> i64) -> ArrayRef {
+    Arc::new(TimestampMillisecondArray::from_iter_values(rows.iter().map(column)).with_timezone("UTC"))
+}
+
+/// Encodes rows as a Snappy-compressed Parquet file with row groups of at
+/// most `row_group_rows`
+pub fn encode(rows: &[EventRow], row_group_rows: usize) -> Result<Vec<u8>, Error> {
+    let schema = schema();
+    let columns: Vec<ArrayRef> = vec![
+        strings(rows, |r| Some(&r.event_id)),
+        strings(rows, |r| Some(&r.project_id)),
+        strings(rows, |r| Some(&r.event_type)),
+        timestamps(rows, |r| r.timestamp),
+        timestamps(rows, |r| r.received_at),
+        strings(rows, |r| r.user_id.as_deref()),
+        strings(rows, |r| r.anonymous_id.as_deref()),
+        strings(rows, |r| r.session_id.as_deref()),
+        strings(rows, |r| r.page_url.as_deref()),
+        strings(rows, |r| r.page_path.as_deref()),
+        strings(rows, |r| r.page_referrer.as_deref()),
+        strings(rows, |r| r.country.as_deref()),
+        Arc::new(BooleanArray::from_iter(rows.iter().map(|r| r.is_bot))),
+        strings(rows, |r| r.properties.as_deref()),
+        strings(rows, |r| r.context.as_deref()),
+        strings(rows, |r| r.region.as_deref()),
+        strings(rows, |r| r.local_date.as_deref()),
+        Arc::new(Int32Array::from_iter(rows.iter().map(|r| r.local_hour))),
+    ];
+    let batch = RecordBatch::try_new(schema.clone(), columns)?;
+
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .set_max_row_group_size(row_group_rows.max(1))
+        .set_key_value_metadata(Some(vec![KeyValue::new(
+            "schema_version".to_string(),
+            SCHEMA_VERSION.to_string(),
+        )]))
+        .build();
+    let mut buffer = Vec::new();
+    let mut writer = ArrowWriter::try_new(&mut buffer, schema, Some(properties))?;
+    writer.write(&batch)?;
+    writer.close()?;
+
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use serde_json::json;
+
+    fn row(event: serde_json::Value) -> EventRow {
+        EventRow::from_event(serde_json::from_value(event).unwrap(), "seq-0".to_string(), 1_700_000_009_000)
+    }
+
+    #[test]
+    fn test_maps_event_to_row() {
+        let row = row(json!({
+            "projectId": "p",
+            "eventType": "pageview",
+            "timestamp": 1_700_000_000_000_i64,
+            "messageId": "msg-1",
+            "properties": {"session_id": "s1"},
+            "context": {"page": {"url": "https://a.com/x", "path": "/x"}, "geo": {"country": "DE"}, "isBot": false},
+            "region": "eu-central-1",
+            "localDate": "2023-11-15",
+            "localHour": 7,
+        }));
+        assert_eq!(row.event_id, "msg-1");
+        assert_eq!(row.received_at, 1_700_000_009_000);
+        assert_eq!(row.session_id.as_deref(), Some("s1"));
+        assert_eq!(row.page_path.as_deref(), Some("/x"));
+        assert_eq!(row.country.as_deref(), Some("DE"));
+        assert_eq!(row.is_bot, Some(false));
+        assert_eq!(row.region.as_deref(), Some("eu-central-1"));
+        assert_eq!((row.local_date.as_deref(), row.local_hour), (Some("2023-11-15"), Some(7)));
+        assert!(row.context.unwrap().contains("https://a.com/x"));
+    }
+
+    #[test]
+    fn test_encodes_with_schema_and_version() {
+        let rows: Vec<_> = (0..5)
+            .map(|i| row(json!({"projectId": "p", "eventType": "click", "timestamp": i, "userId": "u"})))
+            .collect();
+        let file = encode(&rows, 2).unwrap();
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(bytes::Bytes::from(file)).unwrap();
+        let metadata = reader.metadata().clone();
+        assert_eq!(metadata.num_row_groups(), 3);
+        let version = metadata.file_metadata().key_value_metadata().unwrap();
+        assert!(version.iter().any(|kv| kv.key == "schema_version" && kv.value.as_deref() == Some("3")));
+        assert_eq!(reader.schema().fields(), schema().fields());
+        let read: usize = reader.build().unwrap().map(|batch| batch.unwrap().num_rows()).sum();
+        assert_eq!(read, 5);
+    }
+}
@@ -0,0 +1,126 @@
+//! What answers stats, funnel, retention and realtime queries.
+//!
+//! Each backend answers the queries it can and declines the rest, and
+//! [`Backends`] asks them in order: the DynamoDB aggregates first, as
+//! they're cheap to read, then ClickHouse for what they don't count
+//! (referrers, unique visitors, funnels, retention) or when they aren't
+//! deployed. Realtime presence has a table of its own.
+
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use lambda_http::Error;
+use serde::Serialize;
+
+use crate::funnel::FunnelQuery;
+use crate::params::StatsQuery;
+use crate::realtime::{Realtime, RealtimeQuery};
+use crate::retention::RetentionQuery;
+
+/// A series point: a bucket's label and its count
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Point {
+    pub bucket: String,
+    pub count: u64,
+}
+
+/// A ranking entry
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Ranked {
+    pub key: String,
+    pub count: u64,
+}
+
+/// A query's answer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Stats {
+    Series(Vec<Point>),
+    Ranking(Vec<Ranked>),
+    Count(u64),
+}
+
+/// Something that can answer stats queries
+#[async_trait]
+pub trait StatsBackend: Send + Sync {
+    /// The answer for a project, or `None` if this backend doesn't have
+    /// the metric
+    async fn query(&self, project_id: &str, query: &StatsQuery) -> Result<Option<Stats>, Error>;
+
+    /// How many visitors got exactly as far as each step of a funnel, as
+    /// `(level, visitors)` with level 1 the first step, or `None` if this
+    /// backend can't compute funnels
+    async fn funnel(&self, _project_id: &str, _query: &FunnelQuery) -> Result<Option<Vec<(usize, u64)>>, Error> {
+        Ok(None)
+    }
+
+    /// How many of each cohort's visitors were active some days after it,
+    /// as `(cohort, day, visitors)`, or `None` if this backend can't
+    /// compute retention
+    async fn retention(
+        &self,
+        _project_id: &str,
+        _query: &RetentionQuery,
+    ) -> Result<Option<Vec<(NaiveDate, u32, u64)>>, Error> {
+        Ok(None)
+    }
+
+    /// Who's active on a project, or `None` if this backend doesn't know
+    async fn realtime(&self, _project_id: &str, _query: &RealtimeQuery) -> Result<Option<Realtime>, Error> {
+        Ok(None)
+    }
+}
+
+/// Backends asked in order until one answers
+#[derive(Default)]
+pub struct Backends(pub Vec<Box<dyn StatsBackend>>);
+
+#[async_trait]
+impl StatsBackend for Backends {
+    async fn query(&self, project_id: &str, query: &StatsQuery) -> Result<Option<Stats>, Error> {
+        for backend in &self.0 {
+            if let Some(stats) = backend.query(project_id, query).await? {
+                return Ok(Some(stats));
+            }
+        }
+        Ok(None)
+    }
+
+    async fn funnel(&self, project_id: &str, query: &FunnelQuery) -> Result<Option<Vec<(usize, u64)>>, Error> {
+        for backend in &self.0 {
+            if let Some(reached) = backend.funnel(project_id, query).await? {
+                return Ok(Some(reached));
+            }
+        }
+        Ok(None)
+    }
+
+    async fn retention(
+        &self,
+        project_id: &str,
+        query: &RetentionQuery,
+    ) -> Result<Option<Vec<(NaiveDate, u32, u64)>>, Error> {
+        for backend in &self.0 {
+            if let Some(active) = backend.retention(project_id, query).await? {
+                return Ok(Some(active));
+            }
+        }
+        Ok(None)
+    }
+
+    async fn realtime(&self, project_id: &str, query: &RealtimeQuery) -> Result<Option<Realtime>, Error> {
+        for backend in &self.0 {
+            if let Some(realtime) = backend.realtime(project_id, query).await? {
+                return Ok(Some(realtime));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Sorts a ranking by count, then key, and keeps the first `limit`
+pub fn top(counts: impl IntoIterator<Item = (String, u64)>, limit: usize) -> Vec<Ranked> {
+    let mut ranking: Vec<Ranked> = counts.into_iter().map(|(key, count)| Ranked { key, count }).collect();
+    ranking.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.key.cmp(&b.key)));
+    ranking.truncate(limit);
+    ranking
+}
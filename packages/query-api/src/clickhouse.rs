@@ -0,0 +1,316 @@
+//! Stats, funnels and retention from the ClickHouse `events` table.
+//!
+//! Every metric is one `SELECT` over the HTTP interface, with the project,
+//! range and limit passed as query parameters rather than spliced into
+//! the SQL; funnels use `windowFunnel` over each visitor's events, and
+//! retention joins each visitor's first day to the days they were
+//! active. The connection settings are the writer's (`CLICKHOUSE_URL`,
+//! `CLICKHOUSE_DATABASE`, `CLICKHOUSE_TABLE`, `CLICKHOUSE_USER`,
+//! `CLICKHOUSE_PASSWORD`); give the query API a read-only user.
+
+use aggregator::counts::Granularity;
+use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::NaiveDate;
+use clickhouse_writer::clickhouse::ClickHouseConfig;
+use http_body_util::{BodyExt, Full};
+use hyper_rustls::HttpsConnector;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use lambda_http::Error;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::backend::{Point, Ranked, Stats, StatsBackend};
+use crate::funnel::FunnelQuery;
+use crate::params::{Metric, StatsQuery};
+use crate::retention::RetentionQuery;
+
+/// One result row; which columns are set depends on the metric
+#[derive(Debug, Deserialize)]
+struct Row {
+    key: Option<String>,
+    /// Bucket start, in seconds since the epoch
+    bucket: Option<i64>,
+    count: u64,
+}
+
+/// One funnel row: visitors who got exactly as far as `level`
+#[derive(Debug, Deserialize)]
+struct FunnelRow {
+    level: usize,
+    count: u64,
+}
+
+/// One retention row: a cohort's visitors active `day` days after it
+#[derive(Debug, Deserialize)]
+struct RetentionRow {
+    cohort: NaiveDate,
+    day: u32,
+    count: u64,
+}
+
+/// Filter on the project and range parameters
+const RANGE: &str = "project_id = {project:String} \
+    AND timestamp >= fromUnixTimestamp64Milli({from:Int64}) \
+    AND timestamp < fromUnixTimestamp64Milli({to:Int64})";
+
+/// The SQL for a query against `table`
+pub fn sql(query: &StatsQuery, table: &str) -> String {
+    match query.metric {
+        Metric::Pageviews => {
+            let start = match query.granularity {
+                Granularity::Minute => "toStartOfMinute",
+                Granularity::Hour => "toStartOfHour",
+                Granularity::Day => "toStartOfDay",
+            };
+            format!(
+                "SELECT toUnixTimestamp({}(timestamp)) AS bucket, count() AS count FROM {} \
+                 WHERE {} AND event_type = 'pageview' GROUP BY bucket ORDER BY bucket",
+                start, table, RANGE
+            )
+        }
+        Metric::TopPages => format!(
+            "SELECT page_path AS key, count() AS count FROM {} \
+             WHERE {} AND event_type = 'pageview' AND page_path != '' \
+             GROUP BY key ORDER BY count DESC, key LIMIT {{limit:UInt32}}",
+            table, RANGE
+        ),
+        Metric::TopReferrers => format!(
+            "SELECT domain(page_referrer) AS key, count() AS count FROM {} \
+             WHERE {} AND event_type = 'pageview' AND page_referrer != '' \
+             GROUP BY key ORDER BY count DESC, key LIMIT {{limit:UInt32}}",
+            table, RANGE
+        ),
+        Metric::UniqueVisitors => format!("SELECT uniq(anonymous_id) AS count FROM {} WHERE {}", table, RANGE),
+    }
+}
+
+/// The SQL for a funnel against `table`; step `i` is parameter `step{i}`
+pub fn funnel_sql(query: &FunnelQuery, table: &str) -> String {
+    let conditions: Vec<String> = (0..query.steps.len())
+        .map(|index| format!("event_type = {{step{}:String}}", index))
+        .collect();
+    format!(
+        "SELECT level, count() AS count FROM (\
+         SELECT anonymous_id, windowFunnel({{window:UInt64}})(toDateTime(timestamp), {}) AS level \
+         FROM {} WHERE {} AND anonymous_id != '' GROUP BY anonymous_id\
+         ) WHERE level > 0 GROUP BY level ORDER BY level",
+        conditions.join(", "),
+        table,
+        RANGE
+    )
+}
+
+/// The SQL for retention against `table`. Cohorts need each visitor's
+/// first event ever, so that side reads the project's whole history.
+pub fn retention_sql(table: &str) -> String {
+    format!(
+        "SELECT toString(cohort) AS cohort, toUInt32(dateDiff('day', cohort, day)) AS day, \
+         uniqExact(anonymous_id) AS count FROM (\
+         SELECT DISTINCT anonymous_id, toDate(timestamp) AS day FROM {table} \
+         WHERE {range} AND anonymous_id != ''\
+         ) AS activity INNER JOIN (\
+         SELECT anonymous_id, toDate(min(timestamp)) AS cohort FROM {table} \
+         WHERE project_id = {{project:String}} AND anonymous_id != '' GROUP BY anonymous_id \
+         HAVING cohort >= toDate(fromUnixTimestamp64Milli({{from:Int64}})) AND cohort <= {{last:Date}}\
+         ) AS cohorts USING anonymous_id \
+         WHERE day >= cohort AND dateDiff('day', cohort, day) <= {{days:UInt32}} \
+         GROUP BY cohort, day ORDER BY cohort, day",
+        table = table,
+        range = RANGE
+    )
+}
+
+/// Stats answered by ClickHouse over HTTP
+pub struct ClickHouseStats {
+    http: Client<HttpsConnector<HttpConnector>, Full<Bytes>>,
+    config: ClickHouseConfig,
+}
+
+impl ClickHouseStats {
+    pub fn new(config: ClickHouseConfig) -> Result<Self, Error> {
+        let connector = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_native_roots()?
+            .https_or_http()
+            .enable_http1()
+            .build();
+        Ok(Self {
+            http: Client::builder(TokioExecutor::new()).build(connector),
+            config,
+        })
+    }
+
+    /// The query URL with a project's parameters and range (in epoch
+    /// milliseconds), plus any others
+    pub fn url(&self, project_id: &str, range: (i64, i64), extra: &[(String, String)]) -> String {
+        let mut params = url::form_urlencoded::Serializer::new(String::new());
+        params.append_pair("default_format", "JSONEachRow");
+        params.append_pair("output_format_json_quote_64bit_integers", "0");
+        params.append_pair("readonly", "2");
+        params.append_pair("param_project", project_id);
+        params.append_pair("param_from", &range.0.to_string());
+        params.append_pair("param_to", &range.1.to_string());
+        for (name, value) in extra {
+            params.append_pair(&format!("param_{}", name), value);
+        }
+        format!("{}/?{}", self.config.url.trim_end_matches('/'), params.finish())
+    }
+
+    fn table(&self) -> String {
+        format!("`{}`.`{}`", self.config.database, self.config.table)
+    }
+
+    async fn rows<T: DeserializeOwned>(&self, url: String, sql: String) -> Result<Vec<T>, Error> {
+        let request = http::Request::post(url)
+            .header("x-clickhouse-user", &self.config.user)
+            .header("x-clickhouse-key", &self.config.password)
+            .body(Full::new(Bytes::from(sql)))?;
+        let response = self.http.request(request).await?;
+        let status = response.status();
+        let body = response.into_body().collect().await?.to_bytes();
+        if !status.is_success() {
+            return Err(format!("ClickHouse query failed with {}: {}", status, String::from_utf8_lossy(&body)).into());
+        }
+        body.split(|&byte| byte == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| Ok(serde_json::from_slice(line)?))
+            .collect()
+    }
+}
+
+/// The answer for a metric from its rows. Series get every bucket of the
+/// range, with zeros where ClickHouse had no row.
+fn stats(query: &StatsQuery, rows: Vec<Row>) -> Stats {
+    match query.metric {
+        Metric::Pageviews => {
+            let counts: HashMap<i64, u64> = rows
+                .into_iter()
+                .filter_map(|row| Some((row.bucket?, row.count)))
+                .collect();
+            let points = query
+                .buckets()
+                .into_iter()
+                .map(|start| Point {
+                    bucket: query.granularity.bucket(start),
+                    count: counts.get(&start.timestamp()).copied().unwrap_or_default(),
+                })
+                .collect();
+            Stats::Series(points)
+        }
+        Metric::TopPages | Metric::TopReferrers => Stats::Ranking(
+            rows.into_iter()
+                .map(|row| Ranked {
+                    key: row.key.unwrap_or_default(),
+                    count: row.count,
+                })
+                .collect(),
+        ),
+        Metric::UniqueVisitors => Stats::Count(rows.first().map_or(0, |row| row.count)),
+    }
+}
+
+#[async_trait]
+impl StatsBackend for ClickHouseStats {
+    async fn query(&self, project_id: &str, query: &StatsQuery) -> Result<Option<Stats>, Error> {
+        let range = (query.from.timestamp_millis(), query.to.timestamp_millis());
+        let url = self.url(project_id, range, &[("limit".to_string(), query.limit.to_string())]);
+        let rows = self.rows(url, sql(query, &self.table())).await?;
+        Ok(Some(stats(query, rows)))
+    }
+
+    async fn funnel(&self, project_id: &str, query: &FunnelQuery) -> Result<Option<Vec<(usize, u64)>>, Error> {
+        let range = (query.from.timestamp_millis(), query.to.timestamp_millis());
+        let mut params = vec![("window".to_string(), query.window_secs.to_string())];
+        params.extend(query.steps.iter().enumerate().map(|(i, step)| (format!("step{}", i), step.clone())));
+        let url = self.url(project_id, range, &params);
+        let rows: Vec<FunnelRow> = self.rows(url, funnel_sql(query, &self.table())).await?;
+        Ok(Some(rows.into_iter().map(|row| (row.level, row.count)).collect()))
+    }
+
+    async fn retention(
+        &self,
+        project_id: &str,
+        query: &RetentionQuery,
+    ) -> Result<Option<Vec<(NaiveDate, u32, u64)>>, Error> {
+        let params = [
+            ("last".to_string(), query.to.to_string()),
+            ("days".to_string(), query.days.to_string()),
+        ];
+        let url = self.url(project_id, query.activity_range(), &params);
+        let rows: Vec<RetentionRow> = self.rows(url, retention_sql(&self.table())).await?;
+        Ok(Some(rows.into_iter().map(|row| (row.cohort, row.day, row.count)).collect()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, Utc};
+
+    fn query(metric: Metric) -> StatsQuery {
+        let at = |s: &str| DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc);
+        StatsQuery {
+            metric,
+            from: at("2024-05-01T10:00:00Z"),
+            to: at("2024-05-01T13:00:00Z"),
+            granularity: Granularity::Hour,
+            limit: 5,
+        }
+    }
+
+    #[test]
+    fn test_sql_takes_values_as_parameters() {
+        let top = sql(&query(Metric::TopReferrers), "`default`.`events`");
+        assert!(top.contains("domain(page_referrer) AS key"));
+        assert!(top.contains("project_id = {project:String}"));
+        assert!(top.ends_with("LIMIT {limit:UInt32}"));
+
+        let client = ClickHouseStats::new(ClickHouseConfig::default()).unwrap();
+        let url = client.url("p'; DROP", (1_714_557_600_000, 0), &[("limit".to_string(), "5".to_string())]);
+        assert!(url.contains("param_project=p%27%3B+DROP"));
+        assert!(url.contains("param_from=1714557600000"));
+        assert!(url.contains("param_limit=5"));
+    }
+
+    #[test]
+    fn test_funnel_sql_has_a_condition_per_step() {
+        let query = FunnelQuery {
+            steps: vec!["view".to_string(), "signup".to_string(), "purchase".to_string()],
+            window_secs: 3600,
+            from: DateTime::from_timestamp(0, 0).unwrap(),
+            to: DateTime::from_timestamp(1, 0).unwrap(),
+        };
+        let sql = funnel_sql(&query, "`default`.`events`");
+        assert!(sql.contains(
+            "windowFunnel({window:UInt64})(toDateTime(timestamp), event_type = {step0:String}, \
+             event_type = {step1:String}, event_type = {step2:String})"
+        ));
+    }
+
+    #[test]
+    fn test_retention_rows_parse() {
+        let sql = retention_sql("`default`.`events`");
+        assert!(sql.contains("cohort <= {last:Date}"));
+        assert!(sql.contains("project_id = {project:String}"));
+
+        let row: RetentionRow = serde_json::from_str(r#"{"cohort":"2024-05-01","day":3,"count":12}"#).unwrap();
+        assert_eq!((row.cohort.to_string(), row.day, row.count), ("2024-05-01".to_string(), 3, 12));
+    }
+
+    #[test]
+    fn test_series_fill_missing_buckets() {
+        let rows = vec![Row {
+            key: None,
+            bucket: Some(1_714_561_200),
+            count: 7,
+        }];
+        let Stats::Series(points) = stats(&query(Metric::Pageviews), rows) else {
+            panic!("expected a series");
+        };
+        let counts: Vec<_> = points.iter().map(|p| (p.bucket.as_str(), p.count)).collect();
+        assert_eq!(counts, [("2024-05-01T10", 0), ("2024-05-01T11", 7), ("2024-05-01T12", 0)]);
+    }
+}
@@ -0,0 +1,218 @@
+//! Stats from the real-time aggregates in DynamoDB.
+//!
+//! Reads the counters `packages/aggregator` keeps per project and minute or
+//! hour bucket: `pageviews` from each bucket's `totals` item, and top pages
+//! by summing the `page#{path}` items of every bucket in the range. Only
+//! those two metrics are counted there. Buckets are read a few at a time.
+//!
+//! Realtime presence is read from its own table (`REALTIME_TABLE`): one
+//! `Query` on the project's partition for the minutes of the window.
+
+use aggregator::counts::{BucketKey, Granularity};
+use aggregator::realtime;
+use async_trait::async_trait;
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use lambda_http::Error;
+use std::collections::HashMap;
+
+use crate::backend::{top, Point, Stats, StatsBackend};
+use crate::params::{Metric, StatsQuery};
+use crate::realtime::{summarize, Realtime, RealtimeQuery, Sighting};
+
+/// Buckets read at once
+const CONCURRENT_READS: usize = 8;
+
+/// The aggregates table
+pub struct DynamoAggregates {
+    client: DynamoClient,
+    table_name: String,
+}
+
+fn count(item: &HashMap<String, AttributeValue>, name: &str) -> u64 {
+    item.get(name)
+        .and_then(|value| value.as_n().ok())
+        .and_then(|value| value.parse().ok())
+        .unwrap_or_default()
+}
+
+/// Keys of the buckets a query covers, in order
+pub fn bucket_keys(project_id: &str, query: &StatsQuery) -> Vec<BucketKey> {
+    query
+        .buckets()
+        .into_iter()
+        .map(|start| BucketKey {
+            project_id: project_id.to_string(),
+            granularity: query.granularity,
+            bucket: query.granularity.bucket(start),
+        })
+        .collect()
+}
+
+impl DynamoAggregates {
+    pub fn new(client: DynamoClient, table_name: String) -> Self {
+        Self { client, table_name }
+    }
+
+    async fn pageviews(&self, key: BucketKey) -> Result<Point, Error> {
+        let output = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("pk", AttributeValue::S(key.partition_key()))
+            .key("sk", AttributeValue::S("totals".to_string()))
+            .projection_expression("pageviews")
+            .send()
+            .await?;
+        Ok(Point {
+            count: output.item().map_or(0, |item| count(item, "pageviews")),
+            bucket: key.bucket,
+        })
+    }
+
+    async fn page_views(&self, key: BucketKey) -> Result<Vec<(String, u64)>, Error> {
+        let mut views = Vec::new();
+        let mut start_key = None;
+        loop {
+            let output = self
+                .client
+                .query()
+                .table_name(&self.table_name)
+                .key_condition_expression("pk = :pk AND begins_with(sk, :page)")
+                .expression_attribute_values(":pk", AttributeValue::S(key.partition_key()))
+                .expression_attribute_values(":page", AttributeValue::S("page#".to_string()))
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await?;
+            views.extend(output.items().iter().filter_map(|item| {
+                let path = item.get("sk")?.as_s().ok()?.strip_prefix("page#")?;
+                Some((path.to_string(), count(item, "views")))
+            }));
+            start_key = output.last_evaluated_key;
+            if start_key.is_none() {
+                return Ok(views);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl StatsBackend for DynamoAggregates {
+    async fn query(&self, project_id: &str, query: &StatsQuery) -> Result<Option<Stats>, Error> {
+        let keys = bucket_keys(project_id, query);
+        match query.metric {
+            Metric::Pageviews => {
+                let points = stream::iter(keys)
+                    .map(|key| self.pageviews(key))
+                    .buffered(CONCURRENT_READS)
+                    .try_collect()
+                    .await?;
+                Ok(Some(Stats::Series(points)))
+            }
+            Metric::TopPages => {
+                let mut pages: HashMap<String, u64> = HashMap::new();
+                let mut buckets = stream::iter(keys)
+                    .map(|key| self.page_views(key))
+                    .buffer_unordered(CONCURRENT_READS);
+                while let Some(views) = buckets.try_next().await? {
+                    for (path, views) in views {
+                        *pages.entry(path).or_default() += views;
+                    }
+                }
+                Ok(Some(Stats::Ranking(top(pages, query.limit))))
+            }
+            Metric::TopReferrers | Metric::UniqueVisitors => Ok(None),
+        }
+    }
+}
+
+/// The presence table
+pub struct DynamoPresence {
+    client: DynamoClient,
+    table_name: String,
+}
+
+impl DynamoPresence {
+    pub fn new(client: DynamoClient, table_name: String) -> Self {
+        Self { client, table_name }
+    }
+}
+
+/// A presence item as a sighting, if it has the attributes one needs
+fn sighting(item: &HashMap<String, AttributeValue>) -> Option<Sighting> {
+    let sort_key = item.get("sk")?.as_s().ok()?;
+    let (_, visitor) = sort_key.split_once('#')?;
+    Some(Sighting {
+        visitor: visitor.to_string(),
+        last_seen: item.get("last_seen")?.as_n().ok()?.parse().ok()?,
+        page: item.get("page").and_then(|page| page.as_s().ok()).cloned(),
+    })
+}
+
+#[async_trait]
+impl StatsBackend for DynamoPresence {
+    async fn query(&self, _project_id: &str, _query: &StatsQuery) -> Result<Option<Stats>, Error> {
+        Ok(None)
+    }
+
+    async fn realtime(&self, project_id: &str, query: &RealtimeQuery) -> Result<Option<Realtime>, Error> {
+        let first = realtime::sort_key(&Granularity::Minute.bucket(query.since), "");
+        let mut sightings = Vec::new();
+        let mut start_key = None;
+        loop {
+            let output = self
+                .client
+                .query()
+                .table_name(&self.table_name)
+                .key_condition_expression("pk = :pk AND sk >= :first")
+                .expression_attribute_values(":pk", AttributeValue::S(project_id.to_string()))
+                .expression_attribute_values(":first", AttributeValue::S(first.clone()))
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await?;
+            sightings.extend(output.items().iter().filter_map(sighting));
+            start_key = output.last_evaluated_key;
+            if start_key.is_none() {
+                return Ok(Some(summarize(query, sightings)));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, Utc};
+
+    #[test]
+    fn test_bucket_keys_match_the_aggregator() {
+        let at = |s: &str| DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc);
+        let query = StatsQuery {
+            metric: Metric::Pageviews,
+            from: at("2024-05-01T22:00:00Z"),
+            to: at("2024-05-02T00:30:00Z"),
+            granularity: Granularity::Hour,
+            limit: 10,
+        };
+        let keys: Vec<_> = bucket_keys("p", &query).iter().map(BucketKey::partition_key).collect();
+        assert_eq!(keys, ["p#hour#2024-05-01T22", "p#hour#2024-05-01T23", "p#hour#2024-05-02T00"]);
+    }
+
+    #[test]
+    fn test_reads_sightings_from_presence_items() {
+        let item = HashMap::from([
+            ("sk".to_string(), AttributeValue::S("2024-05-01T12:00#anon#1".to_string())),
+            ("last_seen".to_string(), AttributeValue::N("1714564800000".to_string())),
+            ("page".to_string(), AttributeValue::S("/".to_string())),
+        ]);
+        assert_eq!(
+            sighting(&item),
+            Some(Sighting {
+                visitor: "anon#1".to_string(),
+                last_seen: 1_714_564_800_000,
+                page: Some("/".to_string()),
+            })
+        );
+    }
+}
@@ -0,0 +1,190 @@
+//! Export requests and the services behind them.
+//!
+//! `POST /exports` takes a JSON body:
+//!
+//! - `from`, `to`: RFC 3339 times, required; at most `EXPORT_MAX_DAYS`
+//!   (default 31) apart
+//! - `format`: `csv` (the default) or `parquet`
+//!
+//! and answers 202 with the new job, queued for `packages/exporter`.
+//! `GET /exports/{jobId}` answers the job's status and, once it succeeded,
+//! a `downloadUrl` presigned for `EXPORT_LINK_TTL_SECONDS` (default an
+//! hour); asking again gives a fresh link. Jobs of other projects are
+//! answered 404, like unknown ones.
+
+use async_trait::async_trait;
+use aws_sdk_s3::presigning::PresigningConfig;
+use aws_sdk_s3::Client as S3Client;
+use chrono::{DateTime, Duration, Utc};
+use exporter::jobs::{ExportConfig, ExportFormat, ExportJob, JobQueue, JobStore};
+use lambda_http::Error;
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+struct ExportRequest {
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    format: Option<String>,
+}
+
+/// A validated export request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExportQuery {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub format: ExportFormat,
+}
+
+impl ExportQuery {
+    /// Parses a request body. Errors are messages for a 400.
+    pub fn parse(body: &[u8], max_days: i64) -> Result<Self, String> {
+        let request: ExportRequest =
+            serde_json::from_slice(body).map_err(|e| format!("Invalid export request: {}", e))?;
+        let (Some(from), Some(to)) = (request.from, request.to) else {
+            return Err("from and to are required".to_string());
+        };
+        if from >= to {
+            return Err("from must be before to".to_string());
+        }
+        if to - from > Duration::days(max_days) {
+            return Err(format!("the range covers more than {} days", max_days));
+        }
+        let format = match request.format {
+            Some(format) => format.parse()?,
+            None => ExportFormat::Csv,
+        };
+        Ok(Self { from, to, format })
+    }
+}
+
+/// Hands out download links to exports
+#[async_trait]
+pub trait LinkSigner: Send + Sync {
+    async fn link(&self, key: &str, expires_in: std::time::Duration) -> Result<String, Error>;
+}
+
+/// Presigned S3 `GetObject` links
+pub struct S3LinkSigner {
+    client: S3Client,
+    bucket: String,
+}
+
+impl S3LinkSigner {
+    pub fn new(client: S3Client, bucket: String) -> Self {
+        Self { client, bucket }
+    }
+}
+
+#[async_trait]
+impl LinkSigner for S3LinkSigner {
+    async fn link(&self, key: &str, expires_in: std::time::Duration) -> Result<String, Error> {
+        let request = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .presigned(PresigningConfig::expires_in(expires_in)?)
+            .await?;
+        Ok(request.uri().to_string())
+    }
+}
+
+/// What the export routes use
+pub struct Exports {
+    pub config: ExportConfig,
+    pub jobs: Box<dyn JobStore>,
+    pub queue: Box<dyn JobQueue>,
+    pub links: Box<dyn LinkSigner>,
+}
+
+impl Exports {
+    /// Records and queues a new job
+    pub async fn create(&self, project_id: &str, query: &ExportQuery, now: DateTime<Utc>) -> Result<ExportJob, Error> {
+        let job = ExportJob::new(
+            uuid::Uuid::new_v4().to_string(),
+            project_id.to_string(),
+            query.format,
+            query.from.timestamp_millis(),
+            query.to.timestamp_millis(),
+            now.timestamp_millis(),
+        );
+        self.jobs
+            .put(&job, now.timestamp() + self.config.job_ttl.as_secs() as i64)
+            .await?;
+        self.queue.enqueue(&job.job_id).await?;
+        Ok(job)
+    }
+
+    /// A job of the project, with its download link once it succeeded
+    pub async fn status(&self, project_id: &str, job_id: &str) -> Result<Option<serde_json::Value>, Error> {
+        let Some(job) = self.jobs.get(job_id).await?.filter(|job| job.project_id == project_id) else {
+            return Ok(None);
+        };
+        let link = match job.key {
+            Some(ref key) => Some(self.links.link(key, self.config.link_ttl).await?),
+            None => None,
+        };
+        Ok(Some(job_body(&job, link)))
+    }
+}
+
+/// The job id of a `.../exports/{jobId}` path
+pub fn job_id(path: &str) -> Option<&str> {
+    let (prefix, job_id) = path.rsplit_once('/')?;
+    (prefix.ends_with("/exports") && !job_id.is_empty()).then_some(job_id)
+}
+
+fn rfc3339(ms: i64) -> String {
+    DateTime::from_timestamp_millis(ms).unwrap_or_default().to_rfc3339()
+}
+
+/// How a job is answered
+pub fn job_body(job: &ExportJob, download_url: Option<String>) -> serde_json::Value {
+    let mut body = serde_json::json!({
+        "jobId": job.job_id,
+        "projectId": job.project_id,
+        "format": job.format.as_str(),
+        "from": rfc3339(job.from),
+        "to": rfc3339(job.to),
+        "status": job.status.as_str(),
+        "createdAt": rfc3339(job.created_at),
+    });
+    if let Some(body) = body.as_object_mut() {
+        if let Some(rows) = job.rows {
+            body.insert("rows".to_string(), rows.into());
+        }
+        if let Some(ref error) = job.error {
+            body.insert("error".to_string(), error.clone().into());
+        }
+        if let Some(url) = download_url {
+            body.insert("downloadUrl".to_string(), url.into());
+        }
+    }
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parses_export_requests() {
+        let query = ExportQuery::parse(br#"{"from": "2024-05-01T00:00:00Z", "to": "2024-05-08T00:00:00Z"}"#, 31).unwrap();
+        assert_eq!(query.format, ExportFormat::Csv);
+        assert_eq!(query.to.to_rfc3339(), "2024-05-08T00:00:00+00:00");
+
+        let parquet = br#"{"from": "2024-05-01T00:00:00Z", "to": "2024-05-02T00:00:00Z", "format": "parquet"}"#;
+        assert_eq!(ExportQuery::parse(parquet, 31).unwrap().format, ExportFormat::Parquet);
+
+        assert!(ExportQuery::parse(br#"{"from": "2024-05-01T00:00:00Z"}"#, 31).is_err());
+        assert!(ExportQuery::parse(br#"{"from": "2024-01-01T00:00:00Z", "to": "2024-05-01T00:00:00Z"}"#, 31).is_err());
+        assert!(ExportQuery::parse(br#"{"from": "2024-05-01T00:00:00Z", "to": "2024-05-02T00:00:00Z", "format": "xml"}"#, 31).is_err());
+    }
+
+    #[test]
+    fn test_job_ids_from_paths() {
+        assert_eq!(job_id("/prod/exports/abc"), Some("abc"));
+        assert_eq!(job_id("/prod/exports"), None);
+        assert_eq!(job_id("/prod/stats/abc"), None);
+    }
+}
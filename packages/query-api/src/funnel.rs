@@ -0,0 +1,141 @@
+//! Funnel requests and reports.
+//!
+//! `POST /funnels` takes a JSON body:
+//!
+//! - `steps`: the event types of the funnel, in order (2 to 10)
+//! - `windowSeconds`: how long a visitor has, from the first step, to
+//!   complete the rest (default a day, at most 30 days)
+//! - `from`, `to`: RFC 3339 times; the last 7 days by default
+//!
+//! The report has each step's visitor count, its conversion from the
+//! first step and its drop-off from the previous one.
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Fewest steps a funnel may have
+pub const MIN_STEPS: usize = 2;
+/// Most steps a funnel may have
+pub const MAX_STEPS: usize = 10;
+/// Longest conversion window
+pub const MAX_WINDOW_SECS: u64 = 30 * 86_400;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FunnelRequest {
+    #[serde(default)]
+    steps: Vec<String>,
+    window_seconds: Option<u64>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+}
+
+/// A validated funnel request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunnelQuery {
+    pub steps: Vec<String>,
+    pub window_secs: u64,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+}
+
+impl FunnelQuery {
+    /// Parses a request body. Errors are messages for a 400.
+    pub fn parse(body: &[u8], now: DateTime<Utc>) -> Result<Self, String> {
+        let request: FunnelRequest =
+            serde_json::from_slice(body).map_err(|e| format!("Invalid funnel request: {}", e))?;
+        if !(MIN_STEPS..=MAX_STEPS).contains(&request.steps.len()) {
+            return Err(format!("steps must list {} to {} event types", MIN_STEPS, MAX_STEPS));
+        }
+        if request.steps.iter().any(|step| step.trim().is_empty()) {
+            return Err("steps must not be empty".to_string());
+        }
+        let window_secs = request.window_seconds.unwrap_or(86_400);
+        if !(1..=MAX_WINDOW_SECS).contains(&window_secs) {
+            return Err(format!("windowSeconds must be between 1 and {}", MAX_WINDOW_SECS));
+        }
+        let to = request.to.unwrap_or(now);
+        let from = request.from.unwrap_or(to - Duration::days(7));
+        if from >= to {
+            return Err("from must be before to".to_string());
+        }
+        Ok(Self {
+            steps: request.steps,
+            window_secs,
+            from,
+            to,
+        })
+    }
+}
+
+/// One step of a report
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FunnelStep {
+    pub event: String,
+    /// Visitors who reached this step
+    pub count: u64,
+    /// Share of the first step's visitors who reached this one
+    pub conversion: f64,
+    /// Visitors of the previous step who didn't reach this one
+    pub drop_off: u64,
+}
+
+fn share(part: u64, whole: u64) -> f64 {
+    if whole == 0 {
+        0.0
+    } else {
+        part as f64 / whole as f64
+    }
+}
+
+/// The report from `reached`: how many visitors got exactly as far as each
+/// level, as `(level, visitors)` where level 1 is the first step
+pub fn report(steps: &[String], reached: &[(usize, u64)]) -> Vec<FunnelStep> {
+    let counts: Vec<u64> = (1..=steps.len())
+        .map(|level| reached.iter().filter(|(at, _)| *at >= level).map(|(_, n)| n).sum())
+        .collect();
+    let first = counts.first().copied().unwrap_or_default();
+    steps
+        .iter()
+        .zip(&counts)
+        .enumerate()
+        .map(|(index, (event, &count))| FunnelStep {
+            event: event.clone(),
+            count,
+            conversion: share(count, first),
+            drop_off: index.checked_sub(1).map_or(0, |previous| counts[previous] - count),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2024-05-01T12:00:00Z").unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_parses_with_defaults() {
+        let query = FunnelQuery::parse(br#"{"steps": ["pageview", "signup"]}"#, now()).unwrap();
+        assert_eq!(query.window_secs, 86_400);
+        assert_eq!(query.from.to_rfc3339(), "2024-04-24T12:00:00+00:00");
+
+        assert!(FunnelQuery::parse(br#"{"steps": ["pageview"]}"#, now()).is_err());
+        assert!(FunnelQuery::parse(br#"{"steps": ["a", " "]}"#, now()).is_err());
+        assert!(FunnelQuery::parse(br#"{"steps": ["a", "b"], "windowSeconds": 0}"#, now()).is_err());
+        assert!(FunnelQuery::parse(b"steps", now()).is_err());
+    }
+
+    #[test]
+    fn test_report_counts_visitors_reaching_each_step() {
+        let steps = ["view", "signup", "purchase"].map(String::from);
+        let report = report(&steps, &[(1, 60), (2, 30), (3, 10)]);
+
+        let counts: Vec<_> = report.iter().map(|s| (s.count, s.drop_off)).collect();
+        assert_eq!(counts, [(100, 0), (40, 60), (10, 30)]);
+        assert_eq!(report[2].conversion, 0.1);
+    }
+}
@@ -0,0 +1,605 @@
+//! Request handling: CORS, authentication, `GET /stats`, `POST /funnels`,
+//! `GET /retention`, `GET /realtime`, `POST /exports` and
+//! `GET /exports/{jobId}`.
+//!
+//! Requests are authenticated the way ingestion authenticates them, except
+//! that the API key is always required: a Bearer token names the project
+//! and the `X-API-Key` must belong to it (and, when the key lists browser
+//! origins, be used from one of them). Responses carry the ingestion CORS
+//! headers plus `Authorization`, and an `X-Request-Id`.
+
+use chrono::Utc;
+use ingestion::auth::{ApiKeyCache, ApiKeyConfig};
+use ingestion::handlers::decode_jwt;
+use ingestion::origin::{self, OriginPolicy};
+use ingestion::request_id::{self, RequestId};
+use ingestion::shared::{create_error_response, create_response, env_or, header_value, query_param};
+use lambda_http::{Body, Error, Request, Response};
+use std::sync::Arc;
+
+use crate::backend::StatsBackend;
+use crate::exports::{self, ExportQuery, Exports};
+use crate::funnel::{self, FunnelQuery};
+use crate::params::{Metric, StatsQuery};
+use crate::realtime::RealtimeQuery;
+use crate::retention::{self, RetentionQuery};
+
+/// Headers a browser may send, for preflights
+const ALLOW_HEADERS: &str = "Authorization, Content-Type, X-API-Key, X-Request-Id";
+
+/// Configuration for the query API
+#[derive(Debug, Clone)]
+pub struct QueryConfig {
+    /// Most buckets one query may cover
+    pub max_buckets: usize,
+    pub api_keys: ApiKeyConfig,
+    pub origin_policy: OriginPolicy,
+}
+
+impl Default for QueryConfig {
+    fn default() -> Self {
+        Self {
+            max_buckets: 744,
+            api_keys: ApiKeyConfig::default(),
+            origin_policy: OriginPolicy::default(),
+        }
+    }
+}
+
+impl QueryConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            max_buckets: env_or("QUERY_MAX_BUCKETS", defaults.max_buckets).max(1),
+            api_keys: ApiKeyConfig::from_env(),
+            origin_policy: OriginPolicy::from_env(),
+        }
+    }
+}
+
+/// Shared across requests
+pub struct QueryState {
+    pub config: QueryConfig,
+    pub api_keys: ApiKeyCache,
+    pub backend: Box<dyn StatsBackend>,
+    /// Raw event exports, when configured
+    pub exports: Option<Exports>,
+}
+
+/// Main Lambda handler
+pub async fn function_handler(request: Request, state: Arc<QueryState>) -> Result<Response<Body>, Error> {
+    let request_id = RequestId::from_request(&request);
+    let (mut response, origin) = route(&request, &state).await?;
+    if let Some(ref origin) = origin {
+        response = origin::with_allowed_origin(response, Some(origin));
+    }
+    if let Ok(value) = ALLOW_HEADERS.parse() {
+        response.headers_mut().insert("Access-Control-Allow-Headers", value);
+    }
+    Ok(request_id::stamp(response, &request_id))
+}
+
+/// The response, and the origin to echo when the project restricts them
+async fn route(request: &Request, state: &QueryState) -> Result<(Response<Body>, Option<String>), Error> {
+    if request.method() == "OPTIONS" {
+        return Ok((create_response(200, serde_json::json!({})), None));
+    }
+    let path = request.uri().path().trim_end_matches('/');
+    let endpoint = if path.ends_with("/stats") {
+        Endpoint::Stats
+    } else if path.ends_with("/funnels") {
+        Endpoint::Funnels
+    } else if path.ends_with("/retention") {
+        Endpoint::Retention
+    } else if path.ends_with("/realtime") {
+        Endpoint::Realtime
+    } else if path.ends_with("/exports") {
+        Endpoint::Exports
+    } else if let Some(job_id) = exports::job_id(path) {
+        Endpoint::Export(job_id)
+    } else {
+        return Ok((create_error_response(404, "Not found"), None));
+    };
+    let method = match endpoint {
+        Endpoint::Stats | Endpoint::Retention | Endpoint::Realtime | Endpoint::Export(_) => "GET",
+        Endpoint::Funnels | Endpoint::Exports => "POST",
+    };
+    if request.method() != method {
+        return Ok((create_error_response(405, "Method not allowed"), None));
+    }
+    if !state.config.origin_policy.permits(request) {
+        tracing::warn!("Rejecting request from disallowed origin");
+        return Ok((create_error_response(403, "Origin not allowed"), None));
+    }
+
+    let (project_id, origin) = match authenticate(request, state).await? {
+        Ok(authenticated) => authenticated,
+        Err(rejection) => return Ok((rejection, None)),
+    };
+    let response = match endpoint {
+        Endpoint::Stats => stats(request, state, &project_id).await?,
+        Endpoint::Funnels => funnels(request, state, &project_id).await?,
+        Endpoint::Retention => retention(request, state, &project_id).await?,
+        Endpoint::Realtime => realtime(request, state, &project_id).await?,
+        Endpoint::Exports => create_export(request, state, &project_id).await?,
+        Endpoint::Export(job_id) => export_status(state, &project_id, job_id).await?,
+    };
+    Ok((response, origin))
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Endpoint<'a> {
+    Stats,
+    Funnels,
+    Retention,
+    Realtime,
+    Exports,
+    /// A job's status, by id
+    Export(&'a str),
+}
+
+async fn stats(request: &Request, state: &QueryState, project_id: &str) -> Result<Response<Body>, Error> {
+    let query = match StatsQuery::parse(|name| query_param(request, name), Utc::now(), state.config.max_buckets) {
+        Ok(query) => query,
+        Err(message) => return Ok(create_error_response(400, &message)),
+    };
+
+    let response = match state.backend.query(project_id, &query).await? {
+        Some(stats) => {
+            let mut body = serde_json::json!({
+                "projectId": project_id,
+                "metric": metric_name(query.metric),
+                "from": query.from.to_rfc3339(),
+                "to": query.to.to_rfc3339(),
+                "granularity": query.granularity.as_str(),
+            });
+            if let (Some(body), serde_json::Value::Object(stats)) = (body.as_object_mut(), serde_json::to_value(stats)?) {
+                body.extend(stats);
+            }
+            create_response(200, body)
+        }
+        None => create_error_response(501, "Metric not available"),
+    };
+    Ok(response)
+}
+
+async fn funnels(request: &Request, state: &QueryState, project_id: &str) -> Result<Response<Body>, Error> {
+    let query = match FunnelQuery::parse(request.body(), Utc::now()) {
+        Ok(query) => query,
+        Err(message) => return Ok(create_error_response(400, &message)),
+    };
+
+    let response = match state.backend.funnel(project_id, &query).await? {
+        Some(reached) => create_response(
+            200,
+            serde_json::json!({
+                "projectId": project_id,
+                "from": query.from.to_rfc3339(),
+                "to": query.to.to_rfc3339(),
+                "windowSeconds": query.window_secs,
+                "steps": funnel::report(&query.steps, &reached),
+            }),
+        ),
+        None => create_error_response(501, "Funnels not available"),
+    };
+    Ok(response)
+}
+
+async fn retention(request: &Request, state: &QueryState, project_id: &str) -> Result<Response<Body>, Error> {
+    let now = Utc::now();
+    let query = match RetentionQuery::parse(|name| query_param(request, name), now) {
+        Ok(query) => query,
+        Err(message) => return Ok(create_error_response(400, &message)),
+    };
+
+    let response = match state.backend.retention(project_id, &query).await? {
+        Some(active) => create_response(
+            200,
+            serde_json::json!({
+                "projectId": project_id,
+                "from": query.from.to_string(),
+                "to": query.to.to_string(),
+                "days": query.days,
+                "cohorts": retention::report(&query, &active, now.date_naive()),
+            }),
+        ),
+        None => create_error_response(501, "Retention not available"),
+    };
+    Ok(response)
+}
+
+async fn realtime(request: &Request, state: &QueryState, project_id: &str) -> Result<Response<Body>, Error> {
+    let now = Utc::now();
+    let query = match RealtimeQuery::parse(|name| query_param(request, name), now) {
+        Ok(query) => query,
+        Err(message) => return Ok(create_error_response(400, &message)),
+    };
+
+    let response = match state.backend.realtime(project_id, &query).await? {
+        Some(realtime) => {
+            let mut body = serde_json::json!({
+                "projectId": project_id,
+                "minutes": query.minutes,
+                "asOf": now.to_rfc3339(),
+            });
+            if let (Some(body), serde_json::Value::Object(realtime)) = (body.as_object_mut(), serde_json::to_value(realtime)?) {
+                body.extend(realtime);
+            }
+            create_response(200, body)
+        }
+        None => create_error_response(501, "Realtime not available"),
+    };
+    Ok(response)
+}
+
+async fn create_export(request: &Request, state: &QueryState, project_id: &str) -> Result<Response<Body>, Error> {
+    let Some(ref exports) = state.exports else {
+        return Ok(create_error_response(501, "Exports not available"));
+    };
+    let query = match ExportQuery::parse(request.body(), exports.config.max_days) {
+        Ok(query) => query,
+        Err(message) => return Ok(create_error_response(400, &message)),
+    };
+
+    let job = exports.create(project_id, &query, Utc::now()).await?;
+    tracing::info!("Queued export {} of project {}", job.job_id, project_id);
+    Ok(create_response(202, exports::job_body(&job, None)))
+}
+
+async fn export_status(state: &QueryState, project_id: &str, job_id: &str) -> Result<Response<Body>, Error> {
+    let Some(ref exports) = state.exports else {
+        return Ok(create_error_response(501, "Exports not available"));
+    };
+    let response = match exports.status(project_id, job_id).await? {
+        Some(body) => create_response(200, body),
+        None => create_error_response(404, "Export not found"),
+    };
+    Ok(response)
+}
+
+fn metric_name(metric: Metric) -> &'static str {
+    match metric {
+        Metric::Pageviews => "pageviews",
+        Metric::TopPages => "top_pages",
+        Metric::TopReferrers => "top_referrers",
+        Metric::UniqueVisitors => "unique_visitors",
+    }
+}
+
+/// The project a request may read and its approved origin, or the
+/// rejection to send
+async fn authenticate(
+    request: &Request,
+    state: &QueryState,
+) -> Result<Result<(String, Option<String>), Response<Body>>, Error> {
+    let token = header_value(request, "authorization").and_then(|value| value.strip_prefix("Bearer "));
+    let Some(token) = token else {
+        return Ok(Err(create_error_response(401, "Unauthorized: Missing bearer token")));
+    };
+    let project_id = match decode_jwt(token) {
+        Ok((project_id, _)) => project_id,
+        Err(e) => return Ok(Err(create_error_response(401, &format!("Unauthorized: {}", e)))),
+    };
+    let Some(key) = header_value(request, "x-api-key") else {
+        return Ok(Err(create_error_response(401, "Unauthorized: Missing API key")));
+    };
+
+    let Some(record) = state.api_keys.lookup(key, state.config.api_keys.cache_ttl).await? else {
+        return Ok(Err(create_error_response(401, "Unauthorized: Invalid API key")));
+    };
+    if record.project_id != project_id {
+        tracing::warn!("Rejecting API key of project {} used for {}", record.project_id, project_id);
+        return Ok(Err(create_error_response(403, "API key does not belong to this project")));
+    }
+    let origin = origin::request_origin(request);
+    if !record.allowed_origins.is_empty() {
+        if let Some(ref origin) = origin {
+            if !origin::origin_allowed(&record.allowed_origins, origin) {
+                tracing::warn!("Rejecting request from an origin not allowed for {}", project_id);
+                return Ok(Err(create_error_response(403, "Origin not allowed for this project")));
+            }
+        }
+        return Ok(Ok((project_id, origin)));
+    }
+    Ok(Ok((project_id, None)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{Ranked, Stats};
+    use crate::exports::LinkSigner;
+    use async_trait::async_trait;
+    use base64::Engine;
+    use exporter::jobs::{ExportConfig, ExportJob, JobQueue, JobStatus, JobStore};
+    use ingestion::auth::{ApiKeyRecord, InMemoryApiKeyStore};
+    use lambda_http::RequestExt;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    /// Answers top pages, funnels and retention, not realtime
+    struct FakeBackend;
+
+    #[async_trait]
+    impl StatsBackend for FakeBackend {
+        async fn query(&self, _project_id: &str, query: &StatsQuery) -> Result<Option<Stats>, Error> {
+            Ok((query.metric == Metric::TopPages).then(|| {
+                Stats::Ranking(vec![Ranked {
+                    key: "/pricing".to_string(),
+                    count: 3,
+                }])
+            }))
+        }
+
+        async fn funnel(&self, _project_id: &str, _query: &FunnelQuery) -> Result<Option<Vec<(usize, u64)>>, Error> {
+            Ok(Some(vec![(1, 6), (2, 4)]))
+        }
+
+        async fn retention(
+            &self,
+            _project_id: &str,
+            query: &RetentionQuery,
+        ) -> Result<Option<Vec<(chrono::NaiveDate, u32, u64)>>, Error> {
+            Ok(Some(vec![(query.from, 0, 8), (query.from, 1, 2)]))
+        }
+    }
+
+    /// Jobs and queued ids in memory, shared by clones; links are the key
+    #[derive(Clone, Default)]
+    struct FakeExports {
+        jobs: Arc<Mutex<HashMap<String, ExportJob>>>,
+        queued: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl JobStore for FakeExports {
+        async fn put(&self, job: &ExportJob, _expires_at: i64) -> Result<(), Error> {
+            self.jobs.lock().unwrap().insert(job.job_id.clone(), job.clone());
+            Ok(())
+        }
+
+        async fn get(&self, job_id: &str) -> Result<Option<ExportJob>, Error> {
+            Ok(self.jobs.lock().unwrap().get(job_id).cloned())
+        }
+    }
+
+    #[async_trait]
+    impl JobQueue for FakeExports {
+        async fn enqueue(&self, job_id: &str) -> Result<(), Error> {
+            self.queued.lock().unwrap().push(job_id.to_string());
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl LinkSigner for FakeExports {
+        async fn link(&self, key: &str, _expires_in: std::time::Duration) -> Result<String, Error> {
+            Ok(format!("https://bucket/{}", key))
+        }
+    }
+
+    fn export_state(fake: &FakeExports) -> Arc<QueryState> {
+        let mut state = Arc::into_inner(state()).unwrap();
+        state.exports = Some(Exports {
+            config: ExportConfig::default(),
+            jobs: Box::new(fake.clone()),
+            queue: Box::new(fake.clone()),
+            links: Box::new(fake.clone()),
+        });
+        Arc::new(state)
+    }
+
+    fn state() -> Arc<QueryState> {
+        let store = InMemoryApiKeyStore::default();
+        store.insert("key-p", ApiKeyRecord {
+            project_id: "p".to_string(),
+            allowed_origins: vec!["https://app.example.com".to_string()],
+            ..Default::default()
+        });
+        Arc::new(QueryState {
+            config: QueryConfig::default(),
+            api_keys: ApiKeyCache::new(Arc::new(store)),
+            backend: Box::new(FakeBackend),
+            exports: None,
+        })
+    }
+
+    fn token(project_id: &str) -> String {
+        let claims = serde_json::json!({ "projectId": project_id }).to_string();
+        let claims = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(claims);
+        format!("Bearer h.{}.s", claims)
+    }
+
+    fn request(query: &[(&str, &str)], project_id: &str, key: &str) -> Request {
+        get("/prod/stats", query, project_id, key)
+    }
+
+    fn get(path: &str, query: &[(&str, &str)], project_id: &str, key: &str) -> Request {
+        let query: HashMap<String, String> = query.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        lambda_http::http::Request::builder()
+            .method("GET")
+            .uri(path)
+            .header("Authorization", token(project_id))
+            .header("X-API-Key", key)
+            .header("Origin", "https://app.example.com")
+            .body(Body::Empty)
+            .unwrap()
+            .with_query_string_parameters(query)
+    }
+
+    fn funnel_request(body: &str) -> Request {
+        lambda_http::http::Request::builder()
+            .method("POST")
+            .uri("/prod/funnels")
+            .header("Authorization", token("p"))
+            .header("X-API-Key", "key-p")
+            .body(Body::Text(body.to_string()))
+            .unwrap()
+    }
+
+    fn export_request(body: &str) -> Request {
+        let mut request = funnel_request(body);
+        *request.uri_mut() = "/prod/exports".parse().unwrap();
+        request
+    }
+
+    fn body(response: &Response<Body>) -> serde_json::Value {
+        match response.body() {
+            Body::Text(text) => serde_json::from_str(text).unwrap(),
+            _ => panic!("expected a text body"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_answers_stats_for_the_keys_project() {
+        let response = function_handler(request(&[("metric", "top_pages")], "p", "key-p"), state())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["access-control-allow-origin"], "https://app.example.com");
+        assert!(response.headers()["access-control-allow-headers"].to_str().unwrap().contains("Authorization"));
+        let body = body(&response);
+        assert_eq!(body["metric"], "top_pages");
+        assert_eq!(body["ranking"], serde_json::json!([{"key": "/pricing", "count": 3}]));
+    }
+
+    #[tokio::test]
+    async fn test_rejects_other_projects_and_unknown_keys() {
+        let response = function_handler(request(&[("metric", "top_pages")], "q", "key-p"), state())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 403);
+
+        let response = function_handler(request(&[("metric", "top_pages")], "p", "nope"), state())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 401);
+        assert!(body(&response)["requestId"].is_string());
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_metric_and_bad_params() {
+        let response = function_handler(request(&[("metric", "unique_visitors")], "p", "key-p"), state())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 501);
+
+        let response = function_handler(request(&[("metric", "top_pages"), ("limit", "1000")], "p", "key-p"), state())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_reports_funnel_drop_off() {
+        let response = function_handler(funnel_request(r#"{"steps": ["pageview", "signup"]}"#), state())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), 200);
+        let body = body(&response);
+        assert_eq!(body["windowSeconds"], 86_400);
+        assert_eq!(
+            body["steps"],
+            serde_json::json!([
+                {"event": "pageview", "count": 10, "conversion": 1.0, "dropOff": 0},
+                {"event": "signup", "count": 4, "conversion": 0.4, "dropOff": 6},
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_funnels_need_post_and_valid_steps() {
+        let response = function_handler(funnel_request(r#"{"steps": ["pageview"]}"#), state())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+
+        let mut request = funnel_request("{}");
+        *request.method_mut() = lambda_http::http::Method::GET;
+        let response = function_handler(request, state()).await.unwrap();
+        assert_eq!(response.status(), 405);
+    }
+
+    #[tokio::test]
+    async fn test_reports_retention_cohorts() {
+        let query = [("from", "2024-05-01"), ("to", "2024-05-02"), ("days", "3")];
+        let response = function_handler(get("/prod/retention", &query, "p", "key-p"), state())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), 200);
+        let body = body(&response);
+        assert_eq!(body["days"], 3);
+        assert_eq!(body["cohorts"][0]["retained"], serde_json::json!([8, 2, 0, 0]));
+        assert_eq!(body["cohorts"][0]["rates"][1], 0.25);
+        assert_eq!(body["cohorts"][1]["size"], 0);
+
+        let response = function_handler(get("/prod/retention", &[("days", "365")], "p", "key-p"), state())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_realtime_needs_a_presence_table() {
+        let response = function_handler(get("/prod/realtime", &[], "p", "key-p"), state())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 501);
+
+        let response = function_handler(get("/prod/realtime", &[("minutes", "0")], "p", "key-p"), state())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_queues_exports_and_reports_their_status() {
+        let fake = FakeExports::default();
+        let request = export_request(r#"{"from": "2024-05-01T00:00:00Z", "to": "2024-05-02T00:00:00Z", "format": "parquet"}"#);
+        let response = function_handler(request, export_state(&fake)).await.unwrap();
+
+        assert_eq!(response.status(), 202);
+        let created = body(&response);
+        assert_eq!((created["status"].as_str(), created["format"].as_str()), (Some("pending"), Some("parquet")));
+        let job_id = created["jobId"].as_str().unwrap().to_string();
+        assert_eq!(*fake.queued.lock().unwrap(), vec![job_id.clone()]);
+
+        let path = format!("/prod/exports/{}", job_id);
+        let response = function_handler(get(&path, &[], "p", "key-p"), export_state(&fake)).await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert!(body(&response).get("downloadUrl").is_none());
+
+        if let Some(job) = fake.jobs.lock().unwrap().get_mut(&job_id) {
+            job.status = JobStatus::Succeeded;
+            job.rows = Some(12);
+            job.key = Some(format!("exports/project_id=p/{}.parquet", job_id));
+        }
+        let response = function_handler(get(&path, &[], "p", "key-p"), export_state(&fake)).await.unwrap();
+        let status = body(&response);
+        assert_eq!((status["status"].as_str(), status["rows"].as_u64()), (Some("succeeded"), Some(12)));
+        assert_eq!(status["downloadUrl"], format!("https://bucket/exports/project_id=p/{}.parquet", job_id));
+    }
+
+    #[tokio::test]
+    async fn test_exports_are_private_to_their_project() {
+        let fake = FakeExports::default();
+        let other = ExportJob::new("j-q".to_string(), "q".to_string(), exporter::jobs::ExportFormat::Csv, 0, 1, 0);
+        fake.jobs.lock().unwrap().insert(other.job_id.clone(), other);
+
+        let response = function_handler(get("/prod/exports/j-q", &[], "p", "key-p"), export_state(&fake))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404);
+
+        let response = function_handler(export_request(r#"{"from": "2024-05-01T00:00:00Z"}"#), export_state(&fake))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+        let response = function_handler(export_request("{}"), state()).await.unwrap();
+        assert_eq!(response.status(), 501);
+        assert!(fake.queued.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,23 @@
+//! Read API for dashboards.
+//!
+//! `GET /stats` answers pageviews over time, top pages, top referrers and
+//! unique visitors for the caller's project (see [`params`] and
+//! [`handler`]), from the DynamoDB aggregates where they're counted (see
+//! [`dynamo`]) and ClickHouse otherwise (see [`clickhouse`]), so a
+//! dashboard never needs raw warehouse access. `POST /funnels` reports
+//! step-by-step conversion through a list of events (see [`funnel`]) and
+//! `GET /retention` N-day retention by first-seen cohort (see
+//! [`retention`]). `GET /realtime` counts the visitors active in the last
+//! few minutes and the pages they're on (see [`realtime`]). `POST /exports`
+//! starts a job exporting the project's raw events, polled at
+//! `GET /exports/{jobId}` for its download link (see [`exports`]).
+
+pub mod backend;
+pub mod clickhouse;
+pub mod dynamo;
+pub mod exports;
+pub mod funnel;
+pub mod handler;
+pub mod params;
+pub mod realtime;
+pub mod retention;
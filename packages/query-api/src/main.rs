@@ -0,0 +1,82 @@
+use lambda_http::{run, service_fn, Error, Request};
+use std::sync::Arc;
+
+use aws_sdk_dynamodb::Client as DynamoClient;
+use aws_sdk_s3::Client as S3Client;
+use aws_sdk_sqs::Client as SqsClient;
+use clickhouse_writer::clickhouse::ClickHouseConfig;
+use ingestion::auth::{ApiKeyCache, ApiKeyStore, DynamoApiKeyStore, InMemoryApiKeyStore};
+use exporter::jobs::{DynamoJobStore, ExportConfig, SqsJobQueue};
+use ingestion::shared::env_var;
+use query_api::backend::{Backends, StatsBackend};
+use query_api::clickhouse::ClickHouseStats;
+use query_api::dynamo::{DynamoAggregates, DynamoPresence};
+use query_api::exports::{Exports, S3LinkSigner};
+use query_api::handler::{function_handler, QueryConfig, QueryState};
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    // Initialize tracing
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"))
+        )
+        .json()
+        .init();
+
+    let config = QueryConfig::from_env();
+    let aws = aws_config::load_from_env().await;
+    let dynamo = DynamoClient::new(&aws);
+
+    let mut backends: Vec<Box<dyn StatsBackend>> = Vec::new();
+    if let Some(table) = env_var("AGGREGATES_TABLE") {
+        backends.push(Box::new(DynamoAggregates::new(dynamo.clone(), table)));
+    }
+    if env_var("CLICKHOUSE_URL").is_some() {
+        backends.push(Box::new(ClickHouseStats::new(ClickHouseConfig::from_env())?));
+    }
+    if let Some(table) = env_var("REALTIME_TABLE") {
+        backends.push(Box::new(DynamoPresence::new(dynamo.clone(), table)));
+    }
+    if backends.is_empty() {
+        return Err("Set at least one of AGGREGATES_TABLE, CLICKHOUSE_URL and REALTIME_TABLE".into());
+    }
+
+    // Exports need their job table, queue and bucket
+    let export_config = ExportConfig::from_env();
+    let exports = if [&export_config.jobs_table, &export_config.queue_url, &export_config.bucket]
+        .iter()
+        .all(|setting| !setting.is_empty())
+    {
+        Some(Exports {
+            jobs: Box::new(DynamoJobStore::new(dynamo.clone(), export_config.jobs_table.clone())),
+            queue: Box::new(SqsJobQueue::new(SqsClient::new(&aws), export_config.queue_url.clone())),
+            links: Box::new(S3LinkSigner::new(S3Client::new(&aws), export_config.bucket.clone())),
+            config: export_config,
+        })
+    } else {
+        tracing::info!("EXPORT_JOBS_TABLE, EXPORT_QUEUE_URL or EXPORT_BUCKET not set, exports are off");
+        None
+    };
+
+    let key_store: Arc<dyn ApiKeyStore> = match config.api_keys.table_name {
+        Some(ref table) => Arc::new(DynamoApiKeyStore::new(dynamo, table.clone())),
+        None => {
+            tracing::warn!("API_KEYS_TABLE not set, every request will be rejected");
+            Arc::new(InMemoryApiKeyStore::default())
+        }
+    };
+    let state = Arc::new(QueryState {
+        config,
+        api_keys: ApiKeyCache::new(key_store),
+        backend: Box::new(Backends(backends)),
+        exports,
+    });
+
+    run(service_fn(move |request: Request| {
+        let state = state.clone();
+        async move { function_handler(request, state).await }
+    }))
+    .await
+}
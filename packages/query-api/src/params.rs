@@ -0,0 +1,174 @@
+//! Parsing a stats request's query string.
+//!
+//! `GET /stats?metric=…` takes:
+//!
+//! - `metric`: `pageviews` (a series), `top_pages`, `top_referrers` (rankings)
+//!   or `unique_visitors` (a count)
+//! - `from`, `to`: RFC 3339 times; the last 24 hours by default
+//! - `granularity`: `minute`, `hour` (default) or `day`, for series. From
+//!   the aggregates table, days are the project's reporting days (see
+//!   `ingestion::enrichment::reporting_day`); from ClickHouse, UTC days
+//! - `limit`: rankings' length, 10 by default and at most 100
+//!
+//! A range may cover at most `QUERY_MAX_BUCKETS` buckets of its granularity.
+
+use aggregator::counts::Granularity;
+use chrono::{DateTime, Duration, DurationRound, Utc};
+
+/// What a request asks for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    Pageviews,
+    TopPages,
+    TopReferrers,
+    UniqueVisitors,
+}
+
+impl std::str::FromStr for Metric {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().replace('-', "_").as_str() {
+            "pageviews" => Ok(Self::Pageviews),
+            "top_pages" => Ok(Self::TopPages),
+            "top_referrers" => Ok(Self::TopReferrers),
+            "unique_visitors" => Ok(Self::UniqueVisitors),
+            other => Err(format!("unknown metric \"{}\"", other)),
+        }
+    }
+}
+
+/// Most entries a ranking may ask for
+pub const MAX_LIMIT: usize = 100;
+
+/// A validated stats request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatsQuery {
+    pub metric: Metric,
+    /// Start of the range, inclusive, at a bucket boundary
+    pub from: DateTime<Utc>,
+    /// End of the range, exclusive
+    pub to: DateTime<Utc>,
+    pub granularity: Granularity,
+    pub limit: usize,
+}
+
+impl StatsQuery {
+    /// Parses a request's parameters, as read by `param`. Errors are
+    /// messages for a 400.
+    pub fn parse<'a>(
+        param: impl Fn(&str) -> Option<&'a str>,
+        now: DateTime<Utc>,
+        max_buckets: usize,
+    ) -> Result<Self, String> {
+        let metric = param("metric").ok_or("metric is required")?.parse()?;
+        let granularity = match param("granularity").unwrap_or("hour") {
+            "minute" => Granularity::Minute,
+            "hour" => Granularity::Hour,
+            "day" => Granularity::Day,
+            other => return Err(format!("unknown granularity \"{}\"", other)),
+        };
+        let time = |name: &str| {
+            param(name)
+                .map(|value| {
+                    DateTime::parse_from_rfc3339(value)
+                        .map(|time| time.with_timezone(&Utc))
+                        .map_err(|_| format!("{} must be an RFC 3339 time", name))
+                })
+                .transpose()
+        };
+        let to = time("to")?.unwrap_or(now);
+        let from = time("from")?.unwrap_or(to - Duration::hours(24));
+        let limit = match param("limit") {
+            Some(limit) => limit
+                .parse::<usize>()
+                .ok()
+                .filter(|limit| (1..=MAX_LIMIT).contains(limit))
+                .ok_or(format!("limit must be between 1 and {}", MAX_LIMIT))?,
+            None => 10,
+        };
+
+        let from = from
+            .duration_trunc(bucket_width(granularity))
+            .map_err(|e| e.to_string())?;
+        if from >= to {
+            return Err("from must be before to".to_string());
+        }
+        let query = Self {
+            metric,
+            from,
+            to,
+            granularity,
+            limit,
+        };
+        if query.buckets().len() > max_buckets {
+            return Err(format!("the range covers more than {} buckets", max_buckets));
+        }
+        Ok(query)
+    }
+
+    /// Start times of the buckets the range covers
+    pub fn buckets(&self) -> Vec<DateTime<Utc>> {
+        let width = bucket_width(self.granularity);
+        let mut buckets = Vec::new();
+        let mut start = self.from;
+        while start < self.to {
+            buckets.push(start);
+            start += width;
+        }
+        buckets
+    }
+}
+
+pub fn bucket_width(granularity: Granularity) -> Duration {
+    match granularity {
+        Granularity::Minute => Duration::minutes(1),
+        Granularity::Hour => Duration::hours(1),
+        Granularity::Day => Duration::days(1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn parse(query: &[(&str, &str)]) -> Result<StatsQuery, String> {
+        let params: HashMap<&str, &str> = query.iter().copied().collect();
+        let now = DateTime::parse_from_rfc3339("2024-05-01T12:34:56Z").unwrap().with_timezone(&Utc);
+        StatsQuery::parse(|name| params.get(name).copied(), now, 48)
+    }
+
+    #[test]
+    fn test_defaults_to_the_last_day_by_hour() {
+        let query = parse(&[("metric", "top-pages")]).unwrap();
+        assert_eq!(query.metric, Metric::TopPages);
+        assert_eq!(query.from.to_rfc3339(), "2024-04-30T12:00:00+00:00");
+        assert_eq!((query.buckets().len(), query.limit), (25, 10));
+    }
+
+    #[test]
+    fn test_day_ranges_start_at_midnight() {
+        let query = parse(&[
+            ("metric", "pageviews"),
+            ("granularity", "day"),
+            ("from", "2024-04-25T08:00:00Z"),
+        ])
+        .unwrap();
+        assert_eq!(query.from.to_rfc3339(), "2024-04-25T00:00:00+00:00");
+        assert_eq!(query.buckets().len(), 7);
+    }
+
+    #[test]
+    fn test_rejects_bad_parameters() {
+        assert_eq!(parse(&[]).unwrap_err(), "metric is required");
+        assert!(parse(&[("metric", "bounces")]).is_err());
+        assert!(parse(&[("metric", "pageviews"), ("limit", "0")]).is_err());
+        assert!(parse(&[("metric", "pageviews"), ("from", "yesterday")]).is_err());
+        assert!(parse(&[("metric", "pageviews"), ("from", "2024-05-02T00:00:00Z")]).is_err());
+        assert_eq!(
+            parse(&[("metric", "pageviews"), ("granularity", "minute")]).unwrap_err(),
+            "the range covers more than 48 buckets"
+        );
+    }
+}
@@ -0,0 +1,149 @@
+//! Realtime requests and summaries.
+//!
+//! `GET /realtime` takes:
+//!
+//! - `minutes`: how far back counts as active, 5 by default and at most 30
+//! - `limit`: how many top pages, 10 by default and at most 100
+//!
+//! The answer is how many visitors were seen in that window and which pages
+//! they're on now (each visitor's latest page), from the presence the
+//! aggregator keeps (see `aggregator::realtime`).
+
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::backend::{top, Ranked};
+use crate::params::MAX_LIMIT;
+
+/// Longest window; presence is kept a little longer than this
+pub const MAX_MINUTES: u32 = 30;
+
+/// A validated realtime request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RealtimeQuery {
+    /// Visitors seen since are active
+    pub since: DateTime<Utc>,
+    pub minutes: u32,
+    pub limit: usize,
+}
+
+impl RealtimeQuery {
+    /// Parses a request's parameters, as read by `param`. Errors are
+    /// messages for a 400.
+    pub fn parse<'a>(param: impl Fn(&str) -> Option<&'a str>, now: DateTime<Utc>) -> Result<Self, String> {
+        let minutes = match param("minutes") {
+            Some(minutes) => minutes
+                .parse::<u32>()
+                .ok()
+                .filter(|minutes| (1..=MAX_MINUTES).contains(minutes))
+                .ok_or(format!("minutes must be between 1 and {}", MAX_MINUTES))?,
+            None => 5,
+        };
+        let limit = match param("limit") {
+            Some(limit) => limit
+                .parse::<usize>()
+                .ok()
+                .filter(|limit| (1..=MAX_LIMIT).contains(limit))
+                .ok_or(format!("limit must be between 1 and {}", MAX_LIMIT))?,
+            None => 10,
+        };
+        Ok(Self {
+            since: now - Duration::minutes(i64::from(minutes)),
+            minutes,
+            limit,
+        })
+    }
+}
+
+/// Who's active
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Realtime {
+    pub active_visitors: u64,
+    pub top_pages: Vec<Ranked>,
+}
+
+/// A visitor's activity in one minute, as read from the presence table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sighting {
+    pub visitor: String,
+    /// Epoch milliseconds
+    pub last_seen: i64,
+    pub page: Option<String>,
+}
+
+/// Counts each visitor seen since the query's start once, on their latest
+/// page
+pub fn summarize(query: &RealtimeQuery, sightings: impl IntoIterator<Item = Sighting>) -> Realtime {
+    let since = query.since.timestamp_millis();
+    // Each visitor's latest page, with when it was seen
+    let mut latest: HashMap<String, Option<(i64, String)>> = HashMap::new();
+    for sighting in sightings {
+        if sighting.last_seen < since {
+            continue;
+        }
+        let page = latest.entry(sighting.visitor).or_default();
+        if let Some(path) = sighting.page {
+            if page.as_ref().is_none_or(|(seen, _)| sighting.last_seen >= *seen) {
+                *page = Some((sighting.last_seen, path));
+            }
+        }
+    }
+
+    let mut pages: HashMap<String, u64> = HashMap::new();
+    for (_, path) in latest.values().flatten() {
+        *pages.entry(path.clone()).or_default() += 1;
+    }
+    Realtime {
+        active_visitors: latest.len() as u64,
+        top_pages: top(pages, query.limit),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2024-05-01T12:00:00Z").unwrap().with_timezone(&Utc)
+    }
+
+    fn sighting(visitor: &str, minutes_ago: i64, page: Option<&str>) -> Sighting {
+        Sighting {
+            visitor: visitor.to_string(),
+            last_seen: (now() - Duration::minutes(minutes_ago)).timestamp_millis(),
+            page: page.map(String::from),
+        }
+    }
+
+    #[test]
+    fn test_parses_with_defaults() {
+        let query = RealtimeQuery::parse(|_| None, now()).unwrap();
+        assert_eq!((query.minutes, query.limit), (5, 10));
+        assert_eq!(query.since.to_rfc3339(), "2024-05-01T11:55:00+00:00");
+
+        assert!(RealtimeQuery::parse(|_| Some("31"), now()).is_err());
+        assert!(RealtimeQuery::parse(|name| (name == "limit").then_some("0"), now()).is_err());
+    }
+
+    #[test]
+    fn test_counts_visitors_once_on_their_latest_page() {
+        let query = RealtimeQuery::parse(|_| None, now()).unwrap();
+        let sightings = [
+            sighting("v1", 4, Some("/")),
+            sighting("v1", 1, Some("/pricing")),
+            sighting("v2", 2, Some("/pricing")),
+            sighting("v2", 0, None),
+            sighting("v3", 3, None),
+            sighting("v4", 9, Some("/")),
+        ];
+        let realtime = summarize(&query, sightings);
+
+        assert_eq!(realtime.active_visitors, 3);
+        assert_eq!(realtime.top_pages, [Ranked {
+            key: "/pricing".to_string(),
+            count: 2,
+        }]);
+    }
+}
@@ -0,0 +1,170 @@
+//! Retention requests and reports.
+//!
+//! `GET /retention` takes:
+//!
+//! - `from`, `to`: the first and last cohort dates, as `YYYY-MM-DD` (UTC);
+//!   the 14 days up to today by default, at most 90 cohorts
+//! - `days`: how many days after the first to follow each cohort, 7 by
+//!   default and at most 90
+//!
+//! A cohort is the visitors first seen on a date, over the project's whole
+//! history. The report has each cohort's size and, for day 0 to `days`,
+//! how many of them were active that day and what share that is. Days
+//! that haven't happened yet are left out.
+
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::Serialize;
+
+/// Most cohorts one request may cover
+pub const MAX_COHORTS: i64 = 90;
+/// Most days a cohort may be followed for
+pub const MAX_DAYS: u32 = 90;
+
+/// A validated retention request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetentionQuery {
+    /// First cohort date
+    pub from: NaiveDate,
+    /// Last cohort date, inclusive
+    pub to: NaiveDate,
+    pub days: u32,
+}
+
+impl RetentionQuery {
+    /// Parses a request's parameters, as read by `param`. Errors are
+    /// messages for a 400.
+    pub fn parse<'a>(param: impl Fn(&str) -> Option<&'a str>, now: DateTime<Utc>) -> Result<Self, String> {
+        let date = |name: &str| {
+            param(name)
+                .map(|value| {
+                    NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|_| format!("{} must be a YYYY-MM-DD date", name))
+                })
+                .transpose()
+        };
+        let to = date("to")?.unwrap_or(now.date_naive());
+        let from = date("from")?.unwrap_or(to - Duration::days(13));
+        let days = match param("days") {
+            Some(days) => days
+                .parse::<u32>()
+                .ok()
+                .filter(|days| (1..=MAX_DAYS).contains(days))
+                .ok_or(format!("days must be between 1 and {}", MAX_DAYS))?,
+            None => 7,
+        };
+        if from > to {
+            return Err("from must not be after to".to_string());
+        }
+        if (to - from).num_days() >= MAX_COHORTS {
+            return Err(format!("the range covers more than {} cohorts", MAX_COHORTS));
+        }
+        Ok(Self { from, to, days })
+    }
+
+    /// The cohort dates, in order
+    pub fn cohorts(&self) -> impl Iterator<Item = NaiveDate> {
+        self.from.iter_days().take_while({
+            let to = self.to;
+            move |date| *date <= to
+        })
+    }
+
+    /// The span of activity the report reads, in epoch milliseconds: from
+    /// the first cohort to the end of the last one's last day
+    pub fn activity_range(&self) -> (i64, i64) {
+        let start = |date: NaiveDate| date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc().timestamp_millis();
+        (start(self.from), start(self.to + Duration::days(i64::from(self.days) + 1)))
+    }
+}
+
+/// One cohort of a report
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Cohort {
+    /// The date its visitors were first seen
+    pub date: String,
+    pub size: u64,
+    /// Visitors active on each day after the first, day 0 included
+    pub retained: Vec<u64>,
+    /// `retained` as shares of `size`
+    pub rates: Vec<f64>,
+}
+
+/// The report from `active`: how many of a cohort's visitors were active
+/// some days after it, as `(cohort, day, visitors)`. Every cohort is
+/// listed, empty or not, up to `today`.
+pub fn report(query: &RetentionQuery, active: &[(NaiveDate, u32, u64)], today: NaiveDate) -> Vec<Cohort> {
+    query
+        .cohorts()
+        .map(|cohort| {
+            let elapsed = (today - cohort).num_days().clamp(0, i64::from(query.days));
+            let retained: Vec<u64> = (0..=elapsed as u32)
+                .map(|day| {
+                    active
+                        .iter()
+                        .find(|(date, at, _)| *date == cohort && *at == day)
+                        .map_or(0, |(_, _, visitors)| *visitors)
+                })
+                .collect();
+            let size = retained.first().copied().unwrap_or_default();
+            Cohort {
+                date: cohort.to_string(),
+                size,
+                rates: retained
+                    .iter()
+                    .map(|&count| if size == 0 { 0.0 } else { count as f64 / size as f64 })
+                    .collect(),
+                retained,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2024-05-15T12:00:00Z").unwrap().with_timezone(&Utc)
+    }
+
+    fn parse(query: &[(&str, &str)]) -> Result<RetentionQuery, String> {
+        let params: HashMap<&str, &str> = query.iter().copied().collect();
+        RetentionQuery::parse(|name| params.get(name).copied(), now())
+    }
+
+    fn date(value: &str) -> NaiveDate {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parses_with_defaults() {
+        let query = parse(&[]).unwrap();
+        assert_eq!((query.from, query.to, query.days), (date("2024-05-02"), date("2024-05-15"), 7));
+        assert_eq!(query.cohorts().count(), 14);
+
+        let query = parse(&[("from", "2024-05-01"), ("to", "2024-05-01"), ("days", "2")]).unwrap();
+        assert_eq!(query.activity_range(), (1_714_521_600_000, 1_714_780_800_000));
+
+        assert!(parse(&[("days", "0")]).is_err());
+        assert!(parse(&[("from", "May 1")]).is_err());
+        assert!(parse(&[("from", "2024-05-16")]).is_err());
+        assert!(parse(&[("from", "2024-01-01")]).is_err());
+    }
+
+    #[test]
+    fn test_report_follows_each_cohort_until_today() {
+        let query = parse(&[("from", "2024-05-13"), ("to", "2024-05-14"), ("days", "3")]).unwrap();
+        let active = [
+            (date("2024-05-13"), 0, 10),
+            (date("2024-05-13"), 2, 4),
+            (date("2024-05-14"), 0, 5),
+            (date("2024-05-14"), 1, 5),
+        ];
+        let report = report(&query, &active, date("2024-05-15"));
+
+        assert_eq!(report[0].retained, [10, 0, 4]);
+        assert_eq!(report[0].rates, [1.0, 0.0, 0.4]);
+        assert_eq!((report[1].size, report[1].retained.len()), (5, 2));
+    }
+}
@@ -0,0 +1,31 @@
+//! Configuration of the replicator.
+
+use ingestion::residency::ResidencyConfig;
+use ingestion::shared::env_var;
+
+/// Configuration for the replicator
+#[derive(Debug, Clone, Default)]
+pub struct ReplicatorConfig {
+    /// The primary region's ingest stream
+    pub primary_stream: String,
+    /// The primary region; the function's own when unset
+    pub primary_region: Option<String>,
+    /// Projects pinned to a residency zone (`PROJECT_DATA_RESIDENCY`), whose
+    /// events are not forwarded
+    pub residency: ResidencyConfig,
+}
+
+impl ReplicatorConfig {
+    pub fn from_env() -> Self {
+        Self {
+            primary_stream: env_var("PRIMARY_STREAM_NAME").unwrap_or_default(),
+            primary_region: env_var("PRIMARY_REGION").filter(|region| !region.is_empty()),
+            residency: ResidencyConfig::from_env(),
+        }
+    }
+
+    /// Whether a project's events may leave the region they were ingested in
+    pub fn replicates(&self, project_id: &str) -> bool {
+        !self.residency.project_zones.contains_key(project_id)
+    }
+}
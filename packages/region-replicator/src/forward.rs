@@ -0,0 +1,44 @@
+//! Writing replicated events to the primary stream.
+//!
+//! Events are written as they were ingested, partitioned by project, with
+//! the ingest API's batched `PutRecords` and retry settings (`RETRY_*`).
+//! A retried batch writes its events again, as a retried ingest request
+//! would.
+
+use async_trait::async_trait;
+use aws_sdk_kinesis::Client as KinesisClient;
+use ingestion::models::IngestEventPayload;
+use ingestion::put_records;
+use ingestion::retry::RetryConfig;
+use lambda_runtime::Error;
+
+/// Where replicated events go; a trait so the handler can be tested
+/// without a stream
+#[async_trait]
+pub trait EventForwarder: Send + Sync {
+    async fn forward(&self, events: &[IngestEventPayload]) -> Result<(), Error>;
+}
+
+/// Writes events to a Kinesis stream, usually in another region
+pub struct KinesisForwarder {
+    client: KinesisClient,
+    stream_name: String,
+    retry: RetryConfig,
+}
+
+impl KinesisForwarder {
+    pub fn new(client: KinesisClient, stream_name: String, retry: RetryConfig) -> Self {
+        Self {
+            client,
+            stream_name,
+            retry,
+        }
+    }
+}
+
+#[async_trait]
+impl EventForwarder for KinesisForwarder {
+    async fn forward(&self, events: &[IngestEventPayload]) -> Result<(), Error> {
+        put_records::put_events(&self.client, &self.stream_name, events, &self.retry).await
+    }
+}
@@ -0,0 +1,157 @@
+//! The Kinesis batch handler.
+//!
+//! A batch is decoded (unpacking KPL aggregates) and its events forwarded
+//! in one go, leaving out those of pinned projects. Events without a
+//! `region`, from deployments that don't stamp one, get the stream's. If
+//! forwarding fails the whole batch is reported failed and retried.
+
+use aws_lambda_events::event::kinesis::KinesisEvent;
+use aws_lambda_events::event::streams::{KinesisBatchItemFailure, KinesisEventResponse};
+use ingestion::aggregation;
+use ingestion::models::IngestEventPayload;
+
+use crate::config::ReplicatorConfig;
+use crate::forward::EventForwarder;
+
+/// Forwards a batch, reporting it failed if it couldn't be written
+pub async fn handle(
+    event: KinesisEvent,
+    forwarder: &dyn EventForwarder,
+    config: &ReplicatorConfig,
+) -> KinesisEventResponse {
+    let mut events = Vec::new();
+    let mut pinned = 0;
+    for record in &event.records {
+        for data in aggregation::decode(&record.kinesis.data.0) {
+            let mut event = match serde_json::from_slice::<IngestEventPayload>(&data) {
+                Ok(event) => event,
+                Err(e) => {
+                    tracing::warn!("Skipping a record that isn't a JSON event: {}", e);
+                    continue;
+                }
+            };
+            if !config.replicates(&event.project_id) {
+                pinned += 1;
+                continue;
+            }
+            if event.region.is_none() {
+                event.region = record.aws_region.clone();
+            }
+            events.push(event);
+        }
+    }
+
+    if events.is_empty() {
+        return KinesisEventResponse {
+            batch_item_failures: Vec::new(),
+        };
+    }
+    match forwarder.forward(&events).await {
+        Ok(()) => {
+            tracing::info!("Forwarded {} events, kept {} of pinned projects", events.len(), pinned);
+            KinesisEventResponse {
+                batch_item_failures: Vec::new(),
+            }
+        }
+        Err(e) => {
+            tracing::error!("Failed to forward the batch, retrying it: {}", e);
+            let first = event.records.first().and_then(|record| record.kinesis.sequence_number.clone());
+            KinesisEventResponse {
+                batch_item_failures: vec![KinesisBatchItemFailure { item_identifier: first }],
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use lambda_runtime::Error;
+    use serde_json::json;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct FakeForwarder {
+        fail: bool,
+        events: Mutex<Vec<IngestEventPayload>>,
+    }
+
+    #[async_trait]
+    impl EventForwarder for FakeForwarder {
+        async fn forward(&self, events: &[IngestEventPayload]) -> Result<(), Error> {
+            if self.fail {
+                return Err("ProvisionedThroughputExceededException".into());
+            }
+            self.events.lock().unwrap().extend_from_slice(events);
+            Ok(())
+        }
+    }
+
+    fn batch(events: &[serde_json::Value]) -> KinesisEvent {
+        let records: Vec<_> = events
+            .iter()
+            .enumerate()
+            .map(|(index, event)| {
+                let data = aws_lambda_events::encodings::Base64Data(serde_json::to_vec(event).unwrap());
+                json!({
+                    "awsRegion": "eu-central-1",
+                    "kinesis": {
+                        "sequenceNumber": index.to_string(),
+                        "data": data,
+                        "approximateArrivalTimestamp": 1_700_000_000.0,
+                    },
+                })
+            })
+            .collect();
+        serde_json::from_value(json!({ "Records": records })).unwrap()
+    }
+
+    fn config() -> ReplicatorConfig {
+        let mut config = ReplicatorConfig {
+            primary_stream: "events".to_string(),
+            ..Default::default()
+        };
+        config.residency.project_zones = HashMap::from([("acme-eu".to_string(), "eu".to_string())]);
+        config
+    }
+
+    #[tokio::test]
+    async fn test_forwards_all_but_pinned_projects() {
+        let forwarder = FakeForwarder::default();
+        let event = |project_id: &str, region: Option<&str>| {
+            json!({"projectId": project_id, "eventType": "pageview", "timestamp": 1, "region": region})
+        };
+        let events = [
+            event("startup", Some("eu-west-1")),
+            event("acme-eu", Some("eu-central-1")),
+            event("startup", None),
+        ];
+
+        let response = handle(batch(&events), &forwarder, &config()).await;
+
+        assert!(response.batch_item_failures.is_empty());
+        let forwarded = forwarder.events.lock().unwrap();
+        let regions: Vec<_> = forwarded.iter().map(|e| (e.project_id.as_str(), e.region.as_deref())).collect();
+        assert_eq!(regions, [("startup", Some("eu-west-1")), ("startup", Some("eu-central-1"))]);
+    }
+
+    #[tokio::test]
+    async fn test_forward_failure_retries_the_batch() {
+        let forwarder = FakeForwarder {
+            fail: true,
+            ..Default::default()
+        };
+        let events = [json!({"projectId": "startup", "eventType": "pageview", "timestamp": 1})];
+
+        let response = handle(batch(&events), &forwarder, &config()).await;
+
+        assert_eq!(
+            response.batch_item_failures,
+            [KinesisBatchItemFailure {
+                item_identifier: Some("0".to_string()),
+            }]
+        );
+    }
+}
@@ -0,0 +1,12 @@
+//! Cross-region replication.
+//!
+//! An ingest API deployed outside the primary region writes to a stream of
+//! its own region. This function consumes that stream and forwards its
+//! events to the primary region's stream (see [`forward`]), so the primary
+//! consumers (the Parquet writer and the rest) merge them into the primary
+//! data lake. Events of projects pinned to a residency zone stay in their
+//! region and are not forwarded (see [`handler`]).
+
+pub mod config;
+pub mod forward;
+pub mod handler;
@@ -0,0 +1,43 @@
+use lambda_runtime::{run, service_fn, Error, LambdaEvent};
+use std::sync::Arc;
+
+use aws_lambda_events::event::kinesis::KinesisEvent;
+use aws_sdk_kinesis::Client as KinesisClient;
+use ingestion::retry::RetryConfig;
+use region_replicator::config::ReplicatorConfig;
+use region_replicator::forward::KinesisForwarder;
+use region_replicator::handler::handle;
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    // Initialize tracing
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"))
+        )
+        .json()
+        .init();
+
+    let config = Arc::new(ReplicatorConfig::from_env());
+    if config.primary_stream.is_empty() {
+        return Err("PRIMARY_STREAM_NAME environment variable not set".into());
+    }
+    let aws = aws_config::load_from_env().await;
+    // The primary stream is usually in another region
+    let mut kinesis_config = aws_sdk_kinesis::config::Builder::from(&aws);
+    if let Some(ref region) = config.primary_region {
+        kinesis_config = kinesis_config.region(aws_sdk_kinesis::config::Region::new(region.clone()));
+    }
+    let forwarder = Arc::new(KinesisForwarder::new(
+        KinesisClient::from_conf(kinesis_config.build()),
+        config.primary_stream.clone(),
+        RetryConfig::from_env(),
+    ));
+
+    run(service_fn(move |event: LambdaEvent<KinesisEvent>| {
+        let (forwarder, config) = (forwarder.clone(), config.clone());
+        async move { Ok::<_, Error>(handle(event.payload, forwarder.as_ref(), &config).await) }
+    }))
+    .await
+}
@@ -0,0 +1,307 @@
+//! Reading events back from the archive.
+//!
+//! Objects are picked by key: one in a `dt=YYYY-MM-DD` partition within the
+//! replayed days and, when replaying one project, not in another project's
+//! partition (`project_id=…` of the Parquet lake, `project=…` of the
+//! fallback sink; dead-letter objects aren't partitioned by project, so
+//! their events are filtered after reading). Their format is told by the
+//! extension:
+//!
+//! - `.parquet`: the Parquet writer's files. They only keep its columns and
+//!   the `properties` and `context` JSON, so top-level enrichments (bot
+//!   score, channel, ...) are lost; the row's id becomes `eventId`, so
+//!   consumers that dedupe on it overwrite the original rows
+//! - `.ndjson` and `.ndjson.gz`: one event per line, as the sinks wrote them
+//!
+//! `context.receivedAt` is kept, so the Parquet writer files replayed
+//! events under their original hour.
+
+use arrow_array::{Array, Int32Array, RecordBatch, StringArray, TimestampMillisecondArray};
+use async_trait::async_trait;
+use aws_sdk_s3::Client as S3Client;
+use bytes::Bytes;
+use chrono::NaiveDate;
+use flate2::read::GzDecoder;
+use ingestion::models::IngestEventPayload;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet_writer::files::project_path;
+use std::io::Read;
+
+use crate::Error;
+
+/// The archive's objects; a trait so replay can be tested without S3
+#[async_trait]
+pub trait ArchiveStore: Send + Sync {
+    /// Keys under `prefix`, in order
+    async fn list(&self, prefix: &str) -> Result<Vec<String>, Error>;
+    async fn get(&self, key: &str) -> Result<Vec<u8>, Error>;
+}
+
+/// Objects in an S3 bucket
+pub struct S3ArchiveStore {
+    client: S3Client,
+    bucket: String,
+}
+
+impl S3ArchiveStore {
+    pub fn new(client: S3Client, bucket: String) -> Self {
+        Self { client, bucket }
+    }
+}
+
+#[async_trait]
+impl ArchiveStore for S3ArchiveStore {
+    async fn list(&self, prefix: &str) -> Result<Vec<String>, Error> {
+        let mut keys = Vec::new();
+        let mut token = None;
+        loop {
+            let output = self
+                .client
+                .list_objects_v2()
+                .bucket(&self.bucket)
+                .prefix(prefix)
+                .set_continuation_token(token)
+                .send()
+                .await?;
+            keys.extend(output.contents().iter().filter_map(|object| object.key().map(String::from)));
+            token = output.next_continuation_token;
+            if token.is_none() {
+                return Ok(keys);
+            }
+        }
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>, Error> {
+        let output = self.client.get_object().bucket(&self.bucket).key(key).send().await?;
+        Ok(output.body.collect().await?.into_bytes().to_vec())
+    }
+}
+
+/// Replayed days, both ends included
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateRange {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+}
+
+impl DateRange {
+    /// Parses `YYYY-MM-DD` ends
+    pub fn parse(from: &str, to: &str) -> Result<Self, String> {
+        let day = |day: &str| {
+            NaiveDate::parse_from_str(day, "%Y-%m-%d").map_err(|_| format!("Invalid day {}, expected YYYY-MM-DD", day))
+        };
+        let (from, to) = (day(from)?, day(to)?);
+        if from > to {
+            return Err(format!("{} is after {}", from, to));
+        }
+        Ok(Self { from, to })
+    }
+
+    pub fn contains(&self, day: NaiveDate) -> bool {
+        (self.from..=self.to).contains(&day)
+    }
+}
+
+/// How an archived object is encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Parquet,
+    Ndjson,
+    GzippedNdjson,
+}
+
+impl Format {
+    /// The format of a key, from its extension
+    pub fn of(key: &str) -> Option<Self> {
+        if key.ends_with(".parquet") {
+            Some(Self::Parquet)
+        } else if key.ends_with(".ndjson") {
+            Some(Self::Ndjson)
+        } else if key.ends_with(".ndjson.gz") {
+            Some(Self::GzippedNdjson)
+        } else {
+            None
+        }
+    }
+}
+
+/// Whether a key may hold events of the range, and of `project_id` if set
+pub fn selected(key: &str, range: &DateRange, project_id: Option<&str>) -> bool {
+    let day = key
+        .split('/')
+        .find_map(|segment| segment.strip_prefix("dt="))
+        .and_then(|day| NaiveDate::parse_from_str(day, "%Y-%m-%d").ok());
+    if Format::of(key).is_none() || !day.is_some_and(|day| range.contains(day)) {
+        return false;
+    }
+    let Some(project_id) = project_id else {
+        return true;
+    };
+    key.split('/').all(|segment| {
+        if segment.starts_with("project_id=") {
+            segment == project_path(project_id)
+        } else if let Some(project) = segment.strip_prefix("project=") {
+            project == project_id
+        } else {
+            true
+        }
+    })
+}
+
+/// Reads the events of an archived object
+pub fn decode(key: &str, data: Vec<u8>) -> Result<Vec<IngestEventPayload>, Error> {
+    match Format::of(key) {
+        Some(Format::Parquet) => parquet_events(data),
+        Some(Format::Ndjson) => Ok(ndjson_events(key, &String::from_utf8(data)?)),
+        Some(Format::GzippedNdjson) => {
+            let mut text = String::new();
+            GzDecoder::new(data.as_slice()).read_to_string(&mut text)?;
+            Ok(ndjson_events(key, &text))
+        }
+        None => Err(format!("{} is not an archive of events", key).into()),
+    }
+}
+
+/// Events of NDJSON lines, skipping lines that aren't one
+fn ndjson_events(key: &str, text: &str) -> Vec<IngestEventPayload> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .filter_map(|(index, line)| match serde_json::from_str(line) {
+            Ok(event) => Some(event),
+            Err(e) => {
+                tracing::warn!("Skipping line {} of {}: {}", index + 1, key, e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Events of a Parquet writer file
+fn parquet_events(data: Vec<u8>) -> Result<Vec<IngestEventPayload>, Error> {
+    let mut events = Vec::new();
+    for batch in ParquetRecordBatchReaderBuilder::try_new(Bytes::from(data))?.build()? {
+        let batch = batch?;
+        let strings = |name: &str| {
+            batch
+                .column_by_name(name)
+                .and_then(|column| column.as_any().downcast_ref::<StringArray>())
+                .ok_or_else(|| format!("No {} column", name))
+        };
+        let (ids, projects, types) = (strings("event_id")?, strings("project_id")?, strings("event_type")?);
+        let (users, anonymous) = (strings("user_id")?, strings("anonymous_id")?);
+        let (properties, context) = (strings("properties")?, strings("context")?);
+        let timestamps = timestamps(&batch)?;
+        // Files before schema version 2 have no region, before 3 no local date
+        let regions = strings("region").ok();
+        let local_dates = strings("local_date").ok();
+        let local_hours = batch
+            .column_by_name("local_hour")
+            .and_then(|column| column.as_any().downcast_ref::<Int32Array>());
+
+        let value = |values: &StringArray, row: usize| values.is_valid(row).then(|| values.value(row).to_string());
+        for row in 0..batch.num_rows() {
+            events.push(IngestEventPayload {
+                event_id: value(ids, row),
+                project_id: projects.value(row).to_string(),
+                event_type: types.value(row).to_string(),
+                timestamp: timestamps.value(row),
+                user_id: value(users, row),
+                anonymous_id: value(anonymous, row),
+                properties: value(properties, row).map(|json| serde_json::from_str(&json)).transpose()?,
+                context: value(context, row).map(|json| serde_json::from_str(&json)).transpose()?,
+                region: regions.and_then(|regions| value(regions, row)),
+                local_date: local_dates.and_then(|dates| value(dates, row)),
+                local_hour: local_hours
+                    .filter(|hours| hours.is_valid(row))
+                    .and_then(|hours| u8::try_from(hours.value(row)).ok()),
+                ..Default::default()
+            });
+        }
+    }
+    Ok(events)
+}
+
+fn timestamps(batch: &RecordBatch) -> Result<&TimestampMillisecondArray, Error> {
+    batch
+        .column_by_name("timestamp")
+        .and_then(|column| column.as_any().downcast_ref::<TimestampMillisecondArray>())
+        .ok_or_else(|| "No timestamp column".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use parquet_writer::schema::{encode, EventRow};
+    use serde_json::json;
+    use std::io::Write;
+
+    fn june() -> DateRange {
+        DateRange::parse("2024-06-01", "2024-06-02").unwrap()
+    }
+
+    #[test]
+    fn test_selects_keys_by_day_and_project() {
+        let range = june();
+        assert!(selected("events/project_id=p/dt=2024-06-02/hr=23/1-2.parquet", &range, None));
+        assert!(!selected("events/project_id=p/dt=2024-06-03/hr=00/3-4.parquet", &range, None));
+        assert!(!selected("events/project_id=p/dt=2024-06-01/hr=00/_SUCCESS", &range, None));
+        assert!(!selected("events/project_id=p/1-2.parquet", &range, None));
+
+        assert!(selected("events/project_id=a%2Fb/dt=2024-06-01/hr=00/1.parquet", &range, Some("a/b")));
+        assert!(!selected("events/project_id=p/dt=2024-06-01/hr=00/1.parquet", &range, Some("a/b")));
+        assert!(selected("fallback/project=p/dt=2024-06-01/1-x.ndjson.gz", &range, Some("p")));
+        assert!(!selected("fallback/project=q/dt=2024-06-01/1-x.ndjson.gz", &range, Some("p")));
+        // Dead letters aren't partitioned by project
+        assert!(selected("dead-letter/dt=2024-06-01/1-x.ndjson", &range, Some("p")));
+
+        assert!(DateRange::parse("2024-06-02", "2024-06-01").is_err());
+        assert!(DateRange::parse("June 1st", "2024-06-01").is_err());
+    }
+
+    #[test]
+    fn test_decodes_parquet_rows_back_to_events() {
+        let event: IngestEventPayload = serde_json::from_value(json!({
+            "messageId": "m1",
+            "projectId": "p",
+            "eventType": "pageview",
+            "timestamp": 1_717_200_000_000_i64,
+            "anonymousId": "a1",
+            "properties": {"plan": "pro"},
+            "context": {"page": {"path": "/x"}, "receivedAt": 1_717_200_000_500_i64},
+            "region": "eu-central-1",
+            "localDate": "2024-06-01",
+            "localHour": 2,
+        }))
+        .unwrap();
+        let file = encode(&[EventRow::from_event(event, "fallback".to_string(), 0)], 10).unwrap();
+
+        let events = decode("events/project_id=p/dt=2024-06-01/hr=00/1.parquet", file).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event_id.as_deref(), Some("m1"));
+        assert_eq!(events[0].timestamp, 1_717_200_000_000);
+        assert_eq!(events[0].user_id, None);
+        assert_eq!(events[0].properties.as_ref().unwrap()["plan"], "pro");
+        assert_eq!(events[0].context.as_ref().unwrap().received_at, Some(1_717_200_000_500));
+        assert_eq!(events[0].region.as_deref(), Some("eu-central-1"));
+        assert_eq!((events[0].local_date.as_deref(), events[0].local_hour), (Some("2024-06-01"), Some(2)));
+    }
+
+    #[test]
+    fn test_decodes_ndjson_skipping_bad_lines() {
+        let ndjson = "{\"projectId\":\"p\",\"eventType\":\"a\",\"timestamp\":1}\n\nnot json\n\
+                      {\"projectId\":\"p\",\"eventType\":\"b\",\"timestamp\":2}\n";
+        let mut gzipped = GzEncoder::new(Vec::new(), Compression::default());
+        gzipped.write_all(ndjson.as_bytes()).unwrap();
+
+        for (key, data) in [
+            ("dead-letter/dt=2024-06-01/1.ndjson", ndjson.as_bytes().to_vec()),
+            ("fallback/project=p/dt=2024-06-01/1.ndjson.gz", gzipped.finish().unwrap()),
+        ] {
+            let types: Vec<_> = decode(key, data).unwrap().into_iter().map(|event| event.event_type).collect();
+            assert_eq!(types, ["a", "b"]);
+        }
+    }
+}
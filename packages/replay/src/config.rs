@@ -0,0 +1,51 @@
+//! Replay configuration.
+
+use ingestion::shared::{env_or, env_var};
+
+/// Configuration for a replay
+#[derive(Debug, Clone)]
+pub struct ReplayConfig {
+    /// Bucket holding the archive
+    pub bucket: String,
+    /// Key prefix listed for archived objects: the Parquet writer's
+    /// `PARQUET_PREFIX`, or the fallback or dead-letter sink's prefix
+    pub prefix: String,
+    /// Stream the events are published to
+    pub stream_name: String,
+    /// Only this project's events, when set
+    pub project_id: Option<String>,
+    /// Most events published per second
+    pub rate: u32,
+    /// Skips keys up to and including this one, to resume a replay that
+    /// stopped
+    pub start_after: Option<String>,
+}
+
+impl Default for ReplayConfig {
+    fn default() -> Self {
+        Self {
+            bucket: String::new(),
+            prefix: "events".to_string(),
+            stream_name: String::new(),
+            project_id: None,
+            rate: 500,
+            start_after: None,
+        }
+    }
+}
+
+impl ReplayConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            bucket: env_var("REPLAY_BUCKET").unwrap_or_default(),
+            prefix: env_or("REPLAY_PREFIX", defaults.prefix),
+            stream_name: env_var("REPLAY_STREAM_NAME")
+                .or_else(|| env_var("STREAM_NAME"))
+                .unwrap_or_default(),
+            project_id: env_var("REPLAY_PROJECT").filter(|project| !project.is_empty()),
+            rate: env_or("REPLAY_RATE", defaults.rate).max(1),
+            start_after: env_var("REPLAY_START_AFTER").filter(|key| !key.is_empty()),
+        }
+    }
+}
@@ -0,0 +1,15 @@
+//! S3 → Kinesis replay of archived events.
+//!
+//! For reprocessing after a consumer bug: the events archived in S3 for a
+//! range of days (the Parquet lake, or the NDJSON of the fallback and
+//! dead-letter sinks) are read back and re-published to a stream, paced to
+//! `REPLAY_RATE` events per second and marked `replayed: true`. See
+//! [`archive`] for which objects are read and how, and [`replay`] for the
+//! publishing.
+
+pub mod archive;
+pub mod config;
+pub mod replay;
+
+/// Errors of the replay, as in the ingestion crate
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
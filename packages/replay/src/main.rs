@@ -0,0 +1,47 @@
+use aws_sdk_kinesis::Client as KinesisClient;
+use aws_sdk_s3::Client as S3Client;
+use ingestion::retry::RetryConfig;
+use replay::archive::{DateRange, S3ArchiveStore};
+use replay::config::ReplayConfig;
+use replay::replay::{replay, KinesisPublisher};
+use replay::Error;
+
+/// `replay FROM TO`: replays the archived events of the days from `FROM` to
+/// `TO` (`YYYY-MM-DD`, both included), configured by the `REPLAY_*`
+/// environment variables
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    // Initialize tracing
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"))
+        )
+        .init();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let [from, to] = args.as_slice() else {
+        return Err("Usage: replay FROM TO (days as YYYY-MM-DD)".into());
+    };
+    let range = DateRange::parse(from, to)?;
+
+    let config = ReplayConfig::from_env();
+    if config.bucket.is_empty() {
+        return Err("REPLAY_BUCKET environment variable not set".into());
+    }
+    if config.stream_name.is_empty() {
+        return Err("REPLAY_STREAM_NAME environment variable not set".into());
+    }
+    let aws = aws_config::load_from_env().await;
+    let store = S3ArchiveStore::new(S3Client::new(&aws), config.bucket.clone());
+    let publisher = KinesisPublisher::new(KinesisClient::new(&aws), config.stream_name.clone(), RetryConfig::from_env());
+
+    let replayed = replay(&store, &publisher, &config, &range).await?;
+    tracing::info!(
+        "Replayed {} events from {} objects ({} unreadable)",
+        replayed.events,
+        replayed.objects,
+        replayed.unreadable
+    );
+    Ok(())
+}
@@ -0,0 +1,248 @@
+//! Publishing archived events.
+//!
+//! The selected objects are replayed in key order (by project, then day),
+//! each read whole and published in `PutRecords`-sized chunks keyed by
+//! project, so a project's events keep their order. Publishing is paced
+//! so the replay never goes above `REPLAY_RATE` events per second and
+//! doesn't starve live traffic of the stream's throughput. The replay
+//! stops at the first object it fails to publish, logging the last one
+//! that made it, to resume from with `REPLAY_START_AFTER`; events of the
+//! failed object already published are sent again on resume.
+
+use async_trait::async_trait;
+use aws_sdk_kinesis::Client as KinesisClient;
+use ingestion::models::IngestEventPayload;
+use ingestion::put_records::{self, MAX_RECORDS_PER_REQUEST};
+use ingestion::retry::RetryConfig;
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::archive::{self, ArchiveStore, DateRange};
+use crate::config::ReplayConfig;
+use crate::Error;
+
+/// Where replayed events go; a trait so replay can be tested without
+/// Kinesis
+#[async_trait]
+pub trait Publisher: Send + Sync {
+    async fn publish(&self, events: &[IngestEventPayload]) -> Result<(), Error>;
+}
+
+/// Publishes to a Kinesis stream, with the ingest API's retries
+pub struct KinesisPublisher {
+    client: KinesisClient,
+    stream_name: String,
+    retry: RetryConfig,
+}
+
+impl KinesisPublisher {
+    pub fn new(client: KinesisClient, stream_name: String, retry: RetryConfig) -> Self {
+        Self {
+            client,
+            stream_name,
+            retry,
+        }
+    }
+}
+
+#[async_trait]
+impl Publisher for KinesisPublisher {
+    async fn publish(&self, events: &[IngestEventPayload]) -> Result<(), Error> {
+        put_records::put_events(&self.client, &self.stream_name, events, &self.retry).await
+    }
+}
+
+/// Spaces publishes out to at most `rate` events per second
+pub struct Pacer {
+    rate: u32,
+    started: Instant,
+    sent: u64,
+}
+
+impl Pacer {
+    pub fn new(rate: u32) -> Self {
+        Self {
+            rate: rate.max(1),
+            started: Instant::now(),
+            sent: 0,
+        }
+    }
+
+    /// Waits until `count` more events may be published
+    pub async fn wait(&mut self, count: usize) {
+        let due = self.started + Duration::from_secs_f64(self.sent as f64 / f64::from(self.rate));
+        tokio::time::sleep_until(due).await;
+        self.sent += count as u64;
+    }
+}
+
+/// What a replay did
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Replayed {
+    /// Objects replayed
+    pub objects: usize,
+    /// Objects skipped because they couldn't be read
+    pub unreadable: usize,
+    pub events: u64,
+}
+
+/// Replays the archived events of the range
+pub async fn replay(
+    store: &dyn ArchiveStore,
+    publisher: &dyn Publisher,
+    config: &ReplayConfig,
+    range: &DateRange,
+) -> Result<Replayed, Error> {
+    let prefix = format!("{}/", config.prefix.trim_end_matches('/'));
+    let project_id = config.project_id.as_deref();
+    let mut keys: Vec<String> = store
+        .list(&prefix)
+        .await?
+        .into_iter()
+        .filter(|key| archive::selected(key, range, project_id))
+        .filter(|key| config.start_after.as_ref().is_none_or(|after| key > after))
+        .collect();
+    keys.sort();
+    tracing::info!("Replaying {} objects from {} to {}", keys.len(), range.from, range.to);
+
+    let chunk_size = (config.rate as usize).clamp(1, MAX_RECORDS_PER_REQUEST);
+    let mut pacer = Pacer::new(config.rate);
+    let mut replayed = Replayed::default();
+    for key in keys {
+        let mut events = match archive::decode(&key, store.get(&key).await?) {
+            Ok(events) => events,
+            Err(e) => {
+                tracing::error!("Skipping unreadable {}: {}", key, e);
+                replayed.unreadable += 1;
+                continue;
+            }
+        };
+        events.retain(|event| project_id.is_none_or(|project_id| event.project_id == project_id));
+        for event in &mut events {
+            event.replayed = Some(true);
+        }
+
+        for chunk in events.chunks(chunk_size) {
+            pacer.wait(chunk.len()).await;
+            if let Err(e) = publisher.publish(chunk).await {
+                return Err(format!("Failed to publish {}, {} objects replayed before it: {}", key, replayed.objects, e).into());
+            }
+        }
+        replayed.objects += 1;
+        replayed.events += events.len() as u64;
+        tracing::info!("Replayed {} events of {}", events.len(), key);
+    }
+    Ok(replayed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct FakeArchive(BTreeMap<String, Vec<u8>>);
+
+    #[async_trait]
+    impl ArchiveStore for FakeArchive {
+        async fn list(&self, prefix: &str) -> Result<Vec<String>, Error> {
+            Ok(self.0.keys().filter(|key| key.starts_with(prefix)).cloned().collect())
+        }
+
+        async fn get(&self, key: &str) -> Result<Vec<u8>, Error> {
+            Ok(self.0[key].clone())
+        }
+    }
+
+    /// Records what was published and when
+    #[derive(Default)]
+    struct FakeStream {
+        fail_on: Option<String>,
+        published: Mutex<Vec<(Instant, IngestEventPayload)>>,
+    }
+
+    #[async_trait]
+    impl Publisher for FakeStream {
+        async fn publish(&self, events: &[IngestEventPayload]) -> Result<(), Error> {
+            if events.iter().any(|event| Some(&event.event_type) == self.fail_on.as_ref()) {
+                return Err("ProvisionedThroughputExceededException".into());
+            }
+            let now = Instant::now();
+            let mut published = self.published.lock().unwrap();
+            published.extend(events.iter().map(|event| (now, event.clone())));
+            Ok(())
+        }
+    }
+
+    fn ndjson(project_id: &str, types: &[&str]) -> Vec<u8> {
+        types
+            .iter()
+            .map(|event_type| format!("{{\"projectId\":\"{}\",\"eventType\":\"{}\",\"timestamp\":1}}\n", project_id, event_type))
+            .collect::<String>()
+            .into_bytes()
+    }
+
+    fn archive() -> FakeArchive {
+        FakeArchive(BTreeMap::from([
+            ("fallback/project=p/dt=2024-06-01/1.ndjson".to_string(), ndjson("p", &["a", "b", "c"])),
+            ("fallback/project=p/dt=2024-06-02/2.ndjson".to_string(), ndjson("p", &["d"])),
+            ("fallback/project=p/dt=2024-06-03/3.ndjson".to_string(), ndjson("p", &["late"])),
+            ("fallback/project=q/dt=2024-06-01/4.ndjson".to_string(), ndjson("q", &["other"])),
+            ("fallback/project=p/dt=2024-06-02/5.ndjson".to_string(), b"{".to_vec()),
+            ("fallback/project=p/dt=2024-06-02/6.parquet".to_string(), b"corrupt".to_vec()),
+        ]))
+    }
+
+    fn config() -> ReplayConfig {
+        ReplayConfig {
+            prefix: "fallback".to_string(),
+            project_id: Some("p".to_string()),
+            rate: 2,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_replays_the_range_paced_and_marked() {
+        let (archive, stream) = (archive(), FakeStream::default());
+        let range = DateRange::parse("2024-06-01", "2024-06-02").unwrap();
+        let replayed = replay(&archive, &stream, &config(), &range).await.unwrap();
+        assert_eq!(
+            replayed,
+            Replayed {
+                objects: 3,
+                unreadable: 1,
+                events: 4,
+            }
+        );
+
+        let published = stream.published.lock().unwrap();
+        let types: Vec<_> = published.iter().map(|(_, event)| event.event_type.as_str()).collect();
+        assert_eq!(types, ["a", "b", "c", "d"]);
+        assert!(published.iter().all(|(_, event)| event.replayed == Some(true)));
+        // Two events a second, in chunks of two
+        let seconds: Vec<_> = published.iter().map(|(at, _)| (*at - published[0].0).as_secs()).collect();
+        assert_eq!(seconds, [0, 0, 1, 1]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stops_at_a_failed_object_and_resumes_after_the_last_one() {
+        let archive = archive();
+        let range = DateRange::parse("2024-06-01", "2024-06-02").unwrap();
+        let failing = FakeStream {
+            fail_on: Some("d".to_string()),
+            ..Default::default()
+        };
+        let error = replay(&archive, &failing, &config(), &range).await.unwrap_err();
+        assert!(error.to_string().contains("Failed to publish fallback/project=p/dt=2024-06-02/2.ndjson"));
+
+        let stream = FakeStream::default();
+        let resumed = ReplayConfig {
+            start_after: Some("fallback/project=p/dt=2024-06-01/1.ndjson".to_string()),
+            ..config()
+        };
+        let replayed = replay(&archive, &stream, &resumed, &range).await.unwrap();
+        assert_eq!(replayed.events, 1);
+    }
+}
@@ -0,0 +1,105 @@
+//! Expiring rows of the ClickHouse `events` table.
+//!
+//! A project's rows received before the cutoff are counted, then removed
+//! with a lightweight `DELETE`, as user deletion does (see
+//! `deletion_worker::clickhouse`). The project and cutoff are query
+//! parameters, never spliced into the SQL. The connection settings are the
+//! writer's (`CLICKHOUSE_URL` and the rest).
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use clickhouse_writer::clickhouse::ClickHouseConfig;
+use http_body_util::{BodyExt, Full};
+use hyper_rustls::HttpsConnector;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use lambda_runtime::Error;
+
+use crate::purge::Purger;
+
+/// Filter on the project and cutoff parameters
+const EXPIRED: &str = "project_id = {project:String} \
+    AND received_at < fromUnixTimestamp64Milli({cutoff:Int64})";
+
+/// Removes rows over HTTP
+pub struct ClickHousePurger {
+    http: Client<HttpsConnector<HttpConnector>, Full<Bytes>>,
+    config: ClickHouseConfig,
+}
+
+impl ClickHousePurger {
+    pub fn new(config: ClickHouseConfig) -> Result<Self, Error> {
+        let connector = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_native_roots()?
+            .https_or_http()
+            .enable_http1()
+            .build();
+        Ok(Self {
+            http: Client::builder(TokioExecutor::new()).build(connector),
+            config,
+        })
+    }
+
+    /// The URL for a statement about a project's rows before `cutoff`
+    pub fn url(&self, project_id: &str, cutoff: DateTime<Utc>) -> String {
+        let mut params = url::form_urlencoded::Serializer::new(String::new());
+        params.append_pair("mutations_sync", "2");
+        params.append_pair("param_project", project_id);
+        params.append_pair("param_cutoff", &cutoff.timestamp_millis().to_string());
+        format!("{}/?{}", self.config.url.trim_end_matches('/'), params.finish())
+    }
+
+    fn table(&self) -> String {
+        format!("`{}`.`{}`", self.config.database, self.config.table)
+    }
+
+    /// Runs a statement, returning its output
+    async fn run(&self, project_id: &str, cutoff: DateTime<Utc>, sql: String) -> Result<String, Error> {
+        let request = http::Request::post(self.url(project_id, cutoff))
+            .header("x-clickhouse-user", &self.config.user)
+            .header("x-clickhouse-key", &self.config.password)
+            .body(Full::new(Bytes::from(sql)))?;
+        let response = self.http.request(request).await?;
+        let status = response.status();
+        let body = response.into_body().collect().await?.to_bytes();
+        let body = String::from_utf8_lossy(&body).into_owned();
+        if !status.is_success() {
+            return Err(format!("ClickHouse statement failed with {}: {}", status, body).into());
+        }
+        Ok(body)
+    }
+}
+
+#[async_trait]
+impl Purger for ClickHousePurger {
+    fn name(&self) -> &str {
+        "clickhouse"
+    }
+
+    async fn purge(&self, project_id: &str, cutoff: DateTime<Utc>) -> Result<u64, Error> {
+        let count = format!("SELECT count() FROM {} WHERE {} FORMAT TabSeparated", self.table(), EXPIRED);
+        let rows: u64 = self.run(project_id, cutoff, count).await?.trim().parse()?;
+        if rows > 0 {
+            let delete = format!("DELETE FROM {} WHERE {}", self.table(), EXPIRED);
+            self.run(project_id, cutoff, delete).await?;
+        }
+        Ok(rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_project_and_cutoff_are_parameters() {
+        let purger = ClickHousePurger::new(ClickHouseConfig::default()).unwrap();
+        let url = purger.url("a&b", "2026-09-15T00:00:00Z".parse().unwrap());
+        assert_eq!(
+            url,
+            "http://localhost:8123/?mutations_sync=2&param_project=a%26b&param_cutoff=1789430400000"
+        );
+    }
+}
@@ -0,0 +1,37 @@
+//! Purger configuration.
+
+use ingestion::enrichment::retention::RetentionConfig;
+use ingestion::shared::{env_list, env_var};
+
+/// Attribute holding a state item's last update when its table names none
+pub const DEFAULT_TIMESTAMP_ATTRIBUTE: &str = "ts";
+
+/// Configuration for the purger
+#[derive(Debug, Clone, Default)]
+pub struct PurgerConfig {
+    /// The projects table `packages/admin-api` writes, for their retention
+    pub projects_table: Option<String>,
+    /// Default and per-project retention, as ingestion tags it
+    pub retention: RetentionConfig,
+    /// DynamoDB tables keyed by `pk` = `{project}#{id}`, each with the
+    /// attribute holding an item's last update in epoch milliseconds
+    pub tables: Vec<(String, String)>,
+}
+
+impl PurgerConfig {
+    pub fn from_env() -> Self {
+        Self {
+            projects_table: env_var("API_KEYS_TABLE").filter(|table| !table.is_empty()),
+            retention: RetentionConfig::from_env(),
+            tables: env_list("RETENTION_TABLES").iter().map(|entry| table(entry)).collect(),
+        }
+    }
+}
+
+/// A `RETENTION_TABLES` entry, `table` or `table:attribute`
+fn table(entry: &str) -> (String, String) {
+    match entry.split_once(':') {
+        Some((table, attribute)) => (table.to_string(), attribute.to_string()),
+        None => (entry.to_string(), DEFAULT_TIMESTAMP_ATTRIBUTE.to_string()),
+    }
+}
@@ -0,0 +1,68 @@
+//! Expiring per-visitor state in DynamoDB.
+//!
+//! The tables are keyed by `pk` = `{project}#{id}`, like those user
+//! deletion clears, and each names the attribute holding an item's last
+//! update (`ts` unless configured). A project's items last updated before
+//! the cutoff are found with a filtered scan and deleted.
+
+use async_trait::async_trait;
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use chrono::{DateTime, Utc};
+use lambda_runtime::Error;
+
+use crate::purge::Purger;
+
+/// DynamoDB tables and their timestamp attributes
+pub struct DynamoPurger {
+    client: DynamoClient,
+    tables: Vec<(String, String)>,
+}
+
+impl DynamoPurger {
+    pub fn new(client: DynamoClient, tables: Vec<(String, String)>) -> Self {
+        Self { client, tables }
+    }
+}
+
+#[async_trait]
+impl Purger for DynamoPurger {
+    fn name(&self) -> &str {
+        "dynamodb"
+    }
+
+    async fn purge(&self, project_id: &str, cutoff: DateTime<Utc>) -> Result<u64, Error> {
+        let mut deleted = 0;
+        for (table, attribute) in &self.tables {
+            let mut start_key = None;
+            loop {
+                let output = self
+                    .client
+                    .scan()
+                    .table_name(table)
+                    .filter_expression("begins_with(pk, :project) AND #updated < :cutoff")
+                    .projection_expression("pk")
+                    .expression_attribute_names("#updated", attribute)
+                    .expression_attribute_values(":project", AttributeValue::S(format!("{}#", project_id)))
+                    .expression_attribute_values(":cutoff", AttributeValue::N(cutoff.timestamp_millis().to_string()))
+                    .set_exclusive_start_key(start_key)
+                    .send()
+                    .await?;
+                for pk in output.items().iter().filter_map(|item| item.get("pk")) {
+                    self.client
+                        .delete_item()
+                        .table_name(table)
+                        .key("pk", pk.clone())
+                        .send()
+                        .await?;
+                    deleted += 1;
+                }
+                start_key = output.last_evaluated_key().cloned();
+                if start_key.is_none() {
+                    break;
+                }
+            }
+        }
+        Ok(deleted)
+    }
+}
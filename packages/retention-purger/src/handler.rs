@@ -0,0 +1,167 @@
+//! The scheduled invocation handler.
+//!
+//! Resolves every project's policy, then has each store purge each
+//! project. A failing store doesn't stop the others, but fails the
+//! invocation once they've run, so Lambda's retries of the scheduled event
+//! try again; stores already purged find nothing the second time.
+
+use admin_api::projects::ProjectStore;
+use chrono::{DateTime, Utc};
+use lambda_runtime::Error;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+use crate::config::PurgerConfig;
+use crate::policy::policies;
+use crate::purge::Purger;
+
+/// Where retention comes from and what it's enforced on
+pub struct Stores {
+    /// The admin API's projects, when the table is configured
+    pub projects: Option<Box<dyn ProjectStore>>,
+    pub purgers: Vec<Box<dyn Purger>>,
+    pub config: PurgerConfig,
+}
+
+/// Purges everything expired as of `now`, returning a summary
+pub async fn handle(stores: &Stores, now: DateTime<Utc>) -> Result<Value, Error> {
+    let projects = match stores.projects {
+        Some(ref projects) => projects.list().await?,
+        None => Vec::new(),
+    };
+    let policies = policies(&projects, &stores.config.retention);
+
+    let mut removed: BTreeMap<String, u64> = BTreeMap::new();
+    let mut failures = 0;
+    for policy in &policies {
+        let cutoff = policy.cutoff(now);
+        for purger in &stores.purgers {
+            match purger.purge(&policy.project_id, cutoff).await {
+                Ok(count) => *removed.entry(purger.name().to_string()).or_default() += count,
+                Err(e) => {
+                    tracing::error!("Purging {} from {} failed: {}", policy.project_id, purger.name(), e);
+                    failures += 1;
+                }
+            }
+        }
+    }
+    if failures > 0 {
+        return Err(format!("{} purges failed", failures).into());
+    }
+
+    tracing::info!("Purged {} projects: {:?}", policies.len(), removed);
+    Ok(serde_json::json!({ "projects": policies.len(), "removed": removed }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use admin_api::projects::{Project, ProjectSettings};
+    use async_trait::async_trait;
+    use ingestion::enrichment::retention::RetentionConfig;
+    use serde_json::json;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    struct FakeProjects(Vec<Project>);
+
+    #[async_trait]
+    impl ProjectStore for FakeProjects {
+        async fn create(&self, _: &Project) -> Result<bool, Error> {
+            unreachable!()
+        }
+
+        async fn get(&self, _: &str) -> Result<Option<Project>, Error> {
+            unreachable!()
+        }
+
+        async fn list(&self) -> Result<Vec<Project>, Error> {
+            Ok(self.0.clone())
+        }
+
+        async fn update(&self, _: &Project, _: &str) -> Result<(), Error> {
+            unreachable!()
+        }
+
+        async fn delete(&self, _: &Project) -> Result<(), Error> {
+            unreachable!()
+        }
+    }
+
+    type Calls = Arc<Mutex<Vec<(String, DateTime<Utc>)>>>;
+
+    /// Records the cutoff of each project it's asked to purge
+    #[derive(Clone, Default)]
+    struct Recording(Calls, bool);
+
+    #[async_trait]
+    impl Purger for Recording {
+        fn name(&self) -> &str {
+            if self.1 { "failing" } else { "recording" }
+        }
+
+        async fn purge(&self, project_id: &str, cutoff: DateTime<Utc>) -> Result<u64, Error> {
+            self.0.lock().unwrap().push((project_id.to_string(), cutoff));
+            if self.1 {
+                return Err("unavailable".into());
+            }
+            Ok(2)
+        }
+    }
+
+    fn project(project_id: &str, retention_days: Option<u32>) -> Project {
+        let settings = ProjectSettings {
+            retention_days,
+            ..Default::default()
+        };
+        Project::new(project_id.to_string(), project_id.to_string(), settings, 0).0
+    }
+
+    fn stores(purgers: Vec<Box<dyn Purger>>) -> Stores {
+        Stores {
+            projects: Some(Box::new(FakeProjects(vec![
+                project("acme", Some(30)),
+                project("globex", None),
+                project("initech", None),
+            ]))),
+            purgers,
+            config: PurgerConfig {
+                retention: RetentionConfig {
+                    enabled: false,
+                    default_days: Some(365),
+                    projects: HashMap::from([("initech".to_string(), 7), ("hooli".to_string(), 90)]),
+                },
+                ..Default::default()
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_purges_each_project_at_its_cutoff() {
+        let recording = Recording::default();
+        let now: DateTime<Utc> = "2026-10-16T03:00:00Z".parse().unwrap();
+
+        let summary = handle(&stores(vec![Box::new(recording.clone())]), now).await.unwrap();
+        assert_eq!(summary, json!({ "projects": 4, "removed": { "recording": 8 } }));
+
+        let cutoffs: Vec<(String, String)> = recording
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(project_id, cutoff)| (project_id.clone(), cutoff.date_naive().to_string()))
+            .collect();
+        let expected = [("acme", "2026-09-16"), ("globex", "2025-10-16"), ("hooli", "2026-07-18"), ("initech", "2026-10-09")];
+        assert_eq!(cutoffs, expected.map(|(p, d)| (p.to_string(), d.to_string())));
+    }
+
+    #[tokio::test]
+    async fn test_a_failing_store_fails_the_run_after_the_others() {
+        let recording = Recording::default();
+        let failing = Recording(Arc::default(), true);
+        let stores = stores(vec![Box::new(failing), Box::new(recording.clone())]);
+
+        assert!(handle(&stores, Utc::now()).await.is_err());
+        assert_eq!(recording.0.lock().unwrap().len(), 4);
+    }
+}
@@ -0,0 +1,113 @@
+//! Expiring day partitions of the Parquet data lake.
+//!
+//! The Parquet writer partitions files by the day they were received,
+//! `project_id=…/dt=YYYY-MM-DD/hr=HH` under `PARQUET_PREFIX` of
+//! `PARQUET_BUCKET`, so a day older than the cutoff's can be deleted whole
+//! without reading its files. The cutoff's own day is kept until the next
+//! run. Like user deletion, this only removes the current versions in a
+//! versioned bucket; a lifecycle rule must expire the rest.
+
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, Utc};
+use deletion_worker::lake::LakeStore;
+use lambda_runtime::Error;
+use parquet_writer::files::project_path;
+
+use crate::purge::Purger;
+
+/// The receive day of the partition a key is in
+pub fn partition_day(key: &str) -> Option<NaiveDate> {
+    key.split('/')
+        .find_map(|segment| segment.strip_prefix("dt="))
+        .and_then(|day| NaiveDate::parse_from_str(day, "%Y-%m-%d").ok())
+}
+
+/// Deletes a project's expired partitions
+pub struct LakePurger {
+    store: Box<dyn LakeStore>,
+    prefix: String,
+}
+
+impl LakePurger {
+    pub fn new(store: Box<dyn LakeStore>, prefix: String) -> Self {
+        Self { store, prefix }
+    }
+}
+
+#[async_trait]
+impl Purger for LakePurger {
+    fn name(&self) -> &str {
+        "lake"
+    }
+
+    async fn purge(&self, project_id: &str, cutoff: DateTime<Utc>) -> Result<u64, Error> {
+        let prefix = format!("{}/{}/", self.prefix.trim_end_matches('/'), project_path(project_id));
+        let cutoff = cutoff.date_naive();
+        let mut deleted = 0;
+        for key in self.store.list(&prefix).await? {
+            if partition_day(&key).is_some_and(|day| day < cutoff) {
+                self.store.delete(&key).await?;
+                deleted += 1;
+            }
+        }
+        Ok(deleted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default, Clone)]
+    struct FakeLake(Arc<Mutex<BTreeMap<String, Vec<u8>>>>);
+
+    #[async_trait]
+    impl LakeStore for FakeLake {
+        async fn list(&self, prefix: &str) -> Result<Vec<String>, Error> {
+            Ok(self.0.lock().unwrap().keys().filter(|key| key.starts_with(prefix)).cloned().collect())
+        }
+
+        async fn get(&self, key: &str) -> Result<Vec<u8>, Error> {
+            Ok(self.0.lock().unwrap()[key].clone())
+        }
+
+        async fn put(&self, key: &str, body: Vec<u8>) -> Result<(), Error> {
+            self.0.lock().unwrap().insert(key.to_string(), body);
+            Ok(())
+        }
+
+        async fn delete(&self, key: &str) -> Result<(), Error> {
+            self.0.lock().unwrap().remove(key);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_deletes_days_before_the_cutoff() {
+        let lake = FakeLake::default();
+        for key in [
+            "events/project_id=p/dt=2026-09-14/hr=23/1-2.parquet",
+            "events/project_id=p/dt=2026-09-15/hr=00/3-3.parquet",
+            "events/project_id=p/dt=2026-09-16/hr=10/4-4.parquet",
+            "events/project_id=p2/dt=2026-01-01/hr=00/1-1.parquet",
+        ] {
+            lake.put(key, Vec::new()).await.unwrap();
+        }
+
+        let purger = LakePurger::new(Box::new(lake.clone()), "events/".to_string());
+        let cutoff = "2026-09-15T12:00:00Z".parse().unwrap();
+        assert_eq!(purger.purge("p", cutoff).await.unwrap(), 1);
+
+        let left = lake.list("events/").await.unwrap();
+        assert_eq!(
+            left,
+            [
+                "events/project_id=p/dt=2026-09-15/hr=00/3-3.parquet",
+                "events/project_id=p/dt=2026-09-16/hr=10/4-4.parquet",
+                "events/project_id=p2/dt=2026-01-01/hr=00/1-1.parquet",
+            ]
+        );
+    }
+}
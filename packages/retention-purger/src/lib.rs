@@ -0,0 +1,21 @@
+//! Per-project data retention.
+//!
+//! Runs once a day on an EventBridge schedule. A project's retention is
+//! the one ingestion tags its events with (see
+//! `ingestion::enrichment::retention`): `PROJECT_RETENTION_DAYS`, else the
+//! `retentionDays` set through `packages/admin-api`, else
+//! `RETENTION_DEFAULT_DAYS` (see [`policy`]); projects with none are kept
+//! forever. Whatever a project sent before its cutoff, that many days ago,
+//! is then deleted from every configured store (see [`purge`]): day
+//! partitions of the Parquet data lake ([`lake`]), rows of the ClickHouse
+//! `events` table ([`clickhouse`]) and per-visitor state in DynamoDB
+//! ([`dynamo`]). Every step is idempotent, so a failed run is retried
+//! whole (see [`handler`]).
+
+pub mod clickhouse;
+pub mod config;
+pub mod dynamo;
+pub mod handler;
+pub mod lake;
+pub mod policy;
+pub mod purge;
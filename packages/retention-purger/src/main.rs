@@ -0,0 +1,59 @@
+use lambda_runtime::{run, service_fn, Error, LambdaEvent};
+use serde_json::Value;
+use std::sync::Arc;
+
+use admin_api::projects::{DynamoProjectStore, ProjectStore};
+use aws_sdk_dynamodb::Client as DynamoClient;
+use aws_sdk_s3::Client as S3Client;
+use clickhouse_writer::clickhouse::ClickHouseConfig;
+use deletion_worker::lake::S3LakeStore;
+use ingestion::shared::env_var;
+use parquet_writer::files::ParquetWriterConfig;
+use retention_purger::clickhouse::ClickHousePurger;
+use retention_purger::config::PurgerConfig;
+use retention_purger::dynamo::DynamoPurger;
+use retention_purger::handler::{handle, Stores};
+use retention_purger::lake::LakePurger;
+use retention_purger::purge::Purger;
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    // Initialize tracing
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"))
+        )
+        .json()
+        .init();
+
+    let config = PurgerConfig::from_env();
+    let aws = aws_config::load_from_env().await;
+    let dynamo = DynamoClient::new(&aws);
+
+    let mut purgers: Vec<Box<dyn Purger>> = Vec::new();
+    let lake = ParquetWriterConfig::from_env();
+    if !lake.bucket.is_empty() {
+        purgers.push(Box::new(LakePurger::new(
+            Box::new(S3LakeStore::new(S3Client::new(&aws), lake.bucket)),
+            lake.prefix,
+        )));
+    }
+    if env_var("CLICKHOUSE_URL").is_some() {
+        purgers.push(Box::new(ClickHousePurger::new(ClickHouseConfig::from_env())?));
+    }
+    if !config.tables.is_empty() {
+        purgers.push(Box::new(DynamoPurger::new(dynamo.clone(), config.tables.clone())));
+    }
+    let projects: Option<Box<dyn ProjectStore>> = config
+        .projects_table
+        .clone()
+        .map(|table| Box::new(DynamoProjectStore::new(dynamo, table)) as Box<dyn ProjectStore>);
+    let stores = Arc::new(Stores { projects, purgers, config });
+
+    run(service_fn(move |_: LambdaEvent<Value>| {
+        let stores = stores.clone();
+        async move { handle(&stores, chrono::Utc::now()).await }
+    }))
+    .await
+}
@@ -0,0 +1,39 @@
+//! Which projects expire what.
+
+use admin_api::projects::Project;
+use chrono::{DateTime, Duration, Utc};
+use ingestion::enrichment::retention::RetentionConfig;
+use std::collections::BTreeMap;
+
+/// How long a project keeps its raw events
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Policy {
+    pub project_id: String,
+    pub days: u32,
+}
+
+impl Policy {
+    /// Anything received before this has expired
+    pub fn cutoff(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        now - Duration::days(i64::from(self.days))
+    }
+}
+
+/// The retention of every project in the table or the config, resolved as
+/// ingestion does; projects without one have no policy
+pub fn policies(projects: &[Project], config: &RetentionConfig) -> Vec<Policy> {
+    let mut settings: BTreeMap<&str, Option<u32>> =
+        config.projects.keys().map(|project_id| (project_id.as_str(), None)).collect();
+    for project in projects {
+        settings.insert(&project.project_id, project.settings.retention_days);
+    }
+    settings
+        .into_iter()
+        .filter_map(|(project_id, days)| {
+            Some(Policy {
+                project_id: project_id.to_string(),
+                days: config.days_for(project_id, days)?,
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,15 @@
+//! What each store deletes.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use lambda_runtime::Error;
+
+/// A store holding a project's events or state
+#[async_trait]
+pub trait Purger: Send + Sync {
+    /// Name in the summary
+    fn name(&self) -> &str;
+    /// Deletes what a project sent before `cutoff`, returning how many
+    /// files, rows or items went
+    async fn purge(&self, project_id: &str, cutoff: DateTime<Utc>) -> Result<u64, Error>;
+}
@@ -0,0 +1,259 @@
+//! The Kinesis batch handler and the idle-session sweep.
+//!
+//! A batch is decoded (unpacking KPL aggregates), grouped by visitor and
+//! sorted by timestamp, then stitched onto each visitor's open session.
+//! The derived events are written before the sessions are saved; if either
+//! fails, the whole batch is reported failed and retried. The retry writes
+//! the same derived events again under the same ids, but counts the batch
+//! again into sessions that were already saved. Events of a visitor must
+//! reach one shard in order (`PARTITION_KEY_STRATEGY` `project` or `user`).
+//!
+//! A session whose visitor never comes back is closed by the sweep, run on
+//! a schedule: sessions untouched for the inactivity window are removed
+//! and their `session_end` events written.
+
+use aws_lambda_events::event::kinesis::KinesisEvent;
+use aws_lambda_events::event::streams::{KinesisBatchItemFailure, KinesisEventResponse};
+use ingestion::aggregation;
+use ingestion::models::IngestEventPayload;
+use lambda_runtime::Error;
+use std::collections::BTreeMap;
+
+use crate::emit::SessionEmitter;
+use crate::sessions::{stitch, visitor, Session, SessionizerConfig};
+use crate::store::SessionStore;
+
+/// Events of a batch by project and visitor, sorted by timestamp
+fn group(events: &[IngestEventPayload]) -> BTreeMap<(&str, &str), Vec<&IngestEventPayload>> {
+    let mut visitors: BTreeMap<(&str, &str), Vec<&IngestEventPayload>> = BTreeMap::new();
+    for event in events {
+        if event.context.as_ref().is_some_and(|c| c.is_bot == Some(true)) {
+            continue;
+        }
+        if let Some(visitor) = visitor(event) {
+            visitors.entry((&event.project_id, visitor)).or_default().push(event);
+        }
+    }
+    for events in visitors.values_mut() {
+        events.sort_by_key(|event| event.timestamp);
+    }
+    visitors
+}
+
+async fn sessionize(
+    events: &[IngestEventPayload],
+    store: &dyn SessionStore,
+    emitter: &dyn SessionEmitter,
+    config: &SessionizerConfig,
+    now: i64,
+) -> Result<usize, Error> {
+    let mut open: Vec<Session> = Vec::new();
+    let mut derived = Vec::new();
+    for ((project_id, visitor), events) in group(events) {
+        let current = store.get(project_id, visitor).await?;
+        let (session, session_events) = stitch(current, &events, visitor, config);
+        open.extend(session);
+        derived.extend(session_events);
+    }
+
+    if !derived.is_empty() {
+        emitter.emit(&derived).await?;
+    }
+    for session in &open {
+        store.put(session, now).await?;
+    }
+    Ok(open.len())
+}
+
+/// Sessionizes a batch, reporting it failed if it couldn't be stored
+pub async fn handle(
+    event: KinesisEvent,
+    store: &dyn SessionStore,
+    emitter: &dyn SessionEmitter,
+    config: &SessionizerConfig,
+    now: i64,
+) -> KinesisEventResponse {
+    let mut events = Vec::new();
+    for record in &event.records {
+        for data in aggregation::decode(&record.kinesis.data.0) {
+            match serde_json::from_slice::<IngestEventPayload>(&data) {
+                Ok(event) => events.push(event),
+                Err(e) => tracing::warn!("Skipping a record that isn't a JSON event: {}", e),
+            }
+        }
+    }
+
+    match sessionize(&events, store, emitter, config, now).await {
+        Ok(sessions) => {
+            tracing::info!("Stitched {} events into {} open sessions", events.len(), sessions);
+            KinesisEventResponse {
+                batch_item_failures: Vec::new(),
+            }
+        }
+        Err(e) => {
+            tracing::error!("Failed to sessionize the batch, retrying it: {}", e);
+            let first = event.records.first().and_then(|record| record.kinesis.sequence_number.clone());
+            KinesisEventResponse {
+                batch_item_failures: vec![KinesisBatchItemFailure { item_identifier: first }],
+            }
+        }
+    }
+}
+
+/// Closes sessions idle for the inactivity window, returning how many
+pub async fn sweep(
+    store: &dyn SessionStore,
+    emitter: &dyn SessionEmitter,
+    config: &SessionizerConfig,
+    now: i64,
+) -> Result<usize, Error> {
+    let cutoff = now - config.inactivity.as_millis() as i64;
+    let mut closed = 0;
+    for session in store.idle(cutoff).await? {
+        // A batch may have extended the session since the scan
+        if !store.close(&session, cutoff).await? {
+            continue;
+        }
+        if let Err(e) = emitter.emit(&[session.end_event()]).await {
+            // Put it back so the next sweep tries again
+            store.put(&session, cutoff - 1).await?;
+            return Err(e);
+        }
+        closed += 1;
+    }
+    tracing::info!("Closed {} idle sessions", closed);
+    Ok(closed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use serde_json::json;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct FakeStore {
+        sessions: Mutex<BTreeMap<(String, String), (Session, i64)>>,
+    }
+
+    #[async_trait]
+    impl SessionStore for FakeStore {
+        async fn get(&self, project_id: &str, visitor: &str) -> Result<Option<Session>, Error> {
+            let sessions = self.sessions.lock().unwrap();
+            Ok(sessions.get(&(project_id.to_string(), visitor.to_string())).map(|(s, _)| s.clone()))
+        }
+
+        async fn put(&self, session: &Session, now: i64) -> Result<(), Error> {
+            let key = (session.project_id.clone(), session.visitor.clone());
+            self.sessions.lock().unwrap().insert(key, (session.clone(), now));
+            Ok(())
+        }
+
+        async fn idle(&self, cutoff: i64) -> Result<Vec<Session>, Error> {
+            let sessions = self.sessions.lock().unwrap();
+            Ok(sessions.values().filter(|(_, at)| *at < cutoff).map(|(s, _)| s.clone()).collect())
+        }
+
+        async fn close(&self, session: &Session, cutoff: i64) -> Result<bool, Error> {
+            let key = (session.project_id.clone(), session.visitor.clone());
+            let mut sessions = self.sessions.lock().unwrap();
+            let idle = sessions.get(&key).is_some_and(|(_, at)| *at < cutoff);
+            if idle {
+                sessions.remove(&key);
+            }
+            Ok(idle)
+        }
+    }
+
+    #[derive(Default)]
+    struct FakeEmitter {
+        fail: bool,
+        events: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl SessionEmitter for FakeEmitter {
+        async fn emit(&self, events: &[IngestEventPayload]) -> Result<(), Error> {
+            if self.fail {
+                return Err("ProvisionedThroughputExceededException".into());
+            }
+            let mut emitted = self.events.lock().unwrap();
+            emitted.extend(events.iter().map(|e| format!("{}:{}", e.anonymous_id.as_deref().unwrap(), e.event_type)));
+            Ok(())
+        }
+    }
+
+    fn batch(events: &[(&str, i64)]) -> KinesisEvent {
+        let records: Vec<_> = events
+            .iter()
+            .enumerate()
+            .map(|(index, (visitor, minute))| {
+                let event = json!({
+                    "projectId": "p",
+                    "eventType": "pageview",
+                    "timestamp": 1_700_000_000_000_i64 + minute * 60_000,
+                    "anonymousId": visitor,
+                });
+                let data = aws_lambda_events::encodings::Base64Data(serde_json::to_vec(&event).unwrap());
+                json!({
+                    "kinesis": {
+                        "sequenceNumber": index.to_string(),
+                        "data": data,
+                        "approximateArrivalTimestamp": 1_700_000_000.0,
+                    },
+                })
+            })
+            .collect();
+        serde_json::from_value(json!({ "Records": records })).unwrap()
+    }
+
+    const NOW: i64 = 1_700_000_000_000;
+
+    #[tokio::test]
+    async fn test_sessionizes_across_batches() {
+        let (store, emitter) = (FakeStore::default(), FakeEmitter::default());
+        let config = SessionizerConfig::default();
+
+        handle(batch(&[("a", 5), ("b", 0), ("a", 0)]), &store, &emitter, &config, NOW).await;
+        let response = handle(batch(&[("a", 20), ("b", 40)]), &store, &emitter, &config, NOW).await;
+
+        assert!(response.batch_item_failures.is_empty());
+        assert_eq!(*emitter.events.lock().unwrap(), ["a:session_start", "b:session_start", "b:session_end", "b:session_start"]);
+        let a = store.get("p", "a").await.unwrap().unwrap();
+        assert_eq!((a.events, a.duration_ms()), (3, 20 * 60_000));
+    }
+
+    #[tokio::test]
+    async fn test_emit_failure_retries_the_batch() {
+        let store = FakeStore::default();
+        let emitter = FakeEmitter {
+            fail: true,
+            ..Default::default()
+        };
+        let response = handle(batch(&[("a", 0)]), &store, &emitter, &SessionizerConfig::default(), NOW).await;
+
+        assert_eq!(
+            response.batch_item_failures,
+            [KinesisBatchItemFailure {
+                item_identifier: Some("0".to_string()),
+            }]
+        );
+        assert!(store.get("p", "a").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_sweep_closes_idle_sessions() {
+        let (store, emitter) = (FakeStore::default(), FakeEmitter::default());
+        let config = SessionizerConfig::default();
+        handle(batch(&[("a", 0)]), &store, &emitter, &config, NOW).await;
+        handle(batch(&[("b", 0)]), &store, &emitter, &config, NOW + 20 * 60_000).await;
+
+        let closed = sweep(&store, &emitter, &config, NOW + 31 * 60_000).await.unwrap();
+
+        assert_eq!(closed, 1);
+        assert_eq!(emitter.events.lock().unwrap().last().unwrap(), "a:session_end");
+        assert!(store.get("p", "a").await.unwrap().is_none());
+        assert!(store.get("p", "b").await.unwrap().is_some());
+    }
+}
@@ -0,0 +1,14 @@
+//! Kinesis sessionization.
+//!
+//! Consumes the ingest stream in Lambda batches and stitches each
+//! visitor's events into sessions (see [`sessions`]), keeping open
+//! sessions in DynamoDB (see [`store`]). Starting and ending a session
+//! write `session_start` and `session_end` events, with entry and exit
+//! pages and duration, to a sessions stream (see [`emit`]). The same
+//! function, invoked by a schedule, closes sessions whose visitors went
+//! quiet (see [`handler`]).
+
+pub mod emit;
+pub mod handler;
+pub mod sessions;
+pub mod store;
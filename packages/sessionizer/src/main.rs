@@ -0,0 +1,54 @@
+use lambda_runtime::{run, service_fn, Error, LambdaEvent};
+use std::sync::Arc;
+
+use aws_lambda_events::event::kinesis::KinesisEvent;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use aws_sdk_kinesis::Client as KinesisClient;
+use ingestion::retry::RetryConfig;
+use sessionizer::emit::KinesisEmitter;
+use sessionizer::handler::{handle, sweep};
+use sessionizer::sessions::SessionizerConfig;
+use sessionizer::store::DynamoSessionStore;
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    // Initialize tracing
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"))
+        )
+        .json()
+        .init();
+
+    let config = Arc::new(SessionizerConfig::from_env());
+    if config.table_name.is_empty() {
+        return Err("SESSIONS_TABLE environment variable not set".into());
+    }
+    if config.stream_name.is_empty() {
+        return Err("SESSIONS_STREAM environment variable not set".into());
+    }
+    let aws = aws_config::load_from_env().await;
+    let store = Arc::new(DynamoSessionStore::new(DynamoClient::new(&aws), config.table_name.clone()));
+    let emitter = Arc::new(KinesisEmitter::new(
+        KinesisClient::new(&aws),
+        config.stream_name.clone(),
+        RetryConfig::from_env(),
+    ));
+
+    // Kinesis batches carry `Records`; anything else is the scheduled sweep
+    run(service_fn(move |event: LambdaEvent<serde_json::Value>| {
+        let (store, emitter, config) = (store.clone(), emitter.clone(), config.clone());
+        async move {
+            let now = chrono::Utc::now().timestamp_millis();
+            if event.payload.get("Records").is_some() {
+                let batch: KinesisEvent = serde_json::from_value(event.payload)?;
+                let response = handle(batch, store.as_ref(), emitter.as_ref(), &config, now).await;
+                return Ok::<_, Error>(serde_json::to_value(response)?);
+            }
+            let closed = sweep(store.as_ref(), emitter.as_ref(), &config, now).await?;
+            Ok(serde_json::json!({ "closed": closed }))
+        }
+    }))
+    .await
+}
@@ -0,0 +1,278 @@
+//! Stitching a visitor's events into sessions.
+//!
+//! A visitor is an anonymous id, else a user id, within a project. Their
+//! events belong to one session until a gap of more than the inactivity
+//! window (`SESSION_INACTIVITY_MINUTES`, default 30) between event
+//! timestamps; the next event starts a new session. A session tracks its
+//! entry and exit pages, its first and last event times and its event and
+//! pageview counts. Starting a session yields a `session_start` event and
+//! closing one a `session_end` event carrying the totals. Bot traffic is
+//! left out.
+
+use ingestion::models::IngestEventPayload;
+use ingestion::shared::{env_list, env_or, env_var};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Configuration for the sessionizer
+#[derive(Debug, Clone)]
+pub struct SessionizerConfig {
+    /// DynamoDB table of open sessions
+    pub table_name: String,
+    /// Stream the derived events are written to
+    pub stream_name: String,
+    /// Gap between events that ends a session
+    pub inactivity: Duration,
+    /// Event types counted as pageviews
+    pub pageview_events: Vec<String>,
+}
+
+impl Default for SessionizerConfig {
+    fn default() -> Self {
+        Self {
+            table_name: String::new(),
+            stream_name: String::new(),
+            inactivity: Duration::from_secs(30 * 60),
+            pageview_events: vec!["pageview".to_string()],
+        }
+    }
+}
+
+impl SessionizerConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let pageview_events = env_list("SESSION_PAGEVIEW_EVENTS");
+        Self {
+            table_name: env_var("SESSIONS_TABLE").unwrap_or_default(),
+            stream_name: env_var("SESSIONS_STREAM").unwrap_or_default(),
+            inactivity: Duration::from_secs(60 * env_or("SESSION_INACTIVITY_MINUTES", 30)),
+            pageview_events: if pageview_events.is_empty() { defaults.pageview_events } else { pageview_events },
+        }
+    }
+
+    fn inactivity_ms(&self) -> i64 {
+        self.inactivity.as_millis() as i64
+    }
+}
+
+/// A visitor's session
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+    pub project_id: String,
+    pub visitor: String,
+    pub session_id: String,
+    pub user_id: Option<String>,
+    pub anonymous_id: Option<String>,
+    /// Time of the first event, in milliseconds since the epoch
+    pub started_at: i64,
+    /// Time of the last event, in milliseconds since the epoch
+    pub last_seen: i64,
+    pub entry_page: Option<String>,
+    pub exit_page: Option<String>,
+    pub events: u64,
+    pub pageviews: u64,
+}
+
+/// The visitor an event belongs to
+pub fn visitor(event: &IngestEventPayload) -> Option<&str> {
+    event.anonymous_id.as_deref().or(event.user_id.as_deref())
+}
+
+/// Path of the viewed page: `context.page.path`, else the path of its url
+pub fn page(event: &IngestEventPayload) -> Option<String> {
+    let page = event.context.as_ref()?.page.as_ref()?;
+    match page.path {
+        Some(ref path) if !path.is_empty() => Some(path.clone()),
+        _ => Some(url::Url::parse(page.url.as_deref()?).ok()?.path().to_string()),
+    }
+}
+
+/// A session's id, derived from where it starts so a retried batch
+/// produces the same id
+pub fn session_id(project_id: &str, visitor: &str, started_at: i64) -> String {
+    let digest = Sha256::digest(format!("{}\n{}\n{}", project_id, visitor, started_at));
+    hex::encode(&digest[..16])
+}
+
+impl Session {
+    /// A session starting with `event`
+    pub fn start(event: &IngestEventPayload, visitor: &str, config: &SessionizerConfig) -> Self {
+        let mut session = Self {
+            project_id: event.project_id.clone(),
+            visitor: visitor.to_string(),
+            session_id: session_id(&event.project_id, visitor, event.timestamp),
+            user_id: None,
+            anonymous_id: None,
+            started_at: event.timestamp,
+            last_seen: event.timestamp,
+            entry_page: None,
+            exit_page: None,
+            events: 0,
+            pageviews: 0,
+        };
+        session.add(event, config);
+        session
+    }
+
+    /// Whether `event` comes too long after the session to belong to it
+    pub fn expired_by(&self, event: &IngestEventPayload, config: &SessionizerConfig) -> bool {
+        event.timestamp - self.last_seen > config.inactivity_ms()
+    }
+
+    /// Counts an event into the session. Late events extend it backwards.
+    pub fn add(&mut self, event: &IngestEventPayload, config: &SessionizerConfig) {
+        self.events += 1;
+        if event.user_id.is_some() {
+            self.user_id.clone_from(&event.user_id);
+        }
+        if event.anonymous_id.is_some() {
+            self.anonymous_id.clone_from(&event.anonymous_id);
+        }
+        let page = config
+            .pageview_events
+            .contains(&event.event_type)
+            .then(|| page(event))
+            .flatten();
+        if config.pageview_events.contains(&event.event_type) {
+            self.pageviews += 1;
+        }
+        if page.is_some() && (self.entry_page.is_none() || event.timestamp < self.started_at) {
+            self.entry_page.clone_from(&page);
+        }
+        if page.is_some() && (self.exit_page.is_none() || event.timestamp >= self.last_seen) {
+            self.exit_page = page;
+        }
+        self.started_at = self.started_at.min(event.timestamp);
+        self.last_seen = self.last_seen.max(event.timestamp);
+    }
+
+    pub fn duration_ms(&self) -> i64 {
+        self.last_seen - self.started_at
+    }
+
+    /// The derived event marking the session's start or end
+    fn derived(&self, event_type: &str, timestamp: i64, properties: serde_json::Value) -> IngestEventPayload {
+        let properties: HashMap<String, serde_json::Value> =
+            serde_json::from_value(properties).expect("properties are an object");
+        IngestEventPayload {
+            event_id: Some(format!("{}:{}", self.session_id, event_type)),
+            project_id: self.project_id.clone(),
+            event_type: event_type.to_string(),
+            timestamp,
+            user_id: self.user_id.clone(),
+            anonymous_id: self.anonymous_id.clone(),
+            properties: Some(properties),
+            ..Default::default()
+        }
+    }
+
+    /// The `session_start` event
+    pub fn start_event(&self) -> IngestEventPayload {
+        self.derived(
+            "session_start",
+            self.started_at,
+            json!({"session_id": self.session_id, "entry_page": self.entry_page}),
+        )
+    }
+
+    /// The `session_end` event, with the session's totals
+    pub fn end_event(&self) -> IngestEventPayload {
+        self.derived(
+            "session_end",
+            self.last_seen,
+            json!({
+                "session_id": self.session_id,
+                "entry_page": self.entry_page,
+                "exit_page": self.exit_page,
+                "duration_ms": self.duration_ms(),
+                "event_count": self.events,
+                "pageview_count": self.pageviews,
+            }),
+        )
+    }
+}
+
+/// Folds a visitor's events, sorted by timestamp, into their open session.
+/// Returns the session left open and the derived events, in order.
+pub fn stitch(
+    open: Option<Session>,
+    events: &[&IngestEventPayload],
+    visitor: &str,
+    config: &SessionizerConfig,
+) -> (Option<Session>, Vec<IngestEventPayload>) {
+    let mut derived = Vec::new();
+    let mut current = open;
+    for event in events {
+        match current {
+            Some(ref mut session) if !session.expired_by(event, config) => session.add(event, config),
+            _ => {
+                if let Some(ended) = current.take() {
+                    derived.push(ended.end_event());
+                }
+                let session = Session::start(event, visitor, config);
+                derived.push(session.start_event());
+                current = Some(session);
+            }
+        }
+    }
+    (current, derived)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(event_type: &str, minute: i64, path: &str) -> IngestEventPayload {
+        serde_json::from_value(json!({
+            "projectId": "p",
+            "eventType": event_type,
+            "timestamp": 1_700_000_000_000_i64 + minute * 60_000,
+            "anonymousId": "v1",
+            "context": {"page": {"url": format!("https://a.com{}?x=1", path)}},
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_stitches_sessions_on_inactivity() {
+        let config = SessionizerConfig::default();
+        let events = [
+            event("pageview", 0, "/"),
+            event("click", 5, "/"),
+            event("pageview", 10, "/pricing"),
+            event("pageview", 45, "/docs"),
+        ];
+        let events: Vec<_> = events.iter().collect();
+
+        let (open, derived) = stitch(None, &events, "v1", &config);
+
+        let types: Vec<_> = derived.iter().map(|e| e.event_type.as_str()).collect();
+        assert_eq!(types, ["session_start", "session_end", "session_start"]);
+        let end = derived[1].properties.as_ref().unwrap();
+        assert_eq!(end["entry_page"], "/");
+        assert_eq!(end["exit_page"], "/pricing");
+        assert_eq!(end["duration_ms"], 600_000);
+        assert_eq!((end["event_count"].clone(), end["pageview_count"].clone()), (json!(3), json!(2)));
+        assert_eq!(derived[1].event_id, Some(format!("{}:session_end", end["session_id"].as_str().unwrap())));
+
+        let open = open.unwrap();
+        assert_eq!((open.entry_page.as_deref(), open.events), (Some("/docs"), 1));
+    }
+
+    #[test]
+    fn test_continues_an_open_session() {
+        let config = SessionizerConfig::default();
+        let first = event("pageview", 0, "/");
+        let open = Session::start(&first, "v1", &config);
+        let late = event("pageview", 20, "/about");
+
+        let (open, derived) = stitch(Some(open), &[&late], "v1", &config);
+
+        assert!(derived.is_empty());
+        let open = open.unwrap();
+        assert_eq!((open.events, open.exit_page.as_deref()), (2, Some("/about")));
+        assert_eq!(open.session_id, session_id("p", "v1", first.timestamp));
+    }
+}
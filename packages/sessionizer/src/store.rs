@@ -0,0 +1,195 @@
+//! Open sessions in DynamoDB.
+//!
+//! One item per visitor with an open session, keyed by `pk`
+//! (`{project}#{visitor}`). `updated_at` is when the item was last written
+//! (server time), which the sweep compares against the inactivity window;
+//! `expires_at` lets the table's TTL drop items the sweep never reached.
+
+use async_trait::async_trait;
+use aws_sdk_dynamodb::error::SdkError;
+use aws_sdk_dynamodb::operation::delete_item::DeleteItemError;
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use lambda_runtime::Error;
+use std::collections::HashMap;
+
+use crate::sessions::Session;
+
+/// How long an untouched item is kept past its last write
+const EXPIRY_SECS: i64 = 7 * 86_400;
+
+/// Where open sessions are kept between batches
+#[async_trait]
+pub trait SessionStore: Send + Sync {
+    /// The visitor's open session, if any
+    async fn get(&self, project_id: &str, visitor: &str) -> Result<Option<Session>, Error>;
+    /// Saves a session as open, last written at `now` (epoch milliseconds)
+    async fn put(&self, session: &Session, now: i64) -> Result<(), Error>;
+    /// Sessions last written before `cutoff` (epoch milliseconds)
+    async fn idle(&self, cutoff: i64) -> Result<Vec<Session>, Error>;
+    /// Removes a session unless it was written since `cutoff`, returning
+    /// whether it was removed
+    async fn close(&self, session: &Session, cutoff: i64) -> Result<bool, Error>;
+}
+
+/// Open sessions in a DynamoDB table keyed by `pk`
+pub struct DynamoSessionStore {
+    client: DynamoClient,
+    table_name: String,
+}
+
+fn pk(project_id: &str, visitor: &str) -> String {
+    format!("{}#{}", project_id, visitor)
+}
+
+fn s(value: &str) -> AttributeValue {
+    AttributeValue::S(value.to_string())
+}
+
+fn n(value: impl ToString) -> AttributeValue {
+    AttributeValue::N(value.to_string())
+}
+
+fn get_s(item: &HashMap<String, AttributeValue>, name: &str) -> Option<String> {
+    item.get(name)?.as_s().ok().cloned()
+}
+
+fn get_n<T: std::str::FromStr + Default>(item: &HashMap<String, AttributeValue>, name: &str) -> T {
+    item.get(name)
+        .and_then(|value| value.as_n().ok())
+        .and_then(|value| value.parse().ok())
+        .unwrap_or_default()
+}
+
+/// The session an item holds
+fn session(item: &HashMap<String, AttributeValue>) -> Option<Session> {
+    Some(Session {
+        project_id: get_s(item, "project_id")?,
+        visitor: get_s(item, "visitor")?,
+        session_id: get_s(item, "session_id")?,
+        user_id: get_s(item, "user_id"),
+        anonymous_id: get_s(item, "anonymous_id"),
+        started_at: get_n(item, "started_at"),
+        last_seen: get_n(item, "last_seen"),
+        entry_page: get_s(item, "entry_page"),
+        exit_page: get_s(item, "exit_page"),
+        events: get_n(item, "events"),
+        pageviews: get_n(item, "pageviews"),
+    })
+}
+
+impl DynamoSessionStore {
+    pub fn new(client: DynamoClient, table_name: String) -> Self {
+        Self { client, table_name }
+    }
+}
+
+#[async_trait]
+impl SessionStore for DynamoSessionStore {
+    async fn get(&self, project_id: &str, visitor: &str) -> Result<Option<Session>, Error> {
+        let output = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("pk", s(&pk(project_id, visitor)))
+            .consistent_read(true)
+            .send()
+            .await?;
+        Ok(output.item().and_then(session))
+    }
+
+    async fn put(&self, session: &Session, now: i64) -> Result<(), Error> {
+        let optional = [
+            ("user_id", &session.user_id),
+            ("anonymous_id", &session.anonymous_id),
+            ("entry_page", &session.entry_page),
+            ("exit_page", &session.exit_page),
+        ];
+        let mut item = HashMap::from([
+            ("pk".to_string(), s(&pk(&session.project_id, &session.visitor))),
+            ("project_id".to_string(), s(&session.project_id)),
+            ("visitor".to_string(), s(&session.visitor)),
+            ("session_id".to_string(), s(&session.session_id)),
+            ("started_at".to_string(), n(session.started_at)),
+            ("last_seen".to_string(), n(session.last_seen)),
+            ("events".to_string(), n(session.events)),
+            ("pageviews".to_string(), n(session.pageviews)),
+            ("updated_at".to_string(), n(now)),
+            ("expires_at".to_string(), n(now / 1000 + EXPIRY_SECS)),
+        ]);
+        item.extend(
+            optional
+                .into_iter()
+                .filter_map(|(name, value)| Some((name.to_string(), s(value.as_deref()?)))),
+        );
+        self.client
+            .put_item()
+            .table_name(&self.table_name)
+            .set_item(Some(item))
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    async fn idle(&self, cutoff: i64) -> Result<Vec<Session>, Error> {
+        let mut sessions = Vec::new();
+        let mut start_key = None;
+        loop {
+            let output = self
+                .client
+                .scan()
+                .table_name(&self.table_name)
+                .filter_expression("updated_at < :cutoff")
+                .expression_attribute_values(":cutoff", n(cutoff))
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await?;
+            sessions.extend(output.items().iter().filter_map(session));
+            start_key = output.last_evaluated_key;
+            if start_key.is_none() {
+                return Ok(sessions);
+            }
+        }
+    }
+
+    async fn close(&self, session: &Session, cutoff: i64) -> Result<bool, Error> {
+        let result = self
+            .client
+            .delete_item()
+            .table_name(&self.table_name)
+            .key("pk", s(&pk(&session.project_id, &session.visitor)))
+            .condition_expression("updated_at < :cutoff")
+            .expression_attribute_values(":cutoff", n(cutoff))
+            .send()
+            .await;
+        match result {
+            Ok(_) => Ok(true),
+            Err(SdkError::ServiceError(e)) if matches!(e.err(), DeleteItemError::ConditionalCheckFailedException(_)) => {
+                Ok(false)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reads_a_session_item() {
+        let item = HashMap::from([
+            ("project_id".to_string(), s("p")),
+            ("visitor".to_string(), s("v1")),
+            ("session_id".to_string(), s("abc")),
+            ("started_at".to_string(), n(1000)),
+            ("last_seen".to_string(), n(5000)),
+            ("exit_page".to_string(), s("/x")),
+            ("events".to_string(), n(3)),
+        ]);
+        let session = session(&item).unwrap();
+        assert_eq!((session.started_at, session.last_seen, session.events), (1000, 5000, 3));
+        assert_eq!((session.entry_page, session.exit_page.as_deref()), (None, Some("/x")));
+        assert!(super::session(&HashMap::new()).is_none());
+    }
+}
@@ -0,0 +1,34 @@
+//! Reporter configuration.
+
+use ingestion::metering::MeteringConfig;
+use ingestion::shared::env_var;
+
+/// Configuration for usage reporting
+#[derive(Debug, Clone, Default)]
+pub struct ReporterConfig {
+    /// DynamoDB table of ingestion's monthly usage counters
+    pub usage_table: String,
+    /// DynamoDB table daily billing records are written to
+    pub billing_table: String,
+    /// SQS queue billing records are sent to, when set
+    pub queue_url: Option<String>,
+    /// Usage API billing records are posted to, when set
+    pub api_url: Option<String>,
+    /// Bearer token for the usage API
+    pub api_token: Option<String>,
+    /// Plans and their quotas, as ingestion enforces them
+    pub metering: MeteringConfig,
+}
+
+impl ReporterConfig {
+    pub fn from_env() -> Self {
+        Self {
+            usage_table: env_var("USAGE_TABLE").unwrap_or_default(),
+            billing_table: env_var("BILLING_TABLE").unwrap_or_default(),
+            queue_url: env_var("BILLING_QUEUE_URL"),
+            api_url: env_var("BILLING_API_URL"),
+            api_token: env_var("BILLING_API_TOKEN"),
+            metering: MeteringConfig::from_env(),
+        }
+    }
+}
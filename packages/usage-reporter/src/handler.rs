@@ -0,0 +1,136 @@
+//! The scheduled invocation handler.
+//!
+//! Reads the month's counters and the previous day's records, writes the
+//! day's records, then publishes them. Any failure fails the invocation,
+//! so Lambda's retries of the scheduled event report the day again.
+
+use chrono::Utc;
+use ingestion::metering;
+use lambda_runtime::Error;
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::config::ReporterConfig;
+use crate::publish::Publisher;
+use crate::report::{self, previous_day, report_date};
+use crate::store::{BillingStore, UsageSource};
+
+/// What the reporter reads from, writes to and publishes to
+pub struct Reporter {
+    pub usage: Box<dyn UsageSource>,
+    pub billing: Box<dyn BillingStore>,
+    pub publishers: Vec<Box<dyn Publisher>>,
+    pub config: ReporterConfig,
+}
+
+/// Reports the day the event calls for, returning a summary
+pub async fn handle(event: Value, reporter: &Reporter) -> Result<Value, Error> {
+    let date = report_date(&event, Utc::now())?;
+    let month = metering::month(date.and_hms_opt(0, 0, 0).unwrap().and_utc());
+
+    let totals = reporter.usage.month_totals(&month).await?;
+    let previous = match previous_day(date) {
+        Some(day) => reporter.billing.month_to_date(day).await?,
+        None => HashMap::new(),
+    };
+    let records = report::daily(date, &totals, &previous, &reporter.config.metering);
+
+    reporter.billing.put(&records).await?;
+    for publisher in &reporter.publishers {
+        publisher.publish(&records).await?;
+    }
+
+    let events: u64 = records.iter().map(|record| record.events).sum();
+    tracing::info!("Reported {} events across {} projects for {}", events, records.len(), date);
+    Ok(serde_json::json!({ "date": date.to_string(), "projects": records.len(), "events": events }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::BillingRecord;
+    use async_trait::async_trait;
+    use chrono::NaiveDate;
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+
+    struct FakeUsage(HashMap<String, HashMap<String, u64>>);
+
+    #[async_trait]
+    impl UsageSource for FakeUsage {
+        async fn month_totals(&self, month: &str) -> Result<HashMap<String, u64>, Error> {
+            Ok(self.0.get(month).cloned().unwrap_or_default())
+        }
+    }
+
+    #[derive(Default)]
+    struct FakeBilling(Arc<Mutex<Vec<BillingRecord>>>);
+
+    #[async_trait]
+    impl BillingStore for FakeBilling {
+        async fn month_to_date(&self, date: NaiveDate) -> Result<HashMap<String, u64>, Error> {
+            let records = self.0.lock().unwrap();
+            Ok(records
+                .iter()
+                .filter(|record| record.date == date.to_string())
+                .map(|record| (record.project_id.clone(), record.month_to_date))
+                .collect())
+        }
+
+        async fn put(&self, records: &[BillingRecord]) -> Result<(), Error> {
+            self.0.lock().unwrap().extend_from_slice(records);
+            Ok(())
+        }
+    }
+
+    struct FailingPublisher;
+
+    #[async_trait]
+    impl Publisher for FailingPublisher {
+        async fn publish(&self, _: &[BillingRecord]) -> Result<(), Error> {
+            Err("queue unavailable".into())
+        }
+    }
+
+    fn reporter(totals: &[(&str, &str, u64)], billing: &FakeBilling) -> Reporter {
+        let mut months: HashMap<String, HashMap<String, u64>> = HashMap::new();
+        for (month, project_id, events) in totals {
+            months.entry(month.to_string()).or_default().insert(project_id.to_string(), *events);
+        }
+        Reporter {
+            usage: Box::new(FakeUsage(months)),
+            billing: Box::new(FakeBilling(billing.0.clone())),
+            publishers: Vec::new(),
+            config: ReporterConfig::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reports_each_day_against_the_one_before() {
+        let billing = FakeBilling::default();
+
+        let first = reporter(&[("2026-10", "p", 120)], &billing);
+        let summary = handle(json!({ "date": "2026-10-14" }), &first).await.unwrap();
+        assert_eq!(summary, json!({ "date": "2026-10-14", "projects": 1, "events": 120 }));
+
+        let second = reporter(&[("2026-10", "p", 200), ("2026-10", "q", 5)], &billing);
+        let summary = handle(json!({ "date": "2026-10-15" }), &second).await.unwrap();
+        assert_eq!(summary, json!({ "date": "2026-10-15", "projects": 2, "events": 85 }));
+
+        // A new month starts from zero
+        let third = reporter(&[("2026-10", "p", 900), ("2026-11", "p", 30)], &billing);
+        let summary = handle(json!({ "date": "2026-11-01" }), &third).await.unwrap();
+        assert_eq!(summary, json!({ "date": "2026-11-01", "projects": 1, "events": 30 }));
+    }
+
+    #[tokio::test]
+    async fn test_publish_failures_fail_the_invocation() {
+        let billing = FakeBilling::default();
+        let mut reporter = reporter(&[("2026-10", "p", 120)], &billing);
+        reporter.publishers.push(Box::new(FailingPublisher));
+
+        assert!(handle(json!({ "date": "2026-10-14" }), &reporter).await.is_err());
+        // Kept regardless, and rewritten by the retry
+        assert_eq!(billing.0.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,15 @@
+//! Metered usage → daily billing records.
+//!
+//! Runs once a day on an EventBridge schedule, after midnight UTC. It reads
+//! the monthly usage counters ingestion keeps per project (see
+//! `ingestion::metering`) and turns them into one billing record per
+//! project for the day before (see [`report`]): the events accepted that
+//! day, the month to date and the project's plan. Records are kept in a
+//! DynamoDB table (see [`store`]) and, when configured, sent on to the
+//! billing system through an SQS queue or a usage API (see [`publish`]).
+
+pub mod config;
+pub mod handler;
+pub mod publish;
+pub mod report;
+pub mod store;
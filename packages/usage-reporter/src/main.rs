@@ -0,0 +1,50 @@
+use lambda_runtime::{run, service_fn, Error, LambdaEvent};
+use serde_json::Value;
+use std::sync::Arc;
+
+use aws_sdk_dynamodb::Client as DynamoClient;
+use aws_sdk_sqs::Client as SqsClient;
+use usage_reporter::config::ReporterConfig;
+use usage_reporter::handler::{handle, Reporter};
+use usage_reporter::publish::{HttpPublisher, Publisher, SqsPublisher};
+use usage_reporter::store::{DynamoBillingStore, DynamoUsageSource};
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    // Initialize tracing
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"))
+        )
+        .json()
+        .init();
+
+    let config = ReporterConfig::from_env();
+    if config.usage_table.is_empty() || config.billing_table.is_empty() {
+        return Err("USAGE_TABLE and BILLING_TABLE must be set".into());
+    }
+    let aws = aws_config::load_from_env().await;
+    let dynamo = DynamoClient::new(&aws);
+
+    let mut publishers: Vec<Box<dyn Publisher>> = Vec::new();
+    if let Some(ref queue_url) = config.queue_url {
+        publishers.push(Box::new(SqsPublisher::new(SqsClient::new(&aws), queue_url.clone())));
+    }
+    if let Some(ref api_url) = config.api_url {
+        publishers.push(Box::new(HttpPublisher::new(api_url.clone(), config.api_token.clone())?));
+    }
+
+    let reporter = Arc::new(Reporter {
+        usage: Box::new(DynamoUsageSource::new(dynamo.clone(), config.usage_table.clone())),
+        billing: Box::new(DynamoBillingStore::new(dynamo, config.billing_table.clone())),
+        publishers,
+        config,
+    });
+
+    run(service_fn(move |event: LambdaEvent<Value>| {
+        let reporter = reporter.clone();
+        async move { handle(event.payload, &reporter).await }
+    }))
+    .await
+}
@@ -0,0 +1,117 @@
+//! Sending billing records to the billing system.
+//!
+//! To an SQS queue, one message per record with the record's JSON as its
+//! body, sent with `SendMessageBatch`; or to a usage API, one HTTPS `POST`
+//! of `{"records": [...]}` per day, with `Authorization: Bearer` when a
+//! token is configured, that succeeds on any 2xx. Reporting a day again
+//! sends its records again, so receivers should key them by `date` and
+//! `projectId`.
+
+use async_trait::async_trait;
+use aws_sdk_sqs::types::SendMessageBatchRequestEntry;
+use aws_sdk_sqs::Client as SqsClient;
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper_rustls::HttpsConnector;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use lambda_runtime::Error;
+
+use crate::report::BillingRecord;
+
+/// Most entries in one `SendMessageBatch` call
+const SQS_BATCH_SIZE: usize = 10;
+
+/// Where billing records are sent
+#[async_trait]
+pub trait Publisher: Send + Sync {
+    async fn publish(&self, records: &[BillingRecord]) -> Result<(), Error>;
+}
+
+/// Sends records to an SQS queue
+pub struct SqsPublisher {
+    client: SqsClient,
+    queue_url: String,
+}
+
+impl SqsPublisher {
+    pub fn new(client: SqsClient, queue_url: String) -> Self {
+        Self { client, queue_url }
+    }
+}
+
+#[async_trait]
+impl Publisher for SqsPublisher {
+    async fn publish(&self, records: &[BillingRecord]) -> Result<(), Error> {
+        for batch in records.chunks(SQS_BATCH_SIZE) {
+            let entries = batch
+                .iter()
+                .enumerate()
+                .map(|(index, record)| {
+                    Ok(SendMessageBatchRequestEntry::builder()
+                        .id(index.to_string())
+                        .message_body(serde_json::to_string(record)?)
+                        .build()?)
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+
+            let output = self
+                .client
+                .send_message_batch()
+                .queue_url(&self.queue_url)
+                .set_entries(Some(entries))
+                .send()
+                .await?;
+
+            if let Some(failed) = output.failed().first() {
+                return Err(format!(
+                    "Failed to send {} billing records to SQS: {}: {}",
+                    output.failed().len(),
+                    failed.code(),
+                    failed.message().unwrap_or_default()
+                )
+                .into());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Posts records to a usage API over HTTPS
+pub struct HttpPublisher {
+    http: Client<HttpsConnector<HttpConnector>, Full<Bytes>>,
+    url: String,
+    token: Option<String>,
+}
+
+impl HttpPublisher {
+    pub fn new(url: String, token: Option<String>) -> Result<Self, Error> {
+        let connector = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_native_roots()?
+            .https_only()
+            .enable_http1()
+            .build();
+        Ok(Self {
+            http: Client::builder(TokioExecutor::new()).build(connector),
+            url,
+            token,
+        })
+    }
+}
+
+#[async_trait]
+impl Publisher for HttpPublisher {
+    async fn publish(&self, records: &[BillingRecord]) -> Result<(), Error> {
+        let body = serde_json::to_vec(&serde_json::json!({ "records": records }))?;
+        let mut request = http::Request::post(&self.url).header("Content-Type", "application/json");
+        if let Some(ref token) = self.token {
+            request = request.header("Authorization", format!("Bearer {}", token));
+        }
+        let response = self.http.request(request.body(Full::new(Bytes::from(body)))?).await?;
+        if !response.status().is_success() {
+            return Err(format!("Usage API responded {}", response.status()).into());
+        }
+        Ok(())
+    }
+}
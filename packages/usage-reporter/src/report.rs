@@ -0,0 +1,141 @@
+//! Turning monthly counters into daily billing records.
+//!
+//! Ingestion only keeps a running total per project and month. A day's
+//! usage is that total as read after the day ended, less the month to date
+//! recorded for the day before (nothing on the 1st). Counts a sandbox
+//! flushes after midnight land on the next day, and a day the reporter
+//! didn't run for is folded into the next one it does, so days are best
+//! reported in order. Reporting a day again rewrites its records.
+
+use chrono::{DateTime, Datelike, Days, NaiveDate, Utc};
+use ingestion::metering::MeteringConfig;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// A project's usage for one day
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BillingRecord {
+    pub project_id: String,
+    /// `YYYY-MM-DD`, UTC
+    pub date: String,
+    /// Events accepted that day
+    pub events: u64,
+    /// Events accepted this month, up to and including the day
+    pub month_to_date: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub plan: Option<String>,
+    /// Monthly event quota of the plan
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quota: Option<u64>,
+}
+
+/// The day to report: `date` from the invocation (`YYYY-MM-DD`, for
+/// backfills), else the day before the scheduled event's `time`, else
+/// yesterday
+pub fn report_date(event: &Value, now: DateTime<Utc>) -> Result<NaiveDate, String> {
+    if let Some(date) = event.get("date").and_then(Value::as_str) {
+        return NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|e| format!("Invalid date {}: {}", date, e));
+    }
+    let time = event
+        .get("time")
+        .and_then(Value::as_str)
+        .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+        .map_or(now, |time| time.with_timezone(&Utc));
+    Ok(time.date_naive() - Days::new(1))
+}
+
+/// The day before `date`, when it's in the same month
+pub fn previous_day(date: NaiveDate) -> Option<NaiveDate> {
+    (date.day() > 1).then(|| date - Days::new(1))
+}
+
+/// One record per project with a counter for `date`'s month, from the
+/// month's `totals` and the month to date as of the `previous` day
+pub fn daily(
+    date: NaiveDate,
+    totals: &HashMap<String, u64>,
+    previous: &HashMap<String, u64>,
+    metering: &MeteringConfig,
+) -> Vec<BillingRecord> {
+    let mut records: Vec<_> = totals
+        .iter()
+        .map(|(project_id, &total)| {
+            let before = previous.get(project_id).copied().unwrap_or(0);
+            BillingRecord {
+                project_id: project_id.clone(),
+                date: date.to_string(),
+                events: total.saturating_sub(before),
+                month_to_date: total,
+                plan: metering
+                    .project_plans
+                    .get(project_id)
+                    .or(metering.default_plan.as_ref())
+                    .cloned(),
+                quota: metering.quota(project_id),
+            }
+        })
+        .collect();
+    records.sort_by(|a, b| a.project_id.cmp(&b.project_id));
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    fn date(date: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn test_reports_the_day_before_the_schedule_fired() {
+        let now = Utc.with_ymd_and_hms(2026, 10, 16, 12, 0, 0).unwrap();
+        let scheduled = json!({ "detail-type": "Scheduled Event", "time": "2026-11-01T00:05:00Z" });
+        assert_eq!(report_date(&scheduled, now).unwrap(), date("2026-10-31"));
+        assert_eq!(report_date(&json!({}), now).unwrap(), date("2026-10-15"));
+        assert_eq!(report_date(&json!({ "date": "2026-09-30" }), now).unwrap(), date("2026-09-30"));
+        assert!(report_date(&json!({ "date": "yesterday" }), now).is_err());
+
+        assert_eq!(previous_day(date("2026-10-31")), Some(date("2026-10-30")));
+        assert_eq!(previous_day(date("2026-11-01")), None);
+    }
+
+    #[test]
+    fn test_days_are_the_difference_between_totals() {
+        let metering = MeteringConfig {
+            plans: HashMap::from([("free".to_string(), 1_000)]),
+            default_plan: Some("free".to_string()),
+            project_plans: HashMap::from([("big".to_string(), "enterprise".to_string())]),
+            ..Default::default()
+        };
+        let totals = HashMap::from([("small".to_string(), 700), ("big".to_string(), 50)]);
+        let previous = HashMap::from([("small".to_string(), 400)]);
+
+        let records = daily(date("2026-10-15"), &totals, &previous, &metering);
+        assert_eq!(
+            records,
+            [
+                BillingRecord {
+                    project_id: "big".to_string(),
+                    date: "2026-10-15".to_string(),
+                    events: 50,
+                    month_to_date: 50,
+                    plan: Some("enterprise".to_string()),
+                    quota: None,
+                },
+                BillingRecord {
+                    project_id: "small".to_string(),
+                    date: "2026-10-15".to_string(),
+                    events: 300,
+                    month_to_date: 700,
+                    plan: Some("free".to_string()),
+                    quota: Some(1_000),
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,148 @@
+//! Usage counters and billing records in DynamoDB.
+//!
+//! The usage table is ingestion's (`ingestion::metering`): one item per
+//! project and month with `projectId` (S), `month` (S, `YYYY-MM`) and the
+//! `events` counter (N). It holds a handful of items per project, so a
+//! month's counters are read with a filtered `Scan`.
+//!
+//! The billing table has one item per day and project: partition key
+//! `date` (S, `YYYY-MM-DD`), sort key `projectId` (S), so a day's records
+//! are one `Query`. Attributes `events` and `monthToDate` (N), and `plan`
+//! (S) and `quota` (N) when the project has them.
+
+use async_trait::async_trait;
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use chrono::NaiveDate;
+use lambda_runtime::Error;
+use std::collections::HashMap;
+
+use crate::report::BillingRecord;
+
+/// Where the monthly usage counters are read from
+#[async_trait]
+pub trait UsageSource: Send + Sync {
+    /// Every project's usage for `month` (`YYYY-MM`)
+    async fn month_totals(&self, month: &str) -> Result<HashMap<String, u64>, Error>;
+}
+
+/// Where daily billing records are kept
+#[async_trait]
+pub trait BillingStore: Send + Sync {
+    /// Month to date of each project reported for `date`
+    async fn month_to_date(&self, date: NaiveDate) -> Result<HashMap<String, u64>, Error>;
+
+    async fn put(&self, records: &[BillingRecord]) -> Result<(), Error>;
+}
+
+fn string(item: &HashMap<String, AttributeValue>, name: &str) -> Option<String> {
+    item.get(name)?.as_s().ok().cloned()
+}
+
+fn number(item: &HashMap<String, AttributeValue>, name: &str) -> Option<u64> {
+    item.get(name)?.as_n().ok()?.parse().ok()
+}
+
+/// Ingestion's usage table
+pub struct DynamoUsageSource {
+    client: DynamoClient,
+    table_name: String,
+}
+
+impl DynamoUsageSource {
+    pub fn new(client: DynamoClient, table_name: String) -> Self {
+        Self { client, table_name }
+    }
+}
+
+#[async_trait]
+impl UsageSource for DynamoUsageSource {
+    async fn month_totals(&self, month: &str) -> Result<HashMap<String, u64>, Error> {
+        let mut totals = HashMap::new();
+        let mut start = None;
+        loop {
+            let output = self
+                .client
+                .scan()
+                .table_name(&self.table_name)
+                .filter_expression("#month = :month")
+                .expression_attribute_names("#month", "month")
+                .expression_attribute_values(":month", AttributeValue::S(month.to_string()))
+                .set_exclusive_start_key(start)
+                .send()
+                .await?;
+            for item in output.items() {
+                if let Some(project_id) = string(item, "projectId") {
+                    totals.insert(project_id, number(item, "events").unwrap_or(0));
+                }
+            }
+            start = output.last_evaluated_key().cloned();
+            if start.is_none() {
+                return Ok(totals);
+            }
+        }
+    }
+}
+
+/// Billing records in a DynamoDB table keyed by `date` and `projectId`
+pub struct DynamoBillingStore {
+    client: DynamoClient,
+    table_name: String,
+}
+
+impl DynamoBillingStore {
+    pub fn new(client: DynamoClient, table_name: String) -> Self {
+        Self { client, table_name }
+    }
+}
+
+#[async_trait]
+impl BillingStore for DynamoBillingStore {
+    async fn month_to_date(&self, date: NaiveDate) -> Result<HashMap<String, u64>, Error> {
+        let mut totals = HashMap::new();
+        let mut start = None;
+        loop {
+            let output = self
+                .client
+                .query()
+                .table_name(&self.table_name)
+                .key_condition_expression("#date = :date")
+                .expression_attribute_names("#date", "date")
+                .expression_attribute_values(":date", AttributeValue::S(date.to_string()))
+                .set_exclusive_start_key(start)
+                .send()
+                .await?;
+            for item in output.items() {
+                if let Some(project_id) = string(item, "projectId") {
+                    totals.insert(project_id, number(item, "monthToDate").unwrap_or(0));
+                }
+            }
+            start = output.last_evaluated_key().cloned();
+            if start.is_none() {
+                return Ok(totals);
+            }
+        }
+    }
+
+    async fn put(&self, records: &[BillingRecord]) -> Result<(), Error> {
+        let n = |value: u64| AttributeValue::N(value.to_string());
+        for record in records {
+            let mut item = self
+                .client
+                .put_item()
+                .table_name(&self.table_name)
+                .item("date", AttributeValue::S(record.date.clone()))
+                .item("projectId", AttributeValue::S(record.project_id.clone()))
+                .item("events", n(record.events))
+                .item("monthToDate", n(record.month_to_date));
+            if let Some(ref plan) = record.plan {
+                item = item.item("plan", AttributeValue::S(plan.clone()));
+            }
+            if let Some(quota) = record.quota {
+                item = item.item("quota", n(quota));
+            }
+            item.send().await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,53 @@
+//! Publishing alerts.
+//!
+//! One SNS message per anomaly: a short subject for email subscribers and
+//! the anomaly as JSON for anything else. An anomaly that lasts is alerted
+//! on every run it's still seen in.
+
+use async_trait::async_trait;
+use aws_sdk_sns::Client as SnsClient;
+use lambda_runtime::Error;
+
+use crate::detect::{Anomaly, Kind};
+
+/// Where alerts go
+#[async_trait]
+pub trait Alerter: Send + Sync {
+    async fn alert(&self, anomaly: &Anomaly) -> Result<(), Error>;
+}
+
+/// Subject line of an anomaly's alert, within SNS's 100 characters
+pub fn subject(anomaly: &Anomaly) -> String {
+    let kind = match anomaly.kind {
+        Kind::Drop => "drop",
+        Kind::Spike => "spike",
+    };
+    let subject = format!("Ingest volume {} for {}", kind, anomaly.project_id);
+    subject.chars().take(100).collect()
+}
+
+/// Publishes to an SNS topic
+pub struct SnsAlerter {
+    client: SnsClient,
+    topic_arn: String,
+}
+
+impl SnsAlerter {
+    pub fn new(client: SnsClient, topic_arn: String) -> Self {
+        Self { client, topic_arn }
+    }
+}
+
+#[async_trait]
+impl Alerter for SnsAlerter {
+    async fn alert(&self, anomaly: &Anomaly) -> Result<(), Error> {
+        self.client
+            .publish()
+            .topic_arn(&self.topic_arn)
+            .subject(subject(anomaly))
+            .message(serde_json::to_string(anomaly)?)
+            .send()
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,60 @@
+//! Monitor configuration.
+
+use ingestion::shared::{env_list, env_or, env_var};
+
+/// Configuration for volume monitoring
+#[derive(Debug, Clone)]
+pub struct MonitorConfig {
+    /// DynamoDB table of the aggregator's counters
+    pub aggregates_table: String,
+    /// Projects table the monitored projects are listed from
+    pub projects_table: Option<String>,
+    /// Projects to monitor instead of every project in the table
+    pub projects: Vec<String>,
+    /// SNS topic alerts are published to
+    pub topic_arn: String,
+    /// Complete hours whose volume is checked
+    pub window_hours: u32,
+    /// Previous days whose same hours make up the baseline
+    pub baseline_days: u32,
+    /// Below this share of the baseline, volume has dropped
+    pub drop_ratio: f64,
+    /// Above this multiple of the baseline, volume has spiked
+    pub spike_ratio: f64,
+    /// Smallest baseline, in events per window, worth alerting on
+    pub min_baseline: u64,
+}
+
+impl Default for MonitorConfig {
+    fn default() -> Self {
+        Self {
+            aggregates_table: String::new(),
+            projects_table: None,
+            projects: Vec::new(),
+            topic_arn: String::new(),
+            window_hours: 1,
+            baseline_days: 7,
+            drop_ratio: 0.5,
+            spike_ratio: 3.0,
+            min_baseline: 100,
+        }
+    }
+}
+
+impl MonitorConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            aggregates_table: env_var("AGGREGATES_TABLE").unwrap_or_default(),
+            projects_table: env_var("API_KEYS_TABLE"),
+            projects: env_list("VOLUME_MONITOR_PROJECTS"),
+            topic_arn: env_var("VOLUME_ALERT_TOPIC_ARN").unwrap_or_default(),
+            window_hours: env_or("VOLUME_WINDOW_HOURS", defaults.window_hours).max(1),
+            // Hour buckets are kept 35 days by default
+            baseline_days: env_or("VOLUME_BASELINE_DAYS", defaults.baseline_days).clamp(1, 35),
+            drop_ratio: env_or("VOLUME_DROP_RATIO", defaults.drop_ratio),
+            spike_ratio: env_or("VOLUME_SPIKE_RATIO", defaults.spike_ratio),
+            min_baseline: env_or("VOLUME_MIN_BASELINE", defaults.min_baseline),
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//! Telling anomalous volume from the usual.
+//!
+//! The window is the last `VOLUME_WINDOW_HOURS` complete hours. Its
+//! baseline is the median of the same hours on each of the previous
+//! `VOLUME_BASELINE_DAYS` days, so a quiet night isn't mistaken for an
+//! outage and one bad day doesn't skew it. Volume below `VOLUME_DROP_RATIO`
+//! of the baseline is a drop (no events at all being the usual sign of a
+//! broken integration), above `VOLUME_SPIKE_RATIO` times it a spike.
+//! Projects whose baseline is under `VOLUME_MIN_BASELINE` events are too
+//! small for either to mean much and aren't judged.
+
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use serde::Serialize;
+
+use crate::config::MonitorConfig;
+
+/// Which way volume moved
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Drop,
+    Spike,
+}
+
+/// A window whose volume is far off its baseline
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Anomaly {
+    pub project_id: String,
+    pub kind: Kind,
+    /// Events in the window
+    pub events: u64,
+    /// Median events in the same window on previous days
+    pub baseline: u64,
+    pub window_start: DateTime<Utc>,
+    pub window_end: DateTime<Utc>,
+}
+
+/// Start of each hour of the window ending at the start of `now`'s hour
+pub fn window(now: DateTime<Utc>, config: &MonitorConfig) -> Vec<DateTime<Utc>> {
+    let end = now.duration_trunc(Duration::hours(1)).unwrap_or(now);
+    (1..=config.window_hours as i64)
+        .rev()
+        .map(|hours| end - Duration::hours(hours))
+        .collect()
+}
+
+/// The window's hours on each previous day the baseline covers
+pub fn baseline_windows(window: &[DateTime<Utc>], config: &MonitorConfig) -> Vec<Vec<DateTime<Utc>>> {
+    (1..=config.baseline_days as i64)
+        .map(|days| window.iter().map(|hour| *hour - Duration::days(days)).collect())
+        .collect()
+}
+
+fn median(counts: &[u64]) -> u64 {
+    let mut counts = counts.to_vec();
+    counts.sort_unstable();
+    match counts.len() {
+        0 => 0,
+        len if len % 2 == 1 => counts[len / 2],
+        len => (counts[len / 2 - 1] + counts[len / 2]) / 2,
+    }
+}
+
+/// The anomaly, if the window's `events` are far off the `history` of
+/// previous days' windows
+pub fn detect(
+    project_id: &str,
+    window: &[DateTime<Utc>],
+    events: u64,
+    history: &[u64],
+    config: &MonitorConfig,
+) -> Option<Anomaly> {
+    let baseline = median(history);
+    if baseline < config.min_baseline {
+        return None;
+    }
+    let ratio = events as f64 / baseline as f64;
+    let kind = if ratio < config.drop_ratio {
+        Kind::Drop
+    } else if ratio > config.spike_ratio {
+        Kind::Spike
+    } else {
+        return None;
+    };
+    Some(Anomaly {
+        project_id: project_id.to_string(),
+        kind,
+        events,
+        baseline,
+        window_start: *window.first()?,
+        window_end: *window.last()? + Duration::hours(1),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(day: u32, hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 10, day, hour, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn test_window_is_the_last_complete_hours() {
+        let config = MonitorConfig {
+            window_hours: 2,
+            baseline_days: 2,
+            ..Default::default()
+        };
+        let now = Utc.with_ymd_and_hms(2026, 10, 16, 9, 5, 30).unwrap();
+        let window = window(now, &config);
+        assert_eq!(window, [at(16, 7), at(16, 8)]);
+        assert_eq!(baseline_windows(&window, &config), [[at(15, 7), at(15, 8)], [at(14, 7), at(14, 8)]]);
+    }
+
+    #[test]
+    fn test_drops_and_spikes_against_the_median_day() {
+        let config = MonitorConfig::default();
+        let window = [at(16, 8)];
+        // One outage day in the history doesn't move the baseline
+        let history = [1_000, 1_200, 0, 900, 1_100];
+
+        let drop = detect("p", &window, 0, &history, &config).unwrap();
+        assert_eq!((drop.kind, drop.baseline), (Kind::Drop, 1_000));
+        assert_eq!((drop.window_start, drop.window_end), (at(16, 8), at(16, 9)));
+        assert_eq!(detect("p", &window, 4_000, &history, &config).unwrap().kind, Kind::Spike);
+        assert_eq!(detect("p", &window, 700, &history, &config), None);
+
+        // Too small to judge
+        assert_eq!(detect("p", &window, 0, &[10, 20, 30], &config), None);
+    }
+}
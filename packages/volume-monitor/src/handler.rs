@@ -0,0 +1,134 @@
+//! The scheduled invocation handler.
+//!
+//! Checks every project's last window against its baseline and alerts on
+//! each anomaly. The window ends at the hour the scheduled event's `time`
+//! falls in, so a retried invocation checks the same hours.
+
+use chrono::{DateTime, Utc};
+use lambda_runtime::Error;
+use serde_json::Value;
+
+use crate::alert::Alerter;
+use crate::config::MonitorConfig;
+use crate::detect::{self, baseline_windows, window};
+use crate::volume::{total, ProjectSource, VolumeSource};
+
+/// What the monitor reads from and alerts to
+pub struct Monitor {
+    pub projects: Box<dyn ProjectSource>,
+    pub volume: Box<dyn VolumeSource>,
+    pub alerter: Box<dyn Alerter>,
+    pub config: MonitorConfig,
+}
+
+/// Checks every project, returning a summary
+pub async fn handle(event: Value, monitor: &Monitor) -> Result<Value, Error> {
+    let now = event
+        .get("time")
+        .and_then(Value::as_str)
+        .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+        .map_or_else(Utc::now, |time| time.with_timezone(&Utc));
+    let window = window(now, &monitor.config);
+    let baselines = baseline_windows(&window, &monitor.config);
+
+    let projects = monitor.projects.project_ids().await?;
+    let mut alerts = Vec::new();
+    for project_id in &projects {
+        let events = total(monitor.volume.as_ref(), project_id, &window).await?;
+        let mut history = Vec::with_capacity(baselines.len());
+        for hours in &baselines {
+            history.push(total(monitor.volume.as_ref(), project_id, hours).await?);
+        }
+        if let Some(anomaly) = detect::detect(project_id, &window, events, &history, &monitor.config) {
+            tracing::warn!(
+                "Ingest volume {:?} for {}: {} events against a baseline of {}",
+                anomaly.kind,
+                project_id,
+                anomaly.events,
+                anomaly.baseline
+            );
+            monitor.alerter.alert(&anomaly).await?;
+            alerts.push(project_id.clone());
+        }
+    }
+
+    tracing::info!("Checked {} projects, {} anomalous", projects.len(), alerts.len());
+    Ok(serde_json::json!({ "projects": projects.len(), "alerts": alerts }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::detect::{Anomaly, Kind};
+    use async_trait::async_trait;
+    use chrono::{Duration, TimeZone};
+    use serde_json::json;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    /// Events per hour by project, the same every hour unless overridden
+    struct FakeVolume {
+        usual: HashMap<String, u64>,
+        hours: HashMap<(String, DateTime<Utc>), u64>,
+    }
+
+    #[async_trait]
+    impl VolumeSource for FakeVolume {
+        async fn events(&self, project_id: &str, hour: DateTime<Utc>) -> Result<u64, Error> {
+            let usual = self.usual.get(project_id).copied().unwrap_or(0);
+            Ok(self.hours.get(&(project_id.to_string(), hour)).copied().unwrap_or(usual))
+        }
+    }
+
+    #[derive(Default)]
+    struct FakeAlerter(Arc<Mutex<Vec<Anomaly>>>);
+
+    #[async_trait]
+    impl Alerter for FakeAlerter {
+        async fn alert(&self, anomaly: &Anomaly) -> Result<(), Error> {
+            self.0.lock().unwrap().push(anomaly.clone());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_alerts_on_projects_off_their_baseline() {
+        let last_hour = Utc.with_ymd_and_hms(2026, 10, 16, 8, 0, 0).unwrap();
+        let alerts = Arc::new(Mutex::new(Vec::new()));
+        let monitor = Monitor {
+            projects: Box::new(vec!["broken".to_string(), "steady".to_string(), "launch".to_string()]),
+            volume: Box::new(FakeVolume {
+                usual: HashMap::from([
+                    ("broken".to_string(), 500),
+                    ("steady".to_string(), 500),
+                    ("launch".to_string(), 500),
+                ]),
+                hours: HashMap::from([
+                    (("broken".to_string(), last_hour), 0),
+                    (("launch".to_string(), last_hour), 5_000),
+                    // A busy hour a day ago only adds to the baseline's range
+                    (("steady".to_string(), last_hour - Duration::days(1)), 5_000),
+                ]),
+            }),
+            alerter: Box::new(FakeAlerter(alerts.clone())),
+            config: MonitorConfig::default(),
+        };
+
+        let summary = handle(json!({ "time": "2026-10-16T09:00:12Z" }), &monitor).await.unwrap();
+        assert_eq!(summary, json!({ "projects": 3, "alerts": ["broken", "launch"] }));
+
+        let alerts = alerts.lock().unwrap();
+        assert_eq!(
+            alerts[0],
+            Anomaly {
+                project_id: "broken".to_string(),
+                kind: Kind::Drop,
+                events: 0,
+                baseline: 500,
+                window_start: last_hour,
+                window_end: last_hour + Duration::hours(1),
+            }
+        );
+        assert_eq!(alerts[1].kind, Kind::Spike);
+    }
+}
@@ -0,0 +1,15 @@
+//! Per-project ingest volume anomaly alerts.
+//!
+//! Runs on an EventBridge schedule, hourly. For each project it compares
+//! the events of the last complete hours with the same hours of the days
+//! before, read from the hourly counters `packages/aggregator` keeps, and
+//! publishes an SNS alert when volume has dropped or spiked well past that
+//! baseline (see [`detect`]), so a broken SDK integration is noticed
+//! within hours rather than when the customer asks where their data went.
+//! See [`volume`] for how counts are read and [`alert`] for the message.
+
+pub mod alert;
+pub mod config;
+pub mod detect;
+pub mod handler;
+pub mod volume;
@@ -0,0 +1,49 @@
+use lambda_runtime::{run, service_fn, Error, LambdaEvent};
+use serde_json::Value;
+use std::sync::Arc;
+
+use admin_api::projects::DynamoProjectStore;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use aws_sdk_sns::Client as SnsClient;
+use volume_monitor::alert::SnsAlerter;
+use volume_monitor::config::MonitorConfig;
+use volume_monitor::handler::{handle, Monitor};
+use volume_monitor::volume::{DynamoVolume, ProjectSource};
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    // Initialize tracing
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"))
+        )
+        .json()
+        .init();
+
+    let config = MonitorConfig::from_env();
+    if config.aggregates_table.is_empty() || config.topic_arn.is_empty() {
+        return Err("AGGREGATES_TABLE and VOLUME_ALERT_TOPIC_ARN must be set".into());
+    }
+    let aws = aws_config::load_from_env().await;
+    let dynamo = DynamoClient::new(&aws);
+
+    let projects: Box<dyn ProjectSource> = match config.projects_table {
+        _ if !config.projects.is_empty() => Box::new(config.projects.clone()),
+        Some(ref table) => Box::new(DynamoProjectStore::new(dynamo.clone(), table.clone())),
+        None => return Err("VOLUME_MONITOR_PROJECTS or API_KEYS_TABLE must be set".into()),
+    };
+
+    let monitor = Arc::new(Monitor {
+        projects,
+        volume: Box::new(DynamoVolume::new(dynamo, config.aggregates_table.clone())),
+        alerter: Box::new(SnsAlerter::new(SnsClient::new(&aws), config.topic_arn.clone())),
+        config,
+    });
+
+    run(service_fn(move |event: LambdaEvent<Value>| {
+        let monitor = monitor.clone();
+        async move { handle(event.payload, &monitor).await }
+    }))
+    .await
+}
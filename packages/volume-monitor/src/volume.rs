@@ -0,0 +1,95 @@
+//! Reading projects and their hourly volume.
+//!
+//! Volume is the `events` counter of each hour bucket's `totals` item in
+//! the aggregates table (see `aggregator::store`); a missing bucket is an
+//! hour without events. Buckets are read a few at a time. Projects are
+//! either configured (`VOLUME_MONITOR_PROJECTS`) or every project in the
+//! projects table the admin API manages.
+
+use admin_api::projects::{DynamoProjectStore, ProjectStore};
+use aggregator::counts::{BucketKey, Granularity};
+use async_trait::async_trait;
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use chrono::{DateTime, Utc};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use lambda_runtime::Error;
+
+/// Buckets read at once
+const CONCURRENT_READS: usize = 8;
+
+/// The projects to monitor
+#[async_trait]
+pub trait ProjectSource: Send + Sync {
+    async fn project_ids(&self) -> Result<Vec<String>, Error>;
+}
+
+/// A fixed list of projects
+#[async_trait]
+impl ProjectSource for Vec<String> {
+    async fn project_ids(&self) -> Result<Vec<String>, Error> {
+        Ok(self.clone())
+    }
+}
+
+#[async_trait]
+impl ProjectSource for DynamoProjectStore {
+    async fn project_ids(&self) -> Result<Vec<String>, Error> {
+        let projects = self.list().await?;
+        Ok(projects.into_iter().map(|project| project.project_id).collect())
+    }
+}
+
+/// Where hourly event counts are read from
+#[async_trait]
+pub trait VolumeSource: Send + Sync {
+    /// Events of the project in the hour starting at `hour`
+    async fn events(&self, project_id: &str, hour: DateTime<Utc>) -> Result<u64, Error>;
+}
+
+/// Sum of the project's events over `hours`
+pub async fn total(source: &dyn VolumeSource, project_id: &str, hours: &[DateTime<Utc>]) -> Result<u64, Error> {
+    stream::iter(hours)
+        .map(|hour| source.events(project_id, *hour))
+        .buffer_unordered(CONCURRENT_READS)
+        .try_fold(0, |total, events| async move { Ok(total + events) })
+        .await
+}
+
+/// The aggregates table
+pub struct DynamoVolume {
+    client: DynamoClient,
+    table_name: String,
+}
+
+impl DynamoVolume {
+    pub fn new(client: DynamoClient, table_name: String) -> Self {
+        Self { client, table_name }
+    }
+}
+
+#[async_trait]
+impl VolumeSource for DynamoVolume {
+    async fn events(&self, project_id: &str, hour: DateTime<Utc>) -> Result<u64, Error> {
+        let key = BucketKey {
+            project_id: project_id.to_string(),
+            granularity: Granularity::Hour,
+            bucket: Granularity::Hour.bucket(hour),
+        };
+        let output = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("pk", AttributeValue::S(key.partition_key()))
+            .key("sk", AttributeValue::S("totals".to_string()))
+            .projection_expression("events")
+            .send()
+            .await?;
+        Ok(output
+            .item()
+            .and_then(|item| item.get("events"))
+            .and_then(|value| value.as_n().ok())
+            .and_then(|value| value.parse().ok())
+            .unwrap_or_default())
+    }
+}
@@ -0,0 +1,62 @@
+//! Forwarder configuration.
+
+use ingestion::retry::RetryConfig;
+use ingestion::shared::{env_or, env_var};
+use std::time::Duration;
+
+/// Configuration for webhook forwarding
+#[derive(Debug, Clone)]
+pub struct ForwarderConfig {
+    /// DynamoDB table of webhook subscriptions
+    pub webhooks_table: String,
+    /// DynamoDB table undeliverable payloads are written to
+    pub dead_letter_table: String,
+    /// Attempts and backoff per delivery, and the retry time one batch may
+    /// spend across all of them
+    pub retry: RetryConfig,
+    /// How long one delivery attempt may take
+    pub timeout: Duration,
+    /// How long dead letters are kept
+    pub dead_letter_ttl: Duration,
+}
+
+impl Default for ForwarderConfig {
+    fn default() -> Self {
+        Self {
+            webhooks_table: String::new(),
+            dead_letter_table: String::new(),
+            retry: RetryConfig {
+                max_attempts: 5,
+                base_delay: Duration::from_millis(500),
+                jitter: true,
+                budget: Duration::from_secs(30),
+            },
+            timeout: Duration::from_secs(5),
+            dead_letter_ttl: Duration::from_secs(14 * 86_400),
+        }
+    }
+}
+
+impl ForwarderConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            webhooks_table: env_var("WEBHOOKS_TABLE").unwrap_or_default(),
+            dead_letter_table: env_var("WEBHOOK_DEAD_LETTER_TABLE").unwrap_or_default(),
+            retry: RetryConfig {
+                max_attempts: env_or("WEBHOOK_MAX_ATTEMPTS", defaults.retry.max_attempts).max(1),
+                base_delay: Duration::from_millis(env_or(
+                    "WEBHOOK_BASE_DELAY_MS",
+                    defaults.retry.base_delay.as_millis() as u64,
+                )),
+                jitter: env_or("WEBHOOK_RETRY_JITTER", defaults.retry.jitter),
+                budget: Duration::from_millis(env_or(
+                    "WEBHOOK_RETRY_BUDGET_MS",
+                    defaults.retry.budget.as_millis() as u64,
+                )),
+            },
+            timeout: Duration::from_millis(env_or("WEBHOOK_TIMEOUT_MS", defaults.timeout.as_millis() as u64)),
+            dead_letter_ttl: Duration::from_secs(86_400 * env_or("WEBHOOK_DEAD_LETTER_TTL_DAYS", 14)),
+        }
+    }
+}
@@ -0,0 +1,70 @@
+//! The dead-letter table.
+//!
+//! A payload that couldn't be delivered is kept, with why, for
+//! `WEBHOOK_DEAD_LETTER_TTL_DAYS`, so it can be inspected and replayed
+//! once the customer's endpoint is fixed.
+
+use async_trait::async_trait;
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use lambda_runtime::Error;
+
+/// An undeliverable payload
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeadLetter {
+    pub project_id: String,
+    pub webhook_id: String,
+    pub delivery_id: String,
+    pub url: String,
+    /// The body that was sent
+    pub payload: String,
+    pub attempts: u32,
+    /// What the last attempt got
+    pub reason: String,
+    /// Epoch milliseconds
+    pub failed_at: i64,
+}
+
+/// Where undeliverable payloads go
+#[async_trait]
+pub trait DeadLetterStore: Send + Sync {
+    async fn put(&self, letter: &DeadLetter, expires_at: i64) -> Result<(), Error>;
+}
+
+/// DynamoDB-backed store
+/// Table schema: partition key `pk` (S, `{project}#{webhook}`), sort key
+/// `sk` (S, `{failed_at}#{delivery_id}`), TTL attribute `expires_at` (N)
+pub struct DynamoDeadLetterStore {
+    client: DynamoClient,
+    table_name: String,
+}
+
+impl DynamoDeadLetterStore {
+    pub fn new(client: DynamoClient, table_name: String) -> Self {
+        Self { client, table_name }
+    }
+}
+
+#[async_trait]
+impl DeadLetterStore for DynamoDeadLetterStore {
+    async fn put(&self, letter: &DeadLetter, expires_at: i64) -> Result<(), Error> {
+        let s = |value: &str| AttributeValue::S(value.to_string());
+        self.client
+            .put_item()
+            .table_name(&self.table_name)
+            .item("pk", s(&format!("{}#{}", letter.project_id, letter.webhook_id)))
+            .item("sk", s(&format!("{:013}#{}", letter.failed_at, letter.delivery_id)))
+            .item("project_id", s(&letter.project_id))
+            .item("webhook_id", s(&letter.webhook_id))
+            .item("delivery_id", s(&letter.delivery_id))
+            .item("url", s(&letter.url))
+            .item("payload", s(&letter.payload))
+            .item("attempts", AttributeValue::N(letter.attempts.to_string()))
+            .item("reason", s(&letter.reason))
+            .item("failed_at", AttributeValue::N(letter.failed_at.to_string()))
+            .item("expires_at", AttributeValue::N(expires_at.to_string()))
+            .send()
+            .await?;
+        Ok(())
+    }
+}
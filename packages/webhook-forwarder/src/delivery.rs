@@ -0,0 +1,224 @@
+//! Delivering a payload to a webhook.
+//!
+//! A delivery is a signed `POST` (see [`signature`](crate::signature)) that
+//! succeeds on any 2xx. Timeouts, connection errors, 408, 429 and 5xx are
+//! retried with jittered exponential backoff (see `ingestion::retry`);
+//! other statuses, redirects included, are final. Every attempt carries the
+//! same `X-Webhook-Delivery` id, also kept across Kinesis retries of the
+//! batch, so receivers can drop duplicates.
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper_rustls::HttpsConnector;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use ingestion::retry::{with_retries, RetryBudget};
+use lambda_runtime::Error;
+use sha2::{Digest, Sha256};
+
+use crate::config::ForwarderConfig;
+use crate::signature;
+use crate::webhooks::Webhook;
+
+/// Sends requests to webhooks
+#[async_trait]
+pub trait Transport: Send + Sync {
+    /// POSTs a body, returning the response status
+    async fn post(&self, url: &str, headers: Vec<(&'static str, String)>, body: Bytes) -> Result<u16, Error>;
+}
+
+/// HTTPS-only client
+pub struct HttpsTransport {
+    http: Client<HttpsConnector<HttpConnector>, Full<Bytes>>,
+}
+
+impl HttpsTransport {
+    pub fn new() -> Result<Self, Error> {
+        let connector = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_native_roots()?
+            .https_only()
+            .enable_http1()
+            .build();
+        Ok(Self {
+            http: Client::builder(TokioExecutor::new()).build(connector),
+        })
+    }
+}
+
+#[async_trait]
+impl Transport for HttpsTransport {
+    async fn post(&self, url: &str, headers: Vec<(&'static str, String)>, body: Bytes) -> Result<u16, Error> {
+        let mut request = http::Request::post(url);
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+        let response = self.http.request(request.body(Full::new(body))?).await?;
+        Ok(response.status().as_u16())
+    }
+}
+
+/// How a delivery ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Delivered,
+    /// Refused or retried out; `reason` describes the last attempt
+    Undeliverable { attempts: u32, reason: String },
+}
+
+/// The id of the delivery of one event to one webhook, the same each time
+/// the event is read from the stream
+pub fn delivery_id(webhook_id: &str, sequence_number: &str, index: usize) -> String {
+    let digest = Sha256::new()
+        .chain_update(webhook_id.as_bytes())
+        .chain_update(b":")
+        .chain_update(sequence_number.as_bytes())
+        .chain_update(b":")
+        .chain_update(index.to_string().as_bytes())
+        .finalize();
+    hex::encode(&digest[..16])
+}
+
+/// Whether a failed status is worth retrying
+fn is_retryable(status: u16) -> bool {
+    matches!(status, 408 | 429) || status >= 500
+}
+
+/// One signed attempt: `Ok` when delivered, `Ok(Err)` when refused for good,
+/// `Err` when worth retrying
+async fn attempt(
+    transport: &dyn Transport,
+    webhook: &Webhook,
+    delivery_id: &str,
+    body: &Bytes,
+    config: &ForwarderConfig,
+) -> Result<Result<(), String>, String> {
+    let timestamp = chrono::Utc::now().timestamp();
+    let headers = vec![
+        ("content-type", "application/json".to_string()),
+        ("x-webhook-id", webhook.webhook_id.clone()),
+        ("x-webhook-delivery", delivery_id.to_string()),
+        ("x-webhook-timestamp", timestamp.to_string()),
+        ("x-webhook-signature", signature::sign(&webhook.secret, timestamp, body)),
+    ];
+    let sent = tokio::time::timeout(config.timeout, transport.post(&webhook.url, headers, body.clone())).await;
+    match sent {
+        Err(_) => Err(format!("timed out after {:?}", config.timeout)),
+        Ok(Err(e)) => Err(e.to_string()),
+        Ok(Ok(status)) if (200..300).contains(&status) => Ok(Ok(())),
+        Ok(Ok(status)) if is_retryable(status) => Err(format!("HTTP {}", status)),
+        Ok(Ok(status)) => Ok(Err(format!("HTTP {}", status))),
+    }
+}
+
+/// Delivers a body to a webhook, retrying while `budget` lasts
+pub async fn deliver(
+    transport: &dyn Transport,
+    webhook: &Webhook,
+    delivery_id: &str,
+    body: &Bytes,
+    config: &ForwarderConfig,
+    budget: &mut RetryBudget,
+) -> Outcome {
+    let mut attempts = 0;
+    let result = with_retries(&config.retry, budget, || {
+        attempts += 1;
+        attempt(transport, webhook, delivery_id, body, config)
+    })
+    .await;
+    match result {
+        Ok(Ok(())) => Outcome::Delivered,
+        Ok(Err(reason)) | Err(reason) => Outcome::Undeliverable { attempts, reason },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    /// Answers the listed statuses in order, then 200
+    #[derive(Default)]
+    struct Scripted {
+        statuses: Mutex<Vec<u16>>,
+        headers: Mutex<Vec<Vec<(&'static str, String)>>>,
+    }
+
+    #[async_trait]
+    impl Transport for Scripted {
+        async fn post(&self, _url: &str, headers: Vec<(&'static str, String)>, _body: Bytes) -> Result<u16, Error> {
+            self.headers.lock().unwrap().push(headers);
+            let mut statuses = self.statuses.lock().unwrap();
+            Ok(if statuses.is_empty() { 200 } else { statuses.remove(0) })
+        }
+    }
+
+    fn webhook() -> Webhook {
+        Webhook {
+            project_id: "p".to_string(),
+            webhook_id: "w".to_string(),
+            url: "https://hooks.example.com".to_string(),
+            secret: "whsec".to_string(),
+            events: Vec::new(),
+        }
+    }
+
+    fn config() -> ForwarderConfig {
+        let mut config = ForwarderConfig::default();
+        config.retry.base_delay = Duration::from_millis(10);
+        config
+    }
+
+    async fn run(statuses: &[u16]) -> (Outcome, Vec<Vec<(&'static str, String)>>) {
+        let transport = Scripted {
+            statuses: Mutex::new(statuses.to_vec()),
+            ..Default::default()
+        };
+        let config = config();
+        let mut budget = RetryBudget::new(config.retry.budget);
+        let outcome = deliver(&transport, &webhook(), "d1", &Bytes::from_static(b"{}"), &config, &mut budget).await;
+        (outcome, transport.headers.into_inner().unwrap())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retries_transient_failures() {
+        let (outcome, attempts) = run(&[503, 429]).await;
+
+        assert_eq!(outcome, Outcome::Delivered);
+        assert_eq!(attempts.len(), 3);
+        assert!(attempts.iter().all(|headers| headers.contains(&("x-webhook-delivery", "d1".to_string()))));
+        assert!(attempts[0].iter().any(|(name, value)| *name == "x-webhook-signature" && value.starts_with("sha256=")));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_gives_up_on_refusals_and_after_max_attempts() {
+        let (outcome, attempts) = run(&[410]).await;
+        assert_eq!(
+            outcome,
+            Outcome::Undeliverable {
+                attempts: 1,
+                reason: "HTTP 410".to_string()
+            }
+        );
+        assert_eq!(attempts.len(), 1);
+
+        let (outcome, attempts) = run(&[500; 6]).await;
+        assert_eq!(
+            outcome,
+            Outcome::Undeliverable {
+                attempts: 5,
+                reason: "HTTP 500".to_string()
+            }
+        );
+        assert_eq!(attempts.len(), 5);
+    }
+
+    #[test]
+    fn test_delivery_ids_are_stable() {
+        assert_eq!(delivery_id("w", "49590", 0), delivery_id("w", "49590", 0));
+        assert_ne!(delivery_id("w", "49590", 0), delivery_id("w", "49590", 1));
+        assert_eq!(delivery_id("w", "49590", 0).len(), 32);
+    }
+}
@@ -0,0 +1,292 @@
+//! The Kinesis batch handler.
+//!
+//! Records are handled in order. Each event (after unpacking KPL
+//! aggregates) goes to every webhook of its project whose filter matches
+//! its type, and a payload that can't be delivered is written to the
+//! dead-letter table. Only when neither happens (the webhooks can't be
+//! read or the dead letter can't be written) is the record reported
+//! failed, so the batch is retried from it: every event is delivered or
+//! dead-lettered at least once. Deliveries already made for a retried
+//! record are sent again, with the same delivery id.
+//!
+//! All deliveries of a batch share one retry budget, so a dead endpoint
+//! costs at most `WEBHOOK_RETRY_BUDGET_MS` of backoff before the rest of
+//! its events are tried once and dead-lettered.
+
+use aws_lambda_events::event::kinesis::KinesisEvent;
+use aws_lambda_events::event::streams::{KinesisBatchItemFailure, KinesisEventResponse};
+use bytes::Bytes;
+use ingestion::aggregation;
+use ingestion::models::IngestEventPayload;
+use ingestion::retry::RetryBudget;
+use lambda_runtime::Error;
+use std::collections::HashMap;
+
+use crate::config::ForwarderConfig;
+use crate::dead_letter::{DeadLetter, DeadLetterStore};
+use crate::delivery::{self, Outcome, Transport};
+use crate::webhooks::{Webhook, WebhookStore};
+
+/// What a batch is forwarded with
+pub struct Forwarder {
+    pub config: ForwarderConfig,
+    pub webhooks: Box<dyn WebhookStore>,
+    pub dead_letters: Box<dyn DeadLetterStore>,
+    pub transport: Box<dyn Transport>,
+}
+
+/// Tallies of a batch, for the log
+#[derive(Debug, Default)]
+struct Forwarded {
+    delivered: usize,
+    dead_lettered: usize,
+}
+
+/// Forwards a batch, reporting the first record that couldn't be handled
+pub async fn handle(event: KinesisEvent, forwarder: &Forwarder) -> KinesisEventResponse {
+    let mut budget = RetryBudget::new(forwarder.config.retry.budget);
+    let mut subscriptions: HashMap<String, Vec<Webhook>> = HashMap::new();
+    let mut forwarded = Forwarded::default();
+
+    for record in &event.records {
+        let sequence_number = record.kinesis.sequence_number.clone().unwrap_or_default();
+        let result = forward_record(
+            &record.kinesis.data.0,
+            &sequence_number,
+            forwarder,
+            &mut subscriptions,
+            &mut budget,
+            &mut forwarded,
+        )
+        .await;
+        if let Err(e) = result {
+            tracing::error!("Failed to forward record {}, retrying from it: {}", sequence_number, e);
+            return KinesisEventResponse {
+                batch_item_failures: vec![KinesisBatchItemFailure {
+                    item_identifier: record.kinesis.sequence_number.clone(),
+                }],
+            };
+        }
+    }
+
+    tracing::info!(
+        "Delivered {} payloads, dead-lettered {}",
+        forwarded.delivered,
+        forwarded.dead_lettered
+    );
+    KinesisEventResponse {
+        batch_item_failures: Vec::new(),
+    }
+}
+
+async fn forward_record(
+    data: &[u8],
+    sequence_number: &str,
+    forwarder: &Forwarder,
+    subscriptions: &mut HashMap<String, Vec<Webhook>>,
+    budget: &mut RetryBudget,
+    forwarded: &mut Forwarded,
+) -> Result<(), Error> {
+    for (index, data) in aggregation::decode(data).into_iter().enumerate() {
+        let event = match serde_json::from_slice::<IngestEventPayload>(&data) {
+            Ok(event) => event,
+            Err(e) => {
+                tracing::warn!("Skipping a record that isn't a JSON event: {}", e);
+                continue;
+            }
+        };
+        if !subscriptions.contains_key(&event.project_id) {
+            let webhooks = forwarder.webhooks.for_project(&event.project_id).await?;
+            subscriptions.insert(event.project_id.clone(), webhooks);
+        }
+
+        let body = Bytes::from(data);
+        let matching = subscriptions[&event.project_id]
+            .iter()
+            .filter(|webhook| webhook.matches(&event.event_type));
+        for webhook in matching {
+            let delivery_id = delivery::delivery_id(&webhook.webhook_id, sequence_number, index);
+            let outcome = delivery::deliver(
+                forwarder.transport.as_ref(),
+                webhook,
+                &delivery_id,
+                &body,
+                &forwarder.config,
+                budget,
+            )
+            .await;
+            let Outcome::Undeliverable { attempts, reason } = outcome else {
+                forwarded.delivered += 1;
+                continue;
+            };
+
+            tracing::warn!(
+                "Dead-lettering delivery {} to webhook {} after {} attempts: {}",
+                delivery_id,
+                webhook.webhook_id,
+                attempts,
+                reason
+            );
+            let now = chrono::Utc::now();
+            let letter = DeadLetter {
+                project_id: webhook.project_id.clone(),
+                webhook_id: webhook.webhook_id.clone(),
+                delivery_id,
+                url: webhook.url.clone(),
+                payload: String::from_utf8_lossy(&body).into_owned(),
+                attempts,
+                reason,
+                failed_at: now.timestamp_millis(),
+            };
+            let expires_at = now.timestamp() + forwarder.config.dead_letter_ttl.as_secs() as i64;
+            forwarder.dead_letters.put(&letter, expires_at).await?;
+            forwarded.dead_lettered += 1;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    /// Webhooks, deliveries and dead letters in memory, shared by clones
+    #[derive(Clone, Default)]
+    struct Fake {
+        webhooks: Vec<Webhook>,
+        /// URLs answering 500
+        failing: Vec<String>,
+        fail_dead_letters: bool,
+        delivered: Arc<Mutex<Vec<(String, String)>>>,
+        dead_letters: Arc<Mutex<Vec<DeadLetter>>>,
+    }
+
+    #[async_trait]
+    impl WebhookStore for Fake {
+        async fn for_project(&self, project_id: &str) -> Result<Vec<Webhook>, Error> {
+            Ok(self.webhooks.iter().filter(|w| w.project_id == project_id).cloned().collect())
+        }
+    }
+
+    #[async_trait]
+    impl DeadLetterStore for Fake {
+        async fn put(&self, letter: &DeadLetter, _expires_at: i64) -> Result<(), Error> {
+            if self.fail_dead_letters {
+                return Err("ProvisionedThroughputExceededException".into());
+            }
+            self.dead_letters.lock().unwrap().push(letter.clone());
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl Transport for Fake {
+        async fn post(&self, url: &str, _headers: Vec<(&'static str, String)>, body: Bytes) -> Result<u16, Error> {
+            if self.failing.iter().any(|failing| failing == url) {
+                return Ok(500);
+            }
+            let body = String::from_utf8(body.to_vec()).unwrap();
+            self.delivered.lock().unwrap().push((url.to_string(), body));
+            Ok(204)
+        }
+    }
+
+    fn webhook(project_id: &str, url: &str, events: &[&str]) -> Webhook {
+        Webhook {
+            project_id: project_id.to_string(),
+            webhook_id: url.to_string(),
+            url: url.to_string(),
+            secret: "whsec".to_string(),
+            events: events.iter().map(|e| e.to_string()).collect(),
+        }
+    }
+
+    fn forwarder(fake: &Fake) -> Forwarder {
+        let mut config = ForwarderConfig::default();
+        config.retry.base_delay = Duration::from_millis(10);
+        Forwarder {
+            config,
+            webhooks: Box::new(fake.clone()),
+            dead_letters: Box::new(fake.clone()),
+            transport: Box::new(fake.clone()),
+        }
+    }
+
+    fn batch(records: &[&str]) -> KinesisEvent {
+        let records: Vec<_> = records
+            .iter()
+            .enumerate()
+            .map(|(index, data)| {
+                let data = aws_lambda_events::encodings::Base64Data(data.as_bytes().to_vec());
+                json!({
+                    "kinesis": {
+                        "sequenceNumber": index.to_string(),
+                        "data": data,
+                        "approximateArrivalTimestamp": 1_700_000_000.0,
+                    },
+                })
+            })
+            .collect();
+        serde_json::from_value(json!({ "Records": records })).unwrap()
+    }
+
+    const SIGNUP: &str = r#"{"projectId":"p","eventType":"signup","timestamp":1700000000000}"#;
+    const PAGEVIEW: &str = r#"{"projectId":"p","eventType":"pageview","timestamp":1700000000000}"#;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_forwards_matching_events() {
+        let fake = Fake {
+            webhooks: vec![
+                webhook("p", "https://a.example.com", &["signup"]),
+                webhook("p", "https://b.example.com", &[]),
+                webhook("q", "https://c.example.com", &[]),
+            ],
+            ..Default::default()
+        };
+        let response = handle(batch(&[SIGNUP, "garbage", PAGEVIEW]), &forwarder(&fake)).await;
+
+        assert!(response.batch_item_failures.is_empty());
+        let delivered = fake.delivered.lock().unwrap();
+        let urls: Vec<_> = delivered.iter().map(|(url, _)| url.as_str()).collect();
+        assert_eq!(urls, ["https://a.example.com", "https://b.example.com", "https://b.example.com"]);
+        assert_eq!(delivered[0].1, SIGNUP);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_dead_letters_undeliverable_payloads() {
+        let fake = Fake {
+            webhooks: vec![webhook("p", "https://down.example.com", &[])],
+            failing: vec!["https://down.example.com".to_string()],
+            ..Default::default()
+        };
+        let response = handle(batch(&[SIGNUP]), &forwarder(&fake)).await;
+
+        assert!(response.batch_item_failures.is_empty());
+        let letters = fake.dead_letters.lock().unwrap();
+        assert_eq!(letters.len(), 1);
+        assert_eq!((letters[0].attempts, letters[0].reason.as_str()), (5, "HTTP 500"));
+        assert_eq!(letters[0].payload, SIGNUP);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retries_from_a_record_that_could_not_be_dead_lettered() {
+        let fake = Fake {
+            webhooks: vec![webhook("p", "https://down.example.com", &["signup"])],
+            failing: vec!["https://down.example.com".to_string()],
+            fail_dead_letters: true,
+            ..Default::default()
+        };
+        let response = handle(batch(&[PAGEVIEW, SIGNUP, SIGNUP]), &forwarder(&fake)).await;
+
+        assert_eq!(
+            response.batch_item_failures,
+            [KinesisBatchItemFailure {
+                item_identifier: Some("1".to_string()),
+            }]
+        );
+    }
+}
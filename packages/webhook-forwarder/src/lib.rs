@@ -0,0 +1,18 @@
+//! Kinesis → customer webhooks.
+//!
+//! Consumes the ingest stream and forwards each event to the HTTPS
+//! webhooks its project configured for that event type (see
+//! [`webhooks`]), signed with the webhook's secret (see [`signature`]).
+//! Failed deliveries are retried with exponential backoff (see
+//! [`delivery`]) and payloads that still can't be delivered go to a
+//! dead-letter table (see [`dead_letter`]). No event is skipped: a batch
+//! that can't be forwarded or dead-lettered is retried from the record
+//! that failed (see [`handler`]), so delivery is at least once. The event
+//! source mapping must enable `ReportBatchItemFailures`.
+
+pub mod config;
+pub mod dead_letter;
+pub mod delivery;
+pub mod handler;
+pub mod signature;
+pub mod webhooks;
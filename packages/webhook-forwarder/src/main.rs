@@ -0,0 +1,41 @@
+use lambda_runtime::{run, service_fn, Error, LambdaEvent};
+use std::sync::Arc;
+
+use aws_lambda_events::event::kinesis::KinesisEvent;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use webhook_forwarder::config::ForwarderConfig;
+use webhook_forwarder::dead_letter::DynamoDeadLetterStore;
+use webhook_forwarder::delivery::HttpsTransport;
+use webhook_forwarder::handler::{handle, Forwarder};
+use webhook_forwarder::webhooks::DynamoWebhookStore;
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    // Initialize tracing
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"))
+        )
+        .json()
+        .init();
+
+    let config = ForwarderConfig::from_env();
+    if config.webhooks_table.is_empty() || config.dead_letter_table.is_empty() {
+        return Err("WEBHOOKS_TABLE and WEBHOOK_DEAD_LETTER_TABLE must be set".into());
+    }
+    let aws = aws_config::load_from_env().await;
+    let client = DynamoClient::new(&aws);
+    let forwarder = Arc::new(Forwarder {
+        webhooks: Box::new(DynamoWebhookStore::new(client.clone(), config.webhooks_table.clone())),
+        dead_letters: Box::new(DynamoDeadLetterStore::new(client, config.dead_letter_table.clone())),
+        transport: Box::new(HttpsTransport::new()?),
+        config,
+    });
+
+    run(service_fn(move |event: LambdaEvent<KinesisEvent>| {
+        let forwarder = forwarder.clone();
+        async move { Ok::<_, Error>(handle(event.payload, &forwarder).await) }
+    }))
+    .await
+}
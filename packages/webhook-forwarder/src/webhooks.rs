@@ -0,0 +1,150 @@
+//! Webhook subscriptions.
+//!
+//! A project may have any number of webhooks, each an HTTPS URL, a signing
+//! secret and the event types it wants. An entry ending in `*` matches by
+//! prefix, and an empty list matches every event.
+
+use async_trait::async_trait;
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::Client as DynamoClient;
+use lambda_runtime::Error;
+use std::collections::HashMap;
+
+/// A customer's webhook
+#[derive(Debug, Clone, PartialEq)]
+pub struct Webhook {
+    pub project_id: String,
+    pub webhook_id: String,
+    pub url: String,
+    /// HMAC key for the payload signatures
+    pub secret: String,
+    /// Event types forwarded; all when empty
+    pub events: Vec<String>,
+}
+
+impl Webhook {
+    /// Whether events of this type are forwarded to the webhook
+    pub fn matches(&self, event_type: &str) -> bool {
+        self.events.is_empty()
+            || self.events.iter().any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => event_type.starts_with(prefix),
+                None => pattern == event_type,
+            })
+    }
+}
+
+/// Where webhooks are configured
+#[async_trait]
+pub trait WebhookStore: Send + Sync {
+    /// The project's enabled webhooks
+    async fn for_project(&self, project_id: &str) -> Result<Vec<Webhook>, Error>;
+}
+
+/// DynamoDB-backed store
+/// Table schema: partition key `project_id` (S), sort key `webhook_id` (S),
+/// attributes `url` (S), `secret` (S), optionally `events` (SS) and
+/// `enabled` (BOOL, true when absent)
+pub struct DynamoWebhookStore {
+    client: DynamoClient,
+    table_name: String,
+}
+
+impl DynamoWebhookStore {
+    pub fn new(client: DynamoClient, table_name: String) -> Self {
+        Self { client, table_name }
+    }
+}
+
+#[async_trait]
+impl WebhookStore for DynamoWebhookStore {
+    async fn for_project(&self, project_id: &str) -> Result<Vec<Webhook>, Error> {
+        let mut webhooks = Vec::new();
+        let mut start_key = None;
+        loop {
+            let output = self
+                .client
+                .query()
+                .table_name(&self.table_name)
+                .key_condition_expression("project_id = :project")
+                .expression_attribute_values(":project", AttributeValue::S(project_id.to_string()))
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await?;
+            webhooks.extend(output.items().iter().filter_map(webhook_from_item));
+            start_key = output.last_evaluated_key().cloned();
+            if start_key.is_none() {
+                break;
+            }
+        }
+        Ok(webhooks)
+    }
+}
+
+/// An enabled webhook of a table item
+fn webhook_from_item(item: &HashMap<String, AttributeValue>) -> Option<Webhook> {
+    let string = |name: &str| item.get(name).and_then(|v| v.as_s().ok()).cloned();
+    if item.get("enabled").and_then(|v| v.as_bool().ok()) == Some(&false) {
+        return None;
+    }
+    let url = string("url").filter(|url| url.starts_with("https://"))?;
+    Some(Webhook {
+        project_id: string("project_id")?,
+        webhook_id: string("webhook_id")?,
+        url,
+        secret: string("secret")?,
+        events: item
+            .get("events")
+            .and_then(|v| v.as_ss().ok())
+            .cloned()
+            .unwrap_or_default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(entries: &[(&str, AttributeValue)]) -> HashMap<String, AttributeValue> {
+        entries.iter().map(|(k, v)| (k.to_string(), v.clone())).collect()
+    }
+
+    #[test]
+    fn test_filters_by_event_type() {
+        let mut webhook = Webhook {
+            project_id: "p".to_string(),
+            webhook_id: "w".to_string(),
+            url: "https://hooks.example.com".to_string(),
+            secret: "s".to_string(),
+            events: Vec::new(),
+        };
+        assert!(webhook.matches("pageview"));
+
+        webhook.events = vec!["signup".to_string(), "checkout_*".to_string()];
+        assert!(webhook.matches("signup"));
+        assert!(webhook.matches("checkout_completed"));
+        assert!(!webhook.matches("pageview"));
+        assert!(!webhook.matches("signup_started"));
+    }
+
+    #[test]
+    fn test_reads_only_enabled_https_webhooks() {
+        let s = |v: &str| AttributeValue::S(v.to_string());
+        let base = [
+            ("project_id", s("p")),
+            ("webhook_id", s("w")),
+            ("url", s("https://hooks.example.com/in")),
+            ("secret", s("whsec")),
+        ];
+        let webhook = webhook_from_item(&item(&base)).unwrap();
+        assert_eq!(webhook.url, "https://hooks.example.com/in");
+        assert!(webhook.events.is_empty());
+
+        let mut disabled = item(&base);
+        disabled.insert("enabled".to_string(), AttributeValue::Bool(false));
+        assert_eq!(webhook_from_item(&disabled), None);
+
+        let mut plain_http = item(&base);
+        plain_http.insert("url".to_string(), s("http://hooks.example.com/in"));
+        assert_eq!(webhook_from_item(&plain_http), None);
+    }
+}